@@ -152,6 +152,8 @@ class Dex:
 class DexString:
     def content(self) -> str:
         """Return the content of this String"""
+    def raw_bytes(self) -> Optional[bytes]:
+        """Return the modified UTF-8 bytes as stored in the dex file, or None if the string does not come from a string table"""
 
 class NativeSymbol:
     def symbol(self) -> str:
@@ -291,6 +293,10 @@ class Class:
         """Find all subclasses of this super class."""
     def find_implementations(self, ao: AnalyzeObject) -> list[Class]:
         """Find all implementations of this interface (if it is an interface)."""
+    def find_supertypes(self, ao: AnalyzeObject) -> list[str]:
+        """All superclasses and implemented interfaces, including framework types, nearest first."""
+    def is_subclass_of(self, ao: AnalyzeObject, name: str) -> bool:
+        """Whether this class extends or implements `name` (e.g. `android.app.Activity`)."""
     def get_annotations_off(self) -> int:
         """Get offset from the start of the file to the annotations structure for this class"""
     def get_class_annotations(self) -> list[Annotation]:
@@ -336,10 +342,12 @@ class AnnotationField:
 
 class AnalyzeObject:
     # atest#
-    def __init__(self, file_name: str, build_graph: bool, max_nesting: int):
+    def __init__(self, file_name: str, build_graph: bool, max_nesting: int, cache_dir: str | None = None):
         """Initialize a analysis session. Currently, build_graph does not do much.
         `max_nesting` specifies how deep the recursion should go to look for libraries and
-        resources.
+        resources. If `cache_dir` is set, the parsed files and built supergraphs are stored
+        there (keyed by the SHA-256 of the file) and reused by later sessions. Entries written
+        by another version of Coeus are ignored.
         """
     def build_supergraph(self, excluded_classes: list[str]):
         """Build supergraph with additional excluded classes"""
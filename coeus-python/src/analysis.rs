@@ -15,6 +15,7 @@ use coeus::{
         self,
         dex::find_cross_reference_array,
         instruction_flow::{Branch, InstructionFlow, LastInstruction, State},
+        native_disassembly::NativeDisassembler,
         Context,
     },
     coeus_emulation::vm::{runtime::StringClass, Register, Value, VM},
@@ -671,11 +672,7 @@ impl FieldAccess {
         if let analysis::Location::DexMethod(method_idx, f) = &self.place {
             let class = self.get_class()?;
             let method_data = f.get_method_by_idx(*method_idx);
-            let method = if let Some(method) = f
-                .methods
-                .iter()
-                .find(|m| m.method_idx == (*method_idx as u16))
-            {
+            let method = if let Some(method) = f.methods.get(*method_idx as usize) {
                 method.clone()
             } else {
                 return Err(PyRuntimeError::new_err("Could not find method"));
@@ -733,6 +730,64 @@ impl NativeSymbol {
         }
         (self.file.data()[self.address as usize..(self.address + self.size - 1) as usize]).to_vec()
     }
+    /// Disassemble the function, calls through the PLT are resolved to the imported symbol
+    pub fn disassemble(&self) -> PyResult<Vec<NativeInstruction>> {
+        let disassembler = NativeDisassembler::new(self.file.clone()).ok_or_else(|| {
+            PyRuntimeError::new_err("Not an ELF file of a supported architecture")
+        })?;
+        let function = disassembler
+            .function_at(self.address)
+            .ok_or_else(|| PyRuntimeError::new_err("Could not disassemble function"))?;
+        Ok(function
+            .instructions
+            .into_iter()
+            .map(|instruction| NativeInstruction { instruction })
+            .collect())
+    }
+}
+
+#[pyclass]
+#[derive(Clone)]
+pub struct NativeInstruction {
+    instruction: analysis::native_disassembly::NativeInstruction,
+}
+
+#[pymethods]
+impl NativeInstruction {
+    pub fn address(&self) -> u64 {
+        self.instruction.address
+    }
+    pub fn bytes<'py>(&self, py: Python<'py>) -> Bound<'py, PyBytes> {
+        PyBytes::new(py, &self.instruction.bytes)
+    }
+    pub fn mnemonic(&self) -> String {
+        self.instruction.mnemonic.clone()
+    }
+    pub fn op_str(&self) -> String {
+        self.instruction.op_str.clone()
+    }
+    pub fn is_thumb(&self) -> bool {
+        self.instruction.thumb
+    }
+    /// One of `Sequential`, `Call`, `Jump`, `ConditionalJump`, `Return` or `Interrupt`
+    pub fn flow(&self) -> String {
+        format!("{:?}", self.instruction.flow)
+    }
+    pub fn target(&self) -> Option<u64> {
+        self.instruction.target
+    }
+    pub fn reference(&self) -> Option<u64> {
+        self.instruction.reference
+    }
+    pub fn symbol(&self) -> Option<String> {
+        self.instruction.symbol.clone()
+    }
+    pub fn __str__(&self) -> String {
+        self.instruction.to_string()
+    }
+    pub fn __repr__(&self) -> String {
+        self.instruction.to_string()
+    }
 }
 
 #[pymethods]
@@ -1110,6 +1165,15 @@ impl DexString {
     pub fn content(&self) -> String {
         self.string.clone()
     }
+    /// The modified UTF-8 bytes as stored in the dex file, `None` if the string does not
+    /// come from a string table
+    pub fn raw_bytes(&self, py: Python) -> Option<PyObject> {
+        let analysis::Location::DexString(idx, dex) = &self._place else {
+            return None;
+        };
+        let entry = dex.strings.get(*idx as usize)?;
+        Some(PyBytes::new(py, &entry.dat).into())
+    }
 }
 
 #[pymethods]
@@ -1692,6 +1756,31 @@ impl Class {
         }
         vec![]
     }
+    /// All superclasses and implemented interfaces, including framework types, nearest first
+    pub fn find_supertypes(&self, ao: &AnalyzeObject) -> Vec<String> {
+        let Some((md, _)) = ao
+            .files
+            .get_multi_dex_from_dex_identifier(&self.file.identifier)
+        else {
+            return vec![];
+        };
+        ao.class_hierarchy(md)
+            .supertypes(self.name())
+            .iter()
+            .map(|class| class.name.clone())
+            .collect()
+    }
+
+    /// Whether this class extends or implements `name` (e.g. `android.app.Activity`)
+    pub fn is_subclass_of(&self, ao: &AnalyzeObject, name: &str) -> bool {
+        let Some((md, _)) = ao
+            .files
+            .get_multi_dex_from_dex_identifier(&self.file.identifier)
+        else {
+            return false;
+        };
+        ao.class_hierarchy(md).is_subtype_of(self.name(), name)
+    }
     /// Return the name of the class
     pub fn name(&self) -> &str {
         &self.class.class_name
@@ -1946,6 +2035,7 @@ pub(crate) fn register(_py: Python, m: &Bound<PyModule>) -> PyResult<()> {
     m.add_class::<Instruction>()?;
     m.add_class::<Branching>()?;
     m.add_class::<NativeSymbol>()?;
+    m.add_class::<NativeInstruction>()?;
     m.add_class::<FieldAccess>()?;
     m.add_class::<Flow>()?;
     m.add_class::<FlowState>()?;
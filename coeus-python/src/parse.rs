@@ -8,7 +8,10 @@ use coeus::coeus_analysis::analysis::dex::get_native_methods;
 use coeus::coeus_analysis::analysis::{
     find_any, find_classes, find_fields, find_methods, get_methods, ALL_TYPES,
 };
-use coeus::coeus_models::models::{AndroidManifest, DexFile, Files};
+use coeus::coeus_models::models::{
+    AndroidManifest, ClassHierarchy, DexFile, Files, MultiDexFile,
+};
+use coeus::coeus_parse::cache::{load_file_cached, AnalysisCache};
 use coeus::coeus_parse::dex::graph::information_graph::build_information_graph;
 use coeus::coeus_parse::dex::graph::Supergraph;
 use pyo3::exceptions::{PyIOError, PyRuntimeError};
@@ -16,7 +19,7 @@ use pyo3::prelude::*;
 use pyo3::types::PyBytes;
 use regex::Regex;
 use std::collections::HashMap;
-use std::sync::Arc;
+use std::sync::{Arc, Mutex};
 
 use crate::analysis::DexString;
 use crate::analysis::Method;
@@ -67,6 +70,10 @@ impl Dex {
 pub struct AnalyzeObject {
     pub(crate) files: Files,
     pub(crate) supergraph: Option<Arc<Supergraph>>,
+    /// The cache used for the supergraph, together with the key of the archive
+    pub(crate) cache: Option<(AnalysisCache, String)>,
+    /// Class hierarchies, built on demand and indexed by the identifier of the primary dex file
+    pub(crate) class_hierarchies: Mutex<HashMap<String, Arc<ClassHierarchy>>>,
 }
 const NON_INTERESTING_CLASSES: [&str; 16] = [
     "Lj$/time",
@@ -104,10 +111,22 @@ impl AnalyzeObject {
         }
         let mut new = NON_INTERESTING_CLASSES.to_vec();
         new.extend(excluded_classes.iter().map(|s| s.as_str()));
+        if let Some((cache, key)) = &self.cache {
+            if let Some(supergraph) = cache.load_supergraph(key, 0, &new, &self.files) {
+                let supergraph = Arc::new(supergraph);
+                self.supergraph = Some(supergraph.clone());
+                return Ok(supergraph);
+            }
+        }
         let Ok(supergraph) = build_information_graph(&self.files.multi_dex[0], c, &new, None, None)
         else {
             return Err("Failed to build the graph".to_string());
         };
+        if let Some((cache, key)) = &self.cache {
+            if let Err(e) = cache.store_supergraph(key, 0, &new, &supergraph) {
+                log::warn!("Could not cache the supergraph: {}", e);
+            }
+        }
         let supergraph = Arc::new(supergraph);
         self.supergraph = Some(supergraph.clone());
         Ok(supergraph)
@@ -116,19 +135,50 @@ impl AnalyzeObject {
     pub fn get_file_field(&self) -> &Files {
         &self.files
     }
+
+    /// The class hierarchy of the multi dex file, completed by the bundled framework stub
+    pub fn class_hierarchy(&self, multi_dex: &MultiDexFile) -> Arc<ClassHierarchy> {
+        let mut hierarchies = self.class_hierarchies.lock().unwrap();
+        hierarchies
+            .entry(multi_dex.primary.identifier.clone())
+            .or_insert_with(|| Arc::new(ClassHierarchy::with_framework(multi_dex)))
+            .clone()
+    }
 }
 
 #[pymethods]
 impl AnalyzeObject {
     #[new]
-    pub fn new(archive: &str, build_graph: bool, max_depth: i64) -> PyResult<Self> {
-        match coeus::coeus_parse::extraction::load_file(archive, build_graph, max_depth) {
-            Ok(files) => Ok(AnalyzeObject {
-                files,
-                supergraph: None,
-            }),
-            Err(e) => Err(PyIOError::new_err(format!("{e:?}"))),
-        }
+    #[pyo3(signature = (archive, build_graph, max_depth, cache_dir = None))]
+    /// Load the archive. If `cache_dir` is given, the parsed files and built supergraphs are
+    /// cached there and reused on the next run.
+    pub fn new(
+        archive: &str,
+        build_graph: bool,
+        max_depth: i64,
+        cache_dir: Option<&str>,
+    ) -> PyResult<Self> {
+        let Some(cache_dir) = cache_dir else {
+            return match coeus::coeus_parse::extraction::load_file(archive, build_graph, max_depth)
+            {
+                Ok(files) => Ok(AnalyzeObject {
+                    files,
+                    supergraph: None,
+                    cache: None,
+                    class_hierarchies: Mutex::new(HashMap::new()),
+                }),
+                Err(e) => Err(PyIOError::new_err(format!("{e:?}"))),
+            };
+        };
+        let cache = AnalysisCache::new(cache_dir);
+        let (files, key) = load_file_cached(archive, build_graph, max_depth, &cache)
+            .map_err(|e| PyIOError::new_err(format!("{e:?}")))?;
+        Ok(AnalyzeObject {
+            files,
+            supergraph: None,
+            cache: Some((cache, key)),
+            class_hierarchies: Mutex::new(HashMap::new()),
+        })
     }
     pub fn build_supergraph(&mut self, ignore_classes: Vec<String>) -> PyResult<()> {
         self.build_main_supergraph(&ignore_classes)
@@ -231,6 +281,16 @@ impl AnalyzeObject {
         .collect()
     }
 
+    /// Link the functions registered with `RegisterNatives` in all native libraries to the
+    /// `native` methods they implement. `as_native_symbol` returns the native function,
+    /// `cross_references` searches uses of the Java method.
+    pub fn find_registered_natives(&self) -> Vec<crate::analysis::Evidence> {
+        coeus::coeus_analysis::analysis::native::find_registered_natives(&self.files)
+            .into_iter()
+            .map(|evidence| crate::analysis::Evidence { evidence })
+            .collect()
+    }
+
     pub fn get_file_names(&self) -> Vec<String> {
         let mut results = vec![];
         for key in self.files.binaries.keys() {
@@ -372,6 +432,37 @@ impl AnalyzeObject {
             .map(|evidence| crate::analysis::Evidence { evidence })
             .collect()
     }
+    /// Find all calls to native functions or imports matching the regex. The callee is the place
+    /// of the cross reference, the calling function the context.
+    pub fn find_native_calls(&self, pattern: &str) -> Vec<crate::analysis::Evidence> {
+        let pattern = if let Ok(reg) = Regex::new(pattern) {
+            reg
+        } else {
+            return vec![];
+        };
+        coeus::coeus_analysis::analysis::native_references::find_native_calls(
+            &pattern,
+            &self.files.binaries,
+        )
+        .into_iter()
+        .map(|evidence| crate::analysis::Evidence { evidence })
+        .collect()
+    }
+    /// Search the disassembly of all native libraries, e.g. `bl .*<strlen>`
+    pub fn find_native_instructions(&self, pattern: &str) -> Vec<crate::analysis::Evidence> {
+        let pattern = if let Ok(reg) = Regex::new(pattern) {
+            reg
+        } else {
+            return vec![];
+        };
+        coeus::coeus_analysis::analysis::native_disassembly::find_native_instructions(
+            &pattern,
+            &self.files.binaries,
+        )
+        .into_iter()
+        .map(|evidence| crate::analysis::Evidence { evidence })
+        .collect()
+    }
 
     /// Find methods in the analyzed object by utilising a regular expression
     #[pyo3(text_signature = "($self, name,/)")]
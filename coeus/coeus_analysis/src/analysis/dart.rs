@@ -0,0 +1,267 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Flutter apps compile their Dart code into an AOT snapshot (`libapp.so`). The strings and the
+//! library, class and function names recovered from the snapshot are searched by `find_strings`
+//! and `find_any`, with the snapshot as `Context::Binary` and the address of the string as
+//! `Location::NativeAddress`.
+
+use std::sync::{Arc, Mutex};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+use regex::Regex;
+
+use coeus_macros::iterator;
+#[cfg(not(target_arch = "wasm32"))]
+use coeus_models::models::{BinaryObject, CpuArch};
+use coeus_models::models::{DartNameKind, DartSnapshot, Files};
+
+#[cfg(not(target_arch = "wasm32"))]
+use super::native_disassembly::{folded_immediates, NativeDisassembler, NativeOperand};
+use super::{ConfidenceLevel, Context, Evidence, Location, ObjectType, StringEvidence};
+
+/// The engine prints its Dart version as `3.3.0 (stable) (Tue Feb 13 ...) on "android_arm64"`
+const DART_VERSION: &str = r"(?-u)(\d+\.\d+\.\d+(?:-[\w.]+)?) \((?:stable|beta|dev|main)\)";
+const FLUTTER_ENGINE: &str = "libflutter.so";
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DartApp {
+    pub file_name: String,
+    pub snapshot: DartSnapshot,
+    /// The `libflutter.so` next to the snapshot, if it was built for the same snapshot version
+    pub engine: Option<String>,
+    /// The Dart version of the engine
+    pub dart_version: Option<String>,
+}
+
+/// All Dart AOT snapshots, sorted by their file name
+pub fn find_dart_apps(files: &Files) -> Vec<DartApp> {
+    let mut apps = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut apps));
+    let binaries = &files.binaries;
+    iterator!(binaries).for_each(|(file_name, object)| {
+        let Some(snapshot) = object.dart_snapshot() else {
+            return;
+        };
+        let (engine, dart_version) =
+            find_engine(files, file_name, &snapshot.isolate_data.version_hash)
+                .map(|(engine, version)| (Some(engine), version))
+                .unwrap_or_default();
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.push(DartApp {
+                file_name: file_name.clone(),
+                snapshot,
+                engine,
+                dart_version,
+            });
+        }
+    });
+    apps.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    apps
+}
+
+/// The engine in the same directory, which contains the version hash of the snapshot
+fn find_engine(
+    files: &Files,
+    file_name: &str,
+    version_hash: &str,
+) -> Option<(String, Option<String>)> {
+    let directory = file_name
+        .rsplit_once('/')
+        .map(|(directory, _)| directory)
+        .unwrap_or("");
+    let engine_name = if directory.is_empty() {
+        FLUTTER_ENGINE.to_string()
+    } else {
+        format!("{}/{}", directory, FLUTTER_ENGINE)
+    };
+    let engine = files.binaries.get(&engine_name)?;
+    let hash = regex::bytes::Regex::new(&regex::escape(version_hash)).ok()?;
+    if !hash.is_match(engine.data()) {
+        return None;
+    }
+    let version = regex::bytes::Regex::new(DART_VERSION)
+        .expect("REGEX IS WRONG")
+        .captures(engine.data())
+        .and_then(|captures| {
+            Some(String::from_utf8_lossy(captures.get(1)?.as_bytes()).to_string())
+        });
+    Some((engine_name, version))
+}
+
+/// Strings (`ObjectType::String`), classes (`ObjectType::Class`) and functions
+/// (`ObjectType::Method`) of all Dart snapshots
+pub fn find_string_matches_in_dart(
+    reg: &Regex,
+    object_types: &[ObjectType],
+    files: &Files,
+) -> Vec<Evidence> {
+    let strings = object_types.iter().any(|t| matches!(t, ObjectType::String));
+    let classes = object_types.iter().any(|t| matches!(t, ObjectType::Class));
+    let functions = object_types.iter().any(|t| matches!(t, ObjectType::Method));
+    if !strings && !classes && !functions {
+        return vec![];
+    }
+    let mut matches = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut matches));
+    let binaries = &files.binaries;
+    iterator!(binaries).for_each(|(file_name, object)| {
+        let Some(snapshot) = object.dart_snapshot() else {
+            return;
+        };
+        let evidence = |content: &str, address: u64, confidence_level| {
+            Evidence::String(StringEvidence {
+                content: content.to_string(),
+                place: Location::NativeAddress(file_name.clone(), address),
+                context: Context::Binary(object.clone(), file_name.clone()),
+                confidence_level,
+                detail: None,
+            })
+        };
+        let mut snapshot_matches = vec![];
+        if strings {
+            snapshot_matches.extend(
+                snapshot
+                    .strings
+                    .iter()
+                    .filter(|string| reg.is_match(&string.content))
+                    .map(|string| {
+                        evidence(&string.content, string.address, ConfidenceLevel::Medium)
+                    }),
+            );
+        }
+        if classes || functions {
+            snapshot_matches.extend(
+                snapshot
+                    .names()
+                    .into_iter()
+                    .filter(|name| match name.kind {
+                        DartNameKind::Class => classes,
+                        DartNameKind::Function => functions,
+                        DartNameKind::Library => false,
+                    })
+                    .filter(|name| reg.is_match(&name.name))
+                    .map(|name| {
+                        evidence(
+                            &name.name,
+                            name.address,
+                            if name.marked {
+                                ConfidenceLevel::Medium
+                            } else {
+                                ConfidenceLevel::Low
+                            },
+                        )
+                    }),
+            );
+        }
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(snapshot_matches);
+        }
+    });
+    matches
+}
+
+/// A load from the object pool of the Dart VM. The pool itself is only built when the snapshot is
+/// deserialized, the code refers to its entries by their index.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DartPoolReference {
+    /// Address of the loading instruction
+    pub address: u64,
+    pub index: u64,
+}
+
+/// All object pool loads in the code of the isolate. Dart reserves a register for the pool
+/// (`x27` on ARM64, `r5` on ARMv7 and `r15` on x86_64, there is no pool on x86) and loads
+/// entries relative to it, offsets beyond the immediate range are added to a scratch register
+/// first.
+#[cfg(not(target_arch = "wasm32"))]
+pub fn pool_references(
+    object: Arc<BinaryObject>,
+    snapshot: &DartSnapshot,
+) -> Vec<DartPoolReference> {
+    /// Code is disassembled in chunks to bound the memory used
+    const CHUNK_SIZE: u64 = 0x10000;
+
+    let Some((start, size)) = snapshot.isolate_instructions else {
+        return vec![];
+    };
+    let Some(disassembler) = NativeDisassembler::new(object) else {
+        return vec![];
+    };
+    let (pool_register, word_size) = match disassembler.arch() {
+        CpuArch::Arm64 => ("x27", 8),
+        CpuArch::ArmV7 => ("r5", 4),
+        CpuArch::X86_64 => ("r15", 8),
+        CpuArch::X86 => return vec![],
+    };
+    // the pointer to the pool is tagged (+1), the entries follow the header and the length
+    let index = |offset: i64| -> Option<u64> {
+        let offset = offset + 1 - 2 * word_size;
+        if offset >= 0 && offset % word_size == 0 {
+            Some((offset / word_size) as u64)
+        } else {
+            None
+        }
+    };
+
+    let mut references = vec![];
+    let end = start.saturating_add(size);
+    let mut address = start;
+    // (scratch register, offset added to the pool register)
+    let mut scratch: Option<(String, i64)> = None;
+    while address < end {
+        let instructions = disassembler.disassemble(address, CHUNK_SIZE.min(end - address), false);
+        let Some(last) = instructions.last() else {
+            address = address.saturating_add(CHUNK_SIZE);
+            continue;
+        };
+        address = last.next_address();
+        for instruction in &instructions {
+            let operands = folded_immediates(instruction);
+            let load = operands.iter().find_map(|operand| match operand {
+                NativeOperand::Memory {
+                    base: Some(base),
+                    index: None,
+                    displacement,
+                    ..
+                } => {
+                    if base == pool_register {
+                        Some(*displacement)
+                    } else {
+                        match &scratch {
+                            Some((register, offset)) if register == base => {
+                                Some(offset + displacement)
+                            }
+                            _ => None,
+                        }
+                    }
+                }
+                _ => None,
+            });
+            if let Some(index) = load.and_then(index) {
+                references.push(DartPoolReference {
+                    address: instruction.address,
+                    index,
+                });
+            }
+            scratch = match operands.as_slice() {
+                [NativeOperand::Register(destination), NativeOperand::Register(source), NativeOperand::Immediate(offset)]
+                    if instruction.mnemonic == "add" && source == pool_register =>
+                {
+                    let shift = if instruction.op_str.ends_with("lsl #12") {
+                        12
+                    } else {
+                        0
+                    };
+                    Some((destination.clone(), offset << shift))
+                }
+                _ => None,
+            };
+        }
+    }
+    references
+}
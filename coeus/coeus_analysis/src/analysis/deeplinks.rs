@@ -0,0 +1,315 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Combine the deep links declared in the manifest with the code handling them. For every
+//! component we look for calls on `android.net.Uri`, and try to recover the parameter names
+//! passed as constant strings to `getQueryParameter` and friends.
+
+use std::{collections::HashMap, sync::Arc};
+
+use coeus_models::models::{Class, DeepLink, DexFile, Files, Instruction, MultiDexFile};
+
+use super::{ConfidenceLevel, Context, Evidence, InstructionEvidence, Location};
+
+const URI_TYPE: &str = "Landroid/net/Uri;";
+const INTENT_TYPE: &str = "Landroid/content/Intent;";
+
+/// Uri methods taking the name of a query parameter as first argument
+const QUERY_PARAMETER_ACCESSORS: [&str; 3] = [
+    "getQueryParameter",
+    "getQueryParameters",
+    "getBooleanQueryParameter",
+];
+
+/// Other Uri methods reading parts of the link
+const URI_ACCESSORS: [&str; 9] = [
+    "getPath",
+    "getPathSegments",
+    "getLastPathSegment",
+    "getHost",
+    "getQuery",
+    "getEncodedQuery",
+    "getQueryParameterNames",
+    "getFragment",
+    "getScheme",
+];
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DeepLinkHandler {
+    pub deep_link: DeepLink,
+    /// Names of the query parameters read by the component
+    pub query_parameters: Vec<String>,
+    /// Other parts of the uri the component reads (e.g. `getPathSegments`)
+    pub uri_accessors: Vec<String>,
+    /// The instructions reading from the uri
+    pub evidences: Vec<Evidence>,
+}
+
+#[derive(Default)]
+struct UriUsage {
+    query_parameters: Vec<String>,
+    uri_accessors: Vec<String>,
+    evidences: Vec<Evidence>,
+}
+
+/// Find all deep links declared in the manifests, together with the parameters their handlers read
+pub fn find_deep_link_handlers(files: &Files) -> Vec<DeepLinkHandler> {
+    let mut handlers = vec![];
+    for md in &files.multi_dex {
+        let mut usages: HashMap<String, UriUsage> = HashMap::new();
+        for deep_link in md.android_manifest.deep_links() {
+            let usage = usages
+                .entry(deep_link.component.clone())
+                .or_insert_with(|| find_uri_usage(&deep_link.component, md));
+            handlers.push(DeepLinkHandler {
+                deep_link,
+                query_parameters: usage.query_parameters.clone(),
+                uri_accessors: usage.uri_accessors.clone(),
+                evidences: usage.evidences.clone(),
+            });
+        }
+    }
+    handlers
+}
+
+/// Scan the component class and its inner classes for uri accesses
+fn find_uri_usage(component: &str, md: &MultiDexFile) -> UriUsage {
+    let class_name = format!("L{};", component.replace('.', "/"));
+    let inner_class_prefix = format!("{}$", class_name.trim_end_matches(';'));
+    let mut usage = UriUsage::default();
+    for (dex_file, class) in md.classes() {
+        if class.class_name == class_name || class.class_name.starts_with(&inner_class_prefix) {
+            scan_class(&class, &dex_file, &mut usage);
+        }
+    }
+    usage.query_parameters.sort();
+    usage.query_parameters.dedup();
+    usage.uri_accessors.sort();
+    usage.uri_accessors.dedup();
+    usage
+}
+
+fn scan_class(class: &Class, dex_file: &Arc<DexFile>, usage: &mut UriUsage) {
+    for method_data in &class.codes {
+        let Some(code) = method_data.code.as_ref() else {
+            continue;
+        };
+        // last constant string loaded into a register, together with its disassembly
+        let mut const_strings: HashMap<u16, (String, String)> = HashMap::new();
+        for (_, offset, instruction) in &code.insns {
+            let disassembly = || {
+                instruction.disassembly_from_opcode(
+                    offset.0 as i32,
+                    &mut HashMap::new(),
+                    dex_file.clone(),
+                )
+            };
+            let (method_idx, first_arg) = match instruction {
+                &Instruction::ConstString(reg, string_idx) => {
+                    if let Some(s) = dex_file.get_string(string_idx) {
+                        const_strings.insert(reg as u16, (s.to_string(), disassembly()));
+                    }
+                    continue;
+                }
+                &Instruction::ConstStringJumbo(reg, string_idx) => {
+                    if let Some(s) = dex_file.get_string(string_idx as usize) {
+                        const_strings.insert(reg as u16, (s.to_string(), disassembly()));
+                    }
+                    continue;
+                }
+                Instruction::InvokeVirtual(_, method_idx, regs)
+                | Instruction::InvokeInterface(_, method_idx, regs)
+                | Instruction::InvokeDirect(_, method_idx, regs) => {
+                    (*method_idx, regs.get(1).map(|&r| r as u16))
+                }
+                &Instruction::InvokeVirtualRange(count, method_idx, first_reg)
+                | &Instruction::InvokeInterfaceRange(count, method_idx, first_reg)
+                | &Instruction::InvokeDirectRange(count, method_idx, first_reg) => {
+                    (method_idx, (count > 1).then(|| first_reg + 1))
+                }
+                _ => continue,
+            };
+            let Some(method) = dex_file.methods.get(method_idx as usize) else {
+                continue;
+            };
+            let type_name = dex_file.get_type_name(method.class_idx).unwrap_or_default();
+            let name = method.method_name.as_str();
+
+            let mut instructions = vec![];
+            let confidence_level = if type_name == URI_TYPE
+                && QUERY_PARAMETER_ACCESSORS.contains(&name)
+            {
+                match first_arg.and_then(|r| const_strings.get(&r)) {
+                    Some((parameter, const_disassembly)) => {
+                        usage.query_parameters.push(parameter.clone());
+                        instructions.push(const_disassembly.clone());
+                        ConfidenceLevel::High
+                    }
+                    None => ConfidenceLevel::Low,
+                }
+            } else if type_name == URI_TYPE && URI_ACCESSORS.contains(&name) {
+                usage.uri_accessors.push(name.to_string());
+                ConfidenceLevel::Medium
+            } else if type_name == INTENT_TYPE && (name == "getData" || name == "getDataString") {
+                usage.uri_accessors.push(format!("Intent.{}", name));
+                ConfidenceLevel::Medium
+            } else {
+                continue;
+            };
+            instructions.push(disassembly());
+            usage
+                .evidences
+                .push(Evidence::Instructions(InstructionEvidence {
+                    instructions,
+                    place: Location::DexMethod(
+                        method_data.method.method_idx as u32,
+                        dex_file.clone(),
+                    ),
+                    context: Context::DexMethod(method_data.method.clone(), dex_file.clone()),
+                    confidence_level,
+                }));
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coeus_models::models::{
+        testing::{format_21c, format_35c, DexBuilder, RETURN_VOID},
+        AndroidActivity, AndroidApplication, AndroidIntentData, AndroidIntentFilter,
+        AndroidManifest, ContentType, IntentContent, Usages,
+    };
+
+    use super::*;
+
+    const CONST_STRING: u8 = 0x1a;
+    const INVOKE_VIRTUAL: u8 = 0x6e;
+
+    fn manifest() -> AndroidManifest {
+        let data = |scheme: &str, host: &str| {
+            IntentContent::Data(AndroidIntentData {
+                scheme: Some(scheme.to_string()),
+                host: Some(host.to_string()),
+                ..Default::default()
+            })
+        };
+        let activity = AndroidActivity {
+            name: ".Main".to_string(),
+            intent_filters: vec![AndroidIntentFilter {
+                auto_verify: None,
+                content: vec![data("myapp", "open"), data("https", "example.com")],
+            }],
+            ..Default::default()
+        };
+        AndroidManifest {
+            package: "com.example".to_string(),
+            content: vec![Usages::Application(AndroidApplication {
+                activities: vec![ContentType::Activity(activity)],
+                ..Default::default()
+            })],
+            ..Default::default()
+        }
+    }
+
+    fn files() -> Files {
+        let mut dex = DexBuilder::new("classes");
+        let string = "Ljava/lang/String;";
+        let get_query_parameter = dex.method(URI_TYPE, "getQueryParameter", string, &[string]);
+        let get_boolean_query_parameter =
+            dex.method(URI_TYPE, "getBooleanQueryParameter", "Z", &[string, "Z"]);
+        let get_host = dex.method(URI_TYPE, "getHost", string, &[]);
+        let get_data = dex.method(INTENT_TYPE, "getData", URI_TYPE, &[]);
+        let id = dex.string("id") as u16;
+        let referrer = dex.string("referrer") as u16;
+        let unrelated = dex.string("unrelated") as u16;
+
+        let main = dex.class("Lcom/example/Main;", Some("Landroid/app/Activity;"), &[]);
+        let on_create = dex.method("Lcom/example/Main;", "onCreate", "V", &[]);
+        let code = [
+            &format_35c(INVOKE_VIRTUAL, get_data, &[2])[..],
+            &format_21c(CONST_STRING, 1, id),
+            &format_35c(INVOKE_VIRTUAL, get_query_parameter, &[0, 1]),
+            &format_35c(INVOKE_VIRTUAL, get_host, &[0]),
+            // the parameter name is not a constant
+            &format_35c(INVOKE_VIRTUAL, get_query_parameter, &[0, 3]),
+            &[RETURN_VOID],
+        ]
+        .concat();
+        dex.virtual_method(main, on_create, &code);
+
+        let inner = dex.class("Lcom/example/Main$1;", Some("Ljava/lang/Object;"), &[]);
+        let run = dex.method("Lcom/example/Main$1;", "run", "V", &[]);
+        let code = [
+            &format_21c(CONST_STRING, 1, referrer)[..],
+            &format_35c(INVOKE_VIRTUAL, get_boolean_query_parameter, &[0, 1, 2]),
+            &[RETURN_VOID],
+        ]
+        .concat();
+        dex.virtual_method(inner, run, &code);
+
+        let other = dex.class(
+            "Lcom/example/MainActivity;",
+            Some("Ljava/lang/Object;"),
+            &[],
+        );
+        let other_method = dex.method("Lcom/example/MainActivity;", "run", "V", &[]);
+        let code = [
+            &format_21c(CONST_STRING, 1, unrelated)[..],
+            &format_35c(INVOKE_VIRTUAL, get_query_parameter, &[0, 1]),
+            &[RETURN_VOID],
+        ]
+        .concat();
+        dex.virtual_method(other, other_method, &code);
+
+        let multi_dex = MultiDexFile::new(manifest(), String::new(), dex.build(), vec![]);
+        Files::new(vec![multi_dex], HashMap::new())
+    }
+
+    #[test]
+    fn finds_parameters_read_by_the_handler() {
+        let handlers = find_deep_link_handlers(&files());
+        // all data elements of a filter are merged
+        let patterns: Vec<_> = handlers
+            .iter()
+            .map(|h| h.deep_link.pattern.to_string())
+            .collect();
+        assert_eq!(
+            patterns,
+            [
+                "myapp://open/*",
+                "myapp://example.com/*",
+                "https://open/*",
+                "https://example.com/*"
+            ]
+        );
+        for handler in &handlers {
+            assert_eq!(handler.deep_link.component, "com.example.Main");
+            assert!(handler.deep_link.exported);
+            assert!(!handler.deep_link.is_app_link);
+            // inner classes are scanned, other classes sharing the prefix are not
+            assert_eq!(handler.query_parameters, ["id", "referrer"]);
+            assert_eq!(handler.uri_accessors, ["Intent.getData", "getHost"]);
+        }
+    }
+
+    #[test]
+    fn evidences_carry_the_confidence() {
+        let handlers = find_deep_link_handlers(&files());
+        let evidences = &handlers[0].evidences;
+        assert_eq!(evidences.len(), 5);
+        let Evidence::Instructions(high) = &evidences[1] else {
+            panic!("expected instruction evidence");
+        };
+        assert!(matches!(high.confidence_level, ConfidenceLevel::High));
+        // the constant string and the call
+        assert_eq!(high.instructions.len(), 2);
+        let Evidence::Instructions(low) = &evidences[3] else {
+            panic!("expected instruction evidence");
+        };
+        assert!(matches!(low.confidence_level, ConfidenceLevel::Low));
+        assert_eq!(low.instructions.len(), 1);
+    }
+}
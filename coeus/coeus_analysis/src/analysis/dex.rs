@@ -7,6 +7,7 @@
 use regex::Regex;
 
 use std::{
+    collections::HashSet,
     sync::{Arc, Mutex},
     vec,
 };
@@ -15,7 +16,10 @@ use std::{
 use rayon::iter::{IndexedParallelIterator, ParallelIterator};
 
 use coeus_macros::iterator;
-use coeus_models::models::{AccessFlags, Class, DexFile, Field, Files, Method, MultiDexFile};
+use coeus_models::models::{
+    field_xref_key, method_overload_xref_key, AccessFlags, Class, DexFile, Field, Files, Method,
+    MultiDexFile, StringQuery, XrefSite,
+};
 
 use super::{
     ConfidenceLevel, Context, CrossReferenceEvidence, Evidence, InstructionEvidence, Location,
@@ -202,224 +206,48 @@ pub fn find_cross_reference_array<'a: 'b, 'b>(
     // evidences
 }
 
+/// One cross reference per method containing any of the sites
+fn cross_references_from_sites(
+    sites: &[XrefSite],
+    multi_dex: &MultiDexFile,
+    place: &Context,
+) -> Vec<Evidence> {
+    let mut seen = HashSet::new();
+    sites
+        .iter()
+        .filter(|site| seen.insert((site.dex, site.method_idx)))
+        .filter_map(|site| multi_dex.xref_method(site))
+        .map(|(f, method)| {
+            Evidence::CrossReference(CrossReferenceEvidence {
+                place: Location::DexMethod(method.method_idx as u32, f.clone()),
+                place_context: Context::DexMethod(method, f),
+                context: place.clone(),
+            })
+        })
+        .collect()
+}
+
 fn find_references_to_string<'a: 'b, 'b>(
     str_idx: u32,
     dex_file: Arc<DexFile>,
-    _multi_dex: &'a MultiDexFile,
+    multi_dex: &'a MultiDexFile,
     place: &'b Context,
 ) -> Vec<Evidence> {
-    let mut context_matches: Vec<Evidence> = vec![];
-    let vec_loc = Arc::new(Mutex::new(&mut context_matches));
-    let classes = &dex_file.classes;
-    iterator!(classes).for_each(|c| {
-        let methods_containing_references: Vec<_> = iterator!(c.codes)
-            .filter(|md| match md.code.as_ref() {
-                Some(code) => {
-                    let references =
-                        iterator!(code.insns).filter(|(_, _, instruction)| match instruction {
-                            coeus_models::models::Instruction::ConstString(_, str_ptr) => {
-                                (*str_ptr as u32) == str_idx
-                            }
-                            coeus_models::models::Instruction::ConstStringJumbo(_, str_ptr) => {
-                                *str_ptr == str_idx
-                            }
-                            _ => false,
-                        });
-                    references.count() > 0
-                }
-                _ => false,
-            })
-            .map(|m| {
-                Evidence::CrossReference(CrossReferenceEvidence {
-                    place: Location::DexMethod(m.method.method_idx as u32, dex_file.clone()),
-                    place_context: Context::DexMethod(m.method.clone(), dex_file.clone()),
-                    context: place.clone(),
-                })
-            })
-            .collect();
-        if let Ok(mut lock) = vec_loc.lock() {
-            lock.extend(methods_containing_references);
-        }
-    });
-    context_matches
+    let Some(content) = dex_file.strings.get(str_idx as usize) else {
+        return vec![];
+    };
+    let sites = multi_dex.xrefs().string_references(&content.to_str_lossy());
+    cross_references_from_sites(sites, multi_dex, place)
 }
 
-//TODO: refactor
 fn find_references_to_type<'a: 'b, 'b>(
     _typ: u32,
     name: &str,
-    dex_file: Arc<DexFile>,
+    _dex_file: Arc<DexFile>,
     multi_dex: &'a MultiDexFile,
     place: &'b Context,
 ) -> Vec<Evidence> {
-    let mut context_matches: Vec<Evidence> = vec![];
-    let vec_loc = Arc::new(Mutex::new(&mut context_matches));
-
-    let type_name = name;
-
-    let classes = multi_dex.classes();
-    iterator!(classes).for_each(|(f, c)| {
-        let methods_containing_references: Vec<_> = iterator!(c.codes)
-            .filter(|md| match md.code.as_ref() {
-                Some(code) => {
-                    let references =
-                            iterator!(code.insns).filter(|(_, _, instruction)| match instruction {
-                                coeus_models::models::Instruction::Invoke(method_idx)
-                                | coeus_models::models::Instruction::InvokeVirtual(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeSuper(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeDirect(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeStatic(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeInterface(
-                                    _,
-                                    method_idx,
-                                    _,
-                                ) => {
-                                    if (*method_idx as usize) >= f.methods.len() {
-                                        return false;
-                                    }
-                                    let method = &f.methods[*method_idx as usize];
-                                    let name_idx = f.types[method.class_idx as usize];
-                                    let name = &f.strings[name_idx as usize];
-                                    if let Ok(name) = name.to_str() {
-                                        return name == type_name;
-                                    }
-                                    false
-                                }
-
-                                coeus_models::models::Instruction::NewInstance(_, type_idx) => {
-                                    if let Some(s) = f.get_type_name(*type_idx as usize) {
-                                        s == name
-                                    } else {
-                                        false
-                                    }
-                                }
-
-                                coeus_models::models::Instruction::StaticGet(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetWide(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetObject(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetBoolean(
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::StaticGetByte(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetChar(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetShort(_, field_id)
-                                | coeus_models::models::Instruction::StaticPut(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutWide(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutObject(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutBoolean(
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::StaticPutByte(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutChar(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutShort(_, field_id)
-                                | coeus_models::models::Instruction::InstanceGet(_, _, field_id)
-                                | coeus_models::models::Instruction::InstanceGetWide(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetObject(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetBoolean(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetByte(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetChar(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetShort(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePut(_, _, field_id)
-                                | coeus_models::models::Instruction::InstancePutWide(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutObject(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutBoolean(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutByte(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutChar(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutShort(
-                                    _,
-                                    _,
-                                    field_id,
-                                ) => match f.fields.get((*field_id) as usize) {
-                                    Some(field) => {
-                                        if let Some(type_name) = f.get_type_name(field.class_idx) {
-                                            type_name == name
-                                        } else {
-                                            false
-                                        }
-                                    }
-                                    _ => false,
-                                },
-                                _ => false,
-                            });
-                    references.count() > 0
-                }
-                None => false,
-            })
-            .map(|m| {
-                Evidence::CrossReference(CrossReferenceEvidence {
-                    place: Location::DexMethod(m.method.method_idx as u32, dex_file.clone()),
-                    place_context: Context::DexMethod(m.method.clone(), f.clone()),
-                    context: place.clone(),
-                })
-            })
-            .collect();
-        if let Ok(mut lock) = vec_loc.lock() {
-            lock.extend(methods_containing_references);
-        }
-    });
-    context_matches
+    cross_references_from_sites(multi_dex.xrefs().type_uses(name), multi_dex, place)
 }
 
 fn find_references_to_field<'a: 'b, 'b>(
@@ -428,184 +256,41 @@ fn find_references_to_field<'a: 'b, 'b>(
     multi_dex: &'a MultiDexFile,
     place: &'b Context,
 ) -> Vec<Evidence> {
-    let mut context_matches: Vec<Evidence> = vec![];
-    let vec_loc = Arc::new(Mutex::new(&mut context_matches));
-    let field_fqdn = format!(
-        "{}->{}",
-        dex_file
-            .get_type_name(field_idx.class_idx as usize)
-            .unwrap_or(""),
-        field_idx.name
-    );
-    let classes = multi_dex.classes();
-    iterator!(classes).for_each(|(f, c)| {
-        let methods_containing_references: Vec<_> = iterator!(c.codes)
-            .filter_map(|md| match md.code.as_ref() {
-                Some(code) => {
-                    let references =
-                        iterator!(code.insns).filter(|(_, _, instruction)| match instruction {
-                            coeus_models::models::Instruction::StaticGet(_, field_id)
-                            | coeus_models::models::Instruction::StaticGetWide(_, field_id)
-                            | coeus_models::models::Instruction::StaticGetObject(_, field_id)
-                            | coeus_models::models::Instruction::StaticGetBoolean(_, field_id)
-                            | coeus_models::models::Instruction::StaticGetByte(_, field_id)
-                            | coeus_models::models::Instruction::StaticGetChar(_, field_id)
-                            | coeus_models::models::Instruction::StaticGetShort(_, field_id)
-                            | coeus_models::models::Instruction::StaticPut(_, field_id)
-                            | coeus_models::models::Instruction::StaticPutWide(_, field_id)
-                            | coeus_models::models::Instruction::StaticPutObject(_, field_id)
-                            | coeus_models::models::Instruction::StaticPutBoolean(_, field_id)
-                            | coeus_models::models::Instruction::StaticPutByte(_, field_id)
-                            | coeus_models::models::Instruction::StaticPutChar(_, field_id)
-                            | coeus_models::models::Instruction::StaticPutShort(_, field_id)
-                            | coeus_models::models::Instruction::InstanceGet(_, _, field_id)
-                            | coeus_models::models::Instruction::InstanceGetWide(_, _, field_id)
-                            | coeus_models::models::Instruction::InstanceGetObject(
-                                _,
-                                _,
-                                field_id,
-                            )
-                            | coeus_models::models::Instruction::InstanceGetBoolean(
-                                _,
-                                _,
-                                field_id,
-                            )
-                            | coeus_models::models::Instruction::InstanceGetByte(_, _, field_id)
-                            | coeus_models::models::Instruction::InstanceGetChar(_, _, field_id)
-                            | coeus_models::models::Instruction::InstanceGetShort(_, _, field_id)
-                            | coeus_models::models::Instruction::InstancePut(_, _, field_id)
-                            | coeus_models::models::Instruction::InstancePutWide(_, _, field_id)
-                            | coeus_models::models::Instruction::InstancePutObject(
-                                _,
-                                _,
-                                field_id,
-                            )
-                            | coeus_models::models::Instruction::InstancePutBoolean(
-                                _,
-                                _,
-                                field_id,
-                            )
-                            | coeus_models::models::Instruction::InstancePutByte(_, _, field_id)
-                            | coeus_models::models::Instruction::InstancePutChar(_, _, field_id)
-                            | coeus_models::models::Instruction::InstancePutShort(_, _, field_id) =>
-                            {
-                                let field = f.fields[*field_id as usize].clone();
-                                let fqdn = format!(
-                                    "{}->{}",
-                                    f.get_type_name(field.class_idx as usize).unwrap_or(""),
-                                    field.name
-                                );
-                                fqdn == field_fqdn
-                            }
-
-                            _ => false,
-                        });
-                    Some((md, references.collect::<Vec<_>>()))
-                }
-                None => None,
-            })
-            .flat_map(|(m, instructions)| {
-                instructions
-                    .iter()
-                    .map(|(_, _, instruction)| {
-                        Evidence::Instructions(InstructionEvidence {
-                            instructions: vec![format!("{:?}", instruction)],
-                            place: Location::DexMethod(
-                                m.method.method_idx as u32,
-                                dex_file.clone(),
-                            ),
-                            context: place.clone(),
-                            confidence_level: ConfidenceLevel::Medium,
-                        })
-                    })
-                    .collect::<Vec<_>>()
-            })
-            .collect();
-        if let Ok(mut lock) = vec_loc.lock() {
-            lock.extend(methods_containing_references);
-        }
-    });
-    context_matches
+    let key = field_xref_key(&dex_file, field_idx);
+    let xrefs = multi_dex.xrefs();
+    xrefs
+        .field_reads(&key)
+        .iter()
+        .chain(xrefs.field_writes(&key))
+        .filter_map(|site| {
+            let (f, method) = multi_dex.xref_method(site)?;
+            let instruction = f
+                .get_method_by_idx(site.method_idx)
+                .and_then(|md| md.code.clone())
+                .and_then(|code| {
+                    code.insns
+                        .iter()
+                        .find(|(_, offset, _)| offset.0 == site.offset)
+                        .map(|(_, _, instruction)| format!("{:?}", instruction))
+                })?;
+            Some(Evidence::Instructions(InstructionEvidence {
+                instructions: vec![instruction],
+                place: Location::DexMethod(method.method_idx as u32, f),
+                context: place.clone(),
+                confidence_level: ConfidenceLevel::Medium,
+            }))
+        })
+        .collect()
 }
+
 fn find_references_to_method<'a: 'b, 'b>(
     looking_for_method_idx: &'b Method,
     dex_file: Arc<DexFile>,
     multi_dex: &'a MultiDexFile,
     place: &'b Context,
 ) -> Vec<Evidence> {
-    let mut context_matches: Vec<Evidence> = vec![];
-    let vec_loc = Arc::new(Mutex::new(&mut context_matches));
-    let parent_method = if let Some(m) = dex_file
-        .methods
-        .get(looking_for_method_idx.method_idx as usize)
-    {
-        m
-    } else {
-        return vec![];
-    };
-    let parent_class = dex_file
-        .get_string(
-            *dex_file
-                .types
-                .get(parent_method.class_idx as usize)
-                .unwrap_or(&0) as usize,
-        )
-        .unwrap_or("unknown-class");
-
-    let classes = multi_dex.classes();
-    iterator!(classes).for_each(|(f, c)| {
-        let methods_containing_references: Vec<_> = iterator!(c.codes)
-            .filter(|md| match md.code.as_ref() {
-                Some(code) => {
-                    let references =
-                        iterator!(code.insns).filter(|(_, _, instruction)| match instruction {
-                            coeus_models::models::Instruction::Invoke(method_idx)
-                            | coeus_models::models::Instruction::InvokeVirtual(_, method_idx, _)
-                            | coeus_models::models::Instruction::InvokeSuper(_, method_idx, _)
-                            | coeus_models::models::Instruction::InvokeDirect(_, method_idx, _)
-                            | coeus_models::models::Instruction::InvokeStatic(_, method_idx, _)
-                            | coeus_models::models::Instruction::InvokeInterface(
-                                _,
-                                method_idx,
-                                _,
-                            ) => {
-                                if let Some(this_method_name) = f.methods.get(*method_idx as usize)
-                                {
-                                    if this_method_name.method_name
-                                        != looking_for_method_idx.method_name
-                                    {
-                                        return false;
-                                    }
-                                    let strings_index =
-                                        f.types[this_method_name.class_idx as usize];
-                                    if let Ok(the_class_name) =
-                                        f.strings[strings_index as usize].to_str()
-                                    {
-                                        return the_class_name == parent_class;
-                                    }
-                                }
-                                false
-                            }
-
-                            _ => false,
-                        });
-                    references.count() > 0
-                }
-                None => false,
-            })
-            .map(|m| {
-                Evidence::CrossReference(CrossReferenceEvidence {
-                    place: Location::DexMethod(m.method.method_idx as u32, f.clone()),
-                    place_context: Context::DexMethod(m.method.clone(), f.clone()),
-                    context: place.clone(),
-                })
-            })
-            .collect();
-        if let Ok(mut lock) = vec_loc.lock() {
-            lock.extend(methods_containing_references);
-        }
-    });
-    context_matches
+    let key = method_overload_xref_key(&dex_file, looking_for_method_idx);
+    cross_references_from_sites(multi_dex.xrefs().overload_callers(&key), multi_dex, place)
 }
 
 fn find_references_to_class<'a: 'b, 'b>(
@@ -614,172 +299,11 @@ fn find_references_to_class<'a: 'b, 'b>(
     multi_dex: &'a MultiDexFile,
     place: &'b Context,
 ) -> Vec<Evidence> {
-    let mut context_matches: Vec<Evidence> = vec![];
-    let vec_loc = Arc::new(Mutex::new(&mut context_matches));
-    let classes = multi_dex.classes();
-    iterator!(classes).for_each(|(f, c)| {
-        let methods_containing_references: Vec<_> = iterator!(c.codes)
-            .filter(|md| match md.code.as_ref() {
-                Some(code) => {
-                    let references =
-                            iterator!(code.insns).filter(|(_, _, instruction)| match instruction {
-                                coeus_models::models::Instruction::Invoke(method_idx)
-                                | coeus_models::models::Instruction::InvokeVirtual(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeSuper(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeDirect(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeStatic(
-                                    _,
-                                    method_idx,
-                                    _,
-                                )
-                                | coeus_models::models::Instruction::InvokeInterface(
-                                    _,
-                                    method_idx,
-                                    _,
-                                ) => {
-                                    if *method_idx == 0xffff {
-                                        return false;
-                                    }
-                                    let method = &f.methods[*method_idx as usize];
-                                    let name_idx = f.types[method.class_idx as usize];
-                                    let name = &f.strings[name_idx as usize];
-                                    if let Ok(name) = name.to_str() {
-                                        
-                                        return name == class_idx.class_name;
-                                    }
-                                    false
-                                }
-
-                                coeus_models::models::Instruction::NewInstance(_, type_idx) => {
-                                    if let Some(s) = f.get_type_name(*type_idx as usize) {
-                                        s == &class_idx.class_name
-                                    } else {
-                                        false
-                                    }
-                                }
-
-                                coeus_models::models::Instruction::StaticGet(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetWide(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetObject(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetBoolean(
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::StaticGetByte(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetChar(_, field_id)
-                                | coeus_models::models::Instruction::StaticGetShort(_, field_id)
-                                | coeus_models::models::Instruction::StaticPut(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutWide(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutObject(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutBoolean(
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::StaticPutByte(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutChar(_, field_id)
-                                | coeus_models::models::Instruction::StaticPutShort(_, field_id)
-                                | coeus_models::models::Instruction::InstanceGet(_, _, field_id)
-                                | coeus_models::models::Instruction::InstanceGetWide(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetObject(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetBoolean(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetByte(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetChar(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstanceGetShort(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePut(_, _, field_id)
-                                | coeus_models::models::Instruction::InstancePutWide(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutObject(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutBoolean(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutByte(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutChar(
-                                    _,
-                                    _,
-                                    field_id,
-                                )
-                                | coeus_models::models::Instruction::InstancePutShort(
-                                    _,
-                                    _,
-                                    field_id,
-                                ) => match f.fields.get((*field_id) as usize) {
-                                    Some(field) => {
-                                        if let Some(class_name) = f.get_type_name(field.class_idx) {
-                                            class_name == class_idx.class_name
-                                        } else {
-                                            false
-                                        }
-                                    }
-                                    _ => false,
-                                },
-                                _ => false,
-                            });
-                    references.count() > 0
-                }
-                None => false,
-            })
-            .map(|m| {
-                Evidence::CrossReference(CrossReferenceEvidence {
-                    place: Location::DexMethod(m.method.method_idx as u32, f.clone()),
-                    place_context: Context::DexMethod(m.method.clone(), f.clone()),
-                    context: place.clone(),
-                })
-            })
-            .collect();
-        if let Ok(mut lock) = vec_loc.lock() {
-            lock.extend(methods_containing_references);
-        }
-    });
-    context_matches
+    cross_references_from_sites(
+        multi_dex.xrefs().type_uses(&class_idx.class_name),
+        multi_dex,
+        place,
+    )
 }
 
 pub fn find_string_matches_for_method_name(reg: &Regex, files: &[MultiDexFile]) -> Vec<Evidence> {
@@ -796,6 +320,7 @@ pub fn find_string_matches_for_method_name(reg: &Regex, files: &[MultiDexFile])
                     place: Location::DexMethod(m.method_idx as u32, dex.clone()),
                     context: Context::DexMethod(m.clone(), dex.clone()),
                     confidence_level: ConfidenceLevel::Medium,
+                    detail: None,
                 })
             })
             .collect();
@@ -819,6 +344,7 @@ pub fn find_string_matches_for_class_name(reg: &Regex, files: &[MultiDexFile]) -
                     place: Location::Class(c.class_idx, dex.clone()),
                     context: Context::DexClass(c.clone(), dex.clone()),
                     confidence_level: ConfidenceLevel::Medium,
+                    detail: None,
                 })
             })
             .collect();
@@ -852,6 +378,7 @@ pub fn find_string_matches_for_type_name(
                     place: Location::Type(*index as u32, dex_file.clone()),
                     context: Context::DexType(*index as u32, type_name.to_string(), dex_file.clone()),
                     confidence_level: ConfidenceLevel::Medium,
+                    detail: None,
                 }))
             })
             .collect();
@@ -881,6 +408,7 @@ pub fn find_string_matches_for_field_name(
                     place: Location::DexField(*index as u32, dex.clone()),
                     context: Context::DexField(m.clone(), dex.clone()),
                     confidence_level: ConfidenceLevel::Medium,
+                    detail: None,
                 }))
             })
             .collect();
@@ -947,6 +475,7 @@ pub fn find_string_matches_for_static_data(
                                 dex.clone(),
                             ),
                             confidence_level: ConfidenceLevel::High,
+                            detail: None,
                         })
                     })
                     .collect::<Vec<Evidence>>()
@@ -977,6 +506,7 @@ pub fn find_string_matches_for_proto(reg: &Regex, files: &[MultiDexFile]) -> Opt
                     place: Location::DexMethod(s.2 as u32, s.0.clone()),
                     context: Context::DexProto(s.1.clone(), s.0.clone()),
                     confidence_level: ConfidenceLevel::Medium,
+                    detail: None,
                 }))
             })
             .collect();
@@ -991,28 +521,23 @@ pub fn find_string_matches_for_string_entries(
     reg: &Regex,
     files: &[MultiDexFile],
 ) -> Option<Vec<Evidence>> {
-    let mut matches = vec![];
-    let vec_lock = Arc::new(Mutex::new(&mut matches));
-    iterator!(files).for_each(|dex| {
-        let strings = dex.strings();
-        let string_matches: Vec<Evidence> = iterator!(strings)
-            .filter(|s| match s.1.to_str() {
-                Ok(r) => reg.is_match(r),
-                _ => false,
-            })
-            .filter_map(|s| {
-                Some(Evidence::String(StringEvidence {
-                    content: s.1.to_str().ok()?.to_string(),
-                    place: Location::DexString(s.2 as u32, s.0.clone()),
-                    context: Context::DexString(s.2 as u32, s.0.clone()),
-                    confidence_level: ConfidenceLevel::VeryLow,
-                }))
-            })
-            .collect();
-        if let Ok(mut lock) = vec_lock.lock() {
-            lock.extend(string_matches);
-        }
-    });
+    let query = StringQuery::Regex(reg.as_str());
+    let matches = iterator!(files)
+        .flat_map(|dex| {
+            dex.find_strings(&query)
+                .into_iter()
+                .filter_map(|(dex_file, idx)| {
+                    Some(Evidence::String(StringEvidence {
+                        content: dex_file.get_string(idx as usize)?.to_string(),
+                        place: Location::DexString(idx, dex_file.clone()),
+                        context: Context::DexString(idx, dex_file),
+                        confidence_level: ConfidenceLevel::VeryLow,
+                        detail: None,
+                    }))
+                })
+                .collect::<Vec<_>>()
+        })
+        .collect();
     Some(matches)
 }
 
@@ -1047,6 +572,7 @@ pub fn find_string_matches_in_dex_with_type(
             ObjectType::StaticData => {
                 find_string_matches_for_static_data(reg, files).unwrap_or_else(|| vec![])
             }
+            ObjectType::Hermes => vec![],
         };
         if let Ok(mut lock) = vec_lock.lock() {
             lock.extend(matches);
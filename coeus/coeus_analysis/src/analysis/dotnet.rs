@@ -0,0 +1,121 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Search in the managed assemblies of Xamarin and .NET apps. Types and methods are reported like
+//! the classes and methods of dex files, the user strings (literals loaded with `ldstr`) like dex
+//! strings. Each evidence points to the metadata token in its assembly.
+
+use std::sync::{Arc, Mutex};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+use regex::Regex;
+
+use coeus_macros::iterator;
+use coeus_models::models::Files;
+
+use super::{ConfidenceLevel, Context, Evidence, Location, ObjectType, StringEvidence};
+
+/// Types (`ObjectType::Class`, `ObjectType::Type`), methods (`ObjectType::Method`) and user strings
+/// (`ObjectType::String`) of all managed assemblies
+pub fn find_string_matches_in_dotnet(
+    reg: &Regex,
+    object_types: &[ObjectType],
+    files: &Files,
+) -> Vec<Evidence> {
+    let types = object_types
+        .iter()
+        .any(|t| matches!(t, ObjectType::Class | ObjectType::Type));
+    let methods = object_types.iter().any(|t| matches!(t, ObjectType::Method));
+    let strings = object_types.iter().any(|t| matches!(t, ObjectType::String));
+    if !types && !methods && !strings {
+        return vec![];
+    }
+    let assemblies = files.managed_assemblies();
+    let mut matches = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut matches));
+    iterator!(assemblies).for_each(|assembly| {
+        let metadata = match assembly.metadata() {
+            Ok(metadata) => metadata,
+            Err(e) => {
+                log::debug!(
+                    "Could not parse {} in {}: {}",
+                    assembly.name,
+                    assembly.file_name,
+                    e
+                );
+                return;
+            }
+        };
+        let place =
+            |token| Location::DotNetToken(assembly.file_name.clone(), assembly.name.clone(), token);
+        let mut assembly_matches = vec![];
+        if types {
+            assembly_matches.extend(
+                metadata
+                    .types
+                    .iter()
+                    .filter(|ty| reg.is_match(&ty.full_name))
+                    .map(|ty| {
+                        Evidence::String(StringEvidence {
+                            content: ty.full_name.clone(),
+                            place: place(ty.token),
+                            context: Context::DotNetType(
+                                Arc::new(ty.clone()),
+                                assembly.name.clone(),
+                            ),
+                            confidence_level: ConfidenceLevel::Medium,
+                            detail: None,
+                        })
+                    }),
+            );
+        }
+        if methods {
+            assembly_matches.extend(
+                metadata
+                    .methods
+                    .iter()
+                    .filter(|method| reg.is_match(&method.name))
+                    .map(|method| {
+                        Evidence::String(StringEvidence {
+                            content: method.name.clone(),
+                            place: place(method.token),
+                            context: Context::DotNetMethod(
+                                Arc::new(method.clone()),
+                                assembly.name.clone(),
+                            ),
+                            confidence_level: ConfidenceLevel::Medium,
+                            detail: None,
+                        })
+                    }),
+            );
+        }
+        if strings {
+            assembly_matches.extend(
+                metadata
+                    .user_strings
+                    .iter()
+                    .filter(|string| reg.is_match(&string.content))
+                    .map(|string| {
+                        Evidence::String(StringEvidence {
+                            content: string.content.clone(),
+                            place: place(string.token),
+                            context: Context::DotNetAssembly(
+                                assembly.file_name.clone(),
+                                assembly.name.clone(),
+                            ),
+                            confidence_level: ConfidenceLevel::Medium,
+                            detail: None,
+                        })
+                    }),
+            );
+        }
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(assembly_matches);
+        }
+    });
+    matches
+}
@@ -0,0 +1,769 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Disassembler and search for Hermes bytecode bundles of React Native apps. The opcodes change
+//! between bytecode versions and are read from tables (`data/hermes_opcodes.txt`). A table is only
+//! used if it decodes the functions of the bundle exactly, so an unknown version fails instead of
+//! producing garbage.
+
+use std::{
+    collections::{HashMap, HashSet},
+    convert::TryInto,
+    fmt::{Display, Formatter},
+    sync::{Arc, Mutex, OnceLock},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+use regex::Regex;
+
+use coeus_macros::iterator;
+use coeus_models::models::{BinaryObject, Files, HermesBytecode, HermesFunction};
+
+use super::{
+    ConfidenceLevel, Context, Evidence, InstructionEvidence, Location, ObjectType, StringEvidence,
+};
+
+const BUNDLED_OPCODES: &str = include_str!("../../data/hermes_opcodes.txt");
+/// Number of functions decoded to check that an opcode table fits the bundle
+const VALIDATED_FUNCTIONS: usize = 64;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HermesOperandType {
+    Reg8,
+    Reg32,
+    UInt8,
+    UInt16,
+    UInt32,
+    /// Signed jump offset relative to the instruction
+    Addr8,
+    Addr32,
+    Imm32,
+    Double,
+}
+
+impl HermesOperandType {
+    fn parse(operand_type: &str) -> Option<Self> {
+        Some(match operand_type {
+            "Reg8" => HermesOperandType::Reg8,
+            "Reg32" => HermesOperandType::Reg32,
+            "UInt8" => HermesOperandType::UInt8,
+            "UInt16" => HermesOperandType::UInt16,
+            "UInt32" => HermesOperandType::UInt32,
+            "Addr8" => HermesOperandType::Addr8,
+            "Addr32" => HermesOperandType::Addr32,
+            "Imm32" => HermesOperandType::Imm32,
+            "Double" => HermesOperandType::Double,
+            _ => return None,
+        })
+    }
+
+    pub fn size(&self) -> usize {
+        match self {
+            HermesOperandType::Reg8 | HermesOperandType::UInt8 | HermesOperandType::Addr8 => 1,
+            HermesOperandType::UInt16 => 2,
+            HermesOperandType::Reg32
+            | HermesOperandType::UInt32
+            | HermesOperandType::Addr32
+            | HermesOperandType::Imm32 => 4,
+            HermesOperandType::Double => 8,
+        }
+    }
+}
+
+/// The table of the bundle an operand indexes
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum HermesTable {
+    String,
+    Function,
+    BigInt,
+}
+
+impl HermesTable {
+    fn parse(table: &str) -> Option<Self> {
+        Some(match table {
+            "string" => HermesTable::String,
+            "function" => HermesTable::Function,
+            "bigint" => HermesTable::BigInt,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HermesOpcode {
+    pub name: String,
+    pub operands: Vec<(HermesOperandType, Option<HermesTable>)>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HermesOpcodeTable {
+    pub versions: Vec<u32>,
+    /// Indexed by the opcode
+    pub opcodes: Vec<HermesOpcode>,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HermesOpcodeTables {
+    pub tables: Vec<HermesOpcodeTable>,
+}
+
+impl HermesOpcodeTables {
+    /// The opcode tables bundled with coeus
+    pub fn bundled() -> Arc<HermesOpcodeTables> {
+        static BUNDLED: OnceLock<Arc<HermesOpcodeTables>> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| {
+                Arc::new(Self::parse(BUNDLED_OPCODES).expect("bundled Hermes opcodes are valid"))
+            })
+            .clone()
+    }
+
+    /// Parse opcode tables in the format of the bundled ones
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut tables = Self::default();
+        for (line_number, line) in content.lines().enumerate() {
+            let error = |msg: &str| format!("line {}: {}", line_number + 1, msg);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let name = tokens.next().unwrap_or_default();
+            if name == "versions" {
+                let versions = tokens
+                    .map(|version| {
+                        version
+                            .parse()
+                            .map_err(|_| error(&format!("invalid version {}", version)))
+                    })
+                    .collect::<Result<Vec<u32>, _>>()?;
+                if versions.is_empty() {
+                    return Err(error("missing version"));
+                }
+                tables.tables.push(HermesOpcodeTable {
+                    versions,
+                    opcodes: vec![],
+                });
+                continue;
+            }
+            let table = tables
+                .tables
+                .last_mut()
+                .ok_or_else(|| error("opcode outside of a table"))?;
+            if table.opcodes.len() > u8::MAX as usize {
+                return Err(error("too many opcodes"));
+            }
+            let operands = tokens
+                .map(|operand| {
+                    let (operand_type, index) = match operand.split_once(':') {
+                        Some((operand_type, table)) => (
+                            operand_type,
+                            Some(
+                                HermesTable::parse(table)
+                                    .ok_or_else(|| error(&format!("unknown table {}", table)))?,
+                            ),
+                        ),
+                        None => (operand, None),
+                    };
+                    let operand_type = HermesOperandType::parse(operand_type)
+                        .ok_or_else(|| error(&format!("unknown operand {}", operand_type)))?;
+                    Ok((operand_type, index))
+                })
+                .collect::<Result<Vec<_>, String>>()?;
+            table.opcodes.push(HermesOpcode {
+                name: name.to_string(),
+                operands,
+            });
+        }
+        Ok(tables)
+    }
+
+    /// All tables, the ones with versions closest to `version` first
+    pub fn candidates(&self, version: u32) -> Vec<&HermesOpcodeTable> {
+        let mut candidates: Vec<&HermesOpcodeTable> = self.tables.iter().collect();
+        candidates.sort_by_key(|table| {
+            table
+                .versions
+                .iter()
+                .map(|v| v.abs_diff(version))
+                .min()
+                .unwrap_or(u32::MAX)
+        });
+        candidates
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum HermesOperand {
+    Register(u32),
+    Unsigned(u32),
+    Signed(i32),
+    Double(f64),
+    /// File offset of the jump target
+    Jump(u32),
+    String(u32),
+    Function(u32),
+    BigInt(u32),
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct HermesInstruction {
+    /// File offset of the instruction
+    pub offset: u32,
+    pub opcode: u8,
+    pub mnemonic: String,
+    pub operands: Vec<HermesOperand>,
+    /// The operands with strings and function names resolved
+    pub op_str: String,
+}
+
+impl Display for HermesInstruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#x}: {} {}", self.offset, self.mnemonic, self.op_str)
+    }
+}
+
+impl HermesInstruction {
+    pub fn strings(&self) -> impl Iterator<Item = u32> + '_ {
+        self.operands.iter().filter_map(|operand| match operand {
+            HermesOperand::String(index) => Some(*index),
+            _ => None,
+        })
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = u32> + '_ {
+        self.operands.iter().filter_map(|operand| match operand {
+            HermesOperand::Function(index) => Some(*index),
+            _ => None,
+        })
+    }
+}
+
+pub struct HermesDisassembler {
+    object: Arc<BinaryObject>,
+    bytecode: HermesBytecode,
+    table: HermesOpcodeTable,
+}
+
+impl HermesDisassembler {
+    /// Parse the bundle and pick the bundled opcode table decoding it
+    pub fn new(object: Arc<BinaryObject>) -> Result<Self, String> {
+        Self::with_opcodes(object, &HermesOpcodeTables::bundled())
+    }
+
+    pub fn with_opcodes(
+        object: Arc<BinaryObject>,
+        tables: &HermesOpcodeTables,
+    ) -> Result<Self, String> {
+        let bytecode = HermesBytecode::parse(object.data())?;
+        let version = bytecode.header.version;
+        let mut disassembler = HermesDisassembler {
+            object,
+            bytecode,
+            table: HermesOpcodeTable::default(),
+        };
+        for table in tables.candidates(version) {
+            disassembler.table = table.clone();
+            let fits = disassembler
+                .bytecode
+                .functions
+                .iter()
+                .filter(|function| function.bytecode_size > 0)
+                .take(VALIDATED_FUNCTIONS)
+                .all(|function| disassembler.disassemble(function).is_ok());
+            if fits {
+                return Ok(disassembler);
+            }
+        }
+        Err(format!(
+            "no opcode table decodes bytecode version {}",
+            version
+        ))
+    }
+
+    pub fn bytecode(&self) -> &HermesBytecode {
+        &self.bytecode
+    }
+
+    pub fn object(&self) -> Arc<BinaryObject> {
+        self.object.clone()
+    }
+
+    /// The versions of the opcode table in use
+    pub fn opcode_versions(&self) -> &[u32] {
+        &self.table.versions
+    }
+
+    /// Decode the whole function. Fails if an opcode is unknown, the last instruction exceeds the
+    /// function or a jump does not target an instruction.
+    pub fn disassemble(&self, function: &HermesFunction) -> Result<Vec<HermesInstruction>, String> {
+        let start = function.offset as usize;
+        let code = self
+            .object
+            .data()
+            .get(start..start + function.bytecode_size as usize)
+            .ok_or_else(|| format!("function {} exceeds the file", function.index))?;
+        let error = |position: usize, msg: &str| {
+            format!(
+                "function {} at {:#x}: {}",
+                function.index,
+                start + position,
+                msg
+            )
+        };
+
+        let mut instructions = vec![];
+        let mut targets = vec![];
+        // the jump tables of `SwitchImm` follow the code
+        let mut end = code.len();
+        let mut position = 0;
+        while position < end {
+            if end < code.len() && end - position < 4 && code[position..end].iter().all(|b| *b == 0)
+            {
+                // alignment of the jump table
+                position = end;
+                break;
+            }
+            let opcode = code[position];
+            let definition = self
+                .table
+                .opcodes
+                .get(opcode as usize)
+                .ok_or_else(|| error(position, &format!("unknown opcode {:#x}", opcode)))?;
+            let mut cursor = position + 1;
+            let mut operands = Vec::with_capacity(definition.operands.len());
+            for (operand_type, index) in &definition.operands {
+                let bytes = code
+                    .get(cursor..cursor + operand_type.size())
+                    .ok_or_else(|| error(position, "instruction exceeds the function"))?;
+                cursor += operand_type.size();
+                let unsigned = match bytes.len() {
+                    1 => bytes[0] as u32,
+                    2 => u16::from_le_bytes(bytes.try_into().unwrap()) as u32,
+                    4 => u32::from_le_bytes(bytes.try_into().unwrap()),
+                    _ => 0,
+                };
+                let operand = match (operand_type, index) {
+                    (HermesOperandType::Double, _) => {
+                        HermesOperand::Double(f64::from_le_bytes(bytes.try_into().unwrap()))
+                    }
+                    (HermesOperandType::Addr8 | HermesOperandType::Addr32, _) => {
+                        let relative = if bytes.len() == 1 {
+                            bytes[0] as i8 as i64
+                        } else {
+                            unsigned as i32 as i64
+                        };
+                        let target = position as i64 + relative;
+                        if target < 0 || target >= end as i64 {
+                            return Err(error(position, "jump outside of the function"));
+                        }
+                        targets.push(target as usize);
+                        HermesOperand::Jump((start as i64 + target) as u32)
+                    }
+                    (HermesOperandType::Reg8 | HermesOperandType::Reg32, _) => {
+                        HermesOperand::Register(unsigned)
+                    }
+                    (HermesOperandType::Imm32, _) => HermesOperand::Signed(unsigned as i32),
+                    (_, Some(HermesTable::String)) => HermesOperand::String(unsigned),
+                    (_, Some(HermesTable::Function)) => HermesOperand::Function(unsigned),
+                    (_, Some(HermesTable::BigInt)) => HermesOperand::BigInt(unsigned),
+                    (_, None) => HermesOperand::Unsigned(unsigned),
+                };
+                operands.push(operand);
+            }
+            if definition.name == "SwitchImm" {
+                if let Some(HermesOperand::Unsigned(table_offset)) = operands.get(1) {
+                    end = end.min(position + *table_offset as usize);
+                }
+            }
+            instructions.push(HermesInstruction {
+                offset: (start + position) as u32,
+                opcode,
+                mnemonic: definition.name.clone(),
+                op_str: self.op_str(&operands),
+                operands,
+            });
+            position = cursor;
+        }
+        if position != end {
+            return Err(error(position, "instruction exceeds the function"));
+        }
+        for target in targets {
+            let target = (start + target) as u32;
+            if instructions
+                .binary_search_by_key(&target, |instruction| instruction.offset)
+                .is_err()
+            {
+                return Err(error(
+                    target as usize - start,
+                    "jump into the middle of an instruction",
+                ));
+            }
+        }
+        Ok(instructions)
+    }
+
+    fn op_str(&self, operands: &[HermesOperand]) -> String {
+        operands
+            .iter()
+            .map(|operand| match operand {
+                HermesOperand::Register(register) => format!("r{}", register),
+                HermesOperand::Unsigned(value) => value.to_string(),
+                HermesOperand::Signed(value) => value.to_string(),
+                HermesOperand::Double(value) => value.to_string(),
+                HermesOperand::Jump(target) => format!("{:#x}", target),
+                HermesOperand::String(index) => match self.bytecode.string(*index) {
+                    Some(string) => format!("{:?}", string),
+                    None => format!("<string {}>", index),
+                },
+                HermesOperand::Function(index) => match self.bytecode.function(*index) {
+                    Some(function) if !function.name.is_empty() => format!("<{}>", function.name),
+                    _ => format!("<function {}>", index),
+                },
+                HermesOperand::BigInt(index) => format!("<bigint {}>", index),
+            })
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+}
+
+/// Strings and function names of all Hermes bundles, for `ObjectType::String` and
+/// `ObjectType::Method` respectively or both for `ObjectType::Hermes`. Strings loaded by a
+/// function are reported once per function, with the function as context.
+pub fn find_string_matches_in_hermes(
+    reg: &Regex,
+    object_types: &[ObjectType],
+    files: &Files,
+) -> Vec<Evidence> {
+    let strings = object_types
+        .iter()
+        .any(|t| matches!(t, ObjectType::String | ObjectType::Hermes));
+    let functions = object_types
+        .iter()
+        .any(|t| matches!(t, ObjectType::Method | ObjectType::Hermes));
+    if !strings && !functions {
+        return vec![];
+    }
+    let mut matches = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut matches));
+    let binaries = &files.binaries;
+    iterator!(binaries).for_each(|(file_name, object)| {
+        if !HermesBytecode::is_hermes_bytecode(object.data()) {
+            return;
+        }
+        let disassembler = HermesDisassembler::new(object.clone()).ok();
+        let Some(bytecode) = disassembler
+            .as_ref()
+            .map(|disassembler| disassembler.bytecode().clone())
+            .or_else(|| object.hermes_bytecode())
+        else {
+            return;
+        };
+        let function_context =
+            |index: u32| Context::HermesFunction(object.clone(), file_name.clone(), index);
+        let mut bundle_matches = vec![];
+        if strings {
+            let matching: HashSet<u32> = bytecode
+                .strings
+                .iter()
+                .filter(|string| reg.is_match(&string.content))
+                .map(|string| string.index)
+                .collect();
+            let mut users: HashMap<u32, Vec<u32>> = HashMap::new();
+            if let (Some(disassembler), false) = (&disassembler, matching.is_empty()) {
+                for function in &bytecode.functions {
+                    let Ok(instructions) = disassembler.disassemble(function) else {
+                        continue;
+                    };
+                    let used: HashSet<u32> = instructions
+                        .iter()
+                        .flat_map(|instruction| instruction.strings())
+                        .filter(|index| matching.contains(index))
+                        .collect();
+                    for index in used {
+                        users.entry(index).or_default().push(function.index);
+                    }
+                }
+            }
+            for string in bytecode
+                .strings
+                .iter()
+                .filter(|string| matching.contains(&string.index))
+            {
+                let evidence = |context| {
+                    Evidence::String(StringEvidence {
+                        content: string.content.clone(),
+                        place: Location::HermesString(file_name.clone(), string.index),
+                        context,
+                        confidence_level: ConfidenceLevel::Medium,
+                        detail: None,
+                    })
+                };
+                match users.get(&string.index) {
+                    Some(functions) => bundle_matches.extend(
+                        functions
+                            .iter()
+                            .map(|function| evidence(function_context(*function))),
+                    ),
+                    None => {
+                        bundle_matches
+                            .push(evidence(Context::Binary(object.clone(), file_name.clone())));
+                    }
+                }
+            }
+        }
+        if functions {
+            bundle_matches.extend(
+                bytecode
+                    .functions
+                    .iter()
+                    .filter(|function| !function.name.is_empty() && reg.is_match(&function.name))
+                    .map(|function| {
+                        Evidence::String(StringEvidence {
+                            content: function.name.clone(),
+                            place: Location::HermesFunction(file_name.clone(), function.index),
+                            context: function_context(function.index),
+                            confidence_level: ConfidenceLevel::Medium,
+                            detail: None,
+                        })
+                    }),
+            );
+        }
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(bundle_matches);
+        }
+    });
+    matches
+}
+
+/// Search the disassembly of all functions of all Hermes bundles. The regex is matched against
+/// the textual form of each instruction, with strings and function names resolved (e.g.
+/// `0x1234: GetById r1, r0, 1, "fetch"`).
+pub fn find_hermes_instructions(
+    reg: &Regex,
+    files: &HashMap<String, Arc<BinaryObject>>,
+) -> Vec<Evidence> {
+    let mut matches = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut matches));
+    iterator!(files).for_each(|(file_name, object)| {
+        if !HermesBytecode::is_hermes_bytecode(object.data()) {
+            return;
+        }
+        let Ok(disassembler) = HermesDisassembler::new(object.clone()) else {
+            return;
+        };
+        let mut bundle_matches = vec![];
+        for function in &disassembler.bytecode().functions {
+            let Ok(instructions) = disassembler.disassemble(function) else {
+                continue;
+            };
+            for instruction in &instructions {
+                let text = instruction.to_string();
+                if !reg.is_match(&text) {
+                    continue;
+                }
+                bundle_matches.push(Evidence::Instructions(InstructionEvidence {
+                    instructions: vec![text],
+                    place: Location::HermesInstruction(file_name.to_string(), instruction.offset),
+                    context: Context::HermesFunction(
+                        object.clone(),
+                        file_name.to_string(),
+                        function.index,
+                    ),
+                    confidence_level: ConfidenceLevel::Medium,
+                }));
+            }
+        }
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(bundle_matches);
+        }
+    });
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use coeus_models::models::{HermesHeader, HermesString};
+
+    use super::*;
+
+    const OPCODES: &str = "
+        # a small table
+        versions 90 91
+        Ret Reg8
+        LoadConstString Reg8 UInt16:string
+        Jmp Addr8
+        CreateClosure Reg8 Reg8 UInt16:function
+        SwitchImm Reg8 UInt32 Addr32 UInt32 UInt32
+    ";
+    const CODE_OFFSET: u32 = 0x10;
+
+    fn function(index: u32, name: &str, offset: u32, bytecode_size: u32) -> HermesFunction {
+        HermesFunction {
+            index,
+            name_index: 0,
+            name: name.to_string(),
+            offset,
+            bytecode_size,
+            param_count: 1,
+            frame_size: 3,
+            environment_size: 0,
+            flags: 0,
+        }
+    }
+
+    fn disassembler(code: &[u8]) -> HermesDisassembler {
+        let mut data = vec![0u8; CODE_OFFSET as usize];
+        data.extend_from_slice(code);
+        HermesDisassembler {
+            object: Arc::new(BinaryObject::new(data)),
+            bytecode: HermesBytecode {
+                header: HermesHeader {
+                    version: 90,
+                    ..Default::default()
+                },
+                functions: vec![
+                    function(0, "", CODE_OFFSET, code.len() as u32),
+                    function(1, "main", 0, 0),
+                ],
+                strings: vec![HermesString {
+                    index: 0,
+                    content: "hello".to_string(),
+                    identifier: false,
+                }],
+            },
+            table: HermesOpcodeTables::parse(OPCODES).unwrap().tables[0].clone(),
+        }
+    }
+
+    fn disassemble(code: &[u8]) -> Result<Vec<String>, String> {
+        let disassembler = disassembler(code);
+        let function = &disassembler.bytecode().functions[0];
+        Ok(disassembler
+            .disassemble(function)?
+            .iter()
+            .map(|instruction| instruction.to_string())
+            .collect())
+    }
+
+    #[test]
+    fn bundled_opcodes_are_valid() {
+        let tables = HermesOpcodeTables::bundled();
+        assert!(!tables.tables.is_empty());
+        for table in &tables.tables {
+            assert!(!table.versions.is_empty());
+            assert!(table.opcodes.len() <= 256);
+            assert!(table.opcodes.iter().any(|opcode| opcode.name == "Ret"));
+        }
+    }
+
+    #[test]
+    fn parses_opcode_tables() {
+        let tables = HermesOpcodeTables::parse(OPCODES).unwrap();
+        assert_eq!(tables.tables.len(), 1);
+        assert_eq!(tables.tables[0].versions, vec![90, 91]);
+        assert_eq!(
+            tables.tables[0].opcodes[1],
+            HermesOpcode {
+                name: "LoadConstString".to_string(),
+                operands: vec![
+                    (HermesOperandType::Reg8, None),
+                    (HermesOperandType::UInt16, Some(HermesTable::String)),
+                ],
+            }
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_opcode_tables() {
+        for content in [
+            "Ret Reg8",
+            "versions",
+            "versions 9x",
+            "versions 1\nRet Reg9",
+            "versions 1\nLoad UInt16:regexp",
+        ] {
+            assert!(HermesOpcodeTables::parse(content).is_err(), "{}", content);
+        }
+        let too_many = format!("versions 1\n{}", "Nop\n".repeat(257));
+        assert_eq!(
+            HermesOpcodeTables::parse(&too_many).unwrap_err(),
+            "line 258: too many opcodes"
+        );
+        assert_eq!(HermesOpcodeTables::parse("").unwrap().tables.len(), 0);
+    }
+
+    #[test]
+    fn orders_candidates_by_version() {
+        let tables =
+            HermesOpcodeTables::parse("versions 84\nRet\nversions 96\nRet\nversions 89 90\nRet")
+                .unwrap();
+        let versions = |version| -> Vec<u32> {
+            tables
+                .candidates(version)
+                .iter()
+                .map(|table| table.versions[0])
+                .collect()
+        };
+        assert_eq!(versions(96), vec![96, 89, 84]);
+        assert_eq!(versions(86), vec![84, 89, 96]);
+        assert_eq!(versions(91), vec![89, 96, 84]);
+    }
+
+    #[test]
+    fn disassembles_functions() {
+        let instructions = disassemble(&[1, 1, 0, 0, 3, 2, 0, 1, 0, 2, 2, 0, 1]).unwrap();
+        assert_eq!(
+            instructions,
+            vec![
+                "0x10: LoadConstString r1, \"hello\"",
+                "0x14: CreateClosure r2, r0, <main>",
+                "0x19: Jmp 0x1b",
+                "0x1b: Ret r1",
+            ]
+        );
+        let disassembler = disassembler(&[1, 1, 0, 0, 3, 2, 0, 1, 0]);
+        let instructions = disassembler
+            .disassemble(&disassembler.bytecode().functions[0])
+            .unwrap();
+        assert_eq!(instructions[0].strings().collect::<Vec<_>>(), vec![0]);
+        assert_eq!(instructions[1].functions().collect::<Vec<_>>(), vec![1]);
+    }
+
+    #[test]
+    fn skips_jump_tables() {
+        let mut code = vec![4, 0];
+        code.extend_from_slice(&22u32.to_le_bytes());
+        code.extend_from_slice(&18u32.to_le_bytes());
+        code.extend_from_slice(&[0; 8]);
+        code.extend_from_slice(&[0, 1, 0, 0]);
+        code.extend_from_slice(&0xffff_fffcu32.to_le_bytes());
+        let instructions = disassemble(&code).unwrap();
+        assert_eq!(
+            instructions,
+            vec!["0x10: SwitchImm r0, 22, 0x22, 0, 0", "0x22: Ret r1"]
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_code() {
+        // unknown opcode, truncated operand, jumps out of the function and into an instruction
+        for code in [&[0xff][..], &[1, 1, 0], &[2, 3], &[2, 0xfe], &[2, 1, 0, 0]] {
+            assert!(disassemble(code).is_err(), "{:?}", code);
+        }
+        // a jump table offset before the end of the instruction
+        let mut code = vec![4, 0];
+        code.extend_from_slice(&2u32.to_le_bytes());
+        code.extend_from_slice(&[0; 12]);
+        assert!(disassemble(&code).is_err());
+
+        let disassembler = disassembler(&[0, 0]);
+        let beyond = function(2, "", CODE_OFFSET, u32::MAX);
+        assert!(disassembler.disassemble(&beyond).is_err());
+        assert_eq!(disassemble(&[]).unwrap(), Vec::<String>::new());
+    }
+}
@@ -0,0 +1,336 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Search in the IL2CPP metadata of Unity games and link its method definitions to their code in
+//! `libil2cpp.so`.
+//!
+//! Since metadata version 24.2 the code registration of `libil2cpp.so` holds one
+//! `Il2CppCodeGenModule` per image:
+//!
+//! ```c
+//! struct Il2CppCodeGenModule {
+//!     const char* moduleName;
+//!     uint32_t methodPointerCount;
+//!     const Il2CppMethodPointer* methodPointers;
+//!     ...
+//! };
+//! ```
+//!
+//! The method pointers are indexed by the row of the method token. Instead of locating the code
+//! registration through the (stripped) initialization code, the modules are found through the
+//! pointer to their name and are accepted if the method count matches the metadata. Older
+//! versions use one global table indexed by `methodIndex` and are not mapped.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use goblin::Object;
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+use regex::Regex;
+
+use coeus_macros::iterator;
+use coeus_models::models::{BinaryObject, CpuArch, Files, Il2CppMetadata};
+
+use super::native::RelocatedImage;
+use super::{ConfidenceLevel, Context, Evidence, Location, ObjectType, StringEvidence};
+
+/// The native code of an IL2CPP method definition
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Il2CppMethodPointer {
+    /// Index of the method definition in the metadata
+    pub method: u32,
+    /// The declaring type and the method, e.g. `Game.Player::Update`
+    pub name: String,
+    /// Address of the function, without the thumb bit
+    pub address: u64,
+    pub thumb: bool,
+}
+
+/// Map the method definitions of `metadata` to their functions in `libil2cpp.so`. Methods
+/// without a body (abstract, extern, generic definitions) have no pointer and are left out.
+pub fn find_il2cpp_method_pointers(
+    bin_elf: &BinaryObject,
+    metadata: &Il2CppMetadata,
+) -> Vec<Il2CppMethodPointer> {
+    if metadata.version == 24 && metadata.sub_version < 2 {
+        log::debug!(
+            "Metadata version 24.{} has no code gen modules",
+            metadata.sub_version
+        );
+        return vec![];
+    }
+    let elf = if let Some(Object::Elf(elf)) = bin_elf.object_no_cache() {
+        elf
+    } else {
+        return vec![];
+    };
+    let Some(image) = RelocatedImage::new(&elf, bin_elf.data()) else {
+        return vec![];
+    };
+    let thumb_mask = if image.library.arch == Some(CpuArch::ArmV7) {
+        1
+    } else {
+        0
+    };
+    let pointer_size = image.pointer_size();
+
+    // the method rows of the images, the number of method pointers of their module
+    let mut image_methods = vec![vec![]; metadata.images.len()];
+    for ty in &metadata.types {
+        if let Some(methods) = image_methods.get_mut(ty.image as usize) {
+            methods.extend(metadata.methods_of(ty));
+        }
+    }
+
+    let names = image_names(&image, bin_elf.data(), metadata);
+    let mut slots: HashMap<u64, Vec<u64>> = HashMap::new();
+    for (slot, value) in image.pointer_slots() {
+        slots.entry(value).or_default().push(slot);
+    }
+
+    let mut pointers = vec![];
+    for (il2cpp_image, methods) in metadata.images.iter().zip(image_methods) {
+        let modules = names
+            .get(il2cpp_image.name.as_str())
+            .into_iter()
+            .flatten()
+            .flat_map(|address| slots.get(address).into_iter().flatten());
+        // the count is a `uint32_t`, padded to the pointer size (Android is little endian)
+        let method_pointers = modules
+            .filter(|module| {
+                module
+                    .checked_add(pointer_size)
+                    .and_then(|count| image.pointer(count))
+                    .map(|count| count & 0xffff_ffff == methods.len() as u64)
+                    .unwrap_or(false)
+            })
+            .find_map(|module| image.pointer(module.checked_add(2 * pointer_size)?));
+        let Some(method_pointers) = method_pointers else {
+            log::debug!("No code gen module for {}", il2cpp_image.name);
+            continue;
+        };
+        for method in &methods {
+            let row = (method.token & 0x00ff_ffff) as u64;
+            if row == 0 || row > methods.len() as u64 {
+                continue;
+            }
+            let slot = method_pointers.checked_add((row - 1) * pointer_size);
+            let function = match slot.and_then(|slot| image.pointer(slot)) {
+                Some(function) if image.is_code(function & !thumb_mask) => function,
+                _ => continue,
+            };
+            let ty = metadata.types.get(method.declaring_type as usize);
+            pointers.push(Il2CppMethodPointer {
+                method: method.index,
+                name: format!(
+                    "{}::{}",
+                    ty.map(|ty| ty.full_name.as_str()).unwrap_or_default(),
+                    method.name
+                ),
+                address: function & !thumb_mask,
+                thumb: function & thumb_mask != 0,
+            });
+        }
+    }
+    pointers
+}
+
+/// The addresses of the image names (e.g. `Assembly-CSharp.dll`) in the data regions. Names
+/// can share their storage with longer strings ending the same way.
+fn image_names<'m>(
+    image: &RelocatedImage,
+    data: &[u8],
+    metadata: &'m Il2CppMetadata,
+) -> HashMap<&'m str, Vec<u64>> {
+    let mut names: HashMap<&str, Vec<u64>> = HashMap::new();
+    for (start, end) in image.data_regions() {
+        let Some(offset) = image.library.file_offset(start) else {
+            continue;
+        };
+        let Some(region) = offset
+            .checked_add(end.saturating_sub(start))
+            .and_then(|region_end| data.get(offset as usize..region_end as usize))
+        else {
+            continue;
+        };
+        for (position, _) in region
+            .windows(5)
+            .enumerate()
+            .filter(|(_, window)| window == b".dll\0")
+        {
+            let name_end = position + 4;
+            for il2cpp_image in &metadata.images {
+                let name = il2cpp_image.name.as_str();
+                if name_end >= name.len()
+                    && &region[name_end - name.len()..name_end] == name.as_bytes()
+                {
+                    names
+                        .entry(name)
+                        .or_default()
+                        .push(start + (name_end - name.len()) as u64);
+                }
+            }
+        }
+    }
+    names
+}
+
+/// Types (`ObjectType::Class`, `ObjectType::Type`), methods (`ObjectType::Method`) and string
+/// literals (`ObjectType::String`) of the IL2CPP metadata. Methods are placed at their function
+/// in each `libil2cpp.so`, or at their token if they could not be mapped.
+pub fn find_string_matches_in_il2cpp(
+    reg: &Regex,
+    object_types: &[ObjectType],
+    files: &Files,
+) -> Vec<Evidence> {
+    let types = object_types
+        .iter()
+        .any(|t| matches!(t, ObjectType::Class | ObjectType::Type));
+    let methods = object_types.iter().any(|t| matches!(t, ObjectType::Method));
+    let strings = object_types.iter().any(|t| matches!(t, ObjectType::String));
+    if !types && !methods && !strings {
+        return vec![];
+    }
+    let all_metadata = files.il2cpp_metadata();
+    let libraries: Vec<_> = files
+        .binaries
+        .iter()
+        .filter(|(name, _)| name.ends_with("libil2cpp.so"))
+        .collect();
+    let mut matches = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut matches));
+    iterator!(all_metadata).for_each(|(file_name, metadata)| {
+        let image_name = |index: u32| {
+            metadata
+                .images
+                .get(index as usize)
+                .map(|image| image.name.clone())
+                .unwrap_or_default()
+        };
+        let place = |image: u32, token| {
+            Location::DotNetToken(file_name.to_string(), image_name(image), token)
+        };
+        let mut metadata_matches = vec![];
+        if types {
+            metadata_matches.extend(
+                metadata
+                    .types
+                    .iter()
+                    .filter(|ty| reg.is_match(&ty.full_name))
+                    .map(|ty| {
+                        Evidence::String(StringEvidence {
+                            content: ty.full_name.clone(),
+                            place: place(ty.image, ty.token),
+                            context: Context::Il2CppType(
+                                Arc::new(ty.clone()),
+                                image_name(ty.image),
+                            ),
+                            confidence_level: ConfidenceLevel::Medium,
+                            detail: None,
+                        })
+                    }),
+            );
+        }
+        if methods {
+            let matching: Vec<_> = metadata
+                .methods
+                .iter()
+                .filter(|method| reg.is_match(&method.name))
+                .collect();
+            let mut functions: HashMap<u32, Vec<(&str, u64)>> = HashMap::new();
+            if !matching.is_empty() {
+                for (library, object) in &libraries {
+                    for pointer in find_il2cpp_method_pointers(object, metadata) {
+                        functions
+                            .entry(pointer.method)
+                            .or_default()
+                            .push((library.as_str(), pointer.address | pointer.thumb as u64));
+                    }
+                }
+            }
+            for method in matching {
+                let image = metadata
+                    .types
+                    .get(method.declaring_type as usize)
+                    .map(|ty| ty.image)
+                    .unwrap_or_default();
+                let context = Context::Il2CppMethod(Arc::new(method.clone()), image_name(image));
+                let places = match functions.get(&method.index) {
+                    Some(functions) => functions
+                        .iter()
+                        .map(|(library, address)| {
+                            Location::NativeAddress(library.to_string(), *address)
+                        })
+                        .collect(),
+                    None => vec![place(image, method.token)],
+                };
+                metadata_matches.extend(places.into_iter().map(|place| {
+                    Evidence::String(StringEvidence {
+                        content: method.name.clone(),
+                        place,
+                        context: context.clone(),
+                        confidence_level: ConfidenceLevel::Medium,
+                        detail: None,
+                    })
+                }));
+            }
+        }
+        if strings {
+            if let Some(object) = files.binaries.get(*file_name) {
+                metadata_matches.extend(
+                    metadata
+                        .string_literals
+                        .iter()
+                        .filter(|literal| reg.is_match(&literal.content))
+                        .map(|literal| {
+                            Evidence::String(StringEvidence {
+                                content: literal.content.clone(),
+                                place: Location::Il2CppStringLiteral(
+                                    file_name.to_string(),
+                                    literal.index,
+                                ),
+                                context: Context::Binary(object.clone(), file_name.to_string()),
+                                confidence_level: ConfidenceLevel::Medium,
+                                detail: None,
+                            })
+                        }),
+                );
+            }
+        }
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(metadata_matches);
+        }
+    });
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn metadata(version: u32, sub_version: u32) -> Il2CppMetadata {
+        Il2CppMetadata {
+            version,
+            sub_version,
+            images: vec![],
+            types: vec![],
+            methods: vec![],
+            string_literals: vec![],
+        }
+    }
+
+    #[test]
+    fn maps_nothing_without_code_gen_modules() {
+        let object = BinaryObject::new(b"\x7fELF\x02\x01\x01".to_vec());
+        assert!(find_il2cpp_method_pointers(&object, &metadata(24, 1)).is_empty());
+        assert!(find_il2cpp_method_pointers(&object, &metadata(27, 0)).is_empty());
+        let object = BinaryObject::new(vec![0xff; 64]);
+        assert!(find_il2cpp_method_pointers(&object, &metadata(29, 0)).is_empty());
+    }
+}
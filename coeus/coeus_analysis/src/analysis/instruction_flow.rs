@@ -1394,6 +1394,7 @@ impl InstructionFlow {
 
                     // invocations
                     Instruction::Invoke(_) => {}
+                    Instruction::InvokeCustom(_) => {}
                     Instruction::InvokeType(_) => {}
 
                     Instruction::InvokeInterface(_, method, ref regs) => {
@@ -1959,6 +1960,7 @@ fn is_function_call(instruction: &Instruction) -> bool {
     matches!(
         instruction,
         Instruction::Invoke(..)
+            | Instruction::InvokeCustom(..)
             | Instruction::InvokeDirect(..)
             | Instruction::InvokeDirectRange(..)
             | Instruction::InvokeInterface(..)
@@ -16,17 +16,38 @@ use regex::Regex;
 use rayon::iter::{IndexedParallelIterator, ParallelIterator};
 
 use coeus_macros::iterator;
-use coeus_models::models::{BinaryObject, Class, DexFile, Field, Files, Method, Proto};
+use coeus_models::models::{
+    ArchiveAnomalyKind, BinaryObject, Class, DexFile, DotNetMethod, DotNetType, Field, Files,
+    Il2CppMethodDefinition, Il2CppTypeDefinition, Method, Proto,
+};
 use serde::Serializer;
 
 use self::{
+    dart::find_string_matches_in_dart,
     dex::find_string_matches_in_dex_with_type,
+    dotnet::find_string_matches_in_dotnet,
+    hermes::find_string_matches_in_hermes,
+    il2cpp::find_string_matches_in_il2cpp,
     native::{find_string_matches_in_elf, BinaryContent},
+    resources::find_string_matches_in_resources,
 };
 
+pub mod dart;
+pub mod deeplinks;
 pub mod dex;
+pub mod dotnet;
+pub mod hermes;
+pub mod il2cpp;
 pub mod instruction_flow;
 pub mod native;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native_disassembly;
+pub mod native_libraries;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod native_references;
+pub mod packers;
+pub mod permissions;
+pub mod resources;
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
 pub struct ClassEvidences {
@@ -81,6 +102,9 @@ pub struct StringEvidence {
     pub place: Location,
     pub context: Context,
     pub confidence_level: ConfidenceLevel,
+    /// Additional information on the match, e.g. the signature of a registered native method
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<String>,
 }
 
 #[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
@@ -119,6 +143,18 @@ pub enum Context {
     #[serde(skip_serializing, skip_deserializing)]
     NativeLib(Arc<BinaryObject>, String, u64, bool, Sym),
     Binary(Arc<BinaryObject>, String),
+    /// A function of a Hermes bundle by its index
+    HermesFunction(Arc<BinaryObject>, String, u32),
+    /// A type of a .NET assembly and the name of the assembly
+    DotNetType(Arc<DotNetType>, String),
+    DotNetMethod(Arc<DotNetMethod>, String),
+    /// A .NET assembly by the file containing it and its name
+    DotNetAssembly(String, String),
+    /// A type of the IL2CPP metadata and the name of its image
+    Il2CppType(Arc<Il2CppTypeDefinition>, String),
+    Il2CppMethod(Arc<Il2CppMethodDefinition>, String),
+    /// An archive (apk, jar, nested zip) by its name
+    Archive(String),
 }
 // impl<'a> From<&'a Context> for (Arc<Class>, Arc<DexFile>) {
 //     fn from(value: &'a Context) -> Self {
@@ -164,6 +200,15 @@ impl std::fmt::Debug for Context {
             Context::DexMethod(m, _) => f = f.field("method", m),
             Context::DexType(_, name, _) => f = f.field("type", name),
             Context::DexProto(p, _) => f = f.field("proto", p),
+            Context::Binary(_, file_name) => f = f.field("binary", file_name),
+            Context::HermesFunction(_, file_name, index) => {
+                f = f.field("binary", file_name).field("hermes_function", index)
+            }
+            Context::DotNetType(t, _) => f = f.field("type", &t.full_name),
+            Context::DotNetMethod(m, _) => f = f.field("method", m),
+            Context::DotNetAssembly(_, name) => f = f.field("assembly", name),
+            Context::Il2CppType(t, _) => f = f.field("type", &t.full_name),
+            Context::Il2CppMethod(m, _) => f = f.field("method", m),
             _ => f = f.field("obj", &self),
         };
         f.finish()
@@ -180,6 +225,20 @@ pub enum Location {
     NativeSymbol,
     NativeLibLoad,
     NativePattern(String, usize),
+    /// A virtual address inside a native library
+    NativeAddress(String, u64),
+    /// An entry of an archive, `None` refers to the archive itself
+    ArchiveEntry(String, Option<String>),
+    /// A string of a Hermes bundle by its index
+    HermesString(String, u32),
+    /// A function of a Hermes bundle by its index
+    HermesFunction(String, u32),
+    /// The file offset of an instruction in a Hermes bundle
+    HermesInstruction(String, u32),
+    /// A metadata token of a .NET assembly, by the file containing the assembly and its name
+    DotNetToken(String, String, u32),
+    /// A string literal of the IL2CPP metadata by its index
+    Il2CppStringLiteral(String, u32),
     Unknown,
 }
 impl Location {
@@ -199,11 +258,7 @@ impl Location {
                         )))
                     })
                 } else {
-                    if let Some(method) = f
-                        .methods
-                        .iter()
-                        .find(|m| m.method_idx == (*method_idx as u16))
-                    {
+                    if let Some(method) = f.methods.get(*method_idx as usize) {
                         Some(Arc::new(Class::new(
                             "NONE".to_string(),
                             0,
@@ -266,8 +321,10 @@ pub enum ObjectType {
     Field,
     Proto,
     StaticData,
+    /// Strings and functions of Hermes bundles
+    Hermes,
 }
-pub const ALL_TYPES: [ObjectType; 7] = [
+pub const ALL_TYPES: [ObjectType; 8] = [
     ObjectType::Method,
     ObjectType::Class,
     ObjectType::Type,
@@ -275,6 +332,7 @@ pub const ALL_TYPES: [ObjectType; 7] = [
     ObjectType::Field,
     ObjectType::Proto,
     ObjectType::StaticData,
+    ObjectType::Hermes,
 ];
 
 const CLASSES: [ObjectType; 2] = [ObjectType::Class, ObjectType::Type];
@@ -288,28 +346,53 @@ const STRINGS: [ObjectType; 1] = [ObjectType::String];
 pub fn find_all_matches(reg: &Regex, files: &Files) -> Vec<Evidence> {
     let mut matches = find_string_matches_in_dex_with_type(&reg, &ALL_TYPES, &files.multi_dex);
     matches.extend(find_string_matches_in_elf(&reg, &files.binaries, false));
+    matches.extend(find_string_matches_in_resources(reg, files));
+    matches.extend(find_string_matches_in_dart(reg, &ALL_TYPES, files));
+    matches.extend(find_string_matches_in_hermes(reg, &ALL_TYPES, files));
+    matches.extend(find_string_matches_in_dotnet(reg, &ALL_TYPES, files));
+    matches.extend(find_string_matches_in_il2cpp(reg, &ALL_TYPES, files));
     matches
 }
 pub fn find_classes(reg: &Regex, files: &Files) -> Vec<Evidence> {
-    find_string_matches_in_dex_with_type(reg, &CLASSES, &files.multi_dex)
+    let mut matches = find_string_matches_in_dex_with_type(reg, &CLASSES, &files.multi_dex);
+    matches.extend(find_string_matches_in_dotnet(reg, &CLASSES, files));
+    matches.extend(find_string_matches_in_il2cpp(reg, &CLASSES, files));
+    matches
 }
 
 pub fn find_methods(reg: &Regex, files: &Files) -> Vec<Evidence> {
-    find_string_matches_in_dex_with_type(reg, &METHODS, &files.multi_dex)
+    let mut matches = find_string_matches_in_dex_with_type(reg, &METHODS, &files.multi_dex);
+    matches.extend(find_string_matches_in_dotnet(reg, &METHODS, files));
+    matches.extend(find_string_matches_in_il2cpp(reg, &METHODS, files));
+    matches
 }
 pub fn find_fields(reg: &Regex, files: &Files) -> Vec<Evidence> {
     find_string_matches_in_dex_with_type(reg, &FIELDS, &files.multi_dex)
 }
 
 pub fn find_strings(reg: &Regex, files: &Files) -> Vec<Evidence> {
-    find_string_matches_in_dex_with_type(&reg, &STRINGS, &files.multi_dex)
+    let mut matches = find_string_matches_in_dex_with_type(reg, &STRINGS, &files.multi_dex);
+    matches.extend(find_string_matches_in_resources(reg, files));
+    matches.extend(find_string_matches_in_dart(reg, &STRINGS, files));
+    matches.extend(find_string_matches_in_hermes(reg, &STRINGS, files));
+    matches.extend(find_string_matches_in_dotnet(reg, &STRINGS, files));
+    matches.extend(find_string_matches_in_il2cpp(reg, &STRINGS, files));
+    matches
 }
 pub fn find_strings_native(reg: &Regex, files: &Files, only_symbols: bool) -> Vec<Evidence> {
     find_string_matches_in_elf(&reg, &files.binaries, only_symbols)
 }
 
 pub fn find_any(reg: &Regex, object_types: &[ObjectType], files: &Files) -> Vec<Evidence> {
-    find_string_matches_in_dex_with_type(reg, object_types, &files.multi_dex)
+    let mut matches = find_string_matches_in_dex_with_type(reg, object_types, &files.multi_dex);
+    if object_types.iter().any(|t| matches!(t, ObjectType::String)) {
+        matches.extend(find_string_matches_in_resources(reg, files));
+    }
+    matches.extend(find_string_matches_in_dart(reg, object_types, files));
+    matches.extend(find_string_matches_in_hermes(reg, object_types, files));
+    matches.extend(find_string_matches_in_dotnet(reg, object_types, files));
+    matches.extend(find_string_matches_in_il2cpp(reg, object_types, files));
+    matches
 }
 
 pub fn get_methods(files: &Files) -> Vec<Evidence> {
@@ -326,6 +409,7 @@ pub fn get_methods(files: &Files) -> Vec<Evidence> {
                     place: Location::DexMethod(m.method_idx as u32, dex.clone()),
                     context: Context::DexMethod(m.clone(), dex.clone()),
                     confidence_level: ConfidenceLevel::Medium,
+                    detail: None,
                 })
             })
             .collect();
@@ -335,3 +419,38 @@ pub fn get_methods(files: &Files) -> Vec<Evidence> {
     });
     all_methods
 }
+
+/// Report all anomalies found while reading the archives. The confidence reflects how likely
+/// the anomaly was introduced on purpose to break analysis tools.
+pub fn find_archive_anomalies(files: &Files) -> Vec<Evidence> {
+    files
+        .archive_anomalies
+        .iter()
+        .map(|anomaly| {
+            let confidence_level = match anomaly.kind {
+                ArchiveAnomalyKind::EncryptionFlag
+                | ArchiveAnomalyKind::DuplicateEntry
+                | ArchiveAnomalyKind::UnsupportedCompression(_)
+                | ArchiveAnomalyKind::LocalHeaderMismatch(_)
+                | ArchiveAnomalyKind::CompressionRatioExceeded(_) => ConfidenceLevel::High,
+                ArchiveAnomalyKind::EntryCountMismatch { .. }
+                | ArchiveAnomalyKind::SizeMismatch { .. }
+                | ArchiveAnomalyKind::CrcMismatch { .. }
+                | ArchiveAnomalyKind::SizeLimitExceeded(_)
+                | ArchiveAnomalyKind::TotalSizeLimitExceeded(_)
+                | ArchiveAnomalyKind::SuspiciousName => ConfidenceLevel::Medium,
+                ArchiveAnomalyKind::MissingCentralDirectory
+                | ArchiveAnomalyKind::CorruptCentralDirectory
+                | ArchiveAnomalyKind::RecoveredFromLocalHeaders
+                | ArchiveAnomalyKind::Truncated => ConfidenceLevel::Low,
+            };
+            Evidence::String(StringEvidence {
+                content: anomaly.to_string(),
+                place: Location::ArchiveEntry(anomaly.archive.clone(), anomaly.entry.clone()),
+                context: Context::Archive(anomaly.archive.clone()),
+                confidence_level,
+                detail: None,
+            })
+        })
+        .collect()
+}
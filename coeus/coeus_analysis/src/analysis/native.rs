@@ -5,18 +5,22 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
-    collections::HashMap,
-    ffi::CStr,
-    io::{BufRead, Cursor},
+    collections::{HashMap, HashSet},
+    convert::{TryFrom, TryInto},
     sync::{Arc, Mutex},
 };
 
 use goblin::{
-    elf::{Elf, Reloc, Sym},
-    elf64::{
-        program_header::PT_LOAD,
-        reloc::{R_386_RELATIVE, R_AARCH64_RELATIVE, R_ARM_RELATIVE, R_X86_64_RELATIVE},
+    elf::{
+        reloc::{
+            R_386_32, R_386_GLOB_DAT, R_386_RELATIVE, R_AARCH64_ABS64, R_AARCH64_GLOB_DAT,
+            R_AARCH64_RELATIVE, R_ARM_ABS32, R_ARM_GLOB_DAT, R_ARM_RELATIVE, R_X86_64_64,
+            R_X86_64_GLOB_DAT, R_X86_64_RELATIVE,
+        },
+        section_header::{SHF_ALLOC, SHF_EXECINSTR, SHT_PROGBITS},
+        Elf, Sym,
     },
+    elf64::program_header::PT_LOAD,
     Object,
 };
 #[cfg(not(target_arch = "wasm32"))]
@@ -26,9 +30,15 @@ use rayon::prelude::ParallelSlice;
 use regex::Regex;
 
 use coeus_macros::{iterator, windows};
-use coeus_models::models::BinaryObject;
+pub use coeus_models::models::CpuArch;
+use coeus_models::models::{BinaryObject, DexFile, Files, Method, NativeLibrary};
 
-use super::{ByteEvidence, ConfidenceLevel, Context, Evidence, Location, StringEvidence};
+#[cfg(not(target_arch = "wasm32"))]
+use super::native_references::string_users;
+use super::{
+    dex::get_native_methods, ByteEvidence, ConfidenceLevel, Context, CrossReferenceEvidence,
+    Evidence, Location, StringEvidence,
+};
 
 #[derive(Clone, Copy, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
 pub enum BinaryContent {
@@ -102,6 +112,7 @@ pub fn find_string_matches_in_elf(
                                 Sym::default(),
                             ),
                             confidence_level: ConfidenceLevel::Medium,
+                            detail: None,
                             place: Location::NativeLibLoad,
                         })
                     })
@@ -119,6 +130,7 @@ pub fn find_string_matches_in_elf(
                             content: elf.strtab.get_at(sym.st_name)?.to_string(),
                             context: Context::NativeSymbol(object.clone(), file_name.to_string()),
                             confidence_level: ConfidenceLevel::Medium,
+                            detail: None,
                             place: Location::NativeSymbol,
                         }))
                     })
@@ -136,6 +148,7 @@ pub fn find_string_matches_in_elf(
                     tmp_matches.push(Evidence::String(StringEvidence {
                         content: mat.as_str().to_string(),
                         confidence_level: ConfidenceLevel::Low,
+                        detail: None,
                         context: Context::Binary(object.clone(), file_name.to_string()),
                         place: Location::Unknown,
                     }));
@@ -179,6 +192,7 @@ pub fn find_exported_functions(reg: &Regex, bin_elf: Arc<BinaryObject>) -> Vec<E
                     sym,
                 ),
                 confidence_level: ConfidenceLevel::Medium,
+                detail: None,
             };
             evidences.push(Evidence::String(evidence));
         }
@@ -219,6 +233,7 @@ pub fn find_imported_functions(reg: &Regex, bin_elf: Arc<BinaryObject>) -> Vec<E
                     sym,
                 ),
                 confidence_level: ConfidenceLevel::Medium,
+                detail: None,
             };
             evidences.push(Evidence::String(evidence));
         }
@@ -238,7 +253,75 @@ pub fn find_strings(reg: &Regex, bin_elf: Arc<BinaryObject>) -> Vec<Evidence> {
         return vec![];
     };
     let mut evidences = vec![];
+    for (section_offset, _, offset, string) in rodata_strings(reg, &elf, bin_elf.data()) {
+        let evidence = StringEvidence {
+            content: string.clone(),
+            place: Location::NativeLibLoad,
+            context: Context::NativeLib(
+                bin_elf.clone(),
+                string,
+                section_offset + offset,
+                false,
+                Sym::default(),
+            ),
+            confidence_level: ConfidenceLevel::Medium,
+            detail: None,
+        };
+        evidences.push(Evidence::String(evidence));
+    }
+    // let relocs = elf.dynrelas.iter().find(|a| a.)
+
+    for str in strings {
+        if reg.is_match(str) {
+            let evidence = StringEvidence {
+                content: str.to_string(),
+                place: Location::NativeLibLoad,
+                context: Context::NativeSymbol(bin_elf.clone(), str.to_string()),
+                confidence_level: ConfidenceLevel::Medium,
+                detail: None,
+            };
+            evidences.push(Evidence::String(evidence));
+        }
+    }
+    evidences
+}
+
+/// The functions using the `.rodata` strings matching the regex, one evidence per string and
+/// function. Strings not used by any function are left out, `find_strings` reports those. The
+/// call graph needed for this is built on the first search in a binary.
+pub fn find_string_users(reg: &Regex, bin_elf: Arc<BinaryObject>) -> Vec<Evidence> {
+    let Some(Object::Elf(elf)) = bin_elf.object_no_cache() else {
+        return vec![];
+    };
+    let rodata_strings = rodata_strings(reg, &elf, bin_elf.data());
+    // strings used by code are reported once per function using them
+    let ranges: Vec<(u64, u64)> = rodata_strings
+        .iter()
+        .map(|(_, address, offset, string)| {
+            (address + offset, address + offset + string.len() as u64)
+        })
+        .collect();
+    let users = string_users(&bin_elf, &ranges);
+    let mut evidences = vec![];
+    for (index, (_, _, _, string)) in rodata_strings.into_iter().enumerate() {
+        for function in users.get(index).cloned().unwrap_or_default() {
+            evidences.push(Evidence::String(StringEvidence {
+                content: string.clone(),
+                place: Location::NativeLibLoad,
+                context: function,
+                confidence_level: ConfidenceLevel::Medium,
+                detail: None,
+            }));
+        }
+    }
+    evidences
+}
+
+/// The C strings in `.rodata` matching the regex, with the file offset and the address of the
+/// section and their offset in it
+fn rodata_strings(reg: &Regex, elf: &Elf, data: &[u8]) -> Vec<(u64, u64, u64, String)> {
     let regex = regex::bytes::Regex::new(r"(?-u)(?P<cstr>[^\x00]+)\x00").expect("REGEX IS WRONG");
+    let mut rodata_strings = vec![];
     for h in &elf.section_headers {
         let name = if let Some(name) = elf.shdr_strtab.get_at(h.sh_name) {
             name
@@ -246,10 +329,12 @@ pub fn find_strings(reg: &Regex, bin_elf: Arc<BinaryObject>) -> Vec<Evidence> {
             continue;
         };
         if name == ".rodata" {
+            let end = h.sh_offset.saturating_add(h.sh_size) as usize;
+            let Some(section) = data.get(h.sh_offset as usize..end) else {
+                continue;
+            };
             let cstrs: Vec<(usize, String)> = regex
-                .captures_iter(
-                    &bin_elf.data()[h.sh_offset as usize..(h.sh_offset + h.sh_size) as usize],
-                )
+                .captures_iter(section)
                 .filter_map(|c| {
                     if let Ok(cstr) = String::from_utf8(c.name("cstr")?.as_bytes().to_vec()) {
                         Some((c.name("cstr")?.start(), cstr))
@@ -258,308 +343,644 @@ pub fn find_strings(reg: &Regex, bin_elf: Arc<BinaryObject>) -> Vec<Evidence> {
                     }
                 })
                 .collect();
-            for (offset, string) in cstrs {
-                if reg.is_match(&string) {
-                    let evidence = StringEvidence {
-                        content: string.clone(),
-                        place: Location::NativeLibLoad,
-                        context: Context::NativeLib(
-                            bin_elf.clone(),
-                            string,
-                            h.sh_offset + offset as u64,
-                            false,
-                            Sym::default(),
-                        ),
-                        confidence_level: ConfidenceLevel::Medium,
-                    };
-                    evidences.push(Evidence::String(evidence));
-                }
-            }
+            rodata_strings.extend(
+                cstrs
+                    .into_iter()
+                    .filter(|(_, string)| reg.is_match(string))
+                    .map(|(offset, string)| (h.sh_offset, h.sh_addr, offset as u64, string)),
+            );
         }
     }
-    // let relocs = elf.dynrelas.iter().find(|a| a.)
+    rodata_strings
+}
 
-    for str in strings {
-        if reg.is_match(str) {
-            let evidence = StringEvidence {
-                content: str.to_string(),
-                place: Location::NativeLibLoad,
-                context: Context::NativeSymbol(bin_elf.clone(), str.to_string()),
-                confidence_level: ConfidenceLevel::Medium,
-            };
-            evidences.push(Evidence::String(evidence));
-        }
-    }
-    evidences
+/// Cross references need the disassembler, which is not available on wasm
+#[cfg(target_arch = "wasm32")]
+fn string_users(_bin_elf: &Arc<BinaryObject>, _ranges: &[(u64, u64)]) -> Vec<Vec<Context>> {
+    vec![]
 }
 
-#[derive(Debug)]
-pub enum CpuArch {
-    ArmV7,
-    Arm64,
-    X86,
-    X86_64,
+/// An entry of a `JNINativeMethod[]` table, which is passed to `RegisterNatives` to bind
+/// `native` methods to functions not following the `Java_*` naming scheme
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JniNativeMethod {
+    pub name: String,
+    pub signature: String,
+    /// Address of the implementing function, without the thumb bit
+    pub function: u64,
+    pub thumb: bool,
+    /// The name of the implementing function, if the library has a symbol for it
+    pub symbol: Option<String>,
+    /// The implementing function is in the dynamic symbol table
+    pub exported: bool,
+    /// Address of the first entry of the table this entry belongs to
+    pub table: u64,
+    pub index: usize,
 }
-/// Find structs, which resemble the NativeFunction struct for the JNIRegisterNative call.
-/// This means, there are three pointers where the first is a string pointer to the function name
-/// and the last pointer is a function pointer (dynamic symbol)
-fn find_jni_register_native(
-    symbol_pos: usize,
-    offset: i64,
-    base_address: u64,
-    file_bytes: &[u8],
-    elf: &Elf,
-) -> Option<Sym> {
-    // We need the endianness to figure out, how to interpret the value
-    let little_endian = matches!(
-        elf.header.endianness().unwrap(),
-        goblin::container::Endian::Little
-    );
-    // We only care about the android subset for now
-    let platform = match elf.header.e_machine {
-        0x28 => CpuArch::ArmV7,
-        0xB7 => CpuArch::Arm64,
-        0x03 => CpuArch::X86,
-        0x3e => CpuArch::X86_64,
-        ty => panic!("{}", ty),
-    };
-    // We check all relocation tables for a relocation pointing to our found symbol
-    let mut register_native_start = 0;
-    // adjust pointer offset for cpu architecture (32 bit has 4byte pointers 64bit 8byte pointers)
-    let offset_from_start = if elf.is_64 { 16 } else { 8 };
-    for r in &elf.dynrelas {
-        let sym = elf.dynsyms.get(r.r_sym).unwrap();
-        if let Some(native_start) = check_reloc_for_symbol(
-            symbol_pos,
-            file_bytes,
-            little_endian,
-            offset,
-            base_address,
-            &platform,
-            &r,
-        ) {
-            register_native_start = native_start;
-        }
-        // if this is a function pointer, then we most likely have a NativeFunction struct
-        if register_native_start > 0 && r.r_offset == register_native_start + offset_from_start {
-            return Some(sym);
+
+/// The pointers of a library as seen after the dynamic linker relocated it to address zero.
+/// Slots with a relative or absolute relocation hold the relocated value, all others the value
+/// from the file. This covers `RELA` (addend in the relocation), `REL` and `RELR` (addend in the
+/// slot) as well as non-PIE libraries without relocations.
+pub(crate) struct RelocatedImage<'a> {
+    data: &'a [u8],
+    pub(crate) library: NativeLibrary,
+    relocated: HashMap<u64, u64>,
+}
+
+impl<'a> RelocatedImage<'a> {
+    pub(crate) fn new(elf: &Elf, data: &'a [u8]) -> Option<Self> {
+        let library = NativeLibrary::from_elf(elf, data);
+        let arch = library.arch?;
+        let mut image = RelocatedImage {
+            data,
+            library,
+            relocated: HashMap::new(),
+        };
+        let (relative, absolute, glob_dat) = match arch {
+            CpuArch::ArmV7 => (R_ARM_RELATIVE, R_ARM_ABS32, R_ARM_GLOB_DAT),
+            CpuArch::Arm64 => (R_AARCH64_RELATIVE, R_AARCH64_ABS64, R_AARCH64_GLOB_DAT),
+            CpuArch::X86 => (R_386_RELATIVE, R_386_32, R_386_GLOB_DAT),
+            CpuArch::X86_64 => (R_X86_64_RELATIVE, R_X86_64_64, R_X86_64_GLOB_DAT),
+        };
+        let relocations = elf
+            .dynrelas
+            .iter()
+            .chain(elf.dynrels.iter())
+            .chain(elf.pltrelocs.iter());
+        for reloc in relocations {
+            let addend = match reloc.r_addend {
+                Some(addend) => addend as u64,
+                None => image.raw_pointer(reloc.r_offset).unwrap_or(0),
+            };
+            let symbol = || {
+                elf.dynsyms
+                    .get(reloc.r_sym)
+                    .filter(|sym| sym.st_shndx != 0)
+                    .map(|sym| sym.st_value)
+            };
+            let value = if reloc.r_type == relative {
+                addend
+            } else if reloc.r_type == absolute {
+                match symbol() {
+                    Some(value) => value.wrapping_add(addend),
+                    None => continue,
+                }
+            } else if reloc.r_type == glob_dat {
+                match symbol() {
+                    Some(value) => value,
+                    None => continue,
+                }
+            } else {
+                continue;
+            };
+            let value = if image.library.is_64 {
+                value
+            } else {
+                value & 0xffff_ffff
+            };
+            image.relocated.insert(reloc.r_offset, value);
         }
+        Some(image)
     }
-    for r in &elf.pltrelocs {
-        let sym = elf.dynsyms.get(r.r_sym).unwrap();
-        if let Some(native_start) = check_reloc_for_symbol(
-            symbol_pos,
-            file_bytes,
-            little_endian,
-            offset,
-            base_address,
-            &platform,
-            &r,
-        ) {
-            register_native_start = native_start;
-        }
-        // if this is a function pointer, then we most likely have a NativeFunction struct
-        if register_native_start > 0 && r.r_offset == register_native_start + offset_from_start {
-            return Some(sym);
+
+    pub(crate) fn pointer_size(&self) -> u64 {
+        if self.library.is_64 {
+            8
+        } else {
+            4
         }
     }
-    for r in &elf.dynrels {
-        let sym = elf.dynsyms.get(r.r_sym).unwrap();
-        if let Some(native_start) = check_reloc_for_symbol(
-            symbol_pos,
-            file_bytes,
-            little_endian,
-            offset,
-            base_address,
-            &platform,
-            &r,
-        ) {
-            register_native_start = native_start;
-        }
-        // if this is a function pointer, then we most likely have a NativeFunction struct
-        if register_native_start > 0 && r.r_offset == register_native_start + offset_from_start {
-            return Some(sym);
-        }
+
+    fn raw_pointer(&self, address: u64) -> Option<u64> {
+        let offset = usize::try_from(self.library.file_offset(address)?).ok()?;
+        let bytes = self
+            .data
+            .get(offset..offset.checked_add(self.pointer_size() as usize)?)?;
+        Some(match (self.library.is_64, self.library.little_endian) {
+            (true, true) => u64::from_le_bytes(bytes.try_into().ok()?),
+            (true, false) => u64::from_be_bytes(bytes.try_into().ok()?),
+            (false, true) => u32::from_le_bytes(bytes.try_into().ok()?) as u64,
+            (false, false) => u32::from_be_bytes(bytes.try_into().ok()?) as u64,
+        })
     }
-    None
-}
 
-/// Do the basic loader relocation (for now only the relative relocations), to see if we find a relocation matching
-/// the string symbol position we are seeking for
-fn check_reloc_for_symbol(
-    symbol_pos: usize,
-    file_bytes: &[u8],
-    little_endian: bool,
-    offset: i64,
-    base_address: u64,
-    platform: &CpuArch,
-    r: &Reloc,
-) -> Option<u64> {
-    // we do not actually check elf architecture, but assume that arm64 and x86_64 are 64bit
-    // we probably could use the elf value
-    let is_64 = matches!(platform, CpuArch::Arm64 | CpuArch::X86_64);
-    // check for architecture
-    // if we have the symbol undefined, the relocation is not based on a symbol, but a direct offset to the string
-    match platform {
-        CpuArch::ArmV7 => {
-            if !(r.r_type == R_ARM_RELATIVE && r.r_sym == 0) {
-                return None;
-            }
-        }
-        CpuArch::Arm64 => {
-            if !(r.r_type == R_AARCH64_RELATIVE && r.r_sym == 0) {
-                return None;
-            }
+    pub(crate) fn pointer(&self, address: u64) -> Option<u64> {
+        match self.relocated.get(&address) {
+            Some(value) => Some(*value),
+            None => self.raw_pointer(address),
         }
-        CpuArch::X86 => {
-            if !(r.r_type == R_386_RELATIVE && r.r_sym == 0) {
-                return None;
-            }
+    }
+
+    /// The value of a slot the dynamic linker writes an address to. Position dependent
+    /// executables are not relocated, there every slot is taken as is.
+    pub(crate) fn relocated_pointer(&self, address: u64) -> Option<u64> {
+        if !self.library.hardening.pie {
+            self.raw_pointer(address)
+        } else {
+            self.relocated.get(&address).copied()
         }
-        CpuArch::X86_64 => {
-            if !(r.r_type == R_X86_64_RELATIVE && r.r_sym == 0) {
-                return None;
-            }
+    }
+
+    fn c_string(&self, address: u64) -> Option<&'a str> {
+        let offset = usize::try_from(self.library.file_offset(address)?).ok()?;
+        let bytes = self.data.get(offset..)?;
+        let end = bytes.iter().take(1024).position(|b| *b == 0)?;
+        std::str::from_utf8(&bytes[..end]).ok()
+    }
+
+    pub(crate) fn is_code(&self, address: u64) -> bool {
+        address != 0
+            && self
+                .library
+                .segments
+                .iter()
+                .any(|segment| segment.is_executable() && segment.contains(address))
+    }
+
+    /// The allocated, non executable parts of the file, which could hold a table. Regions are cut
+    /// off at the end of the file.
+    pub(crate) fn data_regions(&self) -> Vec<(u64, u64)> {
+        let file_size = self.data.len() as u64;
+        let region = |address: u64, offset: u64, size: u64| {
+            let size = size.min(file_size.saturating_sub(offset));
+            (address, address.saturating_add(size))
+        };
+        let sections: Vec<_> = self
+            .library
+            .sections
+            .iter()
+            .filter(|section| {
+                section.kind == SHT_PROGBITS
+                    && section.flags & SHF_ALLOC as u64 != 0
+                    && section.flags & SHF_EXECINSTR as u64 == 0
+            })
+            .map(|section| region(section.address, section.offset, section.size))
+            .collect();
+        if !sections.is_empty() {
+            return sections;
         }
+        // section headers are optional, fall back to the loaded segments
+        self.library
+            .segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD && !segment.is_executable())
+            .map(|segment| region(segment.address, segment.offset, segment.file_size))
+            .collect()
     }
-    // read the value we need to relocate
-    // depending on the architecture and the endianness, we interpret the bytes differently
-    let ptr_value = {
-        if is_64 {
-            let mut bytes = [0; 8];
-            bytes.copy_from_slice(
-                &file_bytes[(r.r_offset as i64 + offset) as usize
-                    ..(r.r_offset as i64 + offset) as usize + 8],
-            );
-            if little_endian {
-                u64::from_le_bytes(bytes)
-            } else {
-                u64::from_be_bytes(bytes)
-            }
-        } else {
-            let mut bytes = [0; 4];
-            bytes.copy_from_slice(
-                &file_bytes[(r.r_offset as i64 + offset) as usize
-                    ..(r.r_offset as i64 + offset) as usize + 4],
-            );
-            if little_endian {
-                u32::from_le_bytes(bytes) as u64
-            } else {
-                u32::from_be_bytes(bytes) as u64
-            }
+
+    /// The slots which could hold an address together with their value. These are the relocated
+    /// slots of position independent libraries and every aligned slot of the data regions
+    /// otherwise.
+    pub(crate) fn pointer_slots(&self) -> Vec<(u64, u64)> {
+        if self.library.hardening.pie {
+            return self
+                .relocated
+                .iter()
+                .map(|(slot, value)| (*slot, *value))
+                .collect();
         }
-    };
-    // for 32bit architecture we just adjust the value for the program base. We might need to adjust the
-    // calculation of it, since it should be truncated to a multiple of the page size.
-    // For 64bit architecture we use the base value and the addended to calculate the location.
-    match platform {
-        CpuArch::ArmV7 => {
-            if ptr_value as i64 - base_address as i64 == symbol_pos as i64 {
-                return Some(r.r_offset);
+        let pointer_size = self.pointer_size();
+        let mut slots = vec![];
+        for (start, end) in self.data_regions() {
+            let Some(mut slot) = align_up(start, pointer_size) else {
+                continue;
+            };
+            while slot
+                .checked_add(pointer_size)
+                .map_or(false, |slot_end| slot_end <= end)
+            {
+                if let Some(value) = self.raw_pointer(slot) {
+                    slots.push((slot, value));
+                }
+                slot += pointer_size;
             }
         }
-        CpuArch::Arm64 => {
-            if r.r_addend.unwrap_or(0) as i64 - base_address as i64 == symbol_pos as i64 {
-                return Some(r.r_offset);
-            }
+        slots
+    }
+
+    /// Read `{ const char* name; const char* signature; void* fnPtr; }` at `address`
+    fn jni_native_method(&self, address: u64) -> Option<(&'a str, &'a str, u64)> {
+        let pointer_size = self.pointer_size();
+        let name = self.c_string(self.pointer(address)?)?;
+        if !is_java_identifier(name) {
+            return None;
         }
-        CpuArch::X86 => {
-            if ptr_value as i64 - base_address as i64 == symbol_pos as i64 {
-                return Some(r.r_offset);
-            }
+        let signature = self.c_string(self.pointer(address.checked_add(pointer_size)?)?)?;
+        if !is_jni_method_signature(signature) {
+            return None;
         }
-        CpuArch::X86_64 => {
-            if r.r_addend.unwrap_or(0) as i64 + base_address as i64 == symbol_pos as i64 {
-                return Some(r.r_offset);
-            }
+        let function = self.pointer(address.checked_add(2 * pointer_size)?)?;
+        let mask = if self.library.arch == Some(CpuArch::ArmV7) {
+            !1
+        } else {
+            !0
+        };
+        if !self.is_code(function & mask) {
+            return None;
         }
+        Some((name, signature, function))
     }
-    None
 }
 
-// We parse the rodata section for strings, matching the name of our function from Java. We use the
-// found symbol position, to find relocations, where this string is used, as this is most likely a
-// NativeFunction struct.
-fn find_symbol(name: &Regex, bytes: &[u8], elf: &Elf) -> Option<usize> {
-    let mut sym_pos = None;
-    for s in &elf.section_headers {
-        // check the name of the section
-        let section_header_name = &elf.shdr_strtab[s.sh_name];
-        if section_header_name == ".rodata" {
-            // we know that rodata has a file_range
-            let file_range = s.file_range().unwrap();
-            // we create a cursor, operating on the rodata section.
-            let bytes_to_search = &bytes[file_range.clone()];
-            let mut cursor = Cursor::new(bytes_to_search);
-            let mut string_buffer = vec![];
-            loop {
-                // we read until we can read no more. Since we are looking for arrays of data, we look for 0x00
-                // terminated data.
-                let str_length = cursor.read_until(0x00, &mut string_buffer).unwrap();
-                if str_length == 0 {
-                    break;
-                }
-                // our function name should be a cstring
-                if let Ok(the_string) = CStr::from_bytes_with_nul(&string_buffer[..str_length]) {
-                    if let Ok(the_string) = the_string.to_str() {
-                        if name.is_match(&the_string) {
-                            sym_pos = file_range
-                                .start
-                                .checked_add(cursor.position() as usize - str_length);
-                        }
-                    }
-                }
-                string_buffer.clear();
+/// Round `address` up to a multiple of `alignment`
+pub(crate) fn align_up(address: u64, alignment: u64) -> Option<u64> {
+    Some(address.checked_add(alignment - 1)? / alignment * alignment)
+}
+
+fn is_java_identifier(name: &str) -> bool {
+    !name.is_empty()
+        && name.len() <= 256
+        && !name.starts_with(|c: char| c.is_ascii_digit())
+        && name
+            .chars()
+            .all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$' || !c.is_ascii())
+}
+
+/// Check for a method descriptor like `(ILjava/lang/String;[B)V`
+fn is_jni_method_signature(signature: &str) -> bool {
+    fn field_type(rest: &str) -> Option<&str> {
+        let rest = rest.trim_start_matches('[');
+        match rest.chars().next()? {
+            'Z' | 'B' | 'C' | 'S' | 'I' | 'J' | 'F' | 'D' => Some(&rest[1..]),
+            'L' => {
+                let end = rest.find(';')?;
+                (end > 1).then(|| &rest[end + 1..])
             }
+            _ => None,
+        }
+    }
+    let Some(mut rest) = signature.strip_prefix('(') else {
+        return false;
+    };
+    while !rest.starts_with(')') {
+        match field_type(rest) {
+            Some(next) => rest = next,
+            None => return false,
         }
     }
-    sym_pos
+    let rest = &rest[1..];
+    rest == "V" || field_type(rest) == Some("")
 }
 
-pub fn find_dynamically_registered_function(
-    reg: &Regex,
-    bin_elf: Arc<BinaryObject>,
-) -> Vec<Evidence> {
+/// Recover all `JNINativeMethod[]` tables of an ELF file. Tables are found by their shape:
+/// consecutive entries of a pointer to a Java identifier, a pointer to a method signature and
+/// a pointer into executable code. This works independent of the architecture and of how
+/// (or whether) the pointers are relocated.
+pub fn find_jni_native_methods(bin_elf: &BinaryObject) -> Vec<JniNativeMethod> {
     let elf = if let Some(Object::Elf(elf)) = bin_elf.object_no_cache() {
         elf
     } else {
         return vec![];
     };
-    let mut evidences = vec![];
+    let Some(image) = RelocatedImage::new(&elf, bin_elf.data()) else {
+        return vec![];
+    };
+    let thumb_mask = if image.library.arch == Some(CpuArch::ArmV7) {
+        1
+    } else {
+        0
+    };
+    let mut symbols = HashMap::new();
+    let symbol_tables = [
+        (&elf.dynsyms, &elf.dynstrtab, true),
+        (&elf.syms, &elf.strtab, false),
+    ];
+    for (syms, strtab, exported) in symbol_tables.iter() {
+        for sym in syms
+            .iter()
+            .filter(|sym| sym.is_function() && sym.st_shndx != 0)
+        {
+            if let Some(name) = strtab.get_at(sym.st_name).filter(|name| !name.is_empty()) {
+                symbols
+                    .entry(sym.st_value & !thumb_mask)
+                    .or_insert_with(|| (name.to_string(), *exported));
+            }
+        }
+    }
 
-    let mut base_address = u64::MAX;
-    let mut file_offset = 0;
-    for p in &elf.program_headers {
-        if p.p_type == PT_LOAD {
-            if p.p_vaddr < base_address {
-                base_address = p.p_vaddr;
+    let pointer_size = image.pointer_size();
+    let entry_size = 3 * pointer_size;
+    let mut methods = vec![];
+    let fits = |address: u64, end: u64| {
+        address
+            .checked_add(entry_size)
+            .map_or(false, |entry_end| entry_end <= end)
+    };
+    for (start, end) in image.data_regions() {
+        let Some(mut address) = align_up(start, pointer_size) else {
+            continue;
+        };
+        while fits(address, end) {
+            if image.jni_native_method(address).is_none() {
+                address += pointer_size;
+                continue;
             }
-            if p.p_vaddr > 0 && p.p_offset != p.p_vaddr {
-                file_offset = p.p_offset as i64 - p.p_vaddr as i64
+            let table = address;
+            let mut index = 0;
+            while fits(address, end) {
+                let Some((name, signature, function)) = image.jni_native_method(address) else {
+                    break;
+                };
+                let function_address = function & !thumb_mask;
+                let symbol = symbols.get(&function_address);
+                methods.push(JniNativeMethod {
+                    name: name.to_string(),
+                    signature: signature.to_string(),
+                    function: function_address,
+                    thumb: function & thumb_mask != 0,
+                    symbol: symbol.map(|(name, _)| name.clone()),
+                    exported: symbol.map_or(false, |(_, exported)| *exported),
+                    table,
+                    index,
+                });
+                index += 1;
+                address += entry_size;
             }
         }
     }
-    let sym_pos = find_symbol(reg, bin_elf.data(), &elf);
-    if let Some(sym_pos) = sym_pos {
-        let native_symbol =
-            find_jni_register_native(sym_pos, file_offset, base_address, &bin_elf.data(), &elf);
-        if let Some(native_symbol) = native_symbol {
-            let sym_name = &elf.dynstrtab[native_symbol.st_name];
-            let evidence = StringEvidence {
-                content: sym_name.to_string(),
+    methods
+}
+
+/// The symbol of the implementing function, or its address for unnamed functions
+fn native_function_name(method: &JniNativeMethod) -> String {
+    method
+        .symbol
+        .clone()
+        .unwrap_or_else(|| format!("sub_{:x}", method.function))
+}
+
+fn native_lib_context(bin_elf: &Arc<BinaryObject>, method: &JniNativeMethod) -> Context {
+    let name = native_function_name(method);
+    let address = method.function | method.thumb as u64;
+    let sym = Sym {
+        st_value: address,
+        ..Sym::default()
+    };
+    Context::NativeLib(bin_elf.clone(), name, address, method.exported, sym)
+}
+
+/// Find all functions registered with `RegisterNatives` whose Java name matches the regex. The
+/// content is the name of the native function, the detail the Java name and signature.
+pub fn find_dynamically_registered_function(
+    reg: &Regex,
+    bin_elf: Arc<BinaryObject>,
+) -> Vec<Evidence> {
+    find_jni_native_methods(&bin_elf)
+        .into_iter()
+        .filter(|method| reg.is_match(&method.name))
+        .map(|method| {
+            Evidence::String(StringEvidence {
+                content: native_function_name(&method),
                 place: Location::NativeLibLoad,
-                context: Context::NativeLib(
-                    bin_elf.clone(),
-                    sym_name.to_string(),
-                    native_symbol.st_value,
-                    true,
-                    native_symbol,
-                ),
+                context: native_lib_context(&bin_elf, &method),
                 confidence_level: ConfidenceLevel::Medium,
+                detail: Some(format!("{}{}", method.name, method.signature)),
+            })
+        })
+        .collect()
+}
+
+/// A `native` method together with the dex file declaring it
+type NativeJavaMethod = (Arc<DexFile>, Arc<Method>);
+
+/// Link the `JNINativeMethod` entries of all native libraries to the `native` methods they
+/// implement. The Java method is the context of the cross reference, the native function the
+/// place. Since the table does not name the class, methods with the same name and signature in
+/// several classes are narrowed down to classes whose name appears as string in the library
+/// (the argument of `FindClass`).
+pub fn find_registered_natives(files: &Files) -> Vec<Evidence> {
+    let mut java_methods: HashMap<(String, String), Vec<NativeJavaMethod>> = HashMap::new();
+    for md in &files.multi_dex {
+        for (dex, method) in get_native_methods(md, files) {
+            let Some(proto) = dex.protos.get(method.proto_idx as usize) else {
+                continue;
             };
+            java_methods
+                .entry((method.method_name.clone(), proto.to_string(&dex)))
+                .or_default()
+                .push((dex.clone(), method));
+        }
+    }
 
-            evidences.push(Evidence::String(evidence));
+    let mut evidences = vec![];
+    let mut binaries: Vec<_> = files.binaries.iter().collect();
+    binaries.sort_by(|a, b| a.0.cmp(b.0));
+    for (file_name, bin_elf) in binaries {
+        let methods = find_jni_native_methods(bin_elf);
+        if methods.is_empty() {
+            continue;
+        }
+        let strings: HashSet<&[u8]> = bin_elf.data().split(|b| *b == 0).collect();
+        for method in methods {
+            let Some(candidates) =
+                java_methods.get(&(method.name.clone(), method.signature.clone()))
+            else {
+                continue;
+            };
+            let class_name = |(dex, java_method): &&NativeJavaMethod| {
+                dex.get_type_name(java_method.class_idx)
+                    .and_then(|name| name.strip_prefix('L'))
+                    .and_then(|name| name.strip_suffix(';'))
+                    .map(|name| strings.contains(name.as_bytes()))
+                    .unwrap_or(false)
+            };
+            let mut linked: Vec<_> = candidates.iter().filter(class_name).collect();
+            if linked.is_empty() {
+                linked = candidates.iter().collect();
+            }
+            for (dex, java_method) in linked {
+                evidences.push(Evidence::CrossReference(CrossReferenceEvidence {
+                    place: Location::NativeAddress(file_name.clone(), method.function),
+                    place_context: native_lib_context(bin_elf, &method),
+                    context: Context::DexMethod(java_method.clone(), dex.clone()),
+                }));
+            }
         }
     }
     evidences
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An AArch64 shared object without code, with `hello` and `secret key` in `.rodata` at 0x100
+    fn library() -> Arc<BinaryObject> {
+        let mut data = vec![0u8; 0x240];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, b"\x7fELF\x02\x01\x01");
+        put(16, &[3, 0, 0xb7, 0, 1, 0, 0, 0]);
+        put(32, &0x40u64.to_le_bytes());
+        put(40, &0x180u64.to_le_bytes());
+        put(52, &[64, 0, 56, 0, 1, 0, 64, 0, 3, 0, 2, 0]);
+        put(0x40, &[1, 0, 0, 0, 4, 0, 0, 0]);
+        put(0x60, &0x240u64.to_le_bytes());
+        put(0x68, &0x240u64.to_le_bytes());
+        put(0x100, b"hello\0secret key\0");
+        put(0x140, b"\0.rodata\0.shstrtab\0");
+        for (index, (name, kind, offset, size)) in
+            [(1u32, 1u32, 0x100u64, 0x11u64), (9, 3, 0x140, 0x13)]
+                .iter()
+                .enumerate()
+        {
+            let header = 0x1c0 + index * 64;
+            put(header, &name.to_le_bytes());
+            put(header + 4, &kind.to_le_bytes());
+            put(header + 16, &offset.to_le_bytes());
+            put(header + 24, &offset.to_le_bytes());
+            put(header + 32, &size.to_le_bytes());
+        }
+        Arc::new(BinaryObject::new(data))
+    }
+
+    #[test]
+    fn finds_rodata_strings() {
+        let evidences = find_strings(&Regex::new("secret").unwrap(), library());
+        assert_eq!(evidences.len(), 1);
+        let Evidence::String(evidence) = &evidences[0] else {
+            panic!("not a string evidence");
+        };
+        assert_eq!(evidence.content, "secret key");
+        assert!(matches!(
+            evidence.context,
+            Context::NativeLib(_, ref name, 0x106, false, _) if name == "secret key"
+        ));
+    }
+
+    #[test]
+    fn strings_without_users_have_no_function_evidence() {
+        let library = library();
+        let reg = Regex::new("hello|secret").unwrap();
+        assert!(find_string_users(&reg, library.clone()).is_empty());
+        assert_eq!(find_strings(&reg, library).len(), 2);
+    }
+
+    #[test]
+    fn ignores_sections_outside_of_the_file() {
+        let library = library();
+        let mut data = library.data().to_vec();
+        data[0x1c0 + 32..0x1c0 + 40].copy_from_slice(&0x1000u64.to_le_bytes());
+        let library = Arc::new(BinaryObject::new(data));
+        assert!(library.object_no_cache().is_some());
+        assert!(find_strings(&Regex::new("secret").unwrap(), library).is_empty());
+    }
+
+    /// A position independent AArch64 library with code at 0..0x200 and data at 0x200..0x400.
+    /// The data starts with a `JNINativeMethod` entry for `getKey` whose pointers are only set
+    /// by `R_AARCH64_RELATIVE` relocations.
+    fn jni_library(e_type: u8, relocations: &[(u64, u64)]) -> Vec<u8> {
+        let mut data = vec![0u8; 0x400];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, b"\x7fELF\x02\x01\x01");
+        put(16, &[e_type, 0, 0xb7, 0, 1, 0, 0, 0]);
+        put(32, &0x40u64.to_le_bytes());
+        put(52, &[64, 0, 56, 0, 3, 0, 64, 0, 0, 0, 0, 0]);
+        let segments = [
+            (1u32, 5u32, 0u64, 0x200u64),
+            (1, 6, 0x200, 0x200),
+            (2, 6, 0x300, 0x40),
+        ];
+        for (index, (kind, flags, offset, size)) in segments.iter().enumerate() {
+            let header = 0x40 + index * 56;
+            put(header, &kind.to_le_bytes());
+            put(header + 4, &flags.to_le_bytes());
+            put(header + 8, &offset.to_le_bytes());
+            put(header + 16, &offset.to_le_bytes());
+            put(header + 32, &size.to_le_bytes());
+            put(header + 40, &size.to_le_bytes());
+        }
+        put(0x100, b"\xc0\x03\x5f\xd6");
+        put(0x180, b"getKey\0\0()Ljava/lang/String;\0");
+        for (index, (offset, addend)) in relocations.iter().enumerate() {
+            let entry = 0x280 + index * 24;
+            put(entry, &offset.to_le_bytes());
+            put(entry + 8, &(R_AARCH64_RELATIVE as u64).to_le_bytes());
+            put(entry + 16, &addend.to_le_bytes());
+        }
+        let dynamic = [
+            (7u64, 0x280u64),
+            (8, 24 * relocations.len() as u64),
+            (9, 24),
+        ];
+        for (index, (tag, value)) in dynamic.iter().enumerate() {
+            put(0x300 + index * 16, &tag.to_le_bytes());
+            put(0x308 + index * 16, &value.to_le_bytes());
+        }
+        data
+    }
+
+    const GET_KEY: [(u64, u64); 3] = [(0x200, 0x180), (0x208, 0x188), (0x210, 0x100)];
+
+    #[test]
+    fn applies_relative_relocations() {
+        let data = jni_library(3, &GET_KEY);
+        let elf = Elf::parse(&data).unwrap();
+        let image = RelocatedImage::new(&elf, &data).unwrap();
+        assert_eq!(image.pointer_size(), 8);
+        assert_eq!(image.pointer(0x208), Some(0x188));
+        assert_eq!(image.relocated_pointer(0x210), Some(0x100));
+        // slots without a relocation keep the value of the file
+        assert_eq!(image.pointer(0x218), Some(0));
+        assert_eq!(image.relocated_pointer(0x218), None);
+        assert_eq!(image.pointer(0x3fc), None);
+        let mut slots = image.pointer_slots();
+        slots.sort_unstable();
+        assert_eq!(slots, GET_KEY.to_vec());
+        assert_eq!(image.data_regions(), vec![(0x200, 0x400)]);
+        assert!(image.is_code(0x100) && !image.is_code(0x200) && !image.is_code(0));
+    }
+
+    #[test]
+    fn finds_relocated_jni_native_methods() {
+        let library = BinaryObject::new(jni_library(3, &GET_KEY));
+        let methods = find_jni_native_methods(&library);
+        assert_eq!(methods.len(), 1);
+        assert_eq!(methods[0].name, "getKey");
+        assert_eq!(methods[0].signature, "()Ljava/lang/String;");
+        assert_eq!(methods[0].function, 0x100);
+        assert_eq!((methods[0].table, methods[0].index), (0x200, 0));
+        assert_eq!(methods[0].symbol, None);
+
+        // without the function pointer the entry does not describe a native method
+        let library = BinaryObject::new(jni_library(3, &GET_KEY[..2]));
+        assert!(find_jni_native_methods(&library).is_empty());
+    }
+
+    #[test]
+    fn ignores_relocations_outside_of_the_image() {
+        let mut relocations = GET_KEY.to_vec();
+        relocations.push((u64::MAX - 3, 0x180));
+        relocations.push((0x210, u64::MAX));
+        let data = jni_library(3, &relocations);
+        let elf = Elf::parse(&data).unwrap();
+        let image = RelocatedImage::new(&elf, &data).unwrap();
+        assert_eq!(image.pointer(u64::MAX - 3), Some(0x180));
+        assert_eq!(image.pointer(0x210), Some(u64::MAX));
+        assert!(find_jni_native_methods(&BinaryObject::new(data)).is_empty());
+    }
+
+    #[test]
+    fn clamps_data_regions_to_the_file() {
+        let mut data = jni_library(2, &[]);
+        // file size of the data segment
+        let header = 0x40 + 56;
+        data[header + 32..header + 40].copy_from_slice(&u64::MAX.to_le_bytes());
+        let elf = Elf::parse(&data).unwrap();
+        let image = RelocatedImage::new(&elf, &data).unwrap();
+        assert_eq!(image.data_regions(), vec![(0x200, 0x400)]);
+        assert_eq!(image.pointer_slots().len(), 0x200 / 8);
+
+        // a segment at the end of the address space
+        data[header + 16..header + 24].copy_from_slice(&(u64::MAX - 0x10).to_le_bytes());
+        let elf = Elf::parse(&data).unwrap();
+        let image = RelocatedImage::new(&elf, &data).unwrap();
+        assert_eq!(image.data_regions(), vec![(u64::MAX - 0x10, u64::MAX)]);
+        assert_eq!(image.pointer_slots().len(), 1);
+        assert!(find_jni_native_methods(&BinaryObject::new(data)).is_empty());
+    }
+}
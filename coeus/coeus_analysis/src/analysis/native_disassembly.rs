@@ -0,0 +1,959 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Disassembly of native code for all `CpuArch`s with capstone. Branch targets are symbolized
+//! with the dynamic and static symbols, calls through the PLT and loads from the GOT are resolved
+//! to the imported function using the relocations of the library.
+//!
+//! On ARMv7 the instruction set is taken from the lowest bit of function addresses (set for
+//! Thumb) and from the `$a`/`$t`/`$d` mapping symbols of unstripped libraries.
+
+use std::{
+    cell::RefCell,
+    collections::{BTreeMap, HashMap},
+    fmt::{Display, Formatter},
+    ops::Bound,
+    sync::{Arc, Mutex},
+};
+
+use capstone::{
+    arch::{
+        self, arm::ArmOperandType, arm64::Arm64OperandType, x86::X86OperandType, ArchDetail,
+        BuildsCapstone, DetailsArchInsn,
+    },
+    Capstone, Insn, InsnGroupType, RegId,
+};
+use goblin::elf::{
+    section_header::SHF_EXECINSTR,
+    sym::{STT_FUNC, STT_GNU_IFUNC},
+    Elf, Sym,
+};
+use rayon::iter::ParallelIterator;
+use regex::Regex;
+
+use coeus_macros::iterator;
+use coeus_models::models::{BinaryObject, CpuArch, NativeLibrary};
+
+use super::{ConfidenceLevel, Context, Evidence, InstructionEvidence, Location};
+
+/// Functions without a size are disassembled up to the next symbol, but not further than this
+const MAX_FUNCTION_SIZE: u64 = 0x10000;
+
+thread_local! {
+    /// Capstone handles can not be shared between threads, each thread builds one per
+    /// architecture and instruction set
+    static CAPSTONES: RefCell<HashMap<(CpuArch, bool), Option<Capstone>>> =
+        RefCell::new(HashMap::new());
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum NativeFlow {
+    Sequential,
+    Call,
+    Jump,
+    ConditionalJump,
+    Return,
+    Interrupt,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum NativeOperand {
+    Register(String),
+    Immediate(i64),
+    Memory {
+        base: Option<String>,
+        index: Option<String>,
+        scale: i32,
+        displacement: i64,
+    },
+    /// Floating point values, system registers, register lists, ...
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NativeInstruction {
+    pub address: u64,
+    pub bytes: Vec<u8>,
+    pub mnemonic: String,
+    pub op_str: String,
+    pub operands: Vec<NativeOperand>,
+    /// Decoded as Thumb instruction
+    pub thumb: bool,
+    pub flow: NativeFlow,
+    /// Destination of direct calls and jumps
+    pub target: Option<u64>,
+    /// The instruction set at `target`, `blx` switches between ARM and Thumb
+    pub target_thumb: bool,
+    /// An absolute address computed from a pc relative operand (`adr`, `adrp`, literal loads,
+    /// `rip` relative operands)
+    pub reference: Option<u64>,
+    /// The symbol at `target` or `reference`, imports called through the PLT or loaded from the
+    /// GOT are resolved to the imported name
+    pub symbol: Option<String>,
+}
+
+impl NativeInstruction {
+    pub fn next_address(&self) -> u64 {
+        self.address.wrapping_add(self.bytes.len() as u64)
+    }
+}
+
+impl Display for NativeInstruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{:#x}: {} {}", self.address, self.mnemonic, self.op_str)?;
+        if let Some(symbol) = &self.symbol {
+            write!(f, " <{}>", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NativeFunction {
+    pub name: String,
+    pub address: u64,
+    pub thumb: bool,
+    pub instructions: Vec<NativeInstruction>,
+}
+
+impl NativeFunction {
+    /// All direct calls with their (symbolized) targets
+    pub fn calls(&self) -> impl Iterator<Item = &NativeInstruction> {
+        self.instructions
+            .iter()
+            .filter(|instruction| instruction.flow == NativeFlow::Call)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct FunctionSymbol {
+    pub name: String,
+    pub address: u64,
+    pub size: u64,
+    pub thumb: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum Mapping {
+    Arm,
+    Thumb,
+    Data,
+}
+
+pub struct NativeDisassembler {
+    object: Arc<BinaryObject>,
+    library: NativeLibrary,
+    arch: CpuArch,
+    /// Defined functions by their address (without the thumb bit)
+    functions: BTreeMap<u64, FunctionSymbol>,
+    /// GOT slots filled by the dynamic linker with the address of an imported symbol
+    got: HashMap<u64, String>,
+    /// PLT stubs jumping to an imported function
+    stubs: HashMap<u64, String>,
+    mapping: BTreeMap<u64, Mapping>,
+}
+
+impl NativeDisassembler {
+    /// Returns `None` if the object is not an ELF file of one of the supported architectures
+    pub fn new(object: Arc<BinaryObject>) -> Option<Self> {
+        let elf = Elf::parse(object.data()).ok()?;
+        let library = NativeLibrary::from_elf(&elf, object.data());
+        let arch = library.arch?;
+        let mut disassembler = NativeDisassembler {
+            object: object.clone(),
+            library,
+            arch,
+            functions: BTreeMap::new(),
+            got: HashMap::new(),
+            stubs: HashMap::new(),
+            mapping: BTreeMap::new(),
+        };
+        disassembler.read_symbols(&elf);
+        disassembler.read_got(&elf);
+        disassembler.resolve_plt_stubs();
+        Some(disassembler)
+    }
+
+    pub fn library(&self) -> &NativeLibrary {
+        &self.library
+    }
+
+    pub fn arch(&self) -> CpuArch {
+        self.arch
+    }
+
+    pub fn functions(&self) -> impl Iterator<Item = &FunctionSymbol> {
+        self.functions.values()
+    }
+
+    /// The name of the function, import or GOT slot at `address`
+    pub fn symbol_at(&self, address: u64) -> Option<&str> {
+        self.stubs
+            .get(&address)
+            .or_else(|| self.got.get(&address))
+            .map(|name| name.as_str())
+            .or_else(|| {
+                self.functions
+                    .get(&address)
+                    .map(|function| function.name.as_str())
+            })
+    }
+
+    /// The imported function called through the PLT stub or loaded from the GOT slot at `address`
+    pub fn import_at(&self, address: u64) -> Option<&str> {
+        self.stubs
+            .get(&address)
+            .or_else(|| self.got.get(&address))
+            .map(|name| name.as_str())
+    }
+
+    /// Register a function found without a symbol (call targets, function pointers), such that it
+    /// bounds the disassembly of the preceding function. Returns `false` if it was known already.
+    pub fn add_function(&mut self, address: u64) -> bool {
+        let (address, thumb) = self.split_thumb_bit(address);
+        if self.functions.contains_key(&address) {
+            return false;
+        }
+        self.functions.insert(
+            address,
+            FunctionSymbol {
+                name: format!("sub_{:x}", address),
+                address,
+                size: 0,
+                thumb,
+            },
+        );
+        true
+    }
+
+    pub fn function_by_name(&self, name: &str) -> Option<NativeFunction> {
+        let symbol = self.functions.values().find(|symbol| symbol.name == name)?;
+        self.function_at(symbol.address | symbol.thumb as u64)
+    }
+
+    /// Disassemble the function at `address`. On ARMv7 the lowest bit of the address selects
+    /// Thumb mode, as in function pointers.
+    pub fn function_at(&self, address: u64) -> Option<NativeFunction> {
+        let (address, thumb) = self.split_thumb_bit(address);
+        let symbol = self.functions.get(&address);
+        let next = self
+            .functions
+            .range((Bound::Excluded(address), Bound::Unbounded))
+            .next()
+            .map(|(next, _)| *next);
+        let size = match symbol {
+            Some(symbol) if symbol.size > 0 => symbol.size,
+            _ => next
+                .map(|next| next - address)
+                .unwrap_or(MAX_FUNCTION_SIZE)
+                .min(MAX_FUNCTION_SIZE),
+        };
+        // never run into the PLT or other sections
+        let size = match self.library.sections.iter().find(|section| {
+            section.flags & SHF_EXECINSTR as u64 != 0
+                && address >= section.address
+                && address - section.address < section.size
+        }) {
+            Some(section) => size.min(section.size - (address - section.address)),
+            None => size,
+        };
+        let instructions = self.disassemble(address, size, thumb);
+        if instructions.is_empty() {
+            return None;
+        }
+        Some(NativeFunction {
+            name: symbol
+                .map(|symbol| symbol.name.clone())
+                .unwrap_or_else(|| format!("sub_{:x}", address)),
+            address,
+            thumb,
+            instructions,
+        })
+    }
+
+    /// Disassemble `size` bytes at the virtual `address`. Data regions marked by mapping symbols
+    /// are skipped, undecodable bytes as well.
+    pub fn disassemble(&self, address: u64, size: u64, thumb: bool) -> Vec<NativeInstruction> {
+        let Some(end) = address.checked_add(size) else {
+            return vec![];
+        };
+        if size == 0 {
+            return vec![];
+        }
+        let mut boundaries: Vec<u64> = self
+            .mapping
+            .range((Bound::Excluded(address), Bound::Excluded(end)))
+            .map(|(boundary, _)| *boundary)
+            .collect();
+        boundaries.push(end);
+        let mut instructions = vec![];
+        let mut start = address;
+        for boundary in boundaries {
+            let mapping = self
+                .mapping
+                .range(..=start)
+                .next_back()
+                .map(|(_, mapping)| *mapping);
+            match mapping {
+                Some(Mapping::Data) => {}
+                Some(Mapping::Thumb) => {
+                    instructions.extend(self.disassemble_range(start, boundary - start, true))
+                }
+                Some(Mapping::Arm) => {
+                    instructions.extend(self.disassemble_range(start, boundary - start, false))
+                }
+                None => instructions.extend(self.disassemble_range(start, boundary - start, thumb)),
+            }
+            start = boundary;
+        }
+        instructions
+    }
+
+    fn disassemble_range(&self, address: u64, size: u64, thumb: bool) -> Vec<NativeInstruction> {
+        let Some(bytes) = self.bytes(address, size) else {
+            return vec![];
+        };
+        let thumb = thumb && self.arch == CpuArch::ArmV7;
+        CAPSTONES.with(|capstones| {
+            let mut capstones = capstones.borrow_mut();
+            let capstone = capstones
+                .entry((self.arch, thumb))
+                .or_insert_with(|| build_capstone(self.arch, thumb));
+            match capstone {
+                Some(capstone) => self.decode(capstone, bytes, address, thumb),
+                None => vec![],
+            }
+        })
+    }
+
+    fn decode(
+        &self,
+        capstone: &Capstone,
+        bytes: &[u8],
+        address: u64,
+        thumb: bool,
+    ) -> Vec<NativeInstruction> {
+        let alignment = match self.arch {
+            CpuArch::X86 | CpuArch::X86_64 => 1,
+            CpuArch::ArmV7 if thumb => 2,
+            _ => 4,
+        };
+        let mut instructions = vec![];
+        let mut offset = 0;
+        while offset < bytes.len() {
+            let decoded =
+                match capstone.disasm_all(&bytes[offset..], address.wrapping_add(offset as u64)) {
+                    Ok(decoded) => decoded,
+                    Err(_) => break,
+                };
+            let mut decoded_size = 0;
+            for insn in decoded.iter() {
+                decoded_size += insn.bytes().len();
+                instructions.push(self.instruction(capstone, &insn, thumb));
+            }
+            // capstone stops at the first invalid instruction
+            offset += decoded_size.max(alignment);
+        }
+        instructions
+    }
+
+    /// The file content at a virtual address, limited to the loaded part of the segment
+    fn bytes(&self, address: u64, size: u64) -> Option<&[u8]> {
+        let segment = self
+            .library
+            .segments
+            .iter()
+            .filter(|segment| segment.file_size > 0)
+            .find(|segment| {
+                address >= segment.address && address - segment.address < segment.file_size
+            })?;
+        let start = (address - segment.address).checked_add(segment.offset)?;
+        let end = segment
+            .offset
+            .checked_add(segment.file_size)?
+            .min(start.saturating_add(size));
+        self.object.data().get(start as usize..end as usize)
+    }
+
+    fn read_u32(&self, address: u64) -> Option<u64> {
+        let bytes = self.bytes(address, 4)?;
+        Some(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as u64)
+    }
+
+    fn split_thumb_bit(&self, address: u64) -> (u64, bool) {
+        if self.arch == CpuArch::ArmV7 {
+            (address & !1, address & 1 == 1)
+        } else {
+            (address, false)
+        }
+    }
+
+    fn instruction(&self, capstone: &Capstone, insn: &Insn, thumb: bool) -> NativeInstruction {
+        let mnemonic = insn.mnemonic().unwrap_or_default().to_string();
+        let mut instruction = NativeInstruction {
+            address: insn.address(),
+            bytes: insn.bytes().to_vec(),
+            mnemonic,
+            op_str: insn.op_str().unwrap_or_default().to_string(),
+            operands: vec![],
+            thumb,
+            flow: NativeFlow::Sequential,
+            target: None,
+            target_thumb: thumb,
+            reference: None,
+            symbol: None,
+        };
+        let Ok(detail) = capstone.insn_detail(insn) else {
+            return instruction;
+        };
+        let reg_name = |reg: RegId| -> Option<String> {
+            (reg.0 != 0).then(|| capstone.reg_name(reg)).flatten()
+        };
+        let mut conditional = false;
+        match detail.arch_detail() {
+            ArchDetail::ArmDetail(arm) => {
+                conditional = !matches!(
+                    arm.cc(),
+                    arch::arm::ArmCC::ARM_CC_AL | arch::arm::ArmCC::ARM_CC_INVALID
+                ) || matches!(instruction.mnemonic.as_str(), "cbz" | "cbnz");
+                for operand in arm.operands() {
+                    instruction.operands.push(match operand.op_type {
+                        ArmOperandType::Reg(reg) => {
+                            NativeOperand::Register(reg_name(reg).unwrap_or_default())
+                        }
+                        ArmOperandType::Imm(imm) => NativeOperand::Immediate(imm as u32 as i64),
+                        ArmOperandType::Mem(mem) => NativeOperand::Memory {
+                            base: reg_name(mem.base()),
+                            index: reg_name(mem.index()),
+                            scale: mem.scale(),
+                            displacement: mem.disp() as i64,
+                        },
+                        _ => NativeOperand::Other,
+                    });
+                }
+            }
+            ArchDetail::Arm64Detail(arm64) => {
+                conditional = !matches!(
+                    arm64.cc(),
+                    arch::arm64::Arm64CC::ARM64_CC_AL
+                        | arch::arm64::Arm64CC::ARM64_CC_NV
+                        | arch::arm64::Arm64CC::ARM64_CC_INVALID
+                ) || matches!(
+                    instruction.mnemonic.as_str(),
+                    "cbz" | "cbnz" | "tbz" | "tbnz"
+                );
+                for operand in arm64.operands() {
+                    instruction.operands.push(match operand.op_type {
+                        Arm64OperandType::Reg(reg) => {
+                            NativeOperand::Register(reg_name(reg).unwrap_or_default())
+                        }
+                        Arm64OperandType::Imm(imm) => NativeOperand::Immediate(imm),
+                        Arm64OperandType::Mem(mem) => NativeOperand::Memory {
+                            base: reg_name(mem.base()),
+                            index: reg_name(mem.index()),
+                            scale: 1,
+                            displacement: mem.disp() as i64,
+                        },
+                        _ => NativeOperand::Other,
+                    });
+                }
+            }
+            ArchDetail::X86Detail(x86) => {
+                conditional = instruction.mnemonic.starts_with('j')
+                    && instruction.mnemonic != "jmp"
+                    || instruction.mnemonic.starts_with("loop");
+                for operand in x86.operands() {
+                    instruction.operands.push(match operand.op_type {
+                        X86OperandType::Reg(reg) => {
+                            NativeOperand::Register(reg_name(reg).unwrap_or_default())
+                        }
+                        X86OperandType::Imm(imm) => NativeOperand::Immediate(imm),
+                        X86OperandType::Mem(mem) => NativeOperand::Memory {
+                            base: reg_name(mem.base()),
+                            index: reg_name(mem.index()),
+                            scale: mem.scale(),
+                            displacement: mem.disp(),
+                        },
+                        _ => NativeOperand::Other,
+                    });
+                }
+            }
+            _ => {}
+        }
+
+        let in_group = |group: InsnGroupType::Type| detail.groups().any(|g| g.0 as u32 == group);
+        instruction.flow = if in_group(InsnGroupType::CS_GRP_RET)
+            || in_group(InsnGroupType::CS_GRP_IRET)
+            || self.is_arm_return(&instruction)
+        {
+            NativeFlow::Return
+        } else if in_group(InsnGroupType::CS_GRP_CALL) {
+            NativeFlow::Call
+        } else if in_group(InsnGroupType::CS_GRP_JUMP) || self.writes_pc(&instruction) {
+            if conditional {
+                NativeFlow::ConditionalJump
+            } else {
+                NativeFlow::Jump
+            }
+        } else if in_group(InsnGroupType::CS_GRP_INT) {
+            NativeFlow::Interrupt
+        } else {
+            NativeFlow::Sequential
+        };
+
+        if instruction.flow != NativeFlow::Sequential {
+            instruction.target =
+                instruction
+                    .operands
+                    .iter()
+                    .rev()
+                    .find_map(|operand| match operand {
+                        NativeOperand::Immediate(imm) => Some(*imm as u64),
+                        _ => None,
+                    });
+            if instruction.target.is_some() && instruction.mnemonic == "blx" {
+                instruction.target_thumb = !thumb;
+            }
+        }
+        instruction.reference = self.pc_relative_reference(&instruction);
+        instruction.symbol = instruction
+            .target
+            .or(instruction.reference)
+            .and_then(|address| self.symbol_at(address))
+            .map(|symbol| symbol.to_string());
+        instruction
+    }
+
+    /// `bx lr`, `pop {..., pc}` and `ldm sp!, {..., pc}` return on ARMv7
+    fn is_arm_return(&self, instruction: &NativeInstruction) -> bool {
+        if self.arch != CpuArch::ArmV7 {
+            return false;
+        }
+        let has_register = |name: &str| {
+            instruction
+                .operands
+                .iter()
+                .any(|operand| matches!(operand, NativeOperand::Register(r) if r == name))
+        };
+        match instruction.mnemonic.as_str() {
+            "bx" => has_register("lr"),
+            "pop" | "pop.w" | "ldm" | "ldmia" | "ldmfd" | "ldm.w" => has_register("pc"),
+            _ => false,
+        }
+    }
+
+    /// `ldr pc, [...]` and `mov pc, ...` on ARMv7
+    fn writes_pc(&self, instruction: &NativeInstruction) -> bool {
+        self.arch == CpuArch::ArmV7
+            && (instruction.mnemonic.starts_with("ldr") || instruction.mnemonic.starts_with("mov"))
+            && matches!(instruction.operands.first(), Some(NativeOperand::Register(r)) if r == "pc")
+    }
+
+    fn pc_relative_reference(&self, instruction: &NativeInstruction) -> Option<u64> {
+        match self.arch {
+            CpuArch::Arm64 => {
+                let literal = matches!(instruction.mnemonic.as_str(), "adr" | "adrp")
+                    || instruction.mnemonic.starts_with("ldr")
+                    || instruction.mnemonic == "prfm";
+                if !literal || instruction.flow != NativeFlow::Sequential {
+                    return None;
+                }
+                match instruction.operands.last()? {
+                    NativeOperand::Immediate(imm) => Some(*imm as u64),
+                    _ => None,
+                }
+            }
+            CpuArch::ArmV7 => {
+                // the pc is two instructions ahead, word aligned in thumb mode
+                let pc = if instruction.thumb {
+                    instruction.address.wrapping_add(4) & !3
+                } else {
+                    instruction.address.wrapping_add(8)
+                };
+                instruction
+                    .operands
+                    .iter()
+                    .find_map(|operand| match operand {
+                        NativeOperand::Memory {
+                            base: Some(base),
+                            index: None,
+                            displacement,
+                            ..
+                        } if base == "pc" => Some(pc.wrapping_add(*displacement as u64)),
+                        _ => None,
+                    })
+            }
+            CpuArch::X86_64 => instruction
+                .operands
+                .iter()
+                .find_map(|operand| match operand {
+                    NativeOperand::Memory {
+                        base: Some(base),
+                        displacement,
+                        ..
+                    } if base == "rip" => Some(
+                        instruction
+                            .next_address()
+                            .wrapping_add(*displacement as u64),
+                    ),
+                    _ => None,
+                }),
+            CpuArch::X86 => None,
+        }
+    }
+
+    fn read_symbols(&mut self, elf: &Elf) {
+        let symbols = elf
+            .dynsyms
+            .iter()
+            .map(|sym| (sym, elf.dynstrtab.get_at(sym.st_name)))
+            .chain(
+                elf.syms
+                    .iter()
+                    .map(|sym| (sym, elf.strtab.get_at(sym.st_name))),
+            );
+        for (sym, name) in symbols {
+            let Some(name) = name else {
+                continue;
+            };
+            if sym.st_shndx == 0 {
+                continue;
+            }
+            let (address, thumb) = self.split_thumb_bit(sym.st_value);
+            if let Some(mapping) = mapping_symbol(name) {
+                self.mapping.insert(address, mapping);
+                continue;
+            }
+            if name.is_empty() || !matches!(sym.st_type(), STT_FUNC | STT_GNU_IFUNC) {
+                continue;
+            }
+            self.functions
+                .entry(address)
+                .or_insert_with(|| FunctionSymbol {
+                    name: name.to_string(),
+                    address,
+                    size: sym.st_size,
+                    thumb,
+                });
+        }
+    }
+
+    fn read_got(&mut self, elf: &Elf) {
+        let relocations = elf
+            .pltrelocs
+            .iter()
+            .chain(elf.dynrelas.iter())
+            .chain(elf.dynrels.iter());
+        for reloc in relocations {
+            if reloc.r_sym == 0 {
+                continue;
+            }
+            let Some(name) = elf
+                .dynsyms
+                .get(reloc.r_sym)
+                .and_then(|sym| elf.dynstrtab.get_at(sym.st_name))
+            else {
+                continue;
+            };
+            if !name.is_empty() {
+                self.got.insert(reloc.r_offset, name.to_string());
+            }
+        }
+    }
+
+    /// Follow the address computation of every PLT stub up to the GOT slot it jumps through
+    fn resolve_plt_stubs(&mut self) {
+        // 32 bit x86 stubs address the GOT relative to ebx, which points to `.got.plt`
+        let got_base = self
+            .library
+            .section(".got.plt")
+            .map(|section| section.address)
+            .unwrap_or_default();
+        let mut stubs = HashMap::new();
+        for name in [".plt", ".plt.sec", ".plt.got"].iter() {
+            let Some(section) = self.library.section(name).cloned() else {
+                continue;
+            };
+            let mut registers: HashMap<String, u64> = HashMap::new();
+            let mut stub_start = section.address;
+            for instruction in self.disassemble_range(section.address, section.size, false) {
+                let address = instruction.address;
+                let operands = folded_immediates(&instruction);
+                let slot = match (self.arch, operands.as_slice()) {
+                    (
+                        CpuArch::Arm64,
+                        [NativeOperand::Register(d), NativeOperand::Immediate(page)],
+                    ) if instruction.mnemonic == "adrp" => {
+                        registers.insert(d.clone(), *page as u64);
+                        stub_start = address;
+                        None
+                    }
+                    (
+                        CpuArch::Arm64,
+                        [NativeOperand::Register(_), NativeOperand::Memory {
+                            base: Some(base),
+                            displacement,
+                            ..
+                        }],
+                    ) if instruction.mnemonic == "ldr" => registers
+                        .get(base)
+                        .map(|value| value.wrapping_add(*displacement as u64)),
+                    (
+                        CpuArch::ArmV7,
+                        [NativeOperand::Register(d), NativeOperand::Register(s), NativeOperand::Immediate(imm)],
+                    ) if instruction.mnemonic == "add" => {
+                        if s == "pc" {
+                            registers.insert(
+                                d.clone(),
+                                address.wrapping_add(8).wrapping_add(*imm as u64),
+                            );
+                            stub_start = address;
+                        } else if let Some(value) = registers.get(s).copied() {
+                            registers
+                                .insert(d.clone(), value.wrapping_add(*imm as u64) & 0xffff_ffff);
+                        }
+                        None
+                    }
+                    (
+                        CpuArch::ArmV7,
+                        [NativeOperand::Register(d), NativeOperand::Register(a), NativeOperand::Register(b)],
+                    ) if instruction.mnemonic == "add" && (a == "pc" || b == "pc") => {
+                        let other = if a == "pc" { b } else { a };
+                        if let Some(value) = registers.get(other).copied() {
+                            registers.insert(
+                                d.clone(),
+                                address.wrapping_add(8).wrapping_add(value) & 0xffff_ffff,
+                            );
+                        }
+                        None
+                    }
+                    (
+                        CpuArch::ArmV7,
+                        [NativeOperand::Register(d), NativeOperand::Memory {
+                            base: Some(base),
+                            displacement,
+                            ..
+                        }],
+                    ) if instruction.mnemonic.starts_with("ldr") => {
+                        if d == "pc" {
+                            registers
+                                .get(base)
+                                .map(|value| value.wrapping_add(*displacement as u64))
+                        } else {
+                            if base == "pc" {
+                                // long stubs load the offset to the GOT from a literal
+                                if let Some(value) = self.read_u32(
+                                    address.wrapping_add(8).wrapping_add(*displacement as u64),
+                                ) {
+                                    registers.insert(d.clone(), value);
+                                    stub_start = address;
+                                }
+                            }
+                            None
+                        }
+                    }
+                    (
+                        CpuArch::X86_64,
+                        [NativeOperand::Memory {
+                            base: Some(base),
+                            displacement,
+                            ..
+                        }],
+                    ) if instruction.flow == NativeFlow::Jump && base == "rip" => {
+                        stub_start = section.address + ((address - section.address) & !15);
+                        Some(
+                            instruction
+                                .next_address()
+                                .wrapping_add(*displacement as u64),
+                        )
+                    }
+                    (
+                        CpuArch::X86,
+                        [NativeOperand::Memory {
+                            base, displacement, ..
+                        }],
+                    ) if instruction.flow == NativeFlow::Jump => {
+                        stub_start = section.address + ((address - section.address) & !15);
+                        match base.as_deref() {
+                            Some("ebx") => Some(got_base.wrapping_add(*displacement as u64)),
+                            None => Some(*displacement as u64),
+                            _ => None,
+                        }
+                    }
+                    _ => None,
+                };
+                if let Some(name) = slot.and_then(|slot| self.got.get(&slot)) {
+                    stubs.insert(stub_start, name.clone());
+                }
+            }
+        }
+        self.stubs = stubs;
+    }
+}
+
+/// capstone splits ARM modified immediates into the value and its rotation (`add ip, pc, #0, #12`)
+pub(crate) fn folded_immediates(instruction: &NativeInstruction) -> Vec<NativeOperand> {
+    let mut operands = instruction.operands.clone();
+    if instruction.mnemonic == "add" || instruction.mnemonic == "sub" {
+        if let [.., NativeOperand::Immediate(value), NativeOperand::Immediate(rotation)] =
+            operands.as_slice()
+        {
+            let value = (*value as u32).rotate_right(*rotation as u32) as i64;
+            operands.truncate(operands.len() - 2);
+            operands.push(NativeOperand::Immediate(value));
+        }
+    }
+    operands
+}
+
+fn build_capstone(arch: CpuArch, thumb: bool) -> Option<Capstone> {
+    let capstone = match arch {
+        CpuArch::ArmV7 => Capstone::new()
+            .arm()
+            .mode(if thumb {
+                arch::arm::ArchMode::Thumb
+            } else {
+                arch::arm::ArchMode::Arm
+            })
+            .detail(true)
+            .build(),
+        CpuArch::Arm64 => Capstone::new()
+            .arm64()
+            .mode(arch::arm64::ArchMode::Arm)
+            .detail(true)
+            .build(),
+        CpuArch::X86 => Capstone::new()
+            .x86()
+            .mode(arch::x86::ArchMode::Mode32)
+            .detail(true)
+            .build(),
+        CpuArch::X86_64 => Capstone::new()
+            .x86()
+            .mode(arch::x86::ArchMode::Mode64)
+            .detail(true)
+            .build(),
+    };
+    capstone.ok()
+}
+
+fn mapping_symbol(name: &str) -> Option<Mapping> {
+    let kind = name.strip_prefix('$')?;
+    match kind.split('.').next()? {
+        "a" | "x" => Some(Mapping::Arm),
+        "t" => Some(Mapping::Thumb),
+        "d" => Some(Mapping::Data),
+        _ => None,
+    }
+}
+
+/// Search the disassembly of all functions of all ELF binaries. The regex is matched against the
+/// textual form of each instruction, including the resolved symbol (e.g.
+/// `0x1234: bl #0x5670 <strlen>`).
+pub fn find_native_instructions(
+    reg: &Regex,
+    files: &HashMap<String, Arc<BinaryObject>>,
+) -> Vec<Evidence> {
+    let mut matches = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut matches));
+    iterator!(files).for_each(|(file_name, object)| {
+        let Some(disassembler) = NativeDisassembler::new(object.clone()) else {
+            return;
+        };
+        let exports: Vec<&str> = disassembler
+            .library()
+            .exports
+            .iter()
+            .map(|export| export.name.as_str())
+            .collect();
+        let mut file_matches = vec![];
+        for symbol in disassembler.functions() {
+            let Some(function) = disassembler.function_at(symbol.address | symbol.thumb as u64)
+            else {
+                continue;
+            };
+            let entry = function.address | function.thumb as u64;
+            let sym = Sym {
+                st_value: entry,
+                st_size: function
+                    .instructions
+                    .last()
+                    .map(|last| last.next_address() - function.address)
+                    .unwrap_or_default(),
+                ..Sym::default()
+            };
+            for instruction in &function.instructions {
+                let text = instruction.to_string();
+                if !reg.is_match(&text) {
+                    continue;
+                }
+                file_matches.push(Evidence::Instructions(InstructionEvidence {
+                    instructions: vec![text],
+                    place: Location::NativeAddress(file_name.to_string(), instruction.address),
+                    context: Context::NativeLib(
+                        object.clone(),
+                        function.name.clone(),
+                        entry,
+                        exports.contains(&function.name.as_str()),
+                        sym,
+                    ),
+                    confidence_level: ConfidenceLevel::Medium,
+                }));
+            }
+        }
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(file_matches);
+        }
+    });
+    matches
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An AArch64 shared object with a single loadable segment, containing `mov x0, #1; ret` at
+    /// 0x100
+    fn disassembler() -> NativeDisassembler {
+        let mut data = vec![0u8; 0x200];
+        let mut put = |offset: usize, bytes: &[u8]| {
+            data[offset..offset + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, b"\x7fELF\x02\x01\x01");
+        put(16, &[3, 0, 0xb7, 0, 1, 0, 0, 0]);
+        put(32, &64u64.to_le_bytes());
+        put(52, &[64, 0, 56, 0, 1, 0, 64, 0]);
+        put(64, &[1, 0, 0, 0, 5, 0, 0, 0]);
+        put(96, &0x200u64.to_le_bytes());
+        put(104, &0x200u64.to_le_bytes());
+        put(112, &0x1000u64.to_le_bytes());
+        put(0x100, &[0x20, 0x00, 0x80, 0xd2, 0xc0, 0x03, 0x5f, 0xd6]);
+        NativeDisassembler::new(Arc::new(BinaryObject::new(data))).unwrap()
+    }
+
+    #[test]
+    fn disassembles_ranges() {
+        let disassembler = disassembler();
+        let instructions = disassembler.disassemble(0x100, 8, false);
+        let mnemonics: Vec<_> = instructions.iter().map(|i| i.mnemonic.as_str()).collect();
+        assert_eq!(mnemonics, vec!["mov", "ret"]);
+        assert_eq!(instructions[1].flow, NativeFlow::Return);
+        // the handles are reused for further ranges
+        assert_eq!(disassembler.disassemble(0x104, 4, false).len(), 1);
+    }
+
+    #[test]
+    fn empty_and_overflowing_ranges_are_not_disassembled() {
+        let disassembler = disassembler();
+        assert!(disassembler.disassemble(0x100, 0, false).is_empty());
+        assert!(disassembler.disassemble(u64::MAX - 2, 16, false).is_empty());
+        assert!(disassembler.disassemble(0x1000, 8, false).is_empty());
+        assert!(disassembler.function_at(u64::MAX).is_none());
+        // limited to the end of the segment
+        let instructions = disassembler.disassemble(0x100, u64::MAX - 0x100, false);
+        assert_eq!(instructions[1].mnemonic, "ret");
+        assert!(instructions.iter().all(|i| i.address < 0x200));
+    }
+}
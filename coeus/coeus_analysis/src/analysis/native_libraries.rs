@@ -0,0 +1,607 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Identify well known native libraries (TLS stacks, databases, cross platform frameworks, game
+//! engines, protectors) and their versions by the signatures of a `NativeSignatureDatabase` (see
+//! `data/native_signatures.txt` for the bundled one). Versions are checked against the known
+//! vulnerable version ranges of the database.
+
+use std::{
+    cmp::Ordering,
+    collections::HashSet,
+    sync::{Arc, Mutex, OnceLock},
+};
+
+use goblin::elf::Elf;
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+
+use coeus_macros::iterator;
+use coeus_models::models::{BinaryObject, Files};
+
+use super::ConfidenceLevel;
+
+const BUNDLED_SIGNATURES: &str = include_str!("../../data/native_signatures.txt");
+/// Matched content longer than this is shortened in `SignatureMatch`
+const MAX_MATCH_LENGTH: usize = 128;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum NativeLibraryCategory {
+    Crypto,
+    Network,
+    Database,
+    Framework,
+    Engine,
+    Compression,
+    Protector,
+    Other,
+}
+
+impl NativeLibraryCategory {
+    fn parse(category: &str) -> Option<Self> {
+        Some(match category {
+            "crypto" => NativeLibraryCategory::Crypto,
+            "network" => NativeLibraryCategory::Network,
+            "database" => NativeLibraryCategory::Database,
+            "framework" => NativeLibraryCategory::Framework,
+            "engine" => NativeLibraryCategory::Engine,
+            "compression" => NativeLibraryCategory::Compression,
+            "protector" => NativeLibraryCategory::Protector,
+            "other" => NativeLibraryCategory::Other,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum SignatureKind {
+    Symbol,
+    String,
+    File,
+    Section,
+}
+
+impl SignatureKind {
+    fn parse(kind: &str) -> Option<Self> {
+        Some(match kind {
+            "symbol" => SignatureKind::Symbol,
+            "string" => SignatureKind::String,
+            "file" => SignatureKind::File,
+            "section" => SignatureKind::Section,
+            _ => return None,
+        })
+    }
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct KnownLibrary {
+    pub id: String,
+    pub name: String,
+    pub category: NativeLibraryCategory,
+    /// Only report the library if a signature of this kind matched
+    pub requires: Option<SignatureKind>,
+}
+
+#[derive(Clone, Debug)]
+struct Signature {
+    library: usize,
+    kind: SignatureKind,
+    regex: regex::bytes::Regex,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum VersionOperator {
+    Less,
+    LessOrEqual,
+    Greater,
+    GreaterOrEqual,
+    Equal,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct VersionConstraint {
+    pub operator: VersionOperator,
+    pub version: String,
+}
+
+impl VersionConstraint {
+    fn parse(constraint: &str) -> Option<Self> {
+        let (operator, version) = [
+            ("<=", VersionOperator::LessOrEqual),
+            (">=", VersionOperator::GreaterOrEqual),
+            ("<", VersionOperator::Less),
+            (">", VersionOperator::Greater),
+            ("=", VersionOperator::Equal),
+        ]
+        .iter()
+        .find_map(|(prefix, operator)| {
+            constraint
+                .strip_prefix(prefix)
+                .map(|version| (*operator, version))
+        })?;
+        if version.is_empty() {
+            return None;
+        }
+        Some(VersionConstraint {
+            operator,
+            version: version.to_string(),
+        })
+    }
+
+    pub fn matches(&self, version: &str) -> bool {
+        let ordering = compare_versions(version, &self.version);
+        match self.operator {
+            VersionOperator::Less => ordering == Ordering::Less,
+            VersionOperator::LessOrEqual => ordering != Ordering::Greater,
+            VersionOperator::Greater => ordering == Ordering::Greater,
+            VersionOperator::GreaterOrEqual => ordering != Ordering::Less,
+            VersionOperator::Equal => ordering == Ordering::Equal,
+        }
+    }
+}
+
+/// Versions in `constraints` (all of them have to match) are affected by `advisory`
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Vulnerability {
+    pub constraints: Vec<VersionConstraint>,
+    pub advisory: String,
+}
+
+impl Vulnerability {
+    pub fn affects(&self, version: &str) -> bool {
+        self.constraints
+            .iter()
+            .all(|constraint| constraint.matches(version))
+    }
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct NativeSignatureDatabase {
+    libraries: Vec<KnownLibrary>,
+    signatures: Vec<Signature>,
+    /// (library, vulnerability)
+    vulnerabilities: Vec<(usize, Vulnerability)>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct SignatureMatch {
+    pub kind: SignatureKind,
+    /// The symbol, section or file name, or the matched part of the content
+    pub content: String,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct IdentifiedLibrary {
+    /// The file in `Files::binaries`
+    pub file_name: String,
+    /// Id of the library in the signature database
+    pub library: String,
+    pub name: String,
+    pub category: NativeLibraryCategory,
+    pub version: Option<String>,
+    pub matches: Vec<SignatureMatch>,
+    pub confidence_level: ConfidenceLevel,
+    /// Known vulnerabilities of `version`
+    pub vulnerabilities: Vec<Vulnerability>,
+}
+
+impl IdentifiedLibrary {
+    pub fn is_vulnerable(&self) -> bool {
+        !self.vulnerabilities.is_empty()
+    }
+}
+
+impl NativeSignatureDatabase {
+    /// The signatures bundled with coeus
+    pub fn bundled() -> Arc<NativeSignatureDatabase> {
+        static BUNDLED: OnceLock<Arc<NativeSignatureDatabase>> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| {
+                Arc::new(
+                    Self::parse(BUNDLED_SIGNATURES).expect("bundled native signatures are valid"),
+                )
+            })
+            .clone()
+    }
+
+    /// Parse signatures in the format of the bundled ones
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut database = Self::default();
+        for (line_number, line) in content.lines().enumerate() {
+            let error = |msg: &str| format!("line {}: {}", line_number + 1, msg);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let (Some(kind), Some(id), Some(rest)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(error("wrong number of arguments"));
+            };
+            let rest = rest.trim();
+            if kind == "library" {
+                if database.library_index(id).is_some() {
+                    return Err(error(&format!("duplicate library {}", id)));
+                }
+                let mut tokens: Vec<&str> = rest.split_whitespace().collect();
+                let category = tokens
+                    .first()
+                    .and_then(|category| NativeLibraryCategory::parse(category))
+                    .ok_or_else(|| error("unknown category"))?;
+                tokens.remove(0);
+                let mut requires = None;
+                while let Some(option) = tokens.last().and_then(|token| token.split_once('=')) {
+                    match option {
+                        ("requires", kind) => {
+                            requires = Some(
+                                SignatureKind::parse(kind)
+                                    .ok_or_else(|| error(&format!("unknown kind {}", kind)))?,
+                            )
+                        }
+                        (option, _) => return Err(error(&format!("unknown option {}", option))),
+                    }
+                    tokens.pop();
+                }
+                if tokens.is_empty() {
+                    return Err(error("missing name"));
+                }
+                database.libraries.push(KnownLibrary {
+                    id: id.to_string(),
+                    name: tokens.join(" "),
+                    category,
+                    requires,
+                });
+                continue;
+            }
+            let library = database
+                .library_index(id)
+                .ok_or_else(|| error(&format!("unknown library {}", id)))?;
+            if kind == "vulnerable" {
+                let mut constraints = vec![];
+                let mut advisory = rest;
+                while let Some((token, remaining)) = advisory
+                    .split_once(char::is_whitespace)
+                    .filter(|(token, _)| token.starts_with(['<', '>', '=']))
+                {
+                    constraints.push(
+                        VersionConstraint::parse(token)
+                            .ok_or_else(|| error(&format!("invalid constraint {}", token)))?,
+                    );
+                    advisory = remaining.trim_start();
+                }
+                if constraints.is_empty() {
+                    return Err(error("missing version constraint"));
+                }
+                database.vulnerabilities.push((
+                    library,
+                    Vulnerability {
+                        constraints,
+                        advisory: advisory.to_string(),
+                    },
+                ));
+                continue;
+            }
+            let kind = SignatureKind::parse(kind)
+                .ok_or_else(|| error(&format!("unknown entry {}", kind)))?;
+            let regex = regex::bytes::Regex::new(rest)
+                .map_err(|e| error(&format!("invalid regex: {}", e)))?;
+            database.signatures.push(Signature {
+                library,
+                kind,
+                regex,
+            });
+        }
+        Ok(database)
+    }
+
+    fn library_index(&self, id: &str) -> Option<usize> {
+        self.libraries.iter().position(|library| library.id == id)
+    }
+
+    pub fn libraries(&self) -> &[KnownLibrary] {
+        &self.libraries
+    }
+
+    /// The known vulnerabilities of `version` of the library with the id `library`
+    pub fn vulnerabilities(&self, library: &str, version: &str) -> Vec<&Vulnerability> {
+        let Some(index) = self.library_index(library) else {
+            return vec![];
+        };
+        self.vulnerabilities
+            .iter()
+            .filter(|(library, vulnerability)| *library == index && vulnerability.affects(version))
+            .map(|(_, vulnerability)| vulnerability)
+            .collect()
+    }
+
+    /// All known libraries matching the ELF file `object`
+    pub fn identify(&self, file_name: &str, object: &BinaryObject) -> Vec<IdentifiedLibrary> {
+        let data = object.data();
+        let Ok(elf) = Elf::parse(data) else {
+            return vec![];
+        };
+        // imports only tell what the library uses
+        let symbols: HashSet<&str> = elf
+            .dynsyms
+            .iter()
+            .filter(|sym| sym.st_shndx != 0)
+            .filter_map(|sym| elf.dynstrtab.get_at(sym.st_name))
+            .chain(
+                elf.syms
+                    .iter()
+                    .filter(|sym| sym.st_shndx != 0)
+                    .filter_map(|sym| elf.strtab.get_at(sym.st_name)),
+            )
+            .filter(|name| !name.is_empty())
+            .collect();
+        let sections: Vec<&str> = elf
+            .section_headers
+            .iter()
+            .filter_map(|header| elf.shdr_strtab.get_at(header.sh_name))
+            .collect();
+        let base_name = file_name.rsplit('/').next().unwrap_or(file_name);
+
+        let mut found: Vec<(Vec<SignatureMatch>, Option<String>)> =
+            vec![(vec![], None); self.libraries.len()];
+        for signature in &self.signatures {
+            let (matches, version) = &mut found[signature.library];
+            let content = match signature.kind {
+                SignatureKind::Symbol => symbols
+                    .iter()
+                    .find(|symbol| signature.regex.is_match(symbol.as_bytes()))
+                    .map(|symbol| symbol.to_string()),
+                SignatureKind::Section => sections
+                    .iter()
+                    .find(|section| signature.regex.is_match(section.as_bytes()))
+                    .map(|section| section.to_string()),
+                SignatureKind::File => signature
+                    .regex
+                    .is_match(base_name.as_bytes())
+                    .then(|| base_name.to_string()),
+                SignatureKind::String => signature.regex.captures(data).map(|captures| {
+                    if let Some(captured) = captures.name("version") {
+                        if version.is_none() {
+                            *version = Some(String::from_utf8_lossy(captured.as_bytes()).into());
+                        }
+                    }
+                    printable(captures.get(0).map(|m| m.as_bytes()).unwrap_or_default())
+                }),
+            };
+            if let Some(content) = content {
+                matches.push(SignatureMatch {
+                    kind: signature.kind,
+                    content,
+                });
+            }
+        }
+
+        let mut identified = vec![];
+        for (library, (matches, version)) in self.libraries.iter().zip(found) {
+            if matches.is_empty() {
+                continue;
+            }
+            if let Some(requires) = library.requires {
+                if !matches.iter().any(|m| m.kind == requires) {
+                    continue;
+                }
+            }
+            let kinds: HashSet<SignatureKind> = matches.iter().map(|m| m.kind).collect();
+            let confidence_level = match (kinds.len(), kinds.contains(&SignatureKind::File)) {
+                (1, true) => ConfidenceLevel::Low,
+                (1, false) => ConfidenceLevel::Medium,
+                _ => ConfidenceLevel::High,
+            };
+            let vulnerabilities = version
+                .as_deref()
+                .map(|version| {
+                    self.vulnerabilities(&library.id, version)
+                        .into_iter()
+                        .cloned()
+                        .collect()
+                })
+                .unwrap_or_default();
+            identified.push(IdentifiedLibrary {
+                file_name: file_name.to_string(),
+                library: library.id.clone(),
+                name: library.name.clone(),
+                category: library.category,
+                version,
+                matches,
+                confidence_level,
+                vulnerabilities,
+            });
+        }
+        identified
+    }
+}
+
+fn printable(bytes: &[u8]) -> String {
+    let content: String = String::from_utf8_lossy(bytes)
+        .chars()
+        .filter(|c| !c.is_control())
+        .collect();
+    match content.char_indices().nth(MAX_MATCH_LENGTH) {
+        Some((end, _)) => format!("{}...", &content[..end]),
+        None => content,
+    }
+}
+
+/// A component of a version. The variant order is the sort order: a prerelease tag precedes the
+/// end of a version (`3.0.0-rc1` < `3.0.0`), which precedes a letter suffix (`1.1.1` < `1.1.1a`),
+/// which precedes a further number (`1.1.1a` < `1.1.1.1`).
+#[derive(Debug, PartialEq, Eq, PartialOrd, Ord)]
+enum VersionPart<'a> {
+    Prerelease(u8, &'a str),
+    End,
+    Suffix(&'a str),
+    Number(u64),
+}
+
+/// Rank of a prerelease tag, `None` for other text
+fn prerelease_rank(tag: &str) -> Option<u8> {
+    const TAGS: [&[&str]; 5] = [
+        &["dev", "snapshot", "nightly"],
+        &["alpha"],
+        &["beta"],
+        &["pre", "preview"],
+        &["rc"],
+    ];
+    TAGS.iter()
+        .position(|names| names.iter().any(|name| tag.eq_ignore_ascii_case(name)))
+        .map(|rank| rank as u8)
+}
+
+fn version_parts(version: &str) -> Vec<VersionPart<'_>> {
+    let mut parts = vec![];
+    for component in version.split(|c: char| !c.is_ascii_alphanumeric()) {
+        let mut rest = component;
+        while !rest.is_empty() {
+            let digits = rest.starts_with(|c: char| c.is_ascii_digit());
+            let end = rest
+                .find(|c: char| c.is_ascii_digit() != digits)
+                .unwrap_or(rest.len());
+            let (part, remaining) = rest.split_at(end);
+            parts.push(if digits {
+                VersionPart::Number(part.parse().unwrap_or(u64::MAX))
+            } else if let Some(rank) = prerelease_rank(part) {
+                VersionPart::Prerelease(rank, part)
+            } else {
+                VersionPart::Suffix(part)
+            });
+            rest = remaining;
+        }
+    }
+    parts
+}
+
+/// Compare versions component wise, numbers numerically and letters in lexical order. A letter
+/// suffix sorts after the version and before a further component (`1.1.1` < `1.1.1a` <
+/// `1.1.1.1`), prerelease tags sort before the release (`3.0.0-beta1` < `3.0.0-rc1` < `3.0.0`).
+pub fn compare_versions(a: &str, b: &str) -> Ordering {
+    let (a, b) = (version_parts(a), version_parts(b));
+    (0..a.len().max(b.len()))
+        .map(|i| {
+            let left = a.get(i).unwrap_or(&VersionPart::End);
+            let right = b.get(i).unwrap_or(&VersionPart::End);
+            left.cmp(right)
+        })
+        .find(|ordering| *ordering != Ordering::Equal)
+        .unwrap_or(Ordering::Equal)
+}
+
+/// Identify the known native libraries of all binaries with the bundled signatures
+pub fn find_known_native_libraries(files: &Files) -> Vec<IdentifiedLibrary> {
+    find_known_native_libraries_with_database(files, &NativeSignatureDatabase::bundled())
+}
+
+pub fn find_known_native_libraries_with_database(
+    files: &Files,
+    database: &NativeSignatureDatabase,
+) -> Vec<IdentifiedLibrary> {
+    let mut identified = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut identified));
+    iterator!(files.binaries).for_each(|(file_name, object)| {
+        if !object.data().starts_with(b"\x7fELF") {
+            return;
+        }
+        let libraries = database.identify(file_name, object);
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(libraries);
+        }
+    });
+    identified.sort_by(|a, b| {
+        a.file_name
+            .cmp(&b.file_name)
+            .then_with(|| a.library.cmp(&b.library))
+    });
+    identified
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_ascending(versions: &[&str]) {
+        for pair in versions.windows(2) {
+            assert_eq!(
+                compare_versions(pair[0], pair[1]),
+                Ordering::Less,
+                "{} < {}",
+                pair[0],
+                pair[1]
+            );
+            assert_eq!(compare_versions(pair[1], pair[0]), Ordering::Greater);
+        }
+    }
+
+    #[test]
+    fn test_compare_versions_letter_suffixes() {
+        assert_ascending(&["1.1.1", "1.1.1a", "1.1.1k", "1.1.1t", "1.1.1.1", "1.1.2"]);
+        assert_ascending(&["1.0.2", "1.0.2k", "1.0.2k-fips", "1.0.10"]);
+    }
+
+    #[test]
+    fn test_compare_versions_prereleases() {
+        assert_ascending(&[
+            "3.0.0-dev",
+            "3.0.0-alpha1",
+            "3.0.0-beta1",
+            "3.0.0-beta2",
+            "3.0.0-beta10",
+            "3.0.0-rc1",
+            "3.0.0",
+            "3.0.0a",
+            "3.0.1",
+        ]);
+        assert_ascending(&["2.4.0beta", "2.4.0RC1", "2.4.0"]);
+    }
+
+    #[test]
+    fn test_compare_versions_equal() {
+        assert_eq!(compare_versions("1.2.3", "1.2.3"), Ordering::Equal);
+        assert_eq!(compare_versions("1.2.3-rc1", "1.2.3.rc1"), Ordering::Equal);
+        assert_eq!(compare_versions("", ""), Ordering::Equal);
+        assert_eq!(compare_versions("", "0"), Ordering::Less);
+    }
+
+    #[test]
+    fn test_vulnerability_ranges() {
+        let heartbleed = Vulnerability {
+            constraints: vec![
+                VersionConstraint::parse(">=1.0.1").unwrap(),
+                VersionConstraint::parse("<1.0.1g").unwrap(),
+            ],
+            advisory: "CVE-2014-0160".to_string(),
+        };
+        assert!(heartbleed.affects("1.0.1"));
+        assert!(heartbleed.affects("1.0.1f"));
+        assert!(!heartbleed.affects("1.0.1g"));
+        assert!(!heartbleed.affects("1.0.1.1"));
+        assert!(!heartbleed.affects("1.0.0z"));
+
+        let openssl3 = Vulnerability {
+            constraints: vec![
+                VersionConstraint::parse(">=3.0.0").unwrap(),
+                VersionConstraint::parse("<3.0.7").unwrap(),
+            ],
+            advisory: "CVE-2022-3602".to_string(),
+        };
+        assert!(!openssl3.affects("3.0.0-beta1"));
+        assert!(openssl3.affects("3.0.0"));
+        assert!(openssl3.affects("3.0.7-alpha1"));
+        assert!(!openssl3.affects("3.0.7"));
+    }
+
+    #[test]
+    fn test_parse_constraint() {
+        assert!(VersionConstraint::parse("<").is_none());
+        assert!(VersionConstraint::parse("1.0").is_none());
+        let constraint = VersionConstraint::parse("<=1.2").unwrap();
+        assert!(constraint.matches("1.2"));
+        assert!(constraint.matches("1.2-rc1"));
+        assert!(!constraint.matches("1.2a"));
+    }
+}
@@ -0,0 +1,1007 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Call graph and string cross references of native libraries. All functions of a library are
+//! disassembled, starting from the symbols, `.init_array`/`.fini_array`, the `JNINativeMethod`
+//! tables and the tables of function pointers in the data sections. Functions without a symbol
+//! are found as call targets and function pointers.
+//!
+//! Addresses computed in registers are followed to the strings, functions and pointer tables
+//! they refer to: `adrp`/`add` and literal loads on AArch64, literal offsets added to `pc` (or
+//! built with `movw`/`movt`) on ARMv7, `rip` relative operands on x86_64 and registers set by
+//! the PC thunks on x86.
+
+use std::{
+    collections::{BTreeMap, HashMap, HashSet},
+    sync::{Arc, Mutex},
+};
+
+use goblin::elf::{section_header::SHF_EXECINSTR, Elf, Sym};
+use petgraph::{
+    graph::{DiGraph, NodeIndex},
+    visit::EdgeRef,
+    Direction,
+};
+use rayon::iter::ParallelIterator;
+use regex::Regex;
+
+use coeus_macros::iterator;
+use coeus_models::models::{BinaryObject, CpuArch};
+
+use super::{
+    native::{align_up, find_jni_native_methods, RelocatedImage},
+    native_disassembly::{
+        folded_immediates, NativeDisassembler, NativeFlow, NativeFunction, NativeInstruction,
+        NativeOperand,
+    },
+    Context, CrossReferenceEvidence, Evidence, Location,
+};
+
+/// Longer "strings" are most likely some other data
+const MAX_STRING_LENGTH: usize = 4096;
+/// Every round disassembles all functions, usually no new ones are found after two or three
+const MAX_ROUNDS: usize = 8;
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum NativeCallKind {
+    /// A call or tail call to a function of the library
+    Direct,
+    /// A call to an imported function through the PLT or a GOT slot
+    Import,
+    /// A function whose address is taken, either directly or by referencing a table of function
+    /// pointers, or which is called through a register
+    Pointer,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum NativeNode {
+    Function {
+        name: String,
+        /// Entry of the function, without the thumb bit
+        address: u64,
+        size: u64,
+        thumb: bool,
+    },
+    Import(String),
+}
+
+impl NativeNode {
+    pub fn name(&self) -> &str {
+        match self {
+            NativeNode::Function { name, .. } => name,
+            NativeNode::Import(name) => name,
+        }
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NativeCall {
+    /// Address of the calling (or referencing) instruction
+    pub site: u64,
+    pub kind: NativeCallKind,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NativeStringReference {
+    /// Entry of the function using the string, without the thumb bit
+    pub function: u64,
+    /// Address of the instruction completing the address computation
+    pub site: u64,
+    pub address: u64,
+    pub content: String,
+}
+
+pub struct NativeCallGraph {
+    pub graph: DiGraph<NativeNode, NativeCall>,
+    pub strings: Vec<NativeStringReference>,
+    functions: HashMap<u64, NodeIndex>,
+    imports: HashMap<String, NodeIndex>,
+    exports: HashSet<String>,
+}
+
+impl NativeCallGraph {
+    /// The graph of the object, built by the first search and shared by all later ones
+    pub fn cached(object: &Arc<BinaryObject>) -> Option<Arc<Self>> {
+        object.derived(|| Self::new(object.clone()))
+    }
+
+    /// Returns `None` if the object is not an ELF file of one of the supported architectures
+    pub fn new(object: Arc<BinaryObject>) -> Option<Self> {
+        let elf = Elf::parse(object.data()).ok()?;
+        let image = RelocatedImage::new(&elf, object.data())?;
+        let disassembler = NativeDisassembler::new(object.clone())?;
+        let mut builder = Builder::new(object.clone(), disassembler, image);
+        let results = builder.analyze_functions();
+
+        let mut call_graph = NativeCallGraph {
+            graph: DiGraph::new(),
+            strings: vec![],
+            functions: HashMap::new(),
+            imports: HashMap::new(),
+            exports: builder
+                .image
+                .library
+                .exports
+                .iter()
+                .map(|export| export.name.clone())
+                .collect(),
+        };
+        for (function, _) in &results {
+            let node = call_graph.graph.add_node(NativeNode::Function {
+                name: function.name.clone(),
+                address: function.address,
+                size: function_size(function),
+                thumb: function.thumb,
+            });
+            call_graph.functions.insert(function.address, node);
+        }
+        let mask = builder.thumb_mask();
+        for (function, uses) in results {
+            let caller = call_graph.functions[&function.address];
+            for (site, callee, kind) in uses.calls {
+                let callee = match callee {
+                    Callee::Function(address) => {
+                        match call_graph.functions.get(&(address & !mask)) {
+                            Some(node) => *node,
+                            None => continue,
+                        }
+                    }
+                    Callee::Import(name) => call_graph.import_node(name),
+                };
+                call_graph
+                    .graph
+                    .add_edge(caller, callee, NativeCall { site, kind });
+            }
+            call_graph
+                .strings
+                .extend(uses.strings.into_iter().map(|(site, address, content)| {
+                    NativeStringReference {
+                        function: function.address,
+                        site,
+                        address,
+                        content,
+                    }
+                }));
+        }
+        Some(call_graph)
+    }
+
+    fn import_node(&mut self, name: String) -> NodeIndex {
+        if let Some(node) = self.imports.get(&name) {
+            return *node;
+        }
+        let node = self.graph.add_node(NativeNode::Import(name.clone()));
+        self.imports.insert(name, node);
+        node
+    }
+
+    /// The node of the function at `address` (with or without the thumb bit)
+    pub fn function(&self, address: u64) -> Option<NodeIndex> {
+        self.functions
+            .get(&address)
+            .or_else(|| self.functions.get(&(address & !1)))
+            .copied()
+    }
+
+    pub fn import(&self, name: &str) -> Option<NodeIndex> {
+        self.imports.get(name).copied()
+    }
+
+    pub fn node(&self, node: NodeIndex) -> &NativeNode {
+        &self.graph[node]
+    }
+
+    /// Functions as `Context::NativeLib`, imports as `Context::NativeSymbol`
+    pub fn context(&self, object: &Arc<BinaryObject>, node: NodeIndex) -> Context {
+        match &self.graph[node] {
+            NativeNode::Function {
+                name,
+                address,
+                size,
+                thumb,
+            } => {
+                let entry = address | *thumb as u64;
+                let sym = Sym {
+                    st_value: entry,
+                    st_size: *size,
+                    ..Sym::default()
+                };
+                Context::NativeLib(
+                    object.clone(),
+                    name.clone(),
+                    entry,
+                    self.exports.contains(name),
+                    sym,
+                )
+            }
+            NativeNode::Import(name) => Context::NativeSymbol(object.clone(), name.clone()),
+        }
+    }
+
+    /// All functions and imports called by `node`, once per call site
+    pub fn callees(&self, node: NodeIndex) -> Vec<(NodeIndex, NativeCall)> {
+        self.graph
+            .edges_directed(node, Direction::Outgoing)
+            .map(|edge| (edge.target(), *edge.weight()))
+            .collect()
+    }
+
+    /// All functions calling `node`, once per call site
+    pub fn callers(&self, node: NodeIndex) -> Vec<(NodeIndex, NativeCall)> {
+        self.graph
+            .edges_directed(node, Direction::Incoming)
+            .map(|edge| (edge.source(), *edge.weight()))
+            .collect()
+    }
+
+    /// The strings used by the function at `address`
+    pub fn strings_of(&self, address: u64) -> impl Iterator<Item = &NativeStringReference> {
+        let address = self
+            .function(address)
+            .and_then(|node| match &self.graph[node] {
+                NativeNode::Function { address, .. } => Some(*address),
+                _ => None,
+            });
+        self.strings
+            .iter()
+            .filter(move |reference| Some(reference.function) == address)
+    }
+
+    /// All uses of the memory from `start` to `end`. Suffixes of a string are referenced on
+    /// their own if the linker merged strings.
+    pub fn string_users(
+        &self,
+        start: u64,
+        end: u64,
+    ) -> impl Iterator<Item = &NativeStringReference> {
+        self.strings
+            .iter()
+            .filter(move |reference| reference.address >= start && reference.address < end)
+    }
+}
+
+fn function_size(function: &NativeFunction) -> u64 {
+    function
+        .instructions
+        .last()
+        .map(|last| last.next_address().wrapping_sub(function.address))
+        .unwrap_or_default()
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Value {
+    Address(u64),
+    /// An offset relative to the pc of a later instruction (ARMv7)
+    Offset(u64),
+    Import(String),
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Callee {
+    /// The entry of a function, with the thumb bit on ARMv7
+    Function(u64),
+    Import(String),
+}
+
+#[derive(Default)]
+struct FunctionUses {
+    calls: Vec<(u64, Callee, NativeCallKind)>,
+    /// The instruction, the address of the string and its content
+    strings: Vec<(u64, u64, String)>,
+}
+
+struct Builder<'a> {
+    object: Arc<BinaryObject>,
+    disassembler: NativeDisassembler,
+    image: RelocatedImage<'a>,
+    arch: CpuArch,
+    /// Tables of function pointers by their first slot: the end of the table and its entries
+    tables: BTreeMap<u64, (u64, Vec<(u64, u64)>)>,
+    code: Vec<(u64, u64)>,
+    data: Vec<(u64, u64)>,
+}
+
+impl<'a> Builder<'a> {
+    fn new(
+        object: Arc<BinaryObject>,
+        disassembler: NativeDisassembler,
+        image: RelocatedImage<'a>,
+    ) -> Self {
+        let arch = disassembler.arch();
+        let library = &image.library;
+        // in older layouts `.rodata` shares the executable segment with `.text`, prefer sections
+        let mut code: Vec<(u64, u64)> = library
+            .sections
+            .iter()
+            .filter(|section| section.flags & SHF_EXECINSTR as u64 != 0)
+            .map(|section| {
+                (
+                    section.address,
+                    section.address.saturating_add(section.size),
+                )
+            })
+            .collect();
+        if code.is_empty() {
+            code = library
+                .segments
+                .iter()
+                .filter(|segment| segment.is_executable())
+                .map(|segment| {
+                    (
+                        segment.address,
+                        segment.address.saturating_add(segment.file_size),
+                    )
+                })
+                .collect();
+        }
+        let got: Vec<u64> = library
+            .sections
+            .iter()
+            .filter(|section| section.name.starts_with(".got"))
+            .map(|section| section.address)
+            .collect();
+        let data = image
+            .data_regions()
+            .into_iter()
+            .filter(|(start, _)| !got.contains(start))
+            .collect();
+        let mut builder = Builder {
+            object,
+            disassembler,
+            image,
+            arch,
+            tables: BTreeMap::new(),
+            code,
+            data,
+        };
+        builder.find_tables();
+        builder
+    }
+
+    fn thumb_mask(&self) -> u64 {
+        if self.arch == CpuArch::ArmV7 {
+            1
+        } else {
+            0
+        }
+    }
+
+    fn is_code(&self, address: u64) -> bool {
+        let address = address & !self.thumb_mask();
+        address != 0
+            && self
+                .code
+                .iter()
+                .any(|(start, end)| address >= *start && address < *end)
+    }
+
+    /// Runs of slots in the data sections holding pointers into code, and the function
+    /// pointers of all `JNINativeMethod[]` tables
+    fn find_tables(&mut self) {
+        let pointer_size = self.image.pointer_size();
+        let mut tables = BTreeMap::new();
+        for (start, end) in &self.data {
+            let Some(mut slot) = align_up(*start, pointer_size) else {
+                continue;
+            };
+            let mut table: Option<(u64, Vec<(u64, u64)>)> = None;
+            while slot
+                .checked_add(pointer_size)
+                .map_or(false, |slot_end| slot_end <= *end)
+            {
+                match self
+                    .image
+                    .relocated_pointer(slot)
+                    .filter(|target| self.is_code(*target))
+                {
+                    Some(target) => table.get_or_insert((slot, vec![])).1.push((slot, target)),
+                    None => {
+                        if let Some((first, entries)) = table.take() {
+                            tables.insert(first, (slot, entries));
+                        }
+                    }
+                }
+                slot += pointer_size;
+            }
+            if let Some((first, entries)) = table.take() {
+                tables.insert(first, (slot, entries));
+            }
+        }
+        let entry_size = 3 * pointer_size;
+        for method in find_jni_native_methods(&self.object) {
+            let slot = method.table + method.index as u64 * entry_size + 2 * pointer_size;
+            let (end, entries) = tables
+                .entry(method.table)
+                .or_insert_with(|| (method.table, vec![]));
+            *end = (*end).max(slot + pointer_size);
+            entries.push((slot, method.function | method.thumb as u64));
+        }
+        self.tables = tables;
+    }
+
+    /// The functions of the table containing `address`, from `address` on
+    fn table_entries(&self, address: u64) -> Option<Vec<u64>> {
+        let (_, (end, entries)) = self.tables.range(..=address).next_back()?;
+        if address >= *end {
+            return None;
+        }
+        Some(
+            entries
+                .iter()
+                .filter(|(slot, _)| *slot >= address)
+                .map(|(_, function)| *function)
+                .collect(),
+        )
+    }
+
+    fn string_at(&self, address: u64) -> Option<String> {
+        if !self
+            .data
+            .iter()
+            .any(|(start, end)| address >= *start && address < *end)
+        {
+            return None;
+        }
+        let offset = self.image.library.file_offset(address)? as usize;
+        let bytes = self.object.data().get(offset..)?;
+        let length = bytes.iter().take(MAX_STRING_LENGTH).position(|b| *b == 0)?;
+        if length == 0 {
+            return None;
+        }
+        let string = std::str::from_utf8(&bytes[..length]).ok()?;
+        if string
+            .chars()
+            .any(|c| c.is_control() && !matches!(c, '\n' | '\r' | '\t'))
+        {
+            return None;
+        }
+        Some(string.to_string())
+    }
+
+    /// Disassemble everything reachable from the symbols and function pointers. Functions
+    /// found in one round bound the disassembly of the preceding function in the next one, the
+    /// result of the round without new functions is kept.
+    fn analyze_functions(&mut self) -> Vec<(NativeFunction, FunctionUses)> {
+        let mask = self.thumb_mask();
+        let mut pending: Vec<u64> = self
+            .disassembler
+            .functions()
+            .map(|symbol| symbol.address | symbol.thumb as u64)
+            .collect();
+        let library = &self.image.library;
+        pending.extend(library.init_array.iter().chain(library.fini_array.iter()));
+        pending.extend(
+            self.tables
+                .values()
+                .flat_map(|(_, entries)| entries.iter().map(|(_, function)| *function)),
+        );
+        let mut entries = HashMap::new();
+        let mut results = vec![];
+        for _ in 0..MAX_ROUNDS {
+            let mut found = false;
+            for entry in pending.drain(..) {
+                let address = entry & !mask;
+                if self.is_code(address) && !entries.contains_key(&address) {
+                    entries.insert(address, entry & mask != 0);
+                    self.disassembler.add_function(entry);
+                    found = true;
+                }
+            }
+            if !found {
+                break;
+            }
+            let mut sorted: Vec<(u64, bool)> = entries
+                .iter()
+                .map(|(address, thumb)| (*address, *thumb))
+                .collect();
+            sorted.sort_unstable();
+            let this = &*self;
+            results = iterator!(sorted)
+                .filter_map(|(address, thumb)| {
+                    let function = this.disassembler.function_at(address | *thumb as u64)?;
+                    let uses = this.analyze(&function, &entries);
+                    Some((function, uses))
+                })
+                .collect();
+            pending = results
+                .iter()
+                .flat_map(|(_, uses)| uses.calls.iter())
+                .filter_map(|(_, callee, _)| match callee {
+                    Callee::Function(target) => Some(*target),
+                    Callee::Import(_) => None,
+                })
+                .collect();
+        }
+        results
+    }
+
+    fn analyze(&self, function: &NativeFunction, entries: &HashMap<u64, bool>) -> FunctionUses {
+        let mut uses = FunctionUses::default();
+        let mut registers: HashMap<String, Value> = HashMap::new();
+        let start = function.address;
+        let end = start.saturating_add(function_size(function));
+        let inside = |address: u64| {
+            let address = address & !self.thumb_mask();
+            address >= start && address < end
+        };
+        // `call next; pop reg` on x86
+        let mut pushed_pc = None;
+        for instruction in &function.instructions {
+            match instruction.flow {
+                NativeFlow::Call | NativeFlow::Jump => {
+                    if let Some(thunk_register) = self.pc_thunk(instruction) {
+                        registers
+                            .insert(thunk_register, Value::Address(instruction.next_address()));
+                        continue;
+                    }
+                    if instruction.target == Some(instruction.next_address()) {
+                        pushed_pc = Some(instruction.next_address());
+                        continue;
+                    }
+                    if let Some((callee, kind)) =
+                        self.callee(instruction, &registers, entries, &inside)
+                    {
+                        uses.calls.push((instruction.address, callee, kind));
+                    }
+                    if instruction.flow == NativeFlow::Call {
+                        registers.retain(|register, _| self.is_callee_saved(register));
+                    }
+                }
+                NativeFlow::Sequential => {
+                    if let (Some(pc), "pop", [NativeOperand::Register(register)]) = (
+                        pushed_pc.take(),
+                        instruction.mnemonic.as_str(),
+                        instruction.operands.as_slice(),
+                    ) {
+                        registers.insert(register.clone(), Value::Address(pc));
+                        continue;
+                    }
+                    self.track(instruction, &mut registers, &mut uses, &inside);
+                }
+                _ => {}
+            }
+        }
+        uses
+    }
+
+    fn callee(
+        &self,
+        instruction: &NativeInstruction,
+        registers: &HashMap<String, Value>,
+        entries: &HashMap<u64, bool>,
+        inside: &dyn Fn(u64) -> bool,
+    ) -> Option<(Callee, NativeCallKind)> {
+        let is_call = instruction.flow == NativeFlow::Call;
+        if let Some(target) = instruction.target {
+            if let Some(name) = self.disassembler.import_at(target) {
+                return Some((Callee::Import(name.to_string()), NativeCallKind::Import));
+            }
+            let known = is_call || (!inside(target) && entries.contains_key(&target));
+            if !known || !self.is_code(target) {
+                return None;
+            }
+            let entry = if self.arch == CpuArch::ArmV7 {
+                target | instruction.target_thumb as u64
+            } else {
+                target
+            };
+            return Some((Callee::Function(entry), NativeCallKind::Direct));
+        }
+        let pointer = |value: u64| {
+            (self.is_code(value) && !inside(value))
+                .then(|| (Callee::Function(value), NativeCallKind::Pointer))
+        };
+        if let Some(slot) = self.memory_slot(instruction, registers) {
+            if let Some(name) = self.disassembler.import_at(slot) {
+                return Some((Callee::Import(name.to_string()), NativeCallKind::Import));
+            }
+            return self.image.relocated_pointer(slot).and_then(pointer);
+        }
+        let register = instruction
+            .operands
+            .iter()
+            .find_map(|operand| match operand {
+                NativeOperand::Register(register) => Some(self.normalize(register)),
+                _ => None,
+            })?;
+        match registers.get(&register)? {
+            Value::Import(name) => Some((Callee::Import(name.clone()), NativeCallKind::Import)),
+            Value::Address(address) => pointer(*address),
+            Value::Offset(_) => None,
+        }
+    }
+
+    /// The register set by a call to `__x86.get_pc_thunk.*`, which is `mov reg, [esp]; ret`
+    fn pc_thunk(&self, instruction: &NativeInstruction) -> Option<String> {
+        if self.arch != CpuArch::X86 || instruction.flow != NativeFlow::Call {
+            return None;
+        }
+        let thunk = self
+            .disassembler
+            .disassemble(instruction.target?, 4, false)
+            .into_iter()
+            .next()?;
+        match (thunk.mnemonic.as_str(), thunk.operands.as_slice()) {
+            (
+                "mov",
+                [NativeOperand::Register(register), NativeOperand::Memory {
+                    base: Some(base),
+                    index: None,
+                    displacement: 0,
+                    ..
+                }],
+            ) if base == "esp" => Some(register.clone()),
+            _ => None,
+        }
+    }
+
+    /// The address of the memory operand, if it is pc relative or its base register is known
+    fn memory_slot(
+        &self,
+        instruction: &NativeInstruction,
+        registers: &HashMap<String, Value>,
+    ) -> Option<u64> {
+        let (base, index, displacement) =
+            instruction
+                .operands
+                .iter()
+                .find_map(|operand| match operand {
+                    NativeOperand::Memory {
+                        base,
+                        index,
+                        displacement,
+                        ..
+                    } => Some((base.as_ref(), index.as_ref(), *displacement)),
+                    _ => None,
+                })?;
+        let base = self.normalize(base?);
+        if base == "pc" || base == "rip" {
+            // ARMv7 literal offsets added to the pc as index register
+            if let Some(Value::Offset(offset)) =
+                index.and_then(|index| registers.get(&self.normalize(index)))
+            {
+                return Some(self.pc(instruction).wrapping_add(*offset) & 0xffff_ffff);
+            }
+            return instruction.reference;
+        }
+        if index.is_some() {
+            return None;
+        }
+        match registers.get(&base)? {
+            Value::Address(address) => Some((*address as i64 + displacement) as u64),
+            _ => None,
+        }
+    }
+
+    /// The value of `pc` as operand of `instruction` on ARMv7
+    fn pc(&self, instruction: &NativeInstruction) -> u64 {
+        if instruction.thumb {
+            instruction.address + 4
+        } else {
+            instruction.address + 8
+        }
+    }
+
+    /// Follow the values of registers through a single instruction and record the strings,
+    /// functions and tables it refers to
+    fn track(
+        &self,
+        instruction: &NativeInstruction,
+        registers: &mut HashMap<String, Value>,
+        uses: &mut FunctionUses,
+        inside: &dyn Fn(u64) -> bool,
+    ) {
+        let site = instruction.address;
+        let mnemonic = instruction.mnemonic.as_str();
+        let operands = folded_immediates(instruction);
+        let destination = match operands.first() {
+            Some(NativeOperand::Register(register)) if self.writes_first_operand(mnemonic) => {
+                Some(self.normalize(register))
+            }
+            _ => None,
+        };
+        let value_of = |register: &String| registers.get(&self.normalize(register)).cloned();
+
+        let value = match (self.arch, mnemonic, operands.as_slice()) {
+            (CpuArch::Arm64, "adrp", [_, NativeOperand::Immediate(page)]) => {
+                Some(Value::Address(*page as u64))
+            }
+            (CpuArch::Arm64, "adr", [_, NativeOperand::Immediate(address)]) => {
+                self.resolve_address(site, *address as u64, uses, inside);
+                Some(Value::Address(*address as u64))
+            }
+            (
+                CpuArch::Arm64,
+                "add",
+                [_, NativeOperand::Register(source), NativeOperand::Immediate(offset)],
+            ) => match value_of(source) {
+                Some(Value::Address(base)) => {
+                    let address = base.wrapping_add(*offset as u64);
+                    self.resolve_address(site, address, uses, inside);
+                    Some(Value::Address(address))
+                }
+                _ => None,
+            },
+            // literal loads
+            (CpuArch::Arm64, _, [_, NativeOperand::Immediate(_)])
+                if mnemonic.starts_with("ldr") && instruction.reference.is_some() =>
+            {
+                let slot = instruction.reference.unwrap_or_default();
+                self.resolve_load(site, slot, uses, inside);
+                self.loaded_value(slot)
+            }
+            (CpuArch::ArmV7, _, [_, NativeOperand::Memory { index: None, .. }])
+                if mnemonic.starts_with("ldr") && instruction.reference.is_some() =>
+            {
+                // pc relative code loads an offset from the literal pool, which has no relocation
+                let slot = instruction.reference.unwrap_or_default();
+                match self.image.relocated_pointer(slot) {
+                    Some(address) => {
+                        self.resolve_address(site, address, uses, inside);
+                        Some(Value::Address(address))
+                    }
+                    None => self.image.pointer(slot).map(Value::Offset),
+                }
+            }
+            (CpuArch::ArmV7, "movw", [_, NativeOperand::Immediate(low)]) => {
+                Some(Value::Offset(*low as u64))
+            }
+            (
+                CpuArch::ArmV7,
+                "movt",
+                [NativeOperand::Register(register), NativeOperand::Immediate(high)],
+            ) => match value_of(register) {
+                Some(Value::Offset(low)) => {
+                    Some(Value::Offset(low & 0xffff | (*high as u64) << 16))
+                }
+                _ => None,
+            },
+            (
+                CpuArch::ArmV7,
+                "add",
+                [NativeOperand::Register(register), NativeOperand::Register(pc)],
+            ) if pc == "pc" => self.add_pc(instruction, value_of(register), uses, inside),
+            (
+                CpuArch::ArmV7,
+                "add",
+                [_, NativeOperand::Register(a), NativeOperand::Register(b)],
+            ) if a == "pc" || b == "pc" => {
+                let other = if a == "pc" { b } else { a };
+                self.add_pc(instruction, value_of(other), uses, inside)
+            }
+            (
+                CpuArch::ArmV7,
+                "add" | "adr",
+                [_, NativeOperand::Register(source), NativeOperand::Immediate(offset)],
+            ) => {
+                let base = if source == "pc" {
+                    Some(Value::Address(self.pc(instruction) & !3))
+                } else {
+                    value_of(source)
+                };
+                match base {
+                    Some(Value::Address(base)) => {
+                        let address = base.wrapping_add(*offset as u64) & 0xffff_ffff;
+                        self.resolve_address(site, address, uses, inside);
+                        Some(Value::Address(address))
+                    }
+                    _ => None,
+                }
+            }
+            (CpuArch::X86 | CpuArch::X86_64, "lea", [_, NativeOperand::Memory { .. }]) => {
+                match self.memory_slot(instruction, registers) {
+                    Some(address) => {
+                        self.resolve_address(site, address, uses, inside);
+                        Some(Value::Address(address))
+                    }
+                    None => None,
+                }
+            }
+            (
+                CpuArch::X86,
+                "add",
+                [NativeOperand::Register(register), NativeOperand::Immediate(offset)],
+            ) => match value_of(register) {
+                Some(Value::Address(base)) => Some(Value::Address(
+                    base.wrapping_add(*offset as u64) & 0xffff_ffff,
+                )),
+                _ => None,
+            },
+            (_, _, [_, NativeOperand::Register(source)]) if mnemonic == "mov" => value_of(source),
+            (_, _, [NativeOperand::Register(_), .., NativeOperand::Memory { .. }])
+                if mnemonic.starts_with("ld") || mnemonic == "mov" =>
+            {
+                match self.memory_slot(instruction, registers) {
+                    Some(slot) => {
+                        self.resolve_load(site, slot, uses, inside);
+                        self.loaded_value(slot)
+                    }
+                    None => None,
+                }
+            }
+            _ => {
+                // e.g. `cmp byte ptr [rip + 0x1234], 0` or stores to a known address
+                if let Some(slot) = self.memory_slot(instruction, registers) {
+                    self.resolve_load(site, slot, uses, inside);
+                }
+                None
+            }
+        };
+        if let Some(destination) = destination {
+            match value {
+                Some(value) => registers.insert(destination, value),
+                None => registers.remove(&destination),
+            };
+        }
+    }
+
+    fn add_pc(
+        &self,
+        instruction: &NativeInstruction,
+        offset: Option<Value>,
+        uses: &mut FunctionUses,
+        inside: &dyn Fn(u64) -> bool,
+    ) -> Option<Value> {
+        let Some(Value::Offset(offset)) = offset else {
+            return None;
+        };
+        let address = self.pc(instruction).wrapping_add(offset) & 0xffff_ffff;
+        self.resolve_address(instruction.address, address, uses, inside);
+        Some(Value::Address(address))
+    }
+
+    fn loaded_value(&self, slot: u64) -> Option<Value> {
+        if let Some(name) = self.disassembler.import_at(slot) {
+            return Some(Value::Import(name.to_string()));
+        }
+        self.image.relocated_pointer(slot).map(Value::Address)
+    }
+
+    /// A computed address: a function, a table of function pointers or a string
+    fn resolve_address(
+        &self,
+        site: u64,
+        address: u64,
+        uses: &mut FunctionUses,
+        inside: &dyn Fn(u64) -> bool,
+    ) -> bool {
+        if self.is_code(address) {
+            if !inside(address) {
+                uses.calls
+                    .push((site, Callee::Function(address), NativeCallKind::Pointer));
+            }
+            return true;
+        }
+        if let Some(functions) = self.table_entries(address) {
+            uses.calls.extend(
+                functions
+                    .into_iter()
+                    .map(|function| (site, Callee::Function(function), NativeCallKind::Pointer)),
+            );
+            return true;
+        }
+        if let Some(string) = self.string_at(address) {
+            uses.strings.push((site, address, string));
+            return true;
+        }
+        false
+    }
+
+    /// A load from `slot`: a pointer to a function, table or string, or the string itself
+    /// (copies of string literals are loaded as vectors)
+    fn resolve_load(
+        &self,
+        site: u64,
+        slot: u64,
+        uses: &mut FunctionUses,
+        inside: &dyn Fn(u64) -> bool,
+    ) {
+        if self.disassembler.import_at(slot).is_some() {
+            return;
+        }
+        if let Some(address) = self.image.relocated_pointer(slot) {
+            if self.resolve_address(site, address, uses, inside) {
+                return;
+            }
+        }
+        if let Some(string) = self.string_at(slot) {
+            uses.strings.push((site, slot, string));
+        }
+    }
+
+    /// 32 bit registers are the lower half of the 64 bit ones on AArch64
+    fn normalize(&self, register: &str) -> String {
+        match register.strip_prefix('w') {
+            Some(number) if self.arch == CpuArch::Arm64 && !number.is_empty() => {
+                format!("x{}", number)
+            }
+            _ => register.to_string(),
+        }
+    }
+
+    fn writes_first_operand(&self, mnemonic: &str) -> bool {
+        !(mnemonic.starts_with("st")
+            || mnemonic.starts_with("cmp")
+            || mnemonic.starts_with("cmn")
+            || mnemonic.starts_with("tst")
+            || mnemonic.starts_with("ccm")
+            || mnemonic.starts_with("fcmp")
+            || mnemonic.starts_with("prfm")
+            || mnemonic.starts_with("push")
+            || mnemonic == "test")
+    }
+
+    fn is_callee_saved(&self, register: &str) -> bool {
+        match self.arch {
+            CpuArch::Arm64 => register
+                .strip_prefix('x')
+                .and_then(|number| number.parse::<u32>().ok())
+                .map_or(false, |number| (19..=29).contains(&number)),
+            CpuArch::ArmV7 => matches!(
+                register,
+                "r4" | "r5" | "r6" | "r7" | "r8" | "r9" | "r10" | "r11" | "sb" | "sl" | "fp"
+            ),
+            CpuArch::X86 => matches!(register, "ebx" | "esi" | "edi" | "ebp"),
+            CpuArch::X86_64 => matches!(register, "rbx" | "rbp" | "r12" | "r13" | "r14" | "r15"),
+        }
+    }
+}
+
+/// The functions using the memory ranges, e.g. the strings of `.rodata` matching a search, as
+/// `Context::NativeLib`
+pub fn string_users(bin_elf: &Arc<BinaryObject>, ranges: &[(u64, u64)]) -> Vec<Vec<Context>> {
+    if ranges.is_empty() {
+        return vec![];
+    }
+    let Some(graph) = NativeCallGraph::cached(bin_elf) else {
+        return vec![];
+    };
+    ranges
+        .iter()
+        .map(|(start, end)| {
+            let mut functions: Vec<u64> = graph
+                .string_users(*start, *end)
+                .map(|reference| reference.function)
+                .collect();
+            functions.sort_unstable();
+            functions.dedup();
+            functions
+                .into_iter()
+                .filter_map(|function| graph.function(function))
+                .map(|node| graph.context(bin_elf, node))
+                .collect()
+        })
+        .collect()
+}
+
+/// Find all calls to functions and imports whose name matches the regex, in all ELF binaries.
+/// The callee is the place of the cross reference, the calling function the context.
+pub fn find_native_calls(reg: &Regex, files: &HashMap<String, Arc<BinaryObject>>) -> Vec<Evidence> {
+    let mut matches = vec![];
+    let vec_lock = Arc::new(Mutex::new(&mut matches));
+    iterator!(files).for_each(|(file_name, object)| {
+        let Some(graph) = NativeCallGraph::cached(object) else {
+            return;
+        };
+        let mut file_matches = vec![];
+        for callee in graph.graph.node_indices() {
+            if !reg.is_match(graph.node(callee).name()) {
+                continue;
+            }
+            for (caller, call) in graph.callers(callee) {
+                file_matches.push(Evidence::CrossReference(CrossReferenceEvidence {
+                    place: Location::NativeAddress(file_name.to_string(), call.site),
+                    place_context: graph.context(object, callee),
+                    context: graph.context(object, caller),
+                }));
+            }
+        }
+        if let Ok(mut lock) = vec_lock.lock() {
+            lock.extend(file_matches);
+        }
+    });
+    matches
+}
@@ -0,0 +1,919 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Detect packers and protectors, which replace the code of an app by a stub decrypting the
+//! original dex files at runtime. Known packers are recognized by the signatures of a
+//! `PackerSignatureDatabase` (see `data/packer_signatures.txt` for the bundled one), unknown ones
+//! by generic indicators: a tiny primary dex bringing its own class loader, components of the
+//! manifest missing from the dex files, encrypted assets and data appended to dex files.
+//!
+//! For each packer the likely payloads are reported together with the methods of the stub which
+//! decrypt and load them. These are the starting points for emulating the unpacking.
+
+use std::{
+    collections::{HashMap, HashSet},
+    sync::{Arc, OnceLock},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+use regex::Regex;
+
+use coeus_macros::iterator;
+use coeus_models::models::{
+    AccessFlags, Class, ContentType, DexFile, Files, Instruction, MethodData, MultiDexFile,
+};
+
+use super::{ConfidenceLevel, Context, Evidence, InstructionEvidence, Location, StringEvidence};
+
+const BUNDLED_SIGNATURES: &str = include_str!("../../data/packer_signatures.txt");
+/// Id of the packer reported if only generic indicators matched
+pub const UNKNOWN_PACKER: &str = "unknown";
+/// A primary dex with at most this many classes is considered a stub
+const MAX_STUB_CLASSES: usize = 100;
+/// Smaller files and appended data are not considered a payload
+const MIN_PAYLOAD_SIZE: usize = 4096;
+/// Bits per byte, compressed and encrypted data come close to 8
+const MIN_PAYLOAD_ENTROPY: f64 = 7.9;
+/// Number of missing components listed in the indicator
+const MAX_LISTED_COMPONENTS: usize = 3;
+
+const DEX_MAGIC: &[u8] = b"dex\n";
+const DEX_FILE_SIZE_OFFSET: usize = 0x20;
+const CIPHER_TYPE: &str = "Ljavax/crypto/Cipher;";
+const DEX_FILE_TYPE: &str = "Ldalvik/system/DexFile;";
+
+/// Class loaders loading dex files, a stub extends or instantiates one of them
+const DEX_CLASS_LOADERS: [&str; 5] = [
+    "Ldalvik/system/BaseDexClassLoader;",
+    "Ldalvik/system/DexClassLoader;",
+    "Ldalvik/system/PathClassLoader;",
+    "Ldalvik/system/InMemoryDexClassLoader;",
+    "Ldalvik/system/DelegateLastClassLoader;",
+];
+
+/// Names used to load dex files through reflection, e.g. by patching `pathList.dexElements`
+const DEX_LOADING_REFLECTION: [&str; 5] = [
+    "dexElements",
+    "makeDexElements",
+    "makePathElements",
+    "makeInMemoryDexElements",
+    "openDexFileNative",
+];
+
+/// Directories of an apk which are not used to hide a payload
+const IGNORED_DIRECTORIES: [&str; 4] = ["res/", "lib/", "META-INF/", "kotlin/"];
+
+/// Compressed formats, which have a high entropy without being encrypted (offset and magic)
+const COMPRESSED_FORMATS: [(usize, &[u8]); 22] = [
+    (0, b"\x89PNG"),
+    (0, b"\xff\xd8\xff"),
+    (0, b"GIF8"),
+    (0, b"RIFF"),
+    (0, b"PK\x03\x04"),
+    (0, b"\x1f\x8b"),
+    (0, b"OggS"),
+    (0, b"ID3"),
+    (0, b"\xff\xfb"),
+    (0, b"\xff\xf3"),
+    (0, b"\xff\xf2"),
+    (0, b"fLaC"),
+    (0, b"\x1a\x45\xdf\xa3"),
+    (4, b"ftyp"),
+    (0, b"7z\xbc\xaf\x27\x1c"),
+    (0, b"BZh"),
+    (0, b"\xfd7zXZ\x00"),
+    (0, b"\x28\xb5\x2f\xfd"),
+    (0, b"wOFF"),
+    (0, b"wOF2"),
+    (0, b"UnityFS"),
+    (0, b"\x7fELF"),
+];
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum PackerIndicatorKind {
+    /// The application class or app component factory of the manifest
+    Application,
+    Class,
+    Library,
+    Asset,
+    Payload,
+    /// A tiny primary dex which extends or instantiates a class loader for dex files
+    StubDex,
+    /// Components of the manifest which are not defined in any dex file
+    MissingComponents,
+    /// A file with random content in an unknown format
+    EncryptedAsset,
+    /// A dex file outside of the `classes*.dex` of the app
+    HiddenDex,
+    /// Data after the end of a dex file
+    AppendedData,
+}
+
+impl PackerIndicatorKind {
+    fn parse(kind: &str) -> Option<Self> {
+        Some(match kind {
+            "application" => PackerIndicatorKind::Application,
+            "class" => PackerIndicatorKind::Class,
+            "library" => PackerIndicatorKind::Library,
+            "asset" => PackerIndicatorKind::Asset,
+            "payload" => PackerIndicatorKind::Payload,
+            _ => return None,
+        })
+    }
+
+    /// Generic indicators are found without signatures and hold for all packers of the app
+    pub fn is_generic(&self) -> bool {
+        !matches!(
+            self,
+            PackerIndicatorKind::Application
+                | PackerIndicatorKind::Class
+                | PackerIndicatorKind::Library
+                | PackerIndicatorKind::Asset
+                | PackerIndicatorKind::Payload
+        )
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct PackerIndicator {
+    pub kind: PackerIndicatorKind,
+    /// The matched class or file name, or a description of the generic indicator
+    pub content: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PayloadKind {
+    /// A plain dex file outside of the `classes*.dex`
+    Dex,
+    /// A file with random content in an unknown format
+    Encrypted,
+    /// Data after the end of a dex file
+    Appended,
+    /// A file named like the payload of the packer
+    Signature,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct PackerPayload {
+    /// The file in `Files::binaries`
+    pub file_name: String,
+    pub kind: PayloadKind,
+    /// Start of the payload in the file, only non zero for appended data
+    pub offset: usize,
+    pub size: usize,
+    /// Shannon entropy of the payload in bits per byte
+    pub entropy: f64,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct KnownPacker {
+    pub id: String,
+    pub name: String,
+}
+
+#[derive(Clone, Debug)]
+struct PackerSignature {
+    packer: usize,
+    kind: PackerIndicatorKind,
+    regex: Regex,
+}
+
+#[derive(Clone, Debug, Default)]
+pub struct PackerSignatureDatabase {
+    packers: Vec<KnownPacker>,
+    signatures: Vec<PackerSignature>,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct DetectedPacker {
+    /// Id of the packer in the signature database, `UNKNOWN_PACKER` for generic indicators only
+    pub packer: String,
+    pub name: String,
+    pub indicators: Vec<PackerIndicator>,
+    pub confidence_level: ConfidenceLevel,
+    /// Files likely holding the encrypted dex files
+    pub payloads: Vec<PackerPayload>,
+    /// Methods of the stub decrypting (cipher, xor loop, native method) or loading the payload
+    pub decryption_routines: Vec<Evidence>,
+}
+
+impl PackerSignatureDatabase {
+    /// The signatures bundled with coeus
+    pub fn bundled() -> Arc<PackerSignatureDatabase> {
+        static BUNDLED: OnceLock<Arc<PackerSignatureDatabase>> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| {
+                Arc::new(
+                    Self::parse(BUNDLED_SIGNATURES).expect("bundled packer signatures are valid"),
+                )
+            })
+            .clone()
+    }
+
+    /// Parse signatures in the format of the bundled ones
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut database = Self::default();
+        for (line_number, line) in content.lines().enumerate() {
+            let error = |msg: &str| format!("line {}: {}", line_number + 1, msg);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut parts = line.splitn(3, char::is_whitespace);
+            let (Some(kind), Some(id), Some(rest)) = (parts.next(), parts.next(), parts.next())
+            else {
+                return Err(error("wrong number of arguments"));
+            };
+            let rest = rest.trim();
+            if kind == "packer" {
+                if database.packer_index(id).is_some() {
+                    return Err(error(&format!("duplicate packer {}", id)));
+                }
+                if id == UNKNOWN_PACKER {
+                    return Err(error(&format!("reserved id {}", id)));
+                }
+                database.packers.push(KnownPacker {
+                    id: id.to_string(),
+                    name: rest.to_string(),
+                });
+                continue;
+            }
+            let packer = database
+                .packer_index(id)
+                .ok_or_else(|| error(&format!("unknown packer {}", id)))?;
+            let kind = PackerIndicatorKind::parse(kind)
+                .ok_or_else(|| error(&format!("unknown entry {}", kind)))?;
+            let regex = Regex::new(rest).map_err(|e| error(&format!("invalid regex: {}", e)))?;
+            database.signatures.push(PackerSignature {
+                packer,
+                kind,
+                regex,
+            });
+        }
+        Ok(database)
+    }
+
+    fn packer_index(&self, id: &str) -> Option<usize> {
+        self.packers.iter().position(|packer| packer.id == id)
+    }
+
+    pub fn packers(&self) -> &[KnownPacker] {
+        &self.packers
+    }
+
+    /// The indicators of every packer, indexed like `packers`
+    fn signature_indicators(&self, files: &Files) -> Vec<Vec<PackerIndicator>> {
+        let mut applications = vec![];
+        for md in &files.multi_dex {
+            if let Some(application) = md.android_manifest.application() {
+                for name in [&application.name, &application.app_component_factory]
+                    .iter()
+                    .copied()
+                    .flatten()
+                {
+                    applications.push(md.android_manifest.qualified_name(name));
+                }
+            }
+        }
+        let classes: Vec<String> = files
+            .multi_dex
+            .iter()
+            .flat_map(|md| md.dex_files())
+            .flat_map(|dex| dex.classes.iter())
+            .map(|class| java_name(&class.class_name))
+            .collect();
+        let mut libraries = vec![];
+        let mut assets = vec![];
+        for (file_name, object) in &files.binaries {
+            if object.data().starts_with(b"\x7fELF") {
+                libraries.push(file_name.rsplit('/').next().unwrap_or(file_name));
+            }
+            assets.push(file_name.as_str());
+        }
+        // the order of the binaries is random
+        libraries.sort_unstable();
+        assets.sort_unstable();
+
+        let mut indicators = vec![vec![]; self.packers.len()];
+        for signature in &self.signatures {
+            let candidates: Box<dyn Iterator<Item = &str>> = match signature.kind {
+                PackerIndicatorKind::Application => {
+                    Box::new(applications.iter().map(|name| name.as_str()))
+                }
+                PackerIndicatorKind::Class => Box::new(classes.iter().map(|name| name.as_str())),
+                PackerIndicatorKind::Library => Box::new(libraries.iter().copied()),
+                _ => Box::new(assets.iter().copied()),
+            };
+            let packer_indicators: &mut Vec<PackerIndicator> = &mut indicators[signature.packer];
+            // payloads are all reported, for the others the first match suffices
+            let matching = candidates.filter(|candidate| signature.regex.is_match(candidate));
+            let matching: Vec<&str> = if signature.kind == PackerIndicatorKind::Payload {
+                matching.collect()
+            } else {
+                matching.take(1).collect()
+            };
+            for content in matching {
+                let indicator = PackerIndicator {
+                    kind: signature.kind,
+                    content: content.to_string(),
+                };
+                if !packer_indicators.contains(&indicator) {
+                    packer_indicators.push(indicator);
+                }
+            }
+        }
+        indicators
+    }
+}
+
+/// `Lcom/stub/StubApp;` to `com.stub.StubApp`
+fn java_name(class_name: &str) -> String {
+    class_name
+        .trim_start_matches('L')
+        .trim_end_matches(';')
+        .replace('/', ".")
+}
+
+fn entropy(data: &[u8]) -> f64 {
+    if data.is_empty() {
+        return 0.0;
+    }
+    let mut counts = [0usize; 256];
+    for byte in data {
+        counts[*byte as usize] += 1;
+    }
+    let length = data.len() as f64;
+    counts
+        .iter()
+        .filter(|count| **count > 0)
+        .map(|count| {
+            let p = *count as f64 / length;
+            -p * p.log2()
+        })
+        .sum()
+}
+
+/// Hidden and plain dex files, data appended to dex files and encrypted files
+fn find_payloads(files: &Files) -> Vec<PackerPayload> {
+    let mut payloads: Vec<PackerPayload> = iterator!(files.binaries)
+        .filter_map(|(file_name, object)| {
+            let data = object.data();
+            if data.starts_with(DEX_MAGIC) {
+                let file_size = data
+                    .get(DEX_FILE_SIZE_OFFSET..DEX_FILE_SIZE_OFFSET + 4)
+                    .map(|size| u32::from_le_bytes([size[0], size[1], size[2], size[3]]))?
+                    as usize;
+                let is_app_dex = !file_name.contains('/')
+                    && file_name.starts_with("classes")
+                    && file_name.ends_with(".dex");
+                if !is_app_dex {
+                    return Some(PackerPayload {
+                        file_name: file_name.clone(),
+                        kind: PayloadKind::Dex,
+                        offset: 0,
+                        size: data.len(),
+                        entropy: entropy(data),
+                    });
+                }
+                let appended = data.get(file_size..)?;
+                return (appended.len() >= MIN_PAYLOAD_SIZE).then(|| PackerPayload {
+                    file_name: file_name.clone(),
+                    kind: PayloadKind::Appended,
+                    offset: file_size,
+                    size: appended.len(),
+                    entropy: entropy(appended),
+                });
+            }
+            if data.len() < MIN_PAYLOAD_SIZE
+                || IGNORED_DIRECTORIES
+                    .iter()
+                    .any(|directory| file_name.starts_with(directory))
+                || COMPRESSED_FORMATS
+                    .iter()
+                    .any(|(offset, magic)| data[*offset..].starts_with(magic))
+            {
+                return None;
+            }
+            let entropy = entropy(data);
+            (entropy >= MIN_PAYLOAD_ENTROPY).then(|| PackerPayload {
+                file_name: file_name.clone(),
+                kind: PayloadKind::Encrypted,
+                offset: 0,
+                size: data.len(),
+                entropy,
+            })
+        })
+        .collect();
+    payloads.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+    payloads
+}
+
+/// Why a method of the stub is a candidate for emulation
+#[derive(Copy, Clone, PartialEq, Eq)]
+enum RoutineKind {
+    Cipher,
+    XorLoop,
+    Loader,
+}
+
+/// The instructions of `method` decrypting or loading dex files
+fn scan_routine(method: &MethodData, dex_file: &Arc<DexFile>) -> Vec<(RoutineKind, String)> {
+    let Some(code) = method.code.as_ref() else {
+        return vec![];
+    };
+    let mut found = vec![];
+    let (mut reads_bytes, mut writes_bytes, mut xor) = (false, false, None);
+    for (_, offset, instruction) in &code.insns {
+        let disassembly = || {
+            instruction.disassembly_from_opcode(
+                offset.0 as i32,
+                &mut HashMap::new(),
+                dex_file.clone(),
+            )
+        };
+        let kind = match instruction {
+            Instruction::InvokeVirtual(_, method_idx, _)
+            | Instruction::InvokeSuper(_, method_idx, _)
+            | Instruction::InvokeDirect(_, method_idx, _)
+            | Instruction::InvokeStatic(_, method_idx, _)
+            | Instruction::InvokeInterface(_, method_idx, _)
+            | Instruction::InvokeVirtualRange(_, method_idx, _)
+            | Instruction::InvokeSuperRange(_, method_idx, _)
+            | Instruction::InvokeDirectRange(_, method_idx, _)
+            | Instruction::InvokeStaticRange(_, method_idx, _)
+            | Instruction::InvokeInterfaceRange(_, method_idx, _) => {
+                let Some(invoked) = dex_file.methods.get(*method_idx as usize) else {
+                    continue;
+                };
+                let class_name = dex_file
+                    .get_type_name(invoked.class_idx)
+                    .unwrap_or_default();
+                let name = invoked.method_name.as_str();
+                if class_name == CIPHER_TYPE && (name == "doFinal" || name == "update") {
+                    RoutineKind::Cipher
+                } else if (DEX_CLASS_LOADERS.contains(&class_name) && name == "<init>")
+                    || (class_name == DEX_FILE_TYPE
+                        && matches!(name, "<init>" | "loadDex" | "openDexFile"))
+                {
+                    RoutineKind::Loader
+                } else {
+                    continue;
+                }
+            }
+            Instruction::ConstString(_, string_idx) => match dex_file.get_string(*string_idx) {
+                Some(string) if DEX_LOADING_REFLECTION.contains(&string) => RoutineKind::Loader,
+                _ => continue,
+            },
+            Instruction::ConstStringJumbo(_, string_idx) => {
+                match dex_file.get_string(*string_idx as usize) {
+                    Some(string) if DEX_LOADING_REFLECTION.contains(&string) => RoutineKind::Loader,
+                    _ => continue,
+                }
+            }
+            Instruction::ArrayGetByte(..) => {
+                reads_bytes = true;
+                continue;
+            }
+            Instruction::ArrayPutByte(..) => {
+                writes_bytes = true;
+                continue;
+            }
+            Instruction::XorInt(..)
+            | Instruction::XorIntDst(..)
+            | Instruction::XorIntDstLit8(..)
+            | Instruction::XorIntDstLit16(..) => {
+                if xor.is_none() {
+                    xor = Some(disassembly());
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        found.push((kind, disassembly()));
+    }
+    // byte wise xor of one array into another
+    if let (true, true, Some(xor)) = (reads_bytes, writes_bytes, xor) {
+        found.push((RoutineKind::XorLoop, xor));
+    }
+    found
+}
+
+/// A class together with the dex file defining it
+type DexClass = (Arc<DexFile>, Arc<Class>);
+
+/// The methods of `classes` decrypting or loading the payload, and their native methods
+fn find_decryption_routines(classes: &[DexClass]) -> Vec<Evidence> {
+    let mut routines = vec![];
+    for (dex_file, class) in classes {
+        for method_data in &class.codes {
+            let place = Location::DexMethod(method_data.method.method_idx as u32, dex_file.clone());
+            let context = Context::DexMethod(method_data.method.clone(), dex_file.clone());
+            if method_data.access_flags.contains(AccessFlags::NATIVE) {
+                routines.push(Evidence::String(StringEvidence {
+                    content: format!(
+                        "{}->{}{}",
+                        class.class_name,
+                        method_data.method.method_name,
+                        method_data.method.proto_name
+                    ),
+                    place,
+                    context,
+                    confidence_level: ConfidenceLevel::Low,
+                    detail: None,
+                }));
+                continue;
+            }
+            let found = scan_routine(method_data, dex_file);
+            if found.is_empty() {
+                continue;
+            }
+            let decrypts = found.iter().any(|(kind, _)| *kind != RoutineKind::Loader);
+            routines.push(Evidence::Instructions(InstructionEvidence {
+                instructions: found
+                    .into_iter()
+                    .map(|(_, instruction)| instruction)
+                    .collect(),
+                place,
+                context,
+                confidence_level: if decrypts {
+                    ConfidenceLevel::Medium
+                } else {
+                    ConfidenceLevel::Low
+                },
+            }));
+        }
+    }
+    routines
+}
+
+/// A tiny primary dex with a class loader for dex files, its classes are the stub
+fn stub_dex(md: &MultiDexFile) -> Option<(PackerIndicator, Vec<DexClass>)> {
+    let primary = &md.primary;
+    if primary.classes.is_empty() || primary.classes.len() > MAX_STUB_CLASSES {
+        return None;
+    }
+    let loader = primary.classes.iter().find_map(|class| {
+        let super_class = primary
+            .get_type_name(class.super_class as usize)
+            .unwrap_or_default();
+        if DEX_CLASS_LOADERS.contains(&super_class) || super_class == "Ljava/lang/ClassLoader;" {
+            return Some(format!("{} extends {}", class.class_name, super_class));
+        }
+        class.codes.iter().find_map(|method_data| {
+            scan_routine(method_data, primary)
+                .into_iter()
+                .find(|(kind, _)| *kind == RoutineKind::Loader)
+                .map(|(_, instruction)| {
+                    format!(
+                        "{}->{}: {}",
+                        class.class_name, method_data.method.method_name, instruction
+                    )
+                })
+        })
+    })?;
+    Some((
+        PackerIndicator {
+            kind: PackerIndicatorKind::StubDex,
+            content: format!("{} classes, {}", primary.classes.len(), loader),
+        },
+        primary
+            .classes
+            .iter()
+            .map(|class| (primary.clone(), class.clone()))
+            .collect(),
+    ))
+}
+
+/// The activities, services, receivers and providers of the manifest without a class
+fn missing_components(md: &MultiDexFile, defined: &HashSet<String>) -> Option<PackerIndicator> {
+    let manifest = &md.android_manifest;
+    let components: Vec<String> = manifest
+        .application()?
+        .activities
+        .iter()
+        .filter_map(|content| match content {
+            ContentType::Activity(activity) => Some(activity.name.as_str()),
+            ContentType::Service(component)
+            | ContentType::Receiver(component)
+            | ContentType::Provider(component) => Some(component.name.as_str()),
+            _ => None,
+        })
+        .map(|name| manifest.qualified_name(name))
+        .collect();
+    let missing: Vec<&String> = components
+        .iter()
+        .filter(|component| !defined.contains(component.as_str()))
+        .collect();
+    if missing.is_empty() || missing.len() * 2 < components.len() {
+        return None;
+    }
+    let listed: Vec<&str> = missing
+        .iter()
+        .take(MAX_LISTED_COMPONENTS)
+        .map(|name| name.as_str())
+        .collect();
+    Some(PackerIndicator {
+        kind: PackerIndicatorKind::MissingComponents,
+        content: format!(
+            "{} of {} components, e.g. {}",
+            missing.len(),
+            components.len(),
+            listed.join(", ")
+        ),
+    })
+}
+
+/// Detect packers with the bundled signatures and the generic indicators
+pub fn find_packers(files: &Files) -> Vec<DetectedPacker> {
+    find_packers_with_database(files, &PackerSignatureDatabase::bundled())
+}
+
+pub fn find_packers_with_database(
+    files: &Files,
+    database: &PackerSignatureDatabase,
+) -> Vec<DetectedPacker> {
+    let signature_indicators = database.signature_indicators(files);
+
+    let mut generic = vec![];
+    let mut stub_classes = vec![];
+    let defined: HashSet<String> = files
+        .multi_dex
+        .iter()
+        .flat_map(|md| md.dex_files())
+        .flat_map(|dex| dex.classes.iter())
+        .map(|class| java_name(&class.class_name))
+        .collect();
+    for md in &files.multi_dex {
+        if let Some((indicator, classes)) = stub_dex(md) {
+            generic.push(indicator);
+            stub_classes.extend(classes);
+        }
+        generic.extend(missing_components(md, &defined));
+    }
+    let payloads = find_payloads(files);
+    for payload in &payloads {
+        let kind = match payload.kind {
+            PayloadKind::Dex => PackerIndicatorKind::HiddenDex,
+            PayloadKind::Encrypted => PackerIndicatorKind::EncryptedAsset,
+            PayloadKind::Appended => PackerIndicatorKind::AppendedData,
+            PayloadKind::Signature => continue,
+        };
+        generic.push(PackerIndicator {
+            kind,
+            content: payload.file_name.clone(),
+        });
+    }
+
+    // the stub, the classes matching a signature and the application classes with their inner
+    // classes
+    let mut stub_names: HashSet<String> = HashSet::new();
+    for indicator in signature_indicators.iter().flatten() {
+        if matches!(
+            indicator.kind,
+            PackerIndicatorKind::Application | PackerIndicatorKind::Class
+        ) {
+            stub_names.insert(indicator.content.clone());
+        }
+    }
+    for md in files.multi_dex.iter().filter(|_| !stub_names.is_empty()) {
+        for (dex_file, class) in md.classes() {
+            let name = java_name(&class.class_name);
+            let outer = name.split('$').next().unwrap_or(&name);
+            let in_stub = stub_classes
+                .iter()
+                .any(|(stub_dex, stub)| Arc::ptr_eq(stub_dex, &dex_file) && stub == &class);
+            if !in_stub && stub_names.contains(outer) {
+                stub_classes.push((dex_file, class));
+            }
+        }
+    }
+    let decryption_routines = find_decryption_routines(&stub_classes);
+
+    let generic_kinds: HashSet<PackerIndicatorKind> =
+        generic.iter().map(|indicator| indicator.kind).collect();
+    // encrypted assets alone are too common, a stub or hidden components are needed
+    let has_stub = generic_kinds.contains(&PackerIndicatorKind::StubDex)
+        || generic_kinds.contains(&PackerIndicatorKind::MissingComponents);
+    let mut detected = vec![];
+    for (packer, indicators) in database.packers.iter().zip(signature_indicators) {
+        if indicators.is_empty() {
+            continue;
+        }
+        let kinds: HashSet<PackerIndicatorKind> =
+            indicators.iter().map(|indicator| indicator.kind).collect();
+        // file names are the easiest to imitate (or to hit by accident)
+        let names_only = kinds.iter().all(|kind| {
+            matches!(
+                kind,
+                PackerIndicatorKind::Asset | PackerIndicatorKind::Payload
+            )
+        });
+        let confidence_level = match (kinds.len(), has_stub, names_only) {
+            (1, false, true) => ConfidenceLevel::Low,
+            (1, false, false) => ConfidenceLevel::Medium,
+            (_, _, true) => ConfidenceLevel::Medium,
+            _ => ConfidenceLevel::High,
+        };
+        let mut packer_payloads = payloads.clone();
+        for indicator in &indicators {
+            if indicator.kind != PackerIndicatorKind::Payload
+                || packer_payloads
+                    .iter()
+                    .any(|payload| payload.file_name == indicator.content)
+            {
+                continue;
+            }
+            if let Some(object) = files.binaries.get(&indicator.content) {
+                packer_payloads.push(PackerPayload {
+                    file_name: indicator.content.clone(),
+                    kind: PayloadKind::Signature,
+                    offset: 0,
+                    size: object.data().len(),
+                    entropy: entropy(object.data()),
+                });
+            }
+        }
+        packer_payloads.sort_by(|a, b| a.file_name.cmp(&b.file_name));
+        detected.push(DetectedPacker {
+            packer: packer.id.clone(),
+            name: packer.name.clone(),
+            indicators: indicators.into_iter().chain(generic.clone()).collect(),
+            confidence_level,
+            payloads: packer_payloads,
+            decryption_routines: decryption_routines.clone(),
+        });
+    }
+
+    if detected.is_empty() && has_stub && generic_kinds.len() >= 2 {
+        detected.push(DetectedPacker {
+            packer: UNKNOWN_PACKER.to_string(),
+            name: "Unknown packer".to_string(),
+            indicators: generic,
+            confidence_level: if generic_kinds.len() >= 3 {
+                ConfidenceLevel::Medium
+            } else {
+                ConfidenceLevel::Low
+            },
+            payloads,
+            decryption_routines,
+        });
+    }
+    detected
+}
+
+#[cfg(test)]
+mod tests {
+    use coeus_models::models::BinaryObject;
+
+    use super::*;
+
+    /// Deterministic bytes with an entropy close to eight bits per byte
+    fn random(size: usize) -> Vec<u8> {
+        let mut state = 0x2545_f491_u64;
+        (0..size)
+            .map(|_| {
+                state ^= state << 13;
+                state ^= state >> 7;
+                state ^= state << 17;
+                (state >> 24) as u8
+            })
+            .collect()
+    }
+
+    /// A dex header claiming `file_size` bytes, followed by `appended`
+    fn dex(file_size: u32, appended: &[u8]) -> Vec<u8> {
+        let mut data = b"dex\n035\0".to_vec();
+        data.resize(0x70, 0);
+        data[DEX_FILE_SIZE_OFFSET..DEX_FILE_SIZE_OFFSET + 4]
+            .copy_from_slice(&file_size.to_le_bytes());
+        data.extend_from_slice(appended);
+        data
+    }
+
+    fn files(binaries: Vec<(&str, Vec<u8>)>) -> Files {
+        Files::new(
+            vec![],
+            binaries
+                .into_iter()
+                .map(|(name, data)| (name.to_string(), Arc::new(BinaryObject::new(data))))
+                .collect(),
+        )
+    }
+
+    #[test]
+    fn parses_signatures() {
+        let database = PackerSignatureDatabase::parse(
+            "# comment\n\npacker test Test Packer\n  library test ^libtest\\.so$\nasset test x",
+        )
+        .unwrap();
+        assert_eq!(database.packers().len(), 1);
+        assert_eq!(database.packers()[0].name, "Test Packer");
+        assert_eq!(database.signatures.len(), 2);
+        assert_eq!(database.signatures[0].kind, PackerIndicatorKind::Library);
+        assert!(database.signatures[0].regex.is_match("libtest.so"));
+        assert!(PackerSignatureDatabase::bundled()
+            .packer_index("jiagu")
+            .is_some());
+    }
+
+    #[test]
+    fn rejects_malformed_signatures() {
+        let error = |content: &str| PackerSignatureDatabase::parse(content).unwrap_err();
+        assert_eq!(error("packer test"), "line 1: wrong number of arguments");
+        assert_eq!(
+            error("packer test A\npacker test B"),
+            "line 2: duplicate packer test"
+        );
+        assert_eq!(
+            error("packer unknown Unknown"),
+            "line 1: reserved id unknown"
+        );
+        assert_eq!(error("class other ^a$"), "line 1: unknown packer other");
+        assert_eq!(
+            error("packer test A\n# comment\nfile test ^a$"),
+            "line 3: unknown entry file"
+        );
+        assert!(error("packer test A\nclass test (").starts_with("line 2: invalid regex"));
+    }
+
+    #[test]
+    fn computes_entropy() {
+        assert_eq!(entropy(&[]), 0.0);
+        assert_eq!(entropy(&[7; 100]), 0.0);
+        assert_eq!(entropy(&[0, 1, 0, 1]), 1.0);
+        let all_bytes: Vec<u8> = (0..=255).collect();
+        assert_eq!(entropy(&all_bytes), 8.0);
+        assert!(entropy(&random(0x4000)) > MIN_PAYLOAD_ENTROPY);
+    }
+
+    #[test]
+    fn finds_payloads() {
+        let mut png = b"\x89PNG".to_vec();
+        png.extend(random(0x2000));
+        let files = files(vec![
+            ("classes.dex", dex(0x70, &random(0x1000))),
+            ("classes2.dex", dex(0x70, &random(0x100))),
+            // the header claims more than the file holds
+            ("classes3.dex", dex(u32::MAX, &random(0x1000))),
+            ("assets/hidden.dex", dex(0x70, &[])),
+            ("assets/truncated", b"dex\n035\0".to_vec()),
+            ("assets/data.bin", random(0x2000)),
+            ("assets/small.bin", random(MIN_PAYLOAD_SIZE - 1)),
+            ("assets/text.txt", vec![b'a'; 0x2000]),
+            ("assets/image.png", png),
+            ("res/raw/noise", random(0x2000)),
+        ]);
+        let payloads: Vec<_> = find_payloads(&files)
+            .into_iter()
+            .map(|p| (p.file_name, p.kind, p.offset, p.size))
+            .collect();
+        assert_eq!(
+            payloads,
+            vec![
+                (
+                    "assets/data.bin".to_string(),
+                    PayloadKind::Encrypted,
+                    0,
+                    0x2000
+                ),
+                ("assets/hidden.dex".to_string(), PayloadKind::Dex, 0, 0x70),
+                (
+                    "classes.dex".to_string(),
+                    PayloadKind::Appended,
+                    0x70,
+                    0x1000
+                ),
+            ]
+        );
+    }
+
+    #[test]
+    fn detects_packers_by_file_names() {
+        let database = PackerSignatureDatabase::parse(
+            "packer test Test\nlibrary test ^libtest\\.so$\npayload test ^assets/test\\.dat$",
+        )
+        .unwrap();
+        let packed = files(vec![
+            ("lib/arm64-v8a/libtest.so", b"\x7fELF".to_vec()),
+            ("assets/test.dat", vec![1, 2, 3]),
+        ]);
+        let detected = find_packers_with_database(&packed, &database);
+        assert_eq!(detected.len(), 1);
+        assert_eq!(detected[0].packer, "test");
+        assert!(matches!(
+            detected[0].confidence_level,
+            ConfidenceLevel::High
+        ));
+        assert_eq!(detected[0].payloads.len(), 1);
+        assert_eq!(detected[0].payloads[0].kind, PayloadKind::Signature);
+        assert_eq!(detected[0].payloads[0].size, 3);
+
+        // an encrypted asset alone is no packer
+        let encrypted = files(vec![("assets/data.bin", random(0x2000))]);
+        assert!(find_packers_with_database(&encrypted, &database).is_empty());
+    }
+}
@@ -0,0 +1,732 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Compare the permissions requested in the manifest with the permissions the code needs. The
+//! code is scanned for calls to framework APIs, reads of content uri fields and constant strings
+//! naming content providers or intent actions, which are mapped to permissions with a
+//! `PermissionMapping` (see `data/permission_mapping.txt` for the bundled one).
+//!
+//! Calls on app classes are attributed to the framework class they inherit the method from, so
+//! that e.g. a custom `WebView` calling `loadUrl` still requires `INTERNET`.
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+use coeus_models::models::{
+    ClassHierarchy, DexFile, Files, Instruction, Method, MultiDexFile, LATEST_API_LEVEL,
+};
+
+use super::{ConfidenceLevel, Context, Evidence, InstructionEvidence, Location};
+
+const BUNDLED_MAPPING: &str = include_str!("../../data/permission_mapping.txt");
+const PERMISSION_PREFIX: &str = "android.permission.";
+
+/// Permissions of which any satisfies a requirement, restricted to the target sdk versions
+/// `since..until`
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PermissionRule {
+    pub permissions: Vec<String>,
+    pub since: Option<u32>,
+    pub until: Option<u32>,
+}
+
+impl PermissionRule {
+    pub fn applies_to(&self, target_sdk_version: u32) -> bool {
+        self.since.is_none_or(|since| since <= target_sdk_version)
+            && self.until.is_none_or(|until| target_sdk_version < until)
+    }
+}
+
+#[derive(Clone, Debug, Default, serde::Serialize, serde::Deserialize)]
+pub struct PermissionMapping {
+    /// class -> (method name or name and prototype, rule)
+    methods: HashMap<String, Vec<(String, PermissionRule)>>,
+    /// (class, field name) -> rules
+    fields: HashMap<(String, String), Vec<PermissionRule>>,
+    /// prefixes of content uris
+    uris: Vec<(String, PermissionRule)>,
+    actions: HashMap<String, Vec<PermissionRule>>,
+}
+
+impl PermissionMapping {
+    /// The mapping bundled with coeus
+    pub fn bundled() -> Arc<PermissionMapping> {
+        static BUNDLED: OnceLock<Arc<PermissionMapping>> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| {
+                Arc::new(Self::parse(BUNDLED_MAPPING).expect("bundled permission mapping is valid"))
+            })
+            .clone()
+    }
+
+    /// Parse a mapping in the format of the bundled one
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut mapping = Self::default();
+        for (line_number, line) in content.lines().enumerate() {
+            let error = |msg: &str| format!("line {}: {}", line_number + 1, msg);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let kind = tokens.next().unwrap_or_default();
+            let (positional, options): (Vec<&str>, Vec<&str>) =
+                tokens.partition(|token| !token.contains('='));
+            let (targets, permissions) = match (kind, positional.as_slice()) {
+                ("api" | "field", [class, member, permissions]) => {
+                    (vec![*class, *member], *permissions)
+                }
+                ("uri" | "action", [target, permissions]) => (vec![*target], *permissions),
+                ("api" | "field" | "uri" | "action", _) => {
+                    return Err(error("wrong number of arguments"))
+                }
+                (other, _) => return Err(error(&format!("unknown entry {}", other))),
+            };
+            let mut rule = PermissionRule {
+                permissions: permissions.split('|').map(qualified_permission).collect(),
+                since: None,
+                until: None,
+            };
+            for option in options {
+                let level = |level: &str| {
+                    level
+                        .parse()
+                        .map_err(|_| error(&format!("invalid api level {}", level)))
+                };
+                match option.split_once('=') {
+                    Some(("since", since)) => rule.since = Some(level(since)?),
+                    Some(("until", until)) => rule.until = Some(level(until)?),
+                    _ => return Err(error(&format!("unknown option {}", option))),
+                }
+            }
+            match kind {
+                "api" => mapping
+                    .methods
+                    .entry(targets[0].to_string())
+                    .or_default()
+                    .push((targets[1].to_string(), rule)),
+                "field" => mapping
+                    .fields
+                    .entry((targets[0].to_string(), targets[1].to_string()))
+                    .or_default()
+                    .push(rule),
+                "uri" => mapping.uris.push((targets[0].to_string(), rule)),
+                _ => mapping
+                    .actions
+                    .entry(targets[0].to_string())
+                    .or_default()
+                    .push(rule),
+            }
+        }
+        Ok(mapping)
+    }
+
+    pub fn has_class(&self, class_name: &str) -> bool {
+        self.methods.contains_key(class_name)
+    }
+
+    /// Rules for the method `method_name` with prototype `proto` (e.g. `(Ljava/lang/String;)V`)
+    pub fn method_rules(
+        &self,
+        class_name: &str,
+        method_name: &str,
+        proto: &str,
+    ) -> Vec<&PermissionRule> {
+        self.methods
+            .get(class_name)
+            .into_iter()
+            .flatten()
+            .filter(|(method, _)| match method.find('(') {
+                Some(pos) => &method[..pos] == method_name && &method[pos..] == proto,
+                None => method == method_name,
+            })
+            .map(|(_, rule)| rule)
+            .collect()
+    }
+
+    pub fn field_rules(&self, class_name: &str, field_name: &str) -> &[PermissionRule] {
+        self.fields
+            .get(&(class_name.to_string(), field_name.to_string()))
+            .map(|rules| rules.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// Rules for a constant string, naming either a content uri or an intent action
+    pub fn string_rules(&self, content: &str) -> Vec<&PermissionRule> {
+        self.uris
+            .iter()
+            .filter(|(prefix, _)| content.starts_with(prefix.as_str()))
+            .map(|(_, rule)| rule)
+            .chain(self.actions.get(content).into_iter().flatten())
+            .collect()
+    }
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum PermissionStatus {
+    /// Requested in the manifest and needed by the code
+    Used,
+    /// Requested in the manifest, but no code needing it was found
+    Unused,
+    /// Needed by the code, but not requested in the manifest
+    Undeclared,
+}
+
+#[derive(Clone, Debug, serde::Serialize, serde::Deserialize)]
+pub struct PermissionUsage {
+    pub permission: String,
+    pub status: PermissionStatus,
+    /// For undeclared permissions, other permissions which would satisfy all of its requirements
+    pub alternatives: Vec<String>,
+    /// The instructions needing the permission
+    pub evidences: Vec<Evidence>,
+}
+
+/// Find the usage of all permissions of all manifests, using the bundled mapping
+pub fn find_permission_usage(files: &Files) -> Vec<PermissionUsage> {
+    find_permission_usage_with_mapping(files, &PermissionMapping::bundled())
+}
+
+/// Report every permission requested in a manifest as used or unused, followed by the
+/// permissions the code needs without requesting them
+pub fn find_permission_usage_with_mapping(
+    files: &Files,
+    mapping: &PermissionMapping,
+) -> Vec<PermissionUsage> {
+    let mut usages = vec![];
+    for md in &files.multi_dex {
+        let declared = md.android_manifest.permissions();
+        let target_sdk_version = md
+            .android_manifest
+            .target_sdk_version()
+            .unwrap_or(LATEST_API_LEVEL);
+        let mut evidences: HashMap<String, Vec<Evidence>> = HashMap::new();
+        // the first permission of a requirement is reported, requirements reporting the same
+        // permission are merged
+        let mut undeclared: Vec<(String, Vec<String>, Vec<Evidence>)> = vec![];
+        for (permissions, evidence) in find_requirements(md, mapping, target_sdk_version) {
+            let declared_permissions: Vec<&String> = permissions
+                .iter()
+                .filter(|p| declared.contains(&p.as_str()))
+                .collect();
+            if declared_permissions.is_empty() {
+                let Some((permission, alternatives)) = permissions.split_first() else {
+                    continue;
+                };
+                match undeclared.iter_mut().find(|(p, ..)| p == permission) {
+                    Some((_, merged_alternatives, evidences)) => {
+                        merged_alternatives.retain(|a| alternatives.contains(a));
+                        evidences.push(evidence);
+                    }
+                    None => {
+                        undeclared.push((permission.clone(), alternatives.to_vec(), vec![evidence]))
+                    }
+                }
+                continue;
+            }
+            for permission in declared_permissions {
+                evidences
+                    .entry(permission.clone())
+                    .or_default()
+                    .push(evidence.clone());
+            }
+        }
+
+        for permission in &declared {
+            let evidences = evidences.remove(*permission).unwrap_or_default();
+            usages.push(PermissionUsage {
+                permission: permission.to_string(),
+                status: if evidences.is_empty() {
+                    PermissionStatus::Unused
+                } else {
+                    PermissionStatus::Used
+                },
+                alternatives: vec![],
+                evidences,
+            });
+        }
+        for (permission, alternatives, evidences) in undeclared {
+            usages.push(PermissionUsage {
+                permission,
+                status: PermissionStatus::Undeclared,
+                alternatives,
+                evidences,
+            });
+        }
+    }
+    usages
+}
+
+/// All places needing a permission, together with the permissions satisfying the requirement
+fn find_requirements(
+    md: &MultiDexFile,
+    mapping: &PermissionMapping,
+    target_sdk_version: u32,
+) -> Vec<(Vec<String>, Evidence)> {
+    let hierarchy = OnceLock::new();
+    let mut requirements = vec![];
+    for (dex_file, class) in md.classes() {
+        for method_data in &class.codes {
+            let Some(code) = method_data.code.as_ref() else {
+                continue;
+            };
+            for (_, offset, instruction) in &code.insns {
+                let (rules, confidence_level) = match instruction {
+                    Instruction::InvokeVirtual(_, method_idx, _)
+                    | Instruction::InvokeSuper(_, method_idx, _)
+                    | Instruction::InvokeDirect(_, method_idx, _)
+                    | Instruction::InvokeStatic(_, method_idx, _)
+                    | Instruction::InvokeInterface(_, method_idx, _)
+                    | Instruction::InvokeVirtualRange(_, method_idx, _)
+                    | Instruction::InvokeSuperRange(_, method_idx, _)
+                    | Instruction::InvokeDirectRange(_, method_idx, _)
+                    | Instruction::InvokeStaticRange(_, method_idx, _)
+                    | Instruction::InvokeInterfaceRange(_, method_idx, _) => {
+                        let Some(method) = dex_file.methods.get(*method_idx as usize) else {
+                            continue;
+                        };
+                        let class_name =
+                            dex_file.get_type_name(method.class_idx).unwrap_or_default();
+                        let rules = mapping.method_rules(
+                            class_name,
+                            &method.method_name,
+                            &method.proto_name,
+                        );
+                        if !rules.is_empty() || mapping.has_class(class_name) {
+                            (rules, ConfidenceLevel::High)
+                        } else {
+                            let hierarchy =
+                                hierarchy.get_or_init(|| ClassHierarchy::with_framework(md));
+                            (
+                                inherited_method_rules(
+                                    hierarchy,
+                                    mapping,
+                                    class_name,
+                                    &method.method_name,
+                                    &method.proto_name,
+                                ),
+                                ConfidenceLevel::Medium,
+                            )
+                        }
+                    }
+                    Instruction::StaticGetObject(_, field_idx) => {
+                        let Some(field) = dex_file.fields.get(*field_idx as usize) else {
+                            continue;
+                        };
+                        let class_name =
+                            dex_file.get_type_name(field.class_idx).unwrap_or_default();
+                        (
+                            mapping
+                                .field_rules(class_name, &field.name)
+                                .iter()
+                                .collect(),
+                            ConfidenceLevel::High,
+                        )
+                    }
+                    Instruction::ConstString(_, string_idx) => {
+                        let Some(content) = dex_file.get_string(*string_idx) else {
+                            continue;
+                        };
+                        (mapping.string_rules(content), ConfidenceLevel::Medium)
+                    }
+                    Instruction::ConstStringJumbo(_, string_idx) => {
+                        let Some(content) = dex_file.get_string(*string_idx as usize) else {
+                            continue;
+                        };
+                        (mapping.string_rules(content), ConfidenceLevel::Medium)
+                    }
+                    _ => continue,
+                };
+                let rules: Vec<_> = rules
+                    .into_iter()
+                    .filter(|rule| rule.applies_to(target_sdk_version))
+                    .collect();
+                if rules.is_empty() {
+                    continue;
+                }
+                let evidence = instruction_evidence(
+                    instruction,
+                    offset.0 as i32,
+                    &method_data.method,
+                    &dex_file,
+                    confidence_level,
+                );
+                for rule in rules {
+                    requirements.push((rule.permissions.clone(), evidence.clone()));
+                }
+            }
+        }
+    }
+    requirements
+}
+
+/// Rules of the framework class an app class inherits the method from
+fn inherited_method_rules<'a>(
+    hierarchy: &ClassHierarchy,
+    mapping: &'a PermissionMapping,
+    class_name: &str,
+    method_name: &str,
+    proto: &str,
+) -> Vec<&'a PermissionRule> {
+    if !hierarchy
+        .get(class_name)
+        .is_some_and(|class| class.is_app())
+    {
+        return vec![];
+    }
+    hierarchy
+        .supertypes(class_name)
+        .iter()
+        .filter(|supertype| !supertype.is_app())
+        .map(|supertype| mapping.method_rules(&supertype.name, method_name, proto))
+        .find(|rules| !rules.is_empty())
+        .unwrap_or_default()
+}
+
+fn instruction_evidence(
+    instruction: &Instruction,
+    offset: i32,
+    method: &Arc<Method>,
+    dex_file: &Arc<DexFile>,
+    confidence_level: ConfidenceLevel,
+) -> Evidence {
+    Evidence::Instructions(InstructionEvidence {
+        instructions: vec![instruction.disassembly_from_opcode(
+            offset,
+            &mut HashMap::new(),
+            dex_file.clone(),
+        )],
+        place: Location::DexMethod(method.method_idx as u32, dex_file.clone()),
+        context: Context::DexMethod(method.clone(), dex_file.clone()),
+        confidence_level,
+    })
+}
+
+fn qualified_permission(permission: &str) -> String {
+    if permission.contains('.') {
+        permission.to_string()
+    } else {
+        format!("{}{}", PERMISSION_PREFIX, permission)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coeus_models::models::{
+        testing::{format_21c, format_35c, DexBuilder, RETURN_VOID},
+        AndroidManifest, AndroidPermission, AndroidSdk, Usages,
+    };
+
+    use super::*;
+
+    const CONST_STRING: u8 = 0x1a;
+    const SGET_OBJECT: u8 = 0x62;
+    const INVOKE_VIRTUAL: u8 = 0x6e;
+
+    const MAPPING: &str = "
+# comment
+api Landroid/webkit/WebView; loadUrl INTERNET
+api Landroid/location/LocationManager; getLastKnownLocation ACCESS_FINE_LOCATION|ACCESS_COARSE_LOCATION
+api Landroid/location/LocationManager; requestLocationUpdates ACCESS_FINE_LOCATION|ACCESS_COARSE_LOCATION
+api Landroid/location/LocationManager; addProximityAlert ACCESS_FINE_LOCATION
+api Landroid/bluetooth/BluetoothAdapter; enable BLUETOOTH_ADMIN until=31
+api Landroid/bluetooth/BluetoothAdapter; enable BLUETOOTH_CONNECT since=31
+api Landroid/net/wifi/WifiManager; setWifiEnabled(Z)Z CHANGE_WIFI_STATE
+field Landroid/provider/ContactsContract$Contacts; CONTENT_URI READ_CONTACTS
+uri content://sms READ_SMS
+action android.intent.action.CALL CALL_PHONE|com.example.permission.CALL
+";
+
+    fn mapping() -> PermissionMapping {
+        PermissionMapping::parse(MAPPING).unwrap()
+    }
+
+    fn permissions(rules: &[&PermissionRule]) -> Vec<String> {
+        rules
+            .iter()
+            .flat_map(|rule| rule.permissions.iter().cloned())
+            .collect()
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        for (content, error) in [
+            ("api La; a", "line 1: wrong number of arguments"),
+            (
+                "uri content://a INTERNET extra",
+                "line 1: wrong number of arguments",
+            ),
+            ("service La; INTERNET", "line 1: unknown entry service"),
+            (
+                "\napi La; a INTERNET since=x",
+                "line 2: invalid api level x",
+            ),
+            (
+                "action a INTERNET after=3",
+                "line 1: unknown option after=3",
+            ),
+        ] {
+            assert_eq!(
+                PermissionMapping::parse(content).unwrap_err(),
+                error,
+                "{}",
+                content
+            );
+        }
+        assert!(PermissionMapping::bundled().has_class("Landroid/webkit/WebView;"));
+    }
+
+    #[test]
+    fn rules_apply_within_their_bounds() {
+        let rule = |since, until| PermissionRule {
+            permissions: vec![],
+            since,
+            until,
+        };
+        assert!(rule(None, None).applies_to(1));
+        assert!(rule(None, None).applies_to(u32::MAX));
+        assert!(!rule(Some(31), None).applies_to(30));
+        assert!(rule(Some(31), None).applies_to(31));
+        assert!(rule(None, Some(31)).applies_to(30));
+        assert!(!rule(None, Some(31)).applies_to(31));
+        assert!(rule(Some(23), Some(31)).applies_to(23));
+        assert!(!rule(Some(23), Some(31)).applies_to(31));
+
+        let mapping = mapping();
+        let enable = mapping.method_rules("Landroid/bluetooth/BluetoothAdapter;", "enable", "()Z");
+        let applicable = |target_sdk_version: u32| -> Vec<&str> {
+            enable
+                .iter()
+                .filter(|rule| rule.applies_to(target_sdk_version))
+                .flat_map(|rule| rule.permissions.iter().map(|p| p.as_str()))
+                .collect()
+        };
+        assert_eq!(applicable(30), ["android.permission.BLUETOOTH_ADMIN"]);
+        assert_eq!(applicable(31), ["android.permission.BLUETOOTH_CONNECT"]);
+    }
+
+    #[test]
+    fn methods_match_by_name_or_prototype() {
+        let mapping = mapping();
+        let location_manager = "Landroid/location/LocationManager;";
+        // a name matches all overloads
+        for proto in ["(Ljava/lang/String;)Landroid/location/Location;", "()V"] {
+            assert_eq!(
+                permissions(&mapping.method_rules(location_manager, "getLastKnownLocation", proto)),
+                [
+                    "android.permission.ACCESS_FINE_LOCATION",
+                    "android.permission.ACCESS_COARSE_LOCATION"
+                ]
+            );
+        }
+        let wifi_manager = "Landroid/net/wifi/WifiManager;";
+        assert_eq!(
+            permissions(&mapping.method_rules(wifi_manager, "setWifiEnabled", "(Z)Z")),
+            ["android.permission.CHANGE_WIFI_STATE"]
+        );
+        assert!(mapping
+            .method_rules(wifi_manager, "setWifiEnabled", "(I)Z")
+            .is_empty());
+        assert!(mapping
+            .method_rules(wifi_manager, "setWifi", "Enabled(Z)Z")
+            .is_empty());
+        assert!(mapping
+            .method_rules(location_manager, "getLastKnown", "()V")
+            .is_empty());
+        assert!(mapping
+            .method_rules("Landroid/app/Activity;", "loadUrl", "()V")
+            .is_empty());
+
+        assert_eq!(
+            mapping.field_rules(
+                "Landroid/provider/ContactsContract$Contacts;",
+                "CONTENT_URI"
+            )[0]
+            .permissions,
+            ["android.permission.READ_CONTACTS"]
+        );
+        assert_eq!(
+            permissions(&mapping.string_rules("content://sms/inbox")),
+            ["android.permission.READ_SMS"]
+        );
+        // permissions with a package are kept as they are
+        assert_eq!(
+            permissions(&mapping.string_rules("android.intent.action.CALL")),
+            [
+                "android.permission.CALL_PHONE",
+                "com.example.permission.CALL"
+            ]
+        );
+        assert!(mapping
+            .string_rules("android.intent.action.CALLS")
+            .is_empty());
+    }
+
+    fn files(declared: &[&str]) -> Files {
+        let mut dex = DexBuilder::new("classes");
+        let string = "Ljava/lang/String;";
+        let location_manager = "Landroid/location/LocationManager;";
+        let get_last_known_location = dex.method(
+            location_manager,
+            "getLastKnownLocation",
+            "Landroid/location/Location;",
+            &[string],
+        );
+        let add_proximity_alert = dex.method(
+            location_manager,
+            "addProximityAlert",
+            "V",
+            &["D", "D", "F", "J", "Landroid/app/PendingIntent;"],
+        );
+        let content_uri = dex.field(
+            "Landroid/provider/ContactsContract$Contacts;",
+            "CONTENT_URI",
+            "Landroid/net/Uri;",
+        );
+        let load_url = dex.method("Lcom/example/Browser;", "loadUrl", "V", &[string]);
+        let other_load_url = dex.method("Lcom/example/Other;", "loadUrl", "V", &[string]);
+        let sms = dex.string("content://sms/inbox") as u16;
+
+        dex.class(
+            "Lcom/example/Browser;",
+            Some("Landroid/webkit/WebView;"),
+            &[],
+        );
+        dex.class("Lcom/example/Other;", Some("Ljava/lang/Object;"), &[]);
+        let main = dex.class("Lcom/example/Main;", Some("Landroid/app/Activity;"), &[]);
+        let on_create = dex.method("Lcom/example/Main;", "onCreate", "V", &[]);
+        let code = [
+            &format_35c(INVOKE_VIRTUAL, get_last_known_location, &[0, 1])[..],
+            &format_35c(INVOKE_VIRTUAL, add_proximity_alert, &[0, 1, 2, 3, 4]),
+            &format_21c(SGET_OBJECT, 0, content_uri),
+            &format_21c(CONST_STRING, 1, sms),
+            &format_35c(INVOKE_VIRTUAL, load_url, &[2, 1]),
+            &format_35c(INVOKE_VIRTUAL, other_load_url, &[3, 1]),
+            &[RETURN_VOID],
+        ]
+        .concat();
+        dex.virtual_method(main, on_create, &code);
+
+        let manifest = AndroidManifest {
+            package: "com.example".to_string(),
+            content: std::iter::once(Usages::UsesSdk(AndroidSdk {
+                min_sdk_version: "21".to_string(),
+                target_sdk_version: "33".to_string(),
+            }))
+            .chain(
+                declared
+                    .iter()
+                    .map(|p| Usages::UsesPermission(AndroidPermission::new(p))),
+            )
+            .collect(),
+            ..Default::default()
+        };
+        let multi_dex = MultiDexFile::new(manifest, String::new(), dex.build(), vec![]);
+        Files::new(vec![multi_dex], HashMap::new())
+    }
+
+    fn confidence_levels(usage: &PermissionUsage) -> Vec<ConfidenceLevel> {
+        usage
+            .evidences
+            .iter()
+            .map(|evidence| match evidence {
+                Evidence::Instructions(evidence) => evidence.confidence_level,
+                other => panic!("expected instruction evidence, got {:?}", other),
+            })
+            .collect()
+    }
+
+    #[test]
+    fn usages_are_reported_per_permission() {
+        let files = files(&[
+            "android.permission.CAMERA",
+            "android.permission.READ_CONTACTS",
+        ]);
+        let usages = find_permission_usage_with_mapping(&files, &mapping());
+        let summary: Vec<_> = usages
+            .iter()
+            .map(|usage| {
+                (
+                    usage.permission.as_str(),
+                    usage.status,
+                    usage.evidences.len(),
+                )
+            })
+            .collect();
+        assert_eq!(
+            summary,
+            [
+                ("android.permission.CAMERA", PermissionStatus::Unused, 0),
+                (
+                    "android.permission.READ_CONTACTS",
+                    PermissionStatus::Used,
+                    1
+                ),
+                (
+                    "android.permission.ACCESS_FINE_LOCATION",
+                    PermissionStatus::Undeclared,
+                    2
+                ),
+                (
+                    "android.permission.READ_SMS",
+                    PermissionStatus::Undeclared,
+                    1
+                ),
+                (
+                    "android.permission.INTERNET",
+                    PermissionStatus::Undeclared,
+                    1
+                ),
+            ]
+        );
+        // only ACCESS_FINE_LOCATION satisfies addProximityAlert
+        assert!(usages[2].alternatives.is_empty());
+    }
+
+    #[test]
+    fn declaring_an_alternative_satisfies_the_requirement() {
+        let files = files(&["android.permission.ACCESS_COARSE_LOCATION"]);
+        let usages = find_permission_usage_with_mapping(&files, &mapping());
+        assert_eq!(
+            usages[0].permission,
+            "android.permission.ACCESS_COARSE_LOCATION"
+        );
+        assert_eq!(usages[0].status, PermissionStatus::Used);
+        assert_eq!(usages[0].evidences.len(), 1);
+        let fine_location = usages
+            .iter()
+            .find(|usage| usage.permission == "android.permission.ACCESS_FINE_LOCATION")
+            .unwrap();
+        assert_eq!(fine_location.status, PermissionStatus::Undeclared);
+        assert_eq!(fine_location.evidences.len(), 1);
+    }
+
+    #[test]
+    fn calls_on_app_classes_are_attributed_to_the_framework() {
+        let files = files(&[]);
+        let usages = find_permission_usage_with_mapping(&files, &mapping());
+        let internet = usages
+            .iter()
+            .find(|usage| usage.permission == "android.permission.INTERNET")
+            .unwrap();
+        // only the subclass of WebView, not the unrelated class with the same method
+        assert_eq!(internet.evidences.len(), 1);
+        assert!(matches!(
+            confidence_levels(internet)[..],
+            [ConfidenceLevel::Medium]
+        ));
+        let contacts = usages
+            .iter()
+            .find(|usage| usage.permission == "android.permission.READ_CONTACTS")
+            .unwrap();
+        assert!(matches!(
+            confidence_levels(contacts)[..],
+            [ConfidenceLevel::High]
+        ));
+    }
+}
@@ -0,0 +1,161 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Resolve resource ids used in bytecode. Resource ids are loaded with a plain `const` instruction
+//! before calls like `getString` or `setContentView`, so we look for 32bit constants pointing into
+//! a package of the resource table and map them to `R.type.name`. The constants are taken from the
+//! cross reference database, such that repeated queries do not scan the code again.
+
+use std::{collections::HashMap, sync::Arc};
+
+use regex::Regex;
+
+use coeus_models::models::{DexFile, Files, Method, MultiDexFile, XrefSite};
+
+use super::{ConfidenceLevel, Context, Evidence, InstructionEvidence, Location, StringEvidence};
+
+/// A constant in the bytecode, which refers to a resource
+struct ResourceConstant {
+    dex_file: Arc<DexFile>,
+    method: Arc<Method>,
+    resource_id: u32,
+    disassembly: String,
+}
+
+fn find_resource_constants<F>(files: &Files, is_match: F) -> Vec<ResourceConstant>
+where
+    F: Fn(u32) -> bool,
+{
+    let Some(arsc) = files.arsc.as_ref() else {
+        return vec![];
+    };
+    let mut constants = vec![];
+    for md in &files.multi_dex {
+        let xrefs = md.xrefs();
+        // the literals are kept in a hash map, sort them for a stable output
+        let mut literals: Vec<_> = xrefs.literals().collect();
+        literals.sort_unstable_by_key(|(resource_id, _)| *resource_id);
+        for (resource_id, sites) in literals {
+            if arsc.get_package((resource_id >> 24) as u8).is_none() || !is_match(resource_id) {
+                continue;
+            }
+            constants.extend(
+                sites
+                    .iter()
+                    .filter_map(|site| resource_constant(md, site, resource_id)),
+            );
+        }
+    }
+    constants
+}
+
+fn resource_constant(
+    multi_dex: &MultiDexFile,
+    site: &XrefSite,
+    resource_id: u32,
+) -> Option<ResourceConstant> {
+    let (dex_file, method) = multi_dex.xref_method(site)?;
+    let method_data = dex_file.get_method_by_idx(site.method_idx)?;
+    let (_, offset, instruction) = method_data
+        .code
+        .as_ref()?
+        .insns
+        .iter()
+        .find(|(_, offset, _)| offset.0 == site.offset)?;
+    let disassembly =
+        instruction.disassembly_from_opcode(offset.0 as i32, &mut HashMap::new(), dex_file.clone());
+    Some(ResourceConstant {
+        dex_file,
+        method,
+        resource_id,
+        disassembly,
+    })
+}
+
+fn to_instruction_evidence(constant: ResourceConstant, description: String) -> Evidence {
+    Evidence::Instructions(InstructionEvidence {
+        instructions: vec![format!("{} # {}", constant.disassembly, description)],
+        place: Location::DexMethod(constant.method.method_idx as u32, constant.dex_file.clone()),
+        context: Context::DexMethod(constant.method, constant.dex_file),
+        confidence_level: ConfidenceLevel::High,
+    })
+}
+
+/// Find all constants in the bytecode which resolve to a resource of the app
+pub fn find_resource_usages(files: &Files) -> Vec<Evidence> {
+    find_resource_constants(files, |_| true)
+        .into_iter()
+        .filter_map(|constant| {
+            let description = files.describe_resource(constant.resource_id)?;
+            Some(to_instruction_evidence(constant, description))
+        })
+        .collect()
+}
+
+/// Find all places where the resource `name` (e.g. `string/app_name`) is used in the bytecode
+pub fn find_resource_usages_by_name(name: &str, files: &Files) -> Vec<Evidence> {
+    let Some(resource_id) = files.get_resource_id(name) else {
+        return vec![];
+    };
+    let Some(description) = files.describe_resource(resource_id) else {
+        return vec![];
+    };
+    find_resource_constants(files, |id| id == resource_id)
+        .into_iter()
+        .map(|constant| to_instruction_evidence(constant, description.clone()))
+        .collect()
+}
+
+/// Search string resources and report the places in the bytecode where matching strings are loaded.
+pub fn find_string_matches_in_resources(reg: &Regex, files: &Files) -> Vec<Evidence> {
+    let Some(arsc) = files.arsc.as_ref() else {
+        return vec![];
+    };
+    let matching_strings: HashMap<u32, Vec<String>> = arsc
+        .get_resources_of_type("string")
+        .into_iter()
+        .filter_map(|resource| {
+            let id = resource.id;
+            let values = files
+                .resolve_resource(id)?
+                .values
+                .into_iter()
+                .filter_map(|(_, value)| value.as_str().map(|s| s.to_string()))
+                .filter(|s| reg.is_match(s))
+                .collect::<Vec<_>>();
+            if values.is_empty() {
+                None
+            } else {
+                Some((id, values))
+            }
+        })
+        .collect();
+    if matching_strings.is_empty() {
+        return vec![];
+    }
+
+    let mut matches = vec![];
+    for constant in find_resource_constants(files, |id| matching_strings.contains_key(&id)) {
+        let Some(values) = matching_strings.get(&constant.resource_id) else {
+            continue;
+        };
+        let mut values = values.clone();
+        values.dedup();
+        for content in values {
+            matches.push(Evidence::String(StringEvidence {
+                content,
+                place: Location::DexMethod(
+                    constant.method.method_idx as u32,
+                    constant.dex_file.clone(),
+                ),
+                context: Context::DexMethod(constant.method.clone(), constant.dex_file.clone()),
+                confidence_level: ConfidenceLevel::Medium,
+                detail: None,
+            }));
+        }
+    }
+    matches
+}
@@ -491,11 +491,7 @@ pub mod dex_module {
         coeus_file: &mut MultiDexFile,
         class_name: ImmutableString,
     ) -> String {
-        if let Some((_, class)) = coeus_file
-            .classes()
-            .iter()
-            .find(|(_f, c)| c.class_name == class_name)
-        {
+        if let Some((_, class)) = coeus_file.get_class_by_name(&class_name) {
             class.get_disassembly(coeus_file)
         } else {
             String::from("")
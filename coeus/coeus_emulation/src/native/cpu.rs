@@ -0,0 +1,1755 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A small AArch64 interpreter. It covers the A64 base instruction set (including LSE atomics) and the
+//! parts of Advanced SIMD compilers emit for memory copies and simple vectorized loops. Floating point
+//! arithmetic is not implemented.
+
+use super::{memory::Memory, NativeException};
+
+const TPIDR_EL0: u32 = 0xde82;
+const NZCV: u32 = 0xda10;
+const FPCR: u32 = 0xda20;
+const FPSR: u32 = 0xda21;
+const CNTVCT_EL0: u32 = 0xdf02;
+const CNTFRQ_EL0: u32 = 0xdf00;
+
+#[derive(Clone, Default, Debug)]
+pub struct Cpu {
+    /// General purpose registers x0 to x30
+    pub x: [u64; 31],
+    pub sp: u64,
+    pub pc: u64,
+    pub n: bool,
+    pub z: bool,
+    pub c: bool,
+    pub v: bool,
+    /// SIMD and floating point registers
+    pub q: [u128; 32],
+    pub tpidr: u64,
+    pub fpcr: u64,
+    pub fpsr: u64,
+    exclusive: Option<u64>,
+    ticks: u64,
+}
+
+fn bits(value: u32, high: u32, low: u32) -> u32 {
+    (value >> low) & ((1 << (high - low + 1)) - 1)
+}
+
+fn bit(value: u32, index: u32) -> bool {
+    (value >> index) & 1 == 1
+}
+
+fn sign_extend(value: u64, width: u32) -> u64 {
+    let shift = 64 - width;
+    (((value << shift) as i64) >> shift) as u64
+}
+
+fn ones(count: u32) -> u64 {
+    if count >= 64 {
+        u64::MAX
+    } else {
+        (1 << count) - 1
+    }
+}
+
+fn mask(value: u64, datasize: u32) -> u64 {
+    value & ones(datasize)
+}
+
+fn ror(value: u64, amount: u32, width: u32) -> u64 {
+    let amount = amount % width;
+    if amount == 0 {
+        return value;
+    }
+    mask((value >> amount) | (value << (width - amount)), width)
+}
+
+fn replicate(value: u64, esize: u32) -> u64 {
+    let mut result = 0;
+    let mut position = 0;
+    while position < 64 {
+        result |= value << position;
+        position += esize;
+    }
+    result
+}
+
+/// DecodeBitMasks from the architecture reference manual, returns `(wmask, tmask)`
+fn decode_bit_masks(n: u32, imms: u32, immr: u32, datasize: u32) -> Option<(u64, u64)> {
+    let combined = (n << 6) | (!imms & 0x3f);
+    if combined == 0 {
+        return None;
+    }
+    let len = 31 - combined.leading_zeros();
+    if len < 1 {
+        return None;
+    }
+    let levels = ones(len) as u32;
+    let s = imms & levels;
+    let r = immr & levels;
+    let diff = s.wrapping_sub(r) & levels;
+    let esize = 1 << len;
+    let welem = ones(s + 1);
+    let telem = ones(diff + 1);
+    let wmask = mask(replicate(ror(welem, r, esize), esize), datasize);
+    let tmask = mask(replicate(telem, esize), datasize);
+    Some((wmask, tmask))
+}
+
+fn add_with_carry(x: u64, y: u64, carry: bool, datasize: u32) -> (u64, bool, bool, bool, bool) {
+    let x = mask(x, datasize);
+    let y = mask(y, datasize);
+    let unsigned_sum = x as u128 + y as u128 + carry as u128;
+    let result = mask(unsigned_sum as u64, datasize);
+    let sx = sign_extend(x, datasize) as i64 as i128;
+    let sy = sign_extend(y, datasize) as i64 as i128;
+    let signed_sum = sx + sy + carry as i128;
+    let n = (result >> (datasize - 1)) & 1 == 1;
+    let z = result == 0;
+    let c = unsigned_sum != result as u128;
+    let v = signed_sum != sign_extend(result, datasize) as i64 as i128;
+    (result, n, z, c, v)
+}
+
+/// Result of executing a single instruction
+pub enum Step {
+    Continue,
+    /// A `BRK`/`UDF` or an explicit trap was reached
+    Trap(u32),
+}
+
+impl Cpu {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Number of executed instructions
+    pub fn ticks(&self) -> u64 {
+        self.ticks
+    }
+
+    fn reg(&self, index: u32, datasize: u32) -> u64 {
+        if index == 31 {
+            0
+        } else {
+            mask(self.x[index as usize], datasize)
+        }
+    }
+
+    fn reg_sp(&self, index: u32, datasize: u32) -> u64 {
+        if index == 31 {
+            mask(self.sp, datasize)
+        } else {
+            mask(self.x[index as usize], datasize)
+        }
+    }
+
+    fn set_reg(&mut self, index: u32, value: u64, datasize: u32) {
+        if index != 31 {
+            self.x[index as usize] = mask(value, datasize);
+        }
+    }
+
+    fn set_reg_sp(&mut self, index: u32, value: u64, datasize: u32) {
+        if index == 31 {
+            self.sp = mask(value, datasize);
+        } else {
+            self.x[index as usize] = mask(value, datasize);
+        }
+    }
+
+    fn set_flags(&mut self, n: bool, z: bool, c: bool, v: bool) {
+        self.n = n;
+        self.z = z;
+        self.c = c;
+        self.v = v;
+    }
+
+    fn set_logical_flags(&mut self, result: u64, datasize: u32) {
+        let n = (result >> (datasize - 1)) & 1 == 1;
+        self.set_flags(n, result == 0, false, false);
+    }
+
+    pub fn nzcv(&self) -> u64 {
+        ((self.n as u64) << 31)
+            | ((self.z as u64) << 30)
+            | ((self.c as u64) << 29)
+            | ((self.v as u64) << 28)
+    }
+
+    fn set_nzcv(&mut self, value: u64) {
+        self.set_flags(
+            value >> 31 & 1 == 1,
+            value >> 30 & 1 == 1,
+            value >> 29 & 1 == 1,
+            value >> 28 & 1 == 1,
+        );
+    }
+
+    fn condition_holds(&self, condition: u32) -> bool {
+        let result = match condition >> 1 {
+            0 => self.z,
+            1 => self.c,
+            2 => self.n,
+            3 => self.v,
+            4 => self.c && !self.z,
+            5 => self.n == self.v,
+            6 => self.n == self.v && !self.z,
+            _ => true,
+        };
+        if condition & 1 == 1 && condition != 0xf {
+            !result
+        } else {
+            result
+        }
+    }
+
+    /// Fetch, decode and execute the instruction at `pc`
+    pub fn step(&mut self, memory: &mut Memory) -> Result<Step, NativeException> {
+        let instruction = memory
+            .read_u32(self.pc)
+            .map_err(|_| NativeException::InvalidProgramCounter(self.pc))?;
+        self.ticks += 1;
+        let pc = self.pc;
+        self.pc = pc.wrapping_add(4);
+        let unsupported = || NativeException::UnsupportedInstruction(pc, instruction);
+        let result = match bits(instruction, 28, 25) {
+            0b1000 | 0b1001 => self.data_processing_immediate(instruction, pc),
+            0b1010 | 0b1011 => self.branch_system(instruction, pc),
+            0b0100 | 0b0110 | 0b1100 | 0b1110 => self.load_store(instruction, pc, memory),
+            0b0101 | 0b1101 => self.data_processing_register(instruction),
+            0b0111 | 0b1111 => self.simd_data_processing(instruction),
+            _ => Err(None),
+        };
+        match result {
+            Ok(step) => Ok(step),
+            Err(Some(exception)) => {
+                self.pc = pc;
+                Err(exception)
+            }
+            Err(None) => {
+                self.pc = pc;
+                Err(unsupported())
+            }
+        }
+    }
+
+    fn data_processing_immediate(
+        &mut self,
+        i: u32,
+        pc: u64,
+    ) -> Result<Step, Option<NativeException>> {
+        let sf = bit(i, 31);
+        let datasize = if sf { 64 } else { 32 };
+        let rd = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        match bits(i, 25, 23) {
+            0b000 | 0b001 => {
+                let imm = (bits(i, 23, 5) << 2 | bits(i, 30, 29)) as u64;
+                let imm = sign_extend(imm, 21);
+                let value = if sf {
+                    (pc & !0xfff).wrapping_add(imm << 12)
+                } else {
+                    pc.wrapping_add(imm)
+                };
+                self.set_reg(rd, value, 64);
+            }
+            0b010 => {
+                let sub = bit(i, 30);
+                let set_flags = bit(i, 29);
+                let mut imm = bits(i, 21, 10) as u64;
+                if bit(i, 22) {
+                    imm <<= 12;
+                }
+                let operand = self.reg_sp(rn, datasize);
+                let (result, n, z, c, v) = if sub {
+                    add_with_carry(operand, !imm, true, datasize)
+                } else {
+                    add_with_carry(operand, imm, false, datasize)
+                };
+                if set_flags {
+                    self.set_flags(n, z, c, v);
+                    self.set_reg(rd, result, datasize);
+                } else {
+                    self.set_reg_sp(rd, result, datasize);
+                }
+            }
+            0b100 => {
+                let n = bits(i, 22, 22);
+                if !sf && n == 1 {
+                    return Err(None);
+                }
+                let (imm, _) =
+                    decode_bit_masks(n, bits(i, 15, 10), bits(i, 21, 16), datasize).ok_or(None)?;
+                let operand = self.reg(rn, datasize);
+                let opc = bits(i, 30, 29);
+                let result = match opc {
+                    0b00 | 0b11 => operand & imm,
+                    0b01 => operand | imm,
+                    _ => operand ^ imm,
+                };
+                if opc == 0b11 {
+                    self.set_logical_flags(result, datasize);
+                    self.set_reg(rd, result, datasize);
+                } else {
+                    self.set_reg_sp(rd, result, datasize);
+                }
+            }
+            0b101 => {
+                let hw = bits(i, 22, 21);
+                if !sf && hw > 1 {
+                    return Err(None);
+                }
+                let shift = hw * 16;
+                let imm = (bits(i, 20, 5) as u64) << shift;
+                match bits(i, 30, 29) {
+                    0b00 => self.set_reg(rd, !imm, datasize),
+                    0b10 => self.set_reg(rd, imm, datasize),
+                    0b11 => {
+                        let value = self.reg(rd, datasize) & !(0xffff << shift) | imm;
+                        self.set_reg(rd, value, datasize);
+                    }
+                    _ => return Err(None),
+                }
+            }
+            0b110 => {
+                let immr = bits(i, 21, 16);
+                let imms = bits(i, 15, 10);
+                // N has to match sf, the 32 bit variants only take 5 bit immediates
+                if bit(i, 22) != sf || immr >= datasize || imms >= datasize {
+                    return Err(None);
+                }
+                let source = self.reg(rn, datasize);
+                let result = match bits(i, 30, 29) {
+                    0b00 => {
+                        // SBFM
+                        if imms >= immr {
+                            let width = imms - immr + 1;
+                            sign_extend((source >> immr) & ones(width), width)
+                        } else {
+                            let width = imms + 1;
+                            sign_extend(source & ones(width), width) << (datasize - immr)
+                        }
+                    }
+                    0b01 => {
+                        // BFM
+                        let destination = self.reg(rd, datasize);
+                        if imms >= immr {
+                            let width = imms - immr + 1;
+                            destination & !ones(width) | ((source >> immr) & ones(width))
+                        } else {
+                            let width = imms + 1;
+                            let lsb = datasize - immr;
+                            destination & !(ones(width) << lsb) | ((source & ones(width)) << lsb)
+                        }
+                    }
+                    0b10 => {
+                        // UBFM
+                        if imms >= immr {
+                            (source >> immr) & ones(imms - immr + 1)
+                        } else {
+                            (source & ones(imms + 1)) << (datasize - immr)
+                        }
+                    }
+                    _ => return Err(None),
+                };
+                self.set_reg(rd, result, datasize);
+            }
+            0b111 => {
+                let rm = bits(i, 20, 16);
+                let lsb = bits(i, 15, 10);
+                if bit(i, 22) != sf || bits(i, 30, 29) != 0 || bit(i, 21) || lsb >= datasize {
+                    return Err(None);
+                }
+                let high = self.reg(rn, datasize);
+                let low = self.reg(rm, datasize);
+                let result = if lsb == 0 {
+                    low
+                } else {
+                    (low >> lsb) | (high << (datasize - lsb))
+                };
+                self.set_reg(rd, result, datasize);
+            }
+            _ => return Err(None),
+        }
+        Ok(Step::Continue)
+    }
+
+    fn branch_system(&mut self, i: u32, pc: u64) -> Result<Step, Option<NativeException>> {
+        if bits(i, 30, 26) == 0b00101 {
+            // B, BL
+            let offset = sign_extend((bits(i, 25, 0) as u64) << 2, 28);
+            if bit(i, 31) {
+                self.x[30] = pc.wrapping_add(4);
+            }
+            self.pc = pc.wrapping_add(offset);
+        } else if bits(i, 31, 24) == 0b0101_0100 {
+            // B.cond
+            let offset = sign_extend((bits(i, 23, 5) as u64) << 2, 21);
+            if self.condition_holds(bits(i, 3, 0)) {
+                self.pc = pc.wrapping_add(offset);
+            }
+        } else if bits(i, 30, 25) == 0b011010 {
+            // CBZ, CBNZ
+            let datasize = if bit(i, 31) { 64 } else { 32 };
+            let offset = sign_extend((bits(i, 23, 5) as u64) << 2, 21);
+            let is_zero = self.reg(bits(i, 4, 0), datasize) == 0;
+            if is_zero != bit(i, 24) {
+                self.pc = pc.wrapping_add(offset);
+            }
+        } else if bits(i, 30, 25) == 0b011011 {
+            // TBZ, TBNZ
+            let bit_position = bits(i, 31, 31) << 5 | bits(i, 23, 19);
+            let offset = sign_extend((bits(i, 18, 5) as u64) << 2, 16);
+            let is_set = (self.reg(bits(i, 4, 0), 64) >> bit_position) & 1 == 1;
+            if is_set == bit(i, 24) {
+                self.pc = pc.wrapping_add(offset);
+            }
+        } else if bits(i, 31, 25) == 0b1101011 {
+            // BR, BLR, RET and their pointer authenticated variants
+            let target = self.reg(bits(i, 9, 5), 64);
+            match bits(i, 24, 21) {
+                0b0000 | 0b1000 | 0b0010 => {}
+                0b0001 | 0b1001 => self.x[30] = pc.wrapping_add(4),
+                _ => return Err(None),
+            }
+            self.pc = target;
+        } else if bits(i, 31, 24) == 0b1101_0100 {
+            // SVC, HVC, SMC, BRK, HLT
+            return match bits(i, 23, 21) {
+                0b001 => Ok(Step::Trap(bits(i, 20, 5))),
+                0b000 => Err(Some(NativeException::SystemCall(bits(i, 20, 5)))),
+                _ => Err(None),
+            };
+        } else if bits(i, 31, 22) == 0b1101010100 {
+            self.system(i)?;
+        } else {
+            return Err(None);
+        }
+        Ok(Step::Continue)
+    }
+
+    fn system(&mut self, i: u32) -> Result<(), Option<NativeException>> {
+        let rt = bits(i, 4, 0);
+        let register = bits(i, 20, 5);
+        match bits(i, 21, 19) {
+            // hints (NOP, YIELD, BTI, PACIASP, ...), barriers and PSTATE writes
+            0b000 => {}
+            // SYS: cache maintenance
+            0b001 => {}
+            // MSR
+            0b010 | 0b011 => {
+                let value = self.reg(rt, 64);
+                match register {
+                    TPIDR_EL0 => self.tpidr = value,
+                    NZCV => self.set_nzcv(value),
+                    FPCR => self.fpcr = value,
+                    FPSR => self.fpsr = value,
+                    _ => log::debug!("ignoring write to system register {:x}", register),
+                }
+            }
+            // MRS
+            0b110 | 0b111 => {
+                let value = match register {
+                    TPIDR_EL0 => self.tpidr,
+                    NZCV => self.nzcv(),
+                    FPCR => self.fpcr,
+                    FPSR => self.fpsr,
+                    CNTVCT_EL0 => self.ticks,
+                    CNTFRQ_EL0 => 19_200_000,
+                    _ => {
+                        log::debug!("reading unknown system register {:x}", register);
+                        0
+                    }
+                };
+                self.set_reg(rt, value, 64);
+            }
+            _ => return Err(None),
+        }
+        Ok(())
+    }
+
+    fn data_processing_register(&mut self, i: u32) -> Result<Step, Option<NativeException>> {
+        let sf = bit(i, 31);
+        let datasize = if sf { 64 } else { 32 };
+        let rd = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        let rm = bits(i, 20, 16);
+        if !bit(i, 28) {
+            if !bit(i, 24) {
+                // logical (shifted register)
+                let amount = bits(i, 15, 10);
+                if !sf && amount > 31 {
+                    return Err(None);
+                }
+                let mut operand2 = self.shift_register(rm, bits(i, 23, 22), amount, datasize);
+                if bit(i, 21) {
+                    operand2 = mask(!operand2, datasize);
+                }
+                let operand1 = self.reg(rn, datasize);
+                let opc = bits(i, 30, 29);
+                let result = match opc {
+                    0b00 | 0b11 => operand1 & operand2,
+                    0b01 => operand1 | operand2,
+                    _ => operand1 ^ operand2,
+                };
+                if opc == 0b11 {
+                    self.set_logical_flags(result, datasize);
+                }
+                self.set_reg(rd, result, datasize);
+            } else {
+                let sub = bit(i, 30);
+                let set_flags = bit(i, 29);
+                let (operand1, operand2) = if !bit(i, 21) {
+                    // add/sub (shifted register)
+                    let shift = bits(i, 23, 22);
+                    if shift == 0b11 {
+                        return Err(None);
+                    }
+                    (
+                        self.reg(rn, datasize),
+                        self.shift_register(rm, shift, bits(i, 15, 10), datasize),
+                    )
+                } else {
+                    // add/sub (extended register)
+                    let shift = bits(i, 12, 10);
+                    if shift > 4 {
+                        return Err(None);
+                    }
+                    let extended = self.extend_register(rm, bits(i, 15, 13));
+                    (self.reg_sp(rn, datasize), mask(extended << shift, datasize))
+                };
+                let (result, n, z, c, v) = if sub {
+                    add_with_carry(operand1, !operand2, true, datasize)
+                } else {
+                    add_with_carry(operand1, operand2, false, datasize)
+                };
+                if set_flags {
+                    self.set_flags(n, z, c, v);
+                    self.set_reg(rd, result, datasize);
+                } else if bit(i, 21) {
+                    self.set_reg_sp(rd, result, datasize);
+                } else {
+                    self.set_reg(rd, result, datasize);
+                }
+            }
+            return Ok(Step::Continue);
+        }
+        match bits(i, 24, 21) {
+            0b0000 => {
+                // ADC, ADCS, SBC, SBCS
+                if bits(i, 15, 10) != 0 {
+                    return Err(None);
+                }
+                let operand1 = self.reg(rn, datasize);
+                let mut operand2 = self.reg(rm, datasize);
+                if bit(i, 30) {
+                    operand2 = !operand2;
+                }
+                let (result, n, z, c, v) = add_with_carry(operand1, operand2, self.c, datasize);
+                if bit(i, 29) {
+                    self.set_flags(n, z, c, v);
+                }
+                self.set_reg(rd, result, datasize);
+            }
+            0b0010 => {
+                // CCMN, CCMP
+                if !bit(i, 29) || bit(i, 10) || bit(i, 4) {
+                    return Err(None);
+                }
+                if self.condition_holds(bits(i, 15, 12)) {
+                    let operand1 = self.reg(rn, datasize);
+                    let operand2 = if bit(i, 11) {
+                        bits(i, 20, 16) as u64
+                    } else {
+                        self.reg(rm, datasize)
+                    };
+                    let (_, n, z, c, v) = if bit(i, 30) {
+                        add_with_carry(operand1, !operand2, true, datasize)
+                    } else {
+                        add_with_carry(operand1, operand2, false, datasize)
+                    };
+                    self.set_flags(n, z, c, v);
+                } else {
+                    self.set_nzcv((bits(i, 3, 0) as u64) << 28);
+                }
+            }
+            0b0100 => {
+                // CSEL, CSINC, CSINV, CSNEG
+                if bit(i, 29) || bit(i, 11) {
+                    return Err(None);
+                }
+                let result = if self.condition_holds(bits(i, 15, 12)) {
+                    self.reg(rn, datasize)
+                } else {
+                    let operand = self.reg(rm, datasize);
+                    match (bit(i, 30), bit(i, 10)) {
+                        (false, false) => operand,
+                        (false, true) => operand.wrapping_add(1),
+                        (true, false) => !operand,
+                        (true, true) => operand.wrapping_neg(),
+                    }
+                };
+                self.set_reg(rd, result, datasize);
+            }
+            0b0110 => {
+                if bit(i, 30) {
+                    self.data_processing_one_source(i, datasize)?;
+                } else {
+                    self.data_processing_two_source(i, datasize)?;
+                }
+            }
+            0b1000..=0b1111 => self.data_processing_three_source(i, datasize)?,
+            _ => return Err(None),
+        }
+        Ok(Step::Continue)
+    }
+
+    fn data_processing_one_source(
+        &mut self,
+        i: u32,
+        datasize: u32,
+    ) -> Result<(), Option<NativeException>> {
+        let rd = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        if bits(i, 20, 16) == 0b00001 {
+            // pointer authentication, we never sign pointers so there is nothing to strip
+            return Ok(());
+        }
+        if bits(i, 20, 16) != 0 || bit(i, 29) {
+            return Err(None);
+        }
+        let operand = self.reg(rn, datasize);
+        let result = match bits(i, 15, 10) {
+            0b000000 => {
+                if datasize == 64 {
+                    operand.reverse_bits()
+                } else {
+                    (operand as u32).reverse_bits() as u64
+                }
+            }
+            0b000001 => {
+                let mut result = 0;
+                for half in 0..datasize / 16 {
+                    let value = (operand >> (half * 16)) as u16;
+                    result |= (value.swap_bytes() as u64) << (half * 16);
+                }
+                result
+            }
+            0b000010 => {
+                if datasize == 64 {
+                    let low = (operand as u32).swap_bytes() as u64;
+                    let high = ((operand >> 32) as u32).swap_bytes() as u64;
+                    high << 32 | low
+                } else {
+                    (operand as u32).swap_bytes() as u64
+                }
+            }
+            0b000011 if datasize == 64 => operand.swap_bytes(),
+            0b000100 => {
+                if datasize == 64 {
+                    operand.leading_zeros() as u64
+                } else {
+                    (operand as u32).leading_zeros() as u64
+                }
+            }
+            0b000101 => {
+                let value = sign_extend(operand, datasize);
+                let flipped = value ^ ((value as i64) >> 63) as u64;
+                let leading = if datasize == 64 {
+                    flipped.leading_zeros()
+                } else {
+                    (flipped as u32).leading_zeros()
+                };
+                (leading - 1) as u64
+            }
+            _ => return Err(None),
+        };
+        self.set_reg(rd, result, datasize);
+        Ok(())
+    }
+
+    fn data_processing_two_source(
+        &mut self,
+        i: u32,
+        datasize: u32,
+    ) -> Result<(), Option<NativeException>> {
+        let rd = bits(i, 4, 0);
+        let operand1 = self.reg(bits(i, 9, 5), datasize);
+        let operand2 = self.reg(bits(i, 20, 16), datasize);
+        if bit(i, 29) {
+            return Err(None);
+        }
+        let result = match bits(i, 15, 10) {
+            0b000010 => operand1.checked_div(operand2).unwrap_or(0),
+            0b000011 => {
+                let dividend = sign_extend(operand1, datasize) as i64;
+                let divisor = sign_extend(operand2, datasize) as i64;
+                if divisor == 0 {
+                    0
+                } else {
+                    dividend.wrapping_div(divisor) as u64
+                }
+            }
+            0b001000 => operand1 << (operand2 % datasize as u64),
+            0b001001 => operand1 >> (operand2 % datasize as u64),
+            0b001010 => {
+                (sign_extend(operand1, datasize) as i64 >> (operand2 % datasize as u64)) as u64
+            }
+            0b001011 => ror(operand1, (operand2 % datasize as u64) as u32, datasize),
+            opcode if opcode & 0b111000 == 0b010000 => {
+                let size = 1 << (opcode & 0b11);
+                let castagnoli = opcode & 0b100 != 0;
+                crc32(operand1 as u32, operand2, size, castagnoli) as u64
+            }
+            _ => return Err(None),
+        };
+        self.set_reg(rd, result, datasize);
+        Ok(())
+    }
+
+    fn data_processing_three_source(
+        &mut self,
+        i: u32,
+        datasize: u32,
+    ) -> Result<(), Option<NativeException>> {
+        let rd = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        let rm = bits(i, 20, 16);
+        let ra = bits(i, 14, 10);
+        let subtract = bit(i, 15);
+        let result = match bits(i, 23, 21) {
+            0b000 => {
+                let product = self.reg(rn, datasize).wrapping_mul(self.reg(rm, datasize));
+                let accumulator = self.reg(ra, datasize);
+                if subtract {
+                    accumulator.wrapping_sub(product)
+                } else {
+                    accumulator.wrapping_add(product)
+                }
+            }
+            0b001 | 0b101 => {
+                let signed = bits(i, 23, 21) == 0b001;
+                let (a, b) = if signed {
+                    (
+                        sign_extend(self.reg(rn, 32), 32),
+                        sign_extend(self.reg(rm, 32), 32),
+                    )
+                } else {
+                    (self.reg(rn, 32), self.reg(rm, 32))
+                };
+                let product = a.wrapping_mul(b);
+                let accumulator = self.reg(ra, 64);
+                if subtract {
+                    accumulator.wrapping_sub(product)
+                } else {
+                    accumulator.wrapping_add(product)
+                }
+            }
+            0b010 => {
+                let product = self.reg(rn, 64) as i64 as i128 * self.reg(rm, 64) as i64 as i128;
+                (product >> 64) as u64
+            }
+            0b110 => {
+                let product = self.reg(rn, 64) as u128 * self.reg(rm, 64) as u128;
+                (product >> 64) as u64
+            }
+            _ => return Err(None),
+        };
+        self.set_reg(rd, result, datasize);
+        Ok(())
+    }
+
+    fn shift_register(&self, rm: u32, shift: u32, amount: u32, datasize: u32) -> u64 {
+        let value = self.reg(rm, datasize);
+        match shift {
+            0b00 => mask(value << amount, datasize),
+            0b01 => value >> amount,
+            0b10 => mask(
+                (sign_extend(value, datasize) as i64 >> amount) as u64,
+                datasize,
+            ),
+            _ => ror(value, amount, datasize),
+        }
+    }
+
+    fn extend_register(&self, rm: u32, option: u32) -> u64 {
+        let value = self.reg(rm, 64);
+        match option {
+            0b000 => value & 0xff,
+            0b001 => value & 0xffff,
+            0b010 => value & 0xffff_ffff,
+            0b011 => value,
+            0b100 => sign_extend(value & 0xff, 8),
+            0b101 => sign_extend(value & 0xffff, 16),
+            0b110 => sign_extend(value & 0xffff_ffff, 32),
+            _ => value,
+        }
+    }
+
+    fn load_store(
+        &mut self,
+        i: u32,
+        pc: u64,
+        memory: &mut Memory,
+    ) -> Result<Step, Option<NativeException>> {
+        let rt = bits(i, 4, 0);
+        let simd = bit(i, 26);
+        if bits(i, 29, 24) == 0b001000 && !simd {
+            self.load_store_exclusive(i, memory)?;
+        } else if bits(i, 29, 27) == 0b011 && bits(i, 25, 24) == 0 {
+            // load register (literal)
+            let address = pc.wrapping_add(sign_extend((bits(i, 23, 5) as u64) << 2, 21));
+            let opc = bits(i, 31, 30);
+            if simd {
+                let size = 4 << opc;
+                if size > 16 {
+                    return Err(None);
+                }
+                let value = read_vector(memory, address, size)?;
+                self.q[rt as usize] = value;
+            } else {
+                match opc {
+                    0b00 => self.set_reg(rt, memory.read_uint(address, 4)?, 64),
+                    0b01 => self.set_reg(rt, memory.read_uint(address, 8)?, 64),
+                    0b10 => self.set_reg(rt, sign_extend(memory.read_uint(address, 4)?, 32), 64),
+                    _ => {}
+                }
+            }
+        } else if bits(i, 29, 27) == 0b101 {
+            self.load_store_pair(i, memory)?;
+        } else if bits(i, 29, 27) == 0b111 {
+            self.load_store_register(i, memory)?;
+        } else if simd && !bit(i, 31) && bits(i, 29, 24) == 0b001100 {
+            self.load_store_multiple(i, memory)?;
+        } else {
+            return Err(None);
+        }
+        Ok(Step::Continue)
+    }
+
+    fn load_store_exclusive(
+        &mut self,
+        i: u32,
+        memory: &mut Memory,
+    ) -> Result<(), Option<NativeException>> {
+        let size = 1usize << bits(i, 31, 30);
+        let datasize = size as u32 * 8;
+        let rt = bits(i, 4, 0);
+        let rt2 = bits(i, 14, 10);
+        let rs = bits(i, 20, 16);
+        let address = self.reg_sp(bits(i, 9, 5), 64);
+        let load = bit(i, 22);
+        let pair = bit(i, 21);
+        let ordered = bit(i, 23);
+        if ordered && pair {
+            // CAS
+            let expected = self.reg(rs, datasize);
+            let old = memory.read_uint(address, size)?;
+            if old == expected {
+                memory.write_uint(address, self.reg(rt, datasize), size)?;
+            }
+            self.set_reg(rs, old, datasize);
+            return Ok(());
+        }
+        if load {
+            if pair {
+                let value1 = memory.read_uint(address, size)?;
+                let value2 = memory.read_uint(address.wrapping_add(size as u64), size)?;
+                self.set_reg(rt, value1, 64);
+                self.set_reg(rt2, value2, 64);
+            } else {
+                let value = memory.read_uint(address, size)?;
+                self.set_reg(rt, value, 64);
+            }
+            if !ordered {
+                self.exclusive = Some(address);
+            }
+        } else {
+            let exclusive = !ordered;
+            if exclusive && self.exclusive != Some(address) {
+                self.set_reg(rs, 1, 32);
+                return Ok(());
+            }
+            memory.write_uint(address, self.reg(rt, datasize), size)?;
+            if pair {
+                memory.write_uint(
+                    address.wrapping_add(size as u64),
+                    self.reg(rt2, datasize),
+                    size,
+                )?;
+            }
+            if exclusive {
+                self.exclusive = None;
+                self.set_reg(rs, 0, 32);
+            }
+        }
+        Ok(())
+    }
+
+    fn load_store_pair(
+        &mut self,
+        i: u32,
+        memory: &mut Memory,
+    ) -> Result<(), Option<NativeException>> {
+        let opc = bits(i, 31, 30);
+        let simd = bit(i, 26);
+        let load = bit(i, 22);
+        let rt = bits(i, 4, 0);
+        let rt2 = bits(i, 14, 10);
+        let rn = bits(i, 9, 5);
+        let (size, signed) = if simd {
+            if opc == 0b11 {
+                return Err(None);
+            }
+            (4u64 << opc, false)
+        } else {
+            match opc {
+                0b00 => (4, false),
+                0b01 if load => (4, true),
+                0b10 => (8, false),
+                _ => return Err(None),
+            }
+        };
+        let offset = sign_extend(bits(i, 21, 15) as u64, 7).wrapping_mul(size);
+        let base = self.reg_sp(rn, 64);
+        let mode = bits(i, 24, 23);
+        let address = if mode == 0b01 {
+            base
+        } else {
+            base.wrapping_add(offset)
+        };
+        if simd {
+            if load {
+                let first = read_vector(memory, address, size as usize)?;
+                let second = read_vector(memory, address.wrapping_add(size), size as usize)?;
+                self.q[rt as usize] = first;
+                self.q[rt2 as usize] = second;
+            } else {
+                write_vector(memory, address, self.q[rt as usize], size as usize)?;
+                write_vector(
+                    memory,
+                    address.wrapping_add(size),
+                    self.q[rt2 as usize],
+                    size as usize,
+                )?;
+            }
+        } else if load {
+            let mut first = memory.read_uint(address, size as usize)?;
+            let mut second = memory.read_uint(address.wrapping_add(size), size as usize)?;
+            if signed {
+                first = sign_extend(first, 32);
+                second = sign_extend(second, 32);
+            }
+            self.set_reg(rt, first, 64);
+            self.set_reg(rt2, second, 64);
+        } else {
+            let datasize = size as u32 * 8;
+            memory.write_uint(address, self.reg(rt, datasize), size as usize)?;
+            memory.write_uint(
+                address.wrapping_add(size),
+                self.reg(rt2, datasize),
+                size as usize,
+            )?;
+        }
+        if mode == 0b01 || mode == 0b11 {
+            self.set_reg_sp(rn, base.wrapping_add(offset), 64);
+        }
+        Ok(())
+    }
+
+    fn load_store_register(
+        &mut self,
+        i: u32,
+        memory: &mut Memory,
+    ) -> Result<(), Option<NativeException>> {
+        let size_bits = bits(i, 31, 30);
+        let opc = bits(i, 23, 22);
+        let simd = bit(i, 26);
+        let rt = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        let scale = if simd && opc & 0b10 != 0 {
+            if size_bits != 0 {
+                return Err(None);
+            }
+            4
+        } else {
+            size_bits
+        };
+        let size = 1usize << scale;
+        let base = self.reg_sp(rn, 64);
+        let mut write_back = None;
+        let address = if bit(i, 24) {
+            base.wrapping_add((bits(i, 21, 10) as u64) << scale)
+        } else if !bit(i, 21) {
+            let offset = sign_extend(bits(i, 20, 12) as u64, 9);
+            match bits(i, 11, 10) {
+                0b00 | 0b10 => base.wrapping_add(offset),
+                0b01 => {
+                    write_back = Some(base.wrapping_add(offset));
+                    base
+                }
+                _ => {
+                    let address = base.wrapping_add(offset);
+                    write_back = Some(address);
+                    address
+                }
+            }
+        } else {
+            match bits(i, 11, 10) {
+                0b10 => {
+                    let option = bits(i, 15, 13);
+                    let amount = if bit(i, 12) { scale } else { 0 };
+                    let offset = self.extend_register(bits(i, 20, 16), option) << amount;
+                    base.wrapping_add(offset)
+                }
+                0b00 if !simd => return self.atomic_memory_operation(i, memory),
+                _ => return Err(None),
+            }
+        };
+        if simd {
+            if opc & 1 == 1 {
+                self.q[rt as usize] = read_vector(memory, address, size)?;
+            } else {
+                write_vector(memory, address, self.q[rt as usize], size)?;
+            }
+        } else {
+            match opc {
+                0b00 => memory.write_uint(address, self.reg(rt, 64), size)?,
+                0b01 => {
+                    let value = memory.read_uint(address, size)?;
+                    self.set_reg(rt, value, 64);
+                }
+                _ => {
+                    if size_bits == 0b11 {
+                        // PRFM
+                    } else if size_bits == 0b10 && opc == 0b11 {
+                        return Err(None);
+                    } else {
+                        let value = sign_extend(memory.read_uint(address, size)?, size as u32 * 8);
+                        let datasize = if opc == 0b10 { 64 } else { 32 };
+                        self.set_reg(rt, value, datasize);
+                    }
+                }
+            }
+        }
+        if let Some(address) = write_back {
+            self.set_reg_sp(rn, address, 64);
+        }
+        Ok(())
+    }
+
+    fn atomic_memory_operation(
+        &mut self,
+        i: u32,
+        memory: &mut Memory,
+    ) -> Result<(), Option<NativeException>> {
+        let size = 1usize << bits(i, 31, 30);
+        let datasize = size as u32 * 8;
+        let rt = bits(i, 4, 0);
+        let rs = bits(i, 20, 16);
+        let address = self.reg_sp(bits(i, 9, 5), 64);
+        let old = memory.read_uint(address, size)?;
+        if bit(i, 15) && bits(i, 14, 12) == 0b100 {
+            // LDAPR
+            self.set_reg(rt, old, 64);
+            return Ok(());
+        }
+        let operand = self.reg(rs, datasize);
+        let signed = |value: u64| sign_extend(value, datasize) as i64;
+        let new = match (bit(i, 15), bits(i, 14, 12)) {
+            (false, 0b000) => old.wrapping_add(operand),
+            (false, 0b001) => old & !operand,
+            (false, 0b010) => old ^ operand,
+            (false, 0b011) => old | operand,
+            (false, 0b100) => {
+                if signed(old) > signed(operand) {
+                    old
+                } else {
+                    operand
+                }
+            }
+            (false, 0b101) => {
+                if signed(old) < signed(operand) {
+                    old
+                } else {
+                    operand
+                }
+            }
+            (false, 0b110) => old.max(operand),
+            (false, 0b111) => old.min(operand),
+            (true, 0b000) => operand,
+            _ => return Err(None),
+        };
+        memory.write_uint(address, new, size)?;
+        self.set_reg(rt, old, 64);
+        Ok(())
+    }
+
+    fn load_store_multiple(
+        &mut self,
+        i: u32,
+        memory: &mut Memory,
+    ) -> Result<(), Option<NativeException>> {
+        // LD1 and ST1 with one to four registers, the interleaving variants are not supported
+        let count = match bits(i, 15, 12) {
+            0b0111 => 1,
+            0b1010 => 2,
+            0b0110 => 3,
+            0b0010 => 4,
+            _ => return Err(None),
+        };
+        let post_index = bit(i, 23);
+        if !post_index && bits(i, 21, 16) != 0 {
+            return Err(None);
+        }
+        let bytes = if bit(i, 30) { 16 } else { 8 };
+        let rt = bits(i, 4, 0);
+        let rn = bits(i, 9, 5);
+        let base = self.reg_sp(rn, 64);
+        for index in 0..count {
+            let register = ((rt + index) % 32) as usize;
+            let address = base.wrapping_add((index * bytes) as u64);
+            if bit(i, 22) {
+                self.q[register] = read_vector(memory, address, bytes as usize)?;
+            } else {
+                write_vector(memory, address, self.q[register], bytes as usize)?;
+            }
+        }
+        if post_index {
+            let rm = bits(i, 20, 16);
+            let increment = if rm == 31 {
+                (count * bytes) as u64
+            } else {
+                self.reg(rm, 64)
+            };
+            self.set_reg_sp(rn, base.wrapping_add(increment), 64);
+        }
+        Ok(())
+    }
+
+    fn simd_data_processing(&mut self, i: u32) -> Result<Step, Option<NativeException>> {
+        let rd = bits(i, 4, 0) as usize;
+        let rn = bits(i, 9, 5) as usize;
+        let rm = bits(i, 20, 16) as usize;
+        let full = bit(i, 30);
+        let width = if full { 128 } else { 64 };
+        let clear_upper = |value: u128| {
+            if full {
+                value
+            } else {
+                value & u64::MAX as u128
+            }
+        };
+        if bits(i, 30, 24) == 0b0011110 && bit(i, 21) && bits(i, 15, 10) == 0 {
+            // FMOV (general)
+            let sf = bit(i, 31);
+            let ftype = bits(i, 23, 22);
+            let rmode = bits(i, 20, 19);
+            let opcode = bits(i, 18, 16);
+            match (sf, ftype, rmode, opcode) {
+                (false, 0b00, 0b00, 0b110) => {
+                    self.set_reg(rd as u32, self.q[rn] as u32 as u64, 64);
+                }
+                (false, 0b00, 0b00, 0b111) => {
+                    self.q[rd] = self.reg(rn as u32, 32) as u128;
+                }
+                (true, 0b01, 0b00, 0b110) => {
+                    self.set_reg(rd as u32, self.q[rn] as u64, 64);
+                }
+                (true, 0b01, 0b00, 0b111) => {
+                    self.q[rd] = self.reg(rn as u32, 64) as u128;
+                }
+                (true, 0b10, 0b01, 0b110) => {
+                    self.set_reg(rd as u32, (self.q[rn] >> 64) as u64, 64);
+                }
+                (true, 0b10, 0b01, 0b111) => {
+                    let low = self.q[rd] & u64::MAX as u128;
+                    self.q[rd] = low | (self.reg(rn as u32, 64) as u128) << 64;
+                }
+                _ => return Err(None),
+            }
+            return Ok(Step::Continue);
+        }
+        if bits(i, 31, 31) != 0 {
+            return Err(None);
+        }
+        if bits(i, 28, 19) == 0b0111100000 && bit(i, 10) {
+            // MOVI, MVNI, ORR, BIC (vector, immediate)
+            let op = bit(i, 29);
+            let cmode = bits(i, 15, 12);
+            let imm8 = (bits(i, 18, 16) << 5 | bits(i, 9, 5)) as u64;
+            let imm64 = expand_simd_immediate(op, cmode, imm8).ok_or(None)?;
+            let imm = (imm64 as u128) << 64 | imm64 as u128;
+            let current = self.q[rd];
+            let result = if cmode < 0b1100 && cmode & 1 == 1 {
+                if op {
+                    current & !imm
+                } else {
+                    current | imm
+                }
+            } else if op && cmode != 0b1110 {
+                !imm
+            } else {
+                imm
+            };
+            self.q[rd] = clear_upper(result);
+            return Ok(Step::Continue);
+        }
+        if bits(i, 28, 21) == 0b01110000 && bit(i, 10) && !bit(i, 15) {
+            // copy operations: DUP, INS, UMOV, SMOV
+            let imm5 = bits(i, 20, 16);
+            if imm5 & 0xf == 0 {
+                return Err(None);
+            }
+            let size = imm5.trailing_zeros();
+            let esize = 8 << size;
+            let index = (imm5 >> (size + 1)) as usize;
+            let imm4 = bits(i, 14, 11);
+            if bit(i, 29) {
+                // INS (element)
+                let source = get_lane(self.q[rn], esize, (imm4 >> size) as usize);
+                self.q[rd] = set_lane(self.q[rd], esize, index, source);
+                return Ok(Step::Continue);
+            }
+            match imm4 {
+                0b0000 => {
+                    let element = get_lane(self.q[rn], esize, index);
+                    self.q[rd] = clear_upper(duplicate(element, esize));
+                }
+                0b0001 => {
+                    let element = mask(self.reg(rn as u32, 64), esize);
+                    self.q[rd] = clear_upper(duplicate(element, esize));
+                }
+                0b0011 => {
+                    let element = mask(self.reg(rn as u32, 64), esize);
+                    self.q[rd] = set_lane(self.q[rd], esize, index, element);
+                }
+                0b0101 => {
+                    let element = sign_extend(get_lane(self.q[rn], esize, index), esize);
+                    self.set_reg(rd as u32, element, if full { 64 } else { 32 });
+                }
+                0b0111 => {
+                    let element = get_lane(self.q[rn], esize, index);
+                    self.set_reg(rd as u32, element, 64);
+                }
+                _ => return Err(None),
+            }
+            return Ok(Step::Continue);
+        }
+        if bits(i, 28, 24) == 0b01110 && bit(i, 21) && bit(i, 10) {
+            // three same
+            let u = bit(i, 29);
+            let size = bits(i, 23, 22);
+            let esize = 8 << size;
+            let a = self.q[rn];
+            let b = self.q[rm];
+            let result = match (bits(i, 15, 11), u) {
+                (0b00011, false) => match size {
+                    0b00 => a & b,
+                    0b01 => a & !b,
+                    0b10 => a | b,
+                    _ => a | !b,
+                },
+                (0b00011, true) => {
+                    let d = self.q[rd];
+                    match size {
+                        0b00 => a ^ b,
+                        0b01 => (d & a) | (!d & b),
+                        0b10 => (d & !b) | (a & b),
+                        _ => (d & b) | (a & !b),
+                    }
+                }
+                (0b10000, sub) => lanewise(a, b, esize, width, |x, y| {
+                    if sub {
+                        x.wrapping_sub(y)
+                    } else {
+                        x.wrapping_add(y)
+                    }
+                }),
+                (0b10001, false) => {
+                    lanewise(
+                        a,
+                        b,
+                        esize,
+                        width,
+                        |x, y| if x & y != 0 { u64::MAX } else { 0 },
+                    )
+                }
+                (0b10001, true) => {
+                    lanewise(a, b, esize, width, |x, y| if x == y { u64::MAX } else { 0 })
+                }
+                (0b00110, unsigned) | (0b00111, unsigned) => {
+                    let or_equal = bit(i, 11);
+                    lanewise(a, b, esize, width, |x, y| {
+                        let holds = if unsigned {
+                            if or_equal {
+                                x >= y
+                            } else {
+                                x > y
+                            }
+                        } else {
+                            let x = sign_extend(x, esize) as i64;
+                            let y = sign_extend(y, esize) as i64;
+                            if or_equal {
+                                x >= y
+                            } else {
+                                x > y
+                            }
+                        };
+                        if holds {
+                            u64::MAX
+                        } else {
+                            0
+                        }
+                    })
+                }
+                (0b10011, false) => lanewise(a, b, esize, width, |x, y| x.wrapping_mul(y)),
+                (0b01100, unsigned) | (0b01101, unsigned) => {
+                    let minimum = bit(i, 11);
+                    lanewise(a, b, esize, width, |x, y| {
+                        let x_larger = if unsigned {
+                            x > y
+                        } else {
+                            (sign_extend(x, esize) as i64) > sign_extend(y, esize) as i64
+                        };
+                        if x_larger != minimum {
+                            x
+                        } else {
+                            y
+                        }
+                    })
+                }
+                _ => return Err(None),
+            };
+            self.q[rd] = clear_upper(result);
+            return Ok(Step::Continue);
+        }
+        if bits(i, 28, 24) == 0b01110 && bits(i, 21, 17) == 0b10000 && bits(i, 11, 10) == 0b10 {
+            // two register miscellaneous
+            let u = bit(i, 29);
+            let size = bits(i, 23, 22);
+            let esize = 8 << size;
+            let a = self.q[rn];
+            let result = match (bits(i, 16, 12), u) {
+                (0b00101, true) if size == 0 => !a,
+                (0b00101, false) if size == 0 => {
+                    lanewise(a, 0, 8, width, |x, _| x.count_ones() as u64)
+                }
+                (0b01001, false) => {
+                    lanewise(a, 0, esize, width, |x, _| if x == 0 { u64::MAX } else { 0 })
+                }
+                (0b01011, true) => lanewise(a, 0, esize, width, |x, _| x.wrapping_neg()),
+                (0b00000, false) => {
+                    // REV64
+                    let mut result = 0u128;
+                    let lanes = 64 / esize;
+                    for lane in 0..(width / esize) as usize {
+                        let group = lane / lanes as usize;
+                        let position = lane % lanes as usize;
+                        let source = group * lanes as usize + (lanes as usize - 1 - position);
+                        result = set_lane(result, esize, lane, get_lane(a, esize, source));
+                    }
+                    result
+                }
+                (0b10010, false) => {
+                    // XTN, XTN2
+                    let narrow = esize;
+                    let mut result = if full {
+                        self.q[rd] & u64::MAX as u128
+                    } else {
+                        0
+                    };
+                    for lane in 0..(64 / narrow) as usize {
+                        let element = get_lane(a, narrow * 2, lane);
+                        let destination = if full {
+                            lane + (64 / narrow) as usize
+                        } else {
+                            lane
+                        };
+                        result = set_lane(result, narrow, destination, element);
+                    }
+                    self.q[rd] = result;
+                    return Ok(Step::Continue);
+                }
+                _ => return Err(None),
+            };
+            self.q[rd] = clear_upper(result);
+            return Ok(Step::Continue);
+        }
+        if bits(i, 28, 23) == 0b011110 && bits(i, 22, 19) != 0 && bit(i, 10) {
+            // shift by immediate
+            let u = bit(i, 29);
+            let immh = bits(i, 22, 19);
+            let immb = bits(i, 18, 16);
+            let esize = 8 << (31 - immh.leading_zeros());
+            let shift_value = immh << 3 | immb;
+            let a = self.q[rn];
+            let result = match (bits(i, 15, 11), u) {
+                (0b00000, unsigned) => {
+                    let shift = esize * 2 - shift_value;
+                    lanewise(a, 0, esize, width, |x, _| {
+                        if unsigned {
+                            if shift >= esize {
+                                0
+                            } else {
+                                x >> shift
+                            }
+                        } else {
+                            (sign_extend(x, esize) as i64 >> shift.min(63)) as u64
+                        }
+                    })
+                }
+                (0b01010, false) => {
+                    let shift = shift_value - esize;
+                    lanewise(a, 0, esize, width, |x, _| x << shift)
+                }
+                (0b10100, unsigned) => {
+                    // SSHLL, USHLL (including SXTL, UXTL)
+                    let shift = shift_value - esize;
+                    let source = if full { a >> 64 } else { a };
+                    let mut result = 0u128;
+                    for lane in 0..(64 / esize) as usize {
+                        let mut element = get_lane(source, esize, lane);
+                        if !unsigned {
+                            element = mask(sign_extend(element, esize), esize * 2);
+                        }
+                        result = set_lane(result, esize * 2, lane, element << shift);
+                    }
+                    self.q[rd] = result;
+                    return Ok(Step::Continue);
+                }
+                _ => return Err(None),
+            };
+            self.q[rd] = clear_upper(result);
+            return Ok(Step::Continue);
+        }
+        if bits(i, 29, 21) == 0b101110000 && !bit(i, 15) && !bit(i, 10) {
+            // EXT
+            let position = bits(i, 14, 11);
+            let bytes = width / 8;
+            let mut concatenated = [0u8; 32];
+            concatenated[..16].copy_from_slice(&self.q[rn].to_le_bytes());
+            concatenated[bytes as usize..bytes as usize + 16]
+                .copy_from_slice(&self.q[rm].to_le_bytes());
+            let mut result = [0u8; 16];
+            result[..bytes as usize]
+                .copy_from_slice(&concatenated[position as usize..(position + bytes) as usize]);
+            self.q[rd] = u128::from_le_bytes(result);
+            return Ok(Step::Continue);
+        }
+        if bits(i, 29, 21) == 0b001110000 && !bit(i, 15) && bits(i, 11, 10) == 0 {
+            // TBL, TBX
+            let count = bits(i, 14, 13) as usize + 1;
+            let extension = bit(i, 12);
+            let mut table = vec![];
+            for index in 0..count {
+                table.extend_from_slice(&self.q[(rn + index) % 32].to_le_bytes());
+            }
+            let indices = self.q[rm].to_le_bytes();
+            let mut result = self.q[rd].to_le_bytes();
+            for lane in 0..(width / 8) as usize {
+                let index = indices[lane] as usize;
+                if index < table.len() {
+                    result[lane] = table[index];
+                } else if !extension {
+                    result[lane] = 0;
+                }
+            }
+            self.q[rd] = clear_upper(u128::from_le_bytes(result));
+            return Ok(Step::Continue);
+        }
+        if bits(i, 28, 24) == 0b01110 && bits(i, 21, 17) == 0b11000 && bits(i, 11, 10) == 0b10 {
+            // across lanes: ADDV, UMAXV, UMINV
+            let size = bits(i, 23, 22);
+            let esize = 8 << size;
+            let lanes = (width / esize) as usize;
+            let values = (0..lanes).map(|lane| get_lane(self.q[rn], esize, lane));
+            let result = match (bits(i, 16, 12), bit(i, 29)) {
+                (0b11011, false) => values.fold(0u64, |a, b| a.wrapping_add(b)),
+                (0b01010, true) => values.max().unwrap_or(0),
+                (0b11010, true) => values.min().unwrap_or(0),
+                _ => return Err(None),
+            };
+            self.q[rd] = mask(result, esize) as u128;
+            return Ok(Step::Continue);
+        }
+        Err(None)
+    }
+}
+
+fn read_vector(memory: &Memory, address: u64, size: usize) -> Result<u128, NativeException> {
+    let mut buffer = [0u8; 16];
+    memory.read(address, &mut buffer[..size])?;
+    Ok(u128::from_le_bytes(buffer))
+}
+
+fn write_vector(
+    memory: &mut Memory,
+    address: u64,
+    value: u128,
+    size: usize,
+) -> Result<(), NativeException> {
+    memory.write(address, &value.to_le_bytes()[..size])
+}
+
+fn get_lane(value: u128, esize: u32, index: usize) -> u64 {
+    ((value >> (esize as usize * index)) as u64) & ones(esize)
+}
+
+fn set_lane(value: u128, esize: u32, index: usize, element: u64) -> u128 {
+    let shift = esize as usize * index;
+    let lane_mask = (ones(esize) as u128) << shift;
+    (value & !lane_mask) | (((element & ones(esize)) as u128) << shift)
+}
+
+fn duplicate(element: u64, esize: u32) -> u128 {
+    let mut result = 0;
+    for lane in 0..(128 / esize) as usize {
+        result = set_lane(result, esize, lane, element);
+    }
+    result
+}
+
+fn lanewise<F: Fn(u64, u64) -> u64>(
+    a: u128,
+    b: u128,
+    esize: u32,
+    width: u32,
+    operation: F,
+) -> u128 {
+    let mut result = 0;
+    for lane in 0..(width / esize) as usize {
+        let value = operation(get_lane(a, esize, lane), get_lane(b, esize, lane));
+        result = set_lane(result, esize, lane, value);
+    }
+    result
+}
+
+/// AdvSIMDExpandImm
+fn expand_simd_immediate(op: bool, cmode: u32, imm8: u64) -> Option<u64> {
+    let imm = match cmode >> 1 {
+        0b000 => replicate(imm8, 32),
+        0b001 => replicate(imm8 << 8, 32),
+        0b010 => replicate(imm8 << 16, 32),
+        0b011 => replicate(imm8 << 24, 32),
+        0b100 => replicate(imm8, 16),
+        0b101 => replicate(imm8 << 8, 16),
+        0b110 => {
+            if cmode & 1 == 0 {
+                replicate(imm8 << 8 | 0xff, 32)
+            } else {
+                replicate(imm8 << 16 | 0xffff, 32)
+            }
+        }
+        _ => {
+            if cmode & 1 == 0 {
+                if op {
+                    let mut result = 0;
+                    for index in 0..8 {
+                        if (imm8 >> index) & 1 == 1 {
+                            result |= 0xff << (index * 8);
+                        }
+                    }
+                    result
+                } else {
+                    replicate(imm8, 8)
+                }
+            } else {
+                // FMOV (vector, immediate)
+                if op {
+                    return None;
+                }
+                let sign = (imm8 >> 7) & 1;
+                let b6 = (imm8 >> 6) & 1;
+                let replicated = if b6 == 1 { 0x1f } else { 0 };
+                let exponent = ((b6 ^ 1) << 7) | (replicated << 2) | ((imm8 >> 4) & 3);
+                let single = sign << 31 | exponent << 23 | (imm8 & 0xf) << 19;
+                replicate(single, 32)
+            }
+        }
+    };
+    Some(imm)
+}
+
+fn crc32(mut crc: u32, value: u64, size: usize, castagnoli: bool) -> u32 {
+    let polynomial = if castagnoli { 0x82f6_3b78 } else { 0xedb8_8320 };
+    for byte in value.to_le_bytes().iter().take(size) {
+        crc ^= *byte as u32;
+        for _ in 0..8 {
+            crc = if crc & 1 == 1 {
+                (crc >> 1) ^ polynomial
+            } else {
+                crc >> 1
+            };
+        }
+    }
+    crc
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Execute a single instruction with the given general purpose registers
+    fn execute(instruction: u32, registers: &[(usize, u64)]) -> Result<Cpu, NativeException> {
+        execute_at(&mut Memory::new(), 0x1000, instruction, registers)
+    }
+
+    /// Execute a single instruction placed at `pc` of `memory`
+    fn execute_at(
+        memory: &mut Memory,
+        pc: u64,
+        instruction: u32,
+        registers: &[(usize, u64)],
+    ) -> Result<Cpu, NativeException> {
+        let mut cpu = Cpu::new();
+        memory.map(pc, 4);
+        memory.write(pc, &instruction.to_le_bytes())?;
+        cpu.pc = pc;
+        for (register, value) in registers {
+            cpu.x[*register] = *value;
+        }
+        cpu.step(memory)?;
+        Ok(cpu)
+    }
+
+    fn is_undefined(result: Result<Cpu, NativeException>) -> bool {
+        matches!(
+            result,
+            Err(NativeException::UnsupportedInstruction(0x1000, _))
+        )
+    }
+
+    #[test]
+    fn executes_bitfield_moves() {
+        // lsr w0, w1, #4
+        let cpu = execute(0x5304_7c20, &[(1, 0xf0)]).unwrap();
+        assert_eq!(cpu.x[0], 0xf);
+        // sxtb x0, w1
+        let cpu = execute(0x9340_1c20, &[(1, 0x80)]).unwrap();
+        assert_eq!(cpu.x[0], 0xffff_ffff_ffff_ff80);
+        // extr w0, w1, w2, #8
+        let cpu = execute(0x1382_2020, &[(1, 0x1122_3344), (2, 0xaabb_ccdd)]).unwrap();
+        assert_eq!(cpu.x[0], 0x44aa_bbcc);
+    }
+
+    #[test]
+    fn reserved_bitfield_encodings_are_undefined() {
+        // ubfm w0, w1 with immr = 40
+        assert!(is_undefined(execute(0x5328_0020, &[])));
+        // ubfm w0, w1 with imms = 40
+        assert!(is_undefined(execute(0x5300_a020, &[])));
+        // sbfm and bfm with N set in the 32 bit variant
+        assert!(is_undefined(execute(0x1341_0020, &[])));
+        assert!(is_undefined(execute(0x3341_0020, &[])));
+        // ubfm x0, x1 without N
+        assert!(is_undefined(execute(0xd301_0020, &[])));
+    }
+
+    #[test]
+    fn reserved_extract_encodings_are_undefined() {
+        // extr w0, w1, w2 with lsb = 40
+        assert!(is_undefined(execute(0x1382_a020, &[])));
+        // extr x0, x1, x2 without N
+        assert!(is_undefined(execute(0x9382_2020, &[])));
+    }
+
+    #[test]
+    fn executes_arithmetic() {
+        // movz x0, #0x1234, lsl #16
+        let cpu = execute(0xd2a2_4680, &[]).unwrap();
+        assert_eq!(cpu.x[0], 0x1234_0000);
+        // add x0, x1, #1
+        let cpu = execute(0x9100_0420, &[(1, u64::MAX)]).unwrap();
+        assert_eq!(cpu.x[0], 0);
+        // subs x0, x1, x2
+        let cpu = execute(0xeb02_0020, &[(1, 3), (2, 5)]).unwrap();
+        assert_eq!(cpu.x[0], (-2i64) as u64);
+        assert!(cpu.n && !cpu.z && !cpu.c && !cpu.v);
+        let cpu = execute(0xeb02_0020, &[(1, 5), (2, 5)]).unwrap();
+        assert!(cpu.z && cpu.c);
+    }
+
+    #[test]
+    fn executes_loads_and_stores() {
+        let mut memory = Memory::new();
+        memory.map(0x2000, 0x10);
+        memory.write_u64(0x2008, 0x1122_3344_5566_7788).unwrap();
+        // ldr x0, [x1, #8]
+        let cpu = execute_at(&mut memory, 0x1000, 0xf940_0420, &[(1, 0x2000)]).unwrap();
+        assert_eq!(cpu.x[0], 0x1122_3344_5566_7788);
+        // str w2, [x1]
+        execute_at(
+            &mut memory,
+            0x1000,
+            0xb900_0022,
+            &[(1, 0x2000), (2, 0xaabb_ccdd)],
+        )
+        .unwrap();
+        assert_eq!(memory.read_u64(0x2000).unwrap(), 0xaabb_ccdd);
+        // ldp x0, x1, [x2]
+        let cpu = execute_at(&mut memory, 0x1000, 0xa940_0440, &[(2, 0x2000)]).unwrap();
+        assert_eq!(cpu.x[0], 0xaabb_ccdd);
+        assert_eq!(cpu.x[1], 0x1122_3344_5566_7788);
+        // ldr x0, [x1] from an unmapped address
+        assert!(matches!(
+            execute_at(&mut memory, 0x1000, 0xf940_0020, &[(1, 0x8000)]),
+            Err(NativeException::UnmappedMemory(0x8000))
+        ));
+    }
+
+    #[test]
+    fn pair_accesses_wrap_at_the_end_of_the_address_space() {
+        let mut memory = Memory::new();
+        memory.map(u64::MAX - 7, 8);
+        // ldp x0, x1, [x2]
+        assert!(matches!(
+            execute_at(&mut memory, 0x1000, 0xa940_0440, &[(2, u64::MAX - 7)]),
+            Err(NativeException::UnmappedMemory(0))
+        ));
+        // stp x0, x1, [x2]
+        assert!(matches!(
+            execute_at(&mut memory, 0x1000, 0xa900_0440, &[(2, u64::MAX - 7)]),
+            Err(NativeException::UnmappedMemory(0))
+        ));
+    }
+
+    #[test]
+    fn executes_branches() {
+        // bl #8
+        let cpu = execute(0x9400_0002, &[]).unwrap();
+        assert_eq!(cpu.pc, 0x1008);
+        assert_eq!(cpu.x[30], 0x1004);
+        // cbz x0, #8
+        assert_eq!(execute(0xb400_0040, &[(0, 0)]).unwrap().pc, 0x1008);
+        assert_eq!(execute(0xb400_0040, &[(0, 1)]).unwrap().pc, 0x1004);
+        // br x1
+        assert_eq!(execute(0xd61f_0020, &[(1, 0x4000)]).unwrap().pc, 0x4000);
+        // bl #-4 in the last word of the address space
+        let cpu = execute_at(&mut Memory::new(), u64::MAX - 3, 0x97ff_ffff, &[]).unwrap();
+        assert_eq!(cpu.pc, u64::MAX - 7);
+        assert_eq!(cpu.x[30], 0);
+    }
+
+    #[test]
+    fn unmapped_program_counters_fail() {
+        let mut cpu = Cpu::new();
+        cpu.pc = 0x5000;
+        assert!(matches!(
+            cpu.step(&mut Memory::new()),
+            Err(NativeException::InvalidProgramCounter(0x5000))
+        ));
+        // brk #1
+        let mut memory = Memory::new();
+        memory.map(0x1000, 4);
+        memory.write_uint(0x1000, 0xd420_0020, 4).unwrap();
+        cpu.pc = 0x1000;
+        assert!(matches!(cpu.step(&mut memory), Ok(Step::Trap(1))));
+    }
+}
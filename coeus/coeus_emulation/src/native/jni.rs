@@ -0,0 +1,891 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The fake `JNIEnv` and `JavaVM`. Both function tables point into the hook range, every call is served
+//! here against the heap of the attached [`VM`]. References are plain handles: local and global references
+//! are the same and never released.
+
+use std::collections::HashMap;
+
+use coeus_models::models::demangle_jni_name;
+
+use crate::vm::{Register, VM};
+
+use super::{NativeEmulator, NativeException, VarArgs, JAVA_VM_HOOKS, JNI_BASE, JNI_ENV_HOOKS};
+
+const JNI_ENV: u64 = JNI_BASE;
+const JNI_ENV_TABLE: u64 = JNI_BASE + 0x100;
+const JAVA_VM: u64 = JNI_BASE + 0x1000;
+const JAVA_VM_TABLE: u64 = JNI_BASE + 0x1100;
+const JNI_ENV_FUNCTIONS: u64 = 233;
+const JAVA_VM_FUNCTIONS: u64 = 8;
+const JNI_VERSION_1_6: u64 = 0x0001_0006;
+const JNI_ABORT: u64 = 2;
+
+/// Handles of objects on the VM heap are the heap address in this range
+const OBJECT_HANDLES: u64 = 0x10_0000_0000;
+const CLASS_HANDLES: u64 = 0x20_0000_0000;
+const METHOD_IDS: u64 = 0x30_0000_0000;
+const FIELD_IDS: u64 = 0x40_0000_0000;
+const HANDLE_MASK: u64 = 0xffff_ffff;
+
+/// Element types in the order of the typed JNI function families (`Call<Type>Method`, `Get<Type>Field`, ...)
+const TYPES: &[u8] = b"LZBCSIJFDV";
+/// Primitive array element types in the order of `New<Type>Array` and friends
+const ARRAY_TYPES: &[u8] = b"ZBCSIJFD";
+
+/// A native method bound with `RegisterNatives`
+#[derive(Clone, Debug)]
+pub struct RegisteredNative {
+    pub class_name: String,
+    pub name: String,
+    pub signature: String,
+    pub address: u64,
+}
+
+#[derive(Clone)]
+struct MemberId {
+    class_name: String,
+    name: String,
+    signature: String,
+    is_static: bool,
+}
+
+#[derive(Clone, Default)]
+pub(super) struct JniState {
+    classes: Vec<String>,
+    methods: Vec<MemberId>,
+    fields: Vec<MemberId>,
+    /// Types of handed out objects, the VM heap does not know the type of arrays
+    object_types: HashMap<u32, String>,
+    /// Elements of arrays created with `NewObjectArray`, the VM only has primitive arrays
+    object_arrays: HashMap<u32, Vec<u64>>,
+    /// Buffers handed out by `Get<Type>ArrayElements`, with the array and element type
+    pinned: HashMap<u64, (u32, u8)>,
+    registered: Vec<RegisteredNative>,
+    pending_exception: Option<String>,
+}
+
+impl NativeEmulator {
+    /// Address of the `JNIEnv` passed to native methods
+    pub fn jni_env(&self) -> u64 {
+        JNI_ENV
+    }
+
+    /// Address of the `JavaVM` passed to `JNI_OnLoad`
+    pub fn java_vm(&self) -> u64 {
+        JAVA_VM
+    }
+
+    /// Methods bound with `RegisterNatives` so far
+    pub fn registered_natives(&self) -> &[RegisteredNative] {
+        &self.jni.registered
+    }
+
+    /// The exception thrown by native code (with `Throw` or `ThrowNew`) and not yet cleared
+    pub fn pending_exception(&self) -> Option<&str> {
+        self.jni.pending_exception.as_deref()
+    }
+
+    pub(crate) fn take_pending_exception(&mut self) -> Option<String> {
+        self.jni.pending_exception.take()
+    }
+
+    /// The implementation of a native method, either registered with `RegisterNatives` or exported
+    /// following the `Java_` naming scheme
+    pub fn find_native(&self, class_name: &str, name: &str, signature: &str) -> Option<u64> {
+        if let Some(registered) = self
+            .jni
+            .registered
+            .iter()
+            .rev()
+            .find(|r| r.class_name == class_name && r.name == name && r.signature == signature)
+        {
+            return Some(registered.address);
+        }
+        let arguments = signature
+            .find(')')
+            .map(|end| &signature[..=end])
+            .unwrap_or(signature);
+        self.libraries
+            .iter()
+            .flat_map(|library| library.exports.iter())
+            .filter(|(symbol, _)| symbol.starts_with("Java_"))
+            .filter_map(|(symbol, address)| Some((demangle_jni_name(symbol)?, *address)))
+            .filter(|(jni_name, _)| {
+                jni_name.class_name == class_name && jni_name.method_name == name
+            })
+            // the overloaded form wins over the short one
+            .max_by_key(|(jni_name, _)| jni_name.arguments.as_deref() == Some(arguments))
+            .filter(|(jni_name, _)| {
+                jni_name.arguments.is_none() || jni_name.arguments.as_deref() == Some(arguments)
+            })
+            .map(|(_, address)| address)
+    }
+
+    pub(super) fn setup_jni(&mut self) {
+        self.memory.map(JNI_BASE, 0x2000);
+        self.memory.map(JNI_ENV_HOOKS, JNI_ENV_FUNCTIONS * 4);
+        self.memory.map(JAVA_VM_HOOKS, JAVA_VM_FUNCTIONS * 4);
+        let _ = self.memory.write_u64(JNI_ENV, JNI_ENV_TABLE);
+        let _ = self.memory.write_u64(JAVA_VM, JAVA_VM_TABLE);
+        // the first entries of both tables are reserved and stay NULL
+        for index in 4..JNI_ENV_FUNCTIONS {
+            let hook = JNI_ENV_HOOKS + index * 4;
+            let _ = self.memory.write_u64(JNI_ENV_TABLE + index * 8, hook);
+            let _ = self.memory.write_uint(hook, 0xd65f_03c0, 4);
+        }
+        for index in 3..JAVA_VM_FUNCTIONS {
+            let hook = JAVA_VM_HOOKS + index * 4;
+            let _ = self.memory.write_u64(JAVA_VM_TABLE + index * 8, hook);
+            let _ = self.memory.write_uint(hook, 0xd65f_03c0, 4);
+        }
+    }
+
+    /// Call a native method with Java arguments (`this` first for instance methods) and convert the result
+    pub(crate) fn call_native_method(
+        &mut self,
+        vm: &mut VM,
+        address: u64,
+        class_name: &str,
+        signature: &str,
+        is_static: bool,
+        arguments: &[Register],
+    ) -> Result<Register, NativeException> {
+        let (parameters, return_type) = parse_signature(signature)
+            .ok_or_else(|| NativeException::Jni(format!("invalid signature {}", signature)))?;
+        let mut arguments = arguments.iter();
+        let receiver = if is_static {
+            self.class_handle(class_name)
+        } else {
+            let this = arguments.next().cloned().unwrap_or(Register::Null);
+            self.handle_for(&this)
+        };
+        let mut integers = vec![JNI_ENV, receiver];
+        let mut floats = vec![];
+        for parameter in &parameters {
+            let register = arguments.next().cloned().unwrap_or(Register::Empty);
+            let value = self.handle_for(&register);
+            match parameter.as_bytes()[0] {
+                b'F' => floats.push(value & 0xffff_ffff),
+                b'D' => floats.push(value),
+                _ => integers.push(value),
+            }
+            if is_wide(parameter) {
+                arguments.next();
+            }
+        }
+        if floats.len() > 8 {
+            return Err(NativeException::Jni(
+                "more than eight floating point arguments".to_string(),
+            ));
+        }
+        let (integer, vector) = self.execute(Some(vm), address, &integers, &floats)?;
+        let raw = match return_type.as_bytes()[0] {
+            b'F' | b'D' => vector as u64,
+            _ => integer,
+        };
+        Ok(self.register_for(vm, &return_type, raw))
+    }
+
+    pub(super) fn call_java_vm_function(&mut self, index: usize) -> Result<(), NativeException> {
+        let result = match index {
+            // DestroyJavaVM, DetachCurrentThread
+            3 | 5 => 0,
+            // AttachCurrentThread, GetEnv, AttachCurrentThreadAsDaemon
+            4 | 6 | 7 => {
+                self.memory.write_u64(self.argument(1), JNI_ENV)?;
+                0
+            }
+            _ => {
+                return Err(NativeException::Jni(format!(
+                    "invalid JavaVM function {}",
+                    index
+                )))
+            }
+        };
+        self.set_return(result);
+        Ok(())
+    }
+
+    pub(super) fn call_jni_function(
+        &mut self,
+        index: usize,
+        vm: Option<&mut VM>,
+    ) -> Result<(), NativeException> {
+        let a1 = self.argument(1);
+        let a2 = self.argument(2);
+        let a3 = self.argument(3);
+        let result = match index {
+            4 => JNI_VERSION_1_6,
+            5 => {
+                self.throw("Ljava/lang/UnsupportedOperationException;", "DefineClass");
+                0
+            }
+            6 => {
+                let name = self.c_string_argument(1)?;
+                let descriptor = if name.starts_with('[') {
+                    name
+                } else {
+                    format!("L{};", name.trim_start_matches('L').trim_end_matches(';'))
+                };
+                self.class_handle(&descriptor)
+            }
+            // reflection is not supported
+            7 | 8 | 9 | 12 => 0,
+            10 => {
+                let vm = require(vm)?;
+                let class_name = self.class_name(a1)?;
+                vm.jni_superclass(&class_name)
+                    .map(|super_class| self.class_handle(&super_class))
+                    .unwrap_or(0)
+            }
+            11 => {
+                let vm = require(vm)?;
+                let sub_class = self.class_name(a1)?;
+                let super_class = self.class_name(a2)?;
+                vm.jni_is_subclass(&sub_class, &super_class) as u64
+            }
+            13 => {
+                let exception = self.object_type(vm.as_deref(), a1);
+                self.throw(&exception, "");
+                0
+            }
+            14 => {
+                let class_name = self.class_name(a1)?;
+                let message = self.c_string_argument(2)?;
+                self.throw(&class_name, &message);
+                0
+            }
+            15 => match self.jni.pending_exception.clone() {
+                Some(exception) => {
+                    let class_name = exception.split(':').next().unwrap_or("").to_string();
+                    self.class_handle(&class_name)
+                }
+                None => 0,
+            },
+            16 => {
+                if let Some(exception) = self.jni.pending_exception.clone() {
+                    self.log.push(format!("ExceptionDescribe: {}", exception));
+                }
+                0
+            }
+            17 => {
+                self.jni.pending_exception = None;
+                0
+            }
+            18 => {
+                let message = self.c_string_argument(1)?;
+                return Err(NativeException::Abort(format!("FatalError: {}", message)));
+            }
+            // PushLocalFrame, DeleteGlobalRef, DeleteLocalRef, EnsureLocalCapacity
+            19 | 22 | 23 | 26 => 0,
+            // PopLocalFrame, NewGlobalRef, NewLocalRef
+            20 | 21 | 25 => a1,
+            24 => (a1 == a2) as u64,
+            27 => {
+                let vm = require(vm)?;
+                let class_name = self.class_name(a1)?;
+                let object = vm.jni_alloc_object(&class_name).map_err(vm_error)?;
+                self.handle_for(&object)
+            }
+            28..=30 => {
+                let vm = require(vm)?;
+                let class_name = self.class_name(a1)?;
+                let method = self.member(&self.jni.methods, METHOD_IDS, a2)?;
+                let object = vm.jni_alloc_object(&class_name).map_err(vm_error)?;
+                let mut arguments = vec![object.clone()];
+                arguments.extend(self.call_arguments(vm, &method.signature, index - 28, 3)?);
+                if let Err(e) =
+                    vm.call_method(&class_name, "<init>", &method.signature, arguments, false)
+                {
+                    self.throw_vm_error(&class_name, &method.name, e);
+                    0
+                } else {
+                    self.handle_for(&object)
+                }
+            }
+            31 => {
+                let class_name = self.object_type(vm.as_deref(), a1);
+                self.class_handle(&class_name)
+            }
+            32 => {
+                if a1 == 0 {
+                    1
+                } else {
+                    let object_type = self.object_type(vm.as_deref(), a1);
+                    let class_name = self.class_name(a2)?;
+                    match vm {
+                        Some(vm) => vm.jni_is_subclass(&object_type, &class_name) as u64,
+                        None => (object_type == class_name) as u64,
+                    }
+                }
+            }
+            33 | 113 => {
+                let class_name = self.class_name(a1)?;
+                let name = self.c_string_argument(2)?;
+                let signature = self.c_string_argument(3)?;
+                self.jni.methods.push(MemberId {
+                    class_name,
+                    name,
+                    signature,
+                    is_static: index == 113,
+                });
+                METHOD_IDS + self.jni.methods.len() as u64 - 1
+            }
+            34..=63 | 64..=93 | 114..=143 => {
+                let vm = require(vm)?;
+                let (first, receiver, method_id, fixed) = match index {
+                    34..=63 => (34, a1, a2, 3),
+                    64..=93 => (64, a1, a3, 4),
+                    _ => (114, 0, a2, 3),
+                };
+                let method = self.member(&self.jni.methods, METHOD_IDS, method_id)?;
+                let mut arguments = vec![];
+                if !method.is_static {
+                    arguments.push(self.register_for(vm, "Ljava/lang/Object;", receiver));
+                }
+                arguments.extend(self.call_arguments(
+                    vm,
+                    &method.signature,
+                    (index - first) % 3,
+                    fixed,
+                )?);
+                let return_type = TYPES[(index - first) / 3];
+                match vm.call_method(
+                    &method.class_name,
+                    &method.name,
+                    &method.signature,
+                    arguments,
+                    method.is_static,
+                ) {
+                    Ok(result) => {
+                        let value = self.handle_for(&result);
+                        if return_type == b'F' || return_type == b'D' {
+                            self.cpu.q[0] = value as u128;
+                        }
+                        value
+                    }
+                    Err(e) => {
+                        self.throw_vm_error(&method.class_name, &method.name, e);
+                        0
+                    }
+                }
+            }
+            94 | 144 => {
+                let class_name = self.class_name(a1)?;
+                let name = self.c_string_argument(2)?;
+                let signature = self.c_string_argument(3)?;
+                self.jni.fields.push(MemberId {
+                    class_name,
+                    name,
+                    signature,
+                    is_static: index == 144,
+                });
+                FIELD_IDS + self.jni.fields.len() as u64 - 1
+            }
+            95..=103 | 145..=153 => {
+                let vm = require(vm)?;
+                let field = self.member(&self.jni.fields, FIELD_IDS, a2)?;
+                let object = if index < 145 { Some(a1 as u32) } else { None };
+                let value =
+                    vm.jni_get_field(object, &field.class_name, &field.name, &field.signature);
+                let value = self.handle_for(&value);
+                let first = if index < 145 { 95 } else { 145 };
+                if matches!(TYPES[index - first], b'F' | b'D') {
+                    self.cpu.q[0] = value as u128;
+                }
+                value
+            }
+            104..=112 | 154..=162 => {
+                let vm = require(vm)?;
+                let field = self.member(&self.jni.fields, FIELD_IDS, a2)?;
+                let first = if index < 154 { 104 } else { 154 };
+                let raw = if matches!(TYPES[index - first], b'F' | b'D') {
+                    self.cpu.q[0] as u64
+                } else {
+                    a3
+                };
+                let value = self.register_for(vm, &field.signature, raw);
+                let object = if index < 154 { Some(a1 as u32) } else { None };
+                vm.jni_set_field(object, &field.class_name, &field.name, value)
+                    .map_err(vm_error)?;
+                0
+            }
+            163 => {
+                let vm = require(vm)?;
+                let data = self.memory.read_bytes(a1, a2 as usize * 2)?;
+                let units: Vec<u16> = data
+                    .chunks(2)
+                    .map(|c| u16::from_le_bytes([c[0], c[1]]))
+                    .collect();
+                let string = vm
+                    .jni_new_string(String::from_utf16_lossy(&units))
+                    .map_err(vm_error)?;
+                self.handle_for(&string)
+            }
+            164 => self.string(vm, a1)?.encode_utf16().count() as u64,
+            165 | 224 => {
+                let units: Vec<u8> = self
+                    .string(vm, a1)?
+                    .encode_utf16()
+                    .chain(std::iter::once(0))
+                    .flat_map(|u| u.to_le_bytes())
+                    .collect();
+                let address = self.malloc(units.len() as u64)?;
+                self.memory.write(address, &units)?;
+                self.set_is_copy(a2)?;
+                address
+            }
+            166 | 170 | 225 => {
+                self.free(a2);
+                0
+            }
+            167 => {
+                let vm = require(vm)?;
+                let string = self.c_string_argument(1)?;
+                let string = vm.jni_new_string(string).map_err(vm_error)?;
+                self.handle_for(&string)
+            }
+            168 => self.string(vm, a1)?.len() as u64,
+            169 => {
+                let string = self.string(vm, a1)?;
+                self.set_is_copy(a2)?;
+                self.alloc_c_string(string.as_bytes())?
+            }
+            171 => {
+                let address = a1 as u32;
+                match self.jni.object_arrays.get(&address) {
+                    Some(elements) => elements.len() as u64,
+                    None => require(vm)?
+                        .jni_array(address)
+                        .map(|array| array.len() as u64)
+                        .unwrap_or(0),
+                }
+            }
+            172 => {
+                let vm = require(vm)?;
+                let element_type = self.class_name(a2)?;
+                let array = vm
+                    .jni_new_array(&format!("[{}", element_type), vec![0; a1 as usize])
+                    .map_err(vm_error)?;
+                let handle = self.handle_for(&array);
+                self.jni
+                    .object_arrays
+                    .insert(handle as u32, vec![a3; a1 as usize]);
+                handle
+            }
+            173 => self
+                .jni
+                .object_arrays
+                .get(&(a1 as u32))
+                .and_then(|elements| elements.get(a2 as usize))
+                .copied()
+                .unwrap_or(0),
+            174 => {
+                if let Some(element) = self
+                    .jni
+                    .object_arrays
+                    .get_mut(&(a1 as u32))
+                    .and_then(|elements| elements.get_mut(a2 as usize))
+                {
+                    *element = a3;
+                }
+                0
+            }
+            175..=182 => {
+                let vm = require(vm)?;
+                let element_type = ARRAY_TYPES[index - 175] as char;
+                let array = vm
+                    .jni_new_array(&format!("[{}", element_type), vec![0; a1 as usize])
+                    .map_err(vm_error)?;
+                self.handle_for(&array)
+            }
+            183..=190 | 222 => {
+                let vm = require(vm)?;
+                let element_type = if index == 222 {
+                    self.array_element_type(Some(&*vm), a1)
+                } else {
+                    ARRAY_TYPES[index - 183]
+                };
+                let array = vm.jni_array(a1 as u32).unwrap_or_default();
+                let data = widen(&array, element_type);
+                let address = self.malloc(data.len() as u64)?;
+                self.memory.write(address, &data)?;
+                self.jni.pinned.insert(address, (a1 as u32, element_type));
+                self.set_is_copy(a2)?;
+                address
+            }
+            191..=198 | 223 => {
+                let vm = require(vm)?;
+                if let Some((array, element_type)) = self.jni.pinned.get(&a2).copied() {
+                    if a3 != JNI_ABORT {
+                        let length = vm.jni_array(array).map(|a| a.len()).unwrap_or(0);
+                        let data = self
+                            .memory
+                            .read_bytes(a2, length * element_size(element_type))?;
+                        vm.jni_set_array_region(array, 0, &narrow(&data, element_type));
+                    }
+                    // JNI_COMMIT keeps the buffer
+                    if a3 != 1 {
+                        self.jni.pinned.remove(&a2);
+                        self.free(a2);
+                    }
+                }
+                0
+            }
+            199..=206 => {
+                let vm = require(vm)?;
+                let element_type = ARRAY_TYPES[index - 199];
+                let array = vm.jni_array(a1 as u32).unwrap_or_default();
+                let start = a2 as usize;
+                let end = start.saturating_add(a3 as usize);
+                if end > array.len() {
+                    self.throw("Ljava/lang/ArrayIndexOutOfBoundsException;", "");
+                } else {
+                    let data = widen(&array[start..end], element_type);
+                    self.memory.write(self.argument(4), &data)?;
+                }
+                0
+            }
+            207..=214 => {
+                let vm = require(vm)?;
+                let element_type = ARRAY_TYPES[index - 207];
+                let length = vm.jni_array(a1 as u32).map(|a| a.len()).unwrap_or(0);
+                let in_bounds = (a2 as usize)
+                    .checked_add(a3 as usize)
+                    .map_or(false, |end| end <= length);
+                if in_bounds {
+                    let data = self
+                        .memory
+                        .read_bytes(self.argument(4), a3 as usize * element_size(element_type))?;
+                    vm.jni_set_array_region(a1 as u32, a2 as usize, &narrow(&data, element_type));
+                } else {
+                    self.throw("Ljava/lang/ArrayIndexOutOfBoundsException;", "");
+                }
+                0
+            }
+            215 => {
+                let class_name = self.class_name(a1)?;
+                for method in 0..a3 {
+                    let entry = a2 + method * 24;
+                    let name = self.memory.read_u64(entry)?;
+                    let signature = self.memory.read_u64(entry + 8)?;
+                    let registered = RegisteredNative {
+                        class_name: class_name.clone(),
+                        name: self.utf8_string(name)?,
+                        signature: self.utf8_string(signature)?,
+                        address: self.memory.read_u64(entry + 16)?,
+                    };
+                    log::debug!("RegisterNatives {:?}", registered);
+                    self.jni.registered.push(registered);
+                }
+                0
+            }
+            216 => {
+                let class_name = self.class_name(a1)?;
+                self.jni.registered.retain(|r| r.class_name != class_name);
+                0
+            }
+            // MonitorEnter, MonitorExit, DeleteWeakGlobalRef
+            217 | 218 | 227 => 0,
+            219 => {
+                self.memory.write_u64(a1, JAVA_VM)?;
+                0
+            }
+            220 | 221 => {
+                let string = self.string(vm, a1)?;
+                let units: Vec<u16> = string.encode_utf16().collect();
+                let start = a2 as usize;
+                let end = start + a3 as usize;
+                if end > units.len() {
+                    self.throw("Ljava/lang/StringIndexOutOfBoundsException;", "");
+                } else if index == 220 {
+                    let data: Vec<u8> = units[start..end]
+                        .iter()
+                        .flat_map(|u| u.to_le_bytes())
+                        .collect();
+                    self.memory.write(self.argument(4), &data)?;
+                } else {
+                    let region = String::from_utf16_lossy(&units[start..end]);
+                    self.memory
+                        .write_c_string(self.argument(4), region.as_bytes())?;
+                }
+                0
+            }
+            226 => a1,
+            228 => self.jni.pending_exception.is_some() as u64,
+            // direct buffers are not supported
+            229 | 230 => 0,
+            231 => u64::MAX,
+            // JNILocalRefType
+            232 => 1,
+            _ => {
+                return Err(NativeException::Jni(format!(
+                    "invalid JNIEnv function {}",
+                    index
+                )))
+            }
+        };
+        self.set_return(result);
+        Ok(())
+    }
+
+    /// Read the arguments of a `Call<Type>Method` style function, passed as `...` (`variant` 0), `va_list` (1)
+    /// or `jvalue` array (2) after `fixed` arguments
+    fn call_arguments(
+        &mut self,
+        vm: &mut VM,
+        signature: &str,
+        variant: usize,
+        fixed: usize,
+    ) -> Result<Vec<Register>, NativeException> {
+        let (parameters, _) = parse_signature(signature)
+            .ok_or_else(|| NativeException::Jni(format!("invalid signature {}", signature)))?;
+        let mut variadic = match variant {
+            0 => Some(VarArgs::after(&self.cpu, fixed)),
+            1 => Some(VarArgs::from_va_list(&self.memory, self.argument(fixed))?),
+            _ => None,
+        };
+        let values = self.argument(fixed);
+        let mut arguments = vec![];
+        for (position, parameter) in parameters.iter().enumerate() {
+            let kind = parameter.as_bytes()[0];
+            let raw = match &mut variadic {
+                Some(variadic) if kind == b'F' => {
+                    // promoted to double
+                    let double = f64::from_bits(variadic.next_double(&self.cpu, &self.memory)?);
+                    (double as f32).to_bits() as u64
+                }
+                Some(variadic) if kind == b'D' => variadic.next_double(&self.cpu, &self.memory)?,
+                Some(variadic) => variadic.next_int(&self.cpu, &self.memory)?,
+                None => self.memory.read_u64(values + position as u64 * 8)?,
+            };
+            arguments.push(self.register_for(vm, parameter, raw));
+            if is_wide(parameter) {
+                arguments.push(Register::Empty);
+            }
+        }
+        Ok(arguments)
+    }
+
+    /// Convert a VM value to its JNI representation
+    fn handle_for(&mut self, register: &Register) -> u64 {
+        match register {
+            Register::Reference(ty, address) => {
+                self.jni.object_types.insert(*address, ty.clone());
+                OBJECT_HANDLES | *address as u64
+            }
+            Register::Literal(value) => *value as i64 as u64,
+            Register::LiteralWide(value) => *value as u64,
+            _ => 0,
+        }
+    }
+
+    /// Convert a JNI value of type `ty` to a VM register
+    fn register_for(&mut self, vm: &mut VM, ty: &str, raw: u64) -> Register {
+        match ty.as_bytes().first() {
+            Some(b'L') | Some(b'[') => {
+                if raw == 0 {
+                    return Register::Null;
+                }
+                match raw & !HANDLE_MASK {
+                    OBJECT_HANDLES => {
+                        let address = raw as u32;
+                        Register::Reference(self.object_type(Some(&*vm), raw), address)
+                    }
+                    CLASS_HANDLES => {
+                        let class_name = self
+                            .jni
+                            .classes
+                            .get((raw & HANDLE_MASK) as usize)
+                            .cloned()
+                            .unwrap_or_default();
+                        match vm.jni_class_object(&class_name) {
+                            Ok(class_object) => {
+                                self.handle_for(&class_object);
+                                class_object
+                            }
+                            Err(_) => Register::Null,
+                        }
+                    }
+                    _ => Register::Null,
+                }
+            }
+            Some(b'J') | Some(b'D') => Register::LiteralWide(raw as i64),
+            Some(b'Z') => Register::Literal(raw as u8 as i32),
+            Some(b'B') => Register::Literal(raw as i8 as i32),
+            Some(b'C') => Register::Literal(raw as u16 as i32),
+            Some(b'S') => Register::Literal(raw as i16 as i32),
+            Some(b'V') => Register::Empty,
+            _ => Register::Literal(raw as i32),
+        }
+    }
+
+    fn class_handle(&mut self, class_name: &str) -> u64 {
+        let index = match self.jni.classes.iter().position(|c| c == class_name) {
+            Some(index) => index,
+            None => {
+                self.jni.classes.push(class_name.to_string());
+                self.jni.classes.len() - 1
+            }
+        };
+        CLASS_HANDLES + index as u64
+    }
+
+    fn class_name(&self, handle: u64) -> Result<String, NativeException> {
+        if handle & !HANDLE_MASK == CLASS_HANDLES {
+            if let Some(class_name) = self.jni.classes.get((handle & HANDLE_MASK) as usize) {
+                return Ok(class_name.clone());
+            }
+        }
+        Err(NativeException::Jni(format!(
+            "invalid jclass {:#x}",
+            handle
+        )))
+    }
+
+    fn object_type(&self, vm: Option<&VM>, handle: u64) -> String {
+        let address = handle as u32;
+        if handle & !HANDLE_MASK == CLASS_HANDLES {
+            return "Ljava/lang/Class;".to_string();
+        }
+        self.jni
+            .object_types
+            .get(&address)
+            .filter(|ty| !ty.is_empty())
+            .cloned()
+            .or_else(|| vm.and_then(|vm| vm.jni_object_type(address)))
+            .unwrap_or_else(|| "Ljava/lang/Object;".to_string())
+    }
+
+    fn array_element_type(&self, vm: Option<&VM>, handle: u64) -> u8 {
+        let ty = self.object_type(vm, handle);
+        match ty.as_bytes() {
+            [b'[', element] if ARRAY_TYPES.contains(element) => *element,
+            _ => b'B',
+        }
+    }
+
+    fn member(
+        &self,
+        members: &[MemberId],
+        base: u64,
+        id: u64,
+    ) -> Result<MemberId, NativeException> {
+        id.checked_sub(base)
+            .and_then(|index| members.get(index as usize))
+            .cloned()
+            .ok_or_else(|| NativeException::Jni(format!("invalid member id {:#x}", id)))
+    }
+
+    fn string(&self, vm: Option<&mut VM>, handle: u64) -> Result<String, NativeException> {
+        let vm = require(vm)?;
+        vm.jni_string(handle as u32)
+            .ok_or_else(|| NativeException::Jni(format!("{:#x} is not a string", handle)))
+    }
+
+    fn utf8_string(&self, address: u64) -> Result<String, NativeException> {
+        let bytes = self.memory.read_c_string(address, 0x10000)?;
+        Ok(String::from_utf8_lossy(&bytes).to_string())
+    }
+
+    fn c_string_argument(&self, index: usize) -> Result<String, NativeException> {
+        self.utf8_string(self.argument(index))
+    }
+
+    fn set_is_copy(&mut self, address: u64) -> Result<(), NativeException> {
+        if address != 0 {
+            self.memory.write_uint(address, 1, 1)?;
+        }
+        Ok(())
+    }
+
+    fn throw(&mut self, class_name: &str, message: &str) {
+        log::debug!("native code threw {}: {}", class_name, message);
+        self.jni.pending_exception = Some(format!("{}: {}", class_name, message));
+    }
+
+    fn throw_vm_error(
+        &mut self,
+        class_name: &str,
+        method_name: &str,
+        error: crate::vm::VMException,
+    ) {
+        log::debug!(
+            "call to {}->{} failed: {:?}",
+            class_name,
+            method_name,
+            error
+        );
+        self.throw(
+            "Ljava/lang/RuntimeException;",
+            &format!("{}->{} failed", class_name, method_name),
+        );
+    }
+}
+
+fn require(vm: Option<&mut VM>) -> Result<&mut VM, NativeException> {
+    vm.ok_or_else(|| NativeException::Jni("no VM attached".to_string()))
+}
+
+fn vm_error(error: crate::vm::VMException) -> NativeException {
+    NativeException::Jni(format!("{:?}", error))
+}
+
+fn is_wide(ty: &str) -> bool {
+    ty == "J" || ty == "D"
+}
+
+fn element_size(element_type: u8) -> usize {
+    match element_type {
+        b'Z' | b'B' => 1,
+        b'C' | b'S' => 2,
+        b'I' | b'F' => 4,
+        _ => 8,
+    }
+}
+
+/// VM arrays store one byte per element, widen them to the native element size
+fn widen(array: &[u8], element_type: u8) -> Vec<u8> {
+    let size = element_size(element_type);
+    array
+        .iter()
+        .flat_map(|element| {
+            let value = match element_type {
+                b'Z' | b'C' => *element as u64,
+                _ => *element as i8 as i64 as u64,
+            };
+            value.to_le_bytes()[..size].to_vec()
+        })
+        .collect()
+}
+
+fn narrow(data: &[u8], element_type: u8) -> Vec<u8> {
+    data.chunks(element_size(element_type))
+        .map(|element| element[0])
+        .collect()
+}
+
+/// Split a method signature into parameter types and return type
+pub(crate) fn parse_signature(signature: &str) -> Option<(Vec<String>, String)> {
+    let (parameters, return_type) = signature.strip_prefix('(')?.split_once(')')?;
+    let mut types = vec![];
+    let mut rest = parameters;
+    while !rest.is_empty() {
+        let dimensions = rest.len() - rest.trim_start_matches('[').len();
+        let end = match rest.as_bytes().get(dimensions)? {
+            b'L' => rest.find(';')? + 1,
+            _ => dimensions + 1,
+        };
+        types.push(rest[..end].to_string());
+        rest = &rest[end..];
+    }
+    if return_type.is_empty() {
+        return None;
+    }
+    Some((types, return_type.to_string()))
+}
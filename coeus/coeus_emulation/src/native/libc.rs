@@ -0,0 +1,1015 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The parts of bionic (libc, libdl, liblog and the C++ runtime entry points) native code commonly needs
+//! before it gets to its actual work. Anything touching files, threads or signals just reports failure.
+
+use std::collections::HashMap;
+
+use crate::vm::VM;
+
+use super::{NativeEmulator, NativeException, VarArgs, STACK_GUARD};
+
+const MAX_STRING: usize = 0x10_0000;
+const MAX_COPY: u64 = 0x1000_0000;
+const ENOENT: u64 = 2;
+const EAGAIN: u64 = 11;
+/// `dlopen` handles are the library index plus this value
+const LIBRARY_HANDLE: u64 = 0x1000;
+/// `dlopen` handle for system libraries we only provide through the shim
+const SYSTEM_HANDLE: u64 = 0xffff_0000;
+
+/// Imports which are variables rather than functions
+const DATA_IMPORTS: &[&str] = &[
+    "__stack_chk_guard",
+    "__sF",
+    "environ",
+    "__progname",
+    "_ctype_",
+    "stdin",
+    "stdout",
+    "stderr",
+];
+
+const SYSTEM_PROPERTIES: &[(&str, &str)] = &[
+    ("ro.build.version.sdk", "30"),
+    ("ro.build.version.release", "11"),
+    ("ro.product.cpu.abi", "arm64-v8a"),
+    ("ro.product.manufacturer", "Google"),
+    ("ro.product.model", "Pixel 4a"),
+    ("ro.product.brand", "google"),
+    ("ro.build.type", "user"),
+    ("ro.build.tags", "release-keys"),
+    ("ro.debuggable", "0"),
+    ("ro.secure", "1"),
+];
+
+#[derive(Clone)]
+pub(super) struct LibcState {
+    random: u64,
+    next_key: u64,
+    thread_specific: HashMap<u64, u64>,
+}
+
+impl Default for LibcState {
+    fn default() -> Self {
+        LibcState {
+            random: 0x2545_f491,
+            next_key: 1,
+            thread_specific: HashMap::new(),
+        }
+    }
+}
+
+impl NativeEmulator {
+    /// Address for an import none of the loaded libraries defines
+    pub(super) fn resolve_import(&mut self, name: &str, is_data: bool) -> u64 {
+        if !is_data && !DATA_IMPORTS.contains(&name) {
+            return self.import_hook(name);
+        }
+        if let Some(address) = self.import_addresses.get(name) {
+            return *address;
+        }
+        let address = self.data_import(name).unwrap_or(0);
+        self.import_addresses.insert(name.to_string(), address);
+        address
+    }
+
+    fn data_import(&mut self, name: &str) -> Result<u64, NativeException> {
+        match name {
+            "__stack_chk_guard" => {
+                let address = self.malloc(8)?;
+                self.memory.write_u64(address, STACK_GUARD)?;
+                Ok(address)
+            }
+            "__progname" => {
+                let string = self.alloc_c_string(b"app_process64")?;
+                let address = self.malloc(8)?;
+                self.memory.write_u64(address, string)?;
+                Ok(address)
+            }
+            "environ" => {
+                let array = self.malloc(8)?;
+                let address = self.malloc(8)?;
+                self.memory.write_u64(address, array)?;
+                Ok(address)
+            }
+            "stdin" | "stdout" | "stderr" => {
+                let file = self.malloc(0x100)?;
+                let address = self.malloc(8)?;
+                self.memory.write_u64(address, file)?;
+                Ok(address)
+            }
+            "_ctype_" => {
+                // bionic's (old) ctype table, indexed with `c + 1`
+                let address = self.malloc(257)?;
+                let table: Vec<u8> = std::iter::once(0)
+                    .chain((0..=255u8).map(ctype_flags))
+                    .collect();
+                self.memory.write(address, &table)?;
+                Ok(address)
+            }
+            _ => self.malloc(0x400),
+        }
+    }
+
+    pub(super) fn call_import(
+        &mut self,
+        name: &str,
+        mut vm: Option<&mut VM>,
+    ) -> Result<(), NativeException> {
+        let a0 = self.argument(0);
+        let a1 = self.argument(1);
+        let a2 = self.argument(2);
+        let a3 = self.argument(3);
+        let result = match name {
+            // memory management
+            "malloc"
+            | "_Znwm"
+            | "_Znam"
+            | "_ZnwmRKSt9nothrow_t"
+            | "_ZnamRKSt9nothrow_t"
+            | "__cxa_allocate_exception" => self.malloc(a0)?,
+            "calloc" => self.malloc(a0.saturating_mul(a1))?,
+            "realloc" => {
+                let address = self.malloc(a1)?;
+                if a0 != 0 {
+                    let old_size = self.heap.allocations.get(&a0).copied().unwrap_or(0);
+                    self.copy(address, a0, old_size.min(a1))?;
+                    self.free(a0);
+                }
+                address
+            }
+            "free" | "_ZdlPv" | "_ZdaPv" | "_ZdlPvm" | "_ZdaPvm" | "__cxa_free_exception" => {
+                self.free(a0);
+                0
+            }
+            "posix_memalign" => {
+                let address = self.aligned_malloc(a1, a2)?;
+                self.memory.write_u64(a0, address)?;
+                0
+            }
+            "memalign" | "aligned_alloc" => self.aligned_malloc(a0, a1)?,
+            "malloc_usable_size" => self.heap.allocations.get(&a0).copied().unwrap_or(0),
+            "mmap" | "mmap64" => {
+                if self.argument(4) as i32 == -1 {
+                    self.aligned_malloc(0x1000, a1)?
+                } else {
+                    u64::MAX
+                }
+            }
+            "munmap" | "mprotect" | "madvise" | "mlock" | "munlock" => 0,
+
+            // memory and strings
+            "memcpy" | "memmove" | "__memcpy_chk" | "__memmove_chk" => {
+                self.copy(a0, a1, a2)?;
+                a0
+            }
+            "memset" | "__memset_chk" => {
+                self.fill(a0, a1 as u8, a2)?;
+                a0
+            }
+            "bzero" => {
+                self.fill(a0, 0, a1)?;
+                0
+            }
+            "memcmp" | "bcmp" => {
+                let left = self.read_limited(a0, a2)?;
+                let right = self.read_limited(a1, a2)?;
+                compare(&left, &right)
+            }
+            "memchr" | "memrchr" => {
+                let data = self.read_limited(a0, a2)?;
+                let position = if name == "memchr" {
+                    data.iter().position(|b| *b == a1 as u8)
+                } else {
+                    data.iter().rposition(|b| *b == a1 as u8)
+                };
+                position.map(|p| a0.wrapping_add(p as u64)).unwrap_or(0)
+            }
+            "strlen" | "__strlen_chk" => self.c_string(a0)?.len() as u64,
+            "strnlen" => self.c_string(a0)?.len().min(a1 as usize) as u64,
+            "strcmp" | "strcasecmp" | "strncmp" | "strncasecmp" => {
+                let mut left = self.c_string(a0)?;
+                let mut right = self.c_string(a1)?;
+                if name.starts_with("strn") {
+                    left.truncate(a2 as usize);
+                    right.truncate(a2 as usize);
+                }
+                if name.contains("case") {
+                    left.make_ascii_lowercase();
+                    right.make_ascii_lowercase();
+                }
+                left.push(0);
+                right.push(0);
+                compare(&left, &right)
+            }
+            "strcpy" | "__strcpy_chk" | "stpcpy" => {
+                let string = self.c_string(a1)?;
+                self.memory.write_c_string(a0, &string)?;
+                if name == "stpcpy" {
+                    a0.wrapping_add(string.len() as u64)
+                } else {
+                    a0
+                }
+            }
+            "strncpy" | "__strncpy_chk" | "__strncpy_chk2" => {
+                if a2 > MAX_COPY {
+                    return Err(NativeException::OutOfMemory);
+                }
+                let mut string = self.c_string(a1)?;
+                string.truncate(a2 as usize);
+                string.resize(a2 as usize, 0);
+                self.memory.write(a0, &string)?;
+                a0
+            }
+            "strcat" | "__strcat_chk" | "strncat" | "__strncat_chk" => {
+                let destination = self.c_string(a0)?;
+                let mut source = self.c_string(a1)?;
+                if name.contains("strncat") {
+                    source.truncate(a2 as usize);
+                }
+                self.memory
+                    .write_c_string(a0.wrapping_add(destination.len() as u64), &source)?;
+                a0
+            }
+            "strlcpy" | "__strlcpy_chk" => {
+                let string = self.c_string(a1)?;
+                if a2 > 0 {
+                    let count = string.len().min(a2 as usize - 1);
+                    self.memory.write_c_string(a0, &string[..count])?;
+                }
+                string.len() as u64
+            }
+            "strlcat" | "__strlcat_chk" => {
+                let destination = self.c_string(a0)?;
+                let source = self.c_string(a1)?;
+                let available = (a2 as usize).saturating_sub(destination.len() + 1);
+                let count = source.len().min(available);
+                if a2 as usize > destination.len() {
+                    self.memory.write_c_string(
+                        a0.wrapping_add(destination.len() as u64),
+                        &source[..count],
+                    )?;
+                }
+                (destination.len() + source.len()) as u64
+            }
+            "strchr" | "__strchr_chk" | "strrchr" | "__strrchr_chk" | "index" | "rindex" => {
+                let mut string = self.c_string(a0)?;
+                string.push(0);
+                let character = a1 as u8;
+                let position = if name.contains('r') && name != "__strchr_chk" {
+                    string.iter().rposition(|b| *b == character)
+                } else {
+                    string.iter().position(|b| *b == character)
+                };
+                position.map(|p| a0.wrapping_add(p as u64)).unwrap_or(0)
+            }
+            "strstr" | "strcasestr" => {
+                let mut haystack = self.c_string(a0)?;
+                let mut needle = self.c_string(a1)?;
+                if name == "strcasestr" {
+                    haystack.make_ascii_lowercase();
+                    needle.make_ascii_lowercase();
+                }
+                if needle.is_empty() {
+                    a0
+                } else {
+                    haystack
+                        .windows(needle.len())
+                        .position(|w| w == needle.as_slice())
+                        .map(|p| a0.wrapping_add(p as u64))
+                        .unwrap_or(0)
+                }
+            }
+            "strspn" | "strcspn" => {
+                let string = self.c_string(a0)?;
+                let set = self.c_string(a1)?;
+                let accept = name == "strspn";
+                string
+                    .iter()
+                    .position(|b| set.contains(b) != accept)
+                    .unwrap_or(string.len()) as u64
+            }
+            "strpbrk" => {
+                let string = self.c_string(a0)?;
+                let set = self.c_string(a1)?;
+                string
+                    .iter()
+                    .position(|b| set.contains(b))
+                    .map(|p| a0.wrapping_add(p as u64))
+                    .unwrap_or(0)
+            }
+            "strdup" | "strndup" => {
+                let mut string = self.c_string(a0)?;
+                if name == "strndup" {
+                    string.truncate(a1 as usize);
+                }
+                self.alloc_c_string(&string)?
+            }
+            "atoi" | "atol" | "atoll" => parse_integer(&self.c_string(a0)?, 10).0 as u64,
+            "strtol" | "strtoll" | "strtoul" | "strtoull" | "strtoimax" | "strtoumax" => {
+                let string = self.c_string(a0)?;
+                let (value, consumed) = parse_integer(&string, a2 as u32);
+                if a1 != 0 {
+                    self.memory
+                        .write_u64(a1, a0.wrapping_add(consumed as u64))?;
+                }
+                value as u64
+            }
+            "toupper" => (a0 as u8 as char).to_ascii_uppercase() as u64,
+            "tolower" => (a0 as u8 as char).to_ascii_lowercase() as u64,
+            "isalpha" | "isdigit" | "isalnum" | "isspace" | "isupper" | "islower" | "isxdigit"
+            | "isprint" | "ispunct" => {
+                let c = a0 as u8;
+                let holds = match name {
+                    "isalpha" => c.is_ascii_alphabetic(),
+                    "isdigit" => c.is_ascii_digit(),
+                    "isalnum" => c.is_ascii_alphanumeric(),
+                    "isspace" => c.is_ascii_whitespace() || c == 0x0b,
+                    "isupper" => c.is_ascii_uppercase(),
+                    "islower" => c.is_ascii_lowercase(),
+                    "isxdigit" => c.is_ascii_hexdigit(),
+                    "isprint" => (0x20..0x7f).contains(&c),
+                    _ => c.is_ascii_punctuation(),
+                };
+                holds as u64
+            }
+            "abs" | "labs" | "llabs" => (a0 as i64).wrapping_abs() as u64,
+            "strerror" => self.alloc_c_string(format!("error {}", a0 as i32).as_bytes())?,
+
+            // formatted output
+            "sprintf" | "snprintf" | "vsprintf" | "vsnprintf" | "__sprintf_chk"
+            | "__snprintf_chk" | "__vsprintf_chk" | "__vsnprintf_chk" => {
+                let (limit, format, fixed) = match name {
+                    "sprintf" => (None, a1, 2),
+                    "snprintf" => (Some(a1), a2, 3),
+                    "vsprintf" => (None, a1, 2),
+                    "vsnprintf" => (Some(a1), a2, 3),
+                    "__sprintf_chk" | "__vsprintf_chk" => (None, a3, 4),
+                    _ => (Some(a1), self.argument(4), 5),
+                };
+                let mut arguments = if name.contains("vs") {
+                    VarArgs::from_va_list(&self.memory, self.argument(fixed))?
+                } else {
+                    VarArgs::after(&self.cpu, fixed)
+                };
+                let output = self.format(format, &mut arguments)?;
+                let written = match limit {
+                    Some(0) => 0,
+                    Some(limit) => output.len().min(limit as usize - 1),
+                    None => output.len(),
+                };
+                if limit != Some(0) {
+                    self.memory.write_c_string(a0, &output[..written])?;
+                }
+                output.len() as u64
+            }
+            "__android_log_print" | "__android_log_vprint" | "__android_log_buf_print" => {
+                let (tag, format, fixed) = if name == "__android_log_buf_print" {
+                    (a2, a3, 4)
+                } else {
+                    (a1, a2, 3)
+                };
+                let mut arguments = if name == "__android_log_vprint" {
+                    VarArgs::from_va_list(&self.memory, a3)?
+                } else {
+                    VarArgs::after(&self.cpu, fixed)
+                };
+                let message = self.format(format, &mut arguments)?;
+                let tag = self.c_string(tag)?;
+                self.write_log(&tag, &message);
+                1
+            }
+            "__android_log_write" => {
+                let tag = self.c_string(a1)?;
+                let message = self.c_string(a2)?;
+                self.write_log(&tag, &message);
+                1
+            }
+            "__android_log_assert" => {
+                let mut arguments = VarArgs::after(&self.cpu, 3);
+                let message = self.format(a2, &mut arguments)?;
+                return Err(NativeException::Abort(
+                    String::from_utf8_lossy(&message).to_string(),
+                ));
+            }
+            "printf" | "vprintf" | "fprintf" | "vfprintf" => {
+                let (format, fixed) = if name.starts_with('f') || name.starts_with("vf") {
+                    (a1, 2)
+                } else {
+                    (a0, 1)
+                };
+                let mut arguments = if name.starts_with('v') {
+                    VarArgs::from_va_list(&self.memory, self.argument(fixed))?
+                } else {
+                    VarArgs::after(&self.cpu, fixed)
+                };
+                let message = self.format(format, &mut arguments)?;
+                self.write_log(b"stdio", &message);
+                message.len() as u64
+            }
+            "puts" | "fputs" => {
+                let message = self.c_string(a0)?;
+                self.write_log(b"stdio", &message);
+                1
+            }
+            "fwrite" => {
+                let data = self.read_limited(a0, a1.saturating_mul(a2))?;
+                self.write_log(b"stdio", &data);
+                a2
+            }
+            "putchar" | "fputc" | "putc" => {
+                self.write_log(b"stdio", &[a0 as u8]);
+                a0
+            }
+            "fflush" | "setvbuf" | "__android_log_is_loggable" => 0,
+
+            // process and threads
+            "__stack_chk_fail" => {
+                return Err(NativeException::Abort(
+                    "stack corruption detected".to_string(),
+                ))
+            }
+            "abort" | "exit" | "_exit" | "__cxa_pure_virtual" | "_ZSt9terminatev" => {
+                return Err(NativeException::Abort(name.to_string()))
+            }
+            "__assert2" | "__assert" => {
+                let expression = self.c_string(if name == "__assert2" { a3 } else { a2 })?;
+                return Err(NativeException::Abort(format!(
+                    "assertion failed: {}",
+                    String::from_utf8_lossy(&expression)
+                )));
+            }
+            "__cxa_throw" | "__cxa_rethrow" | "_Unwind_Resume" => {
+                return Err(NativeException::Abort(format!(
+                    "unhandled C++ exception ({})",
+                    name
+                )))
+            }
+            "__cxa_begin_catch" => a0,
+            "__cxa_end_catch" => 0,
+            "__cxa_atexit"
+            | "atexit"
+            | "__cxa_finalize"
+            | "__register_atfork"
+            | "__cxa_thread_atexit_impl" => 0,
+            "__cxa_guard_acquire" => {
+                let initialized = self.memory.read_uint(a0, 1)?;
+                (initialized == 0) as u64
+            }
+            "__cxa_guard_release" => {
+                self.memory.write_uint(a0, 1, 1)?;
+                0
+            }
+            "__cxa_guard_abort" => 0,
+            "pthread_once" => {
+                if self.memory.read_u32(a0)? == 0 {
+                    self.memory.write_uint(a0, 1, 4)?;
+                    self.execute(vm.as_deref_mut(), a1, &[], &[])?;
+                }
+                0
+            }
+            "pthread_key_create" => {
+                let key = self.libc.next_key;
+                self.libc.next_key += 1;
+                self.memory.write_uint(a0, key, 4)?;
+                0
+            }
+            "pthread_setspecific" => {
+                self.libc.thread_specific.insert(a0 & 0xffff_ffff, a1);
+                0
+            }
+            "pthread_getspecific" => self
+                .libc
+                .thread_specific
+                .get(&(a0 & 0xffff_ffff))
+                .copied()
+                .unwrap_or(0),
+            "pthread_key_delete" => {
+                self.libc.thread_specific.remove(&(a0 & 0xffff_ffff));
+                0
+            }
+            "pthread_self" => self.cpu.tpidr,
+            "pthread_create" => {
+                log::debug!("not starting thread at {:#x}", a2);
+                EAGAIN
+            }
+            name if name.starts_with("pthread_") => 0,
+            "getpid" | "gettid" => 4242,
+            "getppid" => 1,
+            "getuid" | "geteuid" | "getgid" | "getegid" => 10_123,
+            "__errno" => self.cpu.tpidr.wrapping_add(0x10),
+            "sysconf" => match a0 {
+                39 | 40 => 0x1000,
+                96 | 97 => 8,
+                _ => 0,
+            },
+            "getpagesize" => 0x1000,
+            "getauxval" => match a0 {
+                6 => 0x1000,
+                _ => 0,
+            },
+            "android_get_device_api_level" => 30,
+            "__system_property_get" => {
+                let property = self.c_string(a0)?;
+                let value = SYSTEM_PROPERTIES
+                    .iter()
+                    .find(|(key, _)| key.as_bytes() == property.as_slice())
+                    .map(|(_, value)| *value)
+                    .unwrap_or("");
+                self.memory.write_c_string(a1, value.as_bytes())?;
+                value.len() as u64
+            }
+            "__system_property_find" => 0,
+            "time" => {
+                let now = 1_700_000_000;
+                if a0 != 0 {
+                    self.memory.write_u64(a0, now)?;
+                }
+                now
+            }
+            "gettimeofday" | "clock_gettime" => {
+                let target = if name == "clock_gettime" { a1 } else { a0 };
+                let ticks = self.cpu.ticks();
+                self.memory
+                    .write_u64(target, 1_700_000_000 + ticks / 1_000_000_000)?;
+                let fraction = ticks % 1_000_000_000;
+                let fraction = if name == "clock_gettime" {
+                    fraction
+                } else {
+                    fraction / 1000
+                };
+                self.memory.write_u64(target.wrapping_add(8), fraction)?;
+                0
+            }
+            "clock" => self.cpu.ticks(),
+            "rand" | "random" | "lrand48" => self.next_random() & 0x7fff_ffff,
+            "arc4random" => self.next_random() & 0xffff_ffff,
+            "srand" | "srandom" | "srand48" => {
+                self.libc.random = a0;
+                0
+            }
+            "arc4random_buf" | "getrandom" => {
+                if a1 > MAX_COPY {
+                    return Err(NativeException::OutOfMemory);
+                }
+                let bytes: Vec<u8> = (0..a1).map(|_| self.next_random() as u8).collect();
+                self.memory.write(a0, &bytes)?;
+                a1
+            }
+            "usleep" | "sleep" | "nanosleep" | "sched_yield" | "signal" | "sigaction" | "raise"
+            | "kill" | "sigemptyset" | "sigaddset" | "sigprocmask" | "prctl" | "ptrace"
+            | "setenv" | "unsetenv" | "isatty" | "uname" => 0,
+            "getenv" => 0,
+            "open" | "open64" | "openat" | "access" | "faccessat" | "stat" | "lstat" | "fstat"
+            | "stat64" | "readlink" | "read" | "write" | "pread64" | "lseek" | "syscall"
+            | "fork" | "execve" | "socket" | "connect" => {
+                let errno = self.cpu.tpidr.wrapping_add(0x10);
+                self.memory.write_uint(errno, ENOENT, 4)?;
+                u64::MAX
+            }
+            "fopen" | "fopen64" | "fdopen" | "opendir" | "popen" | "fgets" | "readdir" => 0,
+            "close" | "fclose" | "closedir" | "pclose" | "fread" => 0,
+
+            // dynamic linking
+            "dlopen" | "android_dlopen_ext" => self.dlopen(a0),
+            "dlsym" => {
+                let symbol = String::from_utf8_lossy(&self.c_string(a1)?).to_string();
+                self.dlsym(a0, &symbol)
+            }
+            "dlclose" | "dlerror" => 0,
+            "dladdr" => {
+                let library = self.library_at(a0).map(|l| (l.name.clone(), l.base));
+                match library {
+                    Some((library_name, base)) => {
+                        let file_name = self.alloc_c_string(library_name.as_bytes())?;
+                        self.memory.write_u64(a1, file_name)?;
+                        self.memory.write_u64(a1.wrapping_add(8), base)?;
+                        self.memory.write_u64(a1.wrapping_add(16), 0)?;
+                        self.memory.write_u64(a1.wrapping_add(24), 0)?;
+                        1
+                    }
+                    None => 0,
+                }
+            }
+            "JNI_GetCreatedJavaVMs" => {
+                self.memory.write_u64(a0, self.java_vm())?;
+                if a2 != 0 {
+                    self.memory.write_uint(a2, 1, 4)?;
+                }
+                0
+            }
+            "qsort" => {
+                self.sort(vm, a0, a1, a2, a3)?;
+                0
+            }
+            _ => {
+                if self.unresolved_imports.insert(name.to_string()) {
+                    log::debug!("call to unsupported import {}, returning 0", name);
+                }
+                0
+            }
+        };
+        self.set_return(result);
+        Ok(())
+    }
+
+    fn aligned_malloc(&mut self, alignment: u64, size: u64) -> Result<u64, NativeException> {
+        let alignment = alignment.max(16);
+        let address = self.malloc(
+            size.checked_add(alignment)
+                .ok_or(NativeException::OutOfMemory)?,
+        )?;
+        Ok((address + alignment - 1) & !(alignment - 1))
+    }
+
+    fn copy(&mut self, destination: u64, source: u64, size: u64) -> Result<(), NativeException> {
+        let data = self.read_limited(source, size)?;
+        self.memory.write(destination, &data)
+    }
+
+    fn fill(&mut self, destination: u64, value: u8, size: u64) -> Result<(), NativeException> {
+        if size > MAX_COPY {
+            return Err(NativeException::OutOfMemory);
+        }
+        self.memory.write(destination, &vec![value; size as usize])
+    }
+
+    fn read_limited(&self, address: u64, size: u64) -> Result<Vec<u8>, NativeException> {
+        if size > MAX_COPY {
+            return Err(NativeException::OutOfMemory);
+        }
+        self.memory.read_bytes(address, size as usize)
+    }
+
+    fn c_string(&self, address: u64) -> Result<Vec<u8>, NativeException> {
+        self.memory.read_c_string(address, MAX_STRING)
+    }
+
+    fn write_log(&mut self, tag: &[u8], message: &[u8]) {
+        let line = format!(
+            "{}: {}",
+            String::from_utf8_lossy(tag),
+            String::from_utf8_lossy(message).trim_end()
+        );
+        log::debug!("native log {}", line);
+        self.log.push(line);
+    }
+
+    fn next_random(&mut self) -> u64 {
+        // xorshift, deterministic so emulation results are reproducible
+        let mut x = self.libc.random.max(1);
+        x ^= x << 13;
+        x ^= x >> 7;
+        x ^= x << 17;
+        self.libc.random = x;
+        x
+    }
+
+    fn dlopen(&mut self, path: u64) -> u64 {
+        if path == 0 {
+            return SYSTEM_HANDLE;
+        }
+        let Ok(path) = self.c_string(path) else {
+            return 0;
+        };
+        let path = String::from_utf8_lossy(&path).to_string();
+        let file_name = path.rsplit('/').next().unwrap_or(&path).to_string();
+        if let Some(index) = self
+            .libraries
+            .iter()
+            .position(|l| l.name.rsplit('/').next() == Some(file_name.as_str()))
+        {
+            return LIBRARY_HANDLE + index as u64;
+        }
+        if let Some(path) = self.find_library(&file_name) {
+            let binary = self.binaries[&path].clone();
+            match self.load_elf(&path, binary.data()) {
+                Ok(index) => return LIBRARY_HANDLE + index as u64,
+                Err(e) => log::debug!("dlopen of {} failed: {:?}", path, e),
+            }
+        }
+        SYSTEM_HANDLE
+    }
+
+    fn dlsym(&mut self, handle: u64, symbol: &str) -> u64 {
+        let library = handle
+            .checked_sub(LIBRARY_HANDLE)
+            .and_then(|index| self.libraries.get(index as usize));
+        if let Some(library) = library {
+            return library.exports.get(symbol).copied().unwrap_or(0);
+        }
+        self.symbol(symbol)
+            .unwrap_or_else(|| self.resolve_import(symbol, false))
+    }
+
+    /// Insertion sort calling back into the emulated comparison function
+    fn sort(
+        &mut self,
+        mut vm: Option<&mut VM>,
+        base: u64,
+        count: u64,
+        size: u64,
+        comparator: u64,
+    ) -> Result<(), NativeException> {
+        for index in 1..count {
+            let mut current = index;
+            while current > 0 {
+                let left = base.wrapping_add((current - 1).wrapping_mul(size));
+                let right = base.wrapping_add(current.wrapping_mul(size));
+                let (order, _) =
+                    self.execute(vm.as_deref_mut(), comparator, &[left, right], &[])?;
+                if (order as i32) <= 0 {
+                    break;
+                }
+                let left_data = self.read_limited(left, size)?;
+                let right_data = self.read_limited(right, size)?;
+                self.memory.write(left, &right_data)?;
+                self.memory.write(right, &left_data)?;
+                current -= 1;
+            }
+        }
+        Ok(())
+    }
+
+    /// A subset of `printf` formatting: flags, width, precision and the common conversions
+    pub(super) fn format(
+        &self,
+        format: u64,
+        arguments: &mut VarArgs,
+    ) -> Result<Vec<u8>, NativeException> {
+        let format = self.c_string(format)?;
+        let mut output = vec![];
+        let mut index = 0;
+        while index < format.len() {
+            let c = format[index];
+            index += 1;
+            if c != b'%' {
+                output.push(c);
+                continue;
+            }
+            let mut left_align = false;
+            let mut zero_pad = false;
+            let mut plus = false;
+            while let Some(flag) = format.get(index) {
+                match flag {
+                    b'-' => left_align = true,
+                    b'0' => zero_pad = true,
+                    b'+' => plus = true,
+                    b' ' | b'#' => {}
+                    _ => break,
+                }
+                index += 1;
+            }
+            let mut width: usize = 0;
+            if format.get(index) == Some(&b'*') {
+                // a negative width argument is a `-` flag
+                let value = arguments.next_int(&self.cpu, &self.memory)? as i32;
+                left_align |= value < 0;
+                width = value.unsigned_abs() as usize;
+                index += 1;
+            }
+            while let Some(digit) = format.get(index).filter(|d| d.is_ascii_digit()) {
+                width = width
+                    .saturating_mul(10)
+                    .saturating_add((digit - b'0') as usize);
+                index += 1;
+            }
+            let width = width.min(MAX_STRING);
+            let mut precision = None;
+            if format.get(index) == Some(&b'.') {
+                index += 1;
+                let mut value: usize = 0;
+                let mut omitted = false;
+                if format.get(index) == Some(&b'*') {
+                    // a negative precision argument is taken as if it was omitted
+                    let argument = arguments.next_int(&self.cpu, &self.memory)? as i32;
+                    omitted = argument < 0;
+                    value = argument.max(0) as usize;
+                    index += 1;
+                }
+                while let Some(digit) = format.get(index).filter(|d| d.is_ascii_digit()) {
+                    value = value
+                        .saturating_mul(10)
+                        .saturating_add((digit - b'0') as usize);
+                    index += 1;
+                }
+                if !omitted {
+                    precision = Some(value.min(MAX_STRING));
+                }
+            }
+            let mut length = 32;
+            while let Some(modifier) = format.get(index) {
+                match modifier {
+                    b'h' => length /= 2,
+                    b'l' | b'z' | b'j' | b't' | b'q' | b'L' => length = 64,
+                    _ => break,
+                }
+                index += 1;
+            }
+            let Some(&conversion) = format.get(index) else {
+                break;
+            };
+            index += 1;
+            let length = length.max(8);
+            let mut text = match conversion {
+                b'd' | b'i' => {
+                    let value = arguments.next_int(&self.cpu, &self.memory)?;
+                    let shift = 64 - length;
+                    let value = ((value << shift) as i64) >> shift;
+                    if plus && value >= 0 {
+                        format!("+{}", value).into_bytes()
+                    } else {
+                        value.to_string().into_bytes()
+                    }
+                }
+                b'u' | b'x' | b'X' | b'o' => {
+                    let value = arguments.next_int(&self.cpu, &self.memory)?;
+                    let value = if length == 64 {
+                        value
+                    } else {
+                        value & ((1 << length) - 1)
+                    };
+                    match conversion {
+                        b'u' => value.to_string(),
+                        b'x' => format!("{:x}", value),
+                        b'X' => format!("{:X}", value),
+                        _ => format!("{:o}", value),
+                    }
+                    .into_bytes()
+                }
+                b'p' => {
+                    let value = arguments.next_int(&self.cpu, &self.memory)?;
+                    format!("0x{:x}", value).into_bytes()
+                }
+                b'c' => vec![arguments.next_int(&self.cpu, &self.memory)? as u8],
+                b's' => {
+                    let address = arguments.next_int(&self.cpu, &self.memory)?;
+                    let mut string = if address == 0 {
+                        b"(null)".to_vec()
+                    } else {
+                        self.c_string(address)?
+                    };
+                    if let Some(precision) = precision {
+                        string.truncate(precision);
+                    }
+                    string
+                }
+                b'f' | b'F' | b'e' | b'E' | b'g' | b'G' | b'a' | b'A' => {
+                    let value = f64::from_bits(arguments.next_double(&self.cpu, &self.memory)?);
+                    let precision = precision.unwrap_or(6);
+                    match conversion {
+                        b'e' | b'E' => format!("{:.*e}", precision, value),
+                        b'g' | b'G' => format!("{}", value),
+                        _ => format!("{:.*}", precision, value),
+                    }
+                    .into_bytes()
+                }
+                b'%' => b"%".to_vec(),
+                b'n' => {
+                    arguments.next_int(&self.cpu, &self.memory)?;
+                    vec![]
+                }
+                other => vec![b'%', other],
+            };
+            if text.len() < width {
+                let padding = width - text.len();
+                if left_align {
+                    text.extend(std::iter::repeat(b' ').take(padding));
+                } else {
+                    let fill = if zero_pad && conversion != b's' {
+                        b'0'
+                    } else {
+                        b' '
+                    };
+                    let sign = if fill == b'0' && matches!(text.first(), Some(b'-') | Some(b'+')) {
+                        Some(text.remove(0))
+                    } else {
+                        None
+                    };
+                    let mut padded: Vec<u8> = sign.into_iter().collect();
+                    padded.extend(std::iter::repeat(fill).take(padding));
+                    padded.extend(text);
+                    text = padded;
+                }
+            }
+            output.extend(text);
+        }
+        Ok(output)
+    }
+}
+
+fn compare(left: &[u8], right: &[u8]) -> u64 {
+    for (l, r) in left.iter().zip(right.iter()) {
+        if l != r {
+            return (*l as i64 - *r as i64) as u64;
+        }
+    }
+    0
+}
+
+/// `strtol` like parsing, returns the value and the number of consumed bytes
+fn parse_integer(string: &[u8], base: u32) -> (i64, usize) {
+    let mut index = 0;
+    while string.get(index).map_or(false, |c| c.is_ascii_whitespace()) {
+        index += 1;
+    }
+    let mut negative = false;
+    if let Some(sign) = string.get(index).filter(|c| **c == b'-' || **c == b'+') {
+        negative = *sign == b'-';
+        index += 1;
+    }
+    let mut base = base;
+    let has_hex_prefix = string.get(index) == Some(&b'0')
+        && matches!(string.get(index + 1), Some(b'x') | Some(b'X'))
+        && string
+            .get(index + 2)
+            .map_or(false, |c| c.is_ascii_hexdigit());
+    if (base == 0 || base == 16) && has_hex_prefix {
+        base = 16;
+        index += 2;
+    } else if base == 0 && string.get(index) == Some(&b'0') {
+        base = 8;
+    } else if base == 0 {
+        base = 10;
+    }
+    let start = index;
+    let mut value: i64 = 0;
+    while let Some(digit) = string.get(index).and_then(|c| (*c as char).to_digit(base)) {
+        value = value.wrapping_mul(base as i64).wrapping_add(digit as i64);
+        index += 1;
+    }
+    if index == start {
+        return (0, 0);
+    }
+    (
+        if negative {
+            value.wrapping_neg()
+        } else {
+            value
+        },
+        index,
+    )
+}
+
+fn ctype_flags(c: u8) -> u8 {
+    let mut flags = 0;
+    if c.is_ascii_uppercase() {
+        flags |= 0x01;
+    }
+    if c.is_ascii_lowercase() {
+        flags |= 0x02;
+    }
+    if c.is_ascii_digit() {
+        flags |= 0x04;
+    }
+    if c.is_ascii_whitespace() || c == 0x0b {
+        flags |= 0x08;
+    }
+    if c.is_ascii_punctuation() {
+        flags |= 0x10;
+    }
+    if c.is_ascii_control() {
+        flags |= 0x20;
+    }
+    if c.is_ascii_hexdigit() {
+        flags |= 0x40;
+    }
+    if c == b' ' {
+        flags |= 0x80;
+    }
+    flags
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_integers_like_strtol() {
+        assert_eq!(parse_integer(b"  42abc", 10), (42, 4));
+        assert_eq!(parse_integer(b"-17", 0), (-17, 3));
+        assert_eq!(parse_integer(b"+0x1F", 0), (31, 5));
+        assert_eq!(parse_integer(b"0x1F", 10), (0, 1));
+        assert_eq!(parse_integer(b"017", 0), (15, 3));
+        assert_eq!(parse_integer(b"zz", 36), (36 * 35 + 35, 2));
+        // a bare prefix only consumes the zero
+        assert_eq!(parse_integer(b"0xg", 16), (0, 1));
+        assert_eq!(parse_integer(b"-", 10), (0, 0));
+        assert_eq!(parse_integer(b"", 10), (0, 0));
+        // overflow wraps instead of panicking
+        assert_eq!(parse_integer(b"99999999999999999999999", 10).1, 23);
+    }
+
+    #[test]
+    fn compares_like_memcmp() {
+        assert_eq!(compare(b"abc", b"abc"), 0);
+        assert_eq!(compare(b"abc", b"abd") as i64, -1);
+        assert_eq!(compare(b"\xff", b"\x00") as i64, 255);
+        assert_eq!(compare(b"ab", b"abc"), 0);
+    }
+}
@@ -0,0 +1,528 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+use std::sync::Arc;
+
+use coeus_models::models::BinaryObject;
+use goblin::elf::{
+    header::EM_AARCH64,
+    program_header::PT_LOAD,
+    reloc::{
+        R_AARCH64_ABS64, R_AARCH64_GLOB_DAT, R_AARCH64_IRELATIVE, R_AARCH64_JUMP_SLOT,
+        R_AARCH64_RELATIVE,
+    },
+    sym::STT_OBJECT,
+    Elf,
+};
+
+use super::{memory::PAGE_SIZE, NativeEmulator, NativeException, HEAP_BASE, LIBRARY_BASE};
+
+const DT_RELRSZ: u64 = 35;
+const DT_RELR: u64 = 36;
+const DT_ANDROID_REL: u64 = 0x6000_000f;
+const DT_ANDROID_RELSZ: u64 = 0x6000_0010;
+const DT_ANDROID_RELA: u64 = 0x6000_0011;
+const DT_ANDROID_RELASZ: u64 = 0x6000_0012;
+const DT_ANDROID_RELR: u64 = 0x6fff_e000;
+const DT_ANDROID_RELRSZ: u64 = 0x6fff_e001;
+/// Segments are mapped completely when loading, larger images are rejected
+const MAX_IMAGE_SIZE: u64 = 0x1000_0000;
+
+/// A library mapped into the emulator
+#[derive(Clone, Debug)]
+pub struct LoadedLibrary {
+    /// Path of the library in the APK
+    pub name: String,
+    pub base: u64,
+    pub size: u64,
+    /// Defined dynamic symbols with their (relocated) address
+    pub exports: HashMap<String, u64>,
+    /// `DT_NEEDED` entries
+    pub needed: Vec<String>,
+}
+
+impl LoadedLibrary {
+    pub fn jni_onload(&self) -> Option<u64> {
+        self.exports.get("JNI_OnLoad").copied()
+    }
+}
+
+/// A relocation in a form independent of its table (`REL`, `RELA` or the packed android format)
+struct Relocation {
+    offset: u64,
+    r_type: u32,
+    symbol: usize,
+    addend: Option<i64>,
+}
+
+impl NativeEmulator {
+    /// Find the path of a library in the available binaries, preferring the `arm64-v8a` build
+    pub fn find_library(&self, name: &str) -> Option<String> {
+        if self.binaries.contains_key(name) {
+            return Some(name.to_string());
+        }
+        let file_name = name.rsplit('/').next().unwrap_or(name);
+        let mut candidates: Vec<&String> = self
+            .binaries
+            .keys()
+            .filter(|path| path.rsplit('/').next() == Some(file_name))
+            .collect();
+        candidates.sort_by_key(|path| (!path.contains("arm64-v8a"), path.len()));
+        candidates.first().map(|path| path.to_string())
+    }
+
+    /// Load a library and its dependencies from `binaries` and run their initializers. Returns the index
+    /// into [`libraries`](Self::libraries). `JNI_OnLoad` is not called, see `VM::load_native_library`.
+    pub fn load_library(
+        &mut self,
+        name: &str,
+        binaries: &HashMap<String, Arc<BinaryObject>>,
+    ) -> Result<usize, NativeException> {
+        for (path, binary) in binaries {
+            self.binaries
+                .entry(path.to_string())
+                .or_insert_with(|| binary.clone());
+        }
+        let path = self
+            .find_library(name)
+            .ok_or_else(|| NativeException::LibraryNotFound(name.to_string()))?;
+        if let Some(index) = self.libraries.iter().position(|l| l.name == path) {
+            return Ok(index);
+        }
+        let binary = self.binaries[&path].clone();
+        self.load_elf(&path, binary.data())
+    }
+
+    /// Map an ELF file, resolve its imports and run its initializers
+    pub fn load_elf(&mut self, name: &str, data: &[u8]) -> Result<usize, NativeException> {
+        let elf = Elf::parse(data).map_err(|e| NativeException::InvalidElf(e.to_string()))?;
+        if elf.header.e_machine != EM_AARCH64 {
+            return Err(NativeException::UnsupportedArchitecture(
+                elf.header.e_machine,
+            ));
+        }
+        let size = image_size(&elf, data)?;
+        let base = self.map_segments(&elf, data, size)?;
+        let exports = elf
+            .dynsyms
+            .iter()
+            .filter(|sym| sym.st_shndx != 0 && sym.st_value != 0)
+            .filter_map(|sym| {
+                let symbol_name = elf.dynstrtab.get_at(sym.st_name)?;
+                Some((symbol_name.to_string(), base.checked_add(sym.st_value)?))
+            })
+            .collect();
+        let needed: Vec<String> = elf.libraries.iter().map(|l| l.to_string()).collect();
+        self.libraries.push(LoadedLibrary {
+            name: name.to_string(),
+            base,
+            size,
+            exports,
+            needed: needed.clone(),
+        });
+        let index = self.libraries.len() - 1;
+
+        let directory = name.rsplit_once('/').map(|(d, _)| d).unwrap_or("");
+        for library in &needed {
+            let path = if directory.is_empty() {
+                library.to_string()
+            } else {
+                format!("{}/{}", directory, library)
+            };
+            if !self.binaries.contains_key(&path) || self.libraries.iter().any(|l| l.name == path) {
+                continue;
+            }
+            let binary = self.binaries[&path].clone();
+            if let Err(e) = self.load_elf(&path, binary.data()) {
+                log::warn!("could not load dependency {}: {:?}", path, e);
+            }
+        }
+
+        self.relocate(&elf, base)?;
+        self.run_initializers(&elf, base);
+        Ok(index)
+    }
+
+    /// Map the segments after the last loaded library. `size` has to be checked by `image_size`.
+    fn map_segments(&mut self, elf: &Elf, data: &[u8], size: u64) -> Result<u64, NativeException> {
+        let next = self
+            .libraries
+            .iter()
+            .map(|l| l.base + l.size)
+            .max()
+            .unwrap_or(LIBRARY_BASE);
+        let base = (next + 0xffff) & !0xffff;
+        if base + size > HEAP_BASE {
+            return Err(NativeException::OutOfMemory);
+        }
+        for header in elf.program_headers.iter().filter(|ph| ph.p_type == PT_LOAD) {
+            let address = base + header.p_vaddr;
+            self.memory
+                .map(address, header.p_memsz.max(header.p_filesz));
+            let start = header.p_offset as usize;
+            let end = start + header.p_filesz as usize;
+            if start < end {
+                self.memory.write(address, &data[start..end])?;
+            }
+        }
+        Ok(base)
+    }
+
+    fn relocate(&mut self, elf: &Elf, base: u64) -> Result<(), NativeException> {
+        let mut relocations: Vec<Relocation> = elf
+            .dynrelas
+            .iter()
+            .chain(elf.dynrels.iter())
+            .chain(elf.pltrelocs.iter())
+            .map(|r| Relocation {
+                offset: r.r_offset,
+                r_type: r.r_type,
+                symbol: r.r_sym,
+                addend: r.r_addend,
+            })
+            .collect();
+        let dynamic_value = |tag: u64| {
+            elf.dynamic
+                .as_ref()
+                .and_then(|d| d.dyns.iter().find(|entry| entry.d_tag == tag))
+                .map(|entry| entry.d_val)
+        };
+        for (table, size, has_addend) in [
+            (DT_ANDROID_RELA, DT_ANDROID_RELASZ, true),
+            (DT_ANDROID_REL, DT_ANDROID_RELSZ, false),
+        ]
+        .iter()
+        {
+            if let (Some(table), Some(size)) = (dynamic_value(*table), dynamic_value(*size)) {
+                if size > MAX_IMAGE_SIZE {
+                    return Err(NativeException::InvalidElf(
+                        "packed relocations exceed the image".to_string(),
+                    ));
+                }
+                let packed = self
+                    .memory
+                    .read_bytes(base.wrapping_add(table), size as usize)?;
+                relocations.extend(decode_packed_relocations(&packed, *has_addend)?);
+            }
+        }
+
+        for relocation in relocations {
+            self.apply_relocation(elf, base, &relocation)?;
+        }
+
+        for (table, size) in [(DT_RELR, DT_RELRSZ), (DT_ANDROID_RELR, DT_ANDROID_RELRSZ)].iter() {
+            if let (Some(table), Some(size)) = (dynamic_value(*table), dynamic_value(*size)) {
+                self.apply_relr(base, base.wrapping_add(table), size)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_relocation(
+        &mut self,
+        elf: &Elf,
+        base: u64,
+        relocation: &Relocation,
+    ) -> Result<(), NativeException> {
+        let target = base.wrapping_add(relocation.offset);
+        let addend = match relocation.addend {
+            Some(addend) => addend as u64,
+            None => self.memory.read_u64(target)?,
+        };
+        let value = match relocation.r_type {
+            R_AARCH64_RELATIVE => base.wrapping_add(addend),
+            R_AARCH64_ABS64 | R_AARCH64_GLOB_DAT | R_AARCH64_JUMP_SLOT => {
+                let address = self.symbol_address(elf, base, relocation.symbol);
+                if relocation.r_type == R_AARCH64_JUMP_SLOT && relocation.addend.is_none() {
+                    address
+                } else {
+                    address.wrapping_add(addend)
+                }
+            }
+            R_AARCH64_IRELATIVE => {
+                let resolver = base.wrapping_add(addend);
+                self.call(resolver, &[0])?
+            }
+            other => {
+                log::debug!(
+                    "unsupported relocation type {} at {:#x}",
+                    other,
+                    relocation.offset
+                );
+                return Ok(());
+            }
+        };
+        self.memory.write_u64(target, value)
+    }
+
+    /// Resolve a symbol referenced by a relocation. Symbols defined in the library itself win, then the
+    /// exports of the other libraries, and finally the libc shim.
+    fn symbol_address(&mut self, elf: &Elf, base: u64, index: usize) -> u64 {
+        if index == 0 {
+            return 0;
+        }
+        let Some(symbol) = elf.dynsyms.get(index) else {
+            return 0;
+        };
+        if symbol.st_shndx != 0 {
+            return base.wrapping_add(symbol.st_value);
+        }
+        let name = elf.dynstrtab.get_at(symbol.st_name).unwrap_or("");
+        if let Some(address) = self.symbol(name) {
+            return address;
+        }
+        self.resolve_import(name, symbol.st_type() == STT_OBJECT)
+    }
+
+    fn apply_relr(&mut self, base: u64, table: u64, size: u64) -> Result<(), NativeException> {
+        let mut next = 0;
+        for entry_index in 0..size / 8 {
+            let entry = self.memory.read_u64(table.wrapping_add(entry_index * 8))?;
+            if entry & 1 == 0 {
+                let target = base.wrapping_add(entry);
+                let value = self.memory.read_u64(target)?;
+                self.memory.write_u64(target, value.wrapping_add(base))?;
+                next = target.wrapping_add(8);
+            } else {
+                let mut bitmap = entry >> 1;
+                let mut target = next;
+                while bitmap != 0 {
+                    if bitmap & 1 == 1 {
+                        let value = self.memory.read_u64(target)?;
+                        self.memory.write_u64(target, value.wrapping_add(base))?;
+                    }
+                    bitmap >>= 1;
+                    target = target.wrapping_add(8);
+                }
+                next = next.wrapping_add(63 * 8);
+            }
+        }
+        Ok(())
+    }
+
+    fn run_initializers(&mut self, elf: &Elf, base: u64) {
+        let Some(dynamic) = &elf.dynamic else {
+            return;
+        };
+        let mut initializers = vec![];
+        if dynamic.info.init != 0 {
+            initializers.push(base.wrapping_add(dynamic.info.init));
+        }
+        let init_array = base.wrapping_add(dynamic.info.init_array);
+        for index in 0..dynamic.info.init_arraysz as u64 / 8 {
+            match self.memory.read_u64(init_array.wrapping_add(index * 8)) {
+                Ok(0) | Ok(u64::MAX) => {}
+                Ok(initializer) => initializers.push(initializer),
+                Err(_) => break,
+            }
+        }
+        for initializer in initializers {
+            if let Err(e) = self.call(initializer, &[]) {
+                log::warn!("initializer at {:#x} failed: {:?}", initializer, e);
+            }
+        }
+    }
+}
+
+/// The page aligned size of the image. Segments have to be contained in the file and the image
+/// in `MAX_IMAGE_SIZE`.
+fn image_size(elf: &Elf, data: &[u8]) -> Result<u64, NativeException> {
+    let invalid = |message: &str| NativeException::InvalidElf(message.to_string());
+    let mut size = 0;
+    for header in elf.program_headers.iter().filter(|ph| ph.p_type == PT_LOAD) {
+        let file_end = header
+            .p_offset
+            .checked_add(header.p_filesz)
+            .ok_or_else(|| invalid("segment offset overflows"))?;
+        if file_end > data.len() as u64 {
+            return Err(invalid("segment exceeds the file"));
+        }
+        let end = header
+            .p_vaddr
+            .checked_add(header.p_memsz.max(header.p_filesz))
+            .filter(|end| *end <= MAX_IMAGE_SIZE)
+            .ok_or_else(|| invalid("segment exceeds the maximal image size"))?;
+        size = size.max(end);
+    }
+    Ok((size + PAGE_SIZE - 1) & !(PAGE_SIZE - 1))
+}
+
+/// Decode the `APS2` packed relocation format of the android linker
+fn decode_packed_relocations(
+    data: &[u8],
+    has_addend: bool,
+) -> Result<Vec<Relocation>, NativeException> {
+    const GROUPED_BY_INFO: i64 = 1;
+    const GROUPED_BY_OFFSET_DELTA: i64 = 2;
+    const GROUPED_BY_ADDEND: i64 = 4;
+    const GROUP_HAS_ADDEND: i64 = 8;
+
+    let invalid = || NativeException::InvalidElf("invalid packed relocations".to_string());
+    if !data.starts_with(b"APS2") {
+        return Err(invalid());
+    }
+    let mut position = 4;
+    let mut next = || -> Result<i64, NativeException> {
+        let mut result = 0i64;
+        let mut shift = 0;
+        loop {
+            let byte = *data.get(position).ok_or_else(invalid)?;
+            position += 1;
+            result |= ((byte & 0x7f) as i64) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                if shift < 64 && byte & 0x40 != 0 {
+                    result |= -1i64 << shift;
+                }
+                return Ok(result);
+            }
+            if shift >= 64 {
+                return Err(invalid());
+            }
+        }
+    };
+    let count = next()?;
+    // every relocation writes a distinct slot of the image
+    if !(0..=(MAX_IMAGE_SIZE / 8) as i64).contains(&count) {
+        return Err(invalid());
+    }
+    let mut offset = next()? as u64;
+    let mut info = 0;
+    let mut addend = 0i64;
+    let mut relocations = Vec::with_capacity(count as usize);
+    while (relocations.len() as i64) < count {
+        let group_size = next()?;
+        let flags = next()?;
+        let offset_delta = if flags & GROUPED_BY_OFFSET_DELTA != 0 {
+            next()?
+        } else {
+            0
+        };
+        if flags & GROUPED_BY_INFO != 0 {
+            info = next()?;
+        }
+        let group_has_addend = flags & GROUP_HAS_ADDEND != 0;
+        if group_has_addend && flags & GROUPED_BY_ADDEND != 0 {
+            addend = addend.wrapping_add(next()?);
+        } else if !group_has_addend {
+            addend = 0;
+        }
+        if group_size < 0 || group_size > count - relocations.len() as i64 {
+            return Err(invalid());
+        }
+        for _ in 0..group_size {
+            offset = if flags & GROUPED_BY_OFFSET_DELTA != 0 {
+                offset.wrapping_add(offset_delta as u64)
+            } else {
+                offset.wrapping_add(next()? as u64)
+            };
+            if flags & GROUPED_BY_INFO == 0 {
+                info = next()?;
+            }
+            if group_has_addend && flags & GROUPED_BY_ADDEND == 0 {
+                addend = addend.wrapping_add(next()?);
+            }
+            relocations.push(Relocation {
+                offset,
+                r_type: (info as u64 & 0xffff_ffff) as u32,
+                symbol: (info as u64 >> 32) as usize,
+                addend: if has_addend { Some(addend) } else { None },
+            });
+        }
+    }
+    Ok(relocations)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// An AArch64 shared object with a single loadable segment
+    fn elf(offset: u64, address: u64, file_size: u64, memory_size: u64) -> Vec<u8> {
+        let mut data = vec![0u8; 0x200];
+        let mut put = |position: usize, bytes: &[u8]| {
+            data[position..position + bytes.len()].copy_from_slice(bytes);
+        };
+        put(0, b"\x7fELF\x02\x01\x01");
+        put(16, &[3, 0, 0xb7, 0, 1, 0, 0, 0]);
+        put(32, &64u64.to_le_bytes());
+        put(52, &[64, 0, 56, 0, 1, 0, 64, 0]);
+        put(64, &[1, 0, 0, 0, 5, 0, 0, 0]);
+        put(72, &offset.to_le_bytes());
+        put(80, &address.to_le_bytes());
+        put(96, &file_size.to_le_bytes());
+        put(104, &memory_size.to_le_bytes());
+        put(112, &0x1000u64.to_le_bytes());
+        put(0x100, b"\xc0\x03\x5f\xd6");
+        data
+    }
+
+    fn is_invalid(result: Result<usize, NativeException>) -> bool {
+        matches!(result, Err(NativeException::InvalidElf(_)))
+    }
+
+    #[test]
+    fn loads_segments() {
+        let mut emulator = NativeEmulator::new();
+        let index = emulator
+            .load_elf("lib.so", &elf(0, 0, 0x200, 0x3000))
+            .unwrap();
+        let library = &emulator.libraries()[index];
+        assert_eq!(library.size, 0x3000);
+        assert_eq!(
+            emulator.memory().read_u32(library.base + 0x100).unwrap(),
+            0xd65f_03c0
+        );
+        assert!(emulator.memory().is_mapped(library.base + 0x2fff));
+    }
+
+    #[test]
+    fn rejects_oversized_segments() {
+        let mut emulator = NativeEmulator::new();
+        assert!(is_invalid(
+            emulator.load_elf("lib.so", &elf(0, 0, 0x200, u64::MAX))
+        ));
+        assert!(is_invalid(
+            emulator.load_elf("lib.so", &elf(0, u64::MAX - 0x100, 0x200, 0x200))
+        ));
+        assert!(is_invalid(
+            emulator.load_elf("lib.so", &elf(0, 0, 0x200, MAX_IMAGE_SIZE + 1))
+        ));
+        // larger than the file
+        assert!(is_invalid(
+            emulator.load_elf("lib.so", &elf(0, 0, 0x1000, 0x1000))
+        ));
+        assert!(is_invalid(
+            emulator.load_elf("lib.so", &elf(u64::MAX, 0, 0x200, 0x200))
+        ));
+        assert!(emulator.libraries().is_empty());
+    }
+
+    #[test]
+    fn decodes_packed_relocations() {
+        // one group of a single relative relocation at 0x1008
+        let data = b"APS2\x01\x80\x20\x01\x03\x08\x83\x08";
+        let relocations = decode_packed_relocations(data, true).unwrap();
+        assert_eq!(relocations.len(), 1);
+        assert_eq!(relocations[0].offset, 0x1008);
+        assert_eq!(relocations[0].r_type, R_AARCH64_RELATIVE);
+        assert_eq!(relocations[0].addend, Some(0));
+    }
+
+    #[test]
+    fn rejects_malformed_packed_relocations() {
+        assert!(decode_packed_relocations(b"APS1\x00\x00", true).is_err());
+        // truncated
+        assert!(decode_packed_relocations(b"APS2\x01\x80", true).is_err());
+        // a huge count
+        assert!(decode_packed_relocations(b"APS2\xff\xff\xff\xff\x0f\x00", true).is_err());
+        // a negative count
+        assert!(decode_packed_relocations(b"APS2\x7f\x00", true).is_err());
+        // a group larger than the count, without any further data per relocation
+        assert!(decode_packed_relocations(b"APS2\x01\x00\xff\x0f\x03\x08\x83\x08", true).is_err());
+    }
+}
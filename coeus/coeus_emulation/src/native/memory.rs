@@ -0,0 +1,207 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+use std::collections::HashMap;
+
+use super::NativeException;
+
+pub const PAGE_SIZE: u64 = 0x1000;
+
+/// Sparse memory of the emulated process. Pages are allocated on `map` and unmapped accesses fail
+/// with `NativeException::UnmappedMemory`. There are no permissions, code may write to `.text`.
+#[derive(Clone, Default)]
+pub struct Memory {
+    pages: HashMap<u64, Box<[u8]>>,
+}
+
+impl Memory {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Map (zeroed) pages covering `address..address + size`. Already mapped pages are kept.
+    pub fn map(&mut self, address: u64, size: u64) {
+        let start = address & !(PAGE_SIZE - 1);
+        let end = address.saturating_add(size);
+        let mut page = start;
+        while page < end {
+            self.pages
+                .entry(page)
+                .or_insert_with(|| vec![0; PAGE_SIZE as usize].into_boxed_slice());
+            match page.checked_add(PAGE_SIZE) {
+                Some(next) => page = next,
+                None => break,
+            }
+        }
+    }
+
+    pub fn unmap(&mut self, address: u64, size: u64) {
+        let start = address & !(PAGE_SIZE - 1);
+        let end = address.saturating_add(size);
+        let mut page = start;
+        while page < end {
+            self.pages.remove(&page);
+            match page.checked_add(PAGE_SIZE) {
+                Some(next) => page = next,
+                None => break,
+            }
+        }
+    }
+
+    pub fn is_mapped(&self, address: u64) -> bool {
+        self.pages.contains_key(&(address & !(PAGE_SIZE - 1)))
+    }
+
+    pub fn read(&self, address: u64, buffer: &mut [u8]) -> Result<(), NativeException> {
+        let mut done = 0;
+        while done < buffer.len() {
+            let current = address.wrapping_add(done as u64);
+            let page = self
+                .pages
+                .get(&(current & !(PAGE_SIZE - 1)))
+                .ok_or(NativeException::UnmappedMemory(current))?;
+            let offset = (current & (PAGE_SIZE - 1)) as usize;
+            let count = (PAGE_SIZE as usize - offset).min(buffer.len() - done);
+            buffer[done..done + count].copy_from_slice(&page[offset..offset + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    pub fn write(&mut self, address: u64, data: &[u8]) -> Result<(), NativeException> {
+        let mut done = 0;
+        while done < data.len() {
+            let current = address.wrapping_add(done as u64);
+            let page = self
+                .pages
+                .get_mut(&(current & !(PAGE_SIZE - 1)))
+                .ok_or(NativeException::UnmappedMemory(current))?;
+            let offset = (current & (PAGE_SIZE - 1)) as usize;
+            let count = (PAGE_SIZE as usize - offset).min(data.len() - done);
+            page[offset..offset + count].copy_from_slice(&data[done..done + count]);
+            done += count;
+        }
+        Ok(())
+    }
+
+    pub fn read_bytes(&self, address: u64, size: usize) -> Result<Vec<u8>, NativeException> {
+        let mut buffer = vec![0; size];
+        self.read(address, &mut buffer)?;
+        Ok(buffer)
+    }
+
+    /// Read `size` bytes (1, 2, 4 or 8) as little endian value
+    pub fn read_uint(&self, address: u64, size: usize) -> Result<u64, NativeException> {
+        let mut buffer = [0; 8];
+        self.read(address, &mut buffer[..size])?;
+        Ok(u64::from_le_bytes(buffer))
+    }
+
+    pub fn write_uint(
+        &mut self,
+        address: u64,
+        value: u64,
+        size: usize,
+    ) -> Result<(), NativeException> {
+        self.write(address, &value.to_le_bytes()[..size])
+    }
+
+    pub fn read_u64(&self, address: u64) -> Result<u64, NativeException> {
+        self.read_uint(address, 8)
+    }
+
+    pub fn write_u64(&mut self, address: u64, value: u64) -> Result<(), NativeException> {
+        self.write_uint(address, value, 8)
+    }
+
+    pub fn read_u32(&self, address: u64) -> Result<u32, NativeException> {
+        Ok(self.read_uint(address, 4)? as u32)
+    }
+
+    /// Read a nul terminated string, at most `limit` bytes
+    pub fn read_c_string(&self, address: u64, limit: usize) -> Result<Vec<u8>, NativeException> {
+        let mut result = vec![];
+        let mut byte = [0];
+        while result.len() < limit {
+            self.read(address.wrapping_add(result.len() as u64), &mut byte)?;
+            if byte[0] == 0 {
+                break;
+            }
+            result.push(byte[0]);
+        }
+        Ok(result)
+    }
+
+    pub fn write_c_string(&mut self, address: u64, string: &[u8]) -> Result<(), NativeException> {
+        self.write(address, string)?;
+        self.write(address.wrapping_add(string.len() as u64), &[0])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn accesses_cross_page_boundaries() {
+        let mut memory = Memory::new();
+        memory.map(0x1ffc, 8);
+        assert!(memory.is_mapped(0x1000));
+        assert!(memory.is_mapped(0x2000));
+        memory.write_u64(0x1ffc, 0x1122_3344_5566_7788).unwrap();
+        assert_eq!(memory.read_u64(0x1ffc).unwrap(), 0x1122_3344_5566_7788);
+        assert_eq!(memory.read_u32(0x2000).unwrap(), 0x1122_3344);
+        assert_eq!(memory.read_uint(0x1ffc, 2).unwrap(), 0x7788);
+    }
+
+    #[test]
+    fn unmapped_accesses_fail() {
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x1000);
+        assert!(matches!(
+            memory.read_u64(0x1ffc),
+            Err(NativeException::UnmappedMemory(0x2000))
+        ));
+        assert!(matches!(
+            memory.write(0x800, &[1]),
+            Err(NativeException::UnmappedMemory(0x800))
+        ));
+        memory.unmap(0x1000, 1);
+        assert!(!memory.is_mapped(0x1000));
+        assert!(memory.read_bytes(0x1000, 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn maps_the_last_page_of_the_address_space() {
+        let mut memory = Memory::new();
+        memory.map(u64::MAX - 0x10, 0x100);
+        assert!(memory.is_mapped(u64::MAX));
+        assert_eq!(memory.pages.len(), 1);
+        memory.write_c_string(u64::MAX - 3, b"abc").unwrap();
+        assert_eq!(memory.read_c_string(u64::MAX - 3, 16).unwrap(), b"abc");
+        memory.write(u64::MAX, b"d").unwrap();
+        assert!(matches!(
+            memory.read_c_string(u64::MAX - 3, 16),
+            Err(NativeException::UnmappedMemory(0))
+        ));
+        assert!(matches!(
+            memory.read_u64(u64::MAX - 3),
+            Err(NativeException::UnmappedMemory(0))
+        ));
+        memory.unmap(u64::MAX, u64::MAX);
+        assert!(!memory.is_mapped(u64::MAX));
+    }
+
+    #[test]
+    fn c_strings_are_limited() {
+        let mut memory = Memory::new();
+        memory.map(0x1000, 0x1000);
+        memory.write_c_string(0x1000, b"hello").unwrap();
+        assert_eq!(memory.read_c_string(0x1000, 3).unwrap(), b"hel");
+        assert_eq!(memory.read_c_string(0x1000, 100).unwrap(), b"hello");
+        assert_eq!(memory.read_c_string(0x1005, 100).unwrap(), b"");
+    }
+}
@@ -0,0 +1,591 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Emulation of native (AArch64) libraries. Libraries are mapped with their relocations applied, imports
+//! are resolved against the other loaded libraries and a minimal libc/libdl shim, and a fake `JNIEnv` and
+//! `JavaVM` are wired to the heap of the dex [`VM`](crate::vm::VM). This is enough to run `JNI_OnLoad`
+//! and typical string decryption routines, it is not a full Android process: there are no threads, no
+//! signals and no file system.
+
+use std::collections::{HashMap, HashSet};
+use std::sync::Arc;
+
+use coeus_models::models::BinaryObject;
+
+use crate::vm::VM;
+
+pub mod cpu;
+mod jni;
+mod libc;
+mod loader;
+pub mod memory;
+
+pub use jni::RegisteredNative;
+pub use loader::LoadedLibrary;
+
+use cpu::{Cpu, Step};
+use jni::JniState;
+use libc::LibcState;
+use memory::Memory;
+
+/// Libraries are mapped one after the other starting at this address
+const LIBRARY_BASE: u64 = 0x4000_0000;
+const HEAP_BASE: u64 = 0x8_0000_0000;
+const HEAP_SIZE: u64 = 0x1000_0000;
+const STACK_TOP: u64 = 0xc_0000_0000;
+const STACK_SIZE: u64 = 0x80_0000;
+const TLS_BASE: u64 = 0xd_0000_0000;
+const JNI_BASE: u64 = 0xe_0000_0000;
+/// Imported functions are resolved to addresses in this range, each gets one (`ret`) instruction
+const IMPORT_HOOKS: u64 = 0xf_0000_0000;
+const JNI_ENV_HOOKS: u64 = 0xf_1000_0000;
+const JAVA_VM_HOOKS: u64 = 0xf_2000_0000;
+const HOOKS_END: u64 = 0xf_f000_0000;
+/// Return address of calls started from Rust, reaching it ends the emulation
+const RETURN_TRAP: u64 = 0xf_f000_0000;
+const STACK_GUARD: u64 = 0x5eed_c0de_dead_beef;
+const DEFAULT_INSTRUCTION_LIMIT: u64 = 50_000_000;
+
+#[derive(Debug, Clone)]
+pub enum NativeException {
+    UnmappedMemory(u64),
+    InvalidProgramCounter(u64),
+    UnsupportedInstruction(u64, u32),
+    SystemCall(u32),
+    Breakpoint(u64, u32),
+    InvalidElf(String),
+    UnsupportedArchitecture(u16),
+    LibraryNotFound(String),
+    SymbolNotFound(String),
+    InstructionLimitReached(u64),
+    OutOfMemory,
+    /// The native code called `abort`, `__stack_chk_fail` or similar
+    Abort(String),
+    /// A JNI function could not be served, e.g. because no dex VM is attached
+    Jni(String),
+}
+
+/// Variadic arguments, either still in registers and on the stack (`...`) or behind a `va_list`
+pub(crate) enum VarArgs {
+    Registers {
+        general: usize,
+        vector: usize,
+        stack: u64,
+    },
+    List {
+        stack: u64,
+        general_top: u64,
+        vector_top: u64,
+        general_offset: i32,
+        vector_offset: i32,
+    },
+}
+
+impl VarArgs {
+    /// The variadic arguments of the current call, after `fixed` named integer arguments
+    pub(crate) fn after(cpu: &Cpu, fixed: usize) -> Self {
+        VarArgs::Registers {
+            general: fixed,
+            vector: 0,
+            stack: cpu.sp,
+        }
+    }
+
+    /// Read an AArch64 `va_list` structure
+    pub(crate) fn from_va_list(memory: &Memory, address: u64) -> Result<Self, NativeException> {
+        Ok(VarArgs::List {
+            stack: memory.read_u64(address)?,
+            general_top: memory.read_u64(address.wrapping_add(8))?,
+            vector_top: memory.read_u64(address.wrapping_add(16))?,
+            general_offset: memory.read_u32(address.wrapping_add(24))? as i32,
+            vector_offset: memory.read_u32(address.wrapping_add(28))? as i32,
+        })
+    }
+
+    pub(crate) fn next_int(&mut self, cpu: &Cpu, memory: &Memory) -> Result<u64, NativeException> {
+        match self {
+            VarArgs::Registers { general, stack, .. } => {
+                if *general < 8 {
+                    *general += 1;
+                    Ok(cpu.x[*general - 1])
+                } else {
+                    let value = memory.read_u64(*stack)?;
+                    *stack = stack.wrapping_add(8);
+                    Ok(value)
+                }
+            }
+            VarArgs::List {
+                stack,
+                general_top,
+                general_offset,
+                ..
+            } => {
+                if *general_offset < 0 {
+                    let value =
+                        memory.read_u64(general_top.wrapping_add(*general_offset as i64 as u64))?;
+                    *general_offset += 8;
+                    Ok(value)
+                } else {
+                    let value = memory.read_u64(*stack)?;
+                    *stack = stack.wrapping_add(8);
+                    Ok(value)
+                }
+            }
+        }
+    }
+
+    /// The raw bits of the next floating point argument (`float` is promoted to `double`)
+    pub(crate) fn next_double(
+        &mut self,
+        cpu: &Cpu,
+        memory: &Memory,
+    ) -> Result<u64, NativeException> {
+        match self {
+            VarArgs::Registers { vector, stack, .. } => {
+                if *vector < 8 {
+                    *vector += 1;
+                    Ok(cpu.q[*vector - 1] as u64)
+                } else {
+                    let value = memory.read_u64(*stack)?;
+                    *stack = stack.wrapping_add(8);
+                    Ok(value)
+                }
+            }
+            VarArgs::List {
+                stack,
+                vector_top,
+                vector_offset,
+                ..
+            } => {
+                if *vector_offset < 0 {
+                    let value =
+                        memory.read_u64(vector_top.wrapping_add(*vector_offset as i64 as u64))?;
+                    *vector_offset += 16;
+                    Ok(value)
+                } else {
+                    let value = memory.read_u64(*stack)?;
+                    *stack = stack.wrapping_add(8);
+                    Ok(value)
+                }
+            }
+        }
+    }
+}
+
+/// Bump allocator backing `malloc` and friends, `free` does not reuse memory
+#[derive(Clone)]
+struct Heap {
+    next: u64,
+    allocations: HashMap<u64, u64>,
+}
+
+/// An AArch64 process with a fake JNI environment
+#[derive(Clone)]
+pub struct NativeEmulator {
+    cpu: Cpu,
+    memory: Memory,
+    libraries: Vec<LoadedLibrary>,
+    /// Binaries `dlopen` and `DT_NEEDED` entries are resolved against
+    binaries: HashMap<String, Arc<BinaryObject>>,
+    imports: Vec<String>,
+    import_addresses: HashMap<String, u64>,
+    unresolved_imports: HashSet<String>,
+    heap: Heap,
+    jni: JniState,
+    libc: LibcState,
+    log: Vec<String>,
+    instruction_limit: u64,
+}
+
+impl Default for NativeEmulator {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl NativeEmulator {
+    pub fn new() -> Self {
+        let mut emulator = NativeEmulator {
+            cpu: Cpu::new(),
+            memory: Memory::new(),
+            libraries: vec![],
+            binaries: HashMap::new(),
+            imports: vec![],
+            import_addresses: HashMap::new(),
+            unresolved_imports: HashSet::new(),
+            heap: Heap {
+                next: HEAP_BASE,
+                allocations: HashMap::new(),
+            },
+            jni: JniState::default(),
+            libc: LibcState::default(),
+            log: vec![],
+            instruction_limit: DEFAULT_INSTRUCTION_LIMIT,
+        };
+        emulator.memory.map(STACK_TOP - STACK_SIZE, STACK_SIZE);
+        emulator.cpu.sp = STACK_TOP - 0x100;
+
+        // bionic keeps the thread pointer, the thread id, errno and the stack guard in the first TLS slots
+        emulator.memory.map(TLS_BASE, 0x2000);
+        let thread_pointer = TLS_BASE + 0x1000;
+        emulator.cpu.tpidr = thread_pointer;
+        let _ = emulator.memory.write_u64(thread_pointer, thread_pointer);
+        let _ = emulator.memory.write_u64(thread_pointer + 8, 1);
+        let _ = emulator
+            .memory
+            .write_u64(thread_pointer + 0x28, STACK_GUARD);
+
+        emulator.memory.map(RETURN_TRAP, 4);
+        emulator.setup_jni();
+        emulator
+    }
+
+    /// Maximum number of instructions a single call may execute
+    pub fn set_instruction_limit(&mut self, limit: u64) {
+        self.instruction_limit = limit;
+    }
+
+    pub fn cpu(&self) -> &Cpu {
+        &self.cpu
+    }
+
+    pub fn memory(&self) -> &Memory {
+        &self.memory
+    }
+
+    pub fn memory_mut(&mut self) -> &mut Memory {
+        &mut self.memory
+    }
+
+    pub fn libraries(&self) -> &[LoadedLibrary] {
+        &self.libraries
+    }
+
+    /// Imports which are neither exported by a loaded library nor provided by the shim, calls to them return 0
+    pub fn unresolved_imports(&self) -> &HashSet<String> {
+        &self.unresolved_imports
+    }
+
+    /// Messages written with `__android_log_print` and the stdio functions
+    pub fn log(&self) -> &[String] {
+        &self.log
+    }
+
+    /// Address of a symbol exported by any of the loaded libraries
+    pub fn symbol(&self, name: &str) -> Option<u64> {
+        self.libraries
+            .iter()
+            .find_map(|library| library.exports.get(name).copied())
+    }
+
+    /// The loaded library containing `address`
+    pub fn library_at(&self, address: u64) -> Option<&LoadedLibrary> {
+        self.libraries
+            .iter()
+            .find(|library| (library.base..library.base + library.size).contains(&address))
+    }
+
+    /// Allocate `size` bytes of zeroed memory on the native heap
+    pub fn malloc(&mut self, size: u64) -> Result<u64, NativeException> {
+        let size = size.max(1);
+        let address = self.heap.next;
+        let next = address
+            .checked_add(size)
+            .and_then(|end| end.checked_add(15))
+            .ok_or(NativeException::OutOfMemory)?
+            & !15;
+        if next > HEAP_BASE + HEAP_SIZE {
+            return Err(NativeException::OutOfMemory);
+        }
+        self.memory.map(address, size);
+        self.heap.next = next;
+        self.heap.allocations.insert(address, size);
+        Ok(address)
+    }
+
+    pub fn free(&mut self, address: u64) {
+        self.heap.allocations.remove(&address);
+    }
+
+    /// Copy a nul terminated string to the native heap
+    pub fn alloc_c_string(&mut self, string: &[u8]) -> Result<u64, NativeException> {
+        let address = self.malloc(string.len() as u64 + 1)?;
+        self.memory.write_c_string(address, string)?;
+        Ok(address)
+    }
+
+    /// Call the function at `address` with (up to eight register and further stack) integer arguments.
+    /// JNI functions are not available, use [`call_with_vm`](Self::call_with_vm) for code using the `JNIEnv`.
+    pub fn call(&mut self, address: u64, arguments: &[u64]) -> Result<u64, NativeException> {
+        self.execute(None, address, arguments, &[])
+            .map(|(result, _)| result)
+    }
+
+    /// Call the function at `address` with the dex VM attached to the fake `JNIEnv`
+    pub fn call_with_vm(
+        &mut self,
+        vm: &mut VM,
+        address: u64,
+        arguments: &[u64],
+    ) -> Result<u64, NativeException> {
+        self.execute(Some(vm), address, arguments, &[])
+            .map(|(result, _)| result)
+    }
+
+    /// Run a function and return `x0` and `v0`. The CPU state is restored afterwards, which makes calls
+    /// from within hooks (e.g. `pthread_once`) possible.
+    pub(crate) fn execute(
+        &mut self,
+        vm: Option<&mut VM>,
+        address: u64,
+        arguments: &[u64],
+        float_arguments: &[u64],
+    ) -> Result<(u64, u128), NativeException> {
+        let saved = self.cpu.clone();
+        let stack_arguments = arguments.len().saturating_sub(8) as u64;
+        let sp = (self.cpu.sp - 0x100 - stack_arguments * 8) & !0xf;
+        if sp < STACK_TOP - STACK_SIZE + 0x1000 {
+            return Err(NativeException::OutOfMemory);
+        }
+        for (index, argument) in arguments.iter().enumerate() {
+            if index < 8 {
+                self.cpu.x[index] = *argument;
+            } else {
+                self.memory
+                    .write_u64(sp + (index as u64 - 8) * 8, *argument)?;
+            }
+        }
+        for (index, argument) in float_arguments.iter().take(8).enumerate() {
+            self.cpu.q[index] = *argument as u128;
+        }
+        self.cpu.sp = sp;
+        self.cpu.x[30] = RETURN_TRAP;
+        self.cpu.pc = address;
+        let result = self.run(vm);
+        let returned = (self.cpu.x[0], self.cpu.q[0]);
+        let ticks = self.cpu.ticks();
+        if let Err(exception) = &result {
+            log::debug!(
+                "native execution failed at {:#x} after {} instructions: {:?}",
+                self.cpu.pc,
+                ticks,
+                exception
+            );
+        }
+        self.cpu = saved;
+        result.map(|_| returned)
+    }
+
+    fn run(&mut self, mut vm: Option<&mut VM>) -> Result<(), NativeException> {
+        let start = self.cpu.ticks();
+        loop {
+            let pc = self.cpu.pc;
+            if pc == RETURN_TRAP {
+                return Ok(());
+            }
+            if (IMPORT_HOOKS..HOOKS_END).contains(&pc) {
+                let return_address = self.cpu.x[30];
+                self.dispatch_hook(pc, vm.as_deref_mut())?;
+                self.cpu.pc = return_address;
+                continue;
+            }
+            if self.cpu.ticks() - start > self.instruction_limit {
+                return Err(NativeException::InstructionLimitReached(pc));
+            }
+            if let Step::Trap(code) = self.cpu.step(&mut self.memory)? {
+                return Err(NativeException::Breakpoint(pc, code));
+            }
+        }
+    }
+
+    fn dispatch_hook(&mut self, pc: u64, vm: Option<&mut VM>) -> Result<(), NativeException> {
+        let index = ((pc & 0x0fff_ffff) / 4) as usize;
+        match pc & !0x0fff_ffff {
+            IMPORT_HOOKS => {
+                let name = self
+                    .imports
+                    .get(index)
+                    .cloned()
+                    .ok_or(NativeException::InvalidProgramCounter(pc))?;
+                self.call_import(&name, vm)
+            }
+            JNI_ENV_HOOKS => self.call_jni_function(index, vm),
+            JAVA_VM_HOOKS => self.call_java_vm_function(index),
+            _ => Err(NativeException::InvalidProgramCounter(pc)),
+        }
+    }
+
+    /// Address of the hook for an imported function, allocated on first use
+    fn import_hook(&mut self, name: &str) -> u64 {
+        if let Some(address) = self.import_addresses.get(name) {
+            return *address;
+        }
+        let address = IMPORT_HOOKS + self.imports.len() as u64 * 4;
+        self.imports.push(name.to_string());
+        self.import_addresses.insert(name.to_string(), address);
+        self.memory.map(address, 4);
+        // a `ret`, in case the code inspects its imports
+        let _ = self.memory.write_uint(address, 0xd65f_03c0, 4);
+        address
+    }
+
+    /// Set the return value of the hooked function
+    fn set_return(&mut self, value: u64) {
+        self.cpu.x[0] = value;
+    }
+
+    fn argument(&self, index: usize) -> u64 {
+        self.cpu.x[index]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn malloc_rejects_sizes_beyond_the_heap() {
+        let mut emulator = NativeEmulator::new();
+        let address = emulator.malloc(16).unwrap();
+        assert_eq!(address, HEAP_BASE);
+        assert_eq!(emulator.malloc(1).unwrap(), HEAP_BASE + 16);
+        assert!(matches!(
+            emulator.malloc(u64::MAX),
+            Err(NativeException::OutOfMemory)
+        ));
+        assert!(matches!(
+            emulator.malloc(u64::MAX - 20),
+            Err(NativeException::OutOfMemory)
+        ));
+        assert!(matches!(
+            emulator.malloc(HEAP_SIZE),
+            Err(NativeException::OutOfMemory)
+        ));
+    }
+
+    #[test]
+    fn accesses_at_the_end_of_the_address_space_fail() {
+        let emulator = NativeEmulator::new();
+        let mut buffer = [0; 8];
+        assert!(matches!(
+            emulator.memory().read(u64::MAX - 2, &mut buffer),
+            Err(NativeException::UnmappedMemory(_))
+        ));
+    }
+
+    /// Copy instructions to the heap and return their address
+    fn code(emulator: &mut NativeEmulator, instructions: &[u32]) -> u64 {
+        let bytes: Vec<u8> = instructions.iter().flat_map(|i| i.to_le_bytes()).collect();
+        let address = emulator.malloc(bytes.len() as u64).unwrap();
+        emulator.memory_mut().write(address, &bytes).unwrap();
+        address
+    }
+
+    #[test]
+    fn calls_emulated_functions() {
+        let mut emulator = NativeEmulator::new();
+        // add x0, x0, x1; ret
+        let add = code(&mut emulator, &[0x8b01_0000, 0xd65f_03c0]);
+        assert_eq!(emulator.call(add, &[2, 3]).unwrap(), 5);
+        // b .
+        let spin = code(&mut emulator, &[0x1400_0000]);
+        emulator.set_instruction_limit(1000);
+        assert!(matches!(
+            emulator.call(spin, &[]),
+            Err(NativeException::InstructionLimitReached(_))
+        ));
+        assert!(matches!(
+            emulator.call(0x1234_0000, &[]),
+            Err(NativeException::InvalidProgramCounter(0x1234_0000))
+        ));
+    }
+
+    #[test]
+    fn calls_string_imports() {
+        let mut emulator = NativeEmulator::new();
+        let strlen = emulator.import_hook("strlen");
+        let strtol = emulator.import_hook("strtol");
+        let strncpy = emulator.import_hook("strncpy");
+        let hello = emulator.alloc_c_string(b"hello").unwrap();
+        assert_eq!(emulator.call(strlen, &[hello]).unwrap(), 5);
+
+        let number = emulator.alloc_c_string(b" -0x1fz").unwrap();
+        let end = emulator.malloc(8).unwrap();
+        assert_eq!(
+            emulator.call(strtol, &[number, end, 0]).unwrap() as i64,
+            -31
+        );
+        assert_eq!(emulator.memory().read_u64(end).unwrap(), number + 6);
+
+        let buffer = emulator.malloc(8).unwrap();
+        assert!(matches!(
+            emulator.call(strncpy, &[buffer, hello, u64::MAX]),
+            Err(NativeException::OutOfMemory)
+        ));
+        assert!(emulator.call(strlen, &[0x10]).is_err());
+    }
+
+    #[test]
+    fn formats_with_snprintf() {
+        let mut emulator = NativeEmulator::new();
+        let snprintf = emulator.import_hook("snprintf");
+        let buffer = emulator.malloc(32).unwrap();
+        let key = emulator.alloc_c_string(b"key").unwrap();
+        let mut print = |format: &[u8], size: u64, arguments: &[u64]| {
+            let format = emulator.alloc_c_string(format).unwrap();
+            let mut all = vec![buffer, size, format];
+            all.extend_from_slice(arguments);
+            let length = emulator.call(snprintf, &all).unwrap();
+            let output = emulator.memory().read_c_string(buffer, 32).unwrap();
+            (length, String::from_utf8(output).unwrap())
+        };
+        assert_eq!(print(b"%s=%d", 32, &[key, 42]), (6, "key=42".to_string()));
+        assert_eq!(print(b"%s=%d", 4, &[key, 42]), (6, "key".to_string()));
+        assert_eq!(
+            print(b"%05d|%-4x|%.2s|%%", 32, &[(-42i64) as u64, 255, key]),
+            (15, "-0042|ff  |ke|%".to_string())
+        );
+        assert_eq!(
+            print(b"%*d|", 32, &[(-3i64) as u64, 7]),
+            (4, "7  |".to_string())
+        );
+        assert_eq!(
+            print(b"%hhu %lld", 32, &[0x1ff, u64::MAX]),
+            (6, "255 -1".to_string())
+        );
+        assert_eq!(
+            print(b"%.*s", 32, &[(-1i64) as u64, key]),
+            (3, "key".to_string())
+        );
+        // widths are clamped instead of overflowing
+        let (length, _) = print(b"%99999999999999999999999d", 32, &[1]);
+        assert_eq!(length, 0x10_0000);
+    }
+
+    #[test]
+    fn sorts_with_an_emulated_comparator() {
+        let mut emulator = NativeEmulator::new();
+        let qsort = emulator.import_hook("qsort");
+        // ldr w8, [x0]; ldr w9, [x1]; sub w0, w8, w9; ret
+        let comparator = code(
+            &mut emulator,
+            &[0xb940_0008, 0xb940_0029, 0x4b09_0100, 0xd65f_03c0],
+        );
+        let values: Vec<u8> = [3u32, 1, 2, 0]
+            .iter()
+            .flat_map(|v| v.to_le_bytes())
+            .collect();
+        let array = emulator.malloc(16).unwrap();
+        emulator.memory_mut().write(array, &values).unwrap();
+        emulator.call(qsort, &[array, 4, 4, comparator]).unwrap();
+        let sorted = emulator.memory().read_bytes(array, 16).unwrap();
+        let sorted: Vec<u32> = sorted
+            .chunks(4)
+            .map(|c| u32::from_le_bytes([c[0], c[1], c[2], c[3]]))
+            .collect();
+        assert_eq!(sorted, vec![0, 1, 2, 3]);
+    }
+}
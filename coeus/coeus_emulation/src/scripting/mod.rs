@@ -131,8 +131,7 @@ impl EmulatedFunction {
     }
 
     fn new_class_argument(&mut self, class_name: ImmutableString, constructor_shorty: ImmutableString, constructor_args: Array) -> Dynamic {
-        let classes = self.apk.classes();
-        let class = classes.iter().find(|(_,c)| c.class_name == class_name.as_str()).unwrap();
+        let class = self.apk.get_class_by_name(class_name.as_str()).unwrap();
         let ci = ClassInstance::new(class.1.clone());
         let instance = self.vm.new_instance(class_name.to_string(),Value::Object(ci)).unwrap();
         let init_method = class.1.codes.iter().find(|m| {
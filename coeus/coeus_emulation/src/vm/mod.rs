@@ -19,6 +19,7 @@ use rayon::iter::ParallelIterator;
 use coeus_macros::iterator;
 
 use self::runtime::{invoke_runtime, invoke_runtime_with_method, StringClass};
+use crate::native::{NativeEmulator, NativeException};
 
 use coeus_models::models::{
     BinaryObject, Class, CodeItem, DexFile, Instruction, InstructionOffset, InstructionSize,
@@ -26,6 +27,7 @@ use coeus_models::models::{
 };
 
 pub mod dynamic_runtime;
+pub mod native;
 pub mod runtime;
 
 use runtime::VM_BUILTINS;
@@ -187,6 +189,7 @@ pub struct VM {
     stop_on_array_return: bool,
     stop_on_string_return: bool,
     skip_next_breakpoint: bool,
+    native: Option<Box<NativeEmulator>>,
 }
 
 #[derive(Debug)]
@@ -207,6 +210,7 @@ pub enum VMException {
     StaticDataNotFound(u32),
     Breakpoint(InstructionOffset, u32, BreakpointContext),
     ExceptionThrown,
+    Native(NativeException),
 }
 #[derive(Debug, Copy, Clone)]
 pub enum BreakpointContext {
@@ -286,6 +290,7 @@ impl VM {
             stop_on_string_return: false,
             stop_on_string_use: false,
             skip_next_breakpoint: false,
+            native: None,
         }
     }
 
@@ -1208,7 +1213,7 @@ impl VM {
                         return Err(VMException::RegisterNotFound(array_reference as usize));
                     }
                 }
-                Instruction::Invoke(_) => {
+                Instruction::Invoke(_) | Instruction::InvokeCustom(_) => {
                     return Err(VMException::LinkerError);
                 }
                 Instruction::InvokeType(a) => {
@@ -1539,7 +1544,16 @@ impl VM {
                     self.current_state.current_method_index = *method_ref as u32;
                     method_idx = self.current_state.current_method_index;
 
-                    if let Ok((file, the_code)) = self.get_method(&dex_file, (*method_ref) as u32) {
+                    if let Some(result) = self.try_invoke_native(&dex_file, method_idx, &arguments)
+                    {
+                        self.current_state.return_reg = result?;
+                        if let Some(stack_frame) = self.stack_frames.pop() {
+                            method_idx = stack_frame.current_method_index;
+                            self.current_state.current_method_index = method_idx;
+                        }
+                    } else if let Ok((file, the_code)) =
+                        self.get_method(&dex_file, (*method_ref) as u32)
+                    {
                         let the_code = the_code
                             .code
                             .as_ref()
@@ -1674,7 +1688,16 @@ impl VM {
                     self.current_state.current_method_index = *method_ref as u32;
                     method_idx = self.current_state.current_method_index;
 
-                    if let Ok((file, the_code)) = self.get_method(&dex_file, *method_ref as u32) {
+                    if let Some(result) = self.try_invoke_native(&dex_file, method_idx, &arguments)
+                    {
+                        self.current_state.return_reg = result?;
+                        if let Some(stack_frame) = self.stack_frames.pop() {
+                            method_idx = stack_frame.current_method_index;
+                            self.current_state.current_method_index = method_idx;
+                        }
+                    } else if let Ok((file, the_code)) =
+                        self.get_method(&dex_file, *method_ref as u32)
+                    {
                         let method_name = &the_code.name;
                         let access_flags = &the_code.access_flags;
 
@@ -0,0 +1,378 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Glue between the dex VM and the [`NativeEmulator`]: loading libraries, calling native methods and the
+//! heap accessors the fake `JNIEnv` is built on.
+//!
+//! While native code runs the emulator is taken out of the VM, a native method called from Java code
+//! which was itself called from native code is therefore not emulated.
+
+use std::sync::Arc;
+
+use petgraph::graph::NodeIndex;
+
+use coeus_models::models::{AccessFlags, Class, CodeItem, DexFile, Method};
+
+use crate::native::NativeEmulator;
+
+use super::runtime::{ClassObject, JavaObject, StringClass};
+use super::{ClassInstance, Register, VMException, Value, VM, VM_BUILTINS};
+
+impl VM {
+    /// Load a native library from the resources and run its `JNI_OnLoad`. `name` is either a path in the
+    /// APK (`lib/arm64-v8a/libfoo.so`) or the name passed to `System.loadLibrary` (`foo`).
+    pub fn load_native_library(&mut self, name: &str) -> Result<(), VMException> {
+        let file_name = if name.ends_with(".so") {
+            name.to_string()
+        } else {
+            format!("lib{}.so", name)
+        };
+        let mut emulator = self.native.take().unwrap_or_default();
+        let loaded = emulator.libraries().len();
+        let result = match emulator.load_library(&file_name, &self.resources) {
+            Ok(index) if index >= loaded => match emulator.libraries()[index].jni_onload() {
+                Some(jni_onload) => {
+                    let java_vm = emulator.java_vm();
+                    emulator
+                        .call_with_vm(self, jni_onload, &[java_vm, 0])
+                        .map(|version| {
+                            log::debug!("JNI_OnLoad of {} returned {:#x}", name, version)
+                        })
+                }
+                None => Ok(()),
+            },
+            Ok(_) => Ok(()),
+            Err(e) => Err(e),
+        };
+        self.native = Some(emulator);
+        result.map_err(VMException::Native)
+    }
+
+    pub fn native_emulator(&self) -> Option<&NativeEmulator> {
+        self.native.as_deref()
+    }
+
+    pub fn native_emulator_mut(&mut self) -> Option<&mut NativeEmulator> {
+        self.native.as_deref_mut()
+    }
+
+    /// Run the native implementation of a method, `arguments` start with `this` for instance methods.
+    /// The result is also stored in the return register.
+    pub fn invoke_native_method(
+        &mut self,
+        class_name: &str,
+        method_name: &str,
+        signature: &str,
+        arguments: Vec<Register>,
+    ) -> Result<Register, VMException> {
+        let address = self
+            .native
+            .as_ref()
+            .and_then(|emulator| emulator.find_native(class_name, method_name, signature))
+            .ok_or_else(|| {
+                VMException::MethodNotFound(format!("{}->{}{}", class_name, method_name, signature))
+            })?;
+        let is_static = self
+            .lookup_method(class_name, &method_stub(method_name, signature))
+            .map(|(_, method_data)| method_data.access_flags.contains(AccessFlags::STATIC))
+            .unwrap_or(true);
+        let result = self.run_native(address, class_name, signature, is_static, &arguments)?;
+        self.current_state.return_reg = result.clone();
+        Ok(result)
+    }
+
+    /// Called by the invoke instructions: returns `None` if the method is not a native method with a
+    /// known implementation
+    pub(super) fn try_invoke_native(
+        &mut self,
+        dex_file: &Arc<DexFile>,
+        method_idx: u32,
+        arguments: &[Register],
+    ) -> Option<Result<Register, VMException>> {
+        self.native.as_ref()?;
+        let (file, method_data) = self.get_method(dex_file, method_idx).ok()?;
+        if method_data.code.is_some() {
+            return None;
+        }
+        let method = &method_data.method;
+        let class_name = file.get_type_name(method.class_idx)?.to_string();
+        let signature = file.protos.get(method.proto_idx as usize)?.to_string(&file);
+        let address =
+            self.native
+                .as_ref()?
+                .find_native(&class_name, &method.method_name, &signature)?;
+        let is_static = method_data.access_flags.contains(AccessFlags::STATIC);
+        Some(self.run_native(address, &class_name, &signature, is_static, arguments))
+    }
+
+    fn run_native(
+        &mut self,
+        address: u64,
+        class_name: &str,
+        signature: &str,
+        is_static: bool,
+        arguments: &[Register],
+    ) -> Result<Register, VMException> {
+        let mut emulator = self.native.take().ok_or(VMException::LinkerError)?;
+        let result =
+            emulator.call_native_method(self, address, class_name, signature, is_static, arguments);
+        let exception = emulator.take_pending_exception();
+        self.native = Some(emulator);
+        let result = result.map_err(VMException::Native)?;
+        if let Some(exception) = exception {
+            log::debug!("native method threw {}", exception);
+            return Err(VMException::ExceptionThrown);
+        }
+        Ok(result)
+    }
+
+    /// Call a Java method from native code. Methods with code are run on a fresh stack, everything else
+    /// goes to the native emulator or the runtime builtins.
+    pub(crate) fn call_method(
+        &mut self,
+        class_name: &str,
+        name: &str,
+        signature: &str,
+        arguments: Vec<Register>,
+        is_static: bool,
+    ) -> Result<Register, VMException> {
+        let method = method_stub(name, signature);
+        if let Ok((dex_file, method_data)) = self.lookup_method(class_name, &method) {
+            if let Some(code) = &method_data.code {
+                return self.run_nested(
+                    method_data.method_idx,
+                    &dex_file.identifier,
+                    code,
+                    arguments,
+                );
+            }
+            if let Some(address) = self
+                .native
+                .as_ref()
+                .and_then(|emulator| emulator.find_native(class_name, name, signature))
+            {
+                return self.run_native(address, class_name, signature, is_static, &arguments);
+            }
+        }
+        let return_reg = std::mem::replace(&mut self.current_state.return_reg, Register::Empty);
+        let result = self.invoke_runtime_with_method(class_name, Arc::new(method), arguments);
+        let value = std::mem::replace(&mut self.current_state.return_reg, return_reg);
+        result.map(|_| value)
+    }
+
+    fn run_nested(
+        &mut self,
+        method_idx: u32,
+        dex_file: &str,
+        code: &CodeItem,
+        arguments: Vec<Register>,
+    ) -> Result<Register, VMException> {
+        let state = self.current_state.clone();
+        let stack_frames = std::mem::take(&mut self.stack_frames);
+        let result = self.start(method_idx, dex_file, code, arguments);
+        let value = self.current_state.return_reg.clone();
+        self.current_state = state;
+        self.stack_frames = stack_frames;
+        result.map(|_| value)
+    }
+
+    pub(crate) fn jni_new_string(&mut self, string: String) -> Result<Register, VMException> {
+        self.new_instance(
+            StringClass::class_name().to_string(),
+            Value::Object(StringClass::new(string)),
+        )
+    }
+
+    pub(crate) fn jni_string(&self, address: u32) -> Option<String> {
+        self.heap.get(&address)?.as_string()
+    }
+
+    pub(crate) fn jni_new_array(
+        &mut self,
+        ty: &str,
+        data: Vec<u8>,
+    ) -> Result<Register, VMException> {
+        self.new_instance(ty.to_string(), Value::Array(data))
+    }
+
+    pub(crate) fn jni_array(&self, address: u32) -> Option<Vec<u8>> {
+        match self.heap.get(&address) {
+            Some(Value::Array(data)) => Some(data.clone()),
+            _ => None,
+        }
+    }
+
+    /// Overwrite part of an array, returns false if the region is out of bounds
+    pub(crate) fn jni_set_array_region(&mut self, address: u32, start: usize, data: &[u8]) -> bool {
+        match self.heap.get_mut(&address) {
+            Some(Value::Array(array))
+                if start
+                    .checked_add(data.len())
+                    .map_or(false, |end| end <= array.len()) =>
+            {
+                array[start..start + data.len()].copy_from_slice(data);
+                true
+            }
+            _ => false,
+        }
+    }
+
+    pub(crate) fn jni_object_type(&self, address: u32) -> Option<String> {
+        match self.heap.get(&address)? {
+            Value::Object(instance) => Some(instance.class.class_name.clone()),
+            Value::Array(_) => Some("[B".to_string()),
+            _ => None,
+        }
+    }
+
+    pub(crate) fn jni_alloc_object(&mut self, class_name: &str) -> Result<Register, VMException> {
+        let class = self.find_class(class_name);
+        self.new_instance(
+            class_name.to_string(),
+            Value::Object(ClassInstance::new(class)),
+        )
+    }
+
+    pub(crate) fn jni_class_object(&mut self, class_name: &str) -> Result<Register, VMException> {
+        let class = self.find_class(class_name);
+        self.new_instance(
+            ClassObject::class_name(),
+            Value::Object(ClassObject::new(class)),
+        )
+    }
+
+    pub(crate) fn jni_superclass(&self, class_name: &str) -> Option<String> {
+        if class_name == "Ljava/lang/Object;" {
+            return None;
+        }
+        let super_class = std::iter::once(&self.dex_file)
+            .chain(self.runtime.iter())
+            .find_map(|dex| {
+                dex.get_class_by_name(class_name)
+                    .map(|class| class.get_superclass(dex.clone()))
+            })
+            .flatten()
+            .map(|class| class.class_name.clone());
+        Some(super_class.unwrap_or_else(|| "Ljava/lang/Object;".to_string()))
+    }
+
+    pub(crate) fn jni_is_subclass(&self, class_name: &str, super_class: &str) -> bool {
+        let mut current = Some(class_name.to_string());
+        // the depth limit guards against cyclic hierarchies in broken dex files
+        for _ in 0..64 {
+            match current {
+                Some(class) if class == super_class => return true,
+                Some(class) => current = self.jni_superclass(&class),
+                None => return false,
+            }
+        }
+        false
+    }
+
+    /// Read a field, `object` is `None` for static fields
+    pub(crate) fn jni_get_field(
+        &self,
+        object: Option<u32>,
+        class_name: &str,
+        name: &str,
+        signature: &str,
+    ) -> Register {
+        let key = format!("{}->{}", class_name, name);
+        let address = match object {
+            Some(object) => match self.heap.get(&object) {
+                Some(Value::Object(instance)) => instance.instances.get(&key).copied(),
+                _ => None,
+            },
+            None => self.instances.get(&key).map(|(_, address)| *address),
+        };
+        let is_object = signature.starts_with('L') || signature.starts_with('[');
+        match address {
+            Some(address) if is_object => Register::Reference(signature.to_string(), address),
+            None if is_object => Register::Null,
+            _ => {
+                let value = match address.and_then(|address| self.heap.get(&address)) {
+                    Some(Value::Int(value)) => *value,
+                    Some(Value::Short(value)) => *value as i32,
+                    Some(Value::Byte(value)) => *value as i32,
+                    _ => 0,
+                };
+                if signature == "J" || signature == "D" {
+                    Register::LiteralWide(value as i64)
+                } else {
+                    Register::Literal(value)
+                }
+            }
+        }
+    }
+
+    /// Write a field, `object` is `None` for static fields. Primitives get a new heap slot like
+    /// `iput`/`sput` do.
+    pub(crate) fn jni_set_field(
+        &mut self,
+        object: Option<u32>,
+        class_name: &str,
+        name: &str,
+        value: Register,
+    ) -> Result<(), VMException> {
+        let key = format!("{}->{}", class_name, name);
+        let address = match value {
+            Register::Reference(_, address) => Some(address),
+            Register::Literal(value) => Some(self.allocate(Value::Int(value))?),
+            Register::LiteralWide(value) => Some(self.allocate(Value::Int(value as i32))?),
+            _ => None,
+        };
+        match object {
+            Some(object) => {
+                let Some(Value::Object(instance)) = self.heap.get_mut(&object) else {
+                    return Err(VMException::InstanceNotFound(object));
+                };
+                match address {
+                    Some(address) => instance.instances.insert(key, address),
+                    None => instance.instances.remove(&key),
+                };
+            }
+            None => {
+                match address {
+                    Some(address) => self.instances.insert(key, (NodeIndex::new(0), address)),
+                    None => self.instances.remove(&key),
+                };
+            }
+        }
+        Ok(())
+    }
+
+    fn allocate(&mut self, value: Value) -> Result<u32, VMException> {
+        match self.new_instance(String::new(), value)? {
+            Register::Reference(_, address) => Ok(address),
+            _ => Err(VMException::OutOfMemory),
+        }
+    }
+
+    fn find_class(&self, class_name: &str) -> Arc<Class> {
+        std::iter::once(&self.dex_file)
+            .chain(self.runtime.iter())
+            .find_map(|dex| dex.get_class_by_name(class_name))
+            .or_else(|| VM_BUILTINS.get(class_name).cloned())
+            .unwrap_or_else(|| {
+                Arc::new(Class {
+                    class_name: class_name.to_string(),
+                    ..Default::default()
+                })
+            })
+    }
+}
+
+/// A method reference which is only used for lookups by name and prototype
+fn method_stub(name: &str, signature: &str) -> Method {
+    Method {
+        class_idx: 0,
+        method_idx: 0,
+        proto_idx: 0,
+        name_idx: 0,
+        method_name: name.to_string(),
+        proto_name: signature.to_string(),
+    }
+}
@@ -690,6 +690,16 @@ runtime_impl! {
             }
             Ok(())
         }
+        pub fn loadLibrary(vm: &mut VM, args: &[Register]) -> Result<(), VMException> {
+            let Some(name) = args.first().map(|library| vm.get_instance(library.clone())).and_then(|library| library.as_string()) else {
+                return Err(VMException::InvalidRegisterType);
+            };
+            // a missing library only matters once one of its native methods is called
+            if let Err(e) = vm.load_native_library(&name) {
+                log::warn!("Could not load native library {}: {:?}", name, e);
+            }
+            Ok(())
+        }
         pub fn arraycopy(vm : &mut VM, args: &[Register]) -> Result<(), VMException> {
             if args.len() != 5 {
                 return Err(VMException::IndexOutOfBounds);
@@ -13,23 +13,80 @@ use core::ops::{Add, AddAssign};
 mod android;
 pub use android::*;
 
+mod android_xml;
+pub use android_xml::*;
+
+mod api_database;
+pub use api_database::*;
+
+mod archive;
+pub use archive::*;
+
 mod binaryobject;
 pub use binaryobject::*;
 
+mod dart_snapshot;
+pub use dart_snapshot::*;
+
 mod dexfile;
 pub use dexfile::*;
 
+mod dotnet_metadata;
+pub use dotnet_metadata::*;
+
 mod encoding;
 pub use encoding::*;
 
 mod files;
 pub use files::*;
 
+mod framework;
+pub use framework::*;
+
+mod hermes;
+pub use hermes::*;
+
+mod hierarchy;
+pub use hierarchy::*;
+
+mod il2cpp_metadata;
+pub use il2cpp_metadata::*;
+
+mod index;
+pub use index::{DexIndex, Pool};
+
 mod instruction;
 pub use instruction::*;
 
+mod jvm;
+pub use jvm::*;
+
 mod multidexfile;
 pub use multidexfile::*;
+
+mod mutf8;
+pub use mutf8::*;
+
+mod resources;
+pub use resources::*;
+
+mod serialization;
+pub use serialization::*;
+
+mod string_index;
+pub use string_index::StringQuery;
+
+#[cfg(any(test, feature = "testing"))]
+pub mod testing;
+
+mod xref;
+pub use xref::*;
+
+mod native_library;
+pub use native_library::*;
+
+mod xamarin;
+pub use xamarin::*;
 use petgraph::dot::Dot;
 
 #[derive(Clone, Debug, ::serde::Serialize, ::serde::Deserialize, Eq, PartialEq)]
@@ -66,6 +123,17 @@ impl Decode for Method {
     }
 }
 
+#[derive(Clone, Debug, ::serde::Serialize, ::serde::Deserialize, Eq, PartialEq)]
+/// A call site of `invoke-custom`, resolved from its method handle and encoded array.
+pub struct CallSite {
+    /// The index in the method pool of the bootstrap method, `None` if the handle refers to a field
+    pub bootstrap_method_idx: Option<u16>,
+    /// The name of the method linked by the bootstrap method
+    pub method_name: String,
+    /// The index in the proto type pool of the linked method
+    pub proto_idx: u16,
+}
+
 #[derive(Clone, Debug, ::serde::Serialize, ::serde::Deserialize, PartialEq, Eq)]
 /// A field as it is present in the Dex-File. The name is added for convenience, and to save a lookup in the string table.
 pub struct Field {
@@ -154,11 +222,10 @@ impl Method {
     ) -> String {
         let class_name = &types[self.class_idx as usize];
         let ret_type = types[proto_types[self.proto_idx as usize].return_type_idx as usize];
-        let name = std::str::from_utf8(&strings[self.name_idx as usize].dat).unwrap();
-        let short_idx = std::str::from_utf8(
-            &strings[proto_types[self.proto_idx as usize].shorty_idx as usize].dat,
-        )
-        .unwrap();
+        let name = strings[self.name_idx as usize].to_str().unwrap();
+        let short_idx = strings[proto_types[self.proto_idx as usize].shorty_idx as usize]
+            .to_str()
+            .unwrap();
         format!(
             "In Class {}  Return Type: {}  Name: {}(...)[{}]",
             class_name, ret_type, name, short_idx
@@ -169,11 +236,12 @@ impl Method {
         types[self.class_idx as usize].to_string()
     }
     pub fn get_function_name<'a>(&self, strings: &'a [StringEntry]) -> Cow<'a, str> {
-        String::from_utf8_lossy(&strings[self.name_idx as usize].dat)
+        strings[self.name_idx as usize].to_str_lossy()
     }
     pub fn get_prototype(&self, strings: &[StringEntry], proto_types: &[Proto]) -> String {
         let proto = &proto_types[self.proto_idx as usize];
-        std::str::from_utf8(&strings[proto.shorty_idx as usize].dat)
+        strings[proto.shorty_idx as usize]
+            .to_str()
             .unwrap_or("INVALID_TYPE")
             .to_string()
     }
@@ -235,36 +303,74 @@ impl Decode for Proto {
 
 pub struct StringEntry {
     pub utf16_size: u32,
+    /// The raw modified UTF-8 bytes, as stored in the dex file
     pub dat: Vec<u8>,
+    /// Decoded content, only used for strings which are not valid UTF-8
+    #[serde(skip)]
+    decoded: OnceLock<Option<Box<str>>>,
 }
 
 impl StringEntry {
+    pub fn new(utf16_size: u32, dat: Vec<u8>) -> Self {
+        Self {
+            utf16_size,
+            dat,
+            decoded: OnceLock::new(),
+        }
+    }
+    /// Decode the modified UTF-8 content. Unpaired surrogates are replaced with U+FFFD, use
+    /// `to_utf16` to get them as is.
     pub fn to_string(self) -> Result<String, FromUtf8Error> {
-        String::from_utf8(self.dat)
+        String::from_utf8(self.dat).or_else(|e| match mutf8_to_str(e.as_bytes()) {
+            Some(s) => Ok(s.into_owned()),
+            None => Err(e),
+        })
     }
+    /// Decode the modified UTF-8 content. Unpaired surrogates are replaced with U+FFFD, use
+    /// `to_utf16` to get them as is.
     pub fn to_str(&self) -> Result<&str, Utf8Error> {
-        std::str::from_utf8(&self.dat)
+        std::str::from_utf8(&self.dat).or_else(|e| {
+            self.decoded
+                .get_or_init(|| mutf8_to_str(&self.dat).map(|s| s.into()))
+                .as_deref()
+                .ok_or(e)
+        })
     }
     pub fn to_str_lossy(&self) -> Cow<str> {
-        // String::from_utf8_lossy(&self.dat)
-        cesu8::from_java_cesu8(&self.dat).unwrap_or(String::from_utf8_lossy(&self.dat))
+        match self.to_str() {
+            Ok(s) => Cow::Borrowed(s),
+            Err(_) => String::from_utf8_lossy(&self.dat),
+        }
+    }
+    /// The UTF-16 code units of the content, or `None` if the data is malformed
+    pub fn to_utf16(&self) -> Option<Vec<u16>> {
+        decode_mutf8(&self.dat)
+    }
+}
+
+impl From<&str> for StringEntry {
+    fn from(s: &str) -> Self {
+        Self::new(s.encode_utf16().count() as u32, encode_mutf8(s).into_owned())
     }
 }
 
+/// Upper bound of the bytes reserved for a string before reading it
+const MAX_STRING_PREALLOCATION: usize = 0x1000;
+
 impl Decode for StringEntry {
     type DecodableUnit = Self;
 
     fn from_bytes<R: Read + Seek>(byte_view: &mut R) -> Self {
-        let (bytes, val) = StringEntry::read_leb128(byte_view).expect("Cannot read uleb");
-        let mut buf = Vec::with_capacity(bytes);
-        byte_view
-            .take(val)
-            .read_to_end(&mut buf)
-            .expect("Could not take n bytes");
-        StringEntry {
-            utf16_size: val as u32,
-            dat: buf,
+        let (_, val) = StringEntry::read_leb128(byte_view).expect("Cannot read uleb");
+        // the size is given in UTF-16 code units, each taking one to three bytes. The data
+        // itself is terminated by a NUL byte, a NUL in the content is encoded as `0xc0 0x80`.
+        // The size is not trusted, as it might be garbage.
+        let mut buf = Vec::with_capacity((val as usize).min(MAX_STRING_PREALLOCATION));
+        let mut byte = [0u8];
+        while byte_view.read_exact(&mut byte).is_ok() && byte[0] != 0 {
+            buf.push(byte[0]);
         }
+        StringEntry::new(val as u32, buf)
     }
 }
 
@@ -579,6 +685,9 @@ pub struct MethodData {
     pub code: Option<CodeItem>,
     #[serde(skip_serializing, skip_deserializing)]
     pub call_graph: Option<Graph<(u32, Instruction), i32>>,
+    /// The original bytecode, if the method was loaded from a class file
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub jvm_code: Option<Arc<JvmCode>>,
 }
 impl PartialEq for MethodData {
     fn eq(&self, other: &Self) -> bool {
@@ -605,6 +714,13 @@ impl MethodData {
         format!("{:?}", Dot::new(&cg))
     }
     pub fn get_disassembly(&self, file: &Arc<DexFile>) -> String {
+        self.disassemble(file, None)
+    }
+    /// Same as `get_disassembly`, but constants referring to a resource are annotated with its name and value
+    pub fn get_annotated_disassembly(&self, file: &Arc<DexFile>, files: &Files) -> String {
+        self.disassemble(file, Some(files))
+    }
+    fn disassemble(&self, file: &Arc<DexFile>, files: Option<&Files>) -> String {
         let mut lines = vec![];
         if let Some(proto) = file.protos.get(self.method.proto_idx as usize) {
             let return_type = file
@@ -637,14 +753,17 @@ impl MethodData {
                 let mut code_lines = HashMap::new();
                 let mut labels = HashMap::new();
                 for instruction in &method_details.insns {
-                    code_lines.insert(
-                        instruction.1,
-                        instruction.2.disassembly_from_opcode(
-                            instruction.1 .0 as i32,
-                            &mut labels,
-                            file.clone(),
-                        ),
+                    let mut line = instruction.2.disassembly_from_opcode(
+                        instruction.1 .0 as i32,
+                        &mut labels,
+                        file.clone(),
                     );
+                    if let (Some(files), Instruction::ConstLit32(_, lit)) = (files, &instruction.2) {
+                        if let Some(description) = files.describe_resource(*lit as u32) {
+                            line = format!("{} # {}", line, description);
+                        }
+                    }
+                    code_lines.insert(instruction.1, line);
                 }
                 for label in labels {
                     let line = code_lines
@@ -812,11 +931,11 @@ pub struct Class {
     pub annotations: Vec<Annotation>,
     pub method_annotations: Vec<AnnotationMethod>,
     pub field_annotations: Vec<AnnotationField>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
     pub class_data: Option<ClassData>,
     // #[serde(skip_serializing)]
     pub codes: Vec<Arc<MethodData>>,
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
     pub static_fields: Vec<EncodedItem>,
 }
 impl PartialEq for Class {
@@ -886,12 +1005,8 @@ impl Class {
         let class_name = self.class_name.clone();
         let (file, class) = if !self.codes.is_empty() && self.class_data.is_some() {
             (file, Arc::new(self.clone()))
-        } else if let Some((file, class)) = md
-            .classes()
-            .iter()
-            .find(|(_, class)| class.class_name == class_name)
-        {
-            (file.clone(), class.clone())
+        } else if let Some((file, class)) = md.get_class_by_name(&class_name) {
+            (file, class)
         } else {
             return "NO CLASS DEF FOUND".to_string();
         };
@@ -1484,7 +1599,7 @@ impl From<InstructionSize> for usize {
 
 #[repr(C)]
 #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize, PartialEq)]
-
+#[serde(from = "SerializedCodeItem")]
 //we ignore try handlers for know
 pub struct CodeItem {
     pub register_size: u16,
@@ -1493,6 +1608,10 @@ pub struct CodeItem {
     pub tries_size: u16,
     pub debug_info_off: u32,
     pub insns_size: u32,
+    /// The undecoded instruction stream, used to restore the instructions from the complete
+    /// serialization format
+    #[serde(skip_serializing_if = "crate::models::is_compact")]
+    pub raw_insns: Vec<u8>,
     #[serde(skip_serializing, skip_deserializing)]
     pub insns: Vec<(InstructionSize, InstructionOffset, Instruction)>,
     #[serde(skip_serializing, skip_deserializing)]
@@ -1500,6 +1619,40 @@ pub struct CodeItem {
     #[serde(skip_serializing, skip_deserializing)]
     pub switch_data: Vec<(InstructionSize, InstructionOffset, Instruction)>,
 }
+#[derive(::serde::Deserialize)]
+struct SerializedCodeItem {
+    register_size: u16,
+    ins_size: u16,
+    outs_size: u16,
+    tries_size: u16,
+    debug_info_off: u32,
+    insns_size: u32,
+    #[serde(default)]
+    raw_insns: Vec<u8>,
+}
+
+impl From<SerializedCodeItem> for CodeItem {
+    fn from(item: SerializedCodeItem) -> Self {
+        let (insns, array_data, switch_data) = if item.raw_insns.is_empty() {
+            (vec![], vec![], vec![])
+        } else {
+            CodeItem::decode_insns(item.insns_size, &mut std::io::Cursor::new(&item.raw_insns))
+        };
+        CodeItem {
+            register_size: item.register_size,
+            ins_size: item.ins_size,
+            outs_size: item.outs_size,
+            tries_size: item.tries_size,
+            debug_info_off: item.debug_info_off,
+            insns_size: item.insns_size,
+            raw_insns: item.raw_insns,
+            insns,
+            array_data,
+            switch_data,
+        }
+    }
+}
+
 #[derive(Clone, Debug, PartialEq, Eq, ::serde::Serialize, ::serde::Deserialize)]
 pub struct Switch {
     id: u32,
@@ -1523,6 +1676,38 @@ impl Decode for CodeItem {
         let tries_size = u16::from_bytes(byte_view);
         let debug_info_off = u32::from_bytes(byte_view);
         let insns_size = u32::from_bytes(byte_view);
+        let start = byte_view.stream_position().unwrap_or(0);
+        let (insns, array_data, switch_data) = Self::decode_insns(insns_size, byte_view);
+        let end = byte_view.stream_position().unwrap_or(start);
+        let mut raw_insns = vec![0; (end - start) as usize];
+        if byte_view.seek(SeekFrom::Start(start)).is_err()
+            || byte_view.read_exact(&mut raw_insns).is_err()
+        {
+            raw_insns.clear();
+            let _ = byte_view.seek(SeekFrom::Start(end));
+        }
+        CodeItem {
+            register_size,
+            ins_size,
+            outs_size,
+            tries_size,
+            debug_info_off,
+            insns_size,
+            raw_insns,
+            insns,
+            array_data,
+            switch_data,
+        }
+    }
+}
+
+type DecodedInstructions = Vec<(InstructionSize, InstructionOffset, Instruction)>;
+
+impl CodeItem {
+    fn decode_insns<R: Read + Seek>(
+        insns_size: u32,
+        byte_view: &mut R,
+    ) -> (DecodedInstructions, DecodedInstructions, DecodedInstructions) {
         let mut insns: Vec<(InstructionSize, InstructionOffset, Instruction)> = vec![];
         let mut a_data: Vec<(InstructionSize, InstructionOffset, Instruction)> = vec![];
         let mut switch_data: Vec<(InstructionSize, InstructionOffset, Instruction)> = vec![];
@@ -1601,7 +1786,12 @@ impl Decode for CodeItem {
                     ));
                 }
 
-                i += (1 + op_size) / 2 + 4;
+                if op.to_be_bytes()[0] == 0x02 {
+                    // ident, size, keys and targets
+                    i += op_size + 2;
+                } else {
+                    i += (1 + op_size) / 2 + 4;
+                }
                 if (op_size * element_size) % 2 != 0 {
                     u8::from_bytes(byte_view);
                 }
@@ -1619,17 +1809,7 @@ impl Decode for CodeItem {
             insns.push((op_size.into(), i.into(), opccode));
             i += 1 + bytes as u32;
         }
-        CodeItem {
-            register_size,
-            ins_size,
-            outs_size,
-            tries_size,
-            debug_info_off,
-            insns_size,
-            insns,
-            array_data: a_data,
-            switch_data,
-        }
+        (insns, a_data, switch_data)
     }
 }
 use std::{
@@ -1638,6 +1818,7 @@ use std::{
     io::{Read, Seek, SeekFrom},
     str::Utf8Error,
     string::FromUtf8Error,
+    sync::OnceLock,
 };
 
 use petgraph::Graph;
@@ -1695,3 +1876,112 @@ impl ToString for StringType {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn sparse_switch_payload_is_skipped_entirely() {
+        let mut bytes = vec![];
+        // registers, ins, outs, tries, debug info
+        bytes.extend_from_slice(&[0; 12]);
+        // sparse-switch payload with two entries (10 code units) followed by return-void
+        bytes.extend_from_slice(&11u32.to_le_bytes());
+        bytes.extend_from_slice(&0x0200u16.to_le_bytes());
+        bytes.extend_from_slice(&2u16.to_le_bytes());
+        for value in [1i32, 5, 10, 20] {
+            bytes.extend_from_slice(&value.to_le_bytes());
+        }
+        bytes.extend_from_slice(&0x000eu16.to_le_bytes());
+
+        let code = CodeItem::from_bytes(&mut std::io::Cursor::new(bytes));
+        assert_eq!(code.insns.len(), 2);
+        let Instruction::SwitchData(switch) = &code.insns[0].2 else {
+            panic!("expected switch data, got {:?}", code.insns[0].2);
+        };
+        assert_eq!(switch.targets, HashMap::from([(1, 10), (5, 20)]));
+        assert_eq!(code.insns[1].1, InstructionOffset(10));
+        assert_eq!(code.insns[1].2, Instruction::ReturnVoid);
+    }
+
+    fn string_entry(bytes: &[u8]) -> StringEntry {
+        StringEntry::from_bytes(&mut std::io::Cursor::new(bytes))
+    }
+
+    #[test]
+    fn strings_are_read_up_to_the_nul() {
+        // "é" is a single UTF-16 code unit but two bytes
+        let entry = string_entry(&[0x01, 0xc3, 0xa9, 0x00, b'x']);
+        assert_eq!(entry.utf16_size, 1);
+        assert_eq!(entry.dat, [0xc3, 0xa9]);
+        assert_eq!(entry.to_str(), Ok("é"));
+
+        let entry = string_entry(&[0x02, b'h', b'i', 0x00, b'x']);
+        assert_eq!(entry.dat, b"hi");
+        // a missing terminator ends the string at the end of the data
+        assert_eq!(string_entry(&[0x02, b'h', b'i']).dat, b"hi");
+    }
+
+    #[test]
+    fn oversized_string_sizes_are_not_trusted() {
+        // uleb128 of 0xffffffff
+        let entry = string_entry(&[0xff, 0xff, 0xff, 0xff, 0x0f, b'a', b'b', 0x00, b'c', b'd']);
+        assert_eq!(entry.utf16_size, u32::MAX);
+        assert_eq!(entry.dat, b"ab");
+        assert!(entry.dat.capacity() <= MAX_STRING_PREALLOCATION);
+    }
+
+    #[test]
+    fn nul_is_encoded_as_two_bytes() {
+        let entry = StringEntry::from("a\0b");
+        assert_eq!(entry.utf16_size, 3);
+        assert_eq!(entry.dat, [b'a', 0xc0, 0x80, b'b']);
+        assert_eq!(entry.to_str(), Ok("a\0b"));
+
+        // the encoded NUL does not terminate the string
+        let entry = string_entry(&[0x03, b'a', 0xc0, 0x80, b'b', 0x00]);
+        assert_eq!(entry.to_str(), Ok("a\0b"));
+        assert_eq!(entry.to_utf16(), Some(vec![0x61, 0x00, 0x62]));
+    }
+
+    #[test]
+    fn supplementary_characters_are_encoded_as_surrogate_pairs() {
+        // U+1F600 is the surrogate pair d83d de00, each taking three bytes
+        let encoded = [0xed, 0xa0, 0xbd, 0xed, 0xb8, 0x80];
+        let entry = StringEntry::from("😀");
+        assert_eq!(entry.utf16_size, 2);
+        assert_eq!(entry.dat, encoded);
+
+        let mut bytes = vec![0x02];
+        bytes.extend_from_slice(&encoded);
+        bytes.push(0x00);
+        let entry = string_entry(&bytes);
+        assert_eq!(entry.dat, encoded);
+        assert_eq!(entry.to_str(), Ok("😀"));
+        assert_eq!(entry.to_utf16(), Some(vec![0xd83d, 0xde00]));
+        assert_eq!(entry.clone().to_string(), Ok("😀".to_string()));
+
+        // some tools emit plain UTF-8 instead
+        let entry = StringEntry::new(2, "😀".as_bytes().to_vec());
+        assert_eq!(entry.to_str(), Ok("😀"));
+        assert_eq!(entry.to_utf16(), Some(vec![0xd83d, 0xde00]));
+    }
+
+    #[test]
+    fn invalid_sequences_are_decoded_lossily() {
+        // an unpaired high surrogate
+        let entry = StringEntry::new(2, vec![b'a', 0xed, 0xa0, 0xbd]);
+        assert_eq!(entry.to_str(), Ok("a\u{fffd}"));
+        assert_eq!(entry.to_utf16(), Some(vec![0x61, 0xd83d]));
+
+        // an invalid lead byte and a truncated sequence
+        for dat in [vec![b'a', 0xff, b'b'], vec![b'a', 0xe0, 0x80]] {
+            let entry = StringEntry::new(3, dat);
+            assert!(entry.to_str().is_err());
+            assert_eq!(entry.to_utf16(), None);
+            assert!(entry.to_str_lossy().starts_with('a'));
+            assert!(entry.to_str_lossy().contains('\u{fffd}'));
+        }
+    }
+}
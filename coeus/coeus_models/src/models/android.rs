@@ -14,10 +14,10 @@ fn default_as_false() -> bool {
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 /// A non-exhaustive representation of the AndroidManifest
 pub struct AndroidManifest {
-    #[serde(rename = "versionCode")]
+    #[serde(rename = "versionCode", default)]
     /// Version code
     pub version_code: String,
-    #[serde(rename = "versionName")]
+    #[serde(rename = "versionName", default)]
     /// version name
     pub version_name: String,
     /// Android package name
@@ -42,6 +42,17 @@ pub struct AndroidPermission {
     name: String,
 }
 
+impl AndroidPermission {
+    pub fn new(name: &str) -> Self {
+        Self {
+            name: name.to_string(),
+        }
+    }
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+}
+
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AndroidFeature {
     name: Option<String>,
@@ -59,11 +70,16 @@ pub enum PermissionLevel {
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AndroidApplication {
+    /// The `Application` subclass, instantiated before any other component
+    pub name: Option<String>,
+    /// The `AppComponentFactory` instantiating the components (API 28 and later)
+    #[serde(rename = "appComponentFactory")]
+    pub app_component_factory: Option<String>,
     #[serde(rename = "allowBackup", default = "default_as_false")]
     pub allow_backup: bool,
     #[serde(default = "default_as_false")]
     pub debuggable: bool,
-    #[serde(rename = "$value")]
+    #[serde(rename = "$value", default)]
     pub activities: Vec<ContentType>,
 }
 
@@ -116,12 +132,18 @@ pub enum ContentType {
     ActivityAlias(AndroidActivity),
     #[serde(rename = "activity")]
     Activity(AndroidActivity),
+    #[serde(rename = "service")]
+    Service(AndroidComponent),
     #[serde(rename = "receiver")]
+    Receiver(AndroidComponent),
+    #[serde(rename = "provider")]
+    Provider(AndroidComponent),
+    #[serde(rename = "meta-data")]
     #[serde(alias = "intent-filter")]
     #[serde(alias = "uses-library")]
-    #[serde(alias = "meta-data")]
-    #[serde(alias = "service")]
-    #[serde(alias = "provider")]
+    #[serde(alias = "uses-native-library")]
+    #[serde(alias = "profileable")]
+    #[serde(alias = "property")]
     Unknown(Unknown),
 }
 
@@ -131,19 +153,202 @@ pub struct Unknown {
 // intent_filters : Vec<AndroidIntentFilter>
 }
 
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+#[serde(from = "String", into = "String")]
+/// A boolean attribute. Apps may reference a resource instead of a literal value (e.g.
+/// `android:exported="@bool/is_tablet"`), which depends on the device configuration.
+pub enum BoolAttribute {
+    Value(bool),
+    Reference(String),
+}
+
+impl BoolAttribute {
+    /// The literal value, `None` for references
+    pub fn value(&self) -> Option<bool> {
+        match self {
+            BoolAttribute::Value(value) => Some(*value),
+            BoolAttribute::Reference(_) => None,
+        }
+    }
+}
+
+impl From<String> for BoolAttribute {
+    fn from(value: String) -> Self {
+        match value.as_str() {
+            "true" => BoolAttribute::Value(true),
+            "false" => BoolAttribute::Value(false),
+            _ => BoolAttribute::Reference(value),
+        }
+    }
+}
+
+impl From<BoolAttribute> for String {
+    fn from(value: BoolAttribute) -> Self {
+        match value {
+            BoolAttribute::Value(value) => value.to_string(),
+            BoolAttribute::Reference(reference) => reference,
+        }
+    }
+}
+
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(from = "ActivityElement")]
 pub struct AndroidActivity {
     pub name: String,
     pub theme: Option<String>,
     #[serde(rename = "parentActivityName")]
     pub parent_activity_name: Option<String>,
+    /// Only set for `activity-alias`, pointing to the activity handling the intents
+    #[serde(rename = "targetActivity")]
+    pub target_activity: Option<String>,
+    pub exported: Option<BoolAttribute>,
+    pub permission: Option<String>,
     #[serde(rename = "intent-filter", default)]
     pub intent_filters: Vec<AndroidIntentFilter>,
+    #[serde(rename = "meta-data", default)]
+    pub meta_data: Vec<AndroidMetaData>,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+#[serde(from = "ComponentElement")]
+/// A service, receiver or content provider
+pub struct AndroidComponent {
+    pub name: String,
+    pub exported: Option<BoolAttribute>,
+    pub permission: Option<String>,
+    /// Only set for providers
+    pub authorities: Option<String>,
+    /// Only set for providers
+    #[serde(rename = "grantUriPermissions")]
+    pub grant_uri_permissions: Option<BoolAttribute>,
+    #[serde(rename = "intent-filter", default)]
+    pub intent_filters: Vec<AndroidIntentFilter>,
+    #[serde(rename = "meta-data", default)]
+    pub meta_data: Vec<AndroidMetaData>,
+}
+
+/// The xml representation of `AndroidActivity`. Intent filters and meta data may be interleaved,
+/// which `serde_xml_rs` only supports through a `$value` enum.
+#[derive(serde::Deserialize)]
+struct ActivityElement {
+    name: String,
+    theme: Option<String>,
+    #[serde(rename = "parentActivityName")]
+    parent_activity_name: Option<String>,
+    #[serde(rename = "targetActivity")]
+    target_activity: Option<String>,
+    exported: Option<BoolAttribute>,
+    permission: Option<String>,
+    #[serde(rename = "$value", default)]
+    content: Vec<ComponentContent>,
+}
+
+/// The xml representation of `AndroidComponent`
+#[derive(serde::Deserialize)]
+struct ComponentElement {
+    name: String,
+    exported: Option<BoolAttribute>,
+    permission: Option<String>,
+    authorities: Option<String>,
+    #[serde(rename = "grantUriPermissions")]
+    grant_uri_permissions: Option<BoolAttribute>,
+    #[serde(rename = "$value", default)]
+    content: Vec<ComponentContent>,
+}
+
+#[derive(serde::Deserialize)]
+enum ComponentContent {
+    #[serde(rename = "intent-filter")]
+    IntentFilter(AndroidIntentFilter),
+    #[serde(rename = "meta-data")]
+    MetaData(AndroidMetaData),
+    #[serde(rename = "layout")]
+    #[serde(alias = "grant-uri-permission")]
+    #[serde(alias = "path-permission")]
+    #[serde(alias = "property")]
+    Unknown(Unknown),
+}
+
+fn split_content(
+    content: Vec<ComponentContent>,
+) -> (Vec<AndroidIntentFilter>, Vec<AndroidMetaData>) {
+    let mut intent_filters = vec![];
+    let mut meta_data = vec![];
+    for c in content {
+        match c {
+            ComponentContent::IntentFilter(filter) => intent_filters.push(filter),
+            ComponentContent::MetaData(data) => meta_data.push(data),
+            ComponentContent::Unknown(_) => {}
+        }
+    }
+    (intent_filters, meta_data)
+}
+
+impl From<ActivityElement> for AndroidActivity {
+    fn from(element: ActivityElement) -> Self {
+        let (intent_filters, meta_data) = split_content(element.content);
+        Self {
+            name: element.name,
+            theme: element.theme,
+            parent_activity_name: element.parent_activity_name,
+            target_activity: element.target_activity,
+            exported: element.exported,
+            permission: element.permission,
+            intent_filters,
+            meta_data,
+        }
+    }
+}
+
+impl From<ComponentElement> for AndroidComponent {
+    fn from(element: ComponentElement) -> Self {
+        let (intent_filters, meta_data) = split_content(element.content);
+        Self {
+            name: element.name,
+            exported: element.exported,
+            permission: element.permission,
+            authorities: element.authorities,
+            grant_uri_permissions: element.grant_uri_permissions,
+            intent_filters,
+            meta_data,
+        }
+    }
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct AndroidMetaData {
+    pub name: String,
+    pub value: Option<String>,
+    pub resource: Option<String>,
+}
+
+/// Components with intent filters are exported by default. References are resolved at runtime,
+/// hence we fall back to the default for them as well.
+fn is_exported(exported: &Option<BoolAttribute>, intent_filters: &[AndroidIntentFilter]) -> bool {
+    exported
+        .as_ref()
+        .and_then(BoolAttribute::value)
+        .unwrap_or(!intent_filters.is_empty())
+}
+
+impl AndroidActivity {
+    pub fn is_exported(&self) -> bool {
+        is_exported(&self.exported, &self.intent_filters)
+    }
+}
+
+impl AndroidComponent {
+    pub fn is_exported(&self) -> bool {
+        is_exported(&self.exported, &self.intent_filters)
+    }
 }
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
 pub struct AndroidIntentFilter {
-    #[serde(rename = "$value")]
+    /// App links are verified against `/.well-known/assetlinks.json` of the hosts
+    #[serde(rename = "autoVerify")]
+    pub auto_verify: Option<BoolAttribute>,
+    #[serde(rename = "$value", default)]
     pub content: Vec<IntentContent>,
 }
 
@@ -158,8 +363,23 @@ pub struct AndroidIntentCategory {
 }
 
 #[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+/// A `data` element of an intent filter. All data elements of a filter are merged
+/// by Android, so a scheme in one element applies to the host of another.
 pub struct AndroidIntentData {
-    pub name: String,
+    pub scheme: Option<String>,
+    pub host: Option<String>,
+    pub port: Option<String>,
+    pub path: Option<String>,
+    #[serde(rename = "pathPrefix")]
+    pub path_prefix: Option<String>,
+    #[serde(rename = "pathSuffix")]
+    pub path_suffix: Option<String>,
+    #[serde(rename = "pathPattern")]
+    pub path_pattern: Option<String>,
+    #[serde(rename = "pathAdvancedPattern")]
+    pub path_advanced_pattern: Option<String>,
+    #[serde(rename = "mimeType")]
+    pub mime_type: Option<String>,
 }
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
@@ -169,5 +389,449 @@ pub enum IntentContent {
     #[serde(rename = "category")]
     Category(AndroidIntentCategory),
     #[serde(rename = "data")]
-    Data(Unknown),
+    Data(AndroidIntentData),
+    #[serde(rename = "uri-relative-filter-group")]
+    Unknown(Unknown),
+}
+
+impl AndroidIntentFilter {
+    pub fn actions(&self) -> Vec<&str> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                IntentContent::Action(action) => Some(action.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+    pub fn categories(&self) -> Vec<&str> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                IntentContent::Category(category) => Some(category.name.as_str()),
+                _ => None,
+            })
+            .collect()
+    }
+    pub fn data(&self) -> Vec<&AndroidIntentData> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                IntentContent::Data(data) => Some(data),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Combine all data elements into the uri patterns this filter matches. Android matches the
+    /// cross product of schemes, authorities and paths. Hosts are only considered if a scheme is
+    /// present, and paths only if a host is present.
+    pub fn deep_link_patterns(&self) -> Vec<DeepLinkPattern> {
+        let data = self.data();
+        let schemes = data
+            .iter()
+            .filter_map(|d| d.scheme.clone())
+            .collect::<Vec<_>>();
+        let authorities = data
+            .iter()
+            .filter_map(|d| Some((d.host.clone()?, d.port.clone())))
+            .collect::<Vec<_>>();
+        let paths = data
+            .iter()
+            .flat_map(|d| {
+                [
+                    d.path.clone().map(DeepLinkPath::Exact),
+                    d.path_prefix.clone().map(DeepLinkPath::Prefix),
+                    d.path_suffix.clone().map(DeepLinkPath::Suffix),
+                    d.path_pattern.clone().map(DeepLinkPath::Pattern),
+                    d.path_advanced_pattern
+                        .clone()
+                        .map(DeepLinkPath::AdvancedPattern),
+                ]
+            })
+            .flatten()
+            .collect::<Vec<_>>();
+
+        let mut patterns = vec![];
+        for scheme in &schemes {
+            if authorities.is_empty() {
+                patterns.push(DeepLinkPattern {
+                    scheme: scheme.clone(),
+                    host: None,
+                    port: None,
+                    path: None,
+                });
+                continue;
+            }
+            for (host, port) in &authorities {
+                let pattern = DeepLinkPattern {
+                    scheme: scheme.clone(),
+                    host: Some(host.clone()),
+                    port: port.clone(),
+                    path: None,
+                };
+                if paths.is_empty() {
+                    patterns.push(pattern);
+                } else {
+                    patterns.extend(paths.iter().map(|path| DeepLinkPattern {
+                        path: Some(path.clone()),
+                        ..pattern.clone()
+                    }));
+                }
+            }
+        }
+        patterns
+    }
+
+    pub fn auto_verifies(&self) -> bool {
+        self.auto_verify.as_ref().and_then(BoolAttribute::value) == Some(true)
+    }
+
+    /// An app link is a verified http(s) deep link
+    pub fn is_app_link(&self) -> bool {
+        self.auto_verifies()
+            && self
+                .data()
+                .iter()
+                .any(|d| matches!(d.scheme.as_deref(), Some("http") | Some("https")))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum DeepLinkPath {
+    Exact(String),
+    Prefix(String),
+    Suffix(String),
+    Pattern(String),
+    AdvancedPattern(String),
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// A concrete uri pattern accepted by an intent filter
+pub struct DeepLinkPattern {
+    pub scheme: String,
+    pub host: Option<String>,
+    pub port: Option<String>,
+    pub path: Option<DeepLinkPath>,
+}
+
+impl std::fmt::Display for DeepLinkPattern {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:", self.scheme)?;
+        let Some(host) = &self.host else {
+            return f.write_str("*");
+        };
+        write!(f, "//{}", host)?;
+        if let Some(port) = &self.port {
+            write!(f, ":{}", port)?;
+        }
+        match &self.path {
+            Some(DeepLinkPath::Exact(path)) => f.write_str(path),
+            Some(DeepLinkPath::Prefix(path)) => write!(f, "{}*", path),
+            Some(DeepLinkPath::Suffix(path)) => write!(f, "*{}", path),
+            Some(DeepLinkPath::Pattern(path)) | Some(DeepLinkPath::AdvancedPattern(path)) => {
+                f.write_str(path)
+            }
+            None => f.write_str("/*"),
+        }
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// A deep link together with the component handling it
+pub struct DeepLink {
+    /// Fully qualified class name of the component (for aliases the target activity)
+    pub component: String,
+    pub pattern: DeepLinkPattern,
+    pub auto_verify: bool,
+    pub is_app_link: bool,
+    /// Links need the `BROWSABLE` category to be opened from a browser
+    pub browsable: bool,
+    pub exported: bool,
+}
+
+impl AndroidManifest {
+    pub fn application(&self) -> Option<&AndroidApplication> {
+        self.content.iter().find_map(|c| match c {
+            Usages::Application(application) => Some(application),
+            _ => None,
+        })
+    }
+
+    /// The target sdk version, falling back to the min sdk version if no target is given
+    pub fn target_sdk_version(&self) -> Option<u32> {
+        self.content.iter().find_map(|c| match c {
+            Usages::UsesSdk(sdk) => sdk
+                .target_sdk_version
+                .parse()
+                .or_else(|_| sdk.min_sdk_version.parse())
+                .ok(),
+            _ => None,
+        })
+    }
+
+    /// The names of all requested permissions, without duplicates
+    pub fn permissions(&self) -> Vec<&str> {
+        let mut permissions: Vec<&str> = vec![];
+        for c in &self.content {
+            if let Usages::UsesPermission(permission) = c {
+                if !permissions.contains(&permission.name()) {
+                    permissions.push(permission.name());
+                }
+            }
+        }
+        permissions
+    }
+
+    /// Resolve relative component names (e.g. `.MainActivity`) against the package name
+    pub fn qualified_name(&self, name: &str) -> String {
+        if name.starts_with('.') {
+            format!("{}{}", self.package, name)
+        } else if !name.contains('.') {
+            format!("{}.{}", self.package, name)
+        } else {
+            name.to_string()
+        }
+    }
+
+    /// All deep links handled by activities and activity aliases
+    pub fn deep_links(&self) -> Vec<DeepLink> {
+        let Some(application) = self.application() else {
+            return vec![];
+        };
+        let mut deep_links = vec![];
+        for content in &application.activities {
+            let activity = match content {
+                ContentType::Activity(activity) | ContentType::ActivityAlias(activity) => activity,
+                _ => continue,
+            };
+            let component = self.qualified_name(
+                activity
+                    .target_activity
+                    .as_deref()
+                    .unwrap_or(&activity.name),
+            );
+            for filter in &activity.intent_filters {
+                let browsable = filter
+                    .categories()
+                    .contains(&"android.intent.category.BROWSABLE");
+                let is_app_link = filter.is_app_link();
+                deep_links.extend(filter.deep_link_patterns().into_iter().map(|pattern| {
+                    DeepLink {
+                        component: component.clone(),
+                        pattern,
+                        auto_verify: filter.auto_verifies(),
+                        is_app_link,
+                        browsable,
+                        exported: activity.is_exported(),
+                    }
+                }));
+            }
+        }
+        deep_links
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn manifest(application: &str) -> AndroidManifest {
+        let xml = format!(
+            r#"<manifest package="com.example">
+                <uses-sdk minSdkVersion="21" targetSdkVersion="33" />
+                <application>{}</application>
+            </manifest>"#,
+            application
+        );
+        serde_xml_rs::from_str(&xml).expect("valid manifest")
+    }
+
+    fn filter(xml: &str) -> AndroidIntentFilter {
+        serde_xml_rs::from_str(xml).expect("valid intent filter")
+    }
+
+    fn activities(manifest: &AndroidManifest) -> Vec<&AndroidActivity> {
+        manifest
+            .application()
+            .unwrap()
+            .activities
+            .iter()
+            .filter_map(|c| match c {
+                ContentType::Activity(activity) | ContentType::ActivityAlias(activity) => {
+                    Some(activity)
+                }
+                _ => None,
+            })
+            .collect()
+    }
+
+    #[test]
+    fn interleaved_intent_filters_and_meta_data() {
+        let manifest = manifest(
+            r#"<activity name=".Main">
+                <intent-filter>
+                    <action name="android.intent.action.MAIN" />
+                </intent-filter>
+                <meta-data name="first" value="1" />
+                <intent-filter>
+                    <action name="android.intent.action.VIEW" />
+                </intent-filter>
+                <meta-data name="second" resource="@xml/config" />
+            </activity>"#,
+        );
+        let activity = activities(&manifest)[0];
+        assert_eq!(activity.intent_filters.len(), 2);
+        assert_eq!(
+            activity.intent_filters[1].actions(),
+            ["android.intent.action.VIEW"]
+        );
+        assert_eq!(activity.meta_data.len(), 2);
+        assert_eq!(
+            activity.meta_data[1].resource.as_deref(),
+            Some("@xml/config")
+        );
+
+        // the json representation keeps the field names of the manifest
+        let json = serde_json::to_value(activity).unwrap();
+        assert_eq!(json["intent-filter"].as_array().unwrap().len(), 2);
+    }
+
+    #[test]
+    fn exported_may_reference_a_resource() {
+        let manifest = manifest(
+            r#"<activity name=".Referenced" exported="@bool/is_tablet">
+                <intent-filter>
+                    <action name="android.intent.action.VIEW" />
+                </intent-filter>
+            </activity>
+            <activity name=".Hidden" exported="false">
+                <intent-filter>
+                    <action name="android.intent.action.VIEW" />
+                </intent-filter>
+            </activity>
+            <activity name=".Internal" />
+            <provider name=".Provider" authorities="com.example.files" exported="true" grantUriPermissions="@bool/grant" />"#,
+        );
+        assert_eq!(manifest.target_sdk_version(), Some(33));
+        let activities = activities(&manifest);
+        assert_eq!(
+            activities[0].exported,
+            Some(BoolAttribute::Reference("@bool/is_tablet".to_string()))
+        );
+        assert!(activities[0].is_exported());
+        assert!(!activities[1].is_exported());
+        assert!(!activities[2].is_exported());
+
+        let provider = manifest
+            .application()
+            .unwrap()
+            .activities
+            .iter()
+            .find_map(|c| match c {
+                ContentType::Provider(provider) => Some(provider),
+                _ => None,
+            })
+            .unwrap();
+        assert!(provider.is_exported());
+        assert_eq!(
+            provider
+                .grant_uri_permissions
+                .as_ref()
+                .and_then(BoolAttribute::value),
+            None
+        );
+    }
+
+    #[test]
+    fn deep_link_patterns_are_the_cross_product_of_data_elements() {
+        let filter = filter(
+            r#"<intent-filter autoVerify="true">
+                <action name="android.intent.action.VIEW" />
+                <category name="android.intent.category.BROWSABLE" />
+                <data scheme="http" />
+                <data scheme="https" host="example.com" />
+                <data host="www.example.com" port="8080" />
+                <data pathPrefix="/items" />
+                <data path="/about" />
+            </intent-filter>"#,
+        );
+        let patterns: Vec<String> = filter
+            .deep_link_patterns()
+            .iter()
+            .map(|p| p.to_string())
+            .collect();
+        assert_eq!(
+            patterns,
+            [
+                "http://example.com/items*",
+                "http://example.com/about",
+                "http://www.example.com:8080/items*",
+                "http://www.example.com:8080/about",
+                "https://example.com/items*",
+                "https://example.com/about",
+                "https://www.example.com:8080/items*",
+                "https://www.example.com:8080/about",
+            ]
+        );
+        assert!(filter.auto_verifies());
+        assert!(filter.is_app_link());
+    }
+
+    #[test]
+    fn deep_link_patterns_need_a_scheme_and_host() {
+        let scheme_only = filter(
+            r#"<intent-filter>
+                <data scheme="myapp" />
+                <data pathSuffix=".pdf" />
+            </intent-filter>"#,
+        );
+        let patterns = scheme_only.deep_link_patterns();
+        assert_eq!(patterns.len(), 1);
+        assert_eq!(patterns[0].path, None);
+        assert_eq!(patterns[0].to_string(), "myapp:*");
+        assert!(!scheme_only.is_app_link());
+
+        let host_only = filter(r#"<intent-filter><data host="example.com" /></intent-filter>"#);
+        assert!(host_only.deep_link_patterns().is_empty());
+
+        let unverified = filter(
+            r#"<intent-filter autoVerify="@bool/verify">
+                <data scheme="https" host="example.com" pathPattern="/.*/detail" />
+            </intent-filter>"#,
+        );
+        let patterns = unverified.deep_link_patterns();
+        assert_eq!(
+            patterns[0].path,
+            Some(DeepLinkPath::Pattern("/.*/detail".to_string()))
+        );
+        assert_eq!(patterns[0].to_string(), "https://example.com/.*/detail");
+        assert!(!unverified.is_app_link());
+    }
+
+    #[test]
+    fn deep_links_resolve_aliases_and_relative_names() {
+        let manifest = manifest(
+            r#"<activity name=".Main" />
+            <activity-alias name="Alias" targetActivity=".Main" exported="true">
+                <intent-filter>
+                    <category name="android.intent.category.BROWSABLE" />
+                    <data scheme="myapp" host="open" />
+                </intent-filter>
+            </activity-alias>
+            <activity name="com.other.Handler">
+                <intent-filter>
+                    <data scheme="other" />
+                </intent-filter>
+            </activity>"#,
+        );
+        let deep_links = manifest.deep_links();
+        assert_eq!(deep_links.len(), 2);
+        assert_eq!(deep_links[0].component, "com.example.Main");
+        assert_eq!(deep_links[0].pattern.to_string(), "myapp://open/*");
+        assert!(deep_links[0].browsable && deep_links[0].exported);
+        assert_eq!(deep_links[1].component, "com.other.Handler");
+        assert!(!deep_links[1].browsable);
+        assert!(deep_links[1].exported);
+    }
 }
@@ -0,0 +1,439 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Models for xml resources with security relevance, which are referenced from the manifest.
+//! Namely the network security config (`android:networkSecurityConfig`) and the path
+//! definitions of a `FileProvider`.
+//!
+//! Elements which may appear interleaved are modeled with `$value` enums, as `serde_xml_rs`
+//! does not support non-contiguous repeated fields.
+
+fn default_as_false() -> bool {
+    false
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+/// Representation of `res/xml/network_security_config.xml`
+pub struct NetworkSecurityConfig {
+    #[serde(rename = "$value", default)]
+    pub content: Vec<NetworkSecurityConfigContent>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum NetworkSecurityConfigContent {
+    #[serde(rename = "base-config")]
+    BaseConfig(DomainConfig),
+    #[serde(rename = "domain-config")]
+    DomainConfig(DomainConfig),
+    #[serde(rename = "debug-overrides")]
+    DebugOverrides(DomainConfig),
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+/// A `domain-config`. `base-config` and `debug-overrides` share the same structure, but never contain domains.
+pub struct DomainConfig {
+    #[serde(rename = "cleartextTrafficPermitted")]
+    pub cleartext_traffic_permitted: Option<bool>,
+    #[serde(rename = "$value", default)]
+    pub content: Vec<DomainConfigContent>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum DomainConfigContent {
+    #[serde(rename = "domain")]
+    Domain(Domain),
+    #[serde(rename = "pin-set")]
+    PinSet(PinSet),
+    #[serde(rename = "trust-anchors")]
+    TrustAnchors(TrustAnchors),
+    #[serde(rename = "domain-config")]
+    DomainConfig(DomainConfig),
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Domain {
+    #[serde(rename = "includeSubdomains", default = "default_as_false")]
+    pub include_subdomains: bool,
+    #[serde(rename = "$value")]
+    pub name: String,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct PinSet {
+    pub expiration: Option<String>,
+    #[serde(rename = "$value", default)]
+    pub pins: Vec<PinSetContent>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum PinSetContent {
+    #[serde(rename = "pin")]
+    Pin(Pin),
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Pin {
+    /// The digest algorithm, currently only `SHA-256` is supported by Android
+    pub digest: String,
+    /// Base64 encoded hash of the SubjectPublicKeyInfo
+    #[serde(rename = "$value")]
+    pub hash: String,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct TrustAnchors {
+    #[serde(rename = "$value", default)]
+    pub certificates: Vec<TrustAnchorsContent>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum TrustAnchorsContent {
+    #[serde(rename = "certificates")]
+    Certificates(Certificates),
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct Certificates {
+    /// Either `system`, `user` or a reference to a raw resource containing certificates
+    pub src: String,
+    #[serde(rename = "overridePins", default = "default_as_false")]
+    pub override_pins: bool,
+}
+
+impl NetworkSecurityConfig {
+    pub fn base_config(&self) -> Option<&DomainConfig> {
+        self.content.iter().find_map(|c| match c {
+            NetworkSecurityConfigContent::BaseConfig(config) => Some(config),
+            _ => None,
+        })
+    }
+    pub fn debug_overrides(&self) -> Option<&DomainConfig> {
+        self.content.iter().find_map(|c| match c {
+            NetworkSecurityConfigContent::DebugOverrides(config) => Some(config),
+            _ => None,
+        })
+    }
+    /// The top level domain configs. Nested configs are available through `DomainConfig::domain_configs`.
+    pub fn domain_configs(&self) -> Vec<&DomainConfig> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                NetworkSecurityConfigContent::DomainConfig(config) => Some(config),
+                _ => None,
+            })
+            .collect()
+    }
+
+    /// Whether cleartext traffic is permitted for domains without an explicit setting. Without a
+    /// `base-config` this is the platform default, which only forbids cleartext traffic for apps
+    /// targeting API level 28 or higher.
+    pub fn cleartext_permitted_by_default(&self, target_sdk_version: Option<u32>) -> bool {
+        self.base_config()
+            .and_then(|c| c.cleartext_traffic_permitted)
+            .unwrap_or(!matches!(target_sdk_version, Some(sdk) if sdk >= 28))
+    }
+
+    /// All domains for which cleartext traffic is permitted, taking inheritance from enclosing configs
+    /// and the platform default for the given target sdk version into account
+    pub fn cleartext_domains(&self, target_sdk_version: Option<u32>) -> Vec<Domain> {
+        let default = self.cleartext_permitted_by_default(target_sdk_version);
+        let mut domains = vec![];
+        for config in self.domain_configs() {
+            config.collect_effective(default, None, &mut |config, cleartext, _| {
+                if cleartext {
+                    domains.extend(config.domains().into_iter().cloned());
+                }
+            });
+        }
+        domains
+    }
+
+    /// All domains which have a pin-set, together with the pins applying to them
+    pub fn pinned_domains(&self) -> Vec<(Vec<Domain>, PinSet)> {
+        let mut pinned = vec![];
+        for config in self.domain_configs() {
+            config.collect_effective(false, None, &mut |config, _, pin_set| {
+                if let Some(pin_set) = pin_set {
+                    let domains = config.domains().into_iter().cloned().collect();
+                    pinned.push((domains, pin_set.clone()));
+                }
+            });
+        }
+        pinned
+    }
+
+    /// All trust anchors of the base config and domain configs. Debug overrides are not included.
+    pub fn trust_anchors(&self) -> Vec<&Certificates> {
+        let mut anchors = vec![];
+        if let Some(base_config) = self.base_config() {
+            anchors.extend(base_config.trust_anchors());
+        }
+        for config in self.domain_configs() {
+            config.collect_all(&mut |c| anchors.extend(c.trust_anchors()));
+        }
+        anchors
+    }
+
+    /// True if user installed certificates are trusted in release builds
+    pub fn trusts_user_certificates(&self) -> bool {
+        self.trust_anchors().iter().any(|c| c.src == "user")
+    }
+}
+
+impl DomainConfig {
+    pub fn domains(&self) -> Vec<&Domain> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                DomainConfigContent::Domain(domain) => Some(domain),
+                _ => None,
+            })
+            .collect()
+    }
+    pub fn pin_set(&self) -> Option<&PinSet> {
+        self.content.iter().find_map(|c| match c {
+            DomainConfigContent::PinSet(pin_set) => Some(pin_set),
+            _ => None,
+        })
+    }
+    pub fn trust_anchors(&self) -> Vec<&Certificates> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                DomainConfigContent::TrustAnchors(anchors) => Some(anchors),
+                _ => None,
+            })
+            .flat_map(|anchors| anchors.certificates.iter())
+            .map(|TrustAnchorsContent::Certificates(c)| c)
+            .collect()
+    }
+    pub fn domain_configs(&self) -> Vec<&DomainConfig> {
+        self.content
+            .iter()
+            .filter_map(|c| match c {
+                DomainConfigContent::DomainConfig(config) => Some(config),
+                _ => None,
+            })
+            .collect()
+    }
+
+    fn collect_all<'a, F: FnMut(&'a DomainConfig)>(&'a self, f: &mut F) {
+        f(self);
+        for config in self.domain_configs() {
+            config.collect_all(f);
+        }
+    }
+
+    /// Nested configs inherit unspecified settings from the enclosing config
+    fn collect_effective<'a, F>(&'a self, cleartext: bool, pin_set: Option<&'a PinSet>, f: &mut F)
+    where
+        F: FnMut(&'a DomainConfig, bool, Option<&'a PinSet>),
+    {
+        let cleartext = self.cleartext_traffic_permitted.unwrap_or(cleartext);
+        let pin_set = self.pin_set().or(pin_set);
+        f(self, cleartext, pin_set);
+        for config in self.domain_configs() {
+            config.collect_effective(cleartext, pin_set, f);
+        }
+    }
+}
+
+impl PinSet {
+    pub fn pins(&self) -> Vec<&Pin> {
+        self.pins
+            .iter()
+            .map(|PinSetContent::Pin(pin)| pin)
+            .collect()
+    }
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+/// The path configuration of a `FileProvider` (`android.support.FILE_PROVIDER_PATHS`)
+pub struct FileProviderPaths {
+    #[serde(rename = "$value", default)]
+    pub paths: Vec<FileProviderPath>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub enum FileProviderPath {
+    #[serde(rename = "root-path")]
+    RootPath(FileProviderPathEntry),
+    #[serde(rename = "files-path")]
+    FilesPath(FileProviderPathEntry),
+    #[serde(rename = "cache-path")]
+    CachePath(FileProviderPathEntry),
+    #[serde(rename = "external-path")]
+    ExternalPath(FileProviderPathEntry),
+    #[serde(rename = "external-files-path")]
+    ExternalFilesPath(FileProviderPathEntry),
+    #[serde(rename = "external-cache-path")]
+    ExternalCachePath(FileProviderPathEntry),
+    #[serde(rename = "external-media-path")]
+    ExternalMediaPath(FileProviderPathEntry),
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FileProviderPathEntry {
+    pub name: String,
+    #[serde(default)]
+    pub path: String,
+}
+
+impl FileProviderPath {
+    pub fn entry(&self) -> &FileProviderPathEntry {
+        match self {
+            FileProviderPath::RootPath(e)
+            | FileProviderPath::FilesPath(e)
+            | FileProviderPath::CachePath(e)
+            | FileProviderPath::ExternalPath(e)
+            | FileProviderPath::ExternalFilesPath(e)
+            | FileProviderPath::ExternalCachePath(e)
+            | FileProviderPath::ExternalMediaPath(e) => e,
+        }
+    }
+    /// The directory the path is relative to, as returned by the corresponding android api
+    pub fn base_directory(&self) -> &'static str {
+        match self {
+            FileProviderPath::RootPath(_) => "/",
+            FileProviderPath::FilesPath(_) => "Context.getFilesDir()",
+            FileProviderPath::CachePath(_) => "Context.getCacheDir()",
+            FileProviderPath::ExternalPath(_) => "Environment.getExternalStorageDirectory()",
+            FileProviderPath::ExternalFilesPath(_) => "Context.getExternalFilesDir(null)",
+            FileProviderPath::ExternalCachePath(_) => "Context.getExternalCacheDir()",
+            FileProviderPath::ExternalMediaPath(_) => "Context.getExternalMediaDirs()",
+        }
+    }
+    /// Exposing the root or the whole base directory (e.g. `path="."`) is usually not intended
+    pub fn is_overly_broad(&self) -> bool {
+        let path = self.entry().path.trim_matches('/');
+        matches!(self, FileProviderPath::RootPath(_)) || path.is_empty() || path == "."
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config(xml: &str) -> NetworkSecurityConfig {
+        serde_xml_rs::from_str(xml).expect("valid network security config")
+    }
+
+    fn names(domains: &[Domain]) -> Vec<&str> {
+        domains.iter().map(|d| d.name.as_str()).collect()
+    }
+
+    #[test]
+    fn cleartext_default_depends_on_target_sdk() {
+        let config = config(
+            r#"<network-security-config>
+                <domain-config>
+                    <domain>example.com</domain>
+                </domain-config>
+            </network-security-config>"#,
+        );
+        assert!(config.cleartext_permitted_by_default(None));
+        assert!(config.cleartext_permitted_by_default(Some(27)));
+        assert!(!config.cleartext_permitted_by_default(Some(28)));
+        assert_eq!(names(&config.cleartext_domains(Some(27))), ["example.com"]);
+        assert!(config.cleartext_domains(Some(28)).is_empty());
+    }
+
+    #[test]
+    fn base_config_overrides_platform_default() {
+        let config = config(
+            r#"<network-security-config>
+                <base-config cleartextTrafficPermitted="false" />
+                <domain-config>
+                    <domain>example.com</domain>
+                </domain-config>
+            </network-security-config>"#,
+        );
+        assert!(!config.cleartext_permitted_by_default(Some(23)));
+        assert!(config.cleartext_domains(Some(23)).is_empty());
+    }
+
+    #[test]
+    fn nested_domain_configs_inherit_settings() {
+        let config = config(
+            r#"<network-security-config>
+                <domain-config cleartextTrafficPermitted="true">
+                    <domain includeSubdomains="true">example.com</domain>
+                    <pin-set expiration="2030-01-01">
+                        <pin digest="SHA-256">AAAA</pin>
+                    </pin-set>
+                    <domain-config>
+                        <domain>inherited.example.com</domain>
+                    </domain-config>
+                    <domain-config cleartextTrafficPermitted="false">
+                        <domain>secure.example.com</domain>
+                    </domain-config>
+                </domain-config>
+            </network-security-config>"#,
+        );
+        let cleartext = config.cleartext_domains(Some(30));
+        assert_eq!(names(&cleartext), ["example.com", "inherited.example.com"]);
+        assert!(cleartext[0].include_subdomains);
+        assert!(!cleartext[1].include_subdomains);
+
+        let pinned = config.pinned_domains();
+        assert_eq!(pinned.len(), 3);
+        for (_, pin_set) in &pinned {
+            assert_eq!(pin_set.expiration.as_deref(), Some("2030-01-01"));
+            assert_eq!(pin_set.pins()[0].hash, "AAAA");
+        }
+        assert_eq!(names(&pinned[2].0), ["secure.example.com"]);
+    }
+
+    #[test]
+    fn trust_anchors_of_domain_configs_and_overrides() {
+        let config = config(
+            r#"<network-security-config>
+                <base-config>
+                    <trust-anchors>
+                        <certificates src="system" />
+                    </trust-anchors>
+                </base-config>
+                <domain-config>
+                    <domain>example.com</domain>
+                    <trust-anchors>
+                        <certificates src="user" overridePins="true" />
+                        <certificates src="@raw/ca" />
+                    </trust-anchors>
+                </domain-config>
+                <debug-overrides>
+                    <trust-anchors>
+                        <certificates src="user" />
+                    </trust-anchors>
+                </debug-overrides>
+            </network-security-config>"#,
+        );
+        let anchors = config.trust_anchors();
+        let sources: Vec<_> = anchors.iter().map(|c| c.src.as_str()).collect();
+        assert_eq!(sources, ["system", "user", "@raw/ca"]);
+        assert!(!anchors[0].override_pins);
+        assert!(anchors[1].override_pins);
+        assert!(config.trusts_user_certificates());
+    }
+
+    #[test]
+    fn user_certificates_in_debug_overrides_are_ignored() {
+        let config = config(
+            r#"<network-security-config>
+                <debug-overrides>
+                    <trust-anchors>
+                        <certificates src="user" />
+                    </trust-anchors>
+                </debug-overrides>
+            </network-security-config>"#,
+        );
+        assert!(config.trust_anchors().is_empty());
+        assert!(!config.trusts_user_certificates());
+        assert_eq!(
+            config.debug_overrides().unwrap().trust_anchors()[0].src,
+            "user"
+        );
+    }
+}
@@ -0,0 +1,650 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Classes, methods and fields of the android framework together with the API levels they were
+//! added, deprecated and removed in. Coeus bundles a curated subset (see
+//! `data/framework_api.txt`); complete databases are generated from the platform stubs of the
+//! SDK (see `coeus_parse::api_database`) and installed with `ApiDatabase::set_global`.
+
+use std::{
+    collections::{HashMap, HashSet, VecDeque},
+    sync::{Arc, OnceLock, RwLock},
+};
+
+use super::{AccessFlags, FrameworkClass, FrameworkStub, LATEST_API_LEVEL};
+
+const BUNDLED_API: &str = include_str!("../../data/framework_api.txt");
+
+static GLOBAL: RwLock<Option<Arc<ApiDatabase>>> = RwLock::new(None);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ApiLevels {
+    pub since: u32,
+    pub deprecated: Option<u32>,
+    pub removed: Option<u32>,
+}
+
+impl Default for ApiLevels {
+    fn default() -> Self {
+        Self {
+            since: 1,
+            deprecated: None,
+            removed: None,
+        }
+    }
+}
+
+impl ApiLevels {
+    pub fn is_available(&self, api_level: u32) -> bool {
+        self.since <= api_level && self.removed.is_none_or(|removed| api_level < removed)
+    }
+    pub fn is_deprecated(&self, api_level: u32) -> bool {
+        self.deprecated
+            .is_some_and(|deprecated| deprecated <= api_level)
+    }
+}
+
+/// A super class or an implemented interface
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ApiReference {
+    pub name: String,
+    pub levels: ApiLevels,
+}
+
+/// A method or a field
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ApiMember {
+    /// Name and prototype for methods (`loadUrl(Ljava/lang/String;)V`), name and type for
+    /// fields (`RESULT_OK:I`)
+    pub signature: String,
+    pub access_flags: AccessFlags,
+    pub levels: ApiLevels,
+}
+
+impl ApiMember {
+    pub fn name(&self) -> &str {
+        let end = self.signature.find(['(', ':']);
+        &self.signature[..end.unwrap_or(self.signature.len())]
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ApiClass {
+    /// The type descriptor, e.g. `Landroid/app/Activity;`
+    pub name: String,
+    pub access_flags: AccessFlags,
+    pub levels: ApiLevels,
+    /// Super classes over all API levels, at most one of them is available at a time
+    pub super_classes: Vec<ApiReference>,
+    pub interfaces: Vec<ApiReference>,
+    pub methods: Vec<ApiMember>,
+    pub fields: Vec<ApiMember>,
+}
+
+impl ApiClass {
+    pub fn new(name: &str, access_flags: AccessFlags, levels: ApiLevels) -> Self {
+        Self {
+            name: name.to_string(),
+            access_flags,
+            levels,
+            super_classes: vec![],
+            interfaces: vec![],
+            methods: vec![],
+            fields: vec![],
+        }
+    }
+
+    pub fn is_interface(&self) -> bool {
+        self.access_flags.contains(AccessFlags::INTERFACE)
+    }
+
+    pub fn super_class(&self, api_level: u32) -> Option<&str> {
+        self.super_classes
+            .iter()
+            .find(|s| s.levels.is_available(api_level))
+            .map(|s| s.name.as_str())
+    }
+
+    pub fn interfaces(&self, api_level: u32) -> impl Iterator<Item = &str> {
+        self.interfaces
+            .iter()
+            .filter(move |i| i.levels.is_available(api_level))
+            .map(|i| i.name.as_str())
+    }
+
+    /// The method with this name and prototype
+    pub fn method(&self, signature: &str) -> Option<&ApiMember> {
+        self.methods.iter().find(|m| m.signature == signature)
+    }
+
+    /// The field with this name, types are ignored
+    pub fn field(&self, name: &str) -> Option<&ApiMember> {
+        self.fields.iter().find(|f| f.name() == name)
+    }
+}
+
+#[derive(Debug, Clone, Default, serde::Serialize, serde::Deserialize)]
+pub struct ApiDatabase {
+    /// The highest API level covered
+    pub api_level: u32,
+    classes: HashMap<String, ApiClass>,
+}
+
+impl ApiDatabase {
+    pub fn new(api_level: u32) -> Self {
+        Self {
+            api_level,
+            classes: HashMap::new(),
+        }
+    }
+
+    /// The database bundled with coeus
+    pub fn bundled() -> Arc<ApiDatabase> {
+        static BUNDLED: OnceLock<Arc<ApiDatabase>> = OnceLock::new();
+        BUNDLED
+            .get_or_init(|| {
+                let mut database = Self::parse(BUNDLED_API).expect("bundled api database is valid");
+                database.api_level = LATEST_API_LEVEL;
+                Arc::new(database)
+            })
+            .clone()
+    }
+
+    /// The database used when resolving framework types, the bundled one unless another was
+    /// installed with `set_global`
+    pub fn global() -> Arc<ApiDatabase> {
+        match GLOBAL.read().ok().and_then(|global| global.clone()) {
+            Some(database) => database,
+            None => Self::bundled(),
+        }
+    }
+
+    pub fn set_global(database: ApiDatabase) {
+        if let Ok(mut global) = GLOBAL.write() {
+            *global = Some(Arc::new(database));
+        }
+    }
+
+    /// Parse a database in the text format of the bundled one
+    pub fn parse(content: &str) -> Result<Self, String> {
+        let mut database = Self::new(0);
+        let mut current: Option<String> = None;
+        for (line_number, line) in content.lines().enumerate() {
+            let error = |msg: &str| format!("line {}: {}", line_number + 1, msg);
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let mut tokens = line.split_whitespace();
+            let kind = tokens.next().unwrap_or_default();
+            let (positional, options): (Vec<&str>, Vec<&str>) =
+                tokens.partition(|token| !token.contains('='));
+            let name = positional
+                .first()
+                .ok_or_else(|| error("missing name"))?
+                .to_string();
+            let mut levels = ApiLevels::default();
+            if let Some(since) = positional.get(1) {
+                levels.since = parse_level(since).map_err(|e| error(&e))?;
+            }
+            for option in options {
+                match option.split_once('=') {
+                    Some(("deprecated", level)) => {
+                        levels.deprecated = Some(parse_level(level).map_err(|e| error(&e))?)
+                    }
+                    Some(("removed", level)) => {
+                        levels.removed = Some(parse_level(level).map_err(|e| error(&e))?)
+                    }
+                    _ => return Err(error(&format!("unknown option {}", option))),
+                }
+            }
+            for level in [Some(levels.since), levels.deprecated, levels.removed]
+                .iter()
+                .flatten()
+            {
+                database.api_level = database.api_level.max(*level);
+            }
+
+            match kind {
+                "class" | "interface" => {
+                    let access_flags = if kind == "interface" {
+                        AccessFlags::PUBLIC | AccessFlags::INTERFACE | AccessFlags::ABSTRACT
+                    } else {
+                        AccessFlags::PUBLIC
+                    };
+                    let class = database
+                        .classes
+                        .entry(name.clone())
+                        .or_insert_with(|| ApiClass::new(&name, access_flags, levels));
+                    if class.levels.since > levels.since {
+                        class.levels = levels;
+                    }
+                    if let Some(super_class) = positional.get(2) {
+                        // the super class is replaced from this level on
+                        for previous in class.super_classes.iter_mut() {
+                            if previous.levels.removed.is_none()
+                                && previous.levels.since < levels.since
+                            {
+                                previous.levels.removed = Some(levels.since);
+                            }
+                        }
+                        class.super_classes.push(ApiReference {
+                            name: super_class.to_string(),
+                            levels: ApiLevels {
+                                since: levels.since,
+                                ..Default::default()
+                            },
+                        });
+                    }
+                    current = Some(name);
+                }
+                "implements" | "method" | "field" => {
+                    let class = current
+                        .as_ref()
+                        .and_then(|current| database.classes.get_mut(current))
+                        .ok_or_else(|| error("member outside of a class"))?;
+                    match kind {
+                        "implements" => class.interfaces.push(ApiReference { name, levels }),
+                        "method" => class.methods.push(ApiMember {
+                            signature: name,
+                            access_flags: AccessFlags::PUBLIC,
+                            levels,
+                        }),
+                        _ => class.fields.push(ApiMember {
+                            signature: name,
+                            access_flags: AccessFlags::PUBLIC,
+                            levels,
+                        }),
+                    }
+                }
+                other => return Err(error(&format!("unknown entry {}", other))),
+            }
+        }
+        Ok(database)
+    }
+
+    pub fn insert(&mut self, class: ApiClass) {
+        self.classes.insert(class.name.clone(), class);
+    }
+
+    pub fn get_class(&self, class_name: &str) -> Option<&ApiClass> {
+        self.classes.get(class_name)
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item = &ApiClass> {
+        self.classes.values()
+    }
+
+    /// Super classes first, followed by all interfaces, as available at `api_level`
+    fn supertypes(&self, class_name: &str, api_level: u32) -> Vec<&ApiClass> {
+        let mut superclasses = vec![];
+        let mut visited = HashSet::new();
+        let mut current = self.get_class(class_name);
+        while let Some(class) = current {
+            if !visited.insert(class.name.as_str()) {
+                break;
+            }
+            superclasses.push(class);
+            current = class
+                .super_class(api_level)
+                .and_then(|name| self.get_class(name));
+        }
+        let mut queue: VecDeque<&ApiClass> = superclasses.iter().copied().collect();
+        let mut supertypes = superclasses;
+        while let Some(class) = queue.pop_front() {
+            for interface in class.interfaces(api_level) {
+                if let Some(interface) = self.get_class(interface) {
+                    if visited.insert(interface.name.as_str()) {
+                        supertypes.push(interface);
+                        queue.push_back(interface);
+                    }
+                }
+            }
+        }
+        supertypes
+    }
+
+    /// Find the method declared by the class or inherited from a supertype
+    pub fn find_method(
+        &self,
+        class_name: &str,
+        signature: &str,
+        api_level: u32,
+    ) -> Option<(&ApiClass, &ApiMember)> {
+        self.supertypes(class_name, api_level)
+            .into_iter()
+            .find_map(|class| {
+                class
+                    .method(signature)
+                    .filter(|m| m.levels.is_available(api_level))
+                    .map(|m| (class, m))
+            })
+    }
+
+    /// Find the field declared by the class or inherited from a supertype
+    pub fn find_field(
+        &self,
+        class_name: &str,
+        name: &str,
+        api_level: u32,
+    ) -> Option<(&ApiClass, &ApiMember)> {
+        self.supertypes(class_name, api_level)
+            .into_iter()
+            .find_map(|class| {
+                class
+                    .field(name)
+                    .filter(|f| f.levels.is_available(api_level))
+                    .map(|f| (class, f))
+            })
+    }
+
+    /// All overloads of the method with this name, declared by the class or its supertypes
+    pub fn overloads(
+        &self,
+        class_name: &str,
+        name: &str,
+        api_level: u32,
+    ) -> Vec<(&ApiClass, &ApiMember)> {
+        let mut seen = HashSet::new();
+        self.supertypes(class_name, api_level)
+            .into_iter()
+            .flat_map(|class| {
+                class
+                    .methods
+                    .iter()
+                    .filter(move |m| m.name() == name && m.levels.is_available(api_level))
+                    .map(move |m| (class, m))
+            })
+            .filter(|(_, m)| seen.insert(m.signature.as_str()))
+            .collect()
+    }
+
+    /// The hierarchy relevant part of the database at one API level
+    pub fn stub(&self, api_level: u32) -> FrameworkStub {
+        let classes = self
+            .classes()
+            .filter(|class| class.levels.is_available(api_level))
+            .map(|class| {
+                let framework_class = FrameworkClass {
+                    name: class.name.clone(),
+                    is_interface: class.is_interface(),
+                    super_class: class.super_class(api_level).map(|s| s.to_string()),
+                    interfaces: class.interfaces(api_level).map(|i| i.to_string()).collect(),
+                    methods: class
+                        .methods
+                        .iter()
+                        .filter(|m| m.levels.is_available(api_level) && is_overridable(m))
+                        .map(|m| m.signature.clone())
+                        .collect(),
+                };
+                (class.name.clone(), framework_class)
+            })
+            .collect();
+        FrameworkStub { api_level, classes }
+    }
+}
+
+fn is_overridable(method: &ApiMember) -> bool {
+    !method.signature.starts_with('<')
+        && !method
+            .access_flags
+            .intersects(AccessFlags::STATIC | AccessFlags::PRIVATE | AccessFlags::FINAL)
+}
+
+fn parse_level(level: &str) -> Result<u32, String> {
+    level
+        .parse()
+        .map_err(|_| format!("invalid api level {}", level))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const API: &str = "
+# comment
+class Ljava/lang/Object; 1
+    method <init>()V
+    method toString()Ljava/lang/String;
+
+interface Ljava/lang/Runnable; 1
+    method run()V
+
+class Landroid/view/View; 1 Ljava/lang/Object;
+    method setOnClickListener(Landroid/view/View$OnClickListener;)V
+    field VISIBLE:I
+    field GONE:I 1 deprecated=30
+
+class Landroid/widget/AbsoluteLayout; 1 Landroid/view/View; deprecated=3
+class Landroid/webkit/WebView; 1 Landroid/widget/AbsoluteLayout;
+    implements Ljava/lang/Runnable; 21
+    method loadUrl(Ljava/lang/String;)V
+    method loadUrl(Ljava/lang/String;Ljava/util/Map;)V 8
+    method toString()Ljava/lang/String;
+    method savePassword(Ljava/lang/String;)V 1 deprecated=18 removed=30
+class Landroid/webkit/WebView; 30 Landroid/view/View;
+
+class Landroid/util/FloatMath; 1 Ljava/lang/Object; removed=23
+";
+
+    fn database() -> ApiDatabase {
+        ApiDatabase::parse(API).unwrap()
+    }
+
+    #[test]
+    fn entries_are_parsed() {
+        let database = database();
+        assert_eq!(database.api_level, 30);
+        assert_eq!(database.classes().count(), 6);
+
+        let runnable = database.get_class("Ljava/lang/Runnable;").unwrap();
+        assert!(runnable.is_interface());
+        assert!(runnable.super_classes.is_empty());
+
+        let view = database.get_class("Landroid/view/View;").unwrap();
+        assert!(!view.is_interface());
+        assert_eq!(view.super_class(1), Some("Ljava/lang/Object;"));
+        let gone = view.field("GONE").unwrap();
+        assert_eq!(gone.signature, "GONE:I");
+        assert_eq!(
+            gone.levels,
+            ApiLevels {
+                since: 1,
+                deprecated: Some(30),
+                removed: None
+            }
+        );
+
+        let web_view = database.get_class("Landroid/webkit/WebView;").unwrap();
+        assert_eq!(web_view.levels, ApiLevels::default());
+        let save_password = web_view
+            .method("savePassword(Ljava/lang/String;)V")
+            .unwrap();
+        assert_eq!(save_password.name(), "savePassword");
+        assert_eq!(
+            save_password.levels,
+            ApiLevels {
+                since: 1,
+                deprecated: Some(18),
+                removed: Some(30)
+            }
+        );
+        assert_eq!(web_view.interfaces(20).count(), 0);
+        assert_eq!(
+            web_view.interfaces(21).collect::<Vec<_>>(),
+            ["Ljava/lang/Runnable;"]
+        );
+    }
+
+    #[test]
+    fn later_entries_replace_the_super_class() {
+        let database = database();
+        let web_view = database.get_class("Landroid/webkit/WebView;").unwrap();
+        assert_eq!(web_view.super_classes.len(), 2);
+        assert_eq!(
+            web_view.super_class(29),
+            Some("Landroid/widget/AbsoluteLayout;")
+        );
+        assert_eq!(web_view.super_class(30), Some("Landroid/view/View;"));
+        // members of both entries are kept
+        assert_eq!(web_view.methods.len(), 4);
+    }
+
+    #[test]
+    fn invalid_entries_are_rejected() {
+        for (content, error) in [
+            ("class", "line 1: missing name"),
+            ("class La; x", "line 1: invalid api level x"),
+            (
+                "class La; 1\n  method a()V removed=y",
+                "line 2: invalid api level y",
+            ),
+            ("class La; 1 since=2", "line 1: unknown option since=2"),
+            ("\n  method a()V", "line 2: member outside of a class"),
+            ("enum La; 1", "line 1: unknown entry enum"),
+        ] {
+            assert_eq!(
+                ApiDatabase::parse(content).unwrap_err(),
+                error,
+                "{}",
+                content
+            );
+        }
+    }
+
+    #[test]
+    fn levels_bound_the_availability() {
+        let levels = ApiLevels {
+            since: 5,
+            deprecated: Some(10),
+            removed: Some(20),
+        };
+        assert!(!levels.is_available(4));
+        assert!(levels.is_available(5) && levels.is_available(19));
+        assert!(!levels.is_available(20));
+        assert!(!levels.is_deprecated(9));
+        assert!(levels.is_deprecated(10));
+        assert!(ApiLevels::default().is_available(u32::MAX));
+        assert!(!ApiLevels::default().is_deprecated(u32::MAX));
+    }
+
+    #[test]
+    fn members_are_inherited() {
+        let database = database();
+        let web_view = "Landroid/webkit/WebView;";
+        let (class, _) = database
+            .find_method(web_view, "toString()Ljava/lang/String;", 30)
+            .unwrap();
+        assert_eq!(class.name, web_view);
+        let (class, _) = database
+            .find_method(
+                web_view,
+                "setOnClickListener(Landroid/view/View$OnClickListener;)V",
+                29,
+            )
+            .unwrap();
+        assert_eq!(class.name, "Landroid/view/View;");
+        let (class, _) = database.find_method(web_view, "run()V", 21).unwrap();
+        assert_eq!(class.name, "Ljava/lang/Runnable;");
+        assert!(database.find_method(web_view, "run()V", 20).is_none());
+        assert!(database
+            .find_method(web_view, "savePassword(Ljava/lang/String;)V", 30)
+            .is_none());
+
+        let (class, field) = database.find_field(web_view, "VISIBLE", 30).unwrap();
+        assert_eq!(
+            (class.name.as_str(), field.signature.as_str()),
+            ("Landroid/view/View;", "VISIBLE:I")
+        );
+        assert!(database
+            .find_field("Ljava/lang/Object;", "VISIBLE", 30)
+            .is_none());
+        assert!(database.find_method("La/Missing;", "run()V", 30).is_none());
+    }
+
+    #[test]
+    fn overloads_are_collected_once() {
+        let database = database();
+        let signatures = |name: &str, api_level: u32| -> Vec<String> {
+            database
+                .overloads("Landroid/webkit/WebView;", name, api_level)
+                .into_iter()
+                .map(|(_, m)| m.signature.clone())
+                .collect()
+        };
+        assert_eq!(signatures("loadUrl", 7), ["loadUrl(Ljava/lang/String;)V"]);
+        assert_eq!(
+            signatures("loadUrl", 8),
+            [
+                "loadUrl(Ljava/lang/String;)V",
+                "loadUrl(Ljava/lang/String;Ljava/util/Map;)V"
+            ]
+        );
+        // declared by WebView and Object
+        assert_eq!(signatures("toString", 30), ["toString()Ljava/lang/String;"]);
+    }
+
+    #[test]
+    fn stubs_contain_the_available_types() {
+        let database = database();
+        let stub = database.stub(22);
+        assert_eq!(stub.api_level, 22);
+        assert!(stub.get("Landroid/util/FloatMath;").is_some());
+        assert!(database.stub(23).get("Landroid/util/FloatMath;").is_none());
+
+        let web_view = stub.get("Landroid/webkit/WebView;").unwrap();
+        assert_eq!(
+            web_view.super_class.as_deref(),
+            Some("Landroid/widget/AbsoluteLayout;")
+        );
+        assert_eq!(web_view.interfaces, ["Ljava/lang/Runnable;"]);
+        assert!(web_view
+            .methods
+            .contains(&"savePassword(Ljava/lang/String;)V".to_string()));
+        // constructors cannot be overridden
+        let object = stub.get("Ljava/lang/Object;").unwrap();
+        assert_eq!(object.methods, ["toString()Ljava/lang/String;"]);
+        assert!(stub.get("Ljava/lang/Runnable;").unwrap().is_interface);
+
+        let web_view = database.stub(30);
+        let web_view = web_view.get("Landroid/webkit/WebView;").unwrap();
+        assert_eq!(web_view.super_class.as_deref(), Some("Landroid/view/View;"));
+        assert!(!web_view
+            .methods
+            .contains(&"savePassword(Ljava/lang/String;)V".to_string()));
+    }
+
+    #[test]
+    fn bundled_database_is_valid() {
+        let database = ApiDatabase::bundled();
+        assert_eq!(database.api_level, LATEST_API_LEVEL);
+        let activity = database.get_class("Landroid/app/Activity;").unwrap();
+        assert!(!activity.is_interface());
+        assert!(database
+            .find_method(
+                "Landroid/app/Activity;",
+                "onCreate(Landroid/os/Bundle;)V",
+                LATEST_API_LEVEL
+            )
+            .is_some());
+        // every supertype of the bundled classes is bundled as well
+        for class in database.classes() {
+            let supertypes = class
+                .super_classes
+                .iter()
+                .chain(class.interfaces.iter())
+                .map(|reference| reference.name.as_str());
+            for supertype in supertypes {
+                assert!(
+                    database.get_class(supertype).is_some(),
+                    "{} of {}",
+                    supertype,
+                    class.name
+                );
+            }
+        }
+    }
+}
@@ -0,0 +1,107 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Anomalies found while reading (possibly malformed) zip archives. Many of them are
+//! tolerated by Android, but break other tools, which makes them popular among malware.
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct ArchiveAnomaly {
+    /// Name of the archive (nested archives are separated by `/`)
+    pub archive: String,
+    /// The entry the anomaly belongs to, `None` if it concerns the whole archive
+    pub entry: Option<String>,
+    pub kind: ArchiveAnomalyKind,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum ArchiveAnomalyKind {
+    /// No end of central directory record was found
+    MissingCentralDirectory,
+    /// The central directory could not be read completely
+    CorruptCentralDirectory,
+    /// The entries were recovered by scanning for local file headers
+    RecoveredFromLocalHeaders,
+    /// The end of central directory declares a different number of entries
+    EntryCountMismatch { declared: u64, found: u64 },
+    /// Local header and central directory disagree on the given field
+    LocalHeaderMismatch(String),
+    /// The encryption flag is set, but ignored by Android
+    EncryptionFlag,
+    /// An entry with the same name appeared before. Only the first one is used.
+    DuplicateEntry,
+    /// A compression method other than stored or deflate, which was read as stored data
+    UnsupportedCompression(u16),
+    /// The declared uncompressed size does not match the actual size
+    SizeMismatch { declared: u64, actual: u64 },
+    CrcMismatch { declared: u32, actual: u32 },
+    /// The entry is bigger than the allowed maximum and was skipped
+    SizeLimitExceeded(u64),
+    /// The entry decompresses to more than the allowed ratio (zip bomb) and was skipped
+    CompressionRatioExceeded(u64),
+    /// The archive decompresses to more than the allowed total and further entries were skipped
+    TotalSizeLimitExceeded(u64),
+    /// The entry data ends outside of the archive or the compressed stream is broken
+    Truncated,
+    /// The name contains path traversals, absolute paths or control characters
+    SuspiciousName,
+}
+
+impl std::fmt::Display for ArchiveAnomalyKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ArchiveAnomalyKind::MissingCentralDirectory => {
+                f.write_str("end of central directory not found")
+            }
+            ArchiveAnomalyKind::CorruptCentralDirectory => f.write_str("corrupt central directory"),
+            ArchiveAnomalyKind::RecoveredFromLocalHeaders => {
+                f.write_str("entries recovered from local file headers")
+            }
+            ArchiveAnomalyKind::EntryCountMismatch { declared, found } => write!(
+                f,
+                "central directory declares {} entries, but {} were found",
+                declared, found
+            ),
+            ArchiveAnomalyKind::LocalHeaderMismatch(field) => {
+                write!(f, "local header and central directory differ in {}", field)
+            }
+            ArchiveAnomalyKind::EncryptionFlag => f.write_str("encryption flag set"),
+            ArchiveAnomalyKind::DuplicateEntry => f.write_str("duplicate entry name"),
+            ArchiveAnomalyKind::UnsupportedCompression(method) => {
+                write!(f, "unsupported compression method {}", method)
+            }
+            ArchiveAnomalyKind::SizeMismatch { declared, actual } => write!(
+                f,
+                "declared size {} does not match actual size {}",
+                declared, actual
+            ),
+            ArchiveAnomalyKind::CrcMismatch { declared, actual } => write!(
+                f,
+                "declared crc {:#010x} does not match actual crc {:#010x}",
+                declared, actual
+            ),
+            ArchiveAnomalyKind::SizeLimitExceeded(limit) => {
+                write!(f, "entry exceeds the size limit of {} bytes", limit)
+            }
+            ArchiveAnomalyKind::CompressionRatioExceeded(ratio) => {
+                write!(f, "entry exceeds the compression ratio limit of {}", ratio)
+            }
+            ArchiveAnomalyKind::TotalSizeLimitExceeded(limit) => {
+                write!(f, "archive exceeds the total size limit of {} bytes", limit)
+            }
+            ArchiveAnomalyKind::Truncated => f.write_str("entry data is truncated"),
+            ArchiveAnomalyKind::SuspiciousName => f.write_str("suspicious entry name"),
+        }
+    }
+}
+
+impl std::fmt::Display for ArchiveAnomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match &self.entry {
+            Some(entry) => write!(f, "{} ({}): {}", self.archive, entry, self.kind),
+            None => write!(f, "{}: {}", self.archive, self.kind),
+        }
+    }
+}
@@ -4,22 +4,40 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use std::borrow::Cow;
+use std::{
+    any::Any,
+    borrow::Cow,
+    sync::{Arc, OnceLock},
+};
 
 use goblin::Object;
 use regex::Regex;
 
+use super::{
+    is_complete_serialization, DartSnapshot, HermesBytecode, Il2CppMetadata, NativeLibrary,
+};
+
 #[derive(Clone, serde::Serialize, serde::Deserialize)]
 pub struct BinaryObject {
-    #[serde(skip_serializing_if = "BinaryObject::vec_too_large")]
+    #[serde(skip_serializing_if = "BinaryObject::skip_data", default)]
     data: Vec<u8>,
     //object_cache: Option<Object<'a>>,
+    #[serde(skip)]
+    derived: DerivedData,
 }
 
+/// A result computed from the data of a `BinaryObject` by a later crate (e.g. the native call
+/// graph of the analysis), built on first use and shared by all clones of the object
+#[derive(Default, Clone)]
+struct DerivedData(OnceLock<Option<Arc<dyn Any + Send + Sync>>>);
+
 impl BinaryObject {
     pub fn vec_too_large(arr: &[u8]) -> bool {
         arr.len() > 10_000
     }
+    fn skip_data(arr: &[u8]) -> bool {
+        Self::vec_too_large(arr) && !is_complete_serialization()
+    }
 }
 
 impl std::fmt::Debug for BinaryObject {
@@ -52,7 +70,24 @@ impl std::fmt::Debug for BinaryObject {
 
 impl<'a> BinaryObject {
     pub fn new(data: Vec<u8>) -> Self {
-        BinaryObject { data }
+        BinaryObject {
+            data,
+            derived: DerivedData::default(),
+        }
+    }
+
+    /// The result of `init`, which runs only for the first call. A single type can be stored,
+    /// asking for another type returns `None`.
+    pub fn derived<T: Any + Send + Sync>(
+        &self,
+        init: impl FnOnce() -> Option<T>,
+    ) -> Option<Arc<T>> {
+        self.derived
+            .0
+            .get_or_init(|| init().map(|value| Arc::new(value) as Arc<dyn Any + Send + Sync>))
+            .clone()?
+            .downcast()
+            .ok()
     }
 
     pub fn is_match(&self, reg: &Regex) -> bool {
@@ -72,4 +107,43 @@ impl<'a> BinaryObject {
     pub fn data(&'a self) -> &[u8] {
         &self.data
     }
+    /// The typed model of an ELF file, `None` for other binaries
+    pub fn native_library(&self) -> Option<NativeLibrary> {
+        NativeLibrary::parse(&self.data)
+    }
+    /// The Dart AOT snapshot of a Flutter app (`libapp.so`)
+    pub fn dart_snapshot(&self) -> Option<DartSnapshot> {
+        DartSnapshot::parse(&self.data)
+    }
+    /// The bytecode of a React Native bundle compiled with Hermes
+    pub fn hermes_bytecode(&self) -> Option<HermesBytecode> {
+        HermesBytecode::parse(&self.data).ok()
+    }
+    /// The `global-metadata.dat` of a Unity game built with IL2CPP
+    pub fn il2cpp_metadata(&self) -> Option<Il2CppMetadata> {
+        Il2CppMetadata::parse(&self.data).ok()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn derived_data_is_built_once() {
+        let object = BinaryObject::new(vec![1, 2, 3]);
+        let first = object.derived(|| Some(object.data().len())).unwrap();
+        let second = object.derived::<usize>(|| panic!("built twice")).unwrap();
+        assert!(Arc::ptr_eq(&first, &second));
+        let clone = object.clone();
+        assert!(Arc::ptr_eq(&first, &clone.derived(|| None).unwrap()));
+        assert!(object.derived(|| Some("other type")).is_none());
+    }
+
+    #[test]
+    fn failed_derivations_are_not_retried() {
+        let object = BinaryObject::new(vec![]);
+        assert!(object.derived::<usize>(|| None).is_none());
+        assert!(object.derived(|| Some(1usize)).is_none());
+    }
 }
@@ -0,0 +1,884 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Dart AOT snapshots, which Flutter compiles the app code into (`libapp.so`). The ELF file
+//! exports the VM and the isolate snapshot, each split into a data part (the serialized heap) and
+//! the compiled code.
+//!
+//! Only the snapshot header has a stable layout. The serialized objects are grouped in clusters
+//! by their class id, and both the class ids and the encoding of the clusters change with every
+//! Dart version. Strings are recovered without walking the clusters: they are stored as a length
+//! (in the variable length encoding of the Dart VM) directly followed by the characters, and
+//! consecutive strings of one cluster validate each other. On targets without compressed
+//! pointers the strings are part of the read only data image instead, where they are laid out
+//! as heap objects.
+
+use std::{
+    collections::HashSet,
+    convert::{TryFrom, TryInto},
+};
+
+use goblin::elf::{program_header::PT_LOAD, Elf};
+
+use super::CpuArch;
+
+/// `0xdcdcf5f5` in little endian
+const SNAPSHOT_MAGIC: [u8; 4] = [0xf5, 0xf5, 0xdc, 0xdc];
+/// Magic, length and kind
+const HEADER_SIZE: usize = 20;
+/// The md5 hash of the VM sources relevant for the snapshot format
+const VERSION_HASH_LENGTH: usize = 32;
+/// A run of fewer strings is not considered to be a string cluster
+const MIN_STRINGS_IN_CLUSTER: usize = 4;
+const MAX_STRING_LENGTH: u64 = 1 << 20;
+
+const VM_DATA_SYMBOL: &str = "_kDartVmSnapshotData";
+const ISOLATE_DATA_SYMBOL: &str = "_kDartIsolateSnapshotData";
+const ISOLATE_INSTRUCTIONS_SYMBOL: &str = "_kDartIsolateSnapshotInstructions";
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DartSnapshotKind {
+    Full,
+    FullCore,
+    FullJit,
+    FullAot,
+    Unknown(i64),
+}
+
+impl DartSnapshotKind {
+    /// The numbering of current Dart versions
+    fn from_value(value: i64) -> Self {
+        match value {
+            0 => DartSnapshotKind::Full,
+            1 => DartSnapshotKind::FullCore,
+            2 => DartSnapshotKind::FullJit,
+            3 => DartSnapshotKind::FullAot,
+            _ => DartSnapshotKind::Unknown(value),
+        }
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DartSnapshotHeader {
+    /// Virtual address of the snapshot
+    pub address: u64,
+    /// Size including the header
+    pub length: u64,
+    pub kind: DartSnapshotKind,
+    /// The VM only loads snapshots with the hash it was built with, the same hash is contained in
+    /// the matching `libflutter.so`
+    pub version_hash: String,
+    /// Flags the snapshot was compiled with, e.g. `product`, `arm64`, `compressed-pointers`
+    pub features: Vec<String>,
+    /// Number of objects the snapshot refers to, but which are already part of the VM
+    pub base_objects: Option<u64>,
+    /// Number of serialized objects
+    pub objects: Option<u64>,
+    pub clusters: Option<u64>,
+}
+
+impl DartSnapshotHeader {
+    pub fn has_feature(&self, feature: &str) -> bool {
+        self.features.iter().any(|f| f == feature)
+    }
+
+    pub fn is_product(&self) -> bool {
+        self.has_feature("product")
+    }
+
+    pub fn compressed_pointers(&self) -> bool {
+        self.has_feature("compressed-pointers")
+    }
+
+    /// `None` for Dart 3, which dropped the flag as all code is null safe
+    pub fn null_safety(&self) -> Option<bool> {
+        if self.has_feature("null-safety") {
+            Some(true)
+        } else if self.has_feature("no-null-safety") {
+            Some(false)
+        } else {
+            None
+        }
+    }
+
+    /// The target architecture, newer versions append the ABI (`arm64-sysv`)
+    pub fn arch(&self) -> Option<CpuArch> {
+        self.features
+            .iter()
+            .find_map(|feature| match feature.split('-').next()? {
+                "arm" => Some(CpuArch::ArmV7),
+                "arm64" => Some(CpuArch::Arm64),
+                "ia32" => Some(CpuArch::X86),
+                "x64" => Some(CpuArch::X86_64),
+                _ => None,
+            })
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DartString {
+    /// Virtual address of the serialized string (the length in front of the characters)
+    pub address: u64,
+    pub content: String,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum DartNameKind {
+    /// A library uri (`package:app/main.dart`)
+    Library,
+    Class,
+    /// Functions including getters (`get:name`), setters (`set:name`) and constructors
+    /// (`Class.name`)
+    Function,
+}
+
+/// A name derived from the strings of the snapshot. Without obfuscation the names of libraries,
+/// classes and functions are kept for stack traces and `runtimeType`.
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DartName {
+    pub kind: DartNameKind,
+    /// The name without the library key of private names
+    pub name: String,
+    /// Private names are mangled with a key of their library (`_State@123`)
+    pub library_key: Option<String>,
+    /// The class of constructors
+    pub class_name: Option<String>,
+    /// Functions and classes are recognized by their syntax (getter, setter, constructor or
+    /// private name) and not only by the case of the name
+    pub marked: bool,
+    /// Address of the string the name was derived from
+    pub address: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DartSnapshot {
+    pub vm_data: Option<DartSnapshotHeader>,
+    pub isolate_data: DartSnapshotHeader,
+    /// Address and size of the compiled code of the isolate
+    pub isolate_instructions: Option<(u64, u64)>,
+    /// Strings of the isolate snapshot, sorted by their address
+    pub strings: Vec<DartString>,
+}
+
+impl DartSnapshot {
+    /// Parse the snapshots of an ELF file. Returns `None` if the file contains no Dart snapshot.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let elf = Elf::parse(data).ok()?;
+        Self::from_elf(&elf, data)
+    }
+
+    pub fn from_elf(elf: &Elf, data: &[u8]) -> Option<Self> {
+        let symbol = |name: &str| {
+            elf.dynsyms
+                .iter()
+                .find(|sym| elf.dynstrtab.get_at(sym.st_name) == Some(name))
+                .or_else(|| {
+                    elf.syms
+                        .iter()
+                        .find(|sym| elf.strtab.get_at(sym.st_name) == Some(name))
+                })
+                .map(|sym| (sym.st_value, sym.st_size))
+        };
+        let (vm_data, isolate_data) =
+            if let Some((isolate_address, isolate_size)) = symbol(ISOLATE_DATA_SYMBOL) {
+                (
+                    symbol(VM_DATA_SYMBOL).and_then(|(address, size)| {
+                        snapshot_data(elf, data, address, size)
+                            .and_then(|snapshot| parse_header(snapshot, address))
+                    }),
+                    snapshot_data(elf, data, isolate_address, isolate_size)
+                        .map(|snapshot| (isolate_address, snapshot))?,
+                )
+            } else {
+                // the snapshots are written in this order, the VM snapshot is missing in
+                // snapshots of a single isolate
+                let mut snapshots = find_snapshots(elf, data);
+                let isolate = snapshots.pop()?;
+                (
+                    snapshots
+                        .pop()
+                        .and_then(|(address, snapshot)| parse_header(snapshot, address)),
+                    isolate,
+                )
+            };
+        let (isolate_address, isolate_data) = isolate_data;
+        let (header, serialized) = parse_header_with_offset(isolate_data, isolate_address)?;
+
+        let mut strings = cluster_strings(
+            &isolate_data[serialized..],
+            isolate_address.wrapping_add(serialized as u64),
+        );
+        if !header.compressed_pointers() {
+            let word_size = if header.arch().map(|arch| arch.is_64()).unwrap_or(elf.is_64) {
+                8
+            } else {
+                4
+            };
+            let known: HashSet<u64> = strings.iter().map(|string| string.address).collect();
+            strings.extend(
+                image_strings(isolate_data, isolate_address, word_size)
+                    .into_iter()
+                    .filter(|string| !known.contains(&string.address)),
+            );
+            strings.sort_by_key(|string| string.address);
+        }
+
+        Some(DartSnapshot {
+            vm_data,
+            isolate_data: header,
+            isolate_instructions: symbol(ISOLATE_INSTRUCTIONS_SYMBOL).or_else(|| {
+                elf.section_headers
+                    .iter()
+                    .find(|header| elf.shdr_strtab.get_at(header.sh_name) == Some(".text"))
+                    .map(|header| (header.sh_addr, header.sh_size))
+            }),
+            strings,
+        })
+    }
+
+    /// Library, class and function names among the strings, each name is reported once
+    pub fn names(&self) -> Vec<DartName> {
+        let mut seen = HashSet::new();
+        let mut names = vec![];
+        for string in &self.strings {
+            for name in classify_name(&string.content, string.address) {
+                if seen.insert((name.kind, name.name.clone(), name.class_name.clone())) {
+                    names.push(name);
+                }
+            }
+        }
+        names
+    }
+
+    /// The uris of all libraries, e.g. `package:http/http.dart`
+    pub fn libraries(&self) -> Vec<String> {
+        self.names()
+            .into_iter()
+            .filter(|name| name.kind == DartNameKind::Library)
+            .map(|name| name.name)
+            .collect()
+    }
+}
+
+/// The bytes of a snapshot at a virtual address, symbols without a size extend to the end of the
+/// segment
+fn snapshot_data<'a>(elf: &Elf, data: &'a [u8], address: u64, size: u64) -> Option<&'a [u8]> {
+    let segment = elf.program_headers.iter().find(|header| {
+        header.p_type == PT_LOAD
+            && address >= header.p_vaddr
+            && address - header.p_vaddr < header.p_filesz
+    })?;
+    let offset =
+        usize::try_from((address - segment.p_vaddr).checked_add(segment.p_offset)?).ok()?;
+    let end = if size > 0 {
+        offset.saturating_add(usize::try_from(size).unwrap_or(usize::MAX))
+    } else {
+        usize::try_from(segment.p_offset.saturating_add(segment.p_filesz)).unwrap_or(usize::MAX)
+    };
+    let snapshot = data.get(offset..end.min(data.len()))?;
+    if snapshot.starts_with(&SNAPSHOT_MAGIC) {
+        Some(snapshot)
+    } else {
+        None
+    }
+}
+
+/// Snapshots found by their magic, for files without the snapshot symbols
+fn find_snapshots<'a>(elf: &Elf, data: &'a [u8]) -> Vec<(u64, &'a [u8])> {
+    let mut snapshots = vec![];
+    for segment in elf
+        .program_headers
+        .iter()
+        .filter(|header| header.p_type == PT_LOAD)
+    {
+        let start = usize::try_from(segment.p_offset).unwrap_or(usize::MAX);
+        let end = usize::try_from(segment.p_offset.saturating_add(segment.p_filesz))
+            .unwrap_or(usize::MAX)
+            .min(data.len());
+        let Some(content) = data.get(start..end) else {
+            continue;
+        };
+        // snapshots are aligned to at least a word
+        for offset in (0..content.len().saturating_sub(HEADER_SIZE)).step_by(4) {
+            if !content[offset..].starts_with(&SNAPSHOT_MAGIC) {
+                continue;
+            }
+            let address = segment.p_vaddr.wrapping_add(offset as u64);
+            if parse_header(&content[offset..], address).is_some() {
+                snapshots.push((address, &content[offset..]));
+            }
+        }
+    }
+    snapshots
+}
+
+fn parse_header(snapshot: &[u8], address: u64) -> Option<DartSnapshotHeader> {
+    parse_header_with_offset(snapshot, address).map(|(header, _)| header)
+}
+
+/// The header and the offset of the serialized objects
+fn parse_header_with_offset(snapshot: &[u8], address: u64) -> Option<(DartSnapshotHeader, usize)> {
+    if !snapshot.starts_with(&SNAPSHOT_MAGIC) {
+        return None;
+    }
+    // the length does not include the magic
+    let length = i64::from_le_bytes(snapshot.get(4..12)?.try_into().ok()?);
+    let kind = i64::from_le_bytes(snapshot.get(12..20)?.try_into().ok()?);
+    let version_hash = snapshot.get(HEADER_SIZE..HEADER_SIZE + VERSION_HASH_LENGTH)?;
+    if length < 0 || !version_hash.iter().all(|b| b.is_ascii_hexdigit()) {
+        return None;
+    }
+    let features_start = HEADER_SIZE + VERSION_HASH_LENGTH;
+    let features_length = snapshot
+        .get(features_start..)?
+        .iter()
+        .position(|b| *b == 0)?;
+    let features =
+        String::from_utf8_lossy(&snapshot[features_start..features_start + features_length])
+            .split_whitespace()
+            .map(|feature| feature.to_string())
+            .collect();
+
+    let serialized = features_start + features_length + 1;
+    let mut position = serialized;
+    let mut counts = [None; 3];
+    for count in counts.iter_mut() {
+        let Some((value, size)) = read_unsigned(snapshot, position) else {
+            break;
+        };
+        *count = Some(value);
+        position += size;
+    }
+    Some((
+        DartSnapshotHeader {
+            address,
+            length: length as u64 + SNAPSHOT_MAGIC.len() as u64,
+            kind: DartSnapshotKind::from_value(kind),
+            version_hash: String::from_utf8_lossy(version_hash).to_string(),
+            features,
+            base_objects: counts[0],
+            objects: counts[1],
+            clusters: counts[2],
+        },
+        serialized,
+    ))
+}
+
+/// Unsigned values are stored in 7 bit groups, least significant first. The last group has the
+/// highest bit set.
+fn read_unsigned(data: &[u8], position: usize) -> Option<(u64, usize)> {
+    let mut value = 0u64;
+    for (index, byte) in data.get(position..)?.iter().take(10).enumerate() {
+        let shift = 7 * index as u32;
+        if *byte > 0x7f {
+            return Some((value | (u64::from(byte - 0x80) << shift), index + 1));
+        }
+        value |= u64::from(*byte) << shift;
+    }
+    None
+}
+
+#[derive(Copy, Clone, Debug, PartialEq)]
+enum LengthEncoding {
+    /// Dart 2.19 and later share one cluster for both string classes, the lowest bit of the
+    /// length marks two byte strings
+    WithCid,
+    /// Separate clusters for one byte and two byte strings
+    Plain,
+}
+
+fn is_text(c: char) -> bool {
+    !c.is_control() || matches!(c, '\t' | '\n' | '\r')
+}
+
+/// A serialized string at `position` and the position after it
+fn read_string(data: &[u8], position: usize, encoding: LengthEncoding) -> Option<(String, usize)> {
+    let (encoded, size) = read_unsigned(data, position)?;
+    let (length, two_byte) = match encoding {
+        LengthEncoding::WithCid => (encoded >> 1, encoded & 1 == 1),
+        LengthEncoding::Plain => (encoded, false),
+    };
+    if length > MAX_STRING_LENGTH {
+        return None;
+    }
+    let start = position + size;
+    let end = start + if two_byte { 2 * length } else { length } as usize;
+    let characters = data.get(start..end)?;
+    decode_string(characters, two_byte).map(|content| (content, end))
+}
+
+/// One byte strings are Latin-1, two byte strings UTF-16
+fn decode_string(characters: &[u8], two_byte: bool) -> Option<String> {
+    let content: String = if two_byte {
+        char::decode_utf16(
+            characters
+                .chunks_exact(2)
+                .map(|unit| u16::from_le_bytes([unit[0], unit[1]])),
+        )
+        .collect::<Result<_, _>>()
+        .ok()?
+    } else {
+        characters.iter().map(|b| char::from(*b)).collect()
+    };
+    if content.chars().all(is_text) {
+        Some(content)
+    } else {
+        None
+    }
+}
+
+/// Strings of the string clusters, with the encoding yielding more strings
+fn cluster_strings(data: &[u8], address: u64) -> Vec<DartString> {
+    let with_cid = cluster_strings_with_encoding(data, address, LengthEncoding::WithCid);
+    let plain = cluster_strings_with_encoding(data, address, LengthEncoding::Plain);
+    if plain.len() > with_cid.len() {
+        plain
+    } else {
+        with_cid
+    }
+}
+
+fn cluster_strings_with_encoding(
+    data: &[u8],
+    address: u64,
+    encoding: LengthEncoding,
+) -> Vec<DartString> {
+    let mut strings = vec![];
+    let mut position = 0;
+    while position < data.len() {
+        let mut run = vec![];
+        let mut next = position;
+        while let Some((content, end)) = read_string(data, next, encoding) {
+            if run.is_empty() && content.is_empty() {
+                break;
+            }
+            if !content.is_empty() {
+                run.push(DartString {
+                    address: address.wrapping_add(next as u64),
+                    content,
+                });
+            }
+            next = end;
+        }
+        if run.len() >= MIN_STRINGS_IN_CLUSTER {
+            strings.extend(run);
+            position = next;
+        } else {
+            position += 1;
+        }
+    }
+    strings
+}
+
+/// Strings laid out as heap objects: the header word, the length as Smi (tagged with a zero
+/// bit), on 32 bit targets the hash, and the characters padded with zeros to the object
+/// alignment of two words
+fn image_strings(data: &[u8], address: u64, word_size: usize) -> Vec<DartString> {
+    let alignment = 2 * word_size as u64;
+    let word = |offset: usize| -> Option<u64> {
+        let bytes = data.get(offset..offset + word_size)?;
+        Some(if word_size == 8 {
+            u64::from_le_bytes(bytes.try_into().ok()?)
+        } else {
+            u64::from(u32::from_le_bytes(bytes.try_into().ok()?))
+        })
+    };
+    // (offset of the length, offset of the characters)
+    let layouts: &[(usize, usize)] = if word_size == 8 {
+        &[(8, 16)]
+    } else {
+        &[(4, 12), (8, 12)]
+    };
+    let mut strings = vec![];
+    let first = ((alignment - address % alignment) % alignment) as usize;
+    let mut offset = first;
+    while offset + 2 * word_size < data.len() {
+        let mut size = alignment as usize;
+        for (length_offset, characters_offset) in layouts {
+            let Some(smi) = word(offset + length_offset) else {
+                continue;
+            };
+            let length = smi >> 1;
+            if smi & 1 != 0 || !(2..=MAX_STRING_LENGTH).contains(&length) {
+                continue;
+            }
+            let found = [false, true].iter().find_map(|two_byte| {
+                let start = offset + characters_offset;
+                let end = start + if *two_byte { 2 * length } else { length } as usize;
+                let padded = end.div_ceil(alignment as usize) * alignment as usize;
+                let padding = data.get(end..padded)?;
+                if padding.iter().any(|b| *b != 0) {
+                    return None;
+                }
+                let content = decode_string(data.get(start..end)?, *two_byte)?;
+                Some((content, padded - offset))
+            });
+            if let Some((content, object_size)) = found {
+                strings.push(DartString {
+                    address: address.wrapping_add(offset as u64),
+                    content,
+                });
+                size = object_size;
+                break;
+            }
+        }
+        offset += size;
+    }
+    strings
+}
+
+fn split_library_key(name: &str) -> (&str, Option<String>) {
+    match name.rsplit_once('@') {
+        Some((name, key)) if !key.is_empty() && key.chars().all(|c| c.is_ascii_digit()) => {
+            (name, Some(key.to_string()))
+        }
+        _ => (name, None),
+    }
+}
+
+fn is_identifier(name: &str) -> bool {
+    let mut chars = name.chars();
+    matches!(chars.next(), Some(c) if c.is_ascii_alphabetic() || c == '_' || c == '$')
+        && chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '$')
+}
+
+fn is_class_name(name: &str) -> bool {
+    name.trim_start_matches('_')
+        .chars()
+        .next()
+        .map(|c| c.is_ascii_uppercase())
+        .unwrap_or(false)
+}
+
+fn classify_name(content: &str, address: u64) -> Vec<DartName> {
+    let name = |kind, name: &str, library_key, class_name, marked| DartName {
+        kind,
+        name: name.to_string(),
+        library_key,
+        class_name,
+        marked,
+        address,
+    };
+    if ["package:", "dart:", "file://"]
+        .iter()
+        .any(|scheme| content.starts_with(scheme))
+    {
+        if content.contains(char::is_whitespace) {
+            return vec![];
+        }
+        return vec![name(DartNameKind::Library, content, None, None, true)];
+    }
+    if let Some(accessor) = content
+        .strip_prefix("get:")
+        .or_else(|| content.strip_prefix("set:"))
+    {
+        let (accessor, key) = split_library_key(accessor);
+        if !is_identifier(accessor) {
+            return vec![];
+        }
+        return vec![name(
+            DartNameKind::Function,
+            &format!("{}{}", &content[..4], accessor),
+            key,
+            None,
+            true,
+        )];
+    }
+    if let Some((class, constructor)) = content.split_once('.') {
+        let (class, class_key) = split_library_key(class);
+        let (constructor, constructor_key) = split_library_key(constructor);
+        if !is_identifier(class)
+            || !is_class_name(class)
+            || !(constructor.is_empty() || is_identifier(constructor))
+        {
+            return vec![];
+        }
+        return vec![
+            name(DartNameKind::Class, class, class_key.clone(), None, true),
+            name(
+                DartNameKind::Function,
+                &format!("{}.{}", class, constructor),
+                constructor_key.or(class_key),
+                Some(class.to_string()),
+                true,
+            ),
+        ];
+    }
+    let (identifier, key) = split_library_key(content);
+    if !is_identifier(identifier) {
+        return vec![];
+    }
+    let marked = key.is_some();
+    let kind = if is_class_name(identifier) {
+        DartNameKind::Class
+    } else {
+        DartNameKind::Function
+    };
+    vec![name(kind, identifier, key, None, marked)]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const HASH: &[u8; 32] = b"0123456789abcdef0123456789abcdef";
+    const STRINGS: [&str; 5] = [
+        "package:app/main.dart",
+        "_MyState@123",
+        "get:title",
+        "MyApp.",
+        "build",
+    ];
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    fn unsigned(mut value: u64) -> Vec<u8> {
+        let mut bytes = vec![];
+        while value > 0x7f {
+            bytes.push((value & 0x7f) as u8);
+            value >>= 7;
+        }
+        bytes.push(value as u8 | 0x80);
+        bytes
+    }
+
+    /// A snapshot header followed by the object counts and a cluster of one byte strings
+    fn snapshot(kind: i64, features: &str) -> Vec<u8> {
+        let mut snapshot = SNAPSHOT_MAGIC.to_vec();
+        snapshot.extend_from_slice(&0xf0i64.to_le_bytes());
+        snapshot.extend_from_slice(&kind.to_le_bytes());
+        snapshot.extend_from_slice(HASH);
+        snapshot.extend_from_slice(features.as_bytes());
+        snapshot.push(0);
+        for count in [1, 2, 3] {
+            snapshot.extend(unsigned(count));
+        }
+        for string in STRINGS.iter() {
+            snapshot.extend(unsigned(string.len() as u64));
+            snapshot.extend_from_slice(string.as_bytes());
+        }
+        snapshot
+    }
+
+    /// A 64 bit AArch64 shared object without symbols, its only segment maps the whole file to
+    /// 0x10000 and contains a VM snapshot at 0x100 and an isolate snapshot at 0x200
+    fn elf_with_snapshots(segment_offset: u64, segment_size: u64) -> Vec<u8> {
+        let mut data = vec![0u8; 0x400];
+        put(&mut data, 0, b"\x7fELF\x02\x01\x01");
+        put(&mut data, 16, &[3, 0, 0xb7, 0, 1, 0, 0, 0]);
+        put(&mut data, 32, &64u64.to_le_bytes());
+        put(&mut data, 52, &[64, 0, 56, 0, 1, 0, 64, 0]);
+        put(&mut data, 64, &PT_LOAD.to_le_bytes());
+        put(&mut data, 68, &4u32.to_le_bytes());
+        put(&mut data, 72, &segment_offset.to_le_bytes());
+        put(&mut data, 80, &0x10000u64.to_le_bytes());
+        put(&mut data, 96, &segment_size.to_le_bytes());
+        put(&mut data, 104, &segment_size.to_le_bytes());
+        put(&mut data, 112, &0x1000u64.to_le_bytes());
+        put(&mut data, 0x100, &snapshot(3, "product arm64"));
+        put(
+            &mut data,
+            0x200,
+            &snapshot(3, "product compressed-pointers null-safety arm64-sysv"),
+        );
+        data
+    }
+
+    #[test]
+    fn parses_snapshots_found_by_their_magic() {
+        let snapshot = DartSnapshot::parse(&elf_with_snapshots(0, 0x400)).unwrap();
+        let vm = snapshot.vm_data.as_ref().unwrap();
+        assert_eq!(vm.address, 0x10100);
+        assert_eq!(vm.null_safety(), None);
+
+        let isolate = &snapshot.isolate_data;
+        assert_eq!(isolate.address, 0x10200);
+        assert_eq!(isolate.length, 0xf4);
+        assert_eq!(isolate.kind, DartSnapshotKind::FullAot);
+        assert_eq!(isolate.version_hash.as_bytes(), HASH);
+        assert!(isolate.is_product());
+        assert!(isolate.compressed_pointers());
+        assert_eq!(isolate.null_safety(), Some(true));
+        assert_eq!(isolate.arch(), Some(CpuArch::Arm64));
+        assert_eq!(
+            (isolate.base_objects, isolate.objects, isolate.clusters),
+            (Some(1), Some(2), Some(3))
+        );
+
+        let strings: Vec<&str> = snapshot
+            .strings
+            .iter()
+            .map(|string| string.content.as_str())
+            .collect();
+        assert_eq!(strings, STRINGS);
+        // the header, the hash, the features with their terminator and the three counts
+        let first = 0x10200 + 52 + 51 + 3;
+        assert_eq!(snapshot.strings[0].address, first);
+        assert_eq!(snapshot.strings[1].address, first + 22);
+        assert_eq!(snapshot.libraries(), vec!["package:app/main.dart"]);
+    }
+
+    #[test]
+    fn derives_names_from_strings() {
+        let snapshot = DartSnapshot::parse(&elf_with_snapshots(0, 0x400)).unwrap();
+        let names = snapshot.names();
+        let names: Vec<(DartNameKind, &str, Option<&str>, bool)> = names
+            .iter()
+            .map(|name| {
+                (
+                    name.kind,
+                    name.name.as_str(),
+                    name.library_key.as_deref(),
+                    name.marked,
+                )
+            })
+            .collect();
+        assert_eq!(names.len(), 6);
+        assert!(names.contains(&(DartNameKind::Class, "_MyState", Some("123"), true)));
+        assert!(names.contains(&(DartNameKind::Function, "get:title", None, true)));
+        assert!(names.contains(&(DartNameKind::Class, "MyApp", None, true)));
+        assert!(names.contains(&(DartNameKind::Function, "MyApp.", None, true)));
+        assert!(names.contains(&(DartNameKind::Function, "build", None, false)));
+
+        assert!(classify_name("two words", 0).is_empty());
+        assert!(classify_name("package:a b", 0).is_empty());
+        assert!(classify_name("get:1x", 0).is_empty());
+        assert!(classify_name("lower.case", 0).is_empty());
+    }
+
+    #[test]
+    fn rejects_files_without_snapshots() {
+        assert!(DartSnapshot::parse(b"").is_none());
+        assert!(DartSnapshot::parse(&[0xff; 64]).is_none());
+        let mut data = elf_with_snapshots(0, 0x400);
+        data[0x100..0x300].iter_mut().for_each(|b| *b = 0);
+        assert!(DartSnapshot::parse(&data).is_none());
+    }
+
+    #[test]
+    fn segments_beyond_the_file_are_ignored() {
+        assert!(DartSnapshot::parse(&elf_with_snapshots(u64::MAX, 0x400)).is_none());
+        assert!(DartSnapshot::parse(&elf_with_snapshots(0x300, u64::MAX)).is_none());
+        assert!(DartSnapshot::parse(&elf_with_snapshots(0, u64::MAX)).is_some());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let valid = snapshot(1, "x64");
+        let (header, serialized) = parse_header_with_offset(&valid, 0).unwrap();
+        assert_eq!(header.kind, DartSnapshotKind::FullCore);
+        assert_eq!(header.arch(), Some(CpuArch::X86_64));
+        assert_eq!(serialized, 56);
+        // truncated before the end of the features
+        for length in 0..serialized {
+            assert!(parse_header_with_offset(&valid[..length], 0).is_none());
+        }
+        // truncated counts are missing
+        let header = parse_header(&valid[..serialized + 1], 0).unwrap();
+        assert_eq!((header.base_objects, header.objects), (Some(1), None));
+
+        let mut wrong_magic = valid.clone();
+        wrong_magic[0] = 0;
+        assert!(parse_header(&wrong_magic, 0).is_none());
+        let mut negative_length = valid.clone();
+        put(&mut negative_length, 4, &(-1i64).to_le_bytes());
+        assert!(parse_header(&negative_length, 0).is_none());
+        let mut invalid_hash = valid.clone();
+        invalid_hash[HEADER_SIZE] = b'g';
+        assert!(parse_header(&invalid_hash, 0).is_none());
+        let mut unknown_kind = valid;
+        put(&mut unknown_kind, 12, &42i64.to_le_bytes());
+        assert_eq!(
+            parse_header(&unknown_kind, 0).unwrap().kind,
+            DartSnapshotKind::Unknown(42)
+        );
+    }
+
+    #[test]
+    fn reads_unsigned_values() {
+        for value in [0, 1, 0x7f, 0x80, 300, 1 << 40, u64::MAX >> 1] {
+            let bytes = unsigned(value);
+            assert_eq!(read_unsigned(&bytes, 0), Some((value, bytes.len())));
+        }
+        assert_eq!(read_unsigned(&[0x01, 0x02], 0), None);
+        assert_eq!(read_unsigned(&[0; 16], 0), None);
+        assert_eq!(read_unsigned(&[0x81], 1), None);
+        assert_eq!(read_unsigned(&[0x81], 2), None);
+    }
+
+    #[test]
+    fn reads_strings() {
+        let mut data = unsigned(3 << 1);
+        data.extend_from_slice(b"abc");
+        assert_eq!(
+            read_string(&data, 0, LengthEncoding::WithCid),
+            Some(("abc".to_string(), 4))
+        );
+        let mut data = unsigned(2 << 1 | 1);
+        data.extend_from_slice(&[0xfc, 0, 0xac, 0x20]);
+        assert_eq!(
+            read_string(&data, 0, LengthEncoding::WithCid),
+            Some(("ü€".to_string(), 5))
+        );
+        // truncated, too long, unpaired surrogate and control characters
+        assert_eq!(read_string(&unsigned(4), 0, LengthEncoding::Plain), None);
+        assert_eq!(
+            read_string(&unsigned(u64::MAX >> 1), 0, LengthEncoding::Plain),
+            None
+        );
+        let mut data = unsigned(1 << 1 | 1);
+        data.extend_from_slice(&[0x00, 0xd8]);
+        assert_eq!(read_string(&data, 0, LengthEncoding::WithCid), None);
+        let mut data = unsigned(2);
+        data.extend_from_slice(&[b'a', 0x07]);
+        assert_eq!(read_string(&data, 0, LengthEncoding::Plain), None);
+    }
+
+    #[test]
+    fn short_runs_are_not_clusters() {
+        let mut data = vec![];
+        for string in &STRINGS[..MIN_STRINGS_IN_CLUSTER - 1] {
+            data.extend(unsigned(string.len() as u64));
+            data.extend_from_slice(string.as_bytes());
+        }
+        assert!(cluster_strings(&data, 0).is_empty());
+        assert!(cluster_strings(&[], 0).is_empty());
+        assert!(cluster_strings(&[0xff; 64], 0).is_empty());
+    }
+
+    #[test]
+    fn finds_strings_laid_out_as_objects() {
+        let mut data = vec![0u8; 0x60];
+        put(&mut data, 0x10, &1u64.to_le_bytes());
+        put(&mut data, 0x18, &(11u64 << 1).to_le_bytes());
+        put(&mut data, 0x20, b"hello world");
+        // a two byte string
+        put(&mut data, 0x30, &1u64.to_le_bytes());
+        put(&mut data, 0x38, &(2u64 << 1).to_le_bytes());
+        put(&mut data, 0x40, &[0xfc, 0, 0xac, 0x20]);
+        let strings = image_strings(&data, 0x1000, 8);
+        assert_eq!(
+            strings,
+            vec![
+                DartString {
+                    address: 0x1010,
+                    content: "hello world".to_string(),
+                },
+                DartString {
+                    address: 0x1030,
+                    content: "ü€".to_string(),
+                },
+            ]
+        );
+        // a length beyond the data and non zero padding are rejected
+        put(&mut data, 0x18, &(0x1000u64 << 1).to_le_bytes());
+        put(&mut data, 0x44, &[1]);
+        assert!(image_strings(&data, 0x1000, 8).is_empty());
+        assert!(image_strings(&data, u64::MAX, 4).is_empty());
+    }
+}
@@ -4,35 +4,55 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use coeus_macros::iterator;
 use std::path::Path;
 use std::{collections::HashMap, sync::Arc};
 
-#[cfg(not(target_arch = "wasm32"))]
-use rayon::prelude::*;
-
-use super::{Class, DexHeader, Field, Method, MethodData, Proto, StringEntry};
+use super::{
+    index::method_signature, is_complete_serialization, CallSite, Class, DexHeader, DexIndex,
+    Field, Method, MethodData, Pool, Proto, StringEntry, StringQuery,
+};
 
 #[derive(Debug, Clone, ::serde::Serialize, ::serde::Deserialize)]
 pub struct DexFile {
-    #[serde(skip_serializing)]
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
     pub identifier: String,
     pub file_name: String,
     pub header: DexHeader,
-    #[serde(skip_serializing)]
-    pub strings: Vec<StringEntry>,
-    #[serde(skip_serializing)]
-    pub types: Vec<u32>,
-    #[serde(skip_serializing)]
-    pub methods: Vec<Arc<Method>>,
-    #[serde(skip_serializing)]
-    pub protos: Vec<Arc<Proto>>,
-    #[serde(skip_serializing)]
-    pub fields: Vec<Arc<Field>>,
-    #[serde(skip_serializing)]
-    pub classes: Vec<Arc<Class>>,
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub strings: Pool<StringEntry>,
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub types: Pool<u32>,
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub methods: Pool<Arc<Method>>,
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub protos: Pool<Arc<Proto>>,
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub fields: Pool<Arc<Field>>,
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub classes: Pool<Arc<Class>>,
+    /// The call sites of `invoke-custom` instructions
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub call_sites: Vec<CallSite>,
+    /// The class tables only hold links into `classes`, which are restored after loading the
+    /// complete format
+    #[serde(serialize_with = "serialize_class_table")]
     pub interface_table: HashMap<String, Vec<Arc<Class>>>,
+    #[serde(serialize_with = "serialize_class_table")]
     pub superclass_table: HashMap<String, Vec<Arc<Class>>>,
+    /// Lookup tables for the pools above, built on the first lookup
+    #[serde(skip)]
+    pub index: DexIndex,
+}
+
+fn serialize_class_table<S: serde::Serializer>(
+    table: &HashMap<String, Vec<Arc<Class>>>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    if is_complete_serialization() {
+        serde::Serialize::serialize(&HashMap::<String, Vec<Arc<Class>>>::new(), serializer)
+    } else {
+        serde::Serialize::serialize(table, serializer)
+    }
 }
 
 impl PartialEq for DexFile {
@@ -94,16 +114,34 @@ impl DexFile {
         }
     }
 
+    /// Get the index of the string with exactly this content
+    pub fn get_string_idx(&self, content: &str) -> Option<u32> {
+        self.index.get(self).string_idx(self, content)
+    }
+
+    /// Get the type idx for a type name (e.g. `Ljava/lang/String;`)
+    pub fn get_type_idx(&self, type_name: &str) -> Option<u16> {
+        let name_idx = self.get_string_idx(type_name)?;
+        self.index.get(self).types_by_name.get(&name_idx).copied()
+    }
+
     pub fn get_class_name<T>(&self, class_idx: T) -> Option<&str>
     where
         T: Into<u32> + Copy,
     {
-        self.classes
-            .iter()
-            .find(|c| c.class_idx == class_idx.into())
+        self.get_class_at(self.index.get(self).classes_by_type.get(&class_idx.into()))
             .map(|c| c.class_name.as_str())
     }
 
+    fn get_class_at(&self, position: Option<&usize>) -> Option<&Arc<Class>> {
+        position.and_then(|&pos| self.classes.get(pos))
+    }
+
+    fn get_method_data_at(&self, position: Option<&(usize, usize)>) -> Option<Arc<MethodData>> {
+        let &(class_pos, code_pos) = position?;
+        self.classes.get(class_pos)?.codes.get(code_pos).cloned()
+    }
+
     pub fn get_field_name<T>(&self, field_idx: T) -> Option<&str>
     where
         T: Into<u32> + Copy,
@@ -145,45 +183,24 @@ impl DexFile {
     where
         T: Into<u32> + Copy + Sync,
     {
-        iterator!(self.methods)
-            .filter(|m| self.types[m.class_idx as usize] == type_idx.into())
-            .map(|m| m.clone())
-            .collect()
+        self.index
+            .get(self)
+            .methods_by_type_name
+            .get(&type_idx.into())
+            .map(|positions| {
+                positions
+                    .iter()
+                    .map(|&pos| self.methods[pos].clone())
+                    .collect()
+            })
+            .unwrap_or_default()
     }
 
     pub fn get_method_by_idx<T>(&self, method_idx: T) -> Option<Arc<MethodData>>
     where
         T: Into<u32> + Copy + Sync,
     {
-        #[cfg(not(target_arch = "wasm32"))]
-        if let Some(class) = self
-            .classes
-            .par_iter()
-            .find_first(|c| c.codes.iter().any(|c| c.method_idx == method_idx.into()))
-        {
-            class
-                .codes
-                .par_iter()
-                .find_first(|c| c.method_idx == method_idx.into())
-                .cloned()
-        } else {
-            None
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        if let Some(class) = self.classes.iter().find(|c| {
-            c.codes
-                .iter()
-                .find(|c| c.method_idx == method_idx.into())
-                .is_some()
-        }) {
-            class
-                .codes
-                .iter()
-                .find(|c| c.method_idx == method_idx.into())
-        } else {
-            None
-        }
+        self.get_method_data_at(self.index.get(self).method_data.get(&method_idx.into()))
     }
 
     pub fn get_method_by_name_and_prototype(
@@ -192,106 +209,54 @@ impl DexFile {
         method_name: &str,
         proto_type: &str,
     ) -> Option<Arc<MethodData>> {
-        #[cfg(not(target_arch = "wasm32"))]
-        if let Some(class) = self
-            .classes
-            .par_iter()
-            .find_first(|c| c.class_name == class_name)
-        {
-            class
-                .codes
-                .par_iter()
-                .find_first(|code| {
-                    code.name == method_name
-                        && code.code.is_some()
-                        && proto_type == self.protos[code.method.proto_idx as usize].to_string(self)
-                })
-                .cloned()
-        } else {
-            None
-        }
-
-        #[cfg(target_arch = "wasm32")]
-        if let Some(class) = self.classes.iter().find(|c| c.class_name == class_name) {
-            class
-                .codes
-                .iter()
-                .find(|code| code.name == method_name && code.code.is_some())
-        } else {
-            None
-        }
+        let signature = method_signature(class_name, method_name, proto_type);
+        self.get_method_data_at(self.index.get(self).methods_by_signature.get(&signature))
     }
 
     pub fn get_class_by_type<T>(&self, type_idx: T) -> Option<Arc<Class>>
     where
         T: Into<u32> + Copy + Sync,
     {
-        #[cfg(not(target_arch = "wasm32"))]
-        return self
-            .classes
-            .par_iter()
-            .find_first(|c| c.class_idx == type_idx.into())
-            .cloned();
-        #[cfg(target_arch = "wasm32")]
-        return self
-            .classes
-            .iter()
-            .find(|c| c.class_idx == type_idx.into())
-            .cloned();
+        self.get_class_at(self.index.get(self).classes_by_type.get(&type_idx.into()))
+            .cloned()
     }
 
     pub fn get_class_by_type_name_idx<T>(&self, type_name_idx: T) -> Option<Arc<Class>>
     where
         T: Into<u32> + Copy + Sync,
     {
-        #[cfg(not(target_arch = "wasm32"))]
-        return self
-            .classes
-            .par_iter()
-            .find_first(|c| self.types[c.class_idx as usize] == type_name_idx.into())
-            .cloned();
-        #[cfg(target_arch = "wasm32")]
-        return self
-            .classes
-            .iter()
-            .find(|c| c.class_idx == type_name_idx.into())
-            .cloned();
+        self.get_class_at(
+            self.index
+                .get(self)
+                .classes_by_type_name
+                .get(&type_name_idx.into()),
+        )
+        .cloned()
     }
 
     pub fn get_class_by_name(&self, class_name: &str) -> Option<Arc<Class>> {
-        #[cfg(not(target_arch = "wasm32"))]
-        return self
-            .classes
-            .par_iter()
-            .find_first(|c| c.class_name == class_name)
-            .cloned();
-        #[cfg(target_arch = "wasm32")]
-        return self
-            .classes
-            .iter()
-            .find(|c| c.class_name == class_name)
-            .cloned();
+        self.get_class_at(self.index.get(self).classes_by_name.get(class_name))
+            .cloned()
     }
 
     pub fn get_class_contains_name(&self, class_name: &str) -> Option<Arc<Class>> {
-        #[cfg(not(target_arch = "wasm32"))]
-        return self
-            .classes
-            .par_iter()
-            .find_first(|c| c.class_name.contains(class_name))
-            .cloned();
-        #[cfg(target_arch = "wasm32")]
-        return self
-            .classes
-            .iter()
-            .find(|c| c.class_name.contains(class_name))
-            .cloned();
+        self.get_classes_containing_name(class_name)
+            .into_iter()
+            .next()
     }
 
+    /// Get all classes with a name containing `class_name`, in the order of `classes`
     pub fn get_classes_containing_name(&self, class_name: &str) -> Vec<Arc<Class>> {
-        iterator!(self.classes)
-            .filter(|c| c.class_name.contains(class_name))
-            .cloned()
+        let tables = self.index.get(self);
+        let mut positions: Vec<usize> = self
+            .find_strings(&StringQuery::Substring(class_name))
+            .into_iter()
+            .filter_map(|idx| tables.classes_by_type_name.get(&idx).copied())
+            .collect();
+        positions.sort_unstable();
+        positions
+            .into_iter()
+            .map(|pos| self.classes[pos].clone())
             .collect()
     }
 }
@@ -0,0 +1,956 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Metadata of .NET assemblies (ECMA-335, partition II). The CLI header of the PE image points to
+//! the metadata root, whose streams hold the tables (`#~`), the identifiers (`#Strings`) and the
+//! string literals of the code (`#US`).
+
+use std::convert::{TryFrom, TryInto};
+
+const PE_SIGNATURE: &[u8; 4] = b"PE\0\0";
+const METADATA_SIGNATURE: u32 = 0x424a_5342;
+/// Index of the CLI header in the data directories of the optional header
+const CLI_HEADER_DIRECTORY: usize = 14;
+const SECTION_HEADER_SIZE: usize = 40;
+
+const MODULE: usize = 0x00;
+const TYPE_REF: usize = 0x01;
+const TYPE_DEF: usize = 0x02;
+const FIELD: usize = 0x04;
+const METHOD_DEF: usize = 0x06;
+const PARAM: usize = 0x08;
+const INTERFACE_IMPL: usize = 0x09;
+const MEMBER_REF: usize = 0x0a;
+const DECL_SECURITY: usize = 0x0e;
+const STAND_ALONE_SIG: usize = 0x11;
+const EVENT: usize = 0x14;
+const PROPERTY: usize = 0x17;
+const MODULE_REF: usize = 0x1a;
+const TYPE_SPEC: usize = 0x1b;
+const ASSEMBLY: usize = 0x20;
+const ASSEMBLY_REF: usize = 0x23;
+const FILE: usize = 0x26;
+const EXPORTED_TYPE: usize = 0x27;
+const MANIFEST_RESOURCE: usize = 0x28;
+const NESTED_CLASS: usize = 0x29;
+const GENERIC_PARAM: usize = 0x2a;
+const METHOD_SPEC: usize = 0x2b;
+const GENERIC_PARAM_CONSTRAINT: usize = 0x2c;
+/// Marks unused tags of a coded index
+const NONE: usize = usize::MAX;
+
+const USER_STRING_TOKEN: u32 = 0x70 << 24;
+
+#[derive(Copy, Clone)]
+enum Column {
+    Fixed(usize),
+    String,
+    Guid,
+    Blob,
+    Table(usize),
+    /// An index into one of the tables, tagged with the given number of bits
+    Coded(&'static [usize], u32),
+}
+
+const TYPE_DEF_OR_REF: Column = Column::Coded(&[TYPE_DEF, TYPE_REF, TYPE_SPEC], 2);
+const HAS_CONSTANT: Column = Column::Coded(&[FIELD, PARAM, PROPERTY], 2);
+const HAS_CUSTOM_ATTRIBUTE: Column = Column::Coded(
+    &[
+        METHOD_DEF,
+        FIELD,
+        TYPE_REF,
+        TYPE_DEF,
+        PARAM,
+        INTERFACE_IMPL,
+        MEMBER_REF,
+        MODULE,
+        DECL_SECURITY,
+        PROPERTY,
+        EVENT,
+        STAND_ALONE_SIG,
+        MODULE_REF,
+        TYPE_SPEC,
+        ASSEMBLY,
+        ASSEMBLY_REF,
+        FILE,
+        EXPORTED_TYPE,
+        MANIFEST_RESOURCE,
+        GENERIC_PARAM,
+        GENERIC_PARAM_CONSTRAINT,
+        METHOD_SPEC,
+    ],
+    5,
+);
+const HAS_FIELD_MARSHAL: Column = Column::Coded(&[FIELD, PARAM], 1);
+const HAS_DECL_SECURITY: Column = Column::Coded(&[TYPE_DEF, METHOD_DEF, ASSEMBLY], 2);
+const MEMBER_REF_PARENT: Column =
+    Column::Coded(&[TYPE_DEF, TYPE_REF, MODULE_REF, METHOD_DEF, TYPE_SPEC], 3);
+const HAS_SEMANTICS: Column = Column::Coded(&[EVENT, PROPERTY], 1);
+const METHOD_DEF_OR_REF: Column = Column::Coded(&[METHOD_DEF, MEMBER_REF], 1);
+const MEMBER_FORWARDED: Column = Column::Coded(&[FIELD, METHOD_DEF], 1);
+const IMPLEMENTATION: Column = Column::Coded(&[FILE, ASSEMBLY_REF, EXPORTED_TYPE], 2);
+const CUSTOM_ATTRIBUTE_TYPE: Column = Column::Coded(&[NONE, NONE, METHOD_DEF, MEMBER_REF, NONE], 3);
+const RESOLUTION_SCOPE: Column = Column::Coded(&[MODULE, MODULE_REF, ASSEMBLY_REF, TYPE_REF], 2);
+const TYPE_OR_METHOD_DEF: Column = Column::Coded(&[TYPE_DEF, METHOD_DEF], 1);
+
+/// The columns of all tables in the order of their ids
+fn columns(table: usize) -> Option<&'static [Column]> {
+    use Column::*;
+    Some(match table {
+        0x00 => &[Fixed(2), String, Guid, Guid, Guid],
+        0x01 => &[RESOLUTION_SCOPE, String, String],
+        0x02 => &[
+            Fixed(4),
+            String,
+            String,
+            TYPE_DEF_OR_REF,
+            Table(FIELD),
+            Table(METHOD_DEF),
+        ],
+        0x03 => &[Table(FIELD)],
+        0x04 => &[Fixed(2), String, Blob],
+        0x05 => &[Table(METHOD_DEF)],
+        0x06 => &[Fixed(4), Fixed(2), Fixed(2), String, Blob, Table(PARAM)],
+        0x07 => &[Table(PARAM)],
+        0x08 => &[Fixed(2), Fixed(2), String],
+        0x09 => &[Table(TYPE_DEF), TYPE_DEF_OR_REF],
+        0x0a => &[MEMBER_REF_PARENT, String, Blob],
+        0x0b => &[Fixed(2), HAS_CONSTANT, Blob],
+        0x0c => &[HAS_CUSTOM_ATTRIBUTE, CUSTOM_ATTRIBUTE_TYPE, Blob],
+        0x0d => &[HAS_FIELD_MARSHAL, Blob],
+        0x0e => &[Fixed(2), HAS_DECL_SECURITY, Blob],
+        0x0f => &[Fixed(2), Fixed(4), Table(TYPE_DEF)],
+        0x10 => &[Fixed(4), Table(FIELD)],
+        0x11 => &[Blob],
+        0x12 => &[Table(TYPE_DEF), Table(EVENT)],
+        0x13 => &[Table(EVENT)],
+        0x14 => &[Fixed(2), String, TYPE_DEF_OR_REF],
+        0x15 => &[Table(TYPE_DEF), Table(PROPERTY)],
+        0x16 => &[Table(PROPERTY)],
+        0x17 => &[Fixed(2), String, Blob],
+        0x18 => &[Fixed(2), Table(METHOD_DEF), HAS_SEMANTICS],
+        0x19 => &[Table(TYPE_DEF), METHOD_DEF_OR_REF, METHOD_DEF_OR_REF],
+        0x1a => &[String],
+        0x1b => &[Blob],
+        0x1c => &[Fixed(2), MEMBER_FORWARDED, String, Table(MODULE_REF)],
+        0x1d => &[Fixed(4), Table(FIELD)],
+        0x1e => &[Fixed(4), Fixed(4)],
+        0x1f => &[Fixed(4)],
+        0x20 => &[
+            Fixed(4),
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(4),
+            Blob,
+            String,
+            String,
+        ],
+        0x21 => &[Fixed(4)],
+        0x22 => &[Fixed(4), Fixed(4), Fixed(4)],
+        0x23 => &[
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(2),
+            Fixed(4),
+            Blob,
+            String,
+            String,
+            Blob,
+        ],
+        0x24 => &[Fixed(4), Table(ASSEMBLY_REF)],
+        0x25 => &[Fixed(4), Fixed(4), Fixed(4), Table(ASSEMBLY_REF)],
+        0x26 => &[Fixed(4), String, Blob],
+        0x27 => &[Fixed(4), Fixed(4), String, String, IMPLEMENTATION],
+        0x28 => &[Fixed(4), Fixed(4), String, IMPLEMENTATION],
+        0x29 => &[Table(TYPE_DEF), Table(TYPE_DEF)],
+        0x2a => &[Fixed(2), Fixed(2), TYPE_OR_METHOD_DEF, String],
+        0x2b => &[METHOD_DEF_OR_REF, Blob],
+        0x2c => &[Table(GENERIC_PARAM), TYPE_DEF_OR_REF],
+        _ => return None,
+    })
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DotNetAssemblyName {
+    pub name: String,
+    /// `major.minor.build.revision`
+    pub version: String,
+    pub culture: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DotNetType {
+    pub token: u32,
+    pub namespace: String,
+    pub name: String,
+    /// Namespace and name, nested types are appended to their enclosing type with `+`
+    pub full_name: String,
+    /// Full name of the base type, if it is defined or referenced by name
+    pub extends: Option<String>,
+    pub flags: u32,
+    /// Index of the first method in `DotNetMetadata::methods`
+    pub first_method: u32,
+    pub method_count: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DotNetMethod {
+    pub token: u32,
+    pub name: String,
+    /// Full name of the declaring type
+    pub declaring_type: String,
+    pub flags: u16,
+    /// Relative virtual address of the body, 0 for abstract and native methods
+    pub rva: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DotNetUserString {
+    /// The token loading the string with `ldstr`
+    pub token: u32,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct DotNetMetadata {
+    /// Version of the runtime the assembly was built for (e.g. `v4.0.30319`)
+    pub runtime_version: String,
+    pub assembly: Option<DotNetAssemblyName>,
+    pub references: Vec<DotNetAssemblyName>,
+    pub types: Vec<DotNetType>,
+    pub methods: Vec<DotNetMethod>,
+    pub user_strings: Vec<DotNetUserString>,
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+/// The length prefix of blobs and user strings
+fn compressed_length(data: &[u8], offset: usize) -> Option<(usize, usize)> {
+    let first = *data.get(offset)? as usize;
+    if first & 0x80 == 0 {
+        Some((first, 1))
+    } else if first & 0xc0 == 0x80 {
+        Some((((first & 0x3f) << 8) | *data.get(offset + 1)? as usize, 2))
+    } else if first & 0xe0 == 0xc0 {
+        let rest = data.get(offset + 1..offset + 4)?;
+        Some((
+            ((first & 0x1f) << 24)
+                | (rest[0] as usize) << 16
+                | (rest[1] as usize) << 8
+                | rest[2] as usize,
+            4,
+        ))
+    } else {
+        None
+    }
+}
+
+struct Section {
+    virtual_address: u32,
+    virtual_size: u32,
+    raw_offset: u32,
+    raw_size: u32,
+}
+
+/// The metadata tables of the `#~` stream
+struct Tables<'a> {
+    stream: &'a [u8],
+    strings: &'a [u8],
+    rows: [u32; 64],
+    offsets: [usize; 64],
+    column_sizes: Vec<Vec<usize>>,
+}
+
+impl<'a> Tables<'a> {
+    fn parse(stream: &'a [u8], strings: &'a [u8]) -> Result<Self, String> {
+        let heap_sizes = *stream.get(6).ok_or("truncated table stream")?;
+        let valid = u32_at(stream, 8)
+            .zip(u32_at(stream, 12))
+            .map(|(low, high)| (high as u64) << 32 | low as u64)
+            .ok_or("truncated table stream")?;
+        let mut rows = [0u32; 64];
+        let mut position = 24;
+        for (table, count) in rows.iter_mut().enumerate() {
+            if valid & (1 << table) != 0 {
+                *count = u32_at(stream, position).ok_or("truncated row counts")?;
+                position += 4;
+            }
+        }
+        // uncompressed streams (`#-`) may carry additional data after the row counts
+        if heap_sizes & 0x20 != 0 {
+            position += 4;
+        }
+        let index_size = |rows: u32| if rows < 1 << 16 { 2 } else { 4 };
+        let heap_size = |flag: u8| if heap_sizes & flag != 0 { 4 } else { 2 };
+        let mut offsets = [0usize; 64];
+        let mut column_sizes = vec![vec![]; 64];
+        for table in 0..64 {
+            if rows[table] == 0 {
+                continue;
+            }
+            let columns =
+                columns(table).ok_or_else(|| format!("unsupported table {:#x}", table))?;
+            column_sizes[table] = columns
+                .iter()
+                .map(|column| match column {
+                    Column::Fixed(size) => *size,
+                    Column::String => heap_size(0x01),
+                    Column::Guid => heap_size(0x02),
+                    Column::Blob => heap_size(0x04),
+                    Column::Table(table) => index_size(rows[*table]),
+                    Column::Coded(tables, bits) => {
+                        let max_rows = tables
+                            .iter()
+                            .filter(|table| **table != NONE)
+                            .map(|table| rows[*table])
+                            .max()
+                            .unwrap_or_default();
+                        if max_rows < 1 << (16 - bits) {
+                            2
+                        } else {
+                            4
+                        }
+                    }
+                })
+                .collect();
+            offsets[table] = position;
+            position += rows[table] as usize * column_sizes[table].iter().sum::<usize>();
+        }
+        if position > stream.len() {
+            return Err("tables exceed the table stream".to_string());
+        }
+        Ok(Tables {
+            stream,
+            strings,
+            rows,
+            offsets,
+            column_sizes,
+        })
+    }
+
+    /// The value of a column of a row (0 based)
+    fn cell(&self, table: usize, row: u32, column: usize) -> u32 {
+        let sizes = &self.column_sizes[table];
+        let offset = self.offsets[table]
+            + row as usize * sizes.iter().sum::<usize>()
+            + sizes[..column].iter().sum::<usize>();
+        match sizes[column] {
+            1 => self.stream[offset] as u32,
+            2 => u16_at(self.stream, offset).unwrap_or_default() as u32,
+            _ => u32_at(self.stream, offset).unwrap_or_default(),
+        }
+    }
+
+    fn string(&self, table: usize, row: u32, column: usize) -> String {
+        let offset = self.cell(table, row, column) as usize;
+        let Some(bytes) = self.strings.get(offset..) else {
+            return String::new();
+        };
+        let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+        String::from_utf8_lossy(&bytes[..end]).to_string()
+    }
+
+    fn assembly_name(&self, table: usize, row: u32) -> DotNetAssemblyName {
+        // the Assembly table starts with the hash algorithm, AssemblyRef directly with the version
+        let version = if table == ASSEMBLY { 1 } else { 0 };
+        let name = if table == ASSEMBLY { 7 } else { 6 };
+        DotNetAssemblyName {
+            name: self.string(table, row, name),
+            version: (version..version + 4)
+                .map(|column| self.cell(table, row, column).to_string())
+                .collect::<Vec<_>>()
+                .join("."),
+            culture: self.string(table, row, name + 1),
+        }
+    }
+}
+
+impl DotNetMetadata {
+    /// Check for the DOS header of a PE image
+    pub fn is_pe(data: &[u8]) -> bool {
+        data.starts_with(b"MZ")
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if !Self::is_pe(data) {
+            return Err("not a PE image".to_string());
+        }
+        let (sections, cli_header) = Self::parse_pe(data)?;
+        let to_offset = |rva: u32| -> Option<usize> {
+            sections.iter().find_map(|section| {
+                let start = section.virtual_address;
+                let size = section.virtual_size.max(section.raw_size);
+                if rva >= start && rva - start < size {
+                    usize::try_from(u64::from(rva - start) + u64::from(section.raw_offset)).ok()
+                } else {
+                    None
+                }
+            })
+        };
+        let cli_header = to_offset(cli_header).ok_or("no CLI header")?;
+        let root = u32_at(data, cli_header + 8)
+            .and_then(to_offset)
+            .ok_or("no metadata")?;
+        if u32_at(data, root) != Some(METADATA_SIGNATURE) {
+            return Err("invalid metadata signature".to_string());
+        }
+        let version_length = u32_at(data, root + 12).ok_or("truncated metadata")? as usize;
+        let version = data
+            .get(root + 16..root + 16 + version_length)
+            .ok_or("truncated metadata")?;
+        let runtime_version = String::from_utf8_lossy(
+            &version[..version
+                .iter()
+                .position(|b| *b == 0)
+                .unwrap_or(version.len())],
+        )
+        .to_string();
+
+        let mut position = root + 16 + version_length;
+        let stream_count = u16_at(data, position + 2).ok_or("truncated metadata")?;
+        position += 4;
+        let (mut table_stream, mut strings, mut user_strings): (&[u8], &[u8], &[u8]) =
+            (&[], &[], &[]);
+        for _ in 0..stream_count {
+            let offset = u32_at(data, position).ok_or("truncated stream header")? as usize;
+            let size = u32_at(data, position + 4).ok_or("truncated stream header")? as usize;
+            let name = data.get(position + 8..).ok_or("truncated stream header")?;
+            let name_length = name.iter().position(|b| *b == 0).unwrap_or(name.len());
+            let stream = data
+                .get(root + offset..root + offset + size)
+                .ok_or("stream exceeds the file")?;
+            match &name[..name_length] {
+                b"#~" | b"#-" => table_stream = stream,
+                b"#Strings" => strings = stream,
+                b"#US" => user_strings = stream,
+                _ => {}
+            }
+            position += 8 + ((name_length + 4) & !3);
+        }
+        if table_stream.is_empty() {
+            return Err("no table stream".to_string());
+        }
+
+        let tables = Tables::parse(table_stream, strings)?;
+        let mut metadata = DotNetMetadata {
+            runtime_version,
+            ..Default::default()
+        };
+        if tables.rows[ASSEMBLY] > 0 {
+            metadata.assembly = Some(tables.assembly_name(ASSEMBLY, 0));
+        }
+        metadata.references = (0..tables.rows[ASSEMBLY_REF])
+            .map(|row| tables.assembly_name(ASSEMBLY_REF, row))
+            .collect();
+        metadata.types = Self::types(&tables);
+        let mut declaring_types = vec![""; tables.rows[METHOD_DEF] as usize];
+        for ty in &metadata.types {
+            for index in ty.first_method..ty.first_method + ty.method_count {
+                declaring_types[index as usize] = &ty.full_name;
+            }
+        }
+        metadata.methods = declaring_types
+            .iter()
+            .enumerate()
+            .map(|(index, declaring_type)| {
+                let row = index as u32;
+                DotNetMethod {
+                    token: (METHOD_DEF as u32) << 24 | (row + 1),
+                    name: tables.string(METHOD_DEF, row, 3),
+                    declaring_type: declaring_type.to_string(),
+                    flags: tables.cell(METHOD_DEF, row, 2) as u16,
+                    rva: tables.cell(METHOD_DEF, row, 0),
+                }
+            })
+            .collect();
+        metadata.user_strings = Self::user_strings(user_strings);
+        Ok(metadata)
+    }
+
+    /// The sections and the RVA of the CLI header
+    fn parse_pe(data: &[u8]) -> Result<(Vec<Section>, u32), String> {
+        let pe = u32_at(data, 0x3c).ok_or("truncated DOS header")? as usize;
+        if data.get(pe..pe + 4) != Some(PE_SIGNATURE) {
+            return Err("invalid PE signature".to_string());
+        }
+        let section_count = u16_at(data, pe + 6).ok_or("truncated COFF header")? as usize;
+        let optional_header_size = u16_at(data, pe + 20).ok_or("truncated COFF header")? as usize;
+        let optional_header = pe + 24;
+        let directories = match u16_at(data, optional_header) {
+            Some(0x10b) => optional_header + 96,
+            Some(0x20b) => optional_header + 112,
+            _ => return Err("invalid optional header".to_string()),
+        };
+        let cli_header =
+            u32_at(data, directories + 8 * CLI_HEADER_DIRECTORY).ok_or("no CLI header")?;
+        if cli_header == 0 {
+            return Err("not a .NET assembly".to_string());
+        }
+        let section_table = optional_header + optional_header_size;
+        let sections = (0..section_count)
+            .map(|index| {
+                let header = section_table + index * SECTION_HEADER_SIZE;
+                Some(Section {
+                    virtual_size: u32_at(data, header + 8)?,
+                    virtual_address: u32_at(data, header + 12)?,
+                    raw_size: u32_at(data, header + 16)?,
+                    raw_offset: u32_at(data, header + 20)?,
+                })
+            })
+            .collect::<Option<Vec<_>>>()
+            .ok_or("truncated section table")?;
+        Ok((sections, cli_header))
+    }
+
+    fn types(tables: &Tables) -> Vec<DotNetType> {
+        let type_count = tables.rows[TYPE_DEF];
+        let method_count = tables.rows[METHOD_DEF];
+        let mut enclosing = vec![None; type_count as usize];
+        for row in 0..tables.rows[NESTED_CLASS] {
+            let nested = tables.cell(NESTED_CLASS, row, 0);
+            let outer = tables.cell(NESTED_CLASS, row, 1);
+            if nested > 0 && nested <= type_count && outer > 0 && outer <= type_count {
+                enclosing[nested as usize - 1] = Some(outer - 1);
+            }
+        }
+        let simple_name = |row: u32| {
+            let namespace = tables.string(TYPE_DEF, row, 2);
+            let name = tables.string(TYPE_DEF, row, 1);
+            if namespace.is_empty() {
+                name
+            } else {
+                format!("{}.{}", namespace, name)
+            }
+        };
+        let full_name = |row: u32| {
+            let mut name = simple_name(row);
+            let mut current = row;
+            // bounded, broken metadata could nest types in a cycle
+            for _ in 0..type_count {
+                let Some(outer) = enclosing[current as usize] else {
+                    break;
+                };
+                name = format!("{}+{}", simple_name(outer), name);
+                current = outer;
+            }
+            name
+        };
+        let type_ref_name = |row: u32| {
+            let namespace = tables.string(TYPE_REF, row, 2);
+            let name = tables.string(TYPE_REF, row, 1);
+            if namespace.is_empty() {
+                name
+            } else {
+                format!("{}.{}", namespace, name)
+            }
+        };
+        (0..type_count)
+            .map(|row| {
+                let first = tables.cell(TYPE_DEF, row, 5).max(1).min(method_count + 1);
+                let next = if row + 1 < type_count {
+                    tables.cell(TYPE_DEF, row + 1, 5).min(method_count + 1)
+                } else {
+                    method_count + 1
+                };
+                let extends = tables.cell(TYPE_DEF, row, 3);
+                let extends = match (extends & 3, extends >> 2) {
+                    (_, 0) => None,
+                    (0, index) if index <= type_count => Some(full_name(index - 1)),
+                    (1, index) if index <= tables.rows[TYPE_REF] => Some(type_ref_name(index - 1)),
+                    _ => None,
+                };
+                DotNetType {
+                    token: (TYPE_DEF as u32) << 24 | (row + 1),
+                    namespace: tables.string(TYPE_DEF, row, 2),
+                    name: tables.string(TYPE_DEF, row, 1),
+                    full_name: full_name(row),
+                    extends,
+                    flags: tables.cell(TYPE_DEF, row, 0),
+                    first_method: first - 1,
+                    method_count: next.saturating_sub(first),
+                }
+            })
+            .collect()
+    }
+
+    /// The user string heap is a sequence of UTF-16 strings, each prefixed by its length and
+    /// followed by a flag byte
+    fn user_strings(heap: &[u8]) -> Vec<DotNetUserString> {
+        let mut strings = vec![];
+        // the heap starts with the empty string
+        let mut offset = 1;
+        while let Some((length, prefix)) = compressed_length(heap, offset) {
+            let Some(bytes) = heap.get(offset + prefix..offset + prefix + length) else {
+                break;
+            };
+            if length > 1 {
+                let units: Vec<u16> = bytes[..length - 1]
+                    .chunks_exact(2)
+                    .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                    .collect();
+                strings.push(DotNetUserString {
+                    token: USER_STRING_TOKEN | offset as u32,
+                    content: String::from_utf16_lossy(&units),
+                });
+            }
+            offset += prefix + length.max(1);
+        }
+        strings
+    }
+
+    pub fn methods_of(&self, ty: &DotNetType) -> &[DotNetMethod] {
+        let start = (ty.first_method as usize).min(self.methods.len());
+        let end = (start + ty.method_count as usize).min(self.methods.len());
+        &self.methods[start..end]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const SECTION_TABLE: usize = 0x138;
+    const CLI_HEADER: usize = 0x200;
+    const METADATA_ROOT: usize = 0x250;
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A heap of null terminated identifiers, starting with the empty one
+    #[derive(Default)]
+    struct Strings(Vec<u8>);
+
+    impl Strings {
+        fn add(&mut self, string: &str) -> u32 {
+            if self.0.is_empty() {
+                self.0.push(0);
+            }
+            let offset = self.0.len() as u32;
+            self.0.extend_from_slice(string.as_bytes());
+            self.0.push(0);
+            offset
+        }
+    }
+
+    /// A table stream with small heaps and indices, the rows hold one value per column
+    fn table_stream(tables: &[(usize, Vec<Vec<u32>>)]) -> Vec<u8> {
+        let mut stream = vec![0u8; 24];
+        stream[4] = 2;
+        let valid = tables
+            .iter()
+            .fold(0u64, |valid, (table, _)| valid | 1 << table);
+        put(&mut stream, 8, &valid.to_le_bytes());
+        for (_, rows) in tables {
+            stream.extend_from_slice(&(rows.len() as u32).to_le_bytes());
+        }
+        for (table, rows) in tables {
+            for row in rows {
+                for (column, value) in columns(*table).unwrap().iter().zip(row) {
+                    match column {
+                        Column::Fixed(4) => stream.extend_from_slice(&value.to_le_bytes()),
+                        _ => stream.extend_from_slice(&(*value as u16).to_le_bytes()),
+                    }
+                }
+            }
+        }
+        stream
+    }
+
+    fn user_string_heap(strings: &[&str]) -> Vec<u8> {
+        let mut heap = vec![0];
+        for string in strings {
+            let units: Vec<u8> = string.encode_utf16().flat_map(u16::to_le_bytes).collect();
+            heap.push(units.len() as u8 + 1);
+            heap.extend(units);
+            heap.push(0);
+        }
+        heap
+    }
+
+    /// A PE32 image with one section mapping RVA 0x2000 to the file offset 0x200, which holds the
+    /// CLI header and the metadata with the given streams
+    fn image(streams: &[(&str, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![0u8; 0x280];
+        put(&mut data, 0, b"MZ");
+        put(&mut data, 0x3c, &0x40u32.to_le_bytes());
+        put(&mut data, 0x40, PE_SIGNATURE);
+        put(&mut data, 0x46, &1u16.to_le_bytes());
+        put(&mut data, 0x54, &0xe0u16.to_le_bytes());
+        put(&mut data, 0x58, &0x10bu16.to_le_bytes());
+        put(&mut data, 0x58 + 96 + 8 * 14, &0x2000u32.to_le_bytes());
+        put(&mut data, SECTION_TABLE, b".text\0\0\0");
+        for (offset, value) in [(8, 0x1000u32), (12, 0x2000), (16, 0x1000), (20, 0x200)] {
+            put(&mut data, SECTION_TABLE + offset, &value.to_le_bytes());
+        }
+        put(&mut data, CLI_HEADER, &72u32.to_le_bytes());
+        put(&mut data, CLI_HEADER + 8, &0x2050u32.to_le_bytes());
+
+        put(&mut data, METADATA_ROOT, &METADATA_SIGNATURE.to_le_bytes());
+        put(&mut data, METADATA_ROOT + 12, &12u32.to_le_bytes());
+        put(&mut data, METADATA_ROOT + 16, b"v4.0.30319\0\0");
+        put(
+            &mut data,
+            METADATA_ROOT + 30,
+            &(streams.len() as u16).to_le_bytes(),
+        );
+        let mut headers = vec![];
+        let mut contents = vec![];
+        let headers_size: usize = streams
+            .iter()
+            .map(|(name, _)| 8 + ((name.len() + 4) & !3))
+            .sum();
+        for (name, content) in streams {
+            let offset = 32 + headers_size + contents.len();
+            headers.extend_from_slice(&(offset as u32).to_le_bytes());
+            headers.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            let mut name = name.as_bytes().to_vec();
+            name.resize((name.len() + 4) & !3, 0);
+            headers.extend(name);
+            contents.extend_from_slice(content);
+            contents.resize((contents.len() + 3) & !3, 0);
+        }
+        data.truncate(METADATA_ROOT + 32);
+        data.extend(headers);
+        data.extend(contents);
+        data
+    }
+
+    fn assembly() -> Vec<u8> {
+        let mut strings = Strings::default();
+        let (system, object) = (strings.add("System"), strings.add("Object"));
+        let (app, outer, inner) = (
+            strings.add("App"),
+            strings.add("Outer"),
+            strings.add("Inner"),
+        );
+        let methods: Vec<u32> = [".ctor", "Run", "Get"]
+            .iter()
+            .map(|name| strings.add(name))
+            .collect();
+        let (assembly, mscorlib) = (strings.add("App"), strings.add("mscorlib"));
+        let tables = table_stream(&[
+            (TYPE_REF, vec![vec![0, object, system]]),
+            (
+                TYPE_DEF,
+                vec![
+                    // extends the first TypeRef
+                    vec![0x100001, outer, app, 1 << 2 | 1, 1, 1],
+                    vec![0x100002, inner, 0, 0, 1, 3],
+                ],
+            ),
+            (
+                METHOD_DEF,
+                methods
+                    .iter()
+                    .map(|name| vec![0x2050, 0, 0x1886, *name, 0, 1])
+                    .collect(),
+            ),
+            (ASSEMBLY, vec![vec![0x8004, 1, 2, 3, 4, 0, 0, assembly, 0]]),
+            (ASSEMBLY_REF, vec![vec![4, 0, 0, 0, 0, 0, mscorlib, 0, 0]]),
+            (NESTED_CLASS, vec![vec![2, 1]]),
+        ]);
+        image(&[
+            ("#~", tables),
+            ("#Strings", strings.0),
+            ("#US", user_string_heap(&["hello", "wörld"])),
+            ("#GUID", vec![0; 16]),
+        ])
+    }
+
+    #[test]
+    fn parses_assemblies() {
+        let metadata = DotNetMetadata::parse(&assembly()).unwrap();
+        assert_eq!(metadata.runtime_version, "v4.0.30319");
+        assert_eq!(
+            metadata.assembly,
+            Some(DotNetAssemblyName {
+                name: "App".to_string(),
+                version: "1.2.3.4".to_string(),
+                culture: String::new(),
+            })
+        );
+        assert_eq!(metadata.references.len(), 1);
+        assert_eq!(metadata.references[0].name, "mscorlib");
+        assert_eq!(metadata.references[0].version, "4.0.0.0");
+
+        let full_names: Vec<&str> = metadata
+            .types
+            .iter()
+            .map(|ty| ty.full_name.as_str())
+            .collect();
+        assert_eq!(full_names, vec!["App.Outer", "App.Outer+Inner"]);
+        let outer = &metadata.types[0];
+        assert_eq!(outer.token, 0x0200_0001);
+        assert_eq!(outer.extends.as_deref(), Some("System.Object"));
+        assert_eq!(metadata.types[1].extends, None);
+
+        let methods: Vec<(&str, &str)> = metadata
+            .methods_of(outer)
+            .iter()
+            .map(|method| (method.name.as_str(), method.declaring_type.as_str()))
+            .collect();
+        assert_eq!(methods, vec![(".ctor", "App.Outer"), ("Run", "App.Outer")]);
+        let get = &metadata.methods_of(&metadata.types[1])[0];
+        assert_eq!(
+            (get.token, get.name.as_str(), get.flags, get.rva),
+            (0x0600_0003, "Get", 0x1886, 0x2050)
+        );
+
+        assert_eq!(
+            metadata.user_strings,
+            vec![
+                DotNetUserString {
+                    token: 0x7000_0001,
+                    content: "hello".to_string(),
+                },
+                DotNetUserString {
+                    token: 0x7000_000d,
+                    content: "wörld".to_string(),
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_images() {
+        let data = assembly();
+        for length in 0..data.len() {
+            assert!(
+                DotNetMetadata::parse(&data[..length]).is_err(),
+                "{}",
+                length
+            );
+        }
+        assert!(DotNetMetadata::parse(b"ELF").is_err());
+    }
+
+    #[test]
+    fn rejects_malformed_headers() {
+        let data = assembly();
+        let broken = |offset: usize, bytes: &[u8]| {
+            let mut data = data.clone();
+            put(&mut data, offset, bytes);
+            DotNetMetadata::parse(&data).unwrap_err()
+        };
+        assert_eq!(broken(0x40, b"PX"), "invalid PE signature");
+        assert_eq!(broken(0x58, &[0, 0]), "invalid optional header");
+        assert_eq!(broken(0x128, &[0, 0, 0, 0]), "not a .NET assembly");
+        assert_eq!(broken(0x128, &[0, 0, 0, 1]), "no CLI header");
+        assert_eq!(broken(CLI_HEADER + 8, &[0, 0, 0, 0]), "no metadata");
+        assert_eq!(broken(METADATA_ROOT, b"BSJC"), "invalid metadata signature");
+        assert_eq!(
+            broken(METADATA_ROOT + 12, &u32::MAX.to_le_bytes()),
+            "truncated metadata"
+        );
+        assert_eq!(
+            broken(METADATA_ROOT + 36, &u32::MAX.to_le_bytes()),
+            "stream exceeds the file"
+        );
+        assert_eq!(broken(METADATA_ROOT + 40, b"#X"), "no table stream");
+    }
+
+    #[test]
+    fn sections_at_the_end_of_the_address_space_do_not_overflow() {
+        let mut data = assembly();
+        put(&mut data, SECTION_TABLE + 8, &0x1000u32.to_le_bytes());
+        put(&mut data, SECTION_TABLE + 12, &0xffff_f800u32.to_le_bytes());
+        put(&mut data, SECTION_TABLE + 20, &u32::MAX.to_le_bytes());
+        put(&mut data, 0x128, &0xffff_fff0u32.to_le_bytes());
+        assert_eq!(DotNetMetadata::parse(&data).unwrap_err(), "no metadata");
+    }
+
+    #[test]
+    fn rejects_malformed_tables() {
+        let strings = Strings::default().0;
+        // a table without a known layout
+        let mut tables = table_stream(&[(MODULE, vec![vec![0; 5]])]);
+        put(&mut tables, 8, &(1u64 << 0x3f).to_le_bytes());
+        let data = image(&[("#~", tables), ("#Strings", strings.clone())]);
+        assert_eq!(
+            DotNetMetadata::parse(&data).unwrap_err(),
+            "unsupported table 0x3f"
+        );
+        // more rows than the stream holds
+        let mut tables = table_stream(&[(METHOD_DEF, vec![vec![0; 6]])]);
+        put(&mut tables, 24, &u32::MAX.to_le_bytes());
+        let data = image(&[("#~", tables), ("#Strings", strings.clone())]);
+        assert_eq!(
+            DotNetMetadata::parse(&data).unwrap_err(),
+            "tables exceed the table stream"
+        );
+        let data = image(&[("#~", vec![0; 7])]);
+        assert_eq!(
+            DotNetMetadata::parse(&data).unwrap_err(),
+            "truncated table stream"
+        );
+        let mut tables = table_stream(&[(METHOD_DEF, vec![])]);
+        tables.truncate(24);
+        let data = image(&[("#~", tables)]);
+        assert_eq!(
+            DotNetMetadata::parse(&data).unwrap_err(),
+            "truncated row counts"
+        );
+    }
+
+    #[test]
+    fn tolerates_broken_references() {
+        let mut strings = Strings::default();
+        let (a, b) = (strings.add("A"), strings.add("B"));
+        // types nested in each other, a base type and methods out of range, a name beyond the heap
+        let tables = table_stream(&[
+            (
+                TYPE_DEF,
+                vec![
+                    vec![0, a, 0, 9 << 2, 1, 7],
+                    vec![0, b, 0x1000, 2 << 2 | 1, 1, 0],
+                ],
+            ),
+            (METHOD_DEF, vec![vec![0, 0, 0, 0x1000, 0, 1]]),
+            (NESTED_CLASS, vec![vec![1, 2], vec![2, 1], vec![5, 1]]),
+        ]);
+        let data = image(&[("#~", tables), ("#Strings", strings.0)]);
+        let metadata = DotNetMetadata::parse(&data).unwrap();
+        assert_eq!(metadata.types[0].extends, None);
+        assert_eq!(metadata.types[1].extends, None);
+        assert_eq!(metadata.types[0].method_count, 0);
+        assert!(metadata.methods_of(&metadata.types[0]).is_empty());
+        assert_eq!(metadata.methods[0].name, "");
+        assert_eq!(metadata.types[1].full_name, "B+A+B");
+    }
+
+    #[test]
+    fn reads_compressed_lengths() {
+        assert_eq!(compressed_length(&[0x03], 0), Some((3, 1)));
+        assert_eq!(compressed_length(&[0x80, 0x80], 0), Some((0x80, 2)));
+        assert_eq!(compressed_length(&[0xbf, 0xff], 0), Some((0x3fff, 2)));
+        assert_eq!(
+            compressed_length(&[0xc0, 0x00, 0x40, 0x00], 0),
+            Some((0x4000, 4))
+        );
+        assert_eq!(compressed_length(&[0xc0, 0x00, 0x40], 0), None);
+        assert_eq!(compressed_length(&[0x80], 0), None);
+        assert_eq!(compressed_length(&[0xe0], 0), None);
+        assert_eq!(compressed_length(&[], 0), None);
+        // a string longer than the heap ends the strings
+        let mut heap = user_string_heap(&["ok"]);
+        heap.extend_from_slice(&[0x20, b'a', 0]);
+        assert_eq!(DotNetMetadata::user_strings(&heap).len(), 1);
+    }
+}
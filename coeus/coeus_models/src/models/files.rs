@@ -4,7 +4,12 @@
 // License, v. 2.0. If a copy of the MPL was not distributed with this
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
-use super::{BinaryObject, DexFile, MultiDexFile};
+use super::{
+    extract_managed_assemblies, resolve_reference_chain, ArchiveAnomaly, AssemblyStoreManifest,
+    BinaryObject, DexFile, FileProviderPaths, HermesBytecode, Il2CppMetadata, ManagedAssembly,
+    MultiDexFile, NativeLibrary, NetworkSecurityConfig, Resource, ResourceTable, ResourceValue, StringQuery,
+    FRAMEWORK_PACKAGE_ID,
+};
 use abxml::visitor::{Executor, ModelVisitor, XmlVisitor};
 use coeus_macros::iterator;
 use rayon::prelude::*;
@@ -15,8 +20,14 @@ pub struct Files {
     pub multi_dex: Vec<MultiDexFile>,
     pub binaries: HashMap<String, Arc<BinaryObject>>,
     pub binary_resource_file: Vec<u8>,
-    #[serde(skip_deserializing, skip_serializing)]
-    pub arsc: Option<arsc::Arsc>,
+    /// All binary xml files (layouts, xml resources, ...) decoded to plain xml, indexed by their file name
+    #[serde(default)]
+    pub decoded_xml: HashMap<String, String>,
+    /// Anomalies found while reading the archive and all nested archives
+    #[serde(default)]
+    pub archive_anomalies: Vec<ArchiveAnomaly>,
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub arsc: Option<ResourceTable>,
 }
 
 impl Clone for Files {
@@ -25,7 +36,9 @@ impl Clone for Files {
             multi_dex: self.multi_dex.clone(),
             binaries: self.binaries.clone(),
             binary_resource_file: self.binary_resource_file.clone(),
-            arsc: None,
+            decoded_xml: self.decoded_xml.clone(),
+            archive_anomalies: self.archive_anomalies.clone(),
+            arsc: self.arsc.clone(),
         }
     }
 }
@@ -36,6 +49,8 @@ impl Files {
             multi_dex,
             binaries,
             binary_resource_file: vec![],
+            decoded_xml: HashMap::new(),
+            archive_anomalies: vec![],
             arsc: None,
         }
     }
@@ -48,6 +63,74 @@ impl Files {
             .cloned()
     }
 
+    /// Find all strings matching the query in all dex files
+    pub fn find_strings(&self, query: &StringQuery) -> Vec<(Arc<DexFile>, u32)> {
+        self.multi_dex
+            .iter()
+            .flat_map(|multi_dex| multi_dex.find_strings(query))
+            .collect()
+    }
+
+    /// All ELF files among the binaries, sorted by their path
+    pub fn native_libraries(&self) -> Vec<(&str, NativeLibrary)> {
+        let mut libraries: Vec<_> = self
+            .binaries
+            .iter()
+            .filter_map(|(name, object)| Some((name.as_str(), object.native_library()?)))
+            .collect();
+        libraries.sort_by(|a, b| a.0.cmp(b.0));
+        libraries
+    }
+
+    /// All Hermes bytecode bundles among the binaries, sorted by their path
+    pub fn hermes_bundles(&self) -> Vec<(&str, HermesBytecode)> {
+        let mut bundles: Vec<_> = self
+            .binaries
+            .iter()
+            .filter_map(|(name, object)| Some((name.as_str(), object.hermes_bytecode()?)))
+            .collect();
+        bundles.sort_by(|a, b| a.0.cmp(b.0));
+        bundles
+    }
+
+    /// The IL2CPP metadata of Unity games, sorted by their path. Files with an unsupported
+    /// metadata version are logged and skipped
+    pub fn il2cpp_metadata(&self) -> Vec<(&str, Il2CppMetadata)> {
+        let mut metadata: Vec<_> = self
+            .binaries
+            .iter()
+            .filter(|(_, object)| Il2CppMetadata::is_il2cpp_metadata(object.data()))
+            .filter_map(|(name, object)| match Il2CppMetadata::parse(object.data()) {
+                Ok(metadata) => Some((name.as_str(), metadata)),
+                Err(e) => {
+                    log::warn!("Could not parse IL2CPP metadata {}: {}", name, e);
+                    None
+                }
+            })
+            .collect();
+        metadata.sort_by(|a, b| a.0.cmp(b.0));
+        metadata
+    }
+
+    /// All managed assemblies of Xamarin and .NET apps, decompressed and extracted from the
+    /// assembly stores, sorted by their file and name
+    pub fn managed_assemblies(&self) -> Vec<ManagedAssembly> {
+        let manifest = self
+            .binaries
+            .iter()
+            .find(|(name, _)| name.ends_with("assemblies.manifest"))
+            .map(|(_, object)| {
+                AssemblyStoreManifest::parse(&String::from_utf8_lossy(object.data()))
+            });
+        let mut assemblies: Vec<_> = iterator!(self.binaries)
+            .flat_map(|(name, object)| {
+                extract_managed_assemblies(name, object.data(), manifest.as_ref())
+            })
+            .collect();
+        assemblies.sort_by(|a, b| (&a.file_name, &a.name).cmp(&(&b.file_name, &b.name)));
+        assemblies
+    }
+
     pub fn get_multi_dex_from_dex_identifier(
         &self,
         identifier: &str,
@@ -74,122 +157,152 @@ impl Files {
         let _ = Executor::xml(Cursor::new(&binary_xml), &mut visitor);
         visitor.into_string().ok()
     }
+    pub fn get_decoded_xml(&self, file_name: &str) -> Option<&str> {
+        self.decoded_xml.get(file_name).map(|xml| xml.as_str())
+    }
+
+    /// Decoded xml files with the given root element. The root element is used instead of
+    /// the file name, since resource names are often obfuscated.
+    fn decoded_xml_with_root<'a>(
+        &'a self,
+        root: &'a str,
+    ) -> impl Iterator<Item = (&'a String, &'a String)> {
+        self.decoded_xml
+            .iter()
+            .filter(move |(_, xml)| root_element(xml) == Some(root))
+    }
+
+    pub fn network_security_configs(&self) -> Vec<(String, NetworkSecurityConfig)> {
+        self.decoded_xml_with_root("network-security-config")
+            .filter_map(|(name, xml)| match serde_xml_rs::from_str(xml) {
+                Ok(config) => Some((name.clone(), config)),
+                Err(e) => {
+                    log::warn!("Could not parse network security config {}: {:?}", name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
+    pub fn file_provider_paths(&self) -> Vec<(String, FileProviderPaths)> {
+        self.decoded_xml_with_root("paths")
+            .filter_map(|(name, xml)| match serde_xml_rs::from_str(xml) {
+                Ok(paths) => Some((name.clone(), paths)),
+                Err(e) => {
+                    log::warn!("Could not parse FileProvider paths {}: {:?}", name, e);
+                    None
+                }
+            })
+            .collect()
+    }
+
     pub fn load_arsc(&mut self) -> Result<(), String> {
-        let Ok(arsc) = arsc::parse_from(Cursor::new(&self.binary_resource_file)) else {
-            return Err("Could not load arsc".to_string());
-        };
+        let arsc = ResourceTable::parse(&self.binary_resource_file)
+            .map_err(|e| format!("Could not load arsc: {}", e))?;
         self.arsc = Some(arsc);
         Ok(())
     }
-    pub fn get_string_from_resource(&self, id: u32) -> Option<(String, HashMap<String, String>)> {
-        let Some(arsc) = self.arsc.as_ref() else {
-            return None;
-        };
-        let Some(pkg) = arsc.packages.iter().find(|p| p.id == ((id & 0xff_00_00_00) >> 24)) else {
-            return None
-        };
-        let Some(ty) = pkg.types.iter().find(|ty| ty.id == ((id & 0x00_ff_00_00) >> 16) as usize) else {
-            return None;
-        };
-        if pkg.type_names.strings[ty.id - 1] != "string" {
-            return None;
-        }
-        let mut localized_strings = HashMap::new();
-        let mut entry_name = String::default();
-
-        for resource in &ty.configs {
-            if let Some(entry) = resource
-                .resources
-                .resources
-                .iter()
-                .find(|r| r.spec_id == (id as usize) & 0xff_ff)
-            {
-                let locale = if &resource.id[8..10] == [0, 0] {
-                    "default".to_string()
-                } else if let Ok(locale) = std::str::from_utf8(&resource.id[8..10]) {
-                    locale.to_string()
-                } else {
-                    continue;
-                };
-                if let Some(name) = pkg.key_names.strings.get(entry.name_index) {
-                    entry_name = name.to_string();
-                }
 
-                match &entry.value {
-                    arsc::ResourceValue::Plain(a) => {
-                        if a.is_string() {
-                            if let Some(val) = arsc.global_string_pool.strings.get(a.data_index) {
-                                localized_strings.insert(locale.to_string(), val.to_string());
-                            }
-                        }
-                    }
-                    _ => continue,
-                }
+    /// Resolve a resource id to its values for all configurations. Ids of the android framework
+    /// (`0x01xxxxxx`) are looked up in the bundled framework resource table.
+    pub fn get_resource(&self, id: u32) -> Option<Resource> {
+        if (id >> 24) as u8 == FRAMEWORK_PACKAGE_ID {
+            if let Some(resource) = ResourceTable::framework().and_then(|f| f.get_resource(id)) {
+                return Some(resource);
             }
         }
-        Some((entry_name, localized_strings))
+        self.arsc.as_ref()?.get_resource(id)
     }
 
-    pub fn get_mipmap_file_name_from_resource(&self, id: u32) -> Option<(String, HashMap<String, String>)> {
-        let Some(arsc) = self.arsc.as_ref() else {
-            return None;
-        };
-        let Some(pkg) = arsc.packages.iter().find(|p| p.id == ((id & 0xff_00_00_00) >> 24)) else {
-            return None
-        };
-
-        let Some(ty) = pkg.types.iter().find(|ty| ty.id == ((id & 0x00_ff_00_00) >> 16) as usize) else {
-            return None;
-        };
-
-        let mut resource_map: HashMap<String, String> = HashMap::new();
-        let mut entry_name = String::default();
-
-
-        for resource in &ty.configs {
-            if let Some(entry) = resource
-                .resources
-                .resources
-                .iter()
-                .find(|r| r.spec_id == (id as usize) & 0xff_ff)
-            {
-                
-                let den: u16 = ((resource.id[15] as u16) << 8) + resource.id[14] as u16;
-                
-                let density = match den {
-                    160 => "MDPI".to_string(),
-                    240 => "HDPI".to_string(),
-                    320 => "XHDPI".to_string(),
-                    480 => "XXHDPI".to_string(),
-                    640 => "XXXHDPI".to_string(),
-                    65534 => {
-                        let version = resource.id[24].to_string();
-                        let mut any = "ANYDPI-v".to_string();
-                        any.push_str(&version);
-                        any
-                    },
-                    _ => den.to_string(),
-                };
-
-                if let Some(name) = pkg.key_names.strings.get(entry.name_index) {
-                    entry_name = name.to_string();
-                }
+    /// Lookup a resource id by its name (e.g. `string/app_name` or `@android:string/ok`)
+    pub fn get_resource_id(&self, name: &str) -> Option<u32> {
+        self.arsc
+            .as_ref()
+            .and_then(|arsc| arsc.get_resource_id(name))
+            .or_else(|| ResourceTable::framework()?.get_resource_id(name))
+    }
 
-                match &entry.value {
-                    arsc::ResourceValue::Plain(a) => {
-                        if a.is_string() {
-                            if let Some(val) = arsc.global_string_pool.strings.get(a.data_index) {
-                                resource_map.insert(density.to_string(), val.to_string());
-                            }
-                        }
-                    }
-                    _ => continue,
-                }   
+    /// Resolve a resource id and follow all references, such that every configuration
+    /// maps to a final value.
+    pub fn resolve_resource(&self, id: u32) -> Option<Resource> {
+        let mut resource = self.get_resource(id)?;
+        for (config, value) in resource.values.iter_mut() {
+            *value = resolve_reference_chain(value, config, |id| self.get_resource(id));
+        }
+        Some(resource)
+    }
 
+    /// Short description of a resource used to annotate code, e.g. `R.string.app_name = "Coeus"`.
+    /// Returns `None` if the id is not part of any loaded resource table.
+    pub fn describe_resource(&self, id: u32) -> Option<String> {
+        let resource = self.resolve_resource(id)?;
+        let java_name = resource.java_name();
+        // ids only carry a placeholder value
+        if resource.type_name == "id" {
+            return Some(java_name);
+        }
+        match resource.default_value() {
+            Some(ResourceValue::String(s)) => {
+                Some(format!("{} = \"{}\"", java_name, s.escape_default()))
             }
+            Some(ResourceValue::Bag { .. }) | None => Some(java_name),
+            Some(value) => Some(format!("{} = {}", java_name, value)),
         }
+    }
+
+    pub fn get_string_from_resource(&self, id: u32) -> Option<(String, HashMap<String, String>)> {
+        let resource = self.resolve_resource(id)?;
+        if resource.type_name != "string" {
+            return None;
+        }
+        let localized_strings = resource
+            .values
+            .iter()
+            .filter_map(|(config, value)| {
+                // qualifiers other than the locale (e.g. `night` or `de-v21`) are kept in the key,
+                // such that they do not overwrite the default or the plain locale value
+                let qualifier = if config.is_default() {
+                    "default".to_string()
+                } else {
+                    config.qualifier()
+                };
+                Some((qualifier, value.as_str()?.to_string()))
+            })
+            .collect();
+        Some((resource.name, localized_strings))
+    }
 
-        Some((entry_name, resource_map))
+    pub fn get_mipmap_file_name_from_resource(
+        &self,
+        id: u32,
+    ) -> Option<(String, HashMap<String, String>)> {
+        let resource = self.resolve_resource(id)?;
+        let resource_map = resource
+            .values
+            .iter()
+            .filter_map(|(config, value)| {
+                let density = match config.density {
+                    0xfffe => format!("ANYDPI-v{}", config.sdk_version),
+                    0 => "0".to_string(),
+                    _ => config.density_name().to_uppercase(),
+                };
+                Some((density, value.as_str()?.to_string()))
+            })
+            .collect();
+        Some((resource.name, resource_map))
+    }
+}
 
+/// Name of the first element, skipping the xml declaration and comments
+fn root_element(xml: &str) -> Option<&str> {
+    let mut rest = xml;
+    loop {
+        let start = rest.find('<')?;
+        rest = &rest[start + 1..];
+        if !rest.starts_with('?') && !rest.starts_with('!') {
+            break;
+        }
     }
+    let end = rest.find(|c: char| c.is_whitespace() || c == '>' || c == '/')?;
+    Some(&rest[..end])
 }
@@ -0,0 +1,55 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A stub of the android framework, describing the supertypes and overridable methods of the
+//! framework classes apps commonly extend. It is far from complete, types missing from the stub
+//! are treated as unknown.
+
+use std::collections::HashMap;
+
+use super::ApiDatabase;
+
+/// The highest API level the bundled stub knows about
+pub const LATEST_API_LEVEL: u32 = 34;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FrameworkClass {
+    pub name: String,
+    pub is_interface: bool,
+    /// `None` for `java/lang/Object` and interfaces
+    pub super_class: Option<String>,
+    pub interfaces: Vec<String>,
+    /// Overridable methods as name and prototype, e.g. `onCreate(Landroid/os/Bundle;)V`
+    pub methods: Vec<String>,
+}
+
+/// Framework classes as of one API level
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct FrameworkStub {
+    pub api_level: u32,
+    pub(crate) classes: HashMap<String, FrameworkClass>,
+}
+
+impl FrameworkStub {
+    /// The stub bundled with coeus. Levels above `LATEST_API_LEVEL` yield the latest level.
+    pub fn bundled(api_level: u32) -> Self {
+        ApiDatabase::bundled().stub(api_level)
+    }
+
+    /// Parse an api database in the format of the bundled one (see `data/framework_api.txt`),
+    /// leaving out everything not available at `api_level`
+    pub fn parse(content: &str, api_level: u32) -> Result<Self, String> {
+        Ok(ApiDatabase::parse(content)?.stub(api_level))
+    }
+
+    pub fn get(&self, class_name: &str) -> Option<&FrameworkClass> {
+        self.classes.get(class_name)
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item = &FrameworkClass> {
+        self.classes.values()
+    }
+}
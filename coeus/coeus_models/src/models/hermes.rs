@@ -0,0 +1,544 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! React Native apps compiled with Hermes ship their JavaScript as Hermes bytecode
+//! (`assets/index.android.bundle`). The file starts with a fixed header, followed by the function
+//! headers, the string table and the string storage, each aligned to four bytes. The bytecode of
+//! the functions follows the tables.
+
+use std::convert::TryInto;
+
+/// The magic of bytecode files, delta files for hot reloading use the bitwise complement
+const HERMES_MAGIC: u64 = 0x1f19_03c1_03bc_1fc6;
+/// Older versions use a different layout of the string table
+pub const MIN_HERMES_VERSION: u32 = 74;
+const HEADER_SIZE: usize = 128;
+const SMALL_FUNCTION_HEADER_SIZE: usize = 16;
+const LARGE_FUNCTION_HEADER_SIZE: usize = 28;
+
+/// A string longer than this is stored in the overflow table
+const SMALL_STRING_MAX_LENGTH: u32 = 0xff;
+
+const FLAG_STRICT_MODE: u8 = 1 << 2;
+const FLAG_EXCEPTION_HANDLER: u8 = 1 << 3;
+const FLAG_DEBUG_INFO: u8 = 1 << 4;
+const FLAG_OVERFLOWED: u8 = 1 << 5;
+
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HermesHeader {
+    pub version: u32,
+    /// Hex encoded SHA1 of the JavaScript source
+    pub source_hash: String,
+    pub file_length: u32,
+    /// The function running the top level code of the bundle
+    pub global_code_index: u32,
+    pub function_count: u32,
+    pub string_kind_count: u32,
+    pub identifier_count: u32,
+    pub string_count: u32,
+    pub overflow_string_count: u32,
+    pub string_storage_size: u32,
+    /// Since version 87
+    pub big_int_count: u32,
+    pub big_int_storage_size: u32,
+    pub reg_exp_count: u32,
+    pub reg_exp_storage_size: u32,
+    pub array_buffer_size: u32,
+    pub obj_key_buffer_size: u32,
+    pub obj_value_buffer_size: u32,
+    /// `cjsModuleOffset` before version 78
+    pub segment_id: u32,
+    pub cjs_module_count: u32,
+    /// Since version 84
+    pub function_source_count: u32,
+    pub debug_info_offset: u32,
+    pub options: u8,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HermesFunction {
+    pub index: u32,
+    /// Index of the name in the string table, anonymous functions have an empty name
+    pub name_index: u32,
+    pub name: String,
+    /// File offset of the bytecode
+    pub offset: u32,
+    pub bytecode_size: u32,
+    pub param_count: u32,
+    pub frame_size: u32,
+    pub environment_size: u8,
+    pub flags: u8,
+}
+
+impl HermesFunction {
+    pub fn is_strict(&self) -> bool {
+        self.flags & FLAG_STRICT_MODE != 0
+    }
+
+    pub fn has_exception_handler(&self) -> bool {
+        self.flags & FLAG_EXCEPTION_HANDLER != 0
+    }
+
+    pub fn has_debug_info(&self) -> bool {
+        self.flags & FLAG_DEBUG_INFO != 0
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HermesString {
+    pub index: u32,
+    pub content: String,
+    /// Property and variable names, as opposed to string literals
+    pub identifier: bool,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct HermesBytecode {
+    pub header: HermesHeader,
+    pub functions: Vec<HermesFunction>,
+    pub strings: Vec<HermesString>,
+}
+
+/// Reads the sequential parts of the file, aligning each table to four bytes
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn u8(&mut self) -> Result<u8, String> {
+        let value = *self
+            .data
+            .get(self.position)
+            .ok_or_else(|| format!("unexpected end of file at {:#x}", self.position))?;
+        self.position += 1;
+        Ok(value)
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes(bytes.try_into().unwrap()))
+    }
+
+    fn bytes(&mut self, length: usize) -> Result<&'a [u8], String> {
+        let bytes = self
+            .position
+            .checked_add(length)
+            .and_then(|end| self.data.get(self.position..end))
+            .ok_or_else(|| format!("unexpected end of file at {:#x}", self.position))?;
+        self.position += length;
+        Ok(bytes)
+    }
+
+    fn table(&mut self, count: u32, entry_size: usize) -> Result<&'a [u8], String> {
+        let length = (count as usize)
+            .checked_mul(entry_size)
+            .ok_or_else(|| format!("table of {} entries is too large", count))?;
+        let table = self.bytes(length)?;
+        self.position = (self.position + 3) & !3;
+        Ok(table)
+    }
+}
+
+fn u32_at(table: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes(table[offset..offset + 4].try_into().unwrap())
+}
+
+impl HermesBytecode {
+    /// Check the magic of a bytecode file
+    pub fn is_hermes_bytecode(data: &[u8]) -> bool {
+        data.len() >= 8 && u64::from_le_bytes(data[..8].try_into().unwrap()) == HERMES_MAGIC
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if !Self::is_hermes_bytecode(data) {
+            return Err("not a Hermes bytecode file".to_string());
+        }
+        let header = Self::parse_header(data)?;
+        if header.version < MIN_HERMES_VERSION {
+            return Err(format!("unsupported bytecode version {}", header.version));
+        }
+        let mut reader = Reader {
+            data,
+            position: HEADER_SIZE,
+        };
+        let function_headers = reader.table(header.function_count, SMALL_FUNCTION_HEADER_SIZE)?;
+        let string_kinds = reader.table(header.string_kind_count, 4)?;
+        reader.table(header.identifier_count, 4)?;
+        let small_strings = reader.table(header.string_count, 4)?;
+        let overflow_strings = reader.table(header.overflow_string_count, 8)?;
+        let storage = reader.table(header.string_storage_size, 1)?;
+
+        let identifiers = Self::identifier_flags(string_kinds, header.string_count);
+        let mut strings = Vec::with_capacity(header.string_count as usize);
+        for index in 0..header.string_count {
+            let entry = u32_at(small_strings, 4 * index as usize);
+            let utf16 = entry & 1 == 1;
+            let mut offset = (entry >> 1) & 0x7f_ffff;
+            let mut length = entry >> 24;
+            if length == SMALL_STRING_MAX_LENGTH {
+                let overflow = 8 * offset as usize;
+                if overflow + 8 > overflow_strings.len() {
+                    return Err(format!("string {} has no overflow entry", index));
+                }
+                offset = u32_at(overflow_strings, overflow);
+                length = u32_at(overflow_strings, overflow + 4);
+            }
+            let characters = (length as usize)
+                .checked_mul(if utf16 { 2 } else { 1 })
+                .and_then(|size| {
+                    let start = offset as usize;
+                    storage.get(start..start.checked_add(size)?)
+                })
+                .ok_or_else(|| format!("string {} exceeds the string storage", index))?;
+            let content = if utf16 {
+                String::from_utf16_lossy(
+                    &characters
+                        .chunks_exact(2)
+                        .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+                        .collect::<Vec<_>>(),
+                )
+            } else {
+                characters.iter().map(|b| char::from(*b)).collect()
+            };
+            strings.push(HermesString {
+                index,
+                content,
+                identifier: identifiers.get(index as usize).copied().unwrap_or(false),
+            });
+        }
+
+        let mut functions = Vec::with_capacity(header.function_count as usize);
+        for index in 0..header.function_count {
+            let small = &function_headers[SMALL_FUNCTION_HEADER_SIZE * index as usize..];
+            let mut function = if small[15] & FLAG_OVERFLOWED != 0 {
+                // the offset of the large header is split over `offset` and `infoOffset`
+                let offset = ((u32_at(small, 8) & 0x1ff_ffff) as usize) << 16
+                    | (u32_at(small, 0) & 0x1ff_ffff) as usize;
+                let large = data
+                    .get(offset..offset + LARGE_FUNCTION_HEADER_SIZE)
+                    .ok_or_else(|| format!("function {} has no large header", index))?;
+                Self::parse_large_function_header(large, index)
+            } else {
+                Self::parse_small_function_header(small, index)
+            };
+            function.name = strings
+                .get(function.name_index as usize)
+                .map(|name| name.content.clone())
+                .unwrap_or_default();
+            functions.push(function);
+        }
+
+        Ok(HermesBytecode {
+            header,
+            functions,
+            strings,
+        })
+    }
+
+    fn parse_header(data: &[u8]) -> Result<HermesHeader, String> {
+        let mut reader = Reader { data, position: 8 };
+        let version = reader.u32()?;
+        let source_hash = reader
+            .bytes(20)?
+            .iter()
+            .map(|b| format!("{:02x}", b))
+            .collect();
+        let mut header = HermesHeader {
+            version,
+            source_hash,
+            ..Default::default()
+        };
+        for field in [
+            &mut header.file_length,
+            &mut header.global_code_index,
+            &mut header.function_count,
+            &mut header.string_kind_count,
+            &mut header.identifier_count,
+            &mut header.string_count,
+            &mut header.overflow_string_count,
+            &mut header.string_storage_size,
+        ] {
+            *field = reader.u32()?;
+        }
+        if version >= 87 {
+            header.big_int_count = reader.u32()?;
+            header.big_int_storage_size = reader.u32()?;
+        }
+        for field in [
+            &mut header.reg_exp_count,
+            &mut header.reg_exp_storage_size,
+            &mut header.array_buffer_size,
+            &mut header.obj_key_buffer_size,
+            &mut header.obj_value_buffer_size,
+            &mut header.segment_id,
+            &mut header.cjs_module_count,
+        ] {
+            *field = reader.u32()?;
+        }
+        if version >= 84 {
+            header.function_source_count = reader.u32()?;
+        }
+        header.debug_info_offset = reader.u32()?;
+        header.options = reader.u8()?;
+        Ok(header)
+    }
+
+    /// The string kinds are run length encoded, the highest bit marks identifiers
+    fn identifier_flags(string_kinds: &[u8], string_count: u32) -> Vec<bool> {
+        let string_count = string_count as usize;
+        let mut identifiers = Vec::with_capacity(string_count);
+        for entry in string_kinds.chunks_exact(4) {
+            if identifiers.len() >= string_count {
+                break;
+            }
+            let entry = u32::from_le_bytes(entry.try_into().unwrap());
+            let count = ((entry & 0x7fff_ffff) as usize).min(string_count - identifiers.len());
+            identifiers.extend(std::iter::repeat(entry >> 31 == 1).take(count));
+        }
+        identifiers
+    }
+
+    /// The small header packs the fields into bit fields
+    fn parse_small_function_header(header: &[u8], index: u32) -> HermesFunction {
+        let first = u32_at(header, 0);
+        let second = u32_at(header, 4);
+        HermesFunction {
+            index,
+            name_index: second >> 15,
+            name: String::new(),
+            offset: first & 0x1ff_ffff,
+            bytecode_size: second & 0x7fff,
+            param_count: first >> 25,
+            frame_size: u32_at(header, 8) >> 25,
+            environment_size: header[12],
+            flags: header[15],
+        }
+    }
+
+    fn parse_large_function_header(header: &[u8], index: u32) -> HermesFunction {
+        HermesFunction {
+            index,
+            name_index: u32_at(header, 12),
+            name: String::new(),
+            offset: u32_at(header, 0),
+            bytecode_size: u32_at(header, 8),
+            param_count: u32_at(header, 4),
+            frame_size: u32_at(header, 20),
+            environment_size: header[24],
+            flags: header[27],
+        }
+    }
+
+    pub fn string(&self, index: u32) -> Option<&str> {
+        self.strings
+            .get(index as usize)
+            .map(|string| string.content.as_str())
+    }
+
+    pub fn function(&self, index: u32) -> Option<&HermesFunction> {
+        self.functions.get(index as usize)
+    }
+
+    /// The function running the top level code of the bundle
+    pub fn global_function(&self) -> Option<&HermesFunction> {
+        self.function(self.header.global_code_index)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FUNCTION_HEADERS: usize = HEADER_SIZE;
+    const SMALL_STRINGS: usize = 172;
+    const OVERFLOW_STRINGS: usize = 188;
+    const LARGE_HEADER: usize = 508;
+    const CODE: usize = 536;
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A bundle with an identifier and three string literals, the last one in the overflow table,
+    /// and two functions, the second one with a large header
+    fn bundle(version: u32) -> Vec<u8> {
+        let mut data = vec![0u8; CODE + 4];
+        put(&mut data, 0, &HERMES_MAGIC.to_le_bytes());
+        put(&mut data, 8, &version.to_le_bytes());
+        put(&mut data, 12, &[0xab; 20]);
+        let counts = [data.len() as u32, 0, 2, 2, 1, 4, 1, 311];
+        for (i, count) in counts.iter().enumerate() {
+            put(&mut data, 32 + 4 * i, &count.to_le_bytes());
+        }
+
+        let small = FUNCTION_HEADERS;
+        put(&mut data, small, &(CODE as u32 | 2 << 25).to_le_bytes());
+        put(&mut data, small + 4, &(2u32 | 1 << 15).to_le_bytes());
+        put(&mut data, small + 8, &(5u32 << 25).to_le_bytes());
+        put(&mut data, small + 12, &[1, 0, 0, FLAG_STRICT_MODE]);
+        let overflowed = FUNCTION_HEADERS + SMALL_FUNCTION_HEADER_SIZE;
+        put(&mut data, overflowed, &(LARGE_HEADER as u32).to_le_bytes());
+        put(&mut data, overflowed + 15, &[FLAG_OVERFLOWED]);
+        for (i, value) in [CODE as u32 + 2, 3, 2, 0, 0, 7].iter().enumerate() {
+            put(&mut data, LARGE_HEADER + 4 * i, &value.to_le_bytes());
+        }
+        put(&mut data, LARGE_HEADER + 24, &[4, 0, 0, FLAG_DEBUG_INFO]);
+
+        put(&mut data, 160, &0x8000_0001u32.to_le_bytes());
+        put(&mut data, 164, &3u32.to_le_bytes());
+        for (i, entry) in [
+            4u32 << 24,
+            5 << 24 | 4 << 1,
+            1 << 24 | 9 << 1 | 1,
+            0xff << 24,
+        ]
+        .iter()
+        .enumerate()
+        {
+            put(&mut data, SMALL_STRINGS + 4 * i, &entry.to_le_bytes());
+        }
+        put(&mut data, OVERFLOW_STRINGS, &11u32.to_le_bytes());
+        put(&mut data, OVERFLOW_STRINGS + 4, &300u32.to_le_bytes());
+        put(&mut data, 196, b"mainhello\xac\x20");
+        put(&mut data, 207, &[b'x'; 300]);
+        put(&mut data, CODE, &[0x5c, 0x00, 0x5c, 0x01]);
+        data
+    }
+
+    #[test]
+    fn parses_bundles() {
+        let bytecode = HermesBytecode::parse(&bundle(96)).unwrap();
+        assert_eq!(bytecode.header.version, 96);
+        assert_eq!(bytecode.header.source_hash, "ab".repeat(20));
+        assert_eq!(bytecode.header.string_storage_size, 311);
+
+        let strings: Vec<(&str, bool)> = bytecode
+            .strings
+            .iter()
+            .map(|string| (string.content.as_str(), string.identifier))
+            .collect();
+        let long = "x".repeat(300);
+        assert_eq!(
+            strings,
+            vec![
+                ("main", true),
+                ("hello", false),
+                ("€", false),
+                (long.as_str(), false)
+            ]
+        );
+
+        let main = bytecode.global_function().unwrap();
+        assert_eq!(main.name, "hello");
+        assert_eq!(
+            (main.offset, main.bytecode_size, main.param_count),
+            (CODE as u32, 2, 2)
+        );
+        assert_eq!((main.frame_size, main.environment_size), (5, 1));
+        assert!(main.is_strict() && !main.has_debug_info());
+        let large = bytecode.function(1).unwrap();
+        assert_eq!(large.name, "main");
+        assert_eq!(
+            (large.offset, large.bytecode_size, large.param_count),
+            (CODE as u32 + 2, 2, 3)
+        );
+        assert_eq!((large.frame_size, large.environment_size), (7, 4));
+        assert!(large.has_debug_info() && !large.is_strict());
+        assert!(bytecode.function(2).is_none());
+    }
+
+    #[test]
+    fn parses_headers_of_older_versions() {
+        // without the big int and function source counts the options move forward
+        let mut data = bundle(84);
+        put(&mut data, 100, &[0x12]);
+        assert_eq!(HermesBytecode::parse(&data).unwrap().header.options, 0x12);
+        let mut data = bundle(76);
+        put(&mut data, 96, &[0x34]);
+        assert_eq!(HermesBytecode::parse(&data).unwrap().header.options, 0x34);
+        assert!(HermesBytecode::parse(&bundle(MIN_HERMES_VERSION - 1)).is_err());
+    }
+
+    #[test]
+    fn rejects_truncated_bundles() {
+        let data = bundle(96);
+        assert!(!HermesBytecode::is_hermes_bytecode(&data[..7]));
+        let mut delta = data.clone();
+        put(&mut delta, 0, &(!HERMES_MAGIC).to_le_bytes());
+        assert!(HermesBytecode::parse(&delta).is_err());
+        for length in 0..CODE {
+            assert!(HermesBytecode::parse(&data[..length]).is_err());
+        }
+        // the bytecode itself is only read when disassembling
+        assert!(HermesBytecode::parse(&data[..CODE]).is_ok());
+    }
+
+    #[test]
+    fn rejects_strings_beyond_the_storage() {
+        let mut data = bundle(96);
+        put(
+            &mut data,
+            OVERFLOW_STRINGS + 4,
+            &0x8000_0000u32.to_le_bytes(),
+        );
+        put(
+            &mut data,
+            SMALL_STRINGS + 12,
+            &(0xffu32 << 24 | 1).to_le_bytes(),
+        );
+        assert!(HermesBytecode::parse(&data).is_err());
+
+        let mut data = bundle(96);
+        put(&mut data, OVERFLOW_STRINGS, &u32::MAX.to_le_bytes());
+        assert!(HermesBytecode::parse(&data).is_err());
+
+        // an overflow entry which does not exist
+        let mut data = bundle(96);
+        put(
+            &mut data,
+            SMALL_STRINGS + 12,
+            &(0xffu32 << 24 | 1 << 1).to_le_bytes(),
+        );
+        assert!(HermesBytecode::parse(&data).is_err());
+    }
+
+    #[test]
+    fn rejects_tables_beyond_the_file() {
+        // the counts of the function headers up to the string storage
+        for count in 2..8 {
+            let mut data = bundle(96);
+            put(&mut data, 32 + 4 * count, &u32::MAX.to_le_bytes());
+            assert!(HermesBytecode::parse(&data).is_err());
+        }
+        let mut data = bundle(96);
+        put(
+            &mut data,
+            FUNCTION_HEADERS + SMALL_FUNCTION_HEADER_SIZE + 8,
+            &0x1ff_ffffu32.to_le_bytes(),
+        );
+        assert!(HermesBytecode::parse(&data).is_err());
+    }
+
+    #[test]
+    fn identifier_runs_are_limited_to_the_strings() {
+        let kinds: Vec<u8> = [0xffff_ffffu32, 0x7fff_ffff]
+            .iter()
+            .flat_map(|entry| entry.to_le_bytes())
+            .collect();
+        assert_eq!(HermesBytecode::identifier_flags(&kinds, 3), vec![true; 3]);
+        assert!(HermesBytecode::identifier_flags(&kinds, 0).is_empty());
+        let kinds: Vec<u8> = [0x8000_0001u32, 0x7fff_ffff]
+            .iter()
+            .flat_map(|entry| entry.to_le_bytes())
+            .collect();
+        assert_eq!(
+            HermesBytecode::identifier_flags(&kinds, 3),
+            vec![true, false, false]
+        );
+        assert!(HermesBytecode::identifier_flags(&[1, 0, 0], 3).is_empty());
+    }
+}
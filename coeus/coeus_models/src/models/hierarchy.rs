@@ -0,0 +1,514 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The type hierarchy of all classes of a `MultiDexFile`, optionally completed by a
+//! `FrameworkStub`. Types can be given as descriptors (`Landroid/app/Activity;`) or as java
+//! names (`android.app.Activity`).
+
+use std::{
+    borrow::Cow,
+    collections::{HashMap, HashSet, VecDeque},
+    sync::Arc,
+};
+
+use super::{AccessFlags, ApiDatabase, Class, DexFile, FrameworkStub, MultiDexFile};
+
+const NO_INDEX: u32 = 0xffffffff;
+
+#[derive(Debug, Clone)]
+pub enum ClassOrigin {
+    App(Arc<DexFile>, Arc<Class>),
+    Framework,
+    /// Referenced, but neither defined by the app nor by the framework stub
+    Unknown,
+}
+
+#[derive(Debug, Clone)]
+pub struct HierarchyClass {
+    pub name: String,
+    id: usize,
+    pub is_interface: bool,
+    pub origin: ClassOrigin,
+    super_class: Option<usize>,
+    interfaces: Vec<usize>,
+    /// Overridable methods declared by this type, as name and prototype
+    methods: HashSet<String>,
+}
+
+impl HierarchyClass {
+    pub fn is_app(&self) -> bool {
+        matches!(self.origin, ClassOrigin::App(..))
+    }
+
+    pub fn is_unknown(&self) -> bool {
+        matches!(self.origin, ClassOrigin::Unknown)
+    }
+
+    /// Whether this type declares the method, given as name and prototype (e.g.
+    /// `onCreate(Landroid/os/Bundle;)V`). Only methods which can be overridden are considered.
+    pub fn declares_method(&self, method: &str) -> bool {
+        self.methods.contains(method)
+    }
+
+    pub fn methods(&self) -> impl Iterator<Item = &str> {
+        self.methods.iter().map(|m| m.as_str())
+    }
+}
+
+pub struct ClassHierarchy {
+    classes: Vec<HierarchyClass>,
+    ids: HashMap<String, usize>,
+    /// direct subclasses of each class
+    subclasses: Vec<Vec<usize>>,
+    /// classes and interfaces directly listing each interface
+    implementors: Vec<Vec<usize>>,
+}
+
+impl ClassHierarchy {
+    /// Build the hierarchy of all classes of the app. App classes take precedence over
+    /// framework classes with the same name.
+    pub fn new(multi_dex: &MultiDexFile, framework: Option<&FrameworkStub>) -> Self {
+        let mut hierarchy = Self {
+            classes: vec![],
+            ids: HashMap::new(),
+            subclasses: vec![],
+            implementors: vec![],
+        };
+        // supertypes are resolved after all types are known
+        let mut pending: HashMap<usize, (Option<String>, Vec<String>)> = HashMap::new();
+
+        for dex in multi_dex.dex_files() {
+            for class in &dex.classes {
+                let id = match hierarchy.ids.get(&class.class_name) {
+                    Some(&id) => match &hierarchy.classes[id].origin {
+                        ClassOrigin::App(_, existing)
+                            if existing.class_data.is_some() || class.class_data.is_none() =>
+                        {
+                            continue;
+                        }
+                        _ => id,
+                    },
+                    None => hierarchy.add(&class.class_name, ClassOrigin::Unknown),
+                };
+                let super_class = if class.super_class == NO_INDEX {
+                    None
+                } else {
+                    dex.get_type_name(class.super_class as usize)
+                        .map(|name| name.to_string())
+                };
+                let interfaces = class
+                    .interfaces
+                    .iter()
+                    .filter_map(|&idx| dex.get_type_name(idx).map(|name| name.to_string()))
+                    .collect();
+                let methods = class
+                    .class_data
+                    .iter()
+                    .flat_map(|class_data| class_data.virtual_methods.iter())
+                    .filter_map(|encoded| dex.methods.get(encoded.method_idx as usize))
+                    .map(|method| format!("{}{}", method.method_name, method.proto_name))
+                    .collect();
+
+                let entry = &mut hierarchy.classes[id];
+                entry.is_interface = class.access_flags.contains(AccessFlags::INTERFACE);
+                entry.origin = ClassOrigin::App(dex.clone(), class.clone());
+                entry.methods = methods;
+                pending.insert(id, (super_class, interfaces));
+            }
+        }
+
+        if let Some(framework) = framework {
+            for class in framework.classes() {
+                if hierarchy.ids.contains_key(&class.name) {
+                    continue;
+                }
+                let id = hierarchy.add(&class.name, ClassOrigin::Framework);
+                let entry = &mut hierarchy.classes[id];
+                entry.is_interface = class.is_interface;
+                entry.methods = class.methods.iter().cloned().collect();
+                pending.insert(id, (class.super_class.clone(), class.interfaces.clone()));
+            }
+        }
+
+        let mut pending: Vec<_> = pending.into_iter().collect();
+        pending.sort_unstable_by_key(|(id, _)| *id);
+        for (id, (super_class, interfaces)) in pending {
+            if let Some(super_class) = super_class {
+                let super_id = hierarchy.id_or_unknown(&super_class);
+                hierarchy.classes[id].super_class = Some(super_id);
+                hierarchy.subclasses[super_id].push(id);
+            }
+            for interface in interfaces {
+                let interface_id = hierarchy.id_or_unknown(&interface);
+                hierarchy.classes[id].interfaces.push(interface_id);
+                hierarchy.implementors[interface_id].push(id);
+            }
+        }
+        hierarchy
+    }
+
+    /// Build the hierarchy completed by the global api database at the target sdk version of
+    /// the app
+    pub fn with_framework(multi_dex: &MultiDexFile) -> Self {
+        let api = ApiDatabase::global();
+        let api_level = multi_dex
+            .android_manifest
+            .target_sdk_version()
+            .unwrap_or(api.api_level);
+        Self::new(multi_dex, Some(&api.stub(api_level)))
+    }
+
+    fn add(&mut self, name: &str, origin: ClassOrigin) -> usize {
+        let id = self.classes.len();
+        self.classes.push(HierarchyClass {
+            name: name.to_string(),
+            id,
+            is_interface: false,
+            origin,
+            super_class: None,
+            interfaces: vec![],
+            methods: HashSet::new(),
+        });
+        self.subclasses.push(vec![]);
+        self.implementors.push(vec![]);
+        self.ids.insert(name.to_string(), id);
+        id
+    }
+
+    fn id_or_unknown(&mut self, name: &str) -> usize {
+        match self.ids.get(name) {
+            Some(&id) => id,
+            None => self.add(name, ClassOrigin::Unknown),
+        }
+    }
+
+    fn id(&self, name: &str) -> Option<usize> {
+        self.ids.get(to_descriptor(name).as_ref()).copied()
+    }
+
+    pub fn get(&self, name: &str) -> Option<&HierarchyClass> {
+        self.id(name).map(|id| &self.classes[id])
+    }
+
+    pub fn classes(&self) -> impl Iterator<Item = &HierarchyClass> {
+        self.classes.iter()
+    }
+
+    pub fn super_class(&self, name: &str) -> Option<&HierarchyClass> {
+        let id = self.classes[self.id(name)?].super_class?;
+        Some(&self.classes[id])
+    }
+
+    /// All superclasses, starting with the direct one
+    pub fn superclasses(&self, name: &str) -> Vec<&HierarchyClass> {
+        let Some(start) = self.id(name) else {
+            return vec![];
+        };
+        let mut superclasses = vec![];
+        // guard against cycles in malformed inputs
+        let mut visited = HashSet::from([start]);
+        let mut current = self.classes[start].super_class;
+        while let Some(id) = current {
+            if !visited.insert(id) {
+                break;
+            }
+            superclasses.push(&self.classes[id]);
+            current = self.classes[id].super_class;
+        }
+        superclasses
+    }
+
+    /// All superclasses and implemented interfaces, nearest first
+    pub fn supertypes(&self, name: &str) -> Vec<&HierarchyClass> {
+        let Some(start) = self.id(name) else {
+            return vec![];
+        };
+        self.traverse(start, |class| {
+            class
+                .super_class
+                .into_iter()
+                .chain(class.interfaces.iter().copied())
+        })
+    }
+
+    /// All interfaces implemented directly, by a superclass or by extending another interface
+    pub fn interfaces(&self, name: &str) -> Vec<&HierarchyClass> {
+        self.supertypes(name)
+            .into_iter()
+            .filter(|class| class.is_interface)
+            .collect()
+    }
+
+    /// All classes extending this class, directly or transitively
+    pub fn subclasses(&self, name: &str) -> Vec<&HierarchyClass> {
+        let Some(start) = self.id(name) else {
+            return vec![];
+        };
+        self.traverse(start, |class| self.subclasses[class.id].iter().copied())
+    }
+
+    /// All subtypes, i.e. subclasses, implementing classes and extending interfaces
+    pub fn subtypes(&self, name: &str) -> Vec<&HierarchyClass> {
+        let Some(start) = self.id(name) else {
+            return vec![];
+        };
+        self.traverse(start, |class| {
+            self.subclasses[class.id]
+                .iter()
+                .chain(self.implementors[class.id].iter())
+                .copied()
+        })
+    }
+
+    /// All classes implementing this interface, directly or transitively
+    pub fn implementors(&self, interface: &str) -> Vec<&HierarchyClass> {
+        self.subtypes(interface)
+            .into_iter()
+            .filter(|class| !class.is_interface)
+            .collect()
+    }
+
+    /// Whether `name` extends or implements `supertype`. Every type is a subtype of itself.
+    pub fn is_subtype_of(&self, name: &str, supertype: &str) -> bool {
+        let (Some(id), Some(super_id)) = (self.id(name), self.id(supertype)) else {
+            return false;
+        };
+        id == super_id
+            || self
+                .supertypes(name)
+                .iter()
+                .any(|class| class.id == super_id)
+    }
+
+    /// The supertypes declaring a method which `method` (name and prototype) of `class`
+    /// overrides or implements, nearest first
+    pub fn overridden_methods(&self, class: &str, method: &str) -> Vec<&HierarchyClass> {
+        self.supertypes(class)
+            .into_iter()
+            .filter(|class| class.declares_method(method))
+            .collect()
+    }
+
+    /// The subtypes overriding or implementing `method` (name and prototype) of `class`
+    pub fn overriding_methods(&self, class: &str, method: &str) -> Vec<&HierarchyClass> {
+        self.subtypes(class)
+            .into_iter()
+            .filter(|class| class.declares_method(method))
+            .collect()
+    }
+
+    /// Breadth first traversal, excluding `start`
+    fn traverse<'a, F, I>(&'a self, start: usize, next: F) -> Vec<&'a HierarchyClass>
+    where
+        F: Fn(&'a HierarchyClass) -> I,
+        I: Iterator<Item = usize>,
+    {
+        let mut visited = HashSet::from([start]);
+        let mut queue = VecDeque::from([start]);
+        let mut result = vec![];
+        while let Some(id) = queue.pop_front() {
+            for next_id in next(&self.classes[id]) {
+                if visited.insert(next_id) {
+                    result.push(&self.classes[next_id]);
+                    queue.push_back(next_id);
+                }
+            }
+        }
+        result
+    }
+}
+
+/// Convert java names to descriptors, descriptors are returned as they are
+fn to_descriptor(name: &str) -> Cow<'_, str> {
+    if name.starts_with('[') || (name.starts_with('L') && name.ends_with(';')) {
+        Cow::Borrowed(name)
+    } else {
+        Cow::Owned(format!("L{};", name.replace('.', "/")))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{testing::DexBuilder, AndroidManifest};
+    use super::*;
+
+    const FRAMEWORK: &str = "
+class Ljava/lang/Object; 1
+    method toString()Ljava/lang/String;
+interface Ljava/lang/Runnable; 1
+    method run()V
+interface Landroid/os/Parcelable; 1
+class Landroid/app/Activity; 1 Ljava/lang/Object;
+    method onCreate(Landroid/os/Bundle;)V
+interface Landroid/view/View$OnClickListener; 1
+    method onClick(Landroid/view/View;)V
+";
+
+    fn hierarchy() -> ClassHierarchy {
+        let mut dex = DexBuilder::new("hierarchy");
+        let base = dex.class(
+            "La/Base;",
+            Some("Landroid/app/Activity;"),
+            &["La/Listener;"],
+        );
+        dex.interface("La/Listener;", &["Ljava/lang/Runnable;"]);
+        let main = dex.class(
+            "La/Main;",
+            Some("La/Base;"),
+            &["Landroid/view/View$OnClickListener;"],
+        );
+        for (pos, class) in [(base, "La/Base;"), (main, "La/Main;")] {
+            let on_create = dex.method(class, "onCreate", "V", &["Landroid/os/Bundle;"]);
+            dex.virtual_method(pos, on_create, &[0x000e]);
+        }
+        dex.class("La/Orphan;", Some("Lmissing/Parent;"), &[]);
+        dex.class("La/CycleA;", Some("La/CycleB;"), &[]);
+        dex.class("La/CycleB;", Some("La/CycleA;"), &[]);
+        // a library bundled by the app shadows the framework type
+        dex.interface("Landroid/os/Parcelable;", &[]);
+
+        let multi_dex = MultiDexFile::new(
+            AndroidManifest::default(),
+            String::new(),
+            dex.build(),
+            vec![],
+        );
+        let framework = FrameworkStub::parse(FRAMEWORK, 34).unwrap();
+        ClassHierarchy::new(&multi_dex, Some(&framework))
+    }
+
+    fn names(classes: Vec<&HierarchyClass>) -> Vec<&str> {
+        classes
+            .into_iter()
+            .map(|class| class.name.as_str())
+            .collect()
+    }
+
+    #[test]
+    fn supertypes_are_ordered_nearest_first() {
+        let hierarchy = hierarchy();
+        assert_eq!(
+            names(hierarchy.supertypes("La/Main;")),
+            [
+                "La/Base;",
+                "Landroid/view/View$OnClickListener;",
+                "Landroid/app/Activity;",
+                "La/Listener;",
+                "Ljava/lang/Object;",
+                "Ljava/lang/Runnable;",
+            ]
+        );
+        assert_eq!(
+            names(hierarchy.superclasses("a.Main")),
+            ["La/Base;", "Landroid/app/Activity;", "Ljava/lang/Object;"]
+        );
+        assert_eq!(
+            hierarchy
+                .super_class("La/Main;")
+                .map(|class| class.name.as_str()),
+            Some("La/Base;")
+        );
+        assert!(hierarchy.super_class("java.lang.Object").is_none());
+        assert!(hierarchy.supertypes("La/Missing;").is_empty());
+    }
+
+    #[test]
+    fn interfaces_are_inherited() {
+        let hierarchy = hierarchy();
+        assert_eq!(
+            names(hierarchy.interfaces("La/Main;")),
+            [
+                "Landroid/view/View$OnClickListener;",
+                "La/Listener;",
+                "Ljava/lang/Runnable;",
+            ]
+        );
+        assert_eq!(
+            names(hierarchy.interfaces("La/Listener;")),
+            ["Ljava/lang/Runnable;"]
+        );
+        assert_eq!(
+            names(hierarchy.implementors("java.lang.Runnable")),
+            ["La/Base;", "La/Main;"]
+        );
+        assert_eq!(
+            names(hierarchy.subtypes("java.lang.Runnable")),
+            ["La/Listener;", "La/Base;", "La/Main;"]
+        );
+        assert!(hierarchy.is_subtype_of("La/Main;", "java.lang.Runnable"));
+        assert!(hierarchy.is_subtype_of("La/Main;", "La/Main;"));
+        assert!(!hierarchy.is_subtype_of("java.lang.Runnable", "La/Main;"));
+        assert!(!hierarchy.is_subtype_of("La/Orphan;", "java.lang.Object"));
+    }
+
+    #[test]
+    fn methods_are_attributed_to_their_declaring_types() {
+        let hierarchy = hierarchy();
+        let on_create = "onCreate(Landroid/os/Bundle;)V";
+        assert_eq!(
+            names(hierarchy.overridden_methods("La/Main;", on_create)),
+            ["La/Base;", "Landroid/app/Activity;"]
+        );
+        assert_eq!(
+            names(hierarchy.overriding_methods("android.app.Activity", on_create)),
+            ["La/Base;", "La/Main;"]
+        );
+        assert_eq!(
+            names(hierarchy.overridden_methods("La/Main;", "onClick(Landroid/view/View;)V")),
+            ["Landroid/view/View$OnClickListener;"]
+        );
+    }
+
+    #[test]
+    fn classes_are_classified_by_origin() {
+        let hierarchy = hierarchy();
+        let main = hierarchy.get("a.Main").unwrap();
+        assert!(main.is_app() && !main.is_interface);
+        assert!(hierarchy.get("La/Listener;").unwrap().is_interface);
+
+        let activity = hierarchy.get("android.app.Activity").unwrap();
+        assert!(matches!(activity.origin, ClassOrigin::Framework));
+        assert!(hierarchy.get("java.lang.Runnable").unwrap().is_interface);
+
+        // app classes take precedence over framework classes
+        let parcelable = hierarchy.get("android.os.Parcelable").unwrap();
+        assert!(parcelable.is_app() && parcelable.is_interface);
+        assert_eq!(
+            hierarchy
+                .classes()
+                .filter(|class| class.name == "Landroid/os/Parcelable;")
+                .count(),
+            1
+        );
+        assert!(hierarchy.get("La/Missing;").is_none());
+    }
+
+    #[test]
+    fn missing_superclasses_are_unknown() {
+        let hierarchy = hierarchy();
+        let parent = hierarchy.super_class("La/Orphan;").unwrap();
+        assert_eq!(parent.name, "Lmissing/Parent;");
+        assert!(parent.is_unknown());
+        assert_eq!(
+            names(hierarchy.supertypes("La/Orphan;")),
+            ["Lmissing/Parent;"]
+        );
+        assert_eq!(
+            names(hierarchy.subclasses("missing.Parent")),
+            ["La/Orphan;"]
+        );
+    }
+
+    #[test]
+    fn cycles_terminate() {
+        let hierarchy = hierarchy();
+        assert_eq!(names(hierarchy.superclasses("La/CycleA;")), ["La/CycleB;"]);
+        assert_eq!(names(hierarchy.supertypes("La/CycleA;")), ["La/CycleB;"]);
+        assert_eq!(names(hierarchy.subtypes("La/CycleA;")), ["La/CycleB;"]);
+        assert!(hierarchy.is_subtype_of("La/CycleA;", "La/CycleB;"));
+        assert!(hierarchy.is_subtype_of("La/CycleB;", "La/CycleA;"));
+    }
+}
@@ -0,0 +1,603 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Unity games built with IL2CPP translate their assemblies to native code (`libil2cpp.so`) and
+//! keep the metadata needed for reflection in `global-metadata.dat`. The file starts with a header
+//! of (offset, size) pairs locating its sections. The layout of the definitions changes with the
+//! metadata version, Unity 2019 introduced sub versions of version 24 which are only told apart
+//! by the header size.
+
+use std::convert::TryInto;
+
+const METADATA_MAGIC: u32 = 0xfab1_1baf;
+pub const MIN_IL2CPP_VERSION: u32 = 24;
+pub const MAX_IL2CPP_VERSION: u32 = 31;
+
+// indices of the sections in the header
+const STRING_LITERALS: usize = 0;
+const STRING_LITERAL_DATA: usize = 1;
+const STRINGS: usize = 2;
+const METHODS: usize = 5;
+const NESTED_TYPES: usize = 15;
+const TYPE_DEFINITIONS: usize = 19;
+/// Directly follows the type definitions since 24.2, which removed `rgctxEntries`
+const IMAGES: usize = 20;
+/// Number of sections of versions 24.0 and 24.1
+const RGCTX_SECTION_COUNT: usize = 33;
+
+const STRING_LITERAL_SIZE: usize = 8;
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Il2CppImage {
+    pub index: u32,
+    /// Name of the assembly, e.g. `Assembly-CSharp.dll`
+    pub name: String,
+    pub type_start: u32,
+    pub type_count: u32,
+    pub token: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Il2CppTypeDefinition {
+    pub index: u32,
+    pub namespace: String,
+    pub name: String,
+    /// Namespace and name, nested types are appended to their enclosing type with `+`
+    pub full_name: String,
+    pub image: u32,
+    pub flags: u32,
+    pub method_start: u32,
+    pub method_count: u32,
+    /// The token in the original assembly
+    pub token: u32,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Il2CppMethodDefinition {
+    pub index: u32,
+    pub name: String,
+    pub declaring_type: u32,
+    /// The token in the original assembly, its row indexes the method pointers of the image
+    pub token: u32,
+    pub flags: u16,
+    pub parameter_count: u16,
+    /// Index into the global method pointers, only before version 24.2
+    pub method_index: Option<i32>,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Il2CppStringLiteral {
+    pub index: u32,
+    pub content: String,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct Il2CppMetadata {
+    pub version: u32,
+    /// The sub version of version 24 (0, 1 or 2 for 24.2 and later), 0 otherwise
+    pub sub_version: u32,
+    pub images: Vec<Il2CppImage>,
+    pub types: Vec<Il2CppTypeDefinition>,
+    pub methods: Vec<Il2CppMethodDefinition>,
+    pub string_literals: Vec<Il2CppStringLiteral>,
+}
+
+/// Sizes of the definitions and the offsets of their version dependent fields
+struct Layout {
+    type_size: usize,
+    method_size: usize,
+    image_size: usize,
+    /// Offset of `methodIndex` in method definitions, before 24.2
+    method_index: Option<usize>,
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn u16_at(data: &[u8], offset: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(offset..offset + 2)?.try_into().ok()?,
+    ))
+}
+
+impl Il2CppMetadata {
+    pub fn is_il2cpp_metadata(data: &[u8]) -> bool {
+        u32_at(data, 0) == Some(METADATA_MAGIC)
+    }
+
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        if !Self::is_il2cpp_metadata(data) {
+            return Err("not an IL2CPP metadata file".to_string());
+        }
+        let version = u32_at(data, 4).ok_or("truncated header")?;
+        if !(MIN_IL2CPP_VERSION..=MAX_IL2CPP_VERSION).contains(&version) {
+            return Err(format!("unsupported metadata version {}", version));
+        }
+        // the sections follow the header directly
+        let header_size = u32_at(data, 8).ok_or("truncated header")? as usize;
+        let section_count = header_size.saturating_sub(8) / 8;
+        let has_rgctx = version == 24 && section_count >= RGCTX_SECTION_COUNT;
+        let section = |index: usize| -> Result<&[u8], String> {
+            let index = if index >= IMAGES && has_rgctx {
+                index + 1
+            } else {
+                index
+            };
+            let offset = u32_at(data, 8 + 8 * index).ok_or("truncated header")? as usize;
+            let size = u32_at(data, 12 + 8 * index).ok_or("truncated header")? as usize;
+            data.get(offset..offset + size)
+                .ok_or_else(|| format!("section {} exceeds the file", index))
+        };
+        let images = section(IMAGES)?;
+        let (sub_version, layout) = match (version, has_rgctx) {
+            (24, true) => {
+                // 24.1 added the custom attributes to the images
+                let sub_version = if Self::images_fit(images, 40) { 1 } else { 0 };
+                (
+                    sub_version,
+                    Layout {
+                        type_size: 104,
+                        method_size: 56,
+                        image_size: if sub_version == 1 { 40 } else { 32 },
+                        method_index: Some(24),
+                    },
+                )
+            }
+            (24, false) => (
+                2,
+                Layout {
+                    type_size: 96,
+                    method_size: 36,
+                    image_size: 40,
+                    method_index: None,
+                },
+            ),
+            (version, _) => (
+                0,
+                Layout {
+                    type_size: 88,
+                    method_size: if version >= 31 { 36 } else { 32 },
+                    image_size: 40,
+                    method_index: None,
+                },
+            ),
+        };
+
+        let strings = section(STRINGS)?;
+        let string = |index: u32| -> String {
+            let Some(bytes) = strings.get(index as usize..) else {
+                return String::new();
+            };
+            let end = bytes.iter().position(|b| *b == 0).unwrap_or(bytes.len());
+            String::from_utf8_lossy(&bytes[..end]).to_string()
+        };
+
+        let images: Vec<Il2CppImage> = images
+            .chunks_exact(layout.image_size)
+            .enumerate()
+            .map(|(index, image)| Il2CppImage {
+                index: index as u32,
+                name: string(u32_at(image, 0).unwrap_or_default()),
+                type_start: u32_at(image, 8).unwrap_or_default(),
+                type_count: u32_at(image, 12).unwrap_or_default(),
+                token: u32_at(image, 28).unwrap_or_default(),
+            })
+            .collect();
+
+        let mut types = Self::types(section(TYPE_DEFINITIONS)?, &layout, &string);
+        for image in &images {
+            let start = (image.type_start as usize).min(types.len());
+            let end = (start + image.type_count as usize).min(types.len());
+            for ty in &mut types[start..end] {
+                ty.image = image.index;
+            }
+        }
+        Self::qualify_nested_types(
+            &mut types,
+            section(TYPE_DEFINITIONS)?,
+            section(NESTED_TYPES)?,
+            &layout,
+        );
+
+        let methods: Vec<Il2CppMethodDefinition> = section(METHODS)?
+            .chunks_exact(layout.method_size)
+            .enumerate()
+            .map(|(index, method)| {
+                let end = layout.method_size;
+                Il2CppMethodDefinition {
+                    index: index as u32,
+                    name: string(u32_at(method, 0).unwrap_or_default()),
+                    declaring_type: u32_at(method, 4).unwrap_or_default(),
+                    token: u32_at(method, end - 12).unwrap_or_default(),
+                    flags: u16_at(method, end - 8).unwrap_or_default(),
+                    parameter_count: u16_at(method, end - 2).unwrap_or_default(),
+                    method_index: layout
+                        .method_index
+                        .and_then(|offset| u32_at(method, offset))
+                        .map(|index| index as i32),
+                }
+            })
+            .collect();
+
+        let literal_data = section(STRING_LITERAL_DATA)?;
+        let string_literals = section(STRING_LITERALS)?
+            .chunks_exact(STRING_LITERAL_SIZE)
+            .enumerate()
+            .filter_map(|(index, literal)| {
+                let length = u32_at(literal, 0)? as usize;
+                let start = u32_at(literal, 4)? as usize;
+                Some(Il2CppStringLiteral {
+                    index: index as u32,
+                    content: String::from_utf8_lossy(literal_data.get(start..start + length)?)
+                        .to_string(),
+                })
+            })
+            .collect();
+
+        Ok(Il2CppMetadata {
+            version,
+            sub_version,
+            images,
+            types,
+            methods,
+            string_literals,
+        })
+    }
+
+    /// The types of consecutive images follow each other
+    fn images_fit(images: &[u8], image_size: usize) -> bool {
+        if images.is_empty() || images.len() % image_size != 0 {
+            return false;
+        }
+        let mut next_type = 0;
+        images.chunks_exact(image_size).all(|image| {
+            let (Some(start), Some(count)) = (u32_at(image, 8), u32_at(image, 12)) else {
+                return false;
+            };
+            let fits = start == next_type;
+            next_type = start.wrapping_add(count);
+            fits
+        })
+    }
+
+    fn types(
+        definitions: &[u8],
+        layout: &Layout,
+        string: &dyn Fn(u32) -> String,
+    ) -> Vec<Il2CppTypeDefinition> {
+        let end = layout.type_size;
+        definitions
+            .chunks_exact(layout.type_size)
+            .enumerate()
+            .map(|(index, ty)| {
+                let namespace = string(u32_at(ty, 4).unwrap_or_default());
+                let name = string(u32_at(ty, 0).unwrap_or_default());
+                let full_name = if namespace.is_empty() {
+                    name.clone()
+                } else {
+                    format!("{}.{}", namespace, name)
+                };
+                Il2CppTypeDefinition {
+                    index: index as u32,
+                    namespace,
+                    name,
+                    full_name,
+                    image: 0,
+                    flags: u32_at(ty, end - 60).unwrap_or_default(),
+                    method_start: u32_at(ty, end - 52).unwrap_or_default(),
+                    method_count: u16_at(ty, end - 24).unwrap_or_default() as u32,
+                    token: u32_at(ty, end - 4).unwrap_or_default(),
+                }
+            })
+            .collect()
+    }
+
+    /// Prefix nested types with their enclosing types, the enclosing types list their nested
+    /// types in the nested types section
+    fn qualify_nested_types(
+        types: &mut [Il2CppTypeDefinition],
+        definitions: &[u8],
+        nested_types: &[u8],
+        layout: &Layout,
+    ) {
+        let end = layout.type_size;
+        let mut enclosing = vec![None; types.len()];
+        for (index, ty) in definitions.chunks_exact(layout.type_size).enumerate() {
+            let start = u32_at(ty, end - 40).unwrap_or_default() as usize;
+            let count = u16_at(ty, end - 16).unwrap_or_default() as usize;
+            for nested in start..start + count {
+                if let Some(nested) = u32_at(nested_types, 4 * nested) {
+                    if let Some(slot) = enclosing.get_mut(nested as usize) {
+                        *slot = Some(index);
+                    }
+                }
+            }
+        }
+        let simple_names: Vec<String> = types.iter().map(|ty| ty.full_name.clone()).collect();
+        for (index, ty) in types.iter_mut().enumerate() {
+            let mut current = index;
+            // bounded, broken metadata could nest types in a cycle
+            for _ in 0..simple_names.len() {
+                let Some(outer) = enclosing[current] else {
+                    break;
+                };
+                ty.full_name = format!("{}+{}", simple_names[outer], ty.full_name);
+                current = outer;
+            }
+        }
+    }
+
+    pub fn methods_of(&self, ty: &Il2CppTypeDefinition) -> &[Il2CppMethodDefinition] {
+        let start = (ty.method_start as usize).min(self.methods.len());
+        let end = (start + ty.method_count as usize).min(self.methods.len());
+        &self.methods[start..end]
+    }
+
+    pub fn image_of(&self, method: &Il2CppMethodDefinition) -> Option<&Il2CppImage> {
+        let ty = self.types.get(method.declaring_type as usize)?;
+        self.images.get(ty.image as usize)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A record of `size` bytes with the given little endian fields at their offsets
+    fn record(size: usize, fields: &[(usize, u32)]) -> Vec<u8> {
+        let mut record = vec![0u8; size];
+        for (offset, value) in fields {
+            record[*offset..*offset + 4].copy_from_slice(&value.to_le_bytes());
+        }
+        record
+    }
+
+    /// A metadata file of `section_count` sections laid out in order, with the given contents
+    fn metadata(version: u32, section_count: usize, sections: &[(usize, Vec<u8>)]) -> Vec<u8> {
+        let mut data = vec![];
+        data.extend_from_slice(&METADATA_MAGIC.to_le_bytes());
+        data.extend_from_slice(&version.to_le_bytes());
+        let mut contents = vec![];
+        for index in 0..section_count {
+            let content = sections
+                .iter()
+                .find(|(section, _)| *section == index)
+                .map(|(_, content)| content.as_slice())
+                .unwrap_or_default();
+            let offset = 8 + 8 * section_count + contents.len();
+            data.extend_from_slice(&(offset as u32).to_le_bytes());
+            data.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            contents.extend_from_slice(content);
+        }
+        data.extend(contents);
+        data
+    }
+
+    const STRING_HEAP: &[u8] =
+        b"\0Assembly-CSharp.dll\0Game\0Player\0Inventory\0Update\0Add\0Other.dll\0";
+    const ASSEMBLY_CSHARP: u32 = 1;
+    const GAME: u32 = 21;
+    const PLAYER: u32 = 26;
+    const INVENTORY: u32 = 33;
+    const UPDATE: u32 = 43;
+    const ADD: u32 = 50;
+    const OTHER: u32 = 54;
+
+    /// Two images, the first with a type and its nested type, each with one method, and two
+    /// string literals, laid out for the sizes of types, methods and images of a version
+    fn sections(type_size: usize, method_size: usize, image_size: usize) -> Vec<(usize, Vec<u8>)> {
+        let ty = |name, namespace, method_start, nested_start, nested_count, token| {
+            let end = type_size;
+            let mut ty = record(
+                type_size,
+                &[
+                    (0, name),
+                    (4, namespace),
+                    (end - 60, 0x100001),
+                    (end - 52, method_start),
+                    (end - 40, nested_start),
+                    (end - 4, token),
+                ],
+            );
+            ty[end - 24] = 1;
+            ty[end - 16] = nested_count;
+            ty
+        };
+        let method = |name, declaring_type, token| {
+            let end = method_size;
+            let mut method = record(
+                method_size,
+                &[(0, name), (4, declaring_type), (end - 12, token)],
+            );
+            method[end - 8] = 0x86;
+            method[end - 2] = 1;
+            method
+        };
+        let image = |name, type_start, type_count, token| {
+            record(
+                image_size,
+                &[(0, name), (8, type_start), (12, type_count), (28, token)],
+            )
+        };
+        vec![
+            (
+                STRING_LITERALS,
+                [record(8, &[(0, 5), (4, 0)]), record(8, &[(0, 3), (4, 5)])].concat(),
+            ),
+            (STRING_LITERAL_DATA, b"helloabc".to_vec()),
+            (STRINGS, STRING_HEAP.to_vec()),
+            (
+                METHODS,
+                [method(UPDATE, 0, 0x0600_0001), method(ADD, 1, 0x0600_0002)].concat(),
+            ),
+            (NESTED_TYPES, 1u32.to_le_bytes().to_vec()),
+            (
+                TYPE_DEFINITIONS,
+                [
+                    ty(PLAYER, GAME, 0, 0, 1, 0x0200_0002),
+                    ty(INVENTORY, 0, 1, 0, 0, 0x0200_0003),
+                    ty(PLAYER, 0, 2, 0, 0, 0x0200_0002),
+                ]
+                .concat(),
+            ),
+            (
+                IMAGES,
+                [image(ASSEMBLY_CSHARP, 0, 2, 1), image(OTHER, 2, 1, 2)].concat(),
+            ),
+        ]
+    }
+
+    #[test]
+    fn parses_metadata() {
+        let data = metadata(27, 32, &sections(88, 32, 40));
+        assert!(Il2CppMetadata::is_il2cpp_metadata(&data));
+        let metadata = Il2CppMetadata::parse(&data).unwrap();
+        assert_eq!((metadata.version, metadata.sub_version), (27, 0));
+
+        let images: Vec<(&str, u32, u32)> = metadata
+            .images
+            .iter()
+            .map(|image| (image.name.as_str(), image.type_start, image.type_count))
+            .collect();
+        assert_eq!(
+            images,
+            vec![("Assembly-CSharp.dll", 0, 2), ("Other.dll", 2, 1)]
+        );
+
+        let types: Vec<(&str, u32)> = metadata
+            .types
+            .iter()
+            .map(|ty| (ty.full_name.as_str(), ty.image))
+            .collect();
+        assert_eq!(
+            types,
+            vec![
+                ("Game.Player", 0),
+                ("Game.Player+Inventory", 0),
+                ("Player", 1)
+            ]
+        );
+        let player = &metadata.types[0];
+        assert_eq!(
+            (player.namespace.as_str(), player.name.as_str()),
+            ("Game", "Player")
+        );
+        assert_eq!((player.flags, player.token), (0x100001, 0x0200_0002));
+
+        let update = &metadata.methods_of(player)[0];
+        assert_eq!(
+            (
+                update.name.as_str(),
+                update.token,
+                update.flags,
+                update.parameter_count
+            ),
+            ("Update", 0x0600_0001, 0x86, 1)
+        );
+        assert_eq!(update.method_index, None);
+        assert_eq!(
+            metadata.image_of(update).map(|image| image.name.as_str()),
+            Some("Assembly-CSharp.dll")
+        );
+        assert_eq!(metadata.methods_of(&metadata.types[1])[0].name, "Add");
+        assert!(metadata.methods_of(&metadata.types[2]).is_empty());
+
+        let literals: Vec<&str> = metadata
+            .string_literals
+            .iter()
+            .map(|literal| literal.content.as_str())
+            .collect();
+        assert_eq!(literals, vec!["hello", "abc"]);
+    }
+
+    #[test]
+    fn parses_method_definitions_of_version_31() {
+        let metadata = Il2CppMetadata::parse(&metadata(31, 32, &sections(88, 36, 40))).unwrap();
+        assert_eq!(metadata.methods.len(), 2);
+        assert_eq!(metadata.methods[1].token, 0x0600_0002);
+        assert_eq!(metadata.methods[1].declaring_type, 1);
+    }
+
+    #[test]
+    fn tells_sub_versions_of_version_24_apart() {
+        // 24.0 and 24.1 keep `rgctxEntries` in front of the images
+        let shifted = |image_size| -> Vec<(usize, Vec<u8>)> {
+            sections(104, 56, image_size)
+                .into_iter()
+                .map(|(index, content)| {
+                    if index >= IMAGES {
+                        (index + 1, content)
+                    } else {
+                        (index, content)
+                    }
+                })
+                .collect()
+        };
+        let version_24_0 = Il2CppMetadata::parse(&metadata(24, 33, &shifted(32))).unwrap();
+        assert_eq!(version_24_0.sub_version, 0);
+        assert_eq!(version_24_0.images.len(), 2);
+        let version_24_1 = Il2CppMetadata::parse(&metadata(24, 33, &shifted(40))).unwrap();
+        assert_eq!(version_24_1.sub_version, 1);
+        assert_eq!(version_24_1.types[1].full_name, "Game.Player+Inventory");
+        assert_eq!(version_24_1.methods[0].method_index, Some(0));
+
+        let version_24_2 = Il2CppMetadata::parse(&metadata(24, 32, &sections(96, 36, 40))).unwrap();
+        assert_eq!(version_24_2.sub_version, 2);
+        assert_eq!(version_24_2.methods[0].method_index, None);
+        assert_eq!(version_24_2.methods[1].name, "Add");
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(Il2CppMetadata::parse(b"").is_err());
+        assert!(Il2CppMetadata::parse(&METADATA_MAGIC.to_le_bytes()).is_err());
+        assert!(Il2CppMetadata::parse(&metadata(23, 32, &[])).is_err());
+        assert!(Il2CppMetadata::parse(&metadata(MAX_IL2CPP_VERSION + 1, 32, &[])).is_err());
+        assert!(Il2CppMetadata::parse(&metadata(27, 32, &[])).is_ok());
+
+        let data = metadata(27, 32, &sections(88, 32, 40));
+        for length in 0..data.len() {
+            assert!(
+                Il2CppMetadata::parse(&data[..length]).is_err(),
+                "{}",
+                length
+            );
+        }
+        // a section beyond the end of the file
+        let mut data = data;
+        let size = 12 + 8 * METHODS;
+        data[size..size + 4].copy_from_slice(&u32::MAX.to_le_bytes());
+        assert_eq!(
+            Il2CppMetadata::parse(&data).unwrap_err(),
+            format!("section {} exceeds the file", METHODS)
+        );
+    }
+
+    #[test]
+    fn tolerates_broken_references() {
+        let mut sections = sections(88, 32, 40);
+        // names beyond the string heap, a literal beyond its data and types nested in a cycle
+        sections[0].1 = record(8, &[(0, 4), (4, 6)]);
+        sections[3].1 = record(32, &[(0, u32::MAX), (4, 7)]);
+        sections[4].1 = [0u32, 1, 99].iter().flat_map(|i| i.to_le_bytes()).collect();
+        let types = &mut sections[5].1;
+        types[88 + 48] = 0;
+        types[88 + 72] = 3;
+        let metadata = Il2CppMetadata::parse(&metadata(27, 32, &sections)).unwrap();
+        assert!(metadata.string_literals.is_empty());
+        assert_eq!(metadata.methods[0].name, "");
+        assert!(metadata.image_of(&metadata.methods[0]).is_none());
+        // the inventory lists itself and the player as nested types
+        assert_eq!(
+            metadata.types[0].full_name,
+            "Inventory+Inventory+Inventory+Game.Player"
+        );
+        assert_eq!(metadata.types[2].full_name, "Player");
+    }
+}
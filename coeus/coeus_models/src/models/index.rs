@@ -0,0 +1,418 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Lookup tables for the pools of a `DexFile`. They are built on the first lookup and shared
+//! between clones. The pools count their mutable accesses, tables built before a modification
+//! are rebuilt on the next lookup.
+
+use std::{
+    collections::HashMap,
+    iter::FromIterator,
+    ops::{Deref, DerefMut},
+    sync::{Arc, PoisonError, RwLock, RwLockReadGuard},
+};
+
+use super::{
+    encode_mutf8,
+    string_index::{can_accelerate, StringIndex},
+    DexFile,
+};
+
+/// A pool of a `DexFile`, which behaves like a `Vec`. Every mutable access counts as a
+/// modification, such that the lookup tables of the dex file do not go stale.
+#[derive(Clone)]
+pub struct Pool<T> {
+    entries: Vec<T>,
+    generation: u64,
+}
+
+impl<T> Pool<T> {
+    pub fn into_vec(self) -> Vec<T> {
+        self.entries
+    }
+}
+
+impl<T> Default for Pool<T> {
+    fn default() -> Self {
+        Vec::new().into()
+    }
+}
+
+impl<T> Deref for Pool<T> {
+    type Target = Vec<T>;
+
+    fn deref(&self) -> &Vec<T> {
+        &self.entries
+    }
+}
+
+impl<T> DerefMut for Pool<T> {
+    fn deref_mut(&mut self) -> &mut Vec<T> {
+        self.generation = self.generation.wrapping_add(1);
+        &mut self.entries
+    }
+}
+
+impl<T> From<Vec<T>> for Pool<T> {
+    fn from(entries: Vec<T>) -> Self {
+        Self {
+            entries,
+            generation: 0,
+        }
+    }
+}
+
+impl<T> FromIterator<T> for Pool<T> {
+    fn from_iter<I: IntoIterator<Item = T>>(iter: I) -> Self {
+        Vec::from_iter(iter).into()
+    }
+}
+
+impl<T> IntoIterator for Pool<T> {
+    type Item = T;
+    type IntoIter = std::vec::IntoIter<T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.into_iter()
+    }
+}
+
+impl<'a, T> IntoIterator for &'a Pool<T> {
+    type Item = &'a T;
+    type IntoIter = std::slice::Iter<'a, T>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.entries.iter()
+    }
+}
+
+impl<T: std::fmt::Debug> std::fmt::Debug for Pool<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.entries.fmt(f)
+    }
+}
+
+impl<T: serde::Serialize> serde::Serialize for Pool<T> {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        self.entries.serialize(serializer)
+    }
+}
+
+impl<'de, T: serde::Deserialize<'de>> serde::Deserialize<'de> for Pool<T> {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        Vec::deserialize(deserializer).map(Pool::from)
+    }
+}
+
+/// The generations of all pools of a dex file when a table was built
+type Generations = [u64; 6];
+
+fn generations(dex: &DexFile) -> Generations {
+    [
+        dex.strings.generation,
+        dex.types.generation,
+        dex.methods.generation,
+        dex.protos.generation,
+        dex.fields.generation,
+        dex.classes.generation,
+    ]
+}
+
+type Slot<T> = RwLock<Option<(Generations, Arc<T>)>>;
+
+fn read<T>(slot: &Slot<T>) -> RwLockReadGuard<'_, Option<(Generations, Arc<T>)>> {
+    slot.read().unwrap_or_else(PoisonError::into_inner)
+}
+
+/// Returns the table in `slot` if it is up to date, or builds it. Building holds the write lock,
+/// such that concurrent lookups build the table only once.
+fn cached<T>(slot: &Slot<T>, dex: &DexFile, build: impl FnOnce(&DexFile) -> T) -> Arc<T> {
+    let current = generations(dex);
+    if let Some((built, table)) = &*read(slot) {
+        if *built == current {
+            return table.clone();
+        }
+    }
+    let mut slot = slot.write().unwrap_or_else(PoisonError::into_inner);
+    match &*slot {
+        Some((built, table)) if *built == current => table.clone(),
+        _ => {
+            let table = Arc::new(build(dex));
+            *slot = Some((current, table.clone()));
+            table
+        }
+    }
+}
+
+/// Lazily built lookup tables of a `DexFile`. The string index is only built for string
+/// searches, as it is considerably larger than the other tables.
+#[derive(Default)]
+pub struct DexIndex {
+    tables: Slot<DexLookupTables>,
+    strings: Slot<StringIndex>,
+}
+
+impl Clone for DexIndex {
+    fn clone(&self) -> Self {
+        Self {
+            tables: RwLock::new(read(&self.tables).clone()),
+            strings: RwLock::new(read(&self.strings).clone()),
+        }
+    }
+}
+
+impl std::fmt::Debug for DexIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("DexIndex")
+            .field("tables", &read(&self.tables).is_some())
+            .field("strings", &read(&self.strings).is_some())
+            .finish()
+    }
+}
+
+impl DexIndex {
+    pub(crate) fn get(&self, dex: &DexFile) -> Arc<DexLookupTables> {
+        cached(&self.tables, dex, DexLookupTables::new)
+    }
+
+    pub(crate) fn strings(&self, dex: &DexFile) -> Arc<StringIndex> {
+        cached(&self.strings, dex, StringIndex::new)
+    }
+}
+
+/// All positions refer to the pools of the dex file. If a key occurs more than once, the first
+/// occurrence is stored, as the linear scans did before.
+pub(crate) struct DexLookupTables {
+    /// class name -> position in `classes`
+    pub(crate) classes_by_name: HashMap<String, usize>,
+    /// type idx -> position in `classes`
+    pub(crate) classes_by_type: HashMap<u32, usize>,
+    /// string idx of the type name -> position in `classes`
+    pub(crate) classes_by_type_name: HashMap<u32, usize>,
+    /// method idx -> position in `classes` and in `codes` of that class
+    pub(crate) method_data: HashMap<u32, (usize, usize)>,
+    /// `Lclass;->name(proto)` -> position in `classes` and in `codes`, only for methods with code
+    pub(crate) methods_by_signature: HashMap<String, (usize, usize)>,
+    /// string idx of the class type name -> positions in `methods`
+    pub(crate) methods_by_type_name: HashMap<u32, Vec<usize>>,
+    /// string idx of the type name -> type idx
+    pub(crate) types_by_name: HashMap<u32, u16>,
+    /// string indices sorted by their raw modified UTF-8 content
+    pub(crate) sorted_strings: Vec<u32>,
+}
+
+impl DexLookupTables {
+    fn new(dex: &DexFile) -> Self {
+        let mut classes_by_name = HashMap::with_capacity(dex.classes.len());
+        let mut classes_by_type = HashMap::with_capacity(dex.classes.len());
+        let mut classes_by_type_name = HashMap::with_capacity(dex.classes.len());
+        let mut method_data = HashMap::new();
+        let mut methods_by_signature = HashMap::new();
+        for (class_pos, class) in dex.classes.iter().enumerate() {
+            classes_by_name
+                .entry(class.class_name.clone())
+                .or_insert(class_pos);
+            classes_by_type.entry(class.class_idx).or_insert(class_pos);
+            if let Some(&type_name_idx) = dex.types.get(class.class_idx as usize) {
+                classes_by_type_name
+                    .entry(type_name_idx)
+                    .or_insert(class_pos);
+            }
+            for (code_pos, code) in class.codes.iter().enumerate() {
+                method_data
+                    .entry(code.method_idx)
+                    .or_insert((class_pos, code_pos));
+                if code.code.is_none() {
+                    continue;
+                }
+                if let Some(proto) = dex.protos.get(code.method.proto_idx as usize) {
+                    let signature =
+                        method_signature(&class.class_name, &code.name, &proto.to_string(dex));
+                    methods_by_signature
+                        .entry(signature)
+                        .or_insert((class_pos, code_pos));
+                }
+            }
+        }
+
+        let mut methods_by_type_name: HashMap<u32, Vec<usize>> = HashMap::new();
+        for (pos, method) in dex.methods.iter().enumerate() {
+            if let Some(&type_name_idx) = dex.types.get(method.class_idx as usize) {
+                methods_by_type_name
+                    .entry(type_name_idx)
+                    .or_default()
+                    .push(pos);
+            }
+        }
+
+        let mut types_by_name = HashMap::with_capacity(dex.types.len());
+        for (type_idx, &name_idx) in dex.types.iter().enumerate() {
+            types_by_name.entry(name_idx).or_insert(type_idx as u16);
+        }
+
+        // dex files sort strings by UTF-16 code points, which differs from the byte order
+        // for surrogate pairs, hence we keep our own order
+        let mut sorted_strings: Vec<u32> = (0..dex.strings.len() as u32).collect();
+        sorted_strings.sort_by(|&a, &b| {
+            dex.strings[a as usize]
+                .dat
+                .cmp(&dex.strings[b as usize].dat)
+        });
+
+        Self {
+            classes_by_name,
+            classes_by_type,
+            classes_by_type_name,
+            method_data,
+            methods_by_signature,
+            methods_by_type_name,
+            types_by_name,
+            sorted_strings,
+        }
+    }
+
+    /// Binary search for the string idx with exactly this content
+    pub(crate) fn string_idx(&self, dex: &DexFile, content: &str) -> Option<u32> {
+        if !can_accelerate(content) {
+            // such strings have more than one encoding, compare the decoded content instead
+            return dex
+                .strings
+                .iter()
+                .position(|entry| entry.to_str_lossy() == content)
+                .map(|pos| pos as u32);
+        }
+        let content = encode_mutf8(content);
+        self.sorted_strings
+            .binary_search_by(|&idx| dex.strings[idx as usize].dat.as_slice().cmp(&content))
+            .ok()
+            .map(|pos| self.sorted_strings[pos])
+    }
+
+    /// All string indices starting with `prefix`, in the order of their content
+    pub(crate) fn strings_with_prefix(&self, dex: &DexFile, prefix: &str) -> Vec<u32> {
+        let prefix = encode_mutf8(prefix);
+        let start = self
+            .sorted_strings
+            .partition_point(|&idx| dex.strings[idx as usize].dat.as_slice() < &prefix[..]);
+        self.sorted_strings[start..]
+            .iter()
+            .take_while(|&&idx| dex.strings[idx as usize].dat.starts_with(&prefix))
+            .copied()
+            .collect()
+    }
+}
+
+pub(crate) fn method_signature(class_name: &str, method_name: &str, proto: &str) -> String {
+    format!("{}->{}{}", class_name, method_name, proto)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{testing::DexBuilder, Class, StringEntry};
+    use super::*;
+
+    fn dex() -> DexFile {
+        let mut dex = DexBuilder::new("index");
+        for s in ["zeta", "alpha", "é", "", "alphabet", "😀", "a😀b"] {
+            dex.string(s);
+        }
+        dex.type_idx("[I");
+        dex.class("La/B;", Some("Ljava/lang/Object;"), &[]);
+        dex.class("La/A;", Some("La/B;"), &["Ljava/lang/Runnable;"]);
+        dex.method("La/A;", "run", "V", &[]);
+        let mut dex = dex.build();
+        // a supplementary character encoded as plain UTF-8 instead of a surrogate pair
+        dex.strings
+            .push(StringEntry::new(2, "🦀".as_bytes().to_vec()));
+        dex
+    }
+
+    fn linear_string_idx(dex: &DexFile, content: &str) -> Option<u32> {
+        dex.strings
+            .iter()
+            .position(|s| s.to_str_lossy() == content)
+            .map(|pos| pos as u32)
+    }
+
+    fn linear_type_idx(dex: &DexFile, name: &str) -> Option<u16> {
+        (0..dex.types.len())
+            .find(|&idx| dex.get_type_name(idx) == Some(name))
+            .map(|idx| idx as u16)
+    }
+
+    fn linear_class_name(dex: &DexFile, class_idx: u32) -> Option<&str> {
+        dex.classes
+            .iter()
+            .find(|class| class.class_idx == class_idx)
+            .map(|class| class.class_name.as_str())
+    }
+
+    #[test]
+    fn lookups_agree_with_linear_scans() {
+        let dex = dex();
+        let names: Vec<String> = dex
+            .strings
+            .iter()
+            .map(|s| s.to_str_lossy().into_owned())
+            .chain(["missing".to_string(), "alph".to_string()])
+            .collect();
+        for name in &names {
+            assert_eq!(
+                dex.get_string_idx(name),
+                linear_string_idx(&dex, name),
+                "{}",
+                name
+            );
+            assert_eq!(
+                dex.get_type_idx(name),
+                linear_type_idx(&dex, name),
+                "{}",
+                name
+            );
+        }
+        for class_idx in 0..=dex.types.len() as u32 {
+            assert_eq!(
+                dex.get_class_name(class_idx),
+                linear_class_name(&dex, class_idx)
+            );
+        }
+        assert_eq!(
+            dex.get_type_idx("La/A;"),
+            Some(dex.classes[1].class_idx as u16)
+        );
+        assert_eq!(dex.get_class_name(dex.classes[1].class_idx), Some("La/A;"));
+    }
+
+    #[test]
+    fn modified_pools_are_indexed_again() {
+        let mut dex = dex();
+        assert_eq!(dex.get_string_idx("omega"), None);
+        let shared = dex.clone();
+
+        dex.strings.push(StringEntry::from("omega"));
+        let omega = dex.strings.len() as u32 - 1;
+        assert_eq!(dex.get_string_idx("omega"), Some(omega));
+        // the clone still uses the tables of the unmodified pools
+        assert_eq!(shared.get_string_idx("omega"), None);
+
+        dex.types.push(omega);
+        let class_idx = dex.types.len() as u32 - 1;
+        assert_eq!(dex.get_type_idx("omega"), Some(class_idx as u16));
+        assert_eq!(dex.get_class_name(class_idx), None);
+        dex.classes.push(Arc::new(Class::new(
+            "index".to_string(),
+            class_idx,
+            "omega".to_string(),
+        )));
+        assert_eq!(dex.get_class_name(class_idx), Some("omega"));
+        assert!(dex.get_class_by_name("omega").is_some());
+
+        // entries modified in place are found as well
+        dex.strings[0] = StringEntry::from("psi");
+        assert_eq!(dex.get_string_idx("psi"), Some(0));
+        assert_eq!(dex.get_string_idx("zeta"), None);
+    }
+}
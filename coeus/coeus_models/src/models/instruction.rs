@@ -96,6 +96,8 @@ pub enum Instruction {
     ArrayPutChar(u8, u8, u8),
 
     Invoke(u16),
+    /// `invoke-custom`, the index is into the call site pool
+    InvokeCustom(u16),
 
     InvokeVirtual(u4, u16, Vec<u8>),
     InvokeSuper(u4, u16, Vec<u8>),
@@ -255,6 +257,7 @@ impl Debug for Instruction {
             Self::ArrayGetChar(arg0, arg1, arg2) => f.debug_tuple("ArrayGetChar").field(arg0).field(arg1).field(arg2).finish(),
             Self::ArrayPutChar(arg0, arg1, arg2) => f.debug_tuple("ArrayPutChar").field(arg0).field(arg1).field(arg2).finish(),
             Self::Invoke(arg0) => f.debug_tuple("Invoke").field(arg0).finish(),
+            Self::InvokeCustom(arg0) => f.debug_tuple("InvokeCustom").field(arg0).finish(),
             Self::InvokeVirtual(arg0, arg1, arg2) => f.debug_tuple("InvokeVirtual").field(arg0).field(arg1).field(arg2).finish(),
             Self::InvokeSuper(arg0, arg1, arg2) => f.debug_tuple("InvokeSuper").field(arg0).field(arg1).field(arg2).finish(),
             Self::InvokeDirect(arg0, arg1, arg2) => f.debug_tuple("InvokeDirect").field(arg0).field(arg1).field(arg2).finish(),
@@ -1440,6 +1443,7 @@ impl Instruction {
             0x78 => Instruction::InvokeInterfaceRange(high, data[0], data[1]),
 
             0xfa..=0xfb => Instruction::Invoke(data[0]),
+            0xfc..=0xfd => Instruction::InvokeCustom(data[0]),
 
             0x0e => Instruction::ReturnVoid,
             0x0f..=0x11 => Instruction::Return(high),
@@ -1452,27 +1456,11 @@ impl Instruction {
                 },
             ),
             0x13 => Instruction::ConstLit16(high, data[0] as i16),
-            0x14 => Instruction::ConstLit32(
-                high,
-                i32::from_be_bytes([
-                    ((data[0] >> 8) as u8),
-                    ((data[0] & 0xff) as u8),
-                    ((data[1] >> 8) as u8),
-                    ((data[1] & 0xff) as u8),
-                ]),
-            ),
+            0x14 => Instruction::ConstLit32(high, ((data[1] as u32) << 16 | (data[0] as u32)) as i32),
             0x15 => Instruction::ConstLit32(high, (data[0] as i32) << 16),
             0x16..=0x19 => Instruction::ConstWide,
             0x1a => Instruction::ConstString(high, data[0]),
-            0x1b => Instruction::ConstStringJumbo(
-                high,
-                u32::from_be_bytes([
-                    ((data[0] >> 8) as u8),
-                    ((data[0] & 0xff) as u8),
-                    ((data[1] >> 8) as u8),
-                    ((data[1] & 0xff) as u8),
-                ]),
-            ),
+            0x1b => Instruction::ConstStringJumbo(high, (data[1] as u32) << 16 | (data[0] as u32)),
             0x1c => Instruction::ConstClass(high, data[0]),
             0x1f => Instruction::CheckCast(high, data[0]),
             0x8d => Instruction::IntToByte(u4::new(high & 0b1111), u4::new(high >> 4)),
@@ -1639,3 +1627,36 @@ impl Instruction {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::Instruction;
+
+    #[test]
+    fn decodes_32_bit_literals_low_code_unit_first() {
+        // const v1, #0x7f010002
+        assert_eq!(
+            Instruction::get_opcode(0x0114, &[0x0002, 0x7f01]),
+            Instruction::ConstLit32(1, 0x7f010002)
+        );
+        // const-string/jumbo v2, string@0x00012345
+        assert_eq!(
+            Instruction::get_opcode(0x021b, &[0x2345, 0x0001]),
+            Instruction::ConstStringJumbo(2, 0x00012345)
+        );
+    }
+
+    #[test]
+    fn decodes_invoke_custom_call_sites() {
+        // invoke-custom {v0}, call_site@3
+        assert_eq!(
+            Instruction::get_opcode(0x10fc, &[0x0003, 0x0000]),
+            Instruction::InvokeCustom(3)
+        );
+        // invoke-custom/range {v0 .. v1}, call_site@4
+        assert_eq!(
+            Instruction::get_opcode(0x02fd, &[0x0004, 0x0000]),
+            Instruction::InvokeCustom(4)
+        );
+    }
+}
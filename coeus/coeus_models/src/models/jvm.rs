@@ -0,0 +1,1226 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Models and parser for Java class files, as found in `.jar` and `.aar` libraries.
+//!
+//! References into the constant pool are resolved while parsing, such that every
+//! `JvmInstruction` carries the strings and member references it uses. The class files are
+//! then desugared into the dex models (see `coeus_parse::jvm`), the original JVM bytecode is
+//! kept next to the translated code in `MethodData::jvm_code`.
+
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt::{Display, Formatter},
+    sync::Arc,
+};
+
+use super::mutf8_to_str;
+
+pub const CLASS_FILE_MAGIC: [u8; 4] = [0xca, 0xfe, 0xba, 0xbe];
+
+pub const ACC_SUPER: u16 = 0x0020;
+pub const ACC_MODULE: u16 = 0x8000;
+
+/// Maximum nesting of dynamic constants we resolve (they may refer to each other)
+const MAX_CONSTANT_DEPTH: usize = 8;
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// A parsed class file. Class names are in their internal form (e.g. `java/lang/Object`).
+pub struct ClassFile {
+    pub minor_version: u16,
+    pub major_version: u16,
+    pub access_flags: u16,
+    pub name: String,
+    /// `None` for `java/lang/Object` and `module-info`
+    pub super_name: Option<String>,
+    pub interfaces: Vec<String>,
+    pub fields: Vec<JvmField>,
+    pub methods: Vec<JvmMethod>,
+    pub source_file: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JvmField {
+    pub access_flags: u16,
+    pub name: String,
+    pub descriptor: String,
+    /// The `ConstantValue` attribute of static final fields
+    pub constant_value: Option<JvmConstant>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JvmMethod {
+    pub access_flags: u16,
+    pub name: String,
+    pub descriptor: String,
+    pub code: Option<Arc<JvmCode>>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// The `Code` attribute of a method
+pub struct JvmCode {
+    pub max_stack: u16,
+    pub max_locals: u16,
+    /// The instructions together with their byte offset
+    pub insns: Vec<(u32, JvmInstruction)>,
+    pub exception_table: Vec<JvmExceptionHandler>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct JvmExceptionHandler {
+    pub start_pc: u32,
+    pub end_pc: u32,
+    pub handler_pc: u32,
+    /// `None` catches everything (`finally`)
+    pub catch_type: Option<String>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+/// A resolved `Fieldref`, `Methodref` or `InterfaceMethodref`
+pub struct JvmMemberRef {
+    /// Internal name of the owner, or an array descriptor (e.g. `[I` for `clone`)
+    pub class_name: String,
+    pub name: String,
+    pub descriptor: String,
+    pub is_interface: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// A call site of `invokedynamic` or a dynamic constant
+pub struct JvmDynamic {
+    pub name: String,
+    pub descriptor: String,
+    pub bootstrap_method: Option<Arc<JvmMemberRef>>,
+    pub bootstrap_arguments: Vec<JvmConstant>,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+pub enum JvmConstant {
+    Null,
+    Int(i32),
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    String(String),
+    /// Internal name or array descriptor
+    Class(String),
+    MethodType(String),
+    MethodHandle(u8, Arc<JvmMemberRef>),
+    Dynamic(Box<JvmDynamic>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum JvmType {
+    Int,
+    Long,
+    Float,
+    Double,
+    Reference,
+    Byte,
+    Char,
+    Short,
+    Boolean,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum JvmArithmetic {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Rem,
+    Neg,
+    Shl,
+    Shr,
+    UShr,
+    And,
+    Or,
+    Xor,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+/// Conditions of the `if*` instructions. The order matches the dalvik `if-*` opcodes.
+pub enum JvmCondition {
+    Equal,
+    NotEqual,
+    LessThan,
+    GreaterEqual,
+    GreaterThan,
+    LessEqual,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum JvmInvokeKind {
+    Virtual,
+    Special,
+    Static,
+    Interface,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// A JVM instruction. Branch targets are absolute byte offsets into the code.
+pub enum JvmInstruction {
+    Nop,
+    /// `aconst_null`, `iconst_*`, `bipush`, `sipush`, `ldc*` and friends
+    Const(JvmConstant),
+    Load(JvmType, u16),
+    Store(JvmType, u16),
+    ArrayLoad(JvmType),
+    ArrayStore(JvmType),
+    Pop,
+    Pop2,
+    Dup,
+    DupX1,
+    DupX2,
+    Dup2,
+    Dup2X1,
+    Dup2X2,
+    Swap,
+    Arithmetic(JvmArithmetic, JvmType),
+    /// `iinc`
+    Increment(u16, i16),
+    Convert(JvmType, JvmType),
+    /// `lcmp`, `fcmp*` and `dcmp*`. The flag is set for the `g` variants.
+    Compare(JvmType, bool),
+    /// Compare the top of the stack with zero
+    If(JvmCondition, u32),
+    /// Compare two ints
+    IfCompare(JvmCondition, u32),
+    /// Compare two references (only `Equal` and `NotEqual`)
+    IfReferenceCompare(JvmCondition, u32),
+    IfNull(u32),
+    IfNonNull(u32),
+    Goto(u32),
+    Jsr(u32),
+    Ret(u16),
+    TableSwitch {
+        default: u32,
+        low: i32,
+        targets: Vec<u32>,
+    },
+    LookupSwitch {
+        default: u32,
+        pairs: Vec<(i32, u32)>,
+    },
+    Return(Option<JvmType>),
+    GetStatic(Arc<JvmMemberRef>),
+    PutStatic(Arc<JvmMemberRef>),
+    GetField(Arc<JvmMemberRef>),
+    PutField(Arc<JvmMemberRef>),
+    Invoke(JvmInvokeKind, Arc<JvmMemberRef>),
+    InvokeDynamic(Box<JvmDynamic>),
+    New(String),
+    NewArray(JvmType),
+    ANewArray(String),
+    MultiANewArray(String, u8),
+    ArrayLength,
+    Throw,
+    CheckCast(String),
+    InstanceOf(String),
+    MonitorEnter,
+    MonitorExit,
+    Unknown(u8),
+}
+
+impl ClassFile {
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let mut reader = ClassReader { data, pos: 0 };
+        if reader.bytes(4)? != CLASS_FILE_MAGIC {
+            return Err("Not a class file".to_string());
+        }
+        let minor_version = reader.u16()?;
+        let major_version = reader.u16()?;
+        let pool = ConstantPool::parse(&mut reader)?;
+
+        let access_flags = reader.u16()?;
+        let name = pool.class_name(reader.u16()?)?;
+        let super_name = match reader.u16()? {
+            0 => None,
+            idx => Some(pool.class_name(idx)?),
+        };
+        let interfaces_count = reader.u16()?;
+        let mut interfaces = Vec::with_capacity(interfaces_count as usize);
+        for _ in 0..interfaces_count {
+            interfaces.push(pool.class_name(reader.u16()?)?);
+        }
+
+        let mut raw_fields = vec![];
+        for _ in 0..reader.u16()? {
+            raw_fields.push(RawMember::parse(&mut reader, &pool)?);
+        }
+        let mut raw_methods = vec![];
+        for _ in 0..reader.u16()? {
+            raw_methods.push(RawMember::parse(&mut reader, &pool)?);
+        }
+        let mut source_file = None;
+        let mut bootstrap_methods = vec![];
+        for _ in 0..reader.u16()? {
+            let (attribute_name, content) = read_attribute(&mut reader, &pool)?;
+            match attribute_name.as_str() {
+                "SourceFile" => {
+                    let mut content = ClassReader::new(content);
+                    source_file = Some(pool.utf8(content.u16()?)?);
+                }
+                "BootstrapMethods" => bootstrap_methods = parse_bootstrap_methods(content)?,
+                _ => {}
+            }
+        }
+        let pool = ResolvedPool {
+            pool,
+            bootstrap_methods,
+            member_refs: RefCell::new(HashMap::new()),
+        };
+
+        let fields = raw_fields
+            .into_iter()
+            .map(|field| {
+                let constant_value = field
+                    .attributes
+                    .iter()
+                    .find(|(name, _)| name == "ConstantValue")
+                    .and_then(|(_, content)| ClassReader::new(content).u16().ok())
+                    .and_then(|idx| pool.constant(idx, 0).ok());
+                JvmField {
+                    access_flags: field.access_flags,
+                    name: field.name,
+                    descriptor: field.descriptor,
+                    constant_value,
+                }
+            })
+            .collect();
+        let mut methods = Vec::with_capacity(raw_methods.len());
+        for method in raw_methods {
+            let code = match method.attributes.iter().find(|(name, _)| name == "Code") {
+                Some((_, content)) => Some(Arc::new(JvmCode::parse(content, &pool)?)),
+                None => None,
+            };
+            methods.push(JvmMethod {
+                access_flags: method.access_flags,
+                name: method.name,
+                descriptor: method.descriptor,
+                code,
+            });
+        }
+
+        Ok(ClassFile {
+            minor_version,
+            major_version,
+            access_flags,
+            name,
+            super_name,
+            interfaces,
+            fields,
+            methods,
+            source_file,
+        })
+    }
+
+    /// The type descriptor of this class (e.g. `Ljava/lang/Object;`)
+    pub fn descriptor(&self) -> String {
+        class_name_to_descriptor(&self.name)
+    }
+
+    pub fn is_module_info(&self) -> bool {
+        self.access_flags & ACC_MODULE != 0
+    }
+}
+
+impl JvmCode {
+    fn parse(content: &[u8], pool: &ResolvedPool) -> Result<Self, String> {
+        let mut reader = ClassReader::new(content);
+        let max_stack = reader.u16()?;
+        let max_locals = reader.u16()?;
+        let code_length = reader.u32()? as usize;
+        let code = reader.bytes(code_length)?;
+        let insns = decode_instructions(code, pool)?;
+        let mut exception_table = vec![];
+        for _ in 0..reader.u16()? {
+            let start_pc = reader.u16()? as u32;
+            let end_pc = reader.u16()? as u32;
+            let handler_pc = reader.u16()? as u32;
+            let catch_type = match reader.u16()? {
+                0 => None,
+                idx => Some(pool.pool.class_name(idx)?),
+            };
+            exception_table.push(JvmExceptionHandler {
+                start_pc,
+                end_pc,
+                handler_pc,
+                catch_type,
+            });
+        }
+        Ok(JvmCode {
+            max_stack,
+            max_locals,
+            insns,
+            exception_table,
+        })
+    }
+
+    /// A `javap` like listing of the instructions
+    pub fn get_disassembly(&self) -> String {
+        let mut lines = vec![format!(
+            ".limit stack {}\n.limit locals {}",
+            self.max_stack, self.max_locals
+        )];
+        for handler in &self.exception_table {
+            lines.push(format!(
+                ".catch {} from {} to {} using {}",
+                handler.catch_type.as_deref().unwrap_or("all"),
+                handler.start_pc,
+                handler.end_pc,
+                handler.handler_pc
+            ));
+        }
+        lines.extend(
+            self.insns
+                .iter()
+                .map(|(pc, instruction)| format!("{:>5}: {}", pc, instruction)),
+        );
+        lines.join("\n")
+    }
+}
+
+impl JvmType {
+    fn from_newarray_type(atype: u8) -> Option<Self> {
+        Some(match atype {
+            4 => JvmType::Boolean,
+            5 => JvmType::Char,
+            6 => JvmType::Float,
+            7 => JvmType::Double,
+            8 => JvmType::Byte,
+            9 => JvmType::Short,
+            10 => JvmType::Int,
+            11 => JvmType::Long,
+            _ => return None,
+        })
+    }
+    /// The field descriptor of the type, `Ljava/lang/Object;` for references
+    pub fn descriptor(&self) -> &'static str {
+        match self {
+            JvmType::Int => "I",
+            JvmType::Long => "J",
+            JvmType::Float => "F",
+            JvmType::Double => "D",
+            JvmType::Reference => "Ljava/lang/Object;",
+            JvmType::Byte => "B",
+            JvmType::Char => "C",
+            JvmType::Short => "S",
+            JvmType::Boolean => "Z",
+        }
+    }
+    fn prefix(&self) -> &'static str {
+        match self {
+            JvmType::Int => "i",
+            JvmType::Long => "l",
+            JvmType::Float => "f",
+            JvmType::Double => "d",
+            JvmType::Reference => "a",
+            JvmType::Byte | JvmType::Boolean => "b",
+            JvmType::Char => "c",
+            JvmType::Short => "s",
+        }
+    }
+}
+
+impl JvmInstruction {
+    /// Returns false if execution never continues with the next instruction
+    pub fn falls_through(&self) -> bool {
+        !matches!(
+            self,
+            JvmInstruction::Goto(_)
+                | JvmInstruction::Jsr(_)
+                | JvmInstruction::Ret(_)
+                | JvmInstruction::TableSwitch { .. }
+                | JvmInstruction::LookupSwitch { .. }
+                | JvmInstruction::Return(_)
+                | JvmInstruction::Throw
+                | JvmInstruction::Unknown(_)
+        )
+    }
+    /// All explicit branch targets of this instruction
+    pub fn branch_targets(&self) -> Vec<u32> {
+        match self {
+            JvmInstruction::If(_, target)
+            | JvmInstruction::IfCompare(_, target)
+            | JvmInstruction::IfReferenceCompare(_, target)
+            | JvmInstruction::IfNull(target)
+            | JvmInstruction::IfNonNull(target)
+            | JvmInstruction::Goto(target)
+            | JvmInstruction::Jsr(target) => vec![*target],
+            JvmInstruction::TableSwitch {
+                default, targets, ..
+            } => std::iter::once(*default)
+                .chain(targets.iter().copied())
+                .collect(),
+            JvmInstruction::LookupSwitch { default, pairs } => std::iter::once(*default)
+                .chain(pairs.iter().map(|(_, target)| *target))
+                .collect(),
+            _ => vec![],
+        }
+    }
+}
+
+impl Display for JvmConstant {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JvmConstant::Null => f.write_str("null"),
+            JvmConstant::Int(v) => write!(f, "{}", v),
+            JvmConstant::Float(v) => write!(f, "{:?}f", v),
+            JvmConstant::Long(v) => write!(f, "{}l", v),
+            JvmConstant::Double(v) => write!(f, "{:?}", v),
+            JvmConstant::String(s) => write!(
+                f,
+                "\"{}\"",
+                s.replace('\\', "\\\\")
+                    .replace('\n', "\\n")
+                    .replace('"', "\\\"")
+            ),
+            JvmConstant::Class(name) => write!(f, "class {}", name),
+            JvmConstant::MethodType(descriptor) => write!(f, "methodtype {}", descriptor),
+            JvmConstant::MethodHandle(kind, member) => {
+                write!(f, "methodhandle {} {}", kind, member)
+            }
+            JvmConstant::Dynamic(dynamic) => write!(f, "dynamic {}", dynamic),
+        }
+    }
+}
+
+impl Display for JvmMemberRef {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}.{}:{}", self.class_name, self.name, self.descriptor)
+    }
+}
+
+impl Display for JvmDynamic {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{}:{}", self.name, self.descriptor)?;
+        if let Some(bootstrap_method) = &self.bootstrap_method {
+            write!(f, " bootstrap {}", bootstrap_method)?;
+        }
+        if !self.bootstrap_arguments.is_empty() {
+            let arguments = self
+                .bootstrap_arguments
+                .iter()
+                .map(|a| a.to_string())
+                .collect::<Vec<_>>();
+            write!(f, " [{}]", arguments.join(", "))?;
+        }
+        Ok(())
+    }
+}
+
+impl Display for JvmInstruction {
+    fn fmt(&self, f: &mut Formatter<'_>) -> std::fmt::Result {
+        match self {
+            JvmInstruction::Nop => f.write_str("nop"),
+            JvmInstruction::Const(JvmConstant::Null) => f.write_str("aconst_null"),
+            JvmInstruction::Const(constant @ JvmConstant::Int(_)) => {
+                write!(f, "iconst {}", constant)
+            }
+            JvmInstruction::Const(constant @ JvmConstant::Long(_)) => {
+                write!(f, "lconst {}", constant)
+            }
+            JvmInstruction::Const(constant @ JvmConstant::Float(_)) => {
+                write!(f, "fconst {}", constant)
+            }
+            JvmInstruction::Const(constant @ JvmConstant::Double(_)) => {
+                write!(f, "dconst {}", constant)
+            }
+            JvmInstruction::Const(constant) => write!(f, "ldc {}", constant),
+            JvmInstruction::Load(ty, idx) => write!(f, "{}load {}", ty.prefix(), idx),
+            JvmInstruction::Store(ty, idx) => write!(f, "{}store {}", ty.prefix(), idx),
+            JvmInstruction::ArrayLoad(ty) => write!(f, "{}aload", ty.prefix()),
+            JvmInstruction::ArrayStore(ty) => write!(f, "{}astore", ty.prefix()),
+            JvmInstruction::Pop => f.write_str("pop"),
+            JvmInstruction::Pop2 => f.write_str("pop2"),
+            JvmInstruction::Dup => f.write_str("dup"),
+            JvmInstruction::DupX1 => f.write_str("dup_x1"),
+            JvmInstruction::DupX2 => f.write_str("dup_x2"),
+            JvmInstruction::Dup2 => f.write_str("dup2"),
+            JvmInstruction::Dup2X1 => f.write_str("dup2_x1"),
+            JvmInstruction::Dup2X2 => f.write_str("dup2_x2"),
+            JvmInstruction::Swap => f.write_str("swap"),
+            JvmInstruction::Arithmetic(op, ty) => {
+                let op = match op {
+                    JvmArithmetic::Add => "add",
+                    JvmArithmetic::Sub => "sub",
+                    JvmArithmetic::Mul => "mul",
+                    JvmArithmetic::Div => "div",
+                    JvmArithmetic::Rem => "rem",
+                    JvmArithmetic::Neg => "neg",
+                    JvmArithmetic::Shl => "shl",
+                    JvmArithmetic::Shr => "shr",
+                    JvmArithmetic::UShr => "ushr",
+                    JvmArithmetic::And => "and",
+                    JvmArithmetic::Or => "or",
+                    JvmArithmetic::Xor => "xor",
+                };
+                write!(f, "{}{}", ty.prefix(), op)
+            }
+            JvmInstruction::Increment(idx, value) => write!(f, "iinc {} {}", idx, value),
+            JvmInstruction::Convert(from, to) => write!(f, "{}2{}", from.prefix(), to.prefix()),
+            JvmInstruction::Compare(JvmType::Long, _) => f.write_str("lcmp"),
+            JvmInstruction::Compare(ty, greater) => {
+                write!(f, "{}cmp{}", ty.prefix(), if *greater { "g" } else { "l" })
+            }
+            JvmInstruction::If(condition, target) => {
+                write!(f, "if{} {}", condition_suffix(*condition), target)
+            }
+            JvmInstruction::IfCompare(condition, target) => {
+                write!(f, "if_icmp{} {}", condition_suffix(*condition), target)
+            }
+            JvmInstruction::IfReferenceCompare(condition, target) => {
+                write!(f, "if_acmp{} {}", condition_suffix(*condition), target)
+            }
+            JvmInstruction::IfNull(target) => write!(f, "ifnull {}", target),
+            JvmInstruction::IfNonNull(target) => write!(f, "ifnonnull {}", target),
+            JvmInstruction::Goto(target) => write!(f, "goto {}", target),
+            JvmInstruction::Jsr(target) => write!(f, "jsr {}", target),
+            JvmInstruction::Ret(idx) => write!(f, "ret {}", idx),
+            JvmInstruction::TableSwitch {
+                default,
+                low,
+                targets,
+            } => {
+                let cases = targets
+                    .iter()
+                    .enumerate()
+                    .map(|(i, target)| format!("{}: {}", *low as i64 + i as i64, target))
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "tableswitch {{ {}, default: {} }}",
+                    cases.join(", "),
+                    default
+                )
+            }
+            JvmInstruction::LookupSwitch { default, pairs } => {
+                let cases = pairs
+                    .iter()
+                    .map(|(key, target)| format!("{}: {}", key, target))
+                    .collect::<Vec<_>>();
+                write!(
+                    f,
+                    "lookupswitch {{ {}, default: {} }}",
+                    cases.join(", "),
+                    default
+                )
+            }
+            JvmInstruction::Return(None) => f.write_str("return"),
+            JvmInstruction::Return(Some(ty)) => write!(f, "{}return", ty.prefix()),
+            JvmInstruction::GetStatic(member) => write!(f, "getstatic {}", member),
+            JvmInstruction::PutStatic(member) => write!(f, "putstatic {}", member),
+            JvmInstruction::GetField(member) => write!(f, "getfield {}", member),
+            JvmInstruction::PutField(member) => write!(f, "putfield {}", member),
+            JvmInstruction::Invoke(kind, member) => {
+                let kind = match kind {
+                    JvmInvokeKind::Virtual => "invokevirtual",
+                    JvmInvokeKind::Special => "invokespecial",
+                    JvmInvokeKind::Static => "invokestatic",
+                    JvmInvokeKind::Interface => "invokeinterface",
+                };
+                write!(f, "{} {}", kind, member)
+            }
+            JvmInstruction::InvokeDynamic(dynamic) => write!(f, "invokedynamic {}", dynamic),
+            JvmInstruction::New(class_name) => write!(f, "new {}", class_name),
+            JvmInstruction::NewArray(ty) => write!(f, "newarray {}", ty.descriptor()),
+            JvmInstruction::ANewArray(class_name) => write!(f, "anewarray {}", class_name),
+            JvmInstruction::MultiANewArray(descriptor, dimensions) => {
+                write!(f, "multianewarray {} {}", descriptor, dimensions)
+            }
+            JvmInstruction::ArrayLength => f.write_str("arraylength"),
+            JvmInstruction::Throw => f.write_str("athrow"),
+            JvmInstruction::CheckCast(class_name) => write!(f, "checkcast {}", class_name),
+            JvmInstruction::InstanceOf(class_name) => write!(f, "instanceof {}", class_name),
+            JvmInstruction::MonitorEnter => f.write_str("monitorenter"),
+            JvmInstruction::MonitorExit => f.write_str("monitorexit"),
+            JvmInstruction::Unknown(opcode) => write!(f, "unknown {:#04x}", opcode),
+        }
+    }
+}
+
+fn condition_suffix(condition: JvmCondition) -> &'static str {
+    match condition {
+        JvmCondition::Equal => "eq",
+        JvmCondition::NotEqual => "ne",
+        JvmCondition::LessThan => "lt",
+        JvmCondition::GreaterEqual => "ge",
+        JvmCondition::GreaterThan => "gt",
+        JvmCondition::LessEqual => "le",
+    }
+}
+
+/// Converts an internal class name (`java/lang/Object`) to a descriptor (`Ljava/lang/Object;`).
+/// Array descriptors are returned unchanged.
+pub fn class_name_to_descriptor(name: &str) -> String {
+    if name.starts_with('[') {
+        name.to_string()
+    } else {
+        format!("L{};", name)
+    }
+}
+
+/// Splits a method descriptor into its parameter descriptors and the return descriptor
+pub fn split_method_descriptor(descriptor: &str) -> Option<(Vec<String>, String)> {
+    let rest = descriptor.strip_prefix('(')?;
+    let end = rest.find(')')?;
+    let (mut params, return_type) = (&rest[..end], &rest[end + 1..]);
+    let mut parameters = vec![];
+    while !params.is_empty() {
+        let len = field_descriptor_len(params)?;
+        parameters.push(params[..len].to_string());
+        params = &params[len..];
+    }
+    if return_type.is_empty() {
+        return None;
+    }
+    Some((parameters, return_type.to_string()))
+}
+
+fn field_descriptor_len(descriptor: &str) -> Option<usize> {
+    let dimensions = descriptor.bytes().take_while(|&b| b == b'[').count();
+    match descriptor.as_bytes().get(dimensions)? {
+        b'L' => Some(dimensions + descriptor[dimensions..].find(';')? + 1),
+        b'B' | b'C' | b'D' | b'F' | b'I' | b'J' | b'S' | b'Z' | b'V' => Some(dimensions + 1),
+        _ => None,
+    }
+}
+
+/// Big endian reader over the class file
+struct ClassReader<'a> {
+    data: &'a [u8],
+    pos: usize,
+}
+
+impl<'a> ClassReader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        ClassReader { data, pos: 0 }
+    }
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], String> {
+        let end = self
+            .pos
+            .checked_add(len)
+            .filter(|&end| end <= self.data.len())
+            .ok_or_else(|| format!("Unexpected end of class file at {}", self.pos))?;
+        let bytes = &self.data[self.pos..end];
+        self.pos = end;
+        Ok(bytes)
+    }
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn u16(&mut self) -> Result<u16, String> {
+        let b = self.bytes(2)?;
+        Ok(u16::from_be_bytes([b[0], b[1]]))
+    }
+    fn u32(&mut self) -> Result<u32, String> {
+        let b = self.bytes(4)?;
+        Ok(u32::from_be_bytes([b[0], b[1], b[2], b[3]]))
+    }
+    fn i8(&mut self) -> Result<i8, String> {
+        Ok(self.u8()? as i8)
+    }
+    fn i16(&mut self) -> Result<i16, String> {
+        Ok(self.u16()? as i16)
+    }
+    fn i32(&mut self) -> Result<i32, String> {
+        Ok(self.u32()? as i32)
+    }
+}
+
+#[derive(Debug, Clone)]
+enum PoolEntry {
+    Utf8(String),
+    Integer(i32),
+    Float(f32),
+    Long(i64),
+    Double(f64),
+    Class(u16),
+    String(u16),
+    MemberRef {
+        class: u16,
+        name_and_type: u16,
+        is_interface: bool,
+    },
+    NameAndType(u16, u16),
+    MethodHandle(u8, u16),
+    MethodType(u16),
+    Dynamic(u16, u16),
+    Module,
+    Package,
+    /// The second slot of `Long` and `Double` entries, and index 0
+    Unusable,
+}
+
+struct ConstantPool {
+    entries: Vec<PoolEntry>,
+}
+
+impl ConstantPool {
+    fn parse(reader: &mut ClassReader) -> Result<Self, String> {
+        let count = reader.u16()? as usize;
+        let mut entries = Vec::with_capacity(count);
+        entries.push(PoolEntry::Unusable);
+        while entries.len() < count {
+            let tag = reader.u8()?;
+            let entry = match tag {
+                1 => {
+                    let len = reader.u16()? as usize;
+                    let bytes = reader.bytes(len)?;
+                    PoolEntry::Utf8(
+                        mutf8_to_str(bytes)
+                            .unwrap_or_else(|| String::from_utf8_lossy(bytes))
+                            .into_owned(),
+                    )
+                }
+                3 => PoolEntry::Integer(reader.i32()?),
+                4 => PoolEntry::Float(f32::from_bits(reader.u32()?)),
+                5 => PoolEntry::Long(((reader.u32()? as u64) << 32 | reader.u32()? as u64) as i64),
+                6 => PoolEntry::Double(f64::from_bits(
+                    (reader.u32()? as u64) << 32 | reader.u32()? as u64,
+                )),
+                7 => PoolEntry::Class(reader.u16()?),
+                8 => PoolEntry::String(reader.u16()?),
+                9..=11 => PoolEntry::MemberRef {
+                    class: reader.u16()?,
+                    name_and_type: reader.u16()?,
+                    is_interface: tag == 11,
+                },
+                12 => PoolEntry::NameAndType(reader.u16()?, reader.u16()?),
+                15 => PoolEntry::MethodHandle(reader.u8()?, reader.u16()?),
+                16 => PoolEntry::MethodType(reader.u16()?),
+                17 | 18 => PoolEntry::Dynamic(reader.u16()?, reader.u16()?),
+                19 | 20 => {
+                    reader.u16()?;
+                    if tag == 19 {
+                        PoolEntry::Module
+                    } else {
+                        PoolEntry::Package
+                    }
+                }
+                _ => return Err(format!("Unknown constant pool tag {}", tag)),
+            };
+            let is_wide = matches!(entry, PoolEntry::Long(_) | PoolEntry::Double(_));
+            entries.push(entry);
+            if is_wide {
+                entries.push(PoolEntry::Unusable);
+            }
+        }
+        Ok(ConstantPool { entries })
+    }
+
+    fn get(&self, idx: u16) -> Result<&PoolEntry, String> {
+        self.entries
+            .get(idx as usize)
+            .ok_or_else(|| format!("Constant pool index {} out of bounds", idx))
+    }
+    fn utf8(&self, idx: u16) -> Result<String, String> {
+        match self.get(idx)? {
+            PoolEntry::Utf8(s) => Ok(s.clone()),
+            other => Err(format!("Expected utf8 at {}, found {:?}", idx, other)),
+        }
+    }
+    fn class_name(&self, idx: u16) -> Result<String, String> {
+        match self.get(idx)? {
+            PoolEntry::Class(name_idx) => self.utf8(*name_idx),
+            other => Err(format!("Expected class at {}, found {:?}", idx, other)),
+        }
+    }
+    fn name_and_type(&self, idx: u16) -> Result<(String, String), String> {
+        match self.get(idx)? {
+            PoolEntry::NameAndType(name, descriptor) => {
+                Ok((self.utf8(*name)?, self.utf8(*descriptor)?))
+            }
+            other => Err(format!(
+                "Expected name and type at {}, found {:?}",
+                idx, other
+            )),
+        }
+    }
+    fn member_ref(&self, idx: u16) -> Result<JvmMemberRef, String> {
+        match self.get(idx)? {
+            &PoolEntry::MemberRef {
+                class,
+                name_and_type,
+                is_interface,
+            } => {
+                let (name, descriptor) = self.name_and_type(name_and_type)?;
+                Ok(JvmMemberRef {
+                    class_name: self.class_name(class)?,
+                    name,
+                    descriptor,
+                    is_interface,
+                })
+            }
+            other => Err(format!("Expected member ref at {}, found {:?}", idx, other)),
+        }
+    }
+}
+
+/// The constant pool together with the bootstrap methods, needed to resolve dynamic constants.
+/// Member references are shared between all instructions using the same pool entry.
+struct ResolvedPool {
+    pool: ConstantPool,
+    bootstrap_methods: Vec<(u16, Vec<u16>)>,
+    member_refs: RefCell<HashMap<u16, Arc<JvmMemberRef>>>,
+}
+
+impl ResolvedPool {
+    fn member_ref(&self, idx: u16) -> Result<Arc<JvmMemberRef>, String> {
+        if let Some(member) = self.member_refs.borrow().get(&idx) {
+            return Ok(member.clone());
+        }
+        let member = Arc::new(self.pool.member_ref(idx)?);
+        self.member_refs.borrow_mut().insert(idx, member.clone());
+        Ok(member)
+    }
+
+    fn constant(&self, idx: u16, depth: usize) -> Result<JvmConstant, String> {
+        Ok(match self.pool.get(idx)? {
+            PoolEntry::Integer(v) => JvmConstant::Int(*v),
+            PoolEntry::Float(v) => JvmConstant::Float(*v),
+            PoolEntry::Long(v) => JvmConstant::Long(*v),
+            PoolEntry::Double(v) => JvmConstant::Double(*v),
+            PoolEntry::String(s) => JvmConstant::String(self.pool.utf8(*s)?),
+            PoolEntry::Class(name) => JvmConstant::Class(self.pool.utf8(*name)?),
+            PoolEntry::MethodType(descriptor) => {
+                JvmConstant::MethodType(self.pool.utf8(*descriptor)?)
+            }
+            PoolEntry::MethodHandle(kind, member) => {
+                JvmConstant::MethodHandle(*kind, self.member_ref(*member)?)
+            }
+            PoolEntry::Dynamic(..) => JvmConstant::Dynamic(Box::new(self.dynamic(idx, depth)?)),
+            other => {
+                return Err(format!(
+                    "Expected loadable constant at {}, found {:?}",
+                    idx, other
+                ))
+            }
+        })
+    }
+
+    fn dynamic(&self, idx: u16, depth: usize) -> Result<JvmDynamic, String> {
+        let &PoolEntry::Dynamic(bootstrap_idx, name_and_type) = self.pool.get(idx)? else {
+            return Err(format!("Expected dynamic constant at {}", idx));
+        };
+        let (name, descriptor) = self.pool.name_and_type(name_and_type)?;
+        let mut bootstrap_method = None;
+        let mut bootstrap_arguments = vec![];
+        if let Some((method_handle, arguments)) = self.bootstrap_methods.get(bootstrap_idx as usize)
+        {
+            if let Ok(&PoolEntry::MethodHandle(_, member)) = self.pool.get(*method_handle) {
+                bootstrap_method = self.member_ref(member).ok();
+            }
+            if depth < MAX_CONSTANT_DEPTH {
+                bootstrap_arguments = arguments
+                    .iter()
+                    .filter_map(|&arg| self.constant(arg, depth + 1).ok())
+                    .collect();
+            }
+        }
+        Ok(JvmDynamic {
+            name,
+            descriptor,
+            bootstrap_method,
+            bootstrap_arguments,
+        })
+    }
+}
+
+struct RawMember<'a> {
+    access_flags: u16,
+    name: String,
+    descriptor: String,
+    attributes: Vec<(String, &'a [u8])>,
+}
+
+impl<'a> RawMember<'a> {
+    fn parse(reader: &mut ClassReader<'a>, pool: &ConstantPool) -> Result<Self, String> {
+        let access_flags = reader.u16()?;
+        let name = pool.utf8(reader.u16()?)?;
+        let descriptor = pool.utf8(reader.u16()?)?;
+        let mut attributes = vec![];
+        for _ in 0..reader.u16()? {
+            attributes.push(read_attribute(reader, pool)?);
+        }
+        Ok(RawMember {
+            access_flags,
+            name,
+            descriptor,
+            attributes,
+        })
+    }
+}
+
+fn read_attribute<'a>(
+    reader: &mut ClassReader<'a>,
+    pool: &ConstantPool,
+) -> Result<(String, &'a [u8]), String> {
+    let name_idx = reader.u16()?;
+    let len = reader.u32()? as usize;
+    let content = reader.bytes(len)?;
+    // attributes with broken names are ignored, as the JVM does for unknown attributes
+    Ok((pool.utf8(name_idx).unwrap_or_default(), content))
+}
+
+fn parse_bootstrap_methods(content: &[u8]) -> Result<Vec<(u16, Vec<u16>)>, String> {
+    let mut reader = ClassReader::new(content);
+    let mut methods = vec![];
+    for _ in 0..reader.u16()? {
+        let method_ref = reader.u16()?;
+        let mut arguments = vec![];
+        for _ in 0..reader.u16()? {
+            arguments.push(reader.u16()?);
+        }
+        methods.push((method_ref, arguments));
+    }
+    Ok(methods)
+}
+
+fn branch_target(pc: usize, offset: i32) -> Result<u32, String> {
+    let target = pc as i64 + offset as i64;
+    if target < 0 || target > u32::MAX as i64 {
+        return Err(format!("Invalid branch target {} at {}", target, pc));
+    }
+    Ok(target as u32)
+}
+
+const CONDITIONS: [JvmCondition; 6] = [
+    JvmCondition::Equal,
+    JvmCondition::NotEqual,
+    JvmCondition::LessThan,
+    JvmCondition::GreaterEqual,
+    JvmCondition::GreaterThan,
+    JvmCondition::LessEqual,
+];
+
+/// Types in the order they appear in the typed opcode groups (`iload`, `lload`, ...)
+const LOAD_STORE_TYPES: [JvmType; 5] = [
+    JvmType::Int,
+    JvmType::Long,
+    JvmType::Float,
+    JvmType::Double,
+    JvmType::Reference,
+];
+
+const ARRAY_TYPES: [JvmType; 8] = [
+    JvmType::Int,
+    JvmType::Long,
+    JvmType::Float,
+    JvmType::Double,
+    JvmType::Reference,
+    JvmType::Byte,
+    JvmType::Char,
+    JvmType::Short,
+];
+
+const ARITHMETIC_TYPES: [JvmType; 4] =
+    [JvmType::Int, JvmType::Long, JvmType::Float, JvmType::Double];
+
+fn decode_instructions(
+    code: &[u8],
+    pool: &ResolvedPool,
+) -> Result<Vec<(u32, JvmInstruction)>, String> {
+    use JvmInstruction::*;
+    let mut reader = ClassReader::new(code);
+    let mut insns = vec![];
+    while reader.pos < code.len() {
+        let pc = reader.pos;
+        let opcode = reader.u8()?;
+        let instruction = match opcode {
+            0x00 => Nop,
+            0x01 => Const(JvmConstant::Null),
+            0x02..=0x08 => Const(JvmConstant::Int(opcode as i32 - 0x03)),
+            0x09..=0x0a => Const(JvmConstant::Long(opcode as i64 - 0x09)),
+            0x0b..=0x0d => Const(JvmConstant::Float((opcode - 0x0b) as f32)),
+            0x0e..=0x0f => Const(JvmConstant::Double((opcode - 0x0e) as f64)),
+            0x10 => Const(JvmConstant::Int(reader.i8()? as i32)),
+            0x11 => Const(JvmConstant::Int(reader.i16()? as i32)),
+            0x12 => Const(pool.constant(reader.u8()? as u16, 0)?),
+            0x13 | 0x14 => Const(pool.constant(reader.u16()?, 0)?),
+            0x15..=0x19 => Load(
+                LOAD_STORE_TYPES[(opcode - 0x15) as usize],
+                reader.u8()? as u16,
+            ),
+            0x1a..=0x2d => {
+                let n = opcode - 0x1a;
+                Load(LOAD_STORE_TYPES[(n / 4) as usize], (n % 4) as u16)
+            }
+            0x2e..=0x35 => ArrayLoad(ARRAY_TYPES[(opcode - 0x2e) as usize]),
+            0x36..=0x3a => Store(
+                LOAD_STORE_TYPES[(opcode - 0x36) as usize],
+                reader.u8()? as u16,
+            ),
+            0x3b..=0x4e => {
+                let n = opcode - 0x3b;
+                Store(LOAD_STORE_TYPES[(n / 4) as usize], (n % 4) as u16)
+            }
+            0x4f..=0x56 => ArrayStore(ARRAY_TYPES[(opcode - 0x4f) as usize]),
+            0x57 => Pop,
+            0x58 => Pop2,
+            0x59 => Dup,
+            0x5a => DupX1,
+            0x5b => DupX2,
+            0x5c => Dup2,
+            0x5d => Dup2X1,
+            0x5e => Dup2X2,
+            0x5f => Swap,
+            0x60..=0x77 => {
+                let n = opcode - 0x60;
+                let op = [
+                    JvmArithmetic::Add,
+                    JvmArithmetic::Sub,
+                    JvmArithmetic::Mul,
+                    JvmArithmetic::Div,
+                    JvmArithmetic::Rem,
+                    JvmArithmetic::Neg,
+                ][(n / 4) as usize];
+                Arithmetic(op, ARITHMETIC_TYPES[(n % 4) as usize])
+            }
+            0x78..=0x83 => {
+                let n = opcode - 0x78;
+                let op = [
+                    JvmArithmetic::Shl,
+                    JvmArithmetic::Shr,
+                    JvmArithmetic::UShr,
+                    JvmArithmetic::And,
+                    JvmArithmetic::Or,
+                    JvmArithmetic::Xor,
+                ][(n / 2) as usize];
+                Arithmetic(op, ARITHMETIC_TYPES[(n % 2) as usize])
+            }
+            0x84 => Increment(reader.u8()? as u16, reader.i8()? as i16),
+            0x85..=0x93 => {
+                use JvmType::*;
+                let (from, to) = [
+                    (Int, Long),
+                    (Int, Float),
+                    (Int, Double),
+                    (Long, Int),
+                    (Long, Float),
+                    (Long, Double),
+                    (Float, Int),
+                    (Float, Long),
+                    (Float, Double),
+                    (Double, Int),
+                    (Double, Long),
+                    (Double, Float),
+                    (Int, Byte),
+                    (Int, Char),
+                    (Int, Short),
+                ][(opcode - 0x85) as usize];
+                Convert(from, to)
+            }
+            0x94 => Compare(JvmType::Long, false),
+            0x95 => Compare(JvmType::Float, false),
+            0x96 => Compare(JvmType::Float, true),
+            0x97 => Compare(JvmType::Double, false),
+            0x98 => Compare(JvmType::Double, true),
+            0x99..=0x9e => If(
+                CONDITIONS[(opcode - 0x99) as usize],
+                branch_target(pc, reader.i16()? as i32)?,
+            ),
+            0x9f..=0xa4 => IfCompare(
+                CONDITIONS[(opcode - 0x9f) as usize],
+                branch_target(pc, reader.i16()? as i32)?,
+            ),
+            0xa5..=0xa6 => IfReferenceCompare(
+                CONDITIONS[(opcode - 0xa5) as usize],
+                branch_target(pc, reader.i16()? as i32)?,
+            ),
+            0xa7 => Goto(branch_target(pc, reader.i16()? as i32)?),
+            0xa8 => Jsr(branch_target(pc, reader.i16()? as i32)?),
+            0xa9 => Ret(reader.u8()? as u16),
+            0xaa | 0xab => {
+                // the operands are aligned to 4 bytes relative to the start of the code
+                reader.bytes((4 - reader.pos % 4) % 4)?;
+                let default = branch_target(pc, reader.i32()?)?;
+                if opcode == 0xaa {
+                    let low = reader.i32()?;
+                    let high = reader.i32()?;
+                    if high < low || (high as i64 - low as i64) >= code.len() as i64 {
+                        return Err(format!("Invalid tableswitch bounds at {}", pc));
+                    }
+                    let mut targets = vec![];
+                    for _ in low..=high {
+                        targets.push(branch_target(pc, reader.i32()?)?);
+                    }
+                    TableSwitch {
+                        default,
+                        low,
+                        targets,
+                    }
+                } else {
+                    let npairs = reader.u32()? as usize;
+                    if npairs > code.len() / 8 {
+                        return Err(format!("Invalid lookupswitch size at {}", pc));
+                    }
+                    let mut pairs = vec![];
+                    for _ in 0..npairs {
+                        let key = reader.i32()?;
+                        pairs.push((key, branch_target(pc, reader.i32()?)?));
+                    }
+                    LookupSwitch { default, pairs }
+                }
+            }
+            0xac..=0xb0 => Return(Some(LOAD_STORE_TYPES[(opcode - 0xac) as usize])),
+            0xb1 => Return(None),
+            0xb2 => GetStatic(pool.member_ref(reader.u16()?)?),
+            0xb3 => PutStatic(pool.member_ref(reader.u16()?)?),
+            0xb4 => GetField(pool.member_ref(reader.u16()?)?),
+            0xb5 => PutField(pool.member_ref(reader.u16()?)?),
+            0xb6 => Invoke(JvmInvokeKind::Virtual, pool.member_ref(reader.u16()?)?),
+            0xb7 => Invoke(JvmInvokeKind::Special, pool.member_ref(reader.u16()?)?),
+            0xb8 => Invoke(JvmInvokeKind::Static, pool.member_ref(reader.u16()?)?),
+            0xb9 => {
+                let member = pool.member_ref(reader.u16()?)?;
+                reader.bytes(2)?;
+                Invoke(JvmInvokeKind::Interface, member)
+            }
+            0xba => {
+                let dynamic = pool.dynamic(reader.u16()?, 0)?;
+                reader.bytes(2)?;
+                InvokeDynamic(Box::new(dynamic))
+            }
+            0xbb => New(pool.pool.class_name(reader.u16()?)?),
+            0xbc => {
+                let atype = reader.u8()?;
+                NewArray(
+                    JvmType::from_newarray_type(atype)
+                        .ok_or_else(|| format!("Invalid array type {} at {}", atype, pc))?,
+                )
+            }
+            0xbd => ANewArray(pool.pool.class_name(reader.u16()?)?),
+            0xbe => ArrayLength,
+            0xbf => Throw,
+            0xc0 => CheckCast(pool.pool.class_name(reader.u16()?)?),
+            0xc1 => InstanceOf(pool.pool.class_name(reader.u16()?)?),
+            0xc2 => MonitorEnter,
+            0xc3 => MonitorExit,
+            0xc4 => {
+                let opcode = reader.u8()?;
+                let idx = reader.u16()?;
+                match opcode {
+                    0x15..=0x19 => Load(LOAD_STORE_TYPES[(opcode - 0x15) as usize], idx),
+                    0x36..=0x3a => Store(LOAD_STORE_TYPES[(opcode - 0x36) as usize], idx),
+                    0x84 => Increment(idx, reader.i16()?),
+                    0xa9 => Ret(idx),
+                    _ => return Err(format!("Invalid wide opcode {:#04x} at {}", opcode, pc)),
+                }
+            }
+            0xc5 => MultiANewArray(pool.pool.class_name(reader.u16()?)?, reader.u8()?),
+            0xc6 => IfNull(branch_target(pc, reader.i16()? as i32)?),
+            0xc7 => IfNonNull(branch_target(pc, reader.i16()? as i32)?),
+            0xc8 => Goto(branch_target(pc, reader.i32()?)?),
+            0xc9 => Jsr(branch_target(pc, reader.i32()?)?),
+            _ => Unknown(opcode),
+        };
+        insns.push((pc as u32, instruction));
+    }
+    Ok(insns)
+}
@@ -5,14 +5,42 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 use std::{
-    collections::HashMap,
-    sync::{Arc, RwLock},
+    collections::{HashSet, VecDeque},
+    sync::Arc,
 };
 
 use coeus_macros::iterator;
 use rayon::prelude::*;
 
-use super::{AndroidManifest, Class, DexFile, Field, Method, Proto, StringEntry};
+use super::{
+    AndroidManifest, ApiClass, ApiDatabase, ApiMember, Class, DexFile, Field, Method, MethodData,
+    Proto, StringEntry, XrefDatabase, XrefIndex, XrefSite,
+};
+
+const NO_INDEX: u32 = 0xffffffff;
+
+/// A class defined by the app, or failing that, by the android framework
+#[derive(Debug, Clone)]
+pub enum ResolvedClass<'a> {
+    App(Arc<DexFile>, Arc<Class>),
+    Framework(&'a ApiClass),
+}
+
+impl ResolvedClass<'_> {
+    pub fn name(&self) -> &str {
+        match self {
+            ResolvedClass::App(_, class) => &class.class_name,
+            ResolvedClass::Framework(class) => &class.name,
+        }
+    }
+}
+
+/// A method implemented by the app, or declared by the android framework
+#[derive(Debug, Clone)]
+pub enum ResolvedMethod<'a> {
+    App(Arc<DexFile>, Arc<MethodData>),
+    Framework(&'a ApiClass, &'a ApiMember),
+}
 
 #[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
 /// A multi_dexfile tries to make it easier working with products using
@@ -24,14 +52,13 @@ use super::{AndroidManifest, Class, DexFile, Field, Method, Proto, StringEntry};
 /// The android manifest is a best effort transpilation from XML to a object, though various
 /// types are missing. If the content is needed, use the manifest_content and a XML parsing library.
 pub struct MultiDexFile {
-    #[serde(skip_serializing, skip_deserializing)]
-    /// Loaded classes tries to point to the dex file containing
-    /// the class data
-    loaded_classes: DexLock<HashMap<String, String>>,
     pub manifest_content: String,
     pub android_manifest: AndroidManifest,
     pub primary: Arc<DexFile>,
     pub secondary: Vec<Arc<DexFile>>,
+    /// Cross references of all dex files, built on the first query
+    #[serde(skip_serializing_if = "crate::models::is_compact", default)]
+    pub xref_index: XrefIndex,
 }
 
 impl<'a> MultiDexFile {
@@ -42,14 +69,31 @@ impl<'a> MultiDexFile {
         secondary: Vec<DexFile>,
     ) -> Self {
         Self {
-            loaded_classes: DexLock(RwLock::new(HashMap::new())),
             android_manifest,
             manifest_content,
             primary: Arc::new(primary),
             secondary: secondary.into_iter().map(Arc::new).collect(),
+            xref_index: XrefIndex::default(),
         }
     }
 
+    /// The cross references of all dex files. The database is built on the first call, so the
+    /// dex files must not be modified afterwards.
+    pub fn xrefs(&self) -> &XrefDatabase {
+        self.xref_index.get(self)
+    }
+
+    pub fn has_xrefs(&self) -> bool {
+        self.xref_index.is_built()
+    }
+
+    /// The dex file and the method containing a cross reference
+    pub fn xref_method(&self, site: &XrefSite) -> Option<(Arc<DexFile>, Arc<Method>)> {
+        let dex = self.dex_files().nth(site.dex as usize)?;
+        let method = dex.methods.get(site.method_idx as usize)?;
+        Some((dex.clone(), method.clone()))
+    }
+
     pub fn get_implementations_for(&self, class: &Class) -> Vec<(Arc<DexFile>,Arc<Class>)> {
         let mut impls = self.primary.get_implementations_for(class);
         for s in &self.secondary {
@@ -158,30 +202,29 @@ impl<'a> MultiDexFile {
         entries.collect()
     }
 
+    /// All dex files, starting with the primary one
+    pub fn dex_files(&self) -> impl Iterator<Item = &Arc<DexFile>> {
+        std::iter::once(&self.primary).chain(self.secondary.iter())
+    }
+
     pub fn get_type_idx_for_string(&self, type_name: &str) -> Option<(Arc<DexFile>, u16)> {
-        let the_iter = iterator!(self.primary.types)
-            .enumerate()
-            .map(|(i, s)| (i, self.primary.clone(), s))
-            .chain(iterator!(self.secondary).flat_map(|d| {
-                iterator!(d.types)
-                    .enumerate()
-                    .map(move |(i, s)| (i, d.clone(), s))
-            }))
-            .filter_map(
-                |(idx, file, name_idx)| match file.get_string(*name_idx as usize) {
-                    Some(tn) if tn == type_name => Some((file, idx as u16)),
-                    _ => None,
-                },
-            );
-        #[cfg(not(target_arch = "wasm32"))]
-        {
-            the_iter.find_any(|_| true)
-        }
-        #[cfg(target_arch = "wasm32")]
-        {
-            let mut the_iter = the_iter;
-            the_iter.next()
+        self.dex_files()
+            .find_map(|dex| dex.get_type_idx(type_name).map(|idx| (dex.clone(), idx)))
+    }
+
+    /// Get the class with this name. Definitions with class data are preferred over
+    /// declarations without.
+    pub fn get_class_by_name(&self, class_name: &str) -> Option<(Arc<DexFile>, Arc<Class>)> {
+        let mut declaration = None;
+        for dex in self.dex_files() {
+            if let Some(class) = dex.get_class_by_name(class_name) {
+                if class.class_data.is_some() {
+                    return Some((dex.clone(), class));
+                }
+                declaration.get_or_insert((dex.clone(), class));
+            }
         }
+        declaration
     }
 
     pub fn classes(&self) -> Vec<(Arc<DexFile>, Arc<Class>)> {
@@ -227,71 +270,107 @@ impl<'a> MultiDexFile {
     }
 
     pub fn load_class(&'a self, class_name: &str) -> Option<(Arc<Class>, Arc<DexFile>)> {
-        //check if we loaded the class before
-
-        if let Some(class) = self.loaded_classes.0.read().unwrap().get(class_name) {
-            if &self.primary.identifier == class {
-                return self
-                    .primary
-                    .get_class_by_name(class_name)
-                    .map(|c| (c, self.primary.clone()));
-            } else {
-                #[cfg(not(target_arch = "wasm32"))]
-                if let Some(df) = self
-                    .secondary
-                    .par_iter()
-                    .find_first(|a| &a.identifier == class)
-                {
-                    return df
-                        .get_class_by_name(class_name)
-                        .map(|c| (c, self.primary.clone()));
-                }
-                #[cfg(target_arch = "wasm32")]
-                if let Some(df) = self.secondary.iter().find(|a| &a.identifier == class) {
-                    return df
-                        .get_class_by_name(class_name)
-                        .map(|c| (c, self.primary.clone()));
-                }
-            }
-        }
+        self.dex_files().find_map(|dex| {
+            dex.get_class_by_name(class_name)
+                .filter(|class| class.class_data.is_some())
+                .map(|class| (class, dex.clone()))
+        })
+    }
 
-        let classes = self.classes();
-        #[cfg(not(target_arch = "wasm32"))]
-        let class = classes
-            .par_iter()
-            .find_first(|(_, class)| class.class_name == class_name && class.class_data.is_some());
-
-        #[cfg(target_arch = "wasm32")]
-        let class = classes
-            .iter()
-            .find(|(f, class)| class.class_name == class_name && class.class_data.is_some());
-        if let Some(f) = class {
-            self.loaded_classes
-                .0
-                .write()
-                .unwrap()
-                .insert(f.0.identifier.clone(), class_name.to_string());
-            Some((f.1.clone(), f.0.clone()))
-        } else {
-            None
+    /// The API level used for framework lookups: the target sdk version of the app, or the
+    /// highest level of the database
+    fn api_level(&self, api: &ApiDatabase) -> u32 {
+        self.android_manifest
+            .target_sdk_version()
+            .unwrap_or(api.api_level)
+    }
+
+    /// Resolve a class by its descriptor, falling back to the framework classes of `api` for
+    /// classes the app does not define
+    pub fn resolve_class<'b>(
+        &self,
+        class_name: &str,
+        api: &'b ApiDatabase,
+    ) -> Option<ResolvedClass<'b>> {
+        if let Some((dex, class)) = self.get_class_by_name(class_name) {
+            return Some(ResolvedClass::App(dex, class));
         }
+        api.get_class(class_name)
+            .filter(|class| class.levels.is_available(self.api_level(api)))
+            .map(ResolvedClass::Framework)
     }
-}
 
-#[derive(Debug)]
-struct DexLock<T>(RwLock<T>);
+    /// Resolve a method, given as name and prototype (e.g. `loadUrl(Ljava/lang/String;)V`), the
+    /// way the runtime would: the superclass chain is searched first, then all implemented
+    /// interfaces. Superclasses and interfaces missing from the app are looked up in `api`.
+    pub fn resolve_method<'b>(
+        &self,
+        class_name: &str,
+        signature: &str,
+        api: &'b ApiDatabase,
+    ) -> Option<ResolvedMethod<'b>> {
+        let (method_name, proto) = signature.split_at(signature.find('(')?);
+        let api_level = self.api_level(api);
+        let find = |class: &ResolvedClass<'b>| match class {
+            ResolvedClass::App(dex, class) => dex
+                .get_method_by_name_and_prototype(&class.class_name, method_name, proto)
+                .map(|method| ResolvedMethod::App(dex.clone(), method)),
+            ResolvedClass::Framework(class) => class
+                .method(signature)
+                .filter(|method| method.levels.is_available(api_level))
+                .map(|method| ResolvedMethod::Framework(class, method)),
+        };
+        let super_class = |class: &ResolvedClass<'b>| match class {
+            ResolvedClass::App(dex, class) if class.super_class != NO_INDEX => dex
+                .get_type_name(class.super_class as usize)
+                .map(|name| name.to_string()),
+            ResolvedClass::App(..) => None,
+            ResolvedClass::Framework(class) => {
+                class.super_class(api_level).map(|name| name.to_string())
+            }
+        };
+        let interfaces = |class: &ResolvedClass<'b>| -> Vec<String> {
+            match class {
+                ResolvedClass::App(dex, class) => class
+                    .interfaces
+                    .iter()
+                    .filter_map(|&idx| dex.get_type_name(idx).map(|name| name.to_string()))
+                    .collect(),
+                ResolvedClass::Framework(class) => class
+                    .interfaces(api_level)
+                    .map(|name| name.to_string())
+                    .collect(),
+            }
+        };
 
-impl<T> Clone for DexLock<T>
-where
-    T: Clone,
-{
-    fn clone(&self) -> Self {
-        DexLock(RwLock::new(self.0.read().unwrap().clone()))
-    }
-}
+        let mut visited = HashSet::new();
+        let mut superclasses = vec![];
+        let mut current = self.resolve_class(class_name, api);
+        while let Some(class) = current {
+            if !visited.insert(class.name().to_string()) {
+                break;
+            }
+            if let Some(method) = find(&class) {
+                return Some(method);
+            }
+            current = super_class(&class).and_then(|name| self.resolve_class(&name, api));
+            superclasses.push(class);
+        }
 
-impl<U, V> Default for DexLock<HashMap<U, V>> {
-    fn default() -> Self {
-        DexLock(RwLock::new(HashMap::new()))
+        let mut queue: VecDeque<ResolvedClass<'b>> = superclasses.into();
+        while let Some(class) = queue.pop_front() {
+            for interface in interfaces(&class) {
+                if !visited.insert(interface.clone()) {
+                    continue;
+                }
+                if let Some(interface) = self.resolve_class(&interface, api) {
+                    if let Some(method) = find(&interface) {
+                        return Some(method);
+                    }
+                    queue.push_back(interface);
+                }
+            }
+        }
+        None
     }
 }
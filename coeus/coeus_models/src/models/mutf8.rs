@@ -0,0 +1,75 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Modified UTF-8 as used by dex files and the JVM. It differs from UTF-8 in two ways: NUL is
+//! encoded as `0xc0 0x80` and code points outside the BMP are encoded as surrogate pairs, each
+//! surrogate taking three bytes. Since every UTF-16 code unit is encoded on its own, unpaired
+//! surrogates (which obfuscators like to use) are valid as well.
+
+use std::borrow::Cow;
+
+/// Decode modified UTF-8 to UTF-16 code units. Returns `None` for malformed input.
+///
+/// Plain NUL bytes and four byte UTF-8 sequences are not allowed by the format, but are
+/// accepted, as some tools emit them.
+pub fn decode_mutf8(bytes: &[u8]) -> Option<Vec<u16>> {
+    let mut units = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    let continuation = |i: usize| -> Option<u32> {
+        match bytes.get(i) {
+            Some(&b) if b & 0xc0 == 0x80 => Some((b & 0x3f) as u32),
+            _ => None,
+        }
+    };
+    while i < bytes.len() {
+        let b = bytes[i];
+        match b {
+            0x00..=0x7f => {
+                units.push(b as u16);
+                i += 1;
+            }
+            0xc0..=0xdf => {
+                let c = ((b & 0x1f) as u32) << 6 | continuation(i + 1)?;
+                units.push(c as u16);
+                i += 2;
+            }
+            0xe0..=0xef => {
+                let c =
+                    ((b & 0x0f) as u32) << 12 | continuation(i + 1)? << 6 | continuation(i + 2)?;
+                units.push(c as u16);
+                i += 3;
+            }
+            0xf0..=0xf7 => {
+                let c = ((b & 0x07) as u32) << 18
+                    | continuation(i + 1)? << 12
+                    | continuation(i + 2)? << 6
+                    | continuation(i + 3)?;
+                let c = char::from_u32(c)?;
+                let mut pair = [0u16; 2];
+                units.extend_from_slice(c.encode_utf16(&mut pair));
+                i += 4;
+            }
+            _ => return None,
+        }
+    }
+    Some(units)
+}
+
+/// Decode modified UTF-8 to a string. Unpaired surrogates cannot be represented in a rust
+/// string and are replaced with U+FFFD. Returns `None` for malformed input.
+pub fn mutf8_to_str(bytes: &[u8]) -> Option<Cow<'_, str>> {
+    // valid UTF-8 never contains encoded NULs or surrogates, hence it decodes to itself
+    if let Ok(s) = std::str::from_utf8(bytes) {
+        return Some(Cow::Borrowed(s));
+    }
+    let units = decode_mutf8(bytes)?;
+    Some(Cow::Owned(String::from_utf16_lossy(&units)))
+}
+
+/// Encode a string as modified UTF-8
+pub fn encode_mutf8(s: &str) -> Cow<'_, [u8]> {
+    cesu8::to_java_cesu8(s)
+}
@@ -0,0 +1,771 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A typed view on the ELF shared objects shipped in `lib/<abi>/`. In contrast to
+//! `BinaryObject::object`, which hands out the borrowed goblin structures, a `NativeLibrary` owns
+//! all its data and can be serialized together with the other models.
+
+use std::{collections::HashMap, convert::TryInto};
+
+use goblin::{
+    container::Endian,
+    elf::{
+        dynamic::{
+            DF_1_NOW, DF_BIND_NOW, DT_FINI, DT_FINI_ARRAY, DT_FINI_ARRAYSZ, DT_INIT, DT_INIT_ARRAY,
+            DT_INIT_ARRAYSZ,
+        },
+        header::{EM_386, EM_AARCH64, EM_ARM, EM_X86_64, ET_DYN},
+        note::NT_GNU_BUILD_ID,
+        program_header::{PF_X, PT_GNU_RELRO, PT_GNU_STACK, PT_LOAD},
+        Elf,
+    },
+};
+
+/// The architectures Android ships native code for
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum CpuArch {
+    ArmV7,
+    Arm64,
+    X86,
+    X86_64,
+}
+
+impl CpuArch {
+    pub fn from_machine(e_machine: u16) -> Option<Self> {
+        match e_machine {
+            EM_ARM => Some(CpuArch::ArmV7),
+            EM_AARCH64 => Some(CpuArch::Arm64),
+            EM_386 => Some(CpuArch::X86),
+            EM_X86_64 => Some(CpuArch::X86_64),
+            _ => None,
+        }
+    }
+
+    /// The name of the ABI directory in an APK
+    pub fn abi(&self) -> &'static str {
+        match self {
+            CpuArch::ArmV7 => "armeabi-v7a",
+            CpuArch::Arm64 => "arm64-v8a",
+            CpuArch::X86 => "x86",
+            CpuArch::X86_64 => "x86_64",
+        }
+    }
+
+    pub fn is_64(&self) -> bool {
+        matches!(self, CpuArch::Arm64 | CpuArch::X86_64)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NativeSection {
+    pub name: String,
+    /// `SHT_*`
+    pub kind: u32,
+    pub address: u64,
+    pub offset: u64,
+    pub size: u64,
+    /// `SHF_*`
+    pub flags: u64,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NativeSegment {
+    /// `PT_*`
+    pub kind: u32,
+    /// `PF_*`
+    pub flags: u32,
+    pub offset: u64,
+    pub address: u64,
+    pub file_size: u64,
+    pub memory_size: u64,
+    pub align: u64,
+}
+
+impl NativeSegment {
+    pub fn is_executable(&self) -> bool {
+        self.flags & PF_X != 0
+    }
+
+    pub fn contains(&self, address: u64) -> bool {
+        address >= self.address && address - self.address < self.memory_size
+    }
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NativeSymbol {
+    pub name: String,
+    /// The address of the symbol, with the thumb bit cleared on ARM
+    pub address: u64,
+    pub size: u64,
+    /// Set if the lowest bit of the address was set on ARM
+    pub thumb: bool,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum ImportCategory {
+    Libc,
+    Crypto,
+    Other,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NativeImport {
+    pub name: String,
+    /// The library providing the symbol, only known if the library uses symbol versioning
+    pub library: Option<String>,
+    pub category: ImportCategory,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum Relro {
+    None,
+    /// `PT_GNU_RELRO` without immediate binding, the GOT stays writable
+    Partial,
+    Full,
+}
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct NativeHardening {
+    pub relro: Relro,
+    /// The stack is not executable (`PT_GNU_STACK` without `PF_X`)
+    pub nx: bool,
+    /// `__stack_chk_fail` or `__stack_chk_guard` is used
+    pub stack_canary: bool,
+    /// Any of the checked `__*_chk` variants of libc functions is used
+    pub fortify: bool,
+    pub pie: bool,
+}
+
+/// A JNI export name split into its parts, see `NativeSymbol::jni_method`
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct JniName {
+    /// The class descriptor, e.g. `Lcom/example/Native;`
+    pub class_name: String,
+    pub method_name: String,
+    /// The argument part of the prototype (e.g. `(ILjava/lang/String;)`) for overloaded methods
+    pub arguments: Option<String>,
+}
+
+#[derive(Clone, Debug, PartialEq, serde::Serialize, serde::Deserialize)]
+pub struct NativeLibrary {
+    pub arch: Option<CpuArch>,
+    pub is_64: bool,
+    pub little_endian: bool,
+    pub soname: Option<String>,
+    /// `DT_NEEDED` entries
+    pub needed: Vec<String>,
+    pub sections: Vec<NativeSection>,
+    pub segments: Vec<NativeSegment>,
+    /// Function pointers in `DT_INIT_ARRAY` (including `DT_INIT`), run when the library is loaded
+    pub init_array: Vec<u64>,
+    pub fini_array: Vec<u64>,
+    /// Defined dynamic function symbols
+    pub exports: Vec<NativeSymbol>,
+    pub imports: Vec<NativeImport>,
+    /// Hex encoded `NT_GNU_BUILD_ID`
+    pub build_id: Option<String>,
+    /// There is no `.symtab`, only the dynamic symbols are left
+    pub stripped: bool,
+    pub hardening: NativeHardening,
+    /// The smallest alignment of all `PT_LOAD` segments
+    pub page_alignment: u64,
+}
+
+const PAGE_SIZE_16K: u64 = 0x4000;
+
+const CRYPTO_LIBRARIES: &[&str] = &[
+    "libcrypto.so",
+    "libssl.so",
+    "libsodium.so",
+    "libmbedcrypto.so",
+    "libwolfssl.so",
+    "libgcrypt.so",
+];
+
+const CRYPTO_PREFIXES: &[&str] = &[
+    "EVP_", "AES_", "RSA_", "DSA_", "DH_", "EC_", "ECDH_", "ECDSA_", "BN_", "HMAC", "CMAC_",
+    "RAND_", "SSL_", "TLS_", "X509", "PEM_", "PKCS", "CRYPTO_", "SHA1", "SHA256", "SHA512", "MD5",
+];
+
+const CRYPTO_LIBRARY_PREFIXES: &[&str] = &["mbedtls_", "crypto_", "wc_", "gcry_"];
+
+/// Used when the library does not tell where its imports come from
+const LIBC_FUNCTIONS: &[&str] = &[
+    "abort",
+    "access",
+    "atoi",
+    "atol",
+    "bind",
+    "calloc",
+    "chmod",
+    "close",
+    "closedir",
+    "connect",
+    "dl_iterate_phdr",
+    "dladdr",
+    "dlclose",
+    "dlerror",
+    "dlopen",
+    "dlsym",
+    "dup",
+    "dup2",
+    "execv",
+    "execve",
+    "exit",
+    "fclose",
+    "fcntl",
+    "fdopen",
+    "fgets",
+    "fopen",
+    "fork",
+    "fprintf",
+    "fputs",
+    "fread",
+    "free",
+    "fseek",
+    "fstat",
+    "ftell",
+    "fwrite",
+    "getenv",
+    "getpid",
+    "getppid",
+    "getaddrinfo",
+    "gettid",
+    "gettimeofday",
+    "inet_addr",
+    "ioctl",
+    "kill",
+    "listen",
+    "lseek",
+    "lstat",
+    "malloc",
+    "memchr",
+    "memcmp",
+    "memcpy",
+    "memmove",
+    "memset",
+    "mkdir",
+    "mmap",
+    "mprotect",
+    "munmap",
+    "open",
+    "opendir",
+    "pipe",
+    "popen",
+    "prctl",
+    "printf",
+    "pthread_create",
+    "pthread_join",
+    "pthread_mutex_lock",
+    "pthread_mutex_unlock",
+    "ptrace",
+    "read",
+    "readdir",
+    "readlink",
+    "realloc",
+    "recv",
+    "remove",
+    "rename",
+    "send",
+    "snprintf",
+    "socket",
+    "sprintf",
+    "sscanf",
+    "stat",
+    "strcat",
+    "strchr",
+    "strcmp",
+    "strcpy",
+    "strdup",
+    "strlen",
+    "strncmp",
+    "strncpy",
+    "strrchr",
+    "strstr",
+    "strtol",
+    "syscall",
+    "sysconf",
+    "system",
+];
+
+impl NativeLibrary {
+    /// Parse an ELF file. Returns `None` for anything else.
+    pub fn parse(data: &[u8]) -> Option<Self> {
+        let elf = Elf::parse(data).ok()?;
+        Some(Self::from_elf(&elf, data))
+    }
+
+    pub fn from_elf(elf: &Elf, data: &[u8]) -> Self {
+        let arch = CpuArch::from_machine(elf.header.e_machine);
+        let little_endian = !matches!(elf.header.endianness(), Ok(Endian::Big));
+        // only thumb code has the lowest address bit set
+        let address_mask = if arch == Some(CpuArch::ArmV7) { !1 } else { !0 };
+
+        let sections = elf
+            .section_headers
+            .iter()
+            .map(|header| NativeSection {
+                name: elf
+                    .shdr_strtab
+                    .get_at(header.sh_name)
+                    .unwrap_or_default()
+                    .to_string(),
+                kind: header.sh_type,
+                address: header.sh_addr,
+                offset: header.sh_offset,
+                size: header.sh_size,
+                flags: header.sh_flags,
+            })
+            .collect();
+        let segments: Vec<NativeSegment> = elf
+            .program_headers
+            .iter()
+            .map(|header| NativeSegment {
+                kind: header.p_type,
+                flags: header.p_flags,
+                offset: header.p_offset,
+                address: header.p_vaddr,
+                file_size: header.p_filesz,
+                memory_size: header.p_memsz,
+                align: header.p_align,
+            })
+            .collect();
+
+        let mut library = NativeLibrary {
+            arch,
+            is_64: elf.is_64,
+            little_endian,
+            soname: elf.soname.map(|soname| soname.to_string()),
+            needed: elf.libraries.iter().map(|lib| lib.to_string()).collect(),
+            sections,
+            segments,
+            init_array: vec![],
+            fini_array: vec![],
+            exports: vec![],
+            imports: vec![],
+            build_id: build_id(elf, data),
+            stripped: elf.syms.is_empty(),
+            hardening: NativeHardening {
+                relro: Relro::None,
+                nx: false,
+                stack_canary: false,
+                fortify: false,
+                pie: elf.header.e_type == ET_DYN,
+            },
+            page_alignment: 0,
+        };
+        library.page_alignment = library
+            .segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD)
+            .map(|segment| segment.align)
+            .min()
+            .unwrap_or(0);
+
+        let versions = imported_versions(elf);
+        for (index, sym) in elf.dynsyms.iter().enumerate() {
+            let Some(name) = elf.dynstrtab.get_at(sym.st_name) else {
+                continue;
+            };
+            if name.is_empty() {
+                continue;
+            }
+            if sym.is_import() {
+                let library_name = elf
+                    .versym
+                    .as_ref()
+                    .and_then(|versym| versym.get_at(index))
+                    .and_then(|version| versions.get(&version.version()))
+                    .cloned();
+                let category = import_category(name, library_name.as_deref());
+                library.imports.push(NativeImport {
+                    name: name.to_string(),
+                    library: library_name,
+                    category,
+                });
+            } else if sym.is_function() {
+                library.exports.push(NativeSymbol {
+                    name: name.to_string(),
+                    address: sym.st_value & address_mask,
+                    size: sym.st_size,
+                    thumb: address_mask != !0 && sym.st_value & 1 == 1,
+                });
+            }
+        }
+
+        let hardening = &mut library.hardening;
+        hardening.stack_canary = library
+            .imports
+            .iter()
+            .any(|import| import.name.starts_with("__stack_chk_"));
+        hardening.fortify = library
+            .imports
+            .iter()
+            .any(|import| import.name.starts_with("__") && import.name.ends_with("_chk"));
+        hardening.nx = elf
+            .program_headers
+            .iter()
+            .any(|header| header.p_type == PT_GNU_STACK && header.p_flags & PF_X == 0);
+        if elf
+            .program_headers
+            .iter()
+            .any(|header| header.p_type == PT_GNU_RELRO)
+        {
+            let bind_now = elf.dynamic.as_ref().map_or(false, |dynamic| {
+                dynamic.info.flags & DF_BIND_NOW != 0 || dynamic.info.flags_1 & DF_1_NOW != 0
+            });
+            hardening.relro = if bind_now {
+                Relro::Full
+            } else {
+                Relro::Partial
+            };
+        }
+
+        // the dynamic info of goblin holds file offsets, the arrays are read by address
+        let tag = |tag| dynamic_value(elf, tag).unwrap_or(0);
+        if tag(DT_INIT) != 0 {
+            library.init_array.push(tag(DT_INIT) & address_mask);
+        }
+        library.init_array.extend(library.read_function_pointers(
+            elf,
+            data,
+            tag(DT_INIT_ARRAY),
+            tag(DT_INIT_ARRAYSZ),
+            address_mask,
+        ));
+        if tag(DT_FINI) != 0 {
+            library.fini_array.push(tag(DT_FINI) & address_mask);
+        }
+        library.fini_array.extend(library.read_function_pointers(
+            elf,
+            data,
+            tag(DT_FINI_ARRAY),
+            tag(DT_FINI_ARRAYSZ),
+            address_mask,
+        ));
+        library
+    }
+
+    /// The file offset of a virtual address
+    pub fn file_offset(&self, address: u64) -> Option<u64> {
+        self.segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD)
+            .find(|segment| {
+                address >= segment.address && address - segment.address < segment.file_size
+            })
+            .and_then(|segment| (address - segment.address).checked_add(segment.offset))
+    }
+
+    pub fn section(&self, name: &str) -> Option<&NativeSection> {
+        self.sections.iter().find(|section| section.name == name)
+    }
+
+    /// Exported `Java_*` functions, which are bound to `native` methods by name
+    pub fn jni_exports(&self) -> impl Iterator<Item = &NativeSymbol> {
+        self.exports
+            .iter()
+            .filter(|symbol| symbol.name.starts_with("Java_"))
+    }
+
+    pub fn jni_onload(&self) -> Option<&NativeSymbol> {
+        self.exports
+            .iter()
+            .find(|symbol| symbol.name == "JNI_OnLoad")
+    }
+
+    pub fn imports_of(&self, category: ImportCategory) -> impl Iterator<Item = &NativeImport> {
+        self.imports
+            .iter()
+            .filter(move |import| import.category == category)
+    }
+
+    /// Android 15 devices may use 16 KB pages, which requires all loadable segments to be
+    /// aligned accordingly.
+    pub fn supports_16k_pages(&self) -> bool {
+        self.page_alignment >= PAGE_SIZE_16K
+            && self
+                .segments
+                .iter()
+                .filter(|segment| segment.kind == PT_LOAD)
+                .all(|segment| segment.offset % PAGE_SIZE_16K == segment.address % PAGE_SIZE_16K)
+    }
+
+    fn read_pointer(&self, data: &[u8], address: u64) -> Option<u64> {
+        let offset = self.file_offset(address)? as usize;
+        let size = if self.is_64 { 8 } else { 4 };
+        let bytes = data.get(offset..offset + size)?;
+        Some(match (self.is_64, self.little_endian) {
+            (true, true) => u64::from_le_bytes(bytes.try_into().ok()?),
+            (true, false) => u64::from_be_bytes(bytes.try_into().ok()?),
+            (false, true) => u32::from_le_bytes(bytes.try_into().ok()?) as u64,
+            (false, false) => u32::from_be_bytes(bytes.try_into().ok()?) as u64,
+        })
+    }
+
+    /// The number of bytes from the address to the end of its segment in the file. The sizes in
+    /// the dynamic section are not trusted beyond that.
+    fn available_bytes(&self, data: &[u8], address: u64) -> u64 {
+        let Some(offset) = self.file_offset(address) else {
+            return 0;
+        };
+        let in_segment = self
+            .segments
+            .iter()
+            .filter(|segment| segment.kind == PT_LOAD)
+            .find(|segment| {
+                address >= segment.address && address - segment.address < segment.file_size
+            })
+            .map_or(0, |segment| segment.file_size - (address - segment.address));
+        in_segment.min((data.len() as u64).saturating_sub(offset))
+    }
+
+    /// Read an array of function pointers. With `RELA` relocations the slots are empty in the
+    /// file and the addend of the relocation holds the address.
+    fn read_function_pointers(
+        &self,
+        elf: &Elf,
+        data: &[u8],
+        address: u64,
+        size: u64,
+        address_mask: u64,
+    ) -> Vec<u64> {
+        if address == 0 {
+            return vec![];
+        }
+        let pointer_size = if self.is_64 { 8 } else { 4 };
+        let count = (size / pointer_size).min(self.available_bytes(data, address) / pointer_size);
+        let invalid = if self.is_64 {
+            u64::MAX
+        } else {
+            u32::MAX as u64
+        };
+        (0..count)
+            .map(|i| address + i * pointer_size)
+            .filter_map(|slot| {
+                let value = self.read_pointer(data, slot).unwrap_or(0);
+                if value != 0 && value != invalid {
+                    return Some(value);
+                }
+                elf.dynrelas
+                    .iter()
+                    .find(|reloc| reloc.r_offset == slot)
+                    .and_then(|reloc| reloc.r_addend)
+                    .map(|addend| addend as u64)
+            })
+            .filter(|&value| value != 0 && value != invalid)
+            .map(|value| value & address_mask)
+            .collect()
+    }
+}
+
+impl NativeSymbol {
+    /// Split a `Java_<class>_<method>[__<arguments>]` export into its parts, undoing the JNI name
+    /// mangling (`_1` is `_`, `_2` is `;`, `_3` is `[` and `_0xxxx` a unicode character).
+    pub fn jni_method(&self) -> Option<JniName> {
+        demangle_jni_name(&self.name)
+    }
+}
+
+pub fn demangle_jni_name(name: &str) -> Option<JniName> {
+    let mangled = name.strip_prefix("Java_")?;
+    let (qualified, arguments) = match mangled.find("__") {
+        Some(pos) => (&mangled[..pos], Some(&mangled[pos + 2..])),
+        None => (mangled, None),
+    };
+    let qualified = demangle_jni_part(qualified)?;
+    let (class_name, method_name) = qualified.rsplit_once('/')?;
+    if class_name.is_empty() || method_name.is_empty() {
+        return None;
+    }
+    Some(JniName {
+        class_name: format!("L{};", class_name),
+        method_name: method_name.to_string(),
+        arguments: match arguments {
+            Some(arguments) => Some(format!("({})", demangle_jni_part(arguments)?)),
+            None => None,
+        },
+    })
+}
+
+/// Unescaped underscores separate the package parts, they are returned as `/`
+fn demangle_jni_part(mangled: &str) -> Option<String> {
+    let mut result = String::with_capacity(mangled.len());
+    let mut chars = mangled.chars();
+    while let Some(c) = chars.next() {
+        if c != '_' {
+            result.push(c);
+            continue;
+        }
+        match chars.clone().next() {
+            Some('1') => result.push('_'),
+            Some('2') => result.push(';'),
+            Some('3') => result.push('['),
+            Some('0') => {
+                chars.next();
+                let code: String = chars.by_ref().take(4).collect();
+                result.push(char::from_u32(u32::from_str_radix(&code, 16).ok()?)?);
+                continue;
+            }
+            _ => {
+                result.push('/');
+                continue;
+            }
+        }
+        chars.next();
+    }
+    Some(result)
+}
+
+fn dynamic_value(elf: &Elf, tag: u64) -> Option<u64> {
+    elf.dynamic
+        .as_ref()?
+        .dyns
+        .iter()
+        .find(|entry| entry.d_tag == tag)
+        .map(|entry| entry.d_val)
+}
+
+fn build_id(elf: &Elf, data: &[u8]) -> Option<String> {
+    let notes = elf
+        .iter_note_headers(data)
+        .or_else(|| elf.iter_note_sections(data, None))?;
+    notes
+        .flatten()
+        .find(|note| note.n_type == NT_GNU_BUILD_ID && note.name == "GNU")
+        .map(|note| note.desc.iter().map(|b| format!("{:02x}", b)).collect())
+}
+
+/// Map the version indices of `DT_VERNEED` to the library they come from
+fn imported_versions(elf: &Elf) -> HashMap<u16, String> {
+    let mut versions = HashMap::new();
+    let Some(verneed) = &elf.verneed else {
+        return versions;
+    };
+    for need in verneed.iter() {
+        let Some(library) = elf.dynstrtab.get_at(need.vn_file) else {
+            continue;
+        };
+        for aux in need.iter() {
+            versions.insert(aux.vna_other, library.to_string());
+        }
+    }
+    versions
+}
+
+fn import_category(name: &str, library: Option<&str>) -> ImportCategory {
+    match library {
+        Some(library) if CRYPTO_LIBRARIES.iter().any(|l| library.starts_with(l)) => {
+            return ImportCategory::Crypto
+        }
+        Some(library) if library.starts_with("libc.so") => return ImportCategory::Libc,
+        _ => {}
+    }
+    if CRYPTO_PREFIXES
+        .iter()
+        .any(|prefix| name.starts_with(prefix))
+        || CRYPTO_LIBRARY_PREFIXES
+            .iter()
+            .any(|prefix| name.starts_with(prefix))
+    {
+        ImportCategory::Crypto
+    } else if library.is_none()
+        && (LIBC_FUNCTIONS.contains(&name) || name.starts_with("__") && name.ends_with("_chk"))
+    {
+        ImportCategory::Libc
+    } else {
+        ImportCategory::Other
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A 64 bit AArch64 shared object with one loadable segment and an init array of two
+    /// functions at 0x180, followed by empty slots up to the end of the file
+    fn elf_with_init_array(init_array_size: u64) -> Vec<u8> {
+        let mut data = vec![0u8; 0x200];
+        put(&mut data, 0, b"\x7fELF\x02\x01\x01");
+        put(&mut data, 16, &3u16.to_le_bytes());
+        put(&mut data, 18, &EM_AARCH64.to_le_bytes());
+        put(&mut data, 20, &1u32.to_le_bytes());
+        put(&mut data, 32, &64u64.to_le_bytes());
+        put(&mut data, 52, &[64, 0, 56, 0, 2, 0, 64, 0]);
+        for (i, &(kind, offset, size)) in [(PT_LOAD, 0u64, 0x200u64), (2, 0x100, 0x30)]
+            .iter()
+            .enumerate()
+        {
+            let header = 64 + i * 56;
+            put(&mut data, header, &kind.to_le_bytes());
+            put(&mut data, header + 4, &6u32.to_le_bytes());
+            put(&mut data, header + 8, &offset.to_le_bytes());
+            put(&mut data, header + 16, &offset.to_le_bytes());
+            put(&mut data, header + 24, &offset.to_le_bytes());
+            put(&mut data, header + 32, &size.to_le_bytes());
+            put(&mut data, header + 40, &size.to_le_bytes());
+            put(&mut data, header + 48, &0x1000u64.to_le_bytes());
+        }
+        put(&mut data, 0x100, &DT_INIT_ARRAY.to_le_bytes());
+        put(&mut data, 0x108, &0x180u64.to_le_bytes());
+        put(&mut data, 0x110, &DT_INIT_ARRAYSZ.to_le_bytes());
+        put(&mut data, 0x118, &init_array_size.to_le_bytes());
+        put(&mut data, 0x180, &0x1000u64.to_le_bytes());
+        put(&mut data, 0x188, &0x2000u64.to_le_bytes());
+        data
+    }
+
+    #[test]
+    fn reads_init_arrays() {
+        let library = NativeLibrary::parse(&elf_with_init_array(16)).unwrap();
+        assert_eq!(library.arch, Some(CpuArch::Arm64));
+        assert_eq!(library.init_array, vec![0x1000, 0x2000]);
+        assert_eq!(library.file_offset(0x188), Some(0x188));
+    }
+
+    #[test]
+    fn init_array_sizes_are_limited_to_the_segment() {
+        let library = NativeLibrary::parse(&elf_with_init_array(u64::MAX)).unwrap();
+        assert_eq!(library.init_array, vec![0x1000, 0x2000]);
+    }
+
+    #[test]
+    fn file_offsets_do_not_overflow() {
+        let mut library = NativeLibrary::parse(&elf_with_init_array(16)).unwrap();
+        library.segments[0].offset = u64::MAX;
+        assert_eq!(library.file_offset(0x10), None);
+        assert_eq!(library.file_offset(0x1000), None);
+    }
+
+    #[test]
+    fn rejects_malformed_files() {
+        assert!(NativeLibrary::parse(b"").is_none());
+        assert!(NativeLibrary::parse(b"\x7fELF\x02\x01\x01").is_none());
+        assert!(NativeLibrary::parse(&[0xff; 64]).is_none());
+    }
+
+    #[test]
+    fn demangles_jni_names() {
+        assert_eq!(
+            demangle_jni_name("Java_com_example_Native_run__ILjava_lang_String_2"),
+            Some(JniName {
+                class_name: "Lcom/example/Native;".to_string(),
+                method_name: "run".to_string(),
+                arguments: Some("(ILjava/lang/String;)".to_string()),
+            })
+        );
+        assert_eq!(
+            demangle_jni_name("Java_a_1b_c_0004e").map(|name| name.class_name),
+            Some("La_b;".to_string())
+        );
+        assert_eq!(demangle_jni_name("Java_run"), None);
+        assert_eq!(demangle_jni_name("Java_a_b_0zz"), None);
+    }
+}
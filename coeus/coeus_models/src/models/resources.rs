@@ -0,0 +1,1288 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Models and parser for the compiled resource table (`resources.arsc`).
+//!
+//! The table is parsed into packages, types and configurations. Every entry is kept
+//! per `ResTable_config`, such that a resource id can be resolved to all of its
+//! values together with the qualifiers (e.g. `de-rCH`, `xhdpi`, `v26`) they apply to.
+
+use std::{
+    collections::{BTreeMap, HashMap},
+    convert::TryInto,
+    sync::OnceLock,
+};
+
+const RES_STRING_POOL_TYPE: u16 = 0x0001;
+const RES_TABLE_TYPE: u16 = 0x0002;
+const RES_TABLE_PACKAGE_TYPE: u16 = 0x0200;
+const RES_TABLE_TYPE_TYPE: u16 = 0x0201;
+const RES_TABLE_TYPE_SPEC_TYPE: u16 = 0x0202;
+const RES_TABLE_LIBRARY_TYPE: u16 = 0x0203;
+
+const STRING_POOL_UTF8_FLAG: u32 = 0x0000_0100;
+
+const TYPE_FLAG_SPARSE: u8 = 0x01;
+const TYPE_FLAG_OFFSET16: u8 = 0x02;
+
+const ENTRY_FLAG_COMPLEX: u16 = 0x0001;
+const ENTRY_FLAG_COMPACT: u16 = 0x0008;
+
+/// Maximum number of references we follow before giving up (protects against cycles)
+const MAX_REFERENCE_DEPTH: usize = 16;
+
+/// The id of the android framework package
+pub const FRAMEWORK_PACKAGE_ID: u8 = 0x01;
+/// The id usually assigned to the package of the app itself
+pub const APP_PACKAGE_ID: u8 = 0x7f;
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// A parsed `resources.arsc` file
+pub struct ResourceTable {
+    /// The global string pool, holding all string values
+    pub string_pool: Vec<String>,
+    pub packages: Vec<ResourcePackage>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ResourcePackage {
+    pub id: u8,
+    pub name: String,
+    pub type_names: Vec<String>,
+    pub key_names: Vec<String>,
+    pub types: Vec<ResourceType>,
+    /// Shared libraries referenced by this package (`ResTable_lib_entry`)
+    pub libraries: Vec<(u32, String)>,
+    /// Lookup from `(type, name)` to the resource id
+    names: HashMap<String, HashMap<String, u32>>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ResourceType {
+    pub id: u8,
+    pub name: String,
+    /// Flags of the `ResTable_typeSpec` indicating which configurations an entry varies in
+    pub spec_flags: Vec<u32>,
+    pub configs: Vec<ResourceTypeConfig>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// All entries of a type for one configuration
+pub struct ResourceTypeConfig {
+    pub config: ResourceConfig,
+    pub entries: BTreeMap<u16, ResourceEntry>,
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+pub struct ResourceEntry {
+    /// Index into the key string pool of the package
+    pub key_idx: u32,
+    pub value: ResourceValue,
+}
+
+#[derive(Debug, Clone, PartialEq, serde::Serialize, serde::Deserialize)]
+/// A typed `Res_value` or a bag of values (`ResTable_map_entry`)
+pub enum ResourceValue {
+    Null,
+    Empty,
+    Reference(u32),
+    Attribute(u32),
+    String(String),
+    Float(f32),
+    Dimension(f32, DimensionUnit),
+    Fraction(f32, bool),
+    Integer(i32),
+    Hex(u32),
+    Bool(bool),
+    Color(u32),
+    Bag {
+        parent: u32,
+        items: Vec<(u32, ResourceValue)>,
+    },
+    Unknown(u8, u32),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub enum DimensionUnit {
+    Px,
+    Dp,
+    Sp,
+    Pt,
+    In,
+    Mm,
+    Unknown,
+}
+
+impl std::fmt::Display for DimensionUnit {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            DimensionUnit::Px => "px",
+            DimensionUnit::Dp => "dp",
+            DimensionUnit::Sp => "sp",
+            DimensionUnit::Pt => "pt",
+            DimensionUnit::In => "in",
+            DimensionUnit::Mm => "mm",
+            DimensionUnit::Unknown => "",
+        })
+    }
+}
+
+impl std::fmt::Display for ResourceValue {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            ResourceValue::Null => f.write_str("@null"),
+            ResourceValue::Empty => f.write_str("@empty"),
+            ResourceValue::Reference(id) => write!(f, "@{:#010x}", id),
+            ResourceValue::Attribute(id) => write!(f, "?{:#010x}", id),
+            ResourceValue::String(s) => f.write_str(s),
+            ResourceValue::Float(v) => write!(f, "{}", v),
+            ResourceValue::Dimension(v, unit) => write!(f, "{}{}", v, unit),
+            ResourceValue::Fraction(v, parent) => {
+                write!(f, "{}{}", v * 100.0, if *parent { "%p" } else { "%" })
+            }
+            ResourceValue::Integer(v) => write!(f, "{}", v),
+            ResourceValue::Hex(v) => write!(f, "{:#x}", v),
+            ResourceValue::Bool(v) => write!(f, "{}", v),
+            ResourceValue::Color(v) => write!(f, "#{:08x}", v),
+            ResourceValue::Bag { items, .. } => {
+                let items = items
+                    .iter()
+                    .map(|(key, value)| format!("{:#010x}={}", key, value))
+                    .collect::<Vec<_>>();
+                write!(f, "[{}]", items.join(", "))
+            }
+            ResourceValue::Unknown(ty, data) => write!(f, "({:#x}){:#x}", ty, data),
+        }
+    }
+}
+
+impl ResourceValue {
+    pub fn is_reference(&self) -> bool {
+        matches!(self, ResourceValue::Reference(_))
+    }
+    pub fn as_str(&self) -> Option<&str> {
+        if let ResourceValue::String(s) = self {
+            Some(s)
+        } else {
+            None
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+/// A parsed `ResTable_config`. Fields not present in older tables are zero.
+pub struct ResourceConfig {
+    pub mcc: u16,
+    pub mnc: u16,
+    pub language: String,
+    pub country: String,
+    pub orientation: u8,
+    pub touchscreen: u8,
+    pub density: u16,
+    pub keyboard: u8,
+    pub navigation: u8,
+    pub input_flags: u8,
+    pub screen_width: u16,
+    pub screen_height: u16,
+    pub sdk_version: u16,
+    pub minor_version: u16,
+    pub screen_layout: u8,
+    pub ui_mode: u8,
+    pub smallest_screen_width_dp: u16,
+    pub screen_width_dp: u16,
+    pub screen_height_dp: u16,
+    pub locale_script: String,
+    pub locale_variant: String,
+    pub screen_layout2: u8,
+    pub color_mode: u8,
+}
+
+impl ResourceConfig {
+    fn from_bytes(data: &[u8]) -> Self {
+        let u8_at = |off: usize| data.get(off).copied().unwrap_or(0);
+        let u16_at = |off: usize| u16::from_le_bytes([u8_at(off), u8_at(off + 1)]);
+        let ascii_at = |off: usize, len: usize| {
+            (off..off + len)
+                .map(u8_at)
+                .take_while(|&b| b != 0)
+                .map(|b| b as char)
+                .collect::<String>()
+        };
+        ResourceConfig {
+            mcc: u16_at(4),
+            mnc: u16_at(6),
+            language: unpack_locale_part([u8_at(8), u8_at(9)], b'a'),
+            country: unpack_locale_part([u8_at(10), u8_at(11)], b'0'),
+            orientation: u8_at(12),
+            touchscreen: u8_at(13),
+            density: u16_at(14),
+            keyboard: u8_at(16),
+            navigation: u8_at(17),
+            input_flags: u8_at(18),
+            screen_width: u16_at(20),
+            screen_height: u16_at(22),
+            sdk_version: u16_at(24),
+            minor_version: u16_at(26),
+            screen_layout: u8_at(28),
+            ui_mode: u8_at(29),
+            smallest_screen_width_dp: u16_at(30),
+            screen_width_dp: u16_at(32),
+            screen_height_dp: u16_at(34),
+            locale_script: ascii_at(36, 4),
+            locale_variant: ascii_at(40, 8),
+            screen_layout2: u8_at(48),
+            color_mode: u8_at(49),
+        }
+    }
+
+    pub fn is_default(&self) -> bool {
+        *self == ResourceConfig::default()
+    }
+
+    /// The locale part of the qualifier (e.g. `de`, `de-rCH` or `b+sr+Latn`), or an empty string
+    pub fn locale(&self) -> String {
+        if self.language.is_empty() {
+            return String::new();
+        }
+        if !self.locale_script.is_empty() || !self.locale_variant.is_empty() {
+            let mut parts = vec!["b".to_string(), self.language.clone()];
+            if !self.locale_script.is_empty() {
+                parts.push(self.locale_script.clone());
+            }
+            if !self.country.is_empty() {
+                parts.push(self.country.clone());
+            }
+            if !self.locale_variant.is_empty() {
+                parts.push(self.locale_variant.clone());
+            }
+            return parts.join("+");
+        }
+        if self.country.is_empty() {
+            self.language.clone()
+        } else {
+            format!("{}-r{}", self.language, self.country)
+        }
+    }
+
+    /// Human readable name of the density bucket (e.g. `xhdpi`), or an empty string
+    pub fn density_name(&self) -> String {
+        match self.density {
+            0 => String::new(),
+            120 => "ldpi".to_string(),
+            160 => "mdpi".to_string(),
+            213 => "tvdpi".to_string(),
+            240 => "hdpi".to_string(),
+            320 => "xhdpi".to_string(),
+            480 => "xxhdpi".to_string(),
+            640 => "xxxhdpi".to_string(),
+            0xfffe => "anydpi".to_string(),
+            0xffff => "nodpi".to_string(),
+            d => format!("{}dpi", d),
+        }
+    }
+
+    /// The qualifier string as it would be used for the resource directory (e.g. `de-rCH-land-v21`).
+    /// The default configuration yields an empty string.
+    pub fn qualifier(&self) -> String {
+        let mut parts: Vec<String> = vec![];
+        if self.mcc != 0 {
+            parts.push(format!("mcc{}", self.mcc));
+        }
+        if self.mnc != 0 {
+            parts.push(format!("mnc{}", self.mnc));
+        }
+        let locale = self.locale();
+        if !locale.is_empty() {
+            parts.push(locale);
+        }
+        match self.screen_layout & 0xc0 {
+            0x40 => parts.push("ldltr".to_string()),
+            0x80 => parts.push("ldrtl".to_string()),
+            _ => {}
+        }
+        if self.smallest_screen_width_dp != 0 {
+            parts.push(format!("sw{}dp", self.smallest_screen_width_dp));
+        }
+        if self.screen_width_dp != 0 {
+            parts.push(format!("w{}dp", self.screen_width_dp));
+        }
+        if self.screen_height_dp != 0 {
+            parts.push(format!("h{}dp", self.screen_height_dp));
+        }
+        match self.screen_layout & 0x0f {
+            1 => parts.push("small".to_string()),
+            2 => parts.push("normal".to_string()),
+            3 => parts.push("large".to_string()),
+            4 => parts.push("xlarge".to_string()),
+            _ => {}
+        }
+        match self.screen_layout & 0x30 {
+            0x10 => parts.push("notlong".to_string()),
+            0x20 => parts.push("long".to_string()),
+            _ => {}
+        }
+        match self.screen_layout2 & 0x03 {
+            1 => parts.push("notround".to_string()),
+            2 => parts.push("round".to_string()),
+            _ => {}
+        }
+        match self.color_mode & 0x03 {
+            1 => parts.push("nowidecg".to_string()),
+            2 => parts.push("widecg".to_string()),
+            _ => {}
+        }
+        match self.color_mode & 0x0c {
+            0x04 => parts.push("lowdr".to_string()),
+            0x08 => parts.push("highdr".to_string()),
+            _ => {}
+        }
+        match self.orientation {
+            1 => parts.push("port".to_string()),
+            2 => parts.push("land".to_string()),
+            3 => parts.push("square".to_string()),
+            _ => {}
+        }
+        match self.ui_mode & 0x0f {
+            2 => parts.push("desk".to_string()),
+            3 => parts.push("car".to_string()),
+            4 => parts.push("television".to_string()),
+            5 => parts.push("appliance".to_string()),
+            6 => parts.push("watch".to_string()),
+            7 => parts.push("vrheadset".to_string()),
+            _ => {}
+        }
+        match self.ui_mode & 0x30 {
+            0x10 => parts.push("notnight".to_string()),
+            0x20 => parts.push("night".to_string()),
+            _ => {}
+        }
+        let density = self.density_name();
+        if !density.is_empty() {
+            parts.push(density);
+        }
+        match self.touchscreen {
+            1 => parts.push("notouch".to_string()),
+            3 => parts.push("finger".to_string()),
+            _ => {}
+        }
+        match self.input_flags & 0x03 {
+            1 => parts.push("keysexposed".to_string()),
+            2 => parts.push("keyshidden".to_string()),
+            3 => parts.push("keyssoft".to_string()),
+            _ => {}
+        }
+        match self.keyboard {
+            1 => parts.push("nokeys".to_string()),
+            2 => parts.push("qwerty".to_string()),
+            3 => parts.push("12key".to_string()),
+            _ => {}
+        }
+        match self.input_flags & 0x0c {
+            0x04 => parts.push("navexposed".to_string()),
+            0x08 => parts.push("navhidden".to_string()),
+            _ => {}
+        }
+        match self.navigation {
+            1 => parts.push("nonav".to_string()),
+            2 => parts.push("dpad".to_string()),
+            3 => parts.push("trackball".to_string()),
+            4 => parts.push("wheel".to_string()),
+            _ => {}
+        }
+        if self.screen_width != 0 && self.screen_height != 0 {
+            parts.push(format!("{}x{}", self.screen_width, self.screen_height));
+        }
+        if self.sdk_version != 0 {
+            parts.push(format!("v{}", self.sdk_version));
+        }
+        parts.join("-")
+    }
+}
+
+impl std::fmt::Display for ResourceConfig {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.is_default() {
+            f.write_str("default")
+        } else {
+            f.write_str(&self.qualifier())
+        }
+    }
+}
+
+/// Languages and regions are either two ascii characters, or three characters packed into
+/// two bytes (indicated by the high bit of the first byte).
+fn unpack_locale_part(bytes: [u8; 2], base: u8) -> String {
+    if bytes[0] & 0x80 != 0 {
+        let first = bytes[1] & 0x1f;
+        let second = ((bytes[1] & 0xe0) >> 5) + ((bytes[0] & 0x03) << 3);
+        let third = (bytes[0] & 0x7c) >> 2;
+        [first, second, third]
+            .iter()
+            .map(|c| (c + base) as char)
+            .collect()
+    } else {
+        bytes
+            .iter()
+            .take_while(|&&b| b != 0)
+            .map(|&b| b as char)
+            .collect()
+    }
+}
+
+#[derive(Debug, Clone, serde::Serialize, serde::Deserialize)]
+/// A resource resolved by its id, with the values for all configurations it is defined in
+pub struct Resource {
+    pub id: u32,
+    pub package: String,
+    pub type_name: String,
+    pub name: String,
+    pub values: Vec<(ResourceConfig, ResourceValue)>,
+}
+
+impl Resource {
+    /// The name as it is used in code and xml (e.g. `string/app_name`)
+    pub fn full_name(&self) -> String {
+        format!("{}/{}", self.type_name, self.name)
+    }
+    /// The name as it would be referenced from java (e.g. `R.string.app_name`)
+    pub fn java_name(&self) -> String {
+        format!("R.{}.{}", self.type_name, self.name)
+    }
+    pub fn default_value(&self) -> Option<&ResourceValue> {
+        self.values
+            .iter()
+            .find(|(config, _)| config.is_default())
+            .or_else(|| self.values.first())
+            .map(|(_, value)| value)
+    }
+    /// Get the value for the configuration matching the qualifier exactly (e.g. `de` or `xhdpi-v4`)
+    pub fn value_for_qualifier(&self, qualifier: &str) -> Option<&ResourceValue> {
+        self.values
+            .iter()
+            .find(|(config, _)| config.qualifier() == qualifier)
+            .map(|(_, value)| value)
+    }
+}
+
+/// Helper to read little endian values with bounds checks
+struct ChunkReader<'a> {
+    data: &'a [u8],
+}
+
+impl<'a> ChunkReader<'a> {
+    fn u8(&self, off: usize) -> Option<u8> {
+        self.data.get(off).copied()
+    }
+    fn u16(&self, off: usize) -> Option<u16> {
+        Some(u16::from_le_bytes([self.u8(off)?, self.u8(off + 1)?]))
+    }
+    fn u32(&self, off: usize) -> Option<u32> {
+        Some(u32::from_le_bytes(
+            self.data.get(off..off + 4)?.try_into().ok()?,
+        ))
+    }
+    fn slice(&self, off: usize, len: usize) -> Option<&'a [u8]> {
+        self.data.get(off..off.checked_add(len)?)
+    }
+    fn utf16_fixed(&self, off: usize, chars: usize) -> Option<String> {
+        let bytes = self.slice(off, chars * 2)?;
+        let units = bytes
+            .chunks_exact(2)
+            .map(|c| u16::from_le_bytes([c[0], c[1]]))
+            .take_while(|&c| c != 0)
+            .collect::<Vec<_>>();
+        Some(String::from_utf16_lossy(&units))
+    }
+    /// Returns the chunk type, header size and chunk size at `off`
+    fn chunk_header(&self, off: usize) -> Option<(u16, usize, usize)> {
+        let ty = self.u16(off)?;
+        let header_size = self.u16(off + 2)? as usize;
+        let size = self.u32(off + 4)? as usize;
+        if size < 8 || header_size < 8 || header_size > size {
+            return None;
+        }
+        Some((ty, header_size, size))
+    }
+}
+
+fn parse_string_pool(reader: &ChunkReader, chunk_start: usize) -> Option<Vec<String>> {
+    let (ty, header_size, size) = reader.chunk_header(chunk_start)?;
+    if ty != RES_STRING_POOL_TYPE {
+        return None;
+    }
+    let string_count = reader.u32(chunk_start + 8)? as usize;
+    let flags = reader.u32(chunk_start + 16)?;
+    let strings_start = reader.u32(chunk_start + 20)? as usize;
+    let is_utf8 = flags & STRING_POOL_UTF8_FLAG != 0;
+    let pool = ChunkReader {
+        data: reader.slice(chunk_start, size)?,
+    };
+
+    // every string needs an offset, which bounds the count for malformed pools
+    let mut strings = Vec::with_capacity(string_count.min(size / 4));
+    for i in 0..string_count {
+        let Some(offset) = pool.u32(header_size + i * 4) else {
+            break;
+        };
+        let start = strings_start + offset as usize;
+        let string = if is_utf8 {
+            read_utf8_pool_string(&pool, start)
+        } else {
+            read_utf16_pool_string(&pool, start)
+        };
+        strings.push(string.unwrap_or_default());
+    }
+    Some(strings)
+}
+
+fn read_utf8_pool_string(pool: &ChunkReader, mut off: usize) -> Option<String> {
+    // first the length in utf16 units, then the length in bytes, each one or two bytes long
+    let skip_length = |off: &mut usize| -> Option<usize> {
+        let first = pool.u8(*off)? as usize;
+        *off += 1;
+        if first & 0x80 != 0 {
+            let second = pool.u8(*off)? as usize;
+            *off += 1;
+            Some(((first & 0x7f) << 8) | second)
+        } else {
+            Some(first)
+        }
+    };
+    let _utf16_len = skip_length(&mut off)?;
+    let byte_len = skip_length(&mut off)?;
+    let bytes = pool.slice(off, byte_len)?;
+    Some(String::from_utf8_lossy(bytes).to_string())
+}
+
+fn read_utf16_pool_string(pool: &ChunkReader, mut off: usize) -> Option<String> {
+    let first = pool.u16(off)? as usize;
+    off += 2;
+    let len = if first & 0x8000 != 0 {
+        let second = pool.u16(off)? as usize;
+        off += 2;
+        ((first & 0x7fff) << 16) | second
+    } else {
+        first
+    };
+    pool.utf16_fixed(off, len)
+        .map(|s| s.chars().take(len).collect())
+}
+
+fn parse_value(data_type: u8, data: u32, string_pool: &[String]) -> ResourceValue {
+    match data_type {
+        0x00 if data == 1 => ResourceValue::Empty,
+        0x00 => ResourceValue::Null,
+        0x01 | 0x07 => ResourceValue::Reference(data),
+        0x02 | 0x08 => ResourceValue::Attribute(data),
+        0x03 => string_pool
+            .get(data as usize)
+            .map(|s| ResourceValue::String(s.clone()))
+            .unwrap_or(ResourceValue::Unknown(data_type, data)),
+        0x04 => ResourceValue::Float(f32::from_bits(data)),
+        0x05 => {
+            let unit = match data & 0x0f {
+                0 => DimensionUnit::Px,
+                1 => DimensionUnit::Dp,
+                2 => DimensionUnit::Sp,
+                3 => DimensionUnit::Pt,
+                4 => DimensionUnit::In,
+                5 => DimensionUnit::Mm,
+                _ => DimensionUnit::Unknown,
+            };
+            ResourceValue::Dimension(complex_to_float(data), unit)
+        }
+        0x06 => ResourceValue::Fraction(complex_to_float(data), data & 0x0f == 1),
+        0x10 => ResourceValue::Integer(data as i32),
+        0x11 => ResourceValue::Hex(data),
+        0x12 => ResourceValue::Bool(data != 0),
+        0x1c..=0x1f => ResourceValue::Color(data),
+        _ => ResourceValue::Unknown(data_type, data),
+    }
+}
+
+/// Decode the mantissa and radix of a complex value (dimensions and fractions)
+fn complex_to_float(data: u32) -> f32 {
+    const RADIX_MULTS: [f32; 4] = [
+        1.0 / (1 << 8) as f32,
+        1.0 / (1 << 15) as f32,
+        1.0 / (1 << 23) as f32,
+        1.0 / (1u64 << 31) as f32,
+    ];
+    let mantissa = (data & 0xffff_ff00) as i32;
+    mantissa as f32 * RADIX_MULTS[((data >> 4) & 0x3) as usize]
+}
+
+impl ResourceTable {
+    /// Parse a `resources.arsc` file
+    pub fn parse(data: &[u8]) -> Result<Self, String> {
+        let reader = ChunkReader { data };
+        let (ty, header_size, size) = reader
+            .chunk_header(0)
+            .ok_or_else(|| "Invalid resource table header".to_string())?;
+        if ty != RES_TABLE_TYPE {
+            return Err(format!("Not a resource table (chunk type {:#x})", ty));
+        }
+        let end = size.min(data.len());
+        let mut string_pool = vec![];
+        let mut packages = vec![];
+
+        let mut off = header_size;
+        while off + 8 <= end {
+            let Some((chunk_type, _, chunk_size)) = reader.chunk_header(off) else {
+                break;
+            };
+            match chunk_type {
+                RES_STRING_POOL_TYPE => {
+                    string_pool = parse_string_pool(&reader, off).unwrap_or_default();
+                }
+                RES_TABLE_PACKAGE_TYPE => {
+                    if let Some(package) = parse_package(&reader, off, &string_pool) {
+                        packages.push(package);
+                    }
+                }
+                _ => log::debug!("Skipping resource chunk {:#x}", chunk_type),
+            }
+            off += chunk_size;
+        }
+
+        Ok(ResourceTable {
+            string_pool,
+            packages,
+        })
+    }
+
+    /// The resource table of the android framework, as shipped with `abxml`
+    pub fn framework() -> Option<&'static ResourceTable> {
+        static FRAMEWORK: OnceLock<Option<ResourceTable>> = OnceLock::new();
+        FRAMEWORK
+            .get_or_init(|| ResourceTable::parse(abxml::STR_ARSC).ok())
+            .as_ref()
+    }
+
+    pub fn get_package(&self, package_id: u8) -> Option<&ResourcePackage> {
+        self.packages.iter().find(|p| p.id == package_id)
+    }
+
+    /// Resolve a resource id to all its values over all configurations
+    pub fn get_resource(&self, id: u32) -> Option<Resource> {
+        let package = self.get_package((id >> 24) as u8)?;
+        let type_id = ((id >> 16) & 0xff) as u8;
+        let entry_id = (id & 0xffff) as u16;
+        let ty = package.types.iter().find(|t| t.id == type_id)?;
+
+        let mut name = None;
+        let mut values = vec![];
+        for config in &ty.configs {
+            if let Some(entry) = config.entries.get(&entry_id) {
+                if name.is_none() {
+                    name = package.key_names.get(entry.key_idx as usize).cloned();
+                }
+                values.push((config.config.clone(), entry.value.clone()));
+            }
+        }
+        if values.is_empty() {
+            return None;
+        }
+        Some(Resource {
+            id,
+            package: package.name.clone(),
+            type_name: ty.name.clone(),
+            name: name.unwrap_or_default(),
+            values,
+        })
+    }
+
+    /// Lookup a resource id by its name. Accepts `type/name`, `@type/name` and `@package:type/name`.
+    pub fn get_resource_id(&self, name: &str) -> Option<u32> {
+        let name = name.trim_start_matches('@').trim_start_matches('+');
+        let (package_name, name) = match name.split_once(':') {
+            Some((package, name)) => (Some(package), name),
+            None => (None, name),
+        };
+        let (type_name, entry_name) = name.split_once('/')?;
+        self.packages
+            .iter()
+            .filter(|p| package_name.map(|n| n == p.name).unwrap_or(true))
+            .find_map(|p| p.names.get(type_name)?.get(entry_name).copied())
+    }
+
+    /// Iterate over all resources of a given type (e.g. `string`)
+    pub fn get_resources_of_type(&self, type_name: &str) -> Vec<Resource> {
+        let mut resources = vec![];
+        for package in &self.packages {
+            let Some(names) = package.names.get(type_name) else {
+                continue;
+            };
+            let mut ids = names.values().copied().collect::<Vec<_>>();
+            ids.sort_unstable();
+            resources.extend(ids.into_iter().filter_map(|id| self.get_resource(id)));
+        }
+        resources
+    }
+    /// All resource ids defined in this table
+    pub fn resource_ids(&self) -> Vec<u32> {
+        let mut ids = self
+            .packages
+            .iter()
+            .flat_map(|p| p.names.values().flat_map(|n| n.values().copied()))
+            .collect::<Vec<_>>();
+        ids.sort_unstable();
+        ids
+    }
+}
+
+fn parse_package(
+    reader: &ChunkReader,
+    chunk_start: usize,
+    string_pool: &[String],
+) -> Option<ResourcePackage> {
+    let (_, header_size, size) = reader.chunk_header(chunk_start)?;
+    let id = reader.u32(chunk_start + 8)? as u8;
+    let name = reader.utf16_fixed(chunk_start + 12, 128)?;
+    let type_strings = reader.u32(chunk_start + 268)? as usize;
+    let key_strings = reader.u32(chunk_start + 276)? as usize;
+
+    let type_names = parse_string_pool(reader, chunk_start + type_strings).unwrap_or_default();
+    let key_names = parse_string_pool(reader, chunk_start + key_strings).unwrap_or_default();
+
+    let mut types: Vec<ResourceType> = vec![];
+    let mut libraries = vec![];
+    let end = (chunk_start + size).min(reader.data.len());
+    let mut off = chunk_start + header_size;
+    while off + 8 <= end {
+        let Some((chunk_type, chunk_header_size, chunk_size)) = reader.chunk_header(off) else {
+            break;
+        };
+        match chunk_type {
+            RES_TABLE_TYPE_SPEC_TYPE => {
+                let type_id = reader.u8(off + 8)?;
+                let entry_count = reader.u32(off + 12)? as usize;
+                let spec_flags = (0..entry_count)
+                    .map_while(|i| reader.u32(off + chunk_header_size + i * 4))
+                    .collect();
+                let ty = get_or_insert_type(&mut types, type_id, &type_names);
+                ty.spec_flags = spec_flags;
+            }
+            RES_TABLE_TYPE_TYPE => {
+                let type_id = reader.u8(off + 8)?;
+                if let Some(config) = parse_type_chunk(reader, off, chunk_header_size, string_pool)
+                {
+                    get_or_insert_type(&mut types, type_id, &type_names)
+                        .configs
+                        .push(config);
+                }
+            }
+            RES_TABLE_LIBRARY_TYPE => {
+                let count = reader.u32(off + 8)? as usize;
+                for i in 0..count {
+                    let entry = off + chunk_header_size + i * 260;
+                    let (Some(package_id), Some(package_name)) =
+                        (reader.u32(entry), reader.utf16_fixed(entry + 4, 128))
+                    else {
+                        break;
+                    };
+                    libraries.push((package_id, package_name));
+                }
+            }
+            _ => {}
+        }
+        off += chunk_size;
+    }
+
+    let mut names: HashMap<String, HashMap<String, u32>> = HashMap::new();
+    for ty in &types {
+        let names_of_type = names.entry(ty.name.clone()).or_default();
+        for config in &ty.configs {
+            for (entry_id, entry) in &config.entries {
+                if let Some(key) = key_names.get(entry.key_idx as usize) {
+                    let id = ((id as u32) << 24) | ((ty.id as u32) << 16) | *entry_id as u32;
+                    names_of_type.entry(key.clone()).or_insert(id);
+                }
+            }
+        }
+    }
+
+    Some(ResourcePackage {
+        id,
+        name,
+        type_names,
+        key_names,
+        types,
+        libraries,
+        names,
+    })
+}
+
+fn get_or_insert_type<'a>(
+    types: &'a mut Vec<ResourceType>,
+    type_id: u8,
+    type_names: &[String],
+) -> &'a mut ResourceType {
+    if let Some(pos) = types.iter().position(|t| t.id == type_id) {
+        return &mut types[pos];
+    }
+    types.push(ResourceType {
+        id: type_id,
+        name: type_names
+            .get((type_id as usize).wrapping_sub(1))
+            .cloned()
+            .unwrap_or_else(|| format!("type{:02x}", type_id)),
+        spec_flags: vec![],
+        configs: vec![],
+    });
+    types.last_mut().unwrap()
+}
+
+fn parse_type_chunk(
+    reader: &ChunkReader,
+    chunk_start: usize,
+    header_size: usize,
+    string_pool: &[String],
+) -> Option<ResourceTypeConfig> {
+    let flags = reader.u8(chunk_start + 9)?;
+    let entry_count = reader.u32(chunk_start + 12)? as usize;
+    let entries_start = chunk_start + reader.u32(chunk_start + 16)? as usize;
+    let config_size = reader.u32(chunk_start + 20)? as usize;
+    // the configuration is part of the header, a shorter header is malformed
+    let config_len = config_size.min(header_size.checked_sub(20)?);
+    let config_bytes = reader.slice(chunk_start + 20, config_len)?;
+    let config = ResourceConfig::from_bytes(config_bytes);
+
+    let offsets_start = chunk_start + header_size;
+    let mut offsets: Vec<(u16, usize)> = vec![];
+    if flags & TYPE_FLAG_SPARSE != 0 {
+        for i in 0..entry_count {
+            let idx = reader.u16(offsets_start + i * 4)?;
+            let offset = reader.u16(offsets_start + i * 4 + 2)? as usize * 4;
+            offsets.push((idx, offset));
+        }
+    } else if flags & TYPE_FLAG_OFFSET16 != 0 {
+        for i in 0..entry_count {
+            let offset = reader.u16(offsets_start + i * 2)?;
+            if offset != 0xffff {
+                offsets.push((i as u16, offset as usize * 4));
+            }
+        }
+    } else {
+        for i in 0..entry_count {
+            let offset = reader.u32(offsets_start + i * 4)?;
+            if offset != u32::MAX {
+                offsets.push((i as u16, offset as usize));
+            }
+        }
+    }
+
+    let mut entries = BTreeMap::new();
+    for (idx, offset) in offsets {
+        if let Some(entry) = parse_entry(reader, entries_start + offset, string_pool) {
+            entries.insert(idx, entry);
+        }
+    }
+    Some(ResourceTypeConfig { config, entries })
+}
+
+fn parse_entry(reader: &ChunkReader, off: usize, string_pool: &[String]) -> Option<ResourceEntry> {
+    let size = reader.u16(off)?;
+    let flags = reader.u16(off + 2)?;
+    if flags & ENTRY_FLAG_COMPACT != 0 {
+        // compact entries store the key in the size field and the type in the upper flag byte
+        let data = reader.u32(off + 4)?;
+        return Some(ResourceEntry {
+            key_idx: size as u32,
+            value: parse_value((flags >> 8) as u8, data, string_pool),
+        });
+    }
+    let key_idx = reader.u32(off + 4)?;
+    let value = if flags & ENTRY_FLAG_COMPLEX != 0 {
+        let parent = reader.u32(off + 8)?;
+        let count = reader.u32(off + 12)? as usize;
+        let mut items = Vec::with_capacity(count.min(0x1000));
+        let mut item_off = off + size as usize;
+        for _ in 0..count {
+            let name = reader.u32(item_off)?;
+            let value_size = reader.u16(item_off + 4)? as usize;
+            let data_type = reader.u8(item_off + 7)?;
+            let data = reader.u32(item_off + 8)?;
+            items.push((name, parse_value(data_type, data, string_pool)));
+            item_off += 4 + value_size.max(8);
+        }
+        ResourceValue::Bag { parent, items }
+    } else {
+        let value_off = off + size as usize;
+        let data_type = reader.u8(value_off + 3)?;
+        let data = reader.u32(value_off + 4)?;
+        parse_value(data_type, data, string_pool)
+    };
+    Some(ResourceEntry { key_idx, value })
+}
+
+/// Follow references of a value until a non reference value is found. The lookup function
+/// is used to resolve ids, which allows to combine the app and the framework table.
+pub fn resolve_reference_chain<F>(
+    value: &ResourceValue,
+    config: &ResourceConfig,
+    lookup: F,
+) -> ResourceValue
+where
+    F: Fn(u32) -> Option<Resource>,
+{
+    let mut current = value.clone();
+    for _ in 0..MAX_REFERENCE_DEPTH {
+        let ResourceValue::Reference(id) = current else {
+            return current;
+        };
+        let Some(resource) = lookup(id) else {
+            return current;
+        };
+        // prefer the same configuration, otherwise fall back to the default value
+        let next = resource
+            .values
+            .iter()
+            .find(|(c, _)| c == config)
+            .map(|(_, v)| v)
+            .or_else(|| resource.default_value());
+        match next {
+            Some(next) => current = next.clone(),
+            None => return current,
+        }
+    }
+    current
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk(ty: u16, header: &[u8], body: &[u8]) -> Vec<u8> {
+        let header_size = 8 + header.len();
+        let mut data = vec![];
+        data.extend_from_slice(&ty.to_le_bytes());
+        data.extend_from_slice(&(header_size as u16).to_le_bytes());
+        data.extend_from_slice(&((header_size + body.len()) as u32).to_le_bytes());
+        data.extend_from_slice(header);
+        data.extend_from_slice(body);
+        data
+    }
+
+    fn string_pool(strings: &[&str]) -> Vec<u8> {
+        let mut offsets = vec![];
+        let mut content = vec![];
+        for s in strings {
+            offsets.extend_from_slice(&(content.len() as u32).to_le_bytes());
+            content.extend_from_slice(&[s.len() as u8, s.len() as u8]);
+            content.extend_from_slice(s.as_bytes());
+            content.push(0);
+        }
+        while content.len() % 4 != 0 {
+            content.push(0);
+        }
+        let mut header = vec![];
+        header.extend_from_slice(&(strings.len() as u32).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        header.extend_from_slice(&STRING_POOL_UTF8_FLAG.to_le_bytes());
+        header.extend_from_slice(&(28 + offsets.len() as u32).to_le_bytes());
+        header.extend_from_slice(&0u32.to_le_bytes());
+        offsets.extend_from_slice(&content);
+        chunk(RES_STRING_POOL_TYPE, &header, &offsets)
+    }
+
+    /// A type chunk with a single string entry
+    fn type_chunk(language: &[u8; 2], string_idx: u32, header_size: usize) -> Vec<u8> {
+        let mut config = vec![0u8; 64];
+        config[0..4].copy_from_slice(&64u32.to_le_bytes());
+        config[8..10].copy_from_slice(language);
+        let mut header = vec![1, 0, 0, 0];
+        header.extend_from_slice(&1u32.to_le_bytes());
+        header.extend_from_slice(&(header_size as u32 + 4).to_le_bytes());
+        header.extend_from_slice(&config);
+        header.truncate(header_size - 8);
+        let mut body = 0u32.to_le_bytes().to_vec();
+        // entry: size, flags, key and the value
+        body.extend_from_slice(&[8, 0, 0, 0, 0, 0, 0, 0]);
+        body.extend_from_slice(&[8, 0, 0, 0x03]);
+        body.extend_from_slice(&string_idx.to_le_bytes());
+        chunk(RES_TABLE_TYPE_TYPE, &header, &body)
+    }
+
+    fn table(type_chunks: &[Vec<u8>]) -> Vec<u8> {
+        let type_strings = string_pool(&["string"]);
+        let key_strings = string_pool(&["app_name"]);
+        let mut header = vec![0u8; 280];
+        header[0..4].copy_from_slice(&0x7fu32.to_le_bytes());
+        for (i, c) in "com.example".encode_utf16().enumerate() {
+            header[4 + i * 2..6 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        header[260..264].copy_from_slice(&288u32.to_le_bytes());
+        header[268..272].copy_from_slice(&(288 + type_strings.len() as u32).to_le_bytes());
+        let mut body = type_strings;
+        body.extend_from_slice(&key_strings);
+        body.extend_from_slice(&chunk(
+            RES_TABLE_TYPE_SPEC_TYPE,
+            &[1, 0, 0, 0, 1, 0, 0, 0],
+            &0u32.to_le_bytes(),
+        ));
+        for type_chunk in type_chunks {
+            body.extend_from_slice(type_chunk);
+        }
+        let package = chunk(RES_TABLE_PACKAGE_TYPE, &header, &body);
+
+        let mut content = string_pool(&["Hello", "Hallo"]);
+        content.extend_from_slice(&package);
+        chunk(RES_TABLE_TYPE, &1u32.to_le_bytes(), &content)
+    }
+
+    #[test]
+    fn parses_values_per_configuration() {
+        let data = table(&[type_chunk(&[0, 0], 0, 84), type_chunk(b"de", 1, 84)]);
+        let table = ResourceTable::parse(&data).unwrap();
+        assert_eq!(table.get_resource_id("@string/app_name"), Some(0x7f010000));
+        let resource = table.get_resource(0x7f010000).unwrap();
+        assert_eq!(resource.package, "com.example");
+        assert_eq!(resource.java_name(), "R.string.app_name");
+        assert_eq!(
+            resource.default_value().and_then(|v| v.as_str()),
+            Some("Hello")
+        );
+        assert_eq!(
+            resource.value_for_qualifier("de").and_then(|v| v.as_str()),
+            Some("Hallo")
+        );
+    }
+
+    #[test]
+    fn rejects_type_chunk_with_short_header() {
+        // the header is too short to hold a configuration
+        let data = table(&[type_chunk(&[0, 0], 0, 16), type_chunk(b"de", 1, 84)]);
+        let table = ResourceTable::parse(&data).unwrap();
+        let resource = table.get_resource(0x7f010000).unwrap();
+        assert_eq!(resource.values.len(), 1);
+        assert_eq!(resource.values[0].0.language, "de");
+    }
+
+    #[test]
+    fn tolerates_truncated_tables() {
+        let data = table(&[type_chunk(&[0, 0], 0, 84), type_chunk(b"de", 1, 84)]);
+        for len in 0..data.len() {
+            let _ = ResourceTable::parse(&data[..len]);
+        }
+        assert!(ResourceTable::parse(&[0; 16]).is_err());
+    }
+
+    #[test]
+    fn decodes_packed_locales() {
+        // three letter language codes are packed into two bytes
+        assert_eq!(unpack_locale_part([0xad, 0x05], b'a'), "fil");
+        assert_eq!(unpack_locale_part(*b"de", b'a'), "de");
+        assert_eq!(unpack_locale_part([0, 0], b'a'), "");
+    }
+
+    #[test]
+    fn bounds_string_pools_by_their_size() {
+        let mut data = string_pool(&["a", "b"]);
+        data[8..12].copy_from_slice(&u32::MAX.to_le_bytes());
+        let strings = parse_string_pool(&ChunkReader { data: &data }, 0).unwrap();
+        // the offsets of the two strings, then the content is read as further offsets
+        assert!(strings.len() < data.len() / 4);
+        assert_eq!(strings[..2], ["a".to_string(), "b".to_string()]);
+
+        let mut data = string_pool(&["a"]);
+        data[0] = 0;
+        assert!(parse_string_pool(&ChunkReader { data: &data }, 0).is_none());
+    }
+
+    #[test]
+    fn reads_long_pool_strings() {
+        // utf16 with the two unit length form, utf8 with the two byte length form
+        let mut data = vec![0x00, 0x80, 0x03, 0x00];
+        for c in "abc".encode_utf16() {
+            data.extend_from_slice(&c.to_le_bytes());
+        }
+        let pool = ChunkReader { data: &data };
+        assert_eq!(read_utf16_pool_string(&pool, 0).unwrap(), "abc");
+        assert!(read_utf16_pool_string(
+            &ChunkReader {
+                data: &[0xff, 0xff, 0xff, 0x7f]
+            },
+            0
+        )
+        .is_none());
+
+        let mut data = vec![0x81, 0x00, 0x81, 0x00];
+        data.extend(std::iter::repeat_n(b'x', 0x100));
+        let pool = ChunkReader { data: &data };
+        assert_eq!(read_utf8_pool_string(&pool, 0).unwrap().len(), 0x100);
+        assert!(read_utf8_pool_string(
+            &ChunkReader {
+                data: &data[..0x80]
+            },
+            0
+        )
+        .is_none());
+        assert!(read_utf8_pool_string(&ChunkReader { data: &[0x81] }, 0).is_none());
+    }
+
+    #[test]
+    fn parses_complex_and_compact_entries() {
+        let pool = vec!["text".to_string()];
+        // a bag with a parent and two items, the second one truncated
+        let mut data = vec![16, 0, 1, 0, 2, 0, 0, 0];
+        data.extend_from_slice(&0x7f02_0000u32.to_le_bytes());
+        data.extend_from_slice(&2u32.to_le_bytes());
+        data.extend_from_slice(&0x0101_0000u32.to_le_bytes());
+        data.extend_from_slice(&[8, 0, 0, 0x05]);
+        data.extend_from_slice(&((16 << 8) | 1u32).to_le_bytes());
+        let reader = ChunkReader { data: &data };
+        assert!(parse_entry(&reader, 0, &pool).is_none());
+        data.extend_from_slice(&0x0101_0001u32.to_le_bytes());
+        data.extend_from_slice(&[8, 0, 0, 0x03, 0, 0, 0, 0]);
+        let entry = parse_entry(&ChunkReader { data: &data }, 0, &pool).unwrap();
+        assert_eq!(entry.key_idx, 2);
+        assert_eq!(
+            entry.value,
+            ResourceValue::Bag {
+                parent: 0x7f02_0000,
+                items: vec![
+                    (
+                        0x0101_0000,
+                        ResourceValue::Dimension(16.0, DimensionUnit::Dp)
+                    ),
+                    (0x0101_0001, ResourceValue::String("text".to_string())),
+                ],
+            }
+        );
+        assert_eq!(
+            entry.value.to_string(),
+            "[0x01010000=16dp, 0x01010001=text]"
+        );
+
+        // compact: key 5, type integer
+        let data = [5, 0, 0x08, 0x10, 0xfe, 0xff, 0xff, 0xff];
+        let entry = parse_entry(&ChunkReader { data: &data }, 0, &pool).unwrap();
+        assert_eq!(entry.key_idx, 5);
+        assert_eq!(entry.value, ResourceValue::Integer(-2));
+        assert!(parse_entry(&ChunkReader { data: &data[..6] }, 0, &pool).is_none());
+    }
+
+    #[test]
+    fn parses_values() {
+        let pool = vec!["a".to_string()];
+        assert_eq!(parse_value(0x00, 1, &pool), ResourceValue::Empty);
+        assert_eq!(
+            parse_value(0x03, 0, &pool),
+            ResourceValue::String("a".to_string())
+        );
+        assert_eq!(parse_value(0x03, 1, &pool), ResourceValue::Unknown(0x03, 1));
+        assert_eq!(
+            parse_value(0x12, 0xffff_ffff, &pool),
+            ResourceValue::Bool(true)
+        );
+        assert_eq!(
+            parse_value(0x1d, 0xff00_ff00, &pool).to_string(),
+            "#ff00ff00"
+        );
+        // 50% with the radix 0p23
+        assert_eq!(
+            parse_value(0x06, (1 << 30) | (3 << 4), &pool),
+            ResourceValue::Fraction(0.5, false)
+        );
+        assert_eq!(parse_value(0x2a, 7, &pool), ResourceValue::Unknown(0x2a, 7));
+    }
+
+    #[test]
+    fn parses_sparse_and_offset16_type_chunks() {
+        let config = {
+            let mut config = vec![0u8; 64];
+            config[0..4].copy_from_slice(&64u32.to_le_bytes());
+            config
+        };
+        let header_size = 20 + config.len();
+        let type_chunk = |flags: u8, offsets: &[u8]| {
+            let mut header = vec![1, flags, 0, 0];
+            header.extend_from_slice(&2u32.to_le_bytes());
+            header.extend_from_slice(&((header_size + offsets.len()) as u32).to_le_bytes());
+            header.extend_from_slice(&config);
+            let mut body = offsets.to_vec();
+            body.extend_from_slice(&[8, 0, 0, 0, 3, 0, 0, 0, 8, 0, 0, 0x10, 42, 0, 0, 0]);
+            chunk(RES_TABLE_TYPE_TYPE, &header, &body)
+        };
+        // entries 7 and 0, both at offset 0
+        let data = type_chunk(TYPE_FLAG_SPARSE, &[7, 0, 0, 0, 0, 0, 0, 0]);
+        let reader = ChunkReader { data: &data };
+        let config = parse_type_chunk(&reader, 0, header_size, &[]).unwrap();
+        assert_eq!(config.entries.keys().copied().collect::<Vec<_>>(), [0, 7]);
+        assert_eq!(config.entries[&7].value, ResourceValue::Integer(42));
+        // entry 0 missing, entry 1 at offset 0
+        let data = type_chunk(TYPE_FLAG_OFFSET16, &[0xff, 0xff, 0, 0]);
+        let reader = ChunkReader { data: &data };
+        let config = parse_type_chunk(&reader, 0, header_size, &[]).unwrap();
+        assert_eq!(config.entries.keys().copied().collect::<Vec<_>>(), [1]);
+        assert_eq!(config.entries[&1].key_idx, 3);
+        // offsets beyond the chunk
+        let data = type_chunk(0, &[0xf0, 0, 0, 0, 0, 0, 0, 0x10]);
+        let reader = ChunkReader { data: &data };
+        assert!(parse_type_chunk(&reader, 0, header_size, &[])
+            .unwrap()
+            .entries
+            .is_empty());
+    }
+
+    #[test]
+    fn stops_at_truncated_library_chunks() {
+        let mut library = vec![0u8; 260];
+        library[0..4].copy_from_slice(&2u32.to_le_bytes());
+        for (i, c) in "com.lib".encode_utf16().enumerate() {
+            library[4 + i * 2..6 + i * 2].copy_from_slice(&c.to_le_bytes());
+        }
+        let library = chunk(RES_TABLE_LIBRARY_TYPE, &u32::MAX.to_le_bytes(), &library);
+        let mut data = table(&[type_chunk(&[0, 0], 0, 84), library]);
+        // the package and the table grow by the library chunk
+        let table = ResourceTable::parse(&data).unwrap();
+        assert_eq!(
+            table.packages[0].libraries,
+            vec![(2, "com.lib".to_string())]
+        );
+
+        data[2] = 0;
+        assert!(ResourceTable::parse(&data).is_err());
+        data[0] = 0;
+        data[2] = 12;
+        assert_eq!(
+            ResourceTable::parse(&data).err().unwrap(),
+            "Not a resource table (chunk type 0x0)"
+        );
+    }
+
+    #[test]
+    fn resolves_reference_chains() {
+        let resource = |id: u32, value: ResourceValue| Resource {
+            id,
+            package: "com.example".to_string(),
+            type_name: "string".to_string(),
+            name: "name".to_string(),
+            values: vec![(ResourceConfig::default(), value)],
+        };
+        let config = ResourceConfig::default();
+        let lookup = |id: u32| match id {
+            1 => Some(resource(1, ResourceValue::Reference(2))),
+            2 => Some(resource(2, ResourceValue::String("end".to_string()))),
+            // a reference to itself
+            3 => Some(resource(3, ResourceValue::Reference(3))),
+            _ => None,
+        };
+        assert_eq!(
+            resolve_reference_chain(&ResourceValue::Reference(1), &config, lookup),
+            ResourceValue::String("end".to_string())
+        );
+        assert_eq!(
+            resolve_reference_chain(&ResourceValue::Reference(3), &config, lookup),
+            ResourceValue::Reference(3)
+        );
+        assert_eq!(
+            resolve_reference_chain(&ResourceValue::Reference(4), &config, lookup),
+            ResourceValue::Reference(4)
+        );
+    }
+}
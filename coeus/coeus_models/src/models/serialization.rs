@@ -0,0 +1,38 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! The models are usually serialized to JSON (scripting, python bindings), where pools, decoded
+//! code and large binaries are left out to keep the output small. A persistent cache needs all of
+//! it, hence serialization can be switched to a complete mode for the current thread.
+//!
+//! Deserialization always expects the complete format.
+
+use std::cell::Cell;
+
+thread_local! {
+    static COMPLETE_SERIALIZATION: Cell<bool> = const { Cell::new(false) };
+}
+
+/// Run `f` with complete serialization enabled on the current thread
+pub fn with_complete_serialization<R>(f: impl FnOnce() -> R) -> R {
+    struct Reset(bool);
+    impl Drop for Reset {
+        fn drop(&mut self) {
+            COMPLETE_SERIALIZATION.with(|complete| complete.set(self.0));
+        }
+    }
+    let _reset = Reset(COMPLETE_SERIALIZATION.with(|complete| complete.replace(true)));
+    f()
+}
+
+pub fn is_complete_serialization() -> bool {
+    COMPLETE_SERIALIZATION.with(|complete| complete.get())
+}
+
+/// Used with `skip_serializing_if` for fields only contained in the complete format
+pub(crate) fn is_compact<T>(_: &T) -> bool {
+    !is_complete_serialization()
+}
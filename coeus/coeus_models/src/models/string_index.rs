@@ -0,0 +1,341 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A trigram index over the string table of a dex file. Substring queries intersect the posting
+//! lists of the trigrams of the needle and only verify the remaining candidates. Regex queries
+//! extract literals every match has to contain and use them as a substring prefilter. Exact and
+//! prefix queries use binary search over the sorted string table.
+//!
+//! The index works on the raw modified UTF-8 bytes, hence needles are encoded the same way. As
+//! every UTF-16 code unit is encoded on its own, a string contains a needle if and only if its
+//! bytes contain the encoded needle. The exceptions are unpaired surrogates, which decode to
+//! U+FFFD, and supplementary characters, which some tools encode as plain UTF-8. Needles
+//! containing either are not accelerated.
+//!
+//! All queries return the same strings as a linear scan would, sorted by their string idx.
+
+use std::sync::Arc;
+
+use regex::Regex;
+use regex_syntax::hir::{Hir, HirKind};
+
+use super::{encode_mutf8, DexFile, MultiDexFile};
+
+/// A query against the string tables
+#[derive(Debug, Clone, Copy)]
+pub enum StringQuery<'a> {
+    Exact(&'a str),
+    Prefix(&'a str),
+    Substring(&'a str),
+    /// A pattern with the default flags of `Regex::new`, flags can be set inline (e.g. `(?i)`).
+    /// The literals used to narrow down the search are extracted from the same pattern.
+    Regex(&'a str),
+}
+
+impl<'a> StringQuery<'a> {
+    /// `regex` is the compiled pattern of a regex query
+    fn is_match(&self, regex: Option<&Regex>, content: &str) -> bool {
+        match self {
+            StringQuery::Exact(needle) => content == *needle,
+            StringQuery::Prefix(needle) => content.starts_with(needle),
+            StringQuery::Substring(needle) => content.contains(needle),
+            StringQuery::Regex(_) => regex.is_some_and(|regex| regex.is_match(content)),
+        }
+    }
+}
+
+/// Trigrams of all strings in compressed sparse row form. The posting list of `trigrams[i]`
+/// is `postings[offsets[i]..offsets[i + 1]]`.
+pub(crate) struct StringIndex {
+    trigrams: Vec<u32>,
+    offsets: Vec<u32>,
+    postings: Vec<u32>,
+}
+
+impl StringIndex {
+    pub(crate) fn new(dex: &DexFile) -> Self {
+        let mut pairs: Vec<(u32, u32)> = dex
+            .strings
+            .iter()
+            .enumerate()
+            .flat_map(|(idx, entry)| {
+                entry
+                    .dat
+                    .windows(3)
+                    .map(move |window| (trigram(window), idx as u32))
+            })
+            .collect();
+        pairs.sort_unstable();
+        pairs.dedup();
+
+        let mut trigrams = vec![];
+        let mut offsets = vec![];
+        let mut postings = Vec::with_capacity(pairs.len());
+        for (trigram, idx) in pairs {
+            if trigrams.last() != Some(&trigram) {
+                trigrams.push(trigram);
+                offsets.push(postings.len() as u32);
+            }
+            postings.push(idx);
+        }
+        offsets.push(postings.len() as u32);
+        Self {
+            trigrams,
+            offsets,
+            postings,
+        }
+    }
+
+    fn posting_list(&self, trigram: u32) -> &[u32] {
+        match self.trigrams.binary_search(&trigram) {
+            Ok(pos) => &self.postings[self.offsets[pos] as usize..self.offsets[pos + 1] as usize],
+            Err(_) => &[],
+        }
+    }
+
+    /// All strings containing every trigram of `needle`, or `None` if the needle is too short to
+    /// narrow down the search
+    fn candidates(&self, needle: &str) -> Option<Vec<u32>> {
+        if !can_accelerate(needle) {
+            return None;
+        }
+        let needle = encode_mutf8(needle);
+        if needle.len() < 3 {
+            return None;
+        }
+        let mut lists: Vec<&[u32]> = needle
+            .windows(3)
+            .map(|window| self.posting_list(trigram(window)))
+            .collect();
+        lists.sort_by_key(|list| list.len());
+        let mut candidates = lists[0].to_vec();
+        for list in &lists[1..] {
+            if candidates.is_empty() {
+                break;
+            }
+            candidates.retain(|idx| list.binary_search(idx).is_ok());
+        }
+        Some(candidates)
+    }
+}
+
+/// Whether every string containing `needle` contains its encoding as well
+pub(crate) fn can_accelerate(needle: &str) -> bool {
+    !needle
+        .chars()
+        .any(|c| c == char::REPLACEMENT_CHARACTER || c as u32 > 0xffff)
+}
+
+fn trigram(window: &[u8]) -> u32 {
+    (window[0] as u32) << 16 | (window[1] as u32) << 8 | window[2] as u32
+}
+
+/// Literals of which every match of `hir` contains at least one, or `None` if there are none
+fn required_literals(hir: &Hir) -> Option<Vec<Vec<u8>>> {
+    match hir.kind() {
+        HirKind::Literal(literal) => Some(vec![literal.0.to_vec()]),
+        HirKind::Capture(capture) => required_literals(&capture.sub),
+        HirKind::Repetition(repetition) if repetition.min > 0 => required_literals(&repetition.sub),
+        HirKind::Concat(subs) => subs
+            .iter()
+            .filter_map(required_literals)
+            .max_by_key(|literals| literals.iter().map(|l| l.len()).min().unwrap_or(0)),
+        HirKind::Alternation(subs) => {
+            let mut literals = vec![];
+            for sub in subs {
+                literals.extend(required_literals(sub)?);
+            }
+            Some(literals)
+        }
+        _ => None,
+    }
+}
+
+impl DexFile {
+    /// Find all strings matching the query, returning the string indices in ascending order
+    pub fn find_strings(&self, query: &StringQuery) -> Vec<u32> {
+        let regex = match query {
+            StringQuery::Regex(pattern) => match Regex::new(pattern) {
+                Ok(regex) => Some(regex),
+                Err(e) => {
+                    log::warn!("Invalid regex {}: {}", pattern, e);
+                    return vec![];
+                }
+            },
+            _ => None,
+        };
+        let candidates = match query {
+            StringQuery::Exact(needle) | StringQuery::Prefix(needle) if !can_accelerate(needle) => {
+                None
+            }
+            StringQuery::Exact(needle) => {
+                return self.get_string_idx(needle).into_iter().collect();
+            }
+            StringQuery::Prefix(needle) => {
+                let mut candidates = self.index.get(self).strings_with_prefix(self, needle);
+                candidates.sort_unstable();
+                Some(candidates)
+            }
+            StringQuery::Substring(needle) => self.index.strings(self).candidates(needle),
+            StringQuery::Regex(pattern) => match regex_syntax::parse(pattern)
+                .ok()
+                .as_ref()
+                .and_then(required_literals)
+            {
+                Some(literals) => {
+                    let index = self.index.strings(self);
+                    let mut candidates = Some(vec![]);
+                    for literal in literals {
+                        // literals of byte oriented patterns might not be valid UTF-8
+                        let found = std::str::from_utf8(&literal)
+                            .ok()
+                            .and_then(|literal| index.candidates(literal));
+                        match (found, candidates.as_mut()) {
+                            (Some(found), Some(candidates)) => candidates.extend(found),
+                            _ => {
+                                candidates = None;
+                                break;
+                            }
+                        }
+                    }
+                    candidates.map(|mut candidates| {
+                        candidates.sort_unstable();
+                        candidates.dedup();
+                        candidates
+                    })
+                }
+                None => None,
+            },
+        };
+        let matches = |&idx: &u32| {
+            self.get_string(idx as usize)
+                .map(|content| query.is_match(regex.as_ref(), content))
+                .unwrap_or(false)
+        };
+        match candidates {
+            Some(candidates) => candidates.into_iter().filter(matches).collect(),
+            None => (0..self.strings.len() as u32).filter(matches).collect(),
+        }
+    }
+}
+
+impl MultiDexFile {
+    /// Find all strings matching the query in all dex files
+    pub fn find_strings(&self, query: &StringQuery) -> Vec<(Arc<DexFile>, u32)> {
+        self.dex_files()
+            .flat_map(|dex| {
+                dex.find_strings(query)
+                    .into_iter()
+                    .map(move |idx| (dex.clone(), idx))
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::{testing::DexBuilder, StringEntry};
+    use super::*;
+
+    fn dex() -> DexFile {
+        let mut dex = DexBuilder::new("strings");
+        for s in [
+            "",
+            "http://example.com",
+            "HTTPS://EXAMPLE.COM/path",
+            "https",
+            "ftp://host",
+            "é",
+            "café au lait",
+            "😀",
+            "smile 😀 please",
+            "😀😀",
+            "Lcom/example/Main;",
+            "abc",
+        ] {
+            dex.string(s);
+        }
+        let mut dex = dex.build();
+        // supplementary characters encoded as plain UTF-8 instead of a surrogate pair
+        dex.strings
+            .push(StringEntry::new(3, "x🦀".as_bytes().to_vec()));
+        dex.strings
+            .push(StringEntry::new(2, "🦀".as_bytes().to_vec()));
+        // an unpaired surrogate, which decodes to U+FFFD
+        dex.strings
+            .push(StringEntry::new(2, vec![b'a', 0xed, 0xa0, 0x80]));
+        dex
+    }
+
+    fn linear_scan(dex: &DexFile, matches: impl Fn(&str) -> bool) -> Vec<u32> {
+        (0..dex.strings.len() as u32)
+            .filter(|&idx| dex.get_string(idx as usize).is_some_and(&matches))
+            .collect()
+    }
+
+    #[test]
+    fn queries_agree_with_linear_scans() {
+        let dex = dex();
+        let needles = [
+            "", "http", "https", "example", "EXAMPLE", "é", "caf", "😀", "😀 p", "🦀", "x🦀",
+            "\u{fffd}", "a", "missing", "ab",
+        ];
+        for needle in needles {
+            assert_eq!(
+                dex.find_strings(&StringQuery::Exact(needle)),
+                linear_scan(&dex, |s| s == needle),
+                "exact {}",
+                needle
+            );
+            assert_eq!(
+                dex.find_strings(&StringQuery::Prefix(needle)),
+                linear_scan(&dex, |s| s.starts_with(needle)),
+                "prefix {}",
+                needle
+            );
+            assert_eq!(
+                dex.find_strings(&StringQuery::Substring(needle)),
+                linear_scan(&dex, |s| s.contains(needle)),
+                "substring {}",
+                needle
+            );
+        }
+        let patterns = [
+            "https?://example",
+            "(?i)https?://example",
+            "(?i)CAFÉ",
+            "😀+",
+            "🦀$",
+            "^L.*;$",
+            "example|host",
+            "[a-c]{3}",
+            ".",
+        ];
+        for pattern in patterns {
+            let regex = Regex::new(pattern).unwrap();
+            assert_eq!(
+                dex.find_strings(&StringQuery::Regex(pattern)),
+                linear_scan(&dex, |s| regex.is_match(s)),
+                "regex {}",
+                pattern
+            );
+        }
+        assert!(!dex
+            .find_strings(&StringQuery::Regex("(?i)https?://example"))
+            .is_empty());
+        assert!(dex.find_strings(&StringQuery::Regex("(")).is_empty());
+    }
+
+    #[test]
+    fn supplementary_characters_are_found() {
+        let dex = dex();
+        for content in ["😀", "😀😀", "🦀", "x🦀"] {
+            let idx = dex.get_string_idx(content).unwrap();
+            assert_eq!(dex.get_string(idx as usize), Some(content));
+        }
+        assert_eq!(dex.get_string_idx("🦀🦀"), None);
+    }
+}
@@ -0,0 +1,291 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Small in-memory dex files for tests, such that analyses can be tested without a dex binary.
+//! Only available in tests and with the `testing` feature.
+//!
+//! Code is given as raw code units, the helpers at the end of this module encode the common
+//! instruction formats.
+
+use std::{collections::HashMap, io::Cursor, sync::Arc};
+
+use super::{
+    AccessFlags, Class, ClassData, CodeItem, Decode, DexFile, DexHeader, DexIndex, EncodedMethod,
+    Field, Method, MethodData, Proto, StringEntry,
+};
+
+const NO_INDEX: u32 = 0xffffffff;
+
+pub struct DexBuilder {
+    identifier: String,
+    strings: Vec<String>,
+    string_ids: HashMap<String, u32>,
+    types: Vec<u32>,
+    protos: Vec<Proto>,
+    methods: Vec<Method>,
+    fields: Vec<Field>,
+    classes: Vec<Class>,
+}
+
+impl DexBuilder {
+    pub fn new(identifier: &str) -> Self {
+        Self {
+            identifier: identifier.to_string(),
+            strings: vec![],
+            string_ids: HashMap::new(),
+            types: vec![],
+            protos: vec![],
+            methods: vec![],
+            fields: vec![],
+            classes: vec![],
+        }
+    }
+
+    /// The index of the string, which is added to the pool if needed
+    pub fn string(&mut self, content: &str) -> u32 {
+        if let Some(&idx) = self.string_ids.get(content) {
+            return idx;
+        }
+        let idx = self.strings.len() as u32;
+        self.strings.push(content.to_string());
+        self.string_ids.insert(content.to_string(), idx);
+        idx
+    }
+
+    /// The index of the type with the given descriptor, which is added to the pool if needed
+    pub fn type_idx(&mut self, descriptor: &str) -> u16 {
+        let name_idx = self.string(descriptor);
+        match self.types.iter().position(|&idx| idx == name_idx) {
+            Some(pos) => pos as u16,
+            None => {
+                self.types.push(name_idx);
+                (self.types.len() - 1) as u16
+            }
+        }
+    }
+
+    pub fn proto(&mut self, return_type: &str, parameters: &[&str]) -> u16 {
+        let shorty: String = std::iter::once(return_type)
+            .chain(parameters.iter().copied())
+            .map(|descriptor| match descriptor.as_bytes()[0] {
+                b'[' => 'L',
+                c => c as char,
+            })
+            .collect();
+        let proto = Proto {
+            shorty_idx: self.string(&shorty),
+            return_type_idx: self.type_idx(return_type) as u32,
+            parameters_off: 0,
+            arguments: parameters.iter().map(|p| self.type_idx(p)).collect(),
+        };
+        self.protos.push(proto);
+        (self.protos.len() - 1) as u16
+    }
+
+    /// Add a method reference, e.g. `method("Landroid/net/Uri;", "getHost", "Ljava/lang/String;", &[])`
+    pub fn method(
+        &mut self,
+        class: &str,
+        name: &str,
+        return_type: &str,
+        parameters: &[&str],
+    ) -> u16 {
+        let method_idx = self.methods.len() as u16;
+        let method = Method {
+            class_idx: self.type_idx(class),
+            method_idx,
+            proto_idx: self.proto(return_type, parameters),
+            name_idx: self.string(name),
+            method_name: name.to_string(),
+            proto_name: format!("({}){}", parameters.concat(), return_type),
+        };
+        self.methods.push(method);
+        method_idx
+    }
+
+    pub fn field(&mut self, class: &str, name: &str, field_type: &str) -> u16 {
+        let field = Field {
+            class_idx: self.type_idx(class),
+            type_idx: self.type_idx(field_type),
+            name_idx: self.string(name),
+            name: name.to_string(),
+        };
+        self.fields.push(field);
+        (self.fields.len() - 1) as u16
+    }
+
+    /// Define a class, returning its position in `classes`
+    pub fn class(&mut self, name: &str, super_class: Option<&str>, interfaces: &[&str]) -> usize {
+        let class_idx = self.type_idx(name) as u32;
+        let mut class = Class::new(self.identifier.clone(), class_idx, name.to_string());
+        class.super_class = super_class.map_or(NO_INDEX, |s| self.type_idx(s) as u32);
+        class.interfaces = interfaces.iter().map(|i| self.type_idx(i)).collect();
+        class.class_data = Some(ClassData {
+            static_fields_size: 0,
+            instance_fields_size: 0,
+            direct_methods_size: 0,
+            virtual_methods_size: 0,
+            static_fields: vec![],
+            instance_fields: vec![],
+            direct_methods: vec![],
+            virtual_methods: vec![],
+        });
+        self.classes.push(class);
+        self.classes.len() - 1
+    }
+
+    pub fn interface(&mut self, name: &str, interfaces: &[&str]) -> usize {
+        let pos = self.class(name, Some("Ljava/lang/Object;"), interfaces);
+        self.classes[pos].access_flags =
+            AccessFlags::PUBLIC | AccessFlags::INTERFACE | AccessFlags::ABSTRACT;
+        pos
+    }
+
+    /// Add a virtual method with the given code units to the class at `class_pos`
+    pub fn virtual_method(&mut self, class_pos: usize, method_idx: u16, insns: &[u16]) {
+        self.add_code(class_pos, method_idx, AccessFlags::PUBLIC, insns, true);
+    }
+
+    /// Add a direct method with the given code units to the class at `class_pos`
+    pub fn direct_method(&mut self, class_pos: usize, method_idx: u16, insns: &[u16]) {
+        self.add_code(class_pos, method_idx, AccessFlags::PRIVATE, insns, false);
+    }
+
+    fn add_code(
+        &mut self,
+        class_pos: usize,
+        method_idx: u16,
+        access_flags: AccessFlags,
+        insns: &[u16],
+        is_virtual: bool,
+    ) {
+        let method = Arc::new(self.methods[method_idx as usize].clone());
+        let class = &mut self.classes[class_pos];
+        let encoded = EncodedMethod {
+            method_idx: method_idx as u32,
+            access_flags,
+            code_off: 0,
+        };
+        let class_data = class.class_data.as_mut().unwrap();
+        if is_virtual {
+            class_data.virtual_methods.push(encoded);
+            class_data.virtual_methods_size += 1;
+        } else {
+            class_data.direct_methods.push(encoded);
+            class_data.direct_methods_size += 1;
+        }
+        class.codes.push(Arc::new(MethodData {
+            name: method.method_name.clone(),
+            method,
+            method_idx: method_idx as u32,
+            access_flags,
+            code: Some(code_item(insns)),
+            call_graph: None,
+            jvm_code: None,
+        }));
+    }
+
+    pub fn build(self) -> DexFile {
+        DexFile {
+            identifier: self.identifier.clone(),
+            file_name: format!("{}.dex", self.identifier),
+            header: header(),
+            strings: self
+                .strings
+                .iter()
+                .map(|s| {
+                    StringEntry::new(
+                        s.encode_utf16().count() as u32,
+                        super::encode_mutf8(s).into_owned(),
+                    )
+                })
+                .collect(),
+            types: self.types.into(),
+            methods: self.methods.into_iter().map(Arc::new).collect(),
+            protos: self.protos.into_iter().map(Arc::new).collect(),
+            fields: self.fields.into_iter().map(Arc::new).collect(),
+            classes: self.classes.into_iter().map(Arc::new).collect(),
+            call_sites: vec![],
+            interface_table: HashMap::new(),
+            superclass_table: HashMap::new(),
+            index: DexIndex::default(),
+        }
+    }
+}
+
+fn header() -> DexHeader {
+    DexHeader {
+        magic: *b"dex\n035\0",
+        checksum: 0,
+        signature: [0; 20],
+        file_size: 0,
+        header_size: 0x70,
+        endian_tag: 0x12345678,
+        link_size: 0,
+        link_off: 0,
+        map_off: 0,
+        string_ids_size: 0,
+        string_ids_off: 0,
+        type_ids_size: 0,
+        type_ids_off: 0,
+        proto_ids_size: 0,
+        proto_ids_off: 0,
+        fields_ids_size: 0,
+        fields_ids_off: 0,
+        method_ids_size: 0,
+        method_ids_off: 0,
+        class_defs_size: 0,
+        class_defs_off: 0,
+        data_size: 0,
+        data_off: 0,
+    }
+}
+
+/// Decode the code units the same way the parser does
+pub fn code_item(insns: &[u16]) -> CodeItem {
+    let mut bytes = vec![];
+    // 16 registers, no ins, outs, tries or debug info
+    bytes.extend_from_slice(&16u16.to_le_bytes());
+    bytes.extend_from_slice(&[0; 6]);
+    bytes.extend_from_slice(&0u32.to_le_bytes());
+    bytes.extend_from_slice(&(insns.len() as u32).to_le_bytes());
+    bytes.extend(insns.iter().flat_map(|unit| unit.to_le_bytes()));
+    CodeItem::from_bytes(&mut Cursor::new(bytes))
+}
+
+/// `op vAA, kind@BBBB`, e.g. `const-string`, `new-instance` or `sget`
+pub fn format_21c(opcode: u8, register: u8, idx: u16) -> [u16; 2] {
+    [opcode as u16 | (register as u16) << 8, idx]
+}
+
+/// `op vA, vB, kind@CCCC`, e.g. `iget` or `iput`
+pub fn format_22c(opcode: u8, a: u8, b: u8, idx: u16) -> [u16; 2] {
+    [
+        opcode as u16 | (a as u16 & 0xf) << 8 | (b as u16 & 0xf) << 12,
+        idx,
+    ]
+}
+
+/// `op vAA, #+BBBBBBBB`, e.g. `const`
+pub fn format_31i(opcode: u8, register: u8, literal: u32) -> [u16; 3] {
+    [
+        opcode as u16 | (register as u16) << 8,
+        literal as u16,
+        (literal >> 16) as u16,
+    ]
+}
+
+/// `op {vC, vD, vE, vF, vG}, kind@BBBB`, e.g. `invoke-virtual`. At most five registers.
+pub fn format_35c(opcode: u8, idx: u16, registers: &[u8]) -> [u16; 3] {
+    let register = |i: usize| registers.get(i).map_or(0, |&r| r as u16 & 0xf);
+    [
+        opcode as u16 | (registers.len() as u16) << 12 | register(4) << 8,
+        idx,
+        register(0) | register(1) << 4 | register(2) << 8 | register(3) << 12,
+    ]
+}
+
+pub const RETURN_VOID: u16 = 0x000e;
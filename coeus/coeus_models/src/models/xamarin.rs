@@ -0,0 +1,622 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Xamarin and .NET for Android apps ship their managed assemblies either as single files
+//! (`assemblies/*.dll`, since .NET 9 also wrapped into `lib_*.dll.so`) or bundled into an assembly
+//! store (`assemblies/assemblies*.blob` up to .NET 7, `libassemblies.<abi>.blob.so` since). The
+//! assemblies are usually LZ4 compressed behind an `XALZ` header.
+
+use std::{
+    collections::HashMap,
+    convert::{TryFrom, TryInto},
+};
+
+use goblin::elf::Elf;
+
+use super::DotNetMetadata;
+
+const COMPRESSED_MAGIC: &[u8; 4] = b"XALZ";
+const COMPRESSED_HEADER_SIZE: usize = 12;
+const STORE_MAGIC: &[u8; 4] = b"XABA";
+const STORE_HEADER_SIZE: usize = 20;
+/// `data_offset`, `data_size` and the offsets and sizes of debug and config data
+const V1_DESCRIPTOR_SIZE: usize = 24;
+/// The v1 descriptor preceded by the `mapping_index`
+const V2_DESCRIPTOR_SIZE: usize = 28;
+/// The lower half of the version is the format, the upper half flags the ABI
+const STORE_VERSION_MASK: u32 = 0xffff;
+/// Stores of 64 bit ABIs use 64 bit name hashes in the index
+const STORE_64_BIT: u32 = 0x8000_0000;
+/// ELF wrappers of .NET 9 and later keep the data in this section
+const PAYLOAD_SECTION: &str = "payload";
+/// Upper bound of the decompressed size, the header is not trusted beyond it
+const MAX_ASSEMBLY_SIZE: usize = 256 * 1024 * 1024;
+
+#[derive(Clone, Debug, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct ManagedAssembly {
+    /// The file in the archive containing the assembly
+    pub file_name: String,
+    /// Name of the assembly file, e.g. `Mono.Android.dll`
+    pub name: String,
+    /// Whether the assembly was LZ4 compressed
+    pub compressed: bool,
+    /// The decompressed PE image
+    #[serde(skip)]
+    pub data: Vec<u8>,
+}
+
+impl ManagedAssembly {
+    pub fn metadata(&self) -> Result<DotNetMetadata, String> {
+        DotNetMetadata::parse(&self.data)
+    }
+}
+
+/// Names of the assemblies in the v1 stores, indexed by the store id and the index in the store
+#[derive(Clone, Debug, Default, PartialEq, Eq, serde::Serialize, serde::Deserialize)]
+pub struct AssemblyStoreManifest {
+    pub names: HashMap<(u32, u32), String>,
+}
+
+impl AssemblyStoreManifest {
+    /// Parse `assemblies.manifest`, a table with the columns `Hash 32`, `Hash 64`, `Blob ID`,
+    /// `Blob idx` and `Name`
+    pub fn parse(content: &str) -> Self {
+        let names = content
+            .lines()
+            .filter_map(|line| {
+                let columns: Vec<&str> = line.split_whitespace().collect();
+                let [_, _, store, index, name] = columns.as_slice() else {
+                    return None;
+                };
+                Some((
+                    (store.parse().ok()?, index.parse().ok()?),
+                    assembly_file_name(name),
+                ))
+            })
+            .collect();
+        AssemblyStoreManifest { names }
+    }
+}
+
+fn u32_at(data: &[u8], offset: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(offset..offset + 4)?.try_into().ok()?,
+    ))
+}
+
+fn assembly_file_name(name: &str) -> String {
+    if name.ends_with(".dll") || name.ends_with(".exe") {
+        name.to_string()
+    } else {
+        format!("{}.dll", name)
+    }
+}
+
+pub fn is_compressed_assembly(data: &[u8]) -> bool {
+    data.starts_with(COMPRESSED_MAGIC)
+}
+
+/// Decompress an assembly with an `XALZ` header (magic, descriptor index and decompressed size)
+pub fn decompress_assembly(data: &[u8]) -> Result<Vec<u8>, String> {
+    if !is_compressed_assembly(data) {
+        return Err("not a compressed assembly".to_string());
+    }
+    let size = u32_at(data, 8).ok_or("truncated header")? as usize;
+    if size > MAX_ASSEMBLY_SIZE {
+        return Err(format!("decompressed size {} is too large", size));
+    }
+    lz4_decompress(&data[COMPRESSED_HEADER_SIZE..], size)
+}
+
+/// Decompress a raw LZ4 block
+pub fn lz4_decompress(input: &[u8], size: usize) -> Result<Vec<u8>, String> {
+    let truncated = || "truncated LZ4 block".to_string();
+    // the length of a run is extended by bytes until one is not 255
+    let run_length = |initial: usize, position: &mut usize| -> Result<usize, String> {
+        let mut length = initial;
+        if initial == 15 {
+            loop {
+                let byte = *input.get(*position).ok_or_else(truncated)?;
+                *position += 1;
+                length += byte as usize;
+                if byte != 255 {
+                    break;
+                }
+            }
+        }
+        Ok(length)
+    };
+    let mut output = Vec::with_capacity(size.min(input.len().saturating_mul(255)));
+    let mut position = 0;
+    loop {
+        let token = *input.get(position).ok_or_else(truncated)?;
+        position += 1;
+        let literals = run_length((token >> 4) as usize, &mut position)?;
+        output.extend_from_slice(
+            input
+                .get(position..position + literals)
+                .ok_or_else(truncated)?,
+        );
+        position += literals;
+        if position == input.len() || output.len() >= size {
+            break;
+        }
+        let offset = u16::from_le_bytes(
+            input
+                .get(position..position + 2)
+                .ok_or_else(truncated)?
+                .try_into()
+                .unwrap(),
+        ) as usize;
+        position += 2;
+        if offset == 0 || offset > output.len() {
+            return Err(format!("invalid match offset {}", offset));
+        }
+        let length = run_length((token & 0xf) as usize, &mut position)? + 4;
+        if output.len() + length > size {
+            return Err("LZ4 block exceeds the decompressed size".to_string());
+        }
+        // matches may overlap the bytes they produce
+        let start = output.len() - offset;
+        for index in 0..length {
+            output.push(output[start + index]);
+        }
+    }
+    if output.len() != size {
+        return Err(format!(
+            "decompressed {} bytes instead of {}",
+            output.len(),
+            size
+        ));
+    }
+    Ok(output)
+}
+
+/// The assembly as it is stored, possibly compressed
+fn assembly(file_name: &str, name: String, data: &[u8]) -> Option<ManagedAssembly> {
+    if is_compressed_assembly(data) {
+        match decompress_assembly(data) {
+            Ok(data) => Some(ManagedAssembly {
+                file_name: file_name.to_string(),
+                name,
+                compressed: true,
+                data,
+            }),
+            Err(e) => {
+                log::warn!("Could not decompress {} in {}: {}", name, file_name, e);
+                None
+            }
+        }
+    } else if DotNetMetadata::is_pe(data) {
+        Some(ManagedAssembly {
+            file_name: file_name.to_string(),
+            name,
+            compressed: false,
+            data: data.to_vec(),
+        })
+    } else {
+        None
+    }
+}
+
+/// All assemblies in a file of the archive: a single (compressed) assembly, an assembly store or
+/// an ELF wrapping either. Names of assemblies in v1 stores are taken from the manifest, or the
+/// metadata if there is none.
+pub fn extract_managed_assemblies(
+    file_name: &str,
+    data: &[u8],
+    manifest: Option<&AssemblyStoreManifest>,
+) -> Vec<ManagedAssembly> {
+    let base_name = file_name.rsplit('/').next().unwrap_or(file_name);
+    if data.starts_with(STORE_MAGIC) {
+        return extract_from_store(file_name, data, manifest);
+    }
+    if is_compressed_assembly(data)
+        || (DotNetMetadata::is_pe(data)
+            && (base_name.ends_with(".dll") || base_name.ends_with(".exe")))
+    {
+        return assembly(file_name, base_name.to_string(), data)
+            .into_iter()
+            .collect();
+    }
+    if !data.starts_with(b"\x7fELF") {
+        return vec![];
+    }
+    let Ok(elf) = Elf::parse(data) else {
+        return vec![];
+    };
+    let Some(payload) = elf
+        .section_headers
+        .iter()
+        .find(|header| elf.shdr_strtab.get_at(header.sh_name) == Some(PAYLOAD_SECTION))
+        .and_then(|header| {
+            let start = usize::try_from(header.sh_offset).ok()?;
+            let end = usize::try_from(header.sh_offset.checked_add(header.sh_size)?).ok()?;
+            data.get(start..end)
+        })
+    else {
+        return vec![];
+    };
+    if payload.starts_with(STORE_MAGIC) {
+        return extract_from_store(file_name, payload, manifest);
+    }
+    // `lib_Foo.dll.so` wraps `Foo.dll`
+    let name = base_name.strip_prefix("lib_").unwrap_or(base_name);
+    let name = name.strip_suffix(".so").unwrap_or(name);
+    assembly(file_name, assembly_file_name(name), payload)
+        .into_iter()
+        .collect()
+}
+
+fn extract_from_store(
+    file_name: &str,
+    store: &[u8],
+    manifest: Option<&AssemblyStoreManifest>,
+) -> Vec<ManagedAssembly> {
+    let Some(version) = u32_at(store, 4) else {
+        return vec![];
+    };
+    let entries = match version & STORE_VERSION_MASK {
+        1 => v1_store_entries(store, manifest),
+        _ => v2_store_entries(store, version),
+    };
+    let Some(entries) = entries else {
+        log::warn!("Could not read the assembly store {}", file_name);
+        return vec![];
+    };
+    entries
+        .into_iter()
+        .enumerate()
+        .filter_map(|(index, (name, offset, size))| {
+            let data = store.get(offset..offset + size)?;
+            let mut assembly = assembly(file_name, name.unwrap_or_default(), data)?;
+            if assembly.name.is_empty() {
+                assembly.name = assembly
+                    .metadata()
+                    .ok()
+                    .and_then(|metadata| metadata.assembly)
+                    .map(|name| assembly_file_name(&name.name))
+                    .unwrap_or_else(|| format!("assembly_{}.dll", index));
+            }
+            Some(assembly)
+        })
+        .collect()
+}
+
+/// Header: magic, version, local entry count, global entry count and store id, followed by the
+/// descriptors. The names are only listed in `assemblies.manifest`.
+fn v1_store_entries(
+    store: &[u8],
+    manifest: Option<&AssemblyStoreManifest>,
+) -> Option<Vec<(Option<String>, usize, usize)>> {
+    let count = u32_at(store, 8)? as usize;
+    let store_id = u32_at(store, 16)?;
+    (0..count)
+        .map(|index| {
+            let descriptor = STORE_HEADER_SIZE + index * V1_DESCRIPTOR_SIZE;
+            let name = manifest
+                .and_then(|manifest| manifest.names.get(&(store_id, index as u32)).cloned());
+            Some((
+                name,
+                u32_at(store, descriptor)? as usize,
+                u32_at(store, descriptor + 4)? as usize,
+            ))
+        })
+        .collect()
+}
+
+/// Header: magic, version, entry count, index entry count and index size in bytes, followed by
+/// the index (name hash, descriptor index and a flag byte per entry), the descriptors and the
+/// length prefixed names.
+fn v2_store_entries(store: &[u8], version: u32) -> Option<Vec<(Option<String>, usize, usize)>> {
+    let count = u32_at(store, 8)? as usize;
+    let index_count = u32_at(store, 12)? as usize;
+    let index_size = u32_at(store, 16)? as usize;
+    let hash_size = if version & STORE_64_BIT != 0 { 8 } else { 4 };
+    // prefer the size from the header, fall back to the packed index entries
+    let descriptors = [index_size, index_count * (hash_size + 5)]
+        .iter()
+        .map(|size| STORE_HEADER_SIZE + size)
+        .find(|start| {
+            (0..count).all(|index| {
+                let descriptor = start + index * V2_DESCRIPTOR_SIZE;
+                match (u32_at(store, descriptor + 4), u32_at(store, descriptor + 8)) {
+                    (Some(offset), Some(size)) => store
+                        .get(offset as usize..offset as usize + size as usize)
+                        .map(|data| is_compressed_assembly(data) || DotNetMetadata::is_pe(data))
+                        .unwrap_or(false),
+                    _ => false,
+                }
+            })
+        })?;
+    let mut names = descriptors + count * V2_DESCRIPTOR_SIZE;
+    (0..count)
+        .map(|index| {
+            let descriptor = descriptors + index * V2_DESCRIPTOR_SIZE;
+            let name = u32_at(store, names).and_then(|length| {
+                let name = store.get(names + 4..names + 4 + length as usize)?;
+                names += 4 + length as usize;
+                Some(assembly_file_name(&String::from_utf8_lossy(name)))
+            });
+            Some((
+                name,
+                u32_at(store, descriptor + 4)? as usize,
+                u32_at(store, descriptor + 8)? as usize,
+            ))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// An LZ4 block of literals only
+    fn lz4_literals(data: &[u8]) -> Vec<u8> {
+        let mut block = vec![(data.len().min(15) as u8) << 4];
+        if data.len() >= 15 {
+            let mut rest = data.len() - 15;
+            while rest >= 255 {
+                block.push(255);
+                rest -= 255;
+            }
+            block.push(rest as u8);
+        }
+        block.extend_from_slice(data);
+        block
+    }
+
+    fn compressed(data: &[u8]) -> Vec<u8> {
+        let mut assembly = COMPRESSED_MAGIC.to_vec();
+        assembly.extend_from_slice(&0u32.to_le_bytes());
+        assembly.extend_from_slice(&(data.len() as u32).to_le_bytes());
+        assembly.extend(lz4_literals(data));
+        assembly
+    }
+
+    fn names(assemblies: &[ManagedAssembly]) -> Vec<(&str, bool, &[u8])> {
+        assemblies
+            .iter()
+            .map(|assembly| {
+                (
+                    assembly.name.as_str(),
+                    assembly.compressed,
+                    assembly.data.as_slice(),
+                )
+            })
+            .collect()
+    }
+
+    #[test]
+    fn decompresses_lz4_blocks() {
+        for length in [0, 1, 14, 15, 16, 269, 270, 1000] {
+            let data: Vec<u8> = (0..length).map(|i| i as u8).collect();
+            assert_eq!(lz4_decompress(&lz4_literals(&data), length), Ok(data));
+        }
+        // three literals and an overlapping match of nine bytes repeating them
+        let block = [0x35, b'a', b'b', b'c', 3, 0, 0x00];
+        assert_eq!(lz4_decompress(&block, 12), Ok(b"abcabcabcabc".to_vec()));
+        // a match length extended by one byte
+        let block = [0x1f, b'x', 1, 0, 2, 0x00];
+        assert_eq!(lz4_decompress(&block, 22), Ok(vec![b'x'; 22]));
+    }
+
+    #[test]
+    fn rejects_malformed_lz4_blocks() {
+        assert!(lz4_decompress(&[], 0).is_err());
+        assert!(lz4_decompress(&[0x30, b'a'], 3).is_err());
+        assert!(lz4_decompress(&[0xf0, 255], 300).is_err());
+        assert!(lz4_decompress(&[0x10, b'a', 0], 5).is_err());
+        assert_eq!(
+            lz4_decompress(&[0x10, b'a', 0, 0, 0x00], 5),
+            Err("invalid match offset 0".to_string())
+        );
+        assert_eq!(
+            lz4_decompress(&[0x10, b'a', 2, 0, 0x00], 5),
+            Err("invalid match offset 2".to_string())
+        );
+        assert_eq!(
+            lz4_decompress(&[0x10, b'a', 1, 0, 0x00], 4),
+            Err("LZ4 block exceeds the decompressed size".to_string())
+        );
+        assert_eq!(
+            lz4_decompress(&lz4_literals(b"abc"), 4),
+            Err("decompressed 3 bytes instead of 4".to_string())
+        );
+    }
+
+    #[test]
+    fn decompresses_assemblies() {
+        assert_eq!(decompress_assembly(&compressed(b"MZ")), Ok(b"MZ".to_vec()));
+        assert!(decompress_assembly(b"MZ").is_err());
+        assert!(decompress_assembly(&compressed(b"MZ")[..11]).is_err());
+        let mut too_large = compressed(b"MZ");
+        put(&mut too_large, 8, &u32::MAX.to_le_bytes());
+        assert!(decompress_assembly(&too_large).is_err());
+    }
+
+    #[test]
+    fn parses_manifests() {
+        let manifest = AssemblyStoreManifest::parse(
+            "Hash 32     Hash 64             Blob ID  Blob idx  Name\n\
+             0xa2e0939b  0x4288cfb749e4c631  000      0000      Xamarin.AndroidX.Activity\n\
+             0xd2f2bd1b  0x7a5b1a4e7d1a7d0e  001      0003      App.exe\n\
+             broken line\n",
+        );
+        assert_eq!(manifest.names.len(), 2);
+        assert_eq!(
+            manifest.names.get(&(0, 0)).map(String::as_str),
+            Some("Xamarin.AndroidX.Activity.dll")
+        );
+        assert_eq!(
+            manifest.names.get(&(1, 3)).map(String::as_str),
+            Some("App.exe")
+        );
+    }
+
+    #[test]
+    fn extracts_single_assemblies() {
+        let assemblies = extract_managed_assemblies("assemblies/App.dll", b"MZ\x90\0", None);
+        assert_eq!(
+            names(&assemblies),
+            vec![("App.dll", false, &b"MZ\x90\0"[..])]
+        );
+        assert_eq!(assemblies[0].file_name, "assemblies/App.dll");
+        let assemblies =
+            extract_managed_assemblies("assemblies/Lib.dll", &compressed(b"MZ\x90\0"), None);
+        assert_eq!(
+            names(&assemblies),
+            vec![("Lib.dll", true, &b"MZ\x90\0"[..])]
+        );
+
+        // a PE image which is not named like an assembly and a broken compressed assembly
+        assert!(extract_managed_assemblies("assets/setup.bin", b"MZ", None).is_empty());
+        let mut broken = compressed(b"MZ\x90\0");
+        broken.truncate(14);
+        assert!(extract_managed_assemblies("assemblies/App.dll", &broken, None).is_empty());
+        assert!(extract_managed_assemblies("classes.dex", b"dex\n035", None).is_empty());
+    }
+
+    fn v1_store(assemblies: &[Vec<u8>]) -> Vec<u8> {
+        let mut store = vec![0u8; STORE_HEADER_SIZE + assemblies.len() * V1_DESCRIPTOR_SIZE];
+        put(&mut store, 0, STORE_MAGIC);
+        put(&mut store, 4, &1u32.to_le_bytes());
+        put(&mut store, 8, &(assemblies.len() as u32).to_le_bytes());
+        put(&mut store, 16, &1u32.to_le_bytes());
+        for (index, assembly) in assemblies.iter().enumerate() {
+            let descriptor = STORE_HEADER_SIZE + index * V1_DESCRIPTOR_SIZE;
+            let offset = store.len() as u32;
+            put(&mut store, descriptor, &offset.to_le_bytes());
+            put(
+                &mut store,
+                descriptor + 4,
+                &(assembly.len() as u32).to_le_bytes(),
+            );
+            store.extend_from_slice(assembly);
+        }
+        store
+    }
+
+    #[test]
+    fn extracts_v1_stores() {
+        let store = v1_store(&[b"MZ0000".to_vec(), compressed(b"MZ1111"), b"ELF".to_vec()]);
+        let manifest = AssemblyStoreManifest::parse("0x0 0x0 001 0001 Second");
+        let assemblies =
+            extract_managed_assemblies("assemblies/assemblies.blob", &store, Some(&manifest));
+        assert_eq!(
+            names(&assemblies),
+            vec![
+                ("assembly_0.dll", false, &b"MZ0000"[..]),
+                ("Second.dll", true, &b"MZ1111"[..]),
+            ]
+        );
+
+        // descriptors beyond the store
+        let mut broken = store.clone();
+        put(&mut broken, 8, &u32::MAX.to_le_bytes());
+        assert!(extract_managed_assemblies("assemblies.blob", &broken, None).is_empty());
+        let mut broken = store;
+        put(&mut broken, STORE_HEADER_SIZE, &u32::MAX.to_le_bytes());
+        assert_eq!(
+            extract_managed_assemblies("assemblies.blob", &broken, None).len(),
+            1
+        );
+        assert!(extract_managed_assemblies("assemblies.blob", b"XABA\x01", None).is_empty());
+    }
+
+    /// A store of 64 bit ABIs with the given index size in the header
+    fn v2_store(index_size: u32) -> Vec<u8> {
+        let descriptors = STORE_HEADER_SIZE + 13;
+        let mut store = vec![0u8; descriptors + V2_DESCRIPTOR_SIZE];
+        put(&mut store, 0, STORE_MAGIC);
+        put(&mut store, 4, &(2 | STORE_64_BIT).to_le_bytes());
+        put(&mut store, 8, &1u32.to_le_bytes());
+        put(&mut store, 12, &1u32.to_le_bytes());
+        put(&mut store, 16, &index_size.to_le_bytes());
+        store.extend_from_slice(&3u32.to_le_bytes());
+        store.extend_from_slice(b"Foo");
+        let offset = store.len() as u32;
+        put(&mut store, descriptors + 4, &offset.to_le_bytes());
+        put(&mut store, descriptors + 8, &6u32.to_le_bytes());
+        store.extend_from_slice(b"MZabcd");
+        store
+    }
+
+    #[test]
+    fn extracts_v2_stores() {
+        for index_size in [13, 999] {
+            let assemblies = extract_managed_assemblies(
+                "libassemblies.arm64-v8a.blob.so",
+                &v2_store(index_size),
+                None,
+            );
+            assert_eq!(names(&assemblies), vec![("Foo.dll", false, &b"MZabcd"[..])]);
+        }
+        // the name exceeds the store
+        let mut store = v2_store(13);
+        put(
+            &mut store,
+            STORE_HEADER_SIZE + 13 + V2_DESCRIPTOR_SIZE,
+            &u32::MAX.to_le_bytes(),
+        );
+        let assemblies = extract_managed_assemblies("assemblies.blob", &store, None);
+        assert_eq!(
+            names(&assemblies),
+            vec![("assembly_0.dll", false, &b"MZabcd"[..])]
+        );
+        // no descriptor points to an assembly
+        let mut store = v2_store(13);
+        put(
+            &mut store,
+            STORE_HEADER_SIZE + 13 + 8,
+            &u32::MAX.to_le_bytes(),
+        );
+        assert!(extract_managed_assemblies("assemblies.blob", &store, None).is_empty());
+    }
+
+    /// A 64 bit ELF file with a `payload` section at 0x60
+    fn elf_with_payload(payload: &[u8], offset: u64) -> Vec<u8> {
+        let mut data = vec![0u8; 0x100 + 3 * 64];
+        put(&mut data, 0, b"\x7fELF\x02\x01\x01");
+        put(&mut data, 16, &[3, 0, 0xb7, 0, 1, 0, 0, 0]);
+        put(&mut data, 40, &0x100u64.to_le_bytes());
+        put(&mut data, 52, &[64, 0, 56, 0, 0, 0, 64, 0, 3, 0, 2, 0]);
+        put(&mut data, 0x40, b"\0payload\0.shstrtab\0");
+        put(&mut data, 0x60, payload);
+        for (index, (name, kind, offset, size)) in [
+            (1u32, 1u32, offset, payload.len() as u64),
+            (9, 3, 0x40, 0x13),
+        ]
+        .iter()
+        .enumerate()
+        {
+            let header = 0x140 + index * 64;
+            put(&mut data, header, &name.to_le_bytes());
+            put(&mut data, header + 4, &kind.to_le_bytes());
+            put(&mut data, header + 24, &offset.to_le_bytes());
+            put(&mut data, header + 32, &size.to_le_bytes());
+        }
+        data
+    }
+
+    #[test]
+    fn extracts_assemblies_wrapped_in_elf_files() {
+        let data = elf_with_payload(&compressed(b"MZ"), 0x60);
+        let assemblies = extract_managed_assemblies("lib/arm64-v8a/lib_Foo.dll.so", &data, None);
+        assert_eq!(names(&assemblies), vec![("Foo.dll", true, &b"MZ"[..])]);
+        let data = elf_with_payload(&v1_store(&[b"MZ".to_vec()]), 0x60);
+        assert_eq!(
+            extract_managed_assemblies("libassemblies.x86.blob.so", &data, None).len(),
+            1
+        );
+        let data = elf_with_payload(&compressed(b"MZ"), u64::MAX - 4);
+        assert!(extract_managed_assemblies("lib_Foo.dll.so", &data, None).is_empty());
+    }
+}
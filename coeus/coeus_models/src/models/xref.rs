@@ -0,0 +1,749 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Cross references of all instructions of a `MultiDexFile`. The database is built with a single
+//! pass over all code the first time it is queried, afterwards every query is a hash lookup.
+//! References are keyed by name, such that references from all dex files are found:
+//!
+//! - methods as `Lcls;->name(args)ret`, or as `Lcls;->name` for all overloads
+//! - fields as `Lcls;->name:type`
+//! - strings by their content
+//! - types by their descriptor
+//! - 32 bit literals (e.g. resource ids) by their value
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, OnceLock},
+};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+
+use coeus_macros::iterator;
+
+use super::{is_complete_serialization, DexFile, Field, Instruction, Method, MultiDexFile};
+
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub enum XrefKind {
+    InvokePolymorphic,
+    /// `invoke-custom`, referencing the bootstrap method of the call site
+    InvokeCustom,
+    InvokeVirtual,
+    InvokeSuper,
+    InvokeDirect,
+    InvokeStatic,
+    InvokeInterface,
+    FieldRead,
+    FieldWrite,
+    ConstString,
+    ConstLiteral,
+    ConstClass,
+    CheckCast,
+    NewInstance,
+    NewArray,
+}
+
+/// An instruction referencing something
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash, serde::Serialize, serde::Deserialize)]
+pub struct XrefSite {
+    /// Position of the dex file in `MultiDexFile::dex_files`
+    pub dex: u16,
+    /// The method containing the instruction
+    pub method_idx: u32,
+    /// Offset of the instruction in code units
+    pub offset: u32,
+    pub kind: XrefKind,
+}
+
+#[derive(Debug, Default, Clone, serde::Serialize, serde::Deserialize)]
+pub struct XrefDatabase {
+    calls: HashMap<String, Vec<XrefSite>>,
+    /// the calls of all overloads, keyed by class and method name
+    overload_calls: HashMap<String, Vec<XrefSite>>,
+    field_reads: HashMap<String, Vec<XrefSite>>,
+    field_writes: HashMap<String, Vec<XrefSite>>,
+    strings: HashMap<String, Vec<XrefSite>>,
+    /// const-class, check-cast and array creations
+    types: HashMap<String, Vec<XrefSite>>,
+    instantiations: HashMap<String, Vec<XrefSite>>,
+    /// every site referencing a type, one of its methods or one of its fields
+    type_uses: HashMap<String, Vec<XrefSite>>,
+    /// 32 bit `const` instructions
+    literals: HashMap<u32, Vec<XrefSite>>,
+}
+
+enum Target {
+    /// class name, overload key and method key
+    Method(String, String, String),
+    FieldRead(String, String),
+    FieldWrite(String, String),
+    String(String),
+    Type(String),
+    Instantiation(String),
+    Literal(u32),
+}
+
+impl XrefDatabase {
+    pub fn new(multi_dex: &MultiDexFile) -> Self {
+        let mut database = Self::default();
+        for (dex_pos, dex) in multi_dex.dex_files().enumerate() {
+            let references: Vec<(Target, XrefSite)> = iterator!(dex.classes)
+                .flat_map(|class| {
+                    let mut references = vec![];
+                    for method_data in &class.codes {
+                        let Some(code) = method_data.code.as_ref() else {
+                            continue;
+                        };
+                        for (_, offset, instruction) in &code.insns {
+                            let Some((target, kind)) = reference(dex, instruction) else {
+                                continue;
+                            };
+                            references.push((
+                                target,
+                                XrefSite {
+                                    dex: dex_pos as u16,
+                                    method_idx: method_data.method_idx,
+                                    offset: offset.0,
+                                    kind,
+                                },
+                            ));
+                        }
+                    }
+                    references
+                })
+                .collect();
+            for (target, site) in references {
+                database.insert(target, site);
+            }
+        }
+        database
+    }
+
+    fn insert(&mut self, target: Target, site: XrefSite) {
+        let (map, key, class_name) = match target {
+            Target::Literal(value) => {
+                self.literals.entry(value).or_default().push(site);
+                return;
+            }
+            Target::Method(class_name, overload_key, key) => {
+                self.overload_calls
+                    .entry(overload_key)
+                    .or_default()
+                    .push(site);
+                (&mut self.calls, key, Some(class_name))
+            }
+            Target::FieldRead(class_name, key) => (&mut self.field_reads, key, Some(class_name)),
+            Target::FieldWrite(class_name, key) => (&mut self.field_writes, key, Some(class_name)),
+            Target::String(content) => (&mut self.strings, content, None),
+            Target::Type(type_name) => {
+                self.type_uses
+                    .entry(type_name.clone())
+                    .or_default()
+                    .push(site);
+                (&mut self.types, type_name, None)
+            }
+            Target::Instantiation(type_name) => {
+                self.type_uses
+                    .entry(type_name.clone())
+                    .or_default()
+                    .push(site);
+                (&mut self.instantiations, type_name, None)
+            }
+        };
+        map.entry(key).or_default().push(site);
+        if let Some(class_name) = class_name {
+            self.type_uses.entry(class_name).or_default().push(site);
+        }
+    }
+
+    /// All invocations of the method, given as `Lcls;->name(args)ret`
+    pub fn callers(&self, method: &str) -> &[XrefSite] {
+        lookup(&self.calls, method)
+    }
+
+    /// All invocations of any overload of the method, given as `Lcls;->name`
+    pub fn overload_callers(&self, method: &str) -> &[XrefSite] {
+        lookup(&self.overload_calls, method)
+    }
+
+    /// All reads of the field, given as `Lcls;->name:type`
+    pub fn field_reads(&self, field: &str) -> &[XrefSite] {
+        lookup(&self.field_reads, field)
+    }
+
+    /// All writes of the field, given as `Lcls;->name:type`
+    pub fn field_writes(&self, field: &str) -> &[XrefSite] {
+        lookup(&self.field_writes, field)
+    }
+
+    /// All `const-string` instructions loading exactly this string
+    pub fn string_references(&self, content: &str) -> &[XrefSite] {
+        lookup(&self.strings, content)
+    }
+
+    /// All class constants, casts and array creations of the type
+    pub fn type_references(&self, type_name: &str) -> &[XrefSite] {
+        lookup(&self.types, type_name)
+    }
+
+    /// All `new-instance` instructions of the type
+    pub fn instantiations(&self, type_name: &str) -> &[XrefSite] {
+        lookup(&self.instantiations, type_name)
+    }
+
+    /// All instructions referencing the type or one of its members
+    pub fn type_uses(&self, type_name: &str) -> &[XrefSite] {
+        lookup(&self.type_uses, type_name)
+    }
+
+    /// All 32 bit `const` instructions loading exactly this value
+    pub fn literal_references(&self, value: u32) -> &[XrefSite] {
+        self.literals
+            .get(&value)
+            .map(|sites| sites.as_slice())
+            .unwrap_or_default()
+    }
+
+    /// All 32 bit literals loaded anywhere in the code, together with the loading instructions
+    pub fn literals(&self) -> impl Iterator<Item = (u32, &[XrefSite])> {
+        self.literals
+            .iter()
+            .map(|(value, sites)| (*value, sites.as_slice()))
+    }
+}
+
+fn lookup<'a>(map: &'a HashMap<String, Vec<XrefSite>>, key: &str) -> &'a [XrefSite] {
+    map.get(key)
+        .map(|sites| sites.as_slice())
+        .unwrap_or_default()
+}
+
+/// The key of a method in the database, e.g. `Ljava/lang/Object;->toString()Ljava/lang/String;`
+pub fn method_xref_key(dex: &DexFile, method: &Method) -> String {
+    format!(
+        "{}->{}{}",
+        dex.get_type_name(method.class_idx).unwrap_or_default(),
+        method.method_name,
+        method.proto_name
+    )
+}
+
+/// The key of all overloads of a method in the database, e.g. `Ljava/lang/Object;->equals`
+pub fn method_overload_xref_key(dex: &DexFile, method: &Method) -> String {
+    format!(
+        "{}->{}",
+        dex.get_type_name(method.class_idx).unwrap_or_default(),
+        method.method_name
+    )
+}
+
+/// The key of a field in the database, e.g. `Landroid/os/Build;->MODEL:Ljava/lang/String;`
+pub fn field_xref_key(dex: &DexFile, field: &Field) -> String {
+    format!(
+        "{}->{}:{}",
+        dex.get_type_name(field.class_idx).unwrap_or_default(),
+        field.name,
+        dex.get_type_name(field.type_idx).unwrap_or_default()
+    )
+}
+
+fn reference(dex: &DexFile, instruction: &Instruction) -> Option<(Target, XrefKind)> {
+    let method = |method_idx: &u16| {
+        let method = dex.methods.get(*method_idx as usize)?;
+        let class_name = dex.get_type_name(method.class_idx)?.to_string();
+        Some(Target::Method(
+            class_name,
+            method_overload_xref_key(dex, method),
+            method_xref_key(dex, method),
+        ))
+    };
+    let field = |field_idx: &u16, write: bool| {
+        let field = dex.fields.get(*field_idx as usize)?;
+        let class_name = dex.get_type_name(field.class_idx)?.to_string();
+        let key = field_xref_key(dex, field);
+        Some(if write {
+            Target::FieldWrite(class_name, key)
+        } else {
+            Target::FieldRead(class_name, key)
+        })
+    };
+    let type_name = |type_idx: &u16| dex.get_type_name(*type_idx).map(|name| name.to_string());
+
+    Some(match instruction {
+        Instruction::Invoke(method_idx) => (method(method_idx)?, XrefKind::InvokePolymorphic),
+        Instruction::InvokeCustom(call_site_idx) => {
+            let call_site = dex.call_sites.get(*call_site_idx as usize)?;
+            (
+                method(call_site.bootstrap_method_idx.as_ref()?)?,
+                XrefKind::InvokeCustom,
+            )
+        }
+        Instruction::InvokeVirtual(_, method_idx, _)
+        | Instruction::InvokeVirtualRange(_, method_idx, _) => {
+            (method(method_idx)?, XrefKind::InvokeVirtual)
+        }
+        Instruction::InvokeSuper(_, method_idx, _)
+        | Instruction::InvokeSuperRange(_, method_idx, _) => {
+            (method(method_idx)?, XrefKind::InvokeSuper)
+        }
+        Instruction::InvokeDirect(_, method_idx, _)
+        | Instruction::InvokeDirectRange(_, method_idx, _) => {
+            (method(method_idx)?, XrefKind::InvokeDirect)
+        }
+        Instruction::InvokeStatic(_, method_idx, _)
+        | Instruction::InvokeStaticRange(_, method_idx, _) => {
+            (method(method_idx)?, XrefKind::InvokeStatic)
+        }
+        Instruction::InvokeInterface(_, method_idx, _)
+        | Instruction::InvokeInterfaceRange(_, method_idx, _) => {
+            (method(method_idx)?, XrefKind::InvokeInterface)
+        }
+
+        Instruction::StaticGet(_, field_idx)
+        | Instruction::StaticGetWide(_, field_idx)
+        | Instruction::StaticGetObject(_, field_idx)
+        | Instruction::StaticGetBoolean(_, field_idx)
+        | Instruction::StaticGetByte(_, field_idx)
+        | Instruction::StaticGetChar(_, field_idx)
+        | Instruction::StaticGetShort(_, field_idx)
+        | Instruction::InstanceGet(_, _, field_idx)
+        | Instruction::InstanceGetWide(_, _, field_idx)
+        | Instruction::InstanceGetObject(_, _, field_idx)
+        | Instruction::InstanceGetBoolean(_, _, field_idx)
+        | Instruction::InstanceGetByte(_, _, field_idx)
+        | Instruction::InstanceGetChar(_, _, field_idx)
+        | Instruction::InstanceGetShort(_, _, field_idx) => {
+            (field(field_idx, false)?, XrefKind::FieldRead)
+        }
+        Instruction::StaticPut(_, field_idx)
+        | Instruction::StaticPutWide(_, field_idx)
+        | Instruction::StaticPutObject(_, field_idx)
+        | Instruction::StaticPutBoolean(_, field_idx)
+        | Instruction::StaticPutByte(_, field_idx)
+        | Instruction::StaticPutChar(_, field_idx)
+        | Instruction::StaticPutShort(_, field_idx)
+        | Instruction::InstancePut(_, _, field_idx)
+        | Instruction::InstancePutWide(_, _, field_idx)
+        | Instruction::InstancePutObject(_, _, field_idx)
+        | Instruction::InstancePutBoolean(_, _, field_idx)
+        | Instruction::InstancePutByte(_, _, field_idx)
+        | Instruction::InstancePutChar(_, _, field_idx)
+        | Instruction::InstancePutShort(_, _, field_idx) => {
+            (field(field_idx, true)?, XrefKind::FieldWrite)
+        }
+
+        Instruction::ConstString(_, string_idx) => (
+            Target::String(
+                dex.strings
+                    .get(*string_idx as usize)?
+                    .to_str_lossy()
+                    .into_owned(),
+            ),
+            XrefKind::ConstString,
+        ),
+        Instruction::ConstStringJumbo(_, string_idx) => (
+            Target::String(
+                dex.strings
+                    .get(*string_idx as usize)?
+                    .to_str_lossy()
+                    .into_owned(),
+            ),
+            XrefKind::ConstString,
+        ),
+
+        Instruction::ConstLit32(_, value) => {
+            (Target::Literal(*value as u32), XrefKind::ConstLiteral)
+        }
+
+        Instruction::ConstClass(_, type_idx) => {
+            (Target::Type(type_name(type_idx)?), XrefKind::ConstClass)
+        }
+        Instruction::CheckCast(_, type_idx) => {
+            (Target::Type(type_name(type_idx)?), XrefKind::CheckCast)
+        }
+        Instruction::NewArray(_, _, type_idx)
+        | Instruction::FilledNewArray(_, type_idx, _)
+        | Instruction::FilledNewArrayRange(_, type_idx, _) => {
+            (Target::Type(type_name(type_idx)?), XrefKind::NewArray)
+        }
+        Instruction::NewInstance(_, type_idx) => (
+            Target::Instantiation(type_name(type_idx)?),
+            XrefKind::NewInstance,
+        ),
+        _ => return None,
+    })
+}
+
+/// The lazily built `XrefDatabase` of a `MultiDexFile`. It is only contained in the complete
+/// serialization format, where it is stored if it was built before.
+#[derive(Default, Clone)]
+pub struct XrefIndex(OnceLock<Arc<XrefDatabase>>);
+
+impl std::fmt::Debug for XrefIndex {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_tuple("XrefIndex")
+            .field(&self.0.get().is_some())
+            .finish()
+    }
+}
+
+impl XrefIndex {
+    pub(crate) fn get(&self, multi_dex: &MultiDexFile) -> &XrefDatabase {
+        self.0
+            .get_or_init(|| Arc::new(XrefDatabase::new(multi_dex)))
+    }
+
+    pub(crate) fn is_built(&self) -> bool {
+        self.0.get().is_some()
+    }
+}
+
+impl serde::Serialize for XrefIndex {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let database = if is_complete_serialization() {
+            self.0.get().map(|database| database.as_ref())
+        } else {
+            None
+        };
+        serializer.serialize_newtype_struct("XrefIndex", &database)
+    }
+}
+
+impl<'de> serde::Deserialize<'de> for XrefIndex {
+    fn deserialize<D: serde::Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        #[derive(serde::Deserialize)]
+        struct XrefIndex(Option<XrefDatabase>);
+        let database = XrefIndex::deserialize(deserializer)?.0;
+        let index = OnceLock::new();
+        if let Some(database) = database {
+            let _ = index.set(Arc::new(database));
+        }
+        Ok(self::XrefIndex(index))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashSet;
+
+    use super::super::{
+        testing::{format_21c, format_22c, format_31i, format_35c, DexBuilder, RETURN_VOID},
+        AndroidManifest, CallSite,
+    };
+    use super::*;
+
+    const ALL_KINDS: [XrefKind; 15] = [
+        XrefKind::InvokePolymorphic,
+        XrefKind::InvokeCustom,
+        XrefKind::InvokeVirtual,
+        XrefKind::InvokeSuper,
+        XrefKind::InvokeDirect,
+        XrefKind::InvokeStatic,
+        XrefKind::InvokeInterface,
+        XrefKind::FieldRead,
+        XrefKind::FieldWrite,
+        XrefKind::ConstString,
+        XrefKind::ConstLiteral,
+        XrefKind::ConstClass,
+        XrefKind::CheckCast,
+        XrefKind::NewInstance,
+        XrefKind::NewArray,
+    ];
+
+    const OBJECT: &str = "Ljava/lang/Object;";
+    const STRING: &str = "Ljava/lang/String;";
+
+    /// Concatenate the instructions, returning the code and the offset of each instruction
+    fn assemble(instructions: &[&[u16]]) -> (Vec<u16>, Vec<u32>) {
+        let mut code = vec![];
+        let mut offsets = vec![];
+        for instruction in instructions {
+            offsets.push(code.len() as u32);
+            code.extend_from_slice(instruction);
+        }
+        code.push(RETURN_VOID);
+        (code, offsets)
+    }
+
+    /// A dex file with one instruction of each kind, in the order of `ALL_KINDS` followed by
+    /// the range and jumbo variants
+    fn primary() -> (DexFile, Vec<u32>) {
+        let mut dex = DexBuilder::new("classes");
+        let objects = "[Ljava/lang/Object;";
+        let invoke_exact = dex.method(
+            "Ljava/lang/invoke/MethodHandle;",
+            "invokeExact",
+            OBJECT,
+            &[objects],
+        );
+        let invoke_exact_proto = dex.proto("V", &[]);
+        let bootstrap = dex.method(
+            "La/Main;",
+            "bootstrap",
+            "Ljava/lang/invoke/CallSite;",
+            &[
+                "Ljava/lang/invoke/MethodHandles$Lookup;",
+                STRING,
+                "Ljava/lang/invoke/MethodType;",
+            ],
+        );
+        let to_string = dex.method(OBJECT, "toString", STRING, &[]);
+        let hash_code = dex.method(OBJECT, "hashCode", "I", &[]);
+        let init = dex.method(OBJECT, "<init>", "V", &[]);
+        let value_of = dex.method(STRING, "valueOf", STRING, &[OBJECT]);
+        let value_of_int = dex.method(STRING, "valueOf", STRING, &["I"]);
+        let run = dex.method("Ljava/lang/Runnable;", "run", "V", &[]);
+        let model = dex.field("Landroid/os/Build;", "MODEL", STRING);
+        let count = dex.field("La/Main;", "count", "I");
+        let hello = dex.string("hello") as u16;
+        let jumbo = dex.string("jumbo");
+        let main_type = dex.type_idx("La/Main;");
+        let runnable_type = dex.type_idx("Ljava/lang/Runnable;");
+        let int_array = dex.type_idx("[I");
+        let string_array = dex.type_idx("[Ljava/lang/String;");
+        let string_builder = dex.type_idx("Ljava/lang/StringBuilder;");
+
+        let main = dex.class("La/Main;", Some(OBJECT), &[]);
+        let caller = dex.method("La/Main;", "caller", "V", &[]);
+        let (code, offsets) = assemble(&[
+            // invoke-polymorphic {v0, v1}
+            &[0xfa | 2 << 12, invoke_exact, 0x0010, invoke_exact_proto],
+            &format_35c(0xfc, 0, &[]),
+            &format_35c(0x6e, to_string, &[0]),
+            &format_35c(0x6f, hash_code, &[0]),
+            &format_35c(0x70, init, &[0]),
+            &format_35c(0x71, value_of, &[0]),
+            &format_35c(0x72, run, &[0]),
+            &format_21c(0x62, 0, model),
+            &format_21c(0x67, 0, count),
+            &format_21c(0x1a, 0, hello),
+            &format_31i(0x14, 0, 0x7f010001),
+            &format_21c(0x1c, 0, main_type),
+            &format_21c(0x1f, 0, runnable_type),
+            &format_21c(0x22, 0, string_builder),
+            &format_22c(0x23, 0, 1, int_array),
+            // invoke-static/range {v0}, const-string/jumbo, iget, iput and filled-new-array
+            &[0x77 | 1 << 8, value_of_int, 0],
+            &format_31i(0x1b, 0, jumbo),
+            &format_22c(0x52, 0, 1, count),
+            &format_22c(0x59, 0, 1, count),
+            &format_35c(0x24, string_array, &[0, 1]),
+        ]);
+        dex.direct_method(main, caller, &code);
+        dex.direct_method(main, bootstrap, &[RETURN_VOID]);
+
+        let mut dex = dex.build();
+        dex.call_sites.push(CallSite {
+            bootstrap_method_idx: Some(bootstrap),
+            method_name: "apply".to_string(),
+            proto_idx: 0,
+        });
+        (dex, offsets)
+    }
+
+    /// A second dex file referencing the same method and literal
+    fn secondary() -> DexFile {
+        let mut dex = DexBuilder::new("classes2");
+        let to_string = dex.method(OBJECT, "toString", STRING, &[]);
+        let other = dex.class("Lb/Other;", Some(OBJECT), &[]);
+        let run = dex.method("Lb/Other;", "run", "V", &[]);
+        let (code, _) = assemble(&[
+            &format_35c(0x6e, to_string, &[0]),
+            &format_31i(0x14, 0, 0x7f010001),
+        ]);
+        dex.virtual_method(other, run, &code);
+        dex.build()
+    }
+
+    fn multi_dex() -> (MultiDexFile, Vec<u32>) {
+        let (primary, offsets) = primary();
+        let multi_dex = MultiDexFile::new(
+            AndroidManifest::default(),
+            String::new(),
+            primary,
+            vec![secondary()],
+        );
+        (multi_dex, offsets)
+    }
+
+    /// Dex position, offset and kind of each site
+    type Sites = Vec<(u16, u32, XrefKind)>;
+
+    fn sites(sites: &[XrefSite]) -> Sites {
+        let mut sites: Vec<_> = sites
+            .iter()
+            .map(|site| (site.dex, site.offset, site.kind))
+            .collect();
+        sites.sort_unstable_by_key(|&(dex, offset, _)| (dex, offset));
+        sites
+    }
+
+    #[test]
+    fn every_kind_is_indexed() {
+        let (multi_dex, offsets) = multi_dex();
+        let xrefs = multi_dex.xrefs();
+        let expect = |i: usize, kind: XrefKind| (0u16, offsets[i], kind);
+
+        let lookups: Vec<(&[XrefSite], Sites)> = vec![
+            (
+                xrefs.callers(
+                    "Ljava/lang/invoke/MethodHandle;->invokeExact([Ljava/lang/Object;)Ljava/lang/Object;",
+                ),
+                vec![expect(0, XrefKind::InvokePolymorphic)],
+            ),
+            (
+                xrefs.overload_callers("La/Main;->bootstrap"),
+                vec![expect(1, XrefKind::InvokeCustom)],
+            ),
+            (
+                xrefs.callers("Ljava/lang/Object;->toString()Ljava/lang/String;"),
+                vec![expect(2, XrefKind::InvokeVirtual), (1, 0, XrefKind::InvokeVirtual)],
+            ),
+            (
+                xrefs.callers("Ljava/lang/Object;->hashCode()I"),
+                vec![expect(3, XrefKind::InvokeSuper)],
+            ),
+            (
+                xrefs.callers("Ljava/lang/Object;-><init>()V"),
+                vec![expect(4, XrefKind::InvokeDirect)],
+            ),
+            (
+                xrefs.callers("Ljava/lang/String;->valueOf(Ljava/lang/Object;)Ljava/lang/String;"),
+                vec![expect(5, XrefKind::InvokeStatic)],
+            ),
+            (
+                xrefs.callers("Ljava/lang/Runnable;->run()V"),
+                vec![expect(6, XrefKind::InvokeInterface)],
+            ),
+            (
+                xrefs.field_reads("Landroid/os/Build;->MODEL:Ljava/lang/String;"),
+                vec![expect(7, XrefKind::FieldRead)],
+            ),
+            (
+                xrefs.field_writes("La/Main;->count:I"),
+                vec![expect(8, XrefKind::FieldWrite), expect(18, XrefKind::FieldWrite)],
+            ),
+            (
+                xrefs.string_references("hello"),
+                vec![expect(9, XrefKind::ConstString)],
+            ),
+            (
+                xrefs.literal_references(0x7f010001),
+                vec![expect(10, XrefKind::ConstLiteral), (1, 3, XrefKind::ConstLiteral)],
+            ),
+            (
+                xrefs.type_references("La/Main;"),
+                vec![expect(11, XrefKind::ConstClass)],
+            ),
+            (
+                xrefs.type_references("Ljava/lang/Runnable;"),
+                vec![expect(12, XrefKind::CheckCast)],
+            ),
+            (
+                xrefs.instantiations("Ljava/lang/StringBuilder;"),
+                vec![expect(13, XrefKind::NewInstance)],
+            ),
+            (
+                xrefs.type_references("[I"),
+                vec![expect(14, XrefKind::NewArray)],
+            ),
+            // range and jumbo variants
+            (
+                xrefs.callers("Ljava/lang/String;->valueOf(I)Ljava/lang/String;"),
+                vec![expect(15, XrefKind::InvokeStatic)],
+            ),
+            (
+                xrefs.string_references("jumbo"),
+                vec![expect(16, XrefKind::ConstString)],
+            ),
+            (
+                xrefs.field_reads("La/Main;->count:I"),
+                vec![expect(17, XrefKind::FieldRead)],
+            ),
+            (
+                xrefs.type_references("[Ljava/lang/String;"),
+                vec![expect(19, XrefKind::NewArray)],
+            ),
+        ];
+        let mut covered = HashSet::new();
+        for (i, (found, expected)) in lookups.iter().enumerate() {
+            assert_eq!(&sites(found), expected, "lookup {}", i);
+            covered.extend(found.iter().map(|site| site.kind));
+        }
+        assert_eq!(covered, HashSet::from(ALL_KINDS));
+        // the first instructions are in the order of ALL_KINDS
+        for (i, kind) in ALL_KINDS.iter().enumerate() {
+            assert!(lookups[i].1.contains(&expect(i, *kind)));
+        }
+    }
+
+    #[test]
+    fn sites_point_to_the_containing_method() {
+        let (multi_dex, _) = multi_dex();
+        let xrefs = multi_dex.xrefs();
+        let callers = xrefs.callers("Ljava/lang/Object;->toString()Ljava/lang/String;");
+        let mut methods: Vec<_> = callers
+            .iter()
+            .map(|site| {
+                let (dex, method) = multi_dex.xref_method(site).unwrap();
+                format!("{}:{}", dex.identifier, method_xref_key(&dex, &method))
+            })
+            .collect();
+        methods.sort();
+        assert_eq!(
+            methods,
+            ["classes2:Lb/Other;->run()V", "classes:La/Main;->caller()V"]
+        );
+    }
+
+    #[test]
+    fn overloads_and_type_uses_are_aggregated() {
+        let (multi_dex, offsets) = multi_dex();
+        let xrefs = multi_dex.xrefs();
+        assert_eq!(
+            sites(xrefs.overload_callers("Ljava/lang/String;->valueOf")),
+            [
+                (0, offsets[5], XrefKind::InvokeStatic),
+                (0, offsets[15], XrefKind::InvokeStatic)
+            ]
+        );
+        // calls of its methods
+        assert_eq!(
+            sites(xrefs.type_uses(OBJECT))
+                .iter()
+                .map(|&(_, _, kind)| kind)
+                .collect::<Vec<_>>(),
+            [
+                XrefKind::InvokeVirtual,
+                XrefKind::InvokeSuper,
+                XrefKind::InvokeDirect,
+                XrefKind::InvokeVirtual
+            ]
+        );
+        // the class constant and the accesses of its field
+        assert_eq!(
+            sites(xrefs.type_uses("La/Main;"))
+                .iter()
+                .map(|&(_, offset, _)| offset)
+                .collect::<Vec<_>>(),
+            [
+                offsets[1],
+                offsets[8],
+                offsets[11],
+                offsets[17],
+                offsets[18]
+            ]
+        );
+        assert_eq!(xrefs.type_uses("Ljava/lang/StringBuilder;").len(), 1);
+        assert_eq!(
+            xrefs.literals().map(|(value, _)| value).collect::<Vec<_>>(),
+            [0x7f010001]
+        );
+        assert!(xrefs.callers("Ljava/lang/Object;->toString").is_empty());
+        assert!(xrefs.string_references("hell").is_empty());
+        assert!(xrefs.literal_references(0).is_empty());
+    }
+}
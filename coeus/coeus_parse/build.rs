@@ -0,0 +1,60 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Hashes the sources of the models stored in the analysis cache, such that entries written by a
+//! build with different models are not read.
+
+use std::{
+    env, fs,
+    path::{Path, PathBuf},
+};
+
+use sha2::{Digest, Sha256};
+
+/// Everything serialized into cache entries: the models and the supergraph nodes
+const CACHED_MODEL_SOURCES: &[&str] = &["../coeus_models/src", "src/dex/graph"];
+
+fn collect_sources(directory: &Path, sources: &mut Vec<PathBuf>) {
+    let entries = fs::read_dir(directory)
+        .unwrap_or_else(|e| panic!("Could not read {}: {}", directory.display(), e));
+    for entry in entries.flatten() {
+        let path = entry.path();
+        if path.is_dir() {
+            collect_sources(&path, sources);
+        } else if path.extension().is_some_and(|extension| extension == "rs") {
+            sources.push(path);
+        }
+    }
+}
+
+fn main() {
+    println!("cargo:rerun-if-changed=build.rs");
+    let manifest_dir = PathBuf::from(env::var("CARGO_MANIFEST_DIR").unwrap());
+    let mut sources = vec![];
+    for directory in CACHED_MODEL_SOURCES {
+        println!("cargo:rerun-if-changed={}", directory);
+        collect_sources(&manifest_dir.join(directory), &mut sources);
+    }
+    sources.sort();
+
+    let mut hasher = Sha256::new();
+    for source in &sources {
+        let relative = source.strip_prefix(&manifest_dir).unwrap_or(source);
+        hasher.update(relative.to_string_lossy().as_bytes());
+        hasher.update([0]);
+        hasher.update(fs::read(source).expect("Failed to read model source"));
+    }
+    let hash: String = hasher.finalize()[..8]
+        .iter()
+        .map(|b| format!("{:02x}", b))
+        .collect();
+    let out_dir = PathBuf::from(env::var("OUT_DIR").unwrap());
+    fs::write(
+        out_dir.join("cache_models.rs"),
+        format!("const CACHED_MODELS_HASH: &str = \"{}\";\n", hash),
+    )
+    .expect("Failed to write the models hash");
+}
@@ -0,0 +1,528 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Generation and storage of `ApiDatabase`s. A database is generated from the `android.jar` of a
+//! platform in the SDK (`platforms/android-<level>/android.jar`), optionally together with
+//! `platform-tools/api/api-versions.xml` which provides the API levels of every class and member.
+//! Without the latter everything is assumed to exist since the level of the jar.
+//!
+//! Generated databases are stored as compressed bincode and can be installed with
+//! `ApiDatabase::set_global`, which replaces the bundled database for all later lookups.
+
+use std::{
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::Path,
+};
+
+use coeus_models::models::{
+    class_name_to_descriptor, AccessFlags, ApiClass, ApiDatabase, ApiLevels, ApiMember,
+    ApiReference, ClassFile, ACC_SUPER,
+};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+
+use crate::archive::ZipReader;
+
+const API_DATABASE_MAGIC: &[u8; 8] = b"COEUSAPI";
+/// Bump whenever the layout of the database models changes
+const API_DATABASE_FORMAT_VERSION: u32 = 1;
+
+pub fn load<P: AsRef<Path>>(path: P) -> std::io::Result<ApiDatabase> {
+    from_reader(BufReader::new(File::open(path)?))
+}
+
+pub fn store<P: AsRef<Path>>(database: &ApiDatabase, path: P) -> std::io::Result<()> {
+    let mut writer = BufWriter::new(File::create(path)?);
+    to_writer(database, &mut writer)?;
+    writer.flush()
+}
+
+pub fn from_bytes(data: &[u8]) -> std::io::Result<ApiDatabase> {
+    from_reader(data)
+}
+
+pub fn to_bytes(database: &ApiDatabase) -> std::io::Result<Vec<u8>> {
+    let mut data = vec![];
+    to_writer(database, &mut data)?;
+    Ok(data)
+}
+
+fn from_reader<R: Read>(mut reader: R) -> std::io::Result<ApiDatabase> {
+    let mut magic = [0; 8];
+    reader.read_exact(&mut magic)?;
+    if &magic != API_DATABASE_MAGIC {
+        return Err(invalid_data("Not an api database".to_string()));
+    }
+    let mut version = [0; 4];
+    reader.read_exact(&mut version)?;
+    let version = u32::from_le_bytes(version);
+    if version != API_DATABASE_FORMAT_VERSION {
+        return Err(invalid_data(format!(
+            "Unsupported api database format {}",
+            version
+        )));
+    }
+    bincode::deserialize_from(DeflateDecoder::new(reader)).map_err(to_io_error)
+}
+
+fn to_writer<W: Write>(database: &ApiDatabase, mut writer: W) -> std::io::Result<()> {
+    writer.write_all(API_DATABASE_MAGIC)?;
+    writer.write_all(&API_DATABASE_FORMAT_VERSION.to_le_bytes())?;
+    let mut encoder = DeflateEncoder::new(writer, Compression::best());
+    bincode::serialize_into(&mut encoder, database).map_err(to_io_error)?;
+    encoder.finish()?.flush()
+}
+
+/// Generate a database from the `android.jar` of `api_level` and optionally the content of
+/// `api-versions.xml`. Classes and members only found in the jar are assumed to exist since
+/// `api_level`.
+pub fn generate_api_database(
+    android_jar: &[u8],
+    api_versions_xml: Option<&str>,
+    api_level: u32,
+) -> Result<ApiDatabase, String> {
+    let mut database = match api_versions_xml {
+        Some(xml) => parse_api_versions(xml)?,
+        None => ApiDatabase::new(api_level),
+    };
+    database.api_level = database.api_level.max(api_level);
+    let jar_levels = ApiLevels {
+        since: api_level,
+        ..Default::default()
+    };
+
+    let mut archive = ZipReader::new(android_jar, "android.jar");
+    let class_entries: Vec<usize> = (0..archive.len())
+        .filter(|&i| archive.entries()[i].name.ends_with(".class"))
+        .collect();
+    if class_entries.is_empty() {
+        return Err("android.jar does not contain any class files".to_string());
+    }
+    for i in class_entries {
+        let Some(data) = archive.read(i) else {
+            continue;
+        };
+        let class_file = match ClassFile::parse(&data) {
+            Ok(class_file) => class_file,
+            Err(e) => {
+                log::warn!("Could not parse {}: {}", archive.entries()[i].name, e);
+                continue;
+            }
+        };
+        let name = class_name_to_descriptor(&class_file.name);
+        let access_flags =
+            AccessFlags::from_bits_truncate((class_file.access_flags & !ACC_SUPER) as u64);
+        let mut class = match database.get_class(&name) {
+            Some(class) => class.clone(),
+            None => ApiClass::new(&name, access_flags, jar_levels),
+        };
+        class.access_flags = access_flags;
+        if class.super_classes.is_empty() {
+            if let Some(super_name) = &class_file.super_name {
+                class.super_classes.push(ApiReference {
+                    name: class_name_to_descriptor(super_name),
+                    levels: jar_levels,
+                });
+            }
+        }
+        for interface in &class_file.interfaces {
+            let interface = class_name_to_descriptor(interface);
+            if !class.interfaces.iter().any(|i| i.name == interface) {
+                class.interfaces.push(ApiReference {
+                    name: interface,
+                    levels: jar_levels,
+                });
+            }
+        }
+        for method in &class_file.methods {
+            let signature = format!("{}{}", method.name, method.descriptor);
+            let access_flags = AccessFlags::from_bits_truncate(method.access_flags as u64);
+            match class.methods.iter_mut().find(|m| m.signature == signature) {
+                Some(member) => member.access_flags = access_flags,
+                None => class.methods.push(ApiMember {
+                    signature,
+                    access_flags,
+                    levels: jar_levels,
+                }),
+            }
+        }
+        for field in &class_file.fields {
+            let signature = format!("{}:{}", field.name, field.descriptor);
+            let access_flags = AccessFlags::from_bits_truncate(field.access_flags as u64);
+            // api-versions.xml lists fields without their type
+            match class.fields.iter_mut().find(|f| f.name() == field.name) {
+                Some(member) => {
+                    member.signature = signature;
+                    member.access_flags = access_flags;
+                }
+                None => class.fields.push(ApiMember {
+                    signature,
+                    access_flags,
+                    levels: jar_levels,
+                }),
+            }
+        }
+        database.insert(class);
+    }
+    Ok(database)
+}
+
+#[derive(serde::Deserialize)]
+struct XmlApi {
+    #[serde(rename = "$value", default)]
+    content: Vec<XmlApiContent>,
+}
+
+#[derive(serde::Deserialize)]
+enum XmlApiContent {
+    #[serde(rename = "sdk")]
+    Sdk(serde::de::IgnoredAny),
+    #[serde(rename = "class")]
+    Class(XmlClass),
+}
+
+#[derive(serde::Deserialize)]
+struct XmlClass {
+    name: String,
+    since: Option<String>,
+    deprecated: Option<String>,
+    removed: Option<String>,
+    #[serde(rename = "$value", default)]
+    content: Vec<XmlClassContent>,
+}
+
+#[derive(serde::Deserialize)]
+enum XmlClassContent {
+    #[serde(rename = "extends")]
+    Extends(XmlMember),
+    #[serde(rename = "implements")]
+    Implements(XmlMember),
+    #[serde(rename = "method")]
+    Method(XmlMember),
+    #[serde(rename = "field")]
+    Field(XmlMember),
+}
+
+#[derive(serde::Deserialize)]
+struct XmlMember {
+    name: String,
+    since: Option<String>,
+    deprecated: Option<String>,
+    removed: Option<String>,
+}
+
+impl XmlMember {
+    /// Members inherit the levels of their class if they do not declare their own
+    fn levels(&self, parent: &ApiLevels) -> ApiLevels {
+        ApiLevels {
+            since: self
+                .since
+                .as_deref()
+                .and_then(parse_level)
+                .unwrap_or(parent.since),
+            deprecated: self
+                .deprecated
+                .as_deref()
+                .and_then(parse_level)
+                .or(parent.deprecated),
+            removed: self.removed.as_deref().and_then(parse_level),
+        }
+    }
+}
+
+/// Levels are plain numbers, newer versions of the file may qualify them with an sdk extension
+/// (`0:33`)
+fn parse_level(level: &str) -> Option<u32> {
+    level.rsplit(':').next()?.trim().parse().ok()
+}
+
+fn parse_api_versions(xml: &str) -> Result<ApiDatabase, String> {
+    let api: XmlApi = serde_xml_rs::from_str(xml).map_err(|e| e.to_string())?;
+    let mut database = ApiDatabase::new(0);
+    for content in api.content {
+        let XmlApiContent::Class(xml_class) = content else {
+            continue;
+        };
+        let name = class_name_to_descriptor(&xml_class.name);
+        let levels = ApiLevels {
+            since: xml_class
+                .since
+                .as_deref()
+                .and_then(parse_level)
+                .unwrap_or(1),
+            deprecated: xml_class.deprecated.as_deref().and_then(parse_level),
+            removed: xml_class.removed.as_deref().and_then(parse_level),
+        };
+        let mut class = ApiClass::new(&name, AccessFlags::PUBLIC, levels);
+        for content in xml_class.content {
+            match content {
+                XmlClassContent::Extends(reference) => class.super_classes.push(ApiReference {
+                    name: class_name_to_descriptor(&reference.name),
+                    levels: reference.levels(&ApiLevels {
+                        deprecated: None,
+                        ..levels
+                    }),
+                }),
+                XmlClassContent::Implements(reference) => class.interfaces.push(ApiReference {
+                    name: class_name_to_descriptor(&reference.name),
+                    levels: reference.levels(&ApiLevels {
+                        deprecated: None,
+                        ..levels
+                    }),
+                }),
+                XmlClassContent::Method(member) => class.methods.push(ApiMember {
+                    levels: member.levels(&levels),
+                    signature: member.name,
+                    access_flags: AccessFlags::PUBLIC,
+                }),
+                XmlClassContent::Field(member) => class.fields.push(ApiMember {
+                    levels: member.levels(&levels),
+                    signature: member.name,
+                    access_flags: AccessFlags::PUBLIC,
+                }),
+            }
+        }
+        for level in [Some(levels.since), levels.deprecated, levels.removed]
+            .iter()
+            .flatten()
+        {
+            database.api_level = database.api_level.max(*level);
+        }
+        database.insert(class);
+    }
+    Ok(database)
+}
+
+fn invalid_data(message: String) -> std::io::Error {
+    std::io::Error::new(std::io::ErrorKind::InvalidData, message)
+}
+
+fn to_io_error(e: bincode::Error) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::archive::tests::{stored, zip};
+
+    use super::*;
+
+    const API_VERSIONS: &str = r#"<?xml version="1.0" encoding="utf-8"?>
+<api version="3">
+    <sdk id="30" shortname="R-ext" name="R Extensions" reference="android/os/Build$VERSION_CODES$R"/>
+    <class name="java/lang/Object" since="1">
+        <method name="&lt;init&gt;()V"/>
+        <method name="toString()Ljava/lang/String;"/>
+    </class>
+    <class name="java/lang/Runnable" since="1">
+        <extends name="java/lang/Object"/>
+        <method name="run()V"/>
+    </class>
+    <class name="android/webkit/WebView" since="1">
+        <extends name="android/widget/AbsoluteLayout" removed="30"/>
+        <extends name="android/view/View" since="30"/>
+        <implements name="java/lang/Runnable" since="21"/>
+        <method name="loadUrl(Ljava/lang/String;)V"/>
+        <method name="savePassword(Ljava/lang/String;)V" deprecated="18" removed="30"/>
+        <method name="zoomBy(F)V" since="0:21"/>
+        <field name="SCHEME_TEL" deprecated="33"/>
+    </class>
+</api>
+"#;
+
+    /// The same API as `API_VERSIONS` and `android_jar` in the format of the bundled database
+    const EXPECTED: &str = "
+class Ljava/lang/Object; 1
+    method <init>()V
+    method toString()Ljava/lang/String;
+    method hashCode()I 34
+interface Ljava/lang/Runnable; 1 Ljava/lang/Object;
+    method run()V
+class Landroid/webkit/WebView; 1 Landroid/widget/AbsoluteLayout;
+    implements Ljava/lang/Runnable; 21
+    method loadUrl(Ljava/lang/String;)V
+    method savePassword(Ljava/lang/String;)V 1 deprecated=18 removed=30
+    method zoomBy(F)V 21
+    field SCHEME_TEL:Ljava/lang/String; 1 deprecated=33
+class Landroid/webkit/WebView; 30 Landroid/view/View;
+class Landroid/webkit/CookieManager; 34 Ljava/lang/Object;
+    method getInstance()Landroid/webkit/CookieManager; 34
+";
+
+    const ACC_PUBLIC: u16 = 0x0001;
+    const ACC_PUBLIC_SUPER: u16 = 0x0021;
+    const ACC_PUBLIC_INTERFACE: u16 = 0x0601;
+
+    /// Add a `CONSTANT_Utf8` to the pool, returning its index
+    fn utf8(pool: &mut Vec<Vec<u8>>, content: &str) -> u16 {
+        let mut entry = vec![1];
+        entry.extend((content.len() as u16).to_be_bytes());
+        entry.extend(content.as_bytes());
+        pool.push(entry);
+        pool.len() as u16
+    }
+
+    /// Add a `CONSTANT_Class` to the pool, returning its index
+    fn class(pool: &mut Vec<Vec<u8>>, name: &str) -> u16 {
+        let name_idx = utf8(pool, name);
+        let mut entry = vec![7];
+        entry.extend(name_idx.to_be_bytes());
+        pool.push(entry);
+        pool.len() as u16
+    }
+
+    /// Public members without attributes, given as name and descriptor
+    fn members(pool: &mut Vec<Vec<u8>>, members: &[(&str, &str)]) -> Vec<u8> {
+        let mut data = (members.len() as u16).to_be_bytes().to_vec();
+        for (name, descriptor) in members {
+            data.extend(ACC_PUBLIC.to_be_bytes());
+            data.extend(utf8(pool, name).to_be_bytes());
+            data.extend(utf8(pool, descriptor).to_be_bytes());
+            data.extend(0u16.to_be_bytes());
+        }
+        data
+    }
+
+    fn class_file(
+        access_flags: u16,
+        name: &str,
+        super_name: Option<&str>,
+        interfaces: &[&str],
+        methods: &[(&str, &str)],
+        fields: &[(&str, &str)],
+    ) -> Vec<u8> {
+        let mut pool = vec![];
+        let this_class = class(&mut pool, name);
+        let super_class = super_name.map_or(0, |super_name| class(&mut pool, super_name));
+        let interfaces: Vec<u16> = interfaces.iter().map(|i| class(&mut pool, i)).collect();
+        let fields = members(&mut pool, fields);
+        let methods = members(&mut pool, methods);
+
+        let mut data = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52];
+        data.extend((pool.len() as u16 + 1).to_be_bytes());
+        data.extend(pool.concat());
+        for value in [
+            access_flags,
+            this_class,
+            super_class,
+            interfaces.len() as u16,
+        ] {
+            data.extend(value.to_be_bytes());
+        }
+        data.extend(interfaces.iter().flat_map(|i| i.to_be_bytes()));
+        data.extend(fields);
+        data.extend(methods);
+        // no attributes
+        data.extend(0u16.to_be_bytes());
+        data
+    }
+
+    fn android_jar() -> Vec<u8> {
+        let object = class_file(
+            ACC_PUBLIC_SUPER,
+            "java/lang/Object",
+            None,
+            &[],
+            &[
+                ("<init>", "()V"),
+                ("toString", "()Ljava/lang/String;"),
+                ("hashCode", "()I"),
+            ],
+            &[],
+        );
+        let runnable = class_file(
+            ACC_PUBLIC_INTERFACE,
+            "java/lang/Runnable",
+            Some("java/lang/Object"),
+            &[],
+            &[("run", "()V")],
+            &[],
+        );
+        let web_view = class_file(
+            ACC_PUBLIC_SUPER,
+            "android/webkit/WebView",
+            Some("android/view/View"),
+            &["java/lang/Runnable"],
+            &[
+                ("loadUrl", "(Ljava/lang/String;)V"),
+                ("savePassword", "(Ljava/lang/String;)V"),
+                ("zoomBy", "(F)V"),
+            ],
+            &[("SCHEME_TEL", "Ljava/lang/String;")],
+        );
+        // not listed in api-versions.xml
+        let cookie_manager = class_file(
+            ACC_PUBLIC_SUPER,
+            "android/webkit/CookieManager",
+            Some("java/lang/Object"),
+            &[],
+            &[("getInstance", "()Landroid/webkit/CookieManager;")],
+            &[],
+        );
+        zip(&[
+            stored("java/lang/Object.class", &object),
+            stored("java/lang/Runnable.class", &runnable),
+            stored("android/webkit/WebView.class", &web_view),
+            stored("android/webkit/CookieManager.class", &cookie_manager),
+            stored("META-INF/MANIFEST.MF", b"Manifest-Version: 1.0\n"),
+        ])
+    }
+
+    /// The classes sorted by name. The text format only describes public members, hence their
+    /// access flags are not compared.
+    fn classes(database: &ApiDatabase) -> Vec<ApiClass> {
+        let mut classes: Vec<ApiClass> = database.classes().cloned().collect();
+        for class in classes.iter_mut() {
+            for member in class.methods.iter_mut().chain(class.fields.iter_mut()) {
+                member.access_flags = AccessFlags::PUBLIC;
+            }
+        }
+        classes.sort_by(|a, b| a.name.cmp(&b.name));
+        classes
+    }
+
+    #[test]
+    fn generated_databases_match_the_bundled_format() {
+        let generated = generate_api_database(&android_jar(), Some(API_VERSIONS), 34).unwrap();
+        let expected = ApiDatabase::parse(EXPECTED).unwrap();
+        assert_eq!(generated.api_level, expected.api_level);
+        assert_eq!(classes(&generated), classes(&expected));
+
+        let stored = from_bytes(&to_bytes(&generated).unwrap()).unwrap();
+        assert_eq!(stored.api_level, generated.api_level);
+        assert_eq!(classes(&stored), classes(&generated));
+    }
+
+    #[test]
+    fn jars_without_api_versions_use_the_jar_level() {
+        let generated = generate_api_database(&android_jar(), None, 33).unwrap();
+        assert_eq!(generated.api_level, 33);
+        let web_view = generated.get_class("Landroid/webkit/WebView;").unwrap();
+        assert_eq!(web_view.super_class(33), Some("Landroid/view/View;"));
+        assert_eq!(web_view.super_class(32), None);
+        assert!(web_view
+            .methods
+            .iter()
+            .all(|method| method.levels.since == 33));
+        assert_eq!(
+            web_view.field("SCHEME_TEL").unwrap().signature,
+            "SCHEME_TEL:Ljava/lang/String;"
+        );
+        let runnable = generated.get_class("Ljava/lang/Runnable;").unwrap();
+        assert!(runnable.is_interface());
+
+        let jar = zip(&[stored("README", b"no classes")]);
+        assert!(generate_api_database(&jar, None, 33).is_err());
+    }
+
+    #[test]
+    fn foreign_data_is_rejected() {
+        assert!(from_bytes(b"NOTCOEUS\x01\0\0\0").is_err());
+        let mut data = to_bytes(&ApiDatabase::new(1)).unwrap();
+        data[8] = 0xff;
+        assert!(from_bytes(&data).is_err());
+    }
+}
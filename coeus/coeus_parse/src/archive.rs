@@ -0,0 +1,860 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! A tolerant zip reader, which follows the behavior of Android's `libziparchive` instead of the
+//! zip specification. Entries are taken from the central directory, while the local header is only
+//! used to find the start of the data. The encryption flag is ignored, unknown compression methods
+//! are read as stored data and for duplicate names only the first entry is used. If no central
+//! directory can be found, entries are recovered by scanning for local file headers.
+//!
+//! Everything which deviates from a well formed archive is recorded as an `ArchiveAnomaly`.
+
+use std::{collections::HashSet, convert::TryInto, io::Read};
+
+use coeus_models::models::{ArchiveAnomaly, ArchiveAnomalyKind};
+use flate2::{read::DeflateDecoder, Crc, Decompress, FlushDecompress, Status};
+
+const LOCAL_HEADER_SIGNATURE: u32 = 0x0403_4b50;
+const CENTRAL_HEADER_SIGNATURE: u32 = 0x0201_4b50;
+const EOCD_SIGNATURE: u32 = 0x0605_4b50;
+const EOCD64_LOCATOR_SIGNATURE: u32 = 0x0706_4b50;
+const EOCD64_SIGNATURE: u32 = 0x0606_4b50;
+
+const LOCAL_HEADER_SIZE: usize = 30;
+const CENTRAL_HEADER_SIZE: usize = 46;
+const EOCD_SIZE: usize = 22;
+const MAX_COMMENT_SIZE: usize = 0xffff;
+
+const FLAG_ENCRYPTED: u16 = 0x0001;
+const FLAG_DATA_DESCRIPTOR: u16 = 0x0008;
+
+const METHOD_STORED: u16 = 0;
+const METHOD_DEFLATED: u16 = 8;
+
+#[derive(Debug, Clone, Copy)]
+/// Limits protecting against zip bombs
+pub struct ZipLimits {
+    /// Maximum uncompressed size of a single entry
+    pub max_entry_size: u64,
+    /// Maximum uncompressed size of all entries together
+    pub max_total_size: u64,
+    /// Maximum ratio between uncompressed and compressed size
+    pub max_ratio: u64,
+    /// Entries smaller than this are not checked for their compression ratio
+    pub ratio_threshold: u64,
+}
+
+impl Default for ZipLimits {
+    fn default() -> Self {
+        Self {
+            max_entry_size: 512 * 1024 * 1024,
+            max_total_size: 4 * 1024 * 1024 * 1024,
+            max_ratio: 1000,
+            ratio_threshold: 1024 * 1024,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ZipEntry {
+    pub name: String,
+    pub flags: u16,
+    pub method: u16,
+    pub crc: u32,
+    pub compressed_size: u64,
+    pub uncompressed_size: u64,
+    /// Offset of the local header
+    pub header_offset: u64,
+    /// Offset of the data, if it is already known (recovered entries)
+    data_offset: Option<u64>,
+}
+
+impl ZipEntry {
+    pub fn is_dir(&self) -> bool {
+        self.name.ends_with('/')
+    }
+}
+
+pub struct ZipReader<'a> {
+    data: &'a [u8],
+    archive_name: String,
+    entries: Vec<ZipEntry>,
+    anomalies: Vec<ArchiveAnomaly>,
+    limits: ZipLimits,
+    total_size: u64,
+}
+
+fn u16_at(data: &[u8], off: usize) -> Option<u16> {
+    Some(u16::from_le_bytes(
+        data.get(off..off.checked_add(2)?)?.try_into().ok()?,
+    ))
+}
+fn u32_at(data: &[u8], off: usize) -> Option<u32> {
+    Some(u32::from_le_bytes(
+        data.get(off..off.checked_add(4)?)?.try_into().ok()?,
+    ))
+}
+fn u64_at(data: &[u8], off: usize) -> Option<u64> {
+    Some(u64::from_le_bytes(
+        data.get(off..off.checked_add(8)?)?.try_into().ok()?,
+    ))
+}
+
+impl<'a> ZipReader<'a> {
+    pub fn new(data: &'a [u8], archive_name: &str) -> Self {
+        Self::with_limits(data, archive_name, ZipLimits::default())
+    }
+
+    pub fn with_limits(data: &'a [u8], archive_name: &str, limits: ZipLimits) -> Self {
+        let mut reader = Self {
+            data,
+            archive_name: archive_name.to_string(),
+            entries: vec![],
+            anomalies: vec![],
+            limits,
+            total_size: 0,
+        };
+        match reader.find_eocd() {
+            Some(eocd) => reader.read_central_directory(eocd),
+            None => reader.anomaly(None, ArchiveAnomalyKind::MissingCentralDirectory),
+        }
+        if reader.entries.is_empty() {
+            reader.recover_from_local_headers();
+        }
+        reader.remove_duplicates();
+        reader
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+    pub fn entries(&self) -> &[ZipEntry] {
+        &self.entries
+    }
+    pub fn anomalies(&self) -> &[ArchiveAnomaly] {
+        &self.anomalies
+    }
+    pub fn into_anomalies(self) -> Vec<ArchiveAnomaly> {
+        self.anomalies
+    }
+
+    fn anomaly(&mut self, entry: Option<&str>, kind: ArchiveAnomalyKind) {
+        log::debug!("{}: {:?} {}", self.archive_name, entry, kind);
+        self.anomalies.push(ArchiveAnomaly {
+            archive: self.archive_name.clone(),
+            entry: entry.map(|e| e.to_string()),
+            kind,
+        });
+    }
+
+    /// Search the end of central directory backwards, as the archive comment may contain anything
+    fn find_eocd(&self) -> Option<usize> {
+        if self.data.len() < EOCD_SIZE {
+            return None;
+        }
+        let last = self.data.len() - EOCD_SIZE;
+        let first = last.saturating_sub(MAX_COMMENT_SIZE);
+        (first..=last)
+            .rev()
+            .find(|&off| u32_at(self.data, off) == Some(EOCD_SIGNATURE))
+    }
+
+    fn read_central_directory(&mut self, eocd: usize) {
+        let data = self.data;
+        let mut declared_entries = u16_at(data, eocd + 10).unwrap_or(0) as u64;
+        let mut cd_offset = u32_at(data, eocd + 16).unwrap_or(0) as u64;
+        let mut cd_size = u32_at(data, eocd + 12).unwrap_or(0) as u64;
+
+        // zip64 archives store the real values in a separate record, pointed to by a locator
+        if eocd >= 20 && u32_at(data, eocd - 20) == Some(EOCD64_LOCATOR_SIGNATURE) {
+            // the locator may point anywhere, only offsets within the archive are followed
+            if let Some(eocd64) = u64_at(data, eocd - 12).filter(|&o| o < eocd as u64) {
+                let eocd64 = eocd64 as usize;
+                if u32_at(data, eocd64) == Some(EOCD64_SIGNATURE) {
+                    declared_entries = u64_at(data, eocd64 + 32).unwrap_or(declared_entries);
+                    cd_size = u64_at(data, eocd64 + 40).unwrap_or(cd_size);
+                    cd_offset = u64_at(data, eocd64 + 48).unwrap_or(cd_offset);
+                }
+            }
+        }
+        if cd_offset.saturating_add(cd_size) > eocd as u64 {
+            self.anomaly(None, ArchiveAnomalyKind::CorruptCentralDirectory);
+            return;
+        }
+
+        let mut off = cd_offset as usize;
+        let end = (cd_offset + cd_size) as usize;
+        while off + CENTRAL_HEADER_SIZE <= end {
+            if u32_at(data, off) != Some(CENTRAL_HEADER_SIGNATURE) {
+                self.anomaly(None, ArchiveAnomalyKind::CorruptCentralDirectory);
+                break;
+            }
+            let Some(entry) = self.read_central_header(off) else {
+                self.anomaly(None, ArchiveAnomalyKind::CorruptCentralDirectory);
+                break;
+            };
+            let name_len = u16_at(data, off + 28).unwrap_or(0) as usize;
+            let extra_len = u16_at(data, off + 30).unwrap_or(0) as usize;
+            let comment_len = u16_at(data, off + 32).unwrap_or(0) as usize;
+            off += CENTRAL_HEADER_SIZE + name_len + extra_len + comment_len;
+            self.check_local_header(&entry);
+            self.entries.push(entry);
+        }
+        if declared_entries != self.entries.len() as u64 {
+            self.anomaly(
+                None,
+                ArchiveAnomalyKind::EntryCountMismatch {
+                    declared: declared_entries,
+                    found: self.entries.len() as u64,
+                },
+            );
+        }
+    }
+
+    fn read_central_header(&mut self, off: usize) -> Option<ZipEntry> {
+        let data = self.data;
+        let flags = u16_at(data, off + 8)?;
+        let method = u16_at(data, off + 10)?;
+        let crc = u32_at(data, off + 16)?;
+        let mut compressed_size = u32_at(data, off + 20)? as u64;
+        let mut uncompressed_size = u32_at(data, off + 24)? as u64;
+        let name_len = u16_at(data, off + 28)? as usize;
+        let extra_len = u16_at(data, off + 30)? as usize;
+        let mut header_offset = u32_at(data, off + 42)? as u64;
+        let name_start = off + CENTRAL_HEADER_SIZE;
+        let name =
+            String::from_utf8_lossy(data.get(name_start..name_start + name_len)?).to_string();
+
+        // values of 0xffffffff are stored in the zip64 extra field, in this order
+        let extra = data.get(name_start + name_len..name_start + name_len + extra_len)?;
+        let mut extra_off = 0;
+        while extra_off + 4 <= extra.len() {
+            let id = u16_at(extra, extra_off)?;
+            let size = u16_at(extra, extra_off + 2)? as usize;
+            if id == 0x0001 {
+                let mut field = extra_off + 4;
+                for value in [
+                    &mut uncompressed_size,
+                    &mut compressed_size,
+                    &mut header_offset,
+                ] {
+                    if *value == u32::MAX as u64 {
+                        if let Some(v) = u64_at(extra, field) {
+                            *value = v;
+                            field += 8;
+                        }
+                    }
+                }
+            }
+            extra_off += 4 + size;
+        }
+
+        let entry = ZipEntry {
+            name,
+            flags,
+            method,
+            crc,
+            compressed_size,
+            uncompressed_size,
+            header_offset,
+            data_offset: None,
+        };
+        self.check_entry(&entry);
+        Some(entry)
+    }
+
+    fn check_entry(&mut self, entry: &ZipEntry) {
+        if entry.flags & FLAG_ENCRYPTED != 0 {
+            self.anomaly(Some(&entry.name), ArchiveAnomalyKind::EncryptionFlag);
+        }
+        if entry.method != METHOD_STORED && entry.method != METHOD_DEFLATED {
+            self.anomaly(
+                Some(&entry.name),
+                ArchiveAnomalyKind::UnsupportedCompression(entry.method),
+            );
+        }
+        let name = &entry.name;
+        if name.starts_with('/')
+            || name.split(['/', '\\']).any(|part| part == "..")
+            || name.chars().any(|c| c.is_control())
+        {
+            self.anomaly(Some(name), ArchiveAnomalyKind::SuspiciousName);
+        }
+    }
+
+    fn check_local_header(&mut self, entry: &ZipEntry) {
+        let data = self.data;
+        // the offset may come from a zip64 extra field, keep the field offsets below from overflowing
+        let off = entry.header_offset.min(data.len() as u64) as usize;
+        if u32_at(data, off) != Some(LOCAL_HEADER_SIGNATURE) {
+            self.anomaly(
+                Some(&entry.name),
+                ArchiveAnomalyKind::LocalHeaderMismatch("signature".to_string()),
+            );
+            return;
+        }
+        let mut mismatches = vec![];
+        if u16_at(data, off + 8) != Some(entry.method) {
+            mismatches.push("compression method");
+        }
+        let local_flags = u16_at(data, off + 6).unwrap_or(0);
+        if (local_flags ^ entry.flags) & FLAG_ENCRYPTED != 0 {
+            mismatches.push("encryption flag");
+        }
+        // sizes are only valid if no data descriptor follows the data
+        if local_flags & FLAG_DATA_DESCRIPTOR == 0 {
+            let compressed = u32_at(data, off + 18).unwrap_or(0);
+            let uncompressed = u32_at(data, off + 22).unwrap_or(0);
+            if compressed != u32::MAX
+                && (compressed as u64 != entry.compressed_size
+                    || uncompressed as u64 != entry.uncompressed_size)
+            {
+                mismatches.push("size");
+            }
+        }
+        let name_len = u16_at(data, off + 26).unwrap_or(0) as usize;
+        let name_start = off + LOCAL_HEADER_SIZE;
+        match data.get(name_start..name_start + name_len) {
+            Some(name) if name == entry.name.as_bytes() => {}
+            _ => mismatches.push("name"),
+        }
+        for field in mismatches {
+            self.anomaly(
+                Some(&entry.name),
+                ArchiveAnomalyKind::LocalHeaderMismatch(field.to_string()),
+            );
+        }
+    }
+
+    /// Scan the whole archive for local file headers
+    fn recover_from_local_headers(&mut self) {
+        let data = self.data;
+        let mut off = 0;
+        while off + LOCAL_HEADER_SIZE <= data.len() {
+            if u32_at(data, off) != Some(LOCAL_HEADER_SIGNATURE) {
+                off += 1;
+                continue;
+            }
+            let flags = u16_at(data, off + 6).unwrap_or(0);
+            let method = u16_at(data, off + 8).unwrap_or(0);
+            let crc = u32_at(data, off + 14).unwrap_or(0);
+            let mut compressed_size = u32_at(data, off + 18).unwrap_or(0) as u64;
+            let mut uncompressed_size = u32_at(data, off + 22).unwrap_or(0) as u64;
+            let name_len = u16_at(data, off + 26).unwrap_or(0) as usize;
+            let extra_len = u16_at(data, off + 28).unwrap_or(0) as usize;
+            let name_start = off + LOCAL_HEADER_SIZE;
+            let Some(name) = data.get(name_start..name_start + name_len) else {
+                break;
+            };
+            let data_offset = name_start + name_len + extra_len;
+
+            // with a data descriptor the sizes are unknown, so inflate the stream to find its end
+            if flags & FLAG_DATA_DESCRIPTOR != 0 && method == METHOD_DEFLATED {
+                if let Some((consumed, produced)) = data
+                    .get(data_offset..)
+                    .and_then(|d| deflate_stream_size(d, self.limits.max_entry_size))
+                {
+                    compressed_size = consumed;
+                    uncompressed_size = produced;
+                }
+            }
+            let entry = ZipEntry {
+                name: String::from_utf8_lossy(name).to_string(),
+                flags,
+                method,
+                crc,
+                compressed_size,
+                uncompressed_size,
+                header_offset: off as u64,
+                data_offset: Some(data_offset as u64),
+            };
+            self.check_entry(&entry);
+            self.entries.push(entry);
+            off = data_offset
+                .saturating_add(compressed_size as usize)
+                .max(off + 1);
+        }
+        if !self.entries.is_empty() {
+            self.anomaly(None, ArchiveAnomalyKind::RecoveredFromLocalHeaders);
+        }
+    }
+
+    /// Android rejects or ignores later entries with the same name, we keep the first one
+    fn remove_duplicates(&mut self) {
+        let mut seen = HashSet::new();
+        let mut duplicates = vec![];
+        self.entries.retain(|e| {
+            let first = seen.insert(e.name.clone());
+            if !first {
+                duplicates.push(e.name.clone());
+            }
+            first
+        });
+        for name in duplicates {
+            self.anomaly(Some(&name), ArchiveAnomalyKind::DuplicateEntry);
+        }
+    }
+
+    /// Read and decompress the entry at `index`. Returns `None` if the entry cannot be read or
+    /// exceeds one of the limits.
+    pub fn read(&mut self, index: usize) -> Option<Vec<u8>> {
+        let entry = self.entries.get(index)?.clone();
+        if self.total_size > self.limits.max_total_size {
+            return None;
+        }
+        let data_offset = match entry.data_offset {
+            Some(offset) => offset as usize,
+            None => {
+                let off = entry.header_offset.min(self.data.len() as u64) as usize;
+                let (Some(name_len), Some(extra_len)) =
+                    (u16_at(self.data, off + 26), u16_at(self.data, off + 28))
+                else {
+                    self.anomaly(Some(&entry.name), ArchiveAnomalyKind::Truncated);
+                    return None;
+                };
+                off + LOCAL_HEADER_SIZE + name_len as usize + extra_len as usize
+            }
+        };
+        let compressed = match self
+            .data
+            .get(data_offset..)
+            .and_then(|d| d.get(..entry.compressed_size as usize))
+        {
+            Some(compressed) => compressed,
+            None => {
+                self.anomaly(Some(&entry.name), ArchiveAnomalyKind::Truncated);
+                self.data.get(data_offset..).unwrap_or_default()
+            }
+        };
+
+        let limit = self.limits.max_entry_size.min(
+            (compressed.len() as u64)
+                .saturating_mul(self.limits.max_ratio)
+                .max(self.limits.ratio_threshold),
+        );
+        let content = match entry.method {
+            METHOD_DEFLATED => self.inflate(&entry, compressed, limit)?,
+            METHOD_STORED => compressed.to_vec(),
+            // Android reads any other method as stored data
+            _ => compressed.to_vec(),
+        };
+        if content.len() as u64 > limit {
+            let kind = if limit == self.limits.max_entry_size {
+                ArchiveAnomalyKind::SizeLimitExceeded(limit)
+            } else {
+                ArchiveAnomalyKind::CompressionRatioExceeded(self.limits.max_ratio)
+            };
+            self.anomaly(Some(&entry.name), kind);
+            return None;
+        }
+
+        if content.len() as u64 != entry.uncompressed_size {
+            self.anomaly(
+                Some(&entry.name),
+                ArchiveAnomalyKind::SizeMismatch {
+                    declared: entry.uncompressed_size,
+                    actual: content.len() as u64,
+                },
+            );
+        }
+        let mut crc = Crc::new();
+        crc.update(&content);
+        if crc.sum() != entry.crc {
+            self.anomaly(
+                Some(&entry.name),
+                ArchiveAnomalyKind::CrcMismatch {
+                    declared: entry.crc,
+                    actual: crc.sum(),
+                },
+            );
+        }
+
+        self.total_size += content.len() as u64;
+        if self.total_size > self.limits.max_total_size {
+            self.anomaly(
+                None,
+                ArchiveAnomalyKind::TotalSizeLimitExceeded(self.limits.max_total_size),
+            );
+            return None;
+        }
+        Some(content)
+    }
+
+    fn inflate(&mut self, entry: &ZipEntry, compressed: &[u8], limit: u64) -> Option<Vec<u8>> {
+        match inflate_limited(compressed, limit) {
+            Some((content, complete)) => {
+                if !complete && content.len() as u64 <= limit {
+                    self.anomaly(Some(&entry.name), ArchiveAnomalyKind::Truncated);
+                }
+                Some(content)
+            }
+            None => {
+                self.anomaly(Some(&entry.name), ArchiveAnomalyKind::Truncated);
+                None
+            }
+        }
+    }
+}
+
+/// Inflate at most `limit + 1` bytes, such that exceeding the limit can be detected.
+/// Returns the data and whether the stream was read completely.
+fn inflate_limited(compressed: &[u8], limit: u64) -> Option<(Vec<u8>, bool)> {
+    let mut decoder = DeflateDecoder::new(compressed).take(limit.saturating_add(1));
+    let mut content = vec![];
+    match decoder.read_to_end(&mut content) {
+        Ok(_) => {
+            let complete = content.len() as u64 <= limit;
+            Some((content, complete))
+        }
+        // keep whatever we could decompress from a broken stream
+        Err(_) if !content.is_empty() => Some((content, false)),
+        Err(_) => None,
+    }
+}
+
+/// Returns the number of compressed bytes of a deflate stream and its uncompressed size
+fn deflate_stream_size(data: &[u8], limit: u64) -> Option<(u64, u64)> {
+    let mut decompress = Decompress::new(false);
+    let mut buffer = vec![0u8; 64 * 1024];
+    loop {
+        let (total_in, total_out) = (decompress.total_in(), decompress.total_out());
+        let status = decompress
+            .decompress(
+                data.get(total_in as usize..)?,
+                &mut buffer,
+                FlushDecompress::None,
+            )
+            .ok()?;
+        if status == Status::StreamEnd {
+            return Some((decompress.total_in(), decompress.total_out()));
+        }
+        let no_progress = decompress.total_in() == total_in && decompress.total_out() == total_out;
+        if no_progress || decompress.total_out() > limit {
+            return None;
+        }
+    }
+}
+
+#[cfg(test)]
+pub(crate) mod tests {
+    use std::io::Write;
+
+    use flate2::{write::DeflateEncoder, Compression};
+
+    use super::*;
+
+    #[derive(Clone)]
+    pub(crate) struct TestEntry {
+        name: String,
+        data: Vec<u8>,
+        method: u16,
+        /// Overrides of the central directory values
+        compressed_size: Option<u32>,
+        uncompressed_size: Option<u32>,
+        header_offset: Option<u32>,
+        central_extra: Vec<u8>,
+    }
+
+    pub(crate) fn stored(name: &str, data: &[u8]) -> TestEntry {
+        TestEntry {
+            name: name.to_string(),
+            data: data.to_vec(),
+            method: METHOD_STORED,
+            compressed_size: None,
+            uncompressed_size: None,
+            header_offset: None,
+            central_extra: vec![],
+        }
+    }
+
+    fn deflated(name: &str, data: &[u8]) -> TestEntry {
+        TestEntry {
+            method: METHOD_DEFLATED,
+            ..stored(name, data)
+        }
+    }
+
+    pub(crate) fn zip(entries: &[TestEntry]) -> Vec<u8> {
+        let mut archive = vec![];
+        let mut central_directory = vec![];
+        for entry in entries {
+            let compressed = if entry.method == METHOD_DEFLATED {
+                let mut encoder = DeflateEncoder::new(vec![], Compression::best());
+                encoder.write_all(&entry.data).unwrap();
+                encoder.finish().unwrap()
+            } else {
+                entry.data.clone()
+            };
+            let mut crc = Crc::new();
+            crc.update(&entry.data);
+            let header_offset = archive.len() as u32;
+            let sizes = [compressed.len() as u32, entry.data.len() as u32];
+
+            archive.extend_from_slice(&LOCAL_HEADER_SIGNATURE.to_le_bytes());
+            archive.extend_from_slice(&[20, 0, 0, 0]);
+            archive.extend_from_slice(&entry.method.to_le_bytes());
+            archive.extend_from_slice(&[0; 4]);
+            archive.extend_from_slice(&crc.sum().to_le_bytes());
+            for value in sizes {
+                archive.extend_from_slice(&value.to_le_bytes());
+            }
+            archive.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            archive.extend_from_slice(&0u16.to_le_bytes());
+            archive.extend_from_slice(entry.name.as_bytes());
+            archive.extend_from_slice(&compressed);
+
+            central_directory.extend_from_slice(&CENTRAL_HEADER_SIGNATURE.to_le_bytes());
+            central_directory.extend_from_slice(&[20, 0, 20, 0, 0, 0]);
+            central_directory.extend_from_slice(&entry.method.to_le_bytes());
+            central_directory.extend_from_slice(&[0; 4]);
+            central_directory.extend_from_slice(&crc.sum().to_le_bytes());
+            for value in [
+                entry.compressed_size.unwrap_or(sizes[0]),
+                entry.uncompressed_size.unwrap_or(sizes[1]),
+            ] {
+                central_directory.extend_from_slice(&value.to_le_bytes());
+            }
+            central_directory.extend_from_slice(&(entry.name.len() as u16).to_le_bytes());
+            central_directory.extend_from_slice(&(entry.central_extra.len() as u16).to_le_bytes());
+            // comment length, disk number and attributes
+            central_directory.extend_from_slice(&[0; 10]);
+            central_directory
+                .extend_from_slice(&entry.header_offset.unwrap_or(header_offset).to_le_bytes());
+            central_directory.extend_from_slice(entry.name.as_bytes());
+            central_directory.extend_from_slice(&entry.central_extra);
+        }
+        let cd_offset = archive.len() as u32;
+        archive.extend_from_slice(&central_directory);
+        archive.extend_from_slice(&EOCD_SIGNATURE.to_le_bytes());
+        archive.extend_from_slice(&[0; 4]);
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(entries.len() as u16).to_le_bytes());
+        archive.extend_from_slice(&(central_directory.len() as u32).to_le_bytes());
+        archive.extend_from_slice(&cd_offset.to_le_bytes());
+        archive.extend_from_slice(&0u16.to_le_bytes());
+        archive
+    }
+
+    fn kinds(reader: &ZipReader) -> Vec<ArchiveAnomalyKind> {
+        reader.anomalies().iter().map(|a| a.kind.clone()).collect()
+    }
+
+    fn limits(max_entry_size: u64, max_total_size: u64, max_ratio: u64) -> ZipLimits {
+        ZipLimits {
+            max_entry_size,
+            max_total_size,
+            max_ratio,
+            ratio_threshold: 0,
+        }
+    }
+
+    #[test]
+    fn reads_well_formed_archives() {
+        let data = zip(&[
+            stored("a.txt", b"stored content"),
+            deflated("dir/b.txt", &[b'b'; 1000]),
+        ]);
+        let mut reader = ZipReader::new(&data, "test.zip");
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.entries()[1].name, "dir/b.txt");
+        assert_eq!(reader.read(0).unwrap(), b"stored content");
+        assert_eq!(reader.read(1).unwrap(), vec![b'b'; 1000]);
+        assert!(reader.anomalies().is_empty());
+    }
+
+    #[test]
+    fn default_limits() {
+        let limits = ZipLimits::default();
+        assert_eq!(limits.max_entry_size, 512 * 1024 * 1024);
+        assert_eq!(limits.max_total_size, 4 * 1024 * 1024 * 1024);
+        assert_eq!(limits.max_ratio, 1000);
+    }
+
+    #[test]
+    fn recovers_entries_without_end_of_central_directory() {
+        let data = zip(&[stored("a.txt", b"first"), deflated("b.txt", b"second")]);
+        // cut in the middle of the end of central directory record
+        let truncated = &data[..data.len() - 10];
+        let mut reader = ZipReader::new(truncated, "test.zip");
+        assert_eq!(
+            kinds(&reader),
+            [
+                ArchiveAnomalyKind::MissingCentralDirectory,
+                ArchiveAnomalyKind::RecoveredFromLocalHeaders
+            ]
+        );
+        assert_eq!(reader.len(), 2);
+        assert_eq!(reader.read(0).unwrap(), b"first");
+        assert_eq!(reader.read(1).unwrap(), b"second");
+
+        // nothing to recover from
+        let reader = ZipReader::new(&data[..10], "test.zip");
+        assert!(reader.is_empty());
+        assert_eq!(
+            kinds(&reader),
+            [ArchiveAnomalyKind::MissingCentralDirectory]
+        );
+    }
+
+    #[test]
+    fn ignores_zip64_locator_pointing_out_of_bounds() {
+        let data = zip(&[stored("a.txt", b"content")]);
+        let eocd = data.len() - EOCD_SIZE;
+        for target in [u64::MAX, eocd as u64, 0x10_0000] {
+            let mut patched = data[..eocd].to_vec();
+            patched.extend_from_slice(&EOCD64_LOCATOR_SIGNATURE.to_le_bytes());
+            patched.extend_from_slice(&0u32.to_le_bytes());
+            patched.extend_from_slice(&target.to_le_bytes());
+            patched.extend_from_slice(&1u32.to_le_bytes());
+            patched.extend_from_slice(&data[eocd..]);
+
+            let mut reader = ZipReader::new(&patched, "test.zip");
+            assert_eq!(reader.len(), 1);
+            assert_eq!(reader.read(0).unwrap(), b"content");
+            assert!(reader.anomalies().is_empty());
+        }
+    }
+
+    #[test]
+    fn central_directory_past_end_of_file() {
+        let mut data = zip(&[stored("a.txt", b"content")]);
+        let eocd = data.len() - EOCD_SIZE;
+        data[eocd + 16..eocd + 20].copy_from_slice(&0xffff_fff0u32.to_le_bytes());
+        let mut reader = ZipReader::new(&data, "test.zip");
+        assert_eq!(
+            kinds(&reader),
+            [
+                ArchiveAnomalyKind::CorruptCentralDirectory,
+                ArchiveAnomalyKind::RecoveredFromLocalHeaders
+            ]
+        );
+        assert_eq!(reader.read(0).unwrap(), b"content");
+    }
+
+    #[test]
+    fn local_header_offset_past_end_of_file() {
+        let mut past_eof = stored("a.txt", b"content");
+        past_eof.header_offset = Some(0xffff_fff0);
+        // the real offset is stored in the zip64 extra field
+        let mut zip64 = stored("b.txt", b"content");
+        zip64.header_offset = Some(u32::MAX);
+        zip64.central_extra = [
+            &1u16.to_le_bytes()[..],
+            &8u16.to_le_bytes(),
+            &u64::MAX.to_le_bytes(),
+        ]
+        .concat();
+        let data = zip(&[past_eof, zip64]);
+        let mut reader = ZipReader::new(&data, "test.zip");
+        assert_eq!(reader.entries()[1].header_offset, u64::MAX);
+        assert_eq!(reader.read(0), None);
+        assert_eq!(reader.read(1), None);
+        let signature = ArchiveAnomalyKind::LocalHeaderMismatch("signature".to_string());
+        assert_eq!(
+            kinds(&reader),
+            [
+                signature.clone(),
+                signature,
+                ArchiveAnomalyKind::Truncated,
+                ArchiveAnomalyKind::Truncated
+            ]
+        );
+    }
+
+    #[test]
+    fn compressed_size_past_end_of_file() {
+        let mut entry = stored("a.txt", b"content");
+        entry.compressed_size = Some(0x7fff_ffff);
+        let data = zip(&[entry]);
+        let mut reader = ZipReader::new(&data, "test.zip");
+        let content = reader.read(0).unwrap();
+        // everything up to the end of the archive is read
+        assert!(content.starts_with(b"content"));
+        let kinds = kinds(&reader);
+        assert!(kinds.contains(&ArchiveAnomalyKind::Truncated));
+        assert!(kinds.contains(&ArchiveAnomalyKind::LocalHeaderMismatch("size".to_string())));
+    }
+
+    #[test]
+    fn skips_entries_exceeding_the_entry_size() {
+        let data = zip(&[stored("big", &[0; 200]), stored("small", &[0; 100])]);
+        let mut reader = ZipReader::with_limits(&data, "test.zip", limits(150, u64::MAX, 1000));
+        assert_eq!(reader.read(0), None);
+        assert_eq!(reader.read(1).unwrap().len(), 100);
+        assert_eq!(kinds(&reader), [ArchiveAnomalyKind::SizeLimitExceeded(150)]);
+    }
+
+    #[test]
+    fn skips_entries_exceeding_the_compression_ratio() {
+        let data = zip(&[deflated("bomb", &[0; 100_000]), deflated("text", b"abcdef")]);
+        let mut reader = ZipReader::with_limits(&data, "test.zip", limits(u64::MAX, u64::MAX, 10));
+        assert_eq!(reader.read(0), None);
+        assert_eq!(reader.read(1).unwrap(), b"abcdef");
+        assert_eq!(
+            kinds(&reader),
+            [ArchiveAnomalyKind::CompressionRatioExceeded(10)]
+        );
+
+        // small entries are not checked for their ratio
+        let mut reader = ZipReader::with_limits(
+            &data,
+            "test.zip",
+            ZipLimits {
+                ratio_threshold: 1024 * 1024,
+                ..limits(u64::MAX, u64::MAX, 10)
+            },
+        );
+        assert_eq!(reader.read(0).unwrap().len(), 100_000);
+    }
+
+    #[test]
+    fn stops_reading_after_the_total_size() {
+        let data = zip(&[
+            stored("a", &[0; 100]),
+            stored("b", &[0; 100]),
+            stored("c", &[0; 10]),
+        ]);
+        let mut reader = ZipReader::with_limits(&data, "test.zip", limits(u64::MAX, 150, 1000));
+        assert!(reader.read(0).is_some());
+        assert_eq!(reader.read(1), None);
+        assert_eq!(reader.read(2), None);
+        assert_eq!(
+            kinds(&reader),
+            [ArchiveAnomalyKind::TotalSizeLimitExceeded(150)]
+        );
+    }
+
+    #[test]
+    fn reports_sizes_disagreeing_with_the_content() {
+        let mut stored_entry = stored("stored", b"0123456789");
+        stored_entry.uncompressed_size = Some(5);
+        let mut deflated_entry = deflated("deflated", &[b'x'; 64]);
+        deflated_entry.uncompressed_size = Some(1_000_000);
+        let data = zip(&[stored_entry, deflated_entry]);
+        let mut reader = ZipReader::new(&data, "test.zip");
+        assert_eq!(reader.read(0).unwrap(), b"0123456789");
+        assert_eq!(reader.read(1).unwrap(), vec![b'x'; 64]);
+        let size = ArchiveAnomalyKind::LocalHeaderMismatch("size".to_string());
+        assert_eq!(
+            kinds(&reader),
+            [
+                size.clone(),
+                size,
+                ArchiveAnomalyKind::SizeMismatch {
+                    declared: 5,
+                    actual: 10
+                },
+                ArchiveAnomalyKind::SizeMismatch {
+                    declared: 1_000_000,
+                    actual: 64
+                },
+            ]
+        );
+    }
+}
@@ -0,0 +1,524 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! This module provides an on-disk cache for parsed `Files` and built `Supergraph`s, so that
+//! an archive only needs to be parsed once. Entries are keyed by the SHA-256 of the archive and
+//! stored as compressed bincode of the complete serialization format.
+//!
+//! Every entry starts with a header containing a hash of the cached model sources, computed by
+//! the build script, and the Coeus version. Entries written by a different build are treated as a
+//! cache miss and overwritten on the next store. Call graphs and the superclass and interface tables only hold derived data, they are
+//! rebuilt after loading.
+use std::{
+    collections::HashMap,
+    fs::File,
+    io::{BufReader, BufWriter, Read, Write},
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use coeus_models::models::{
+    with_complete_serialization, Class, DexFile, DexIndex, Files, Pool,
+};
+use flate2::{read::DeflateDecoder, write::DeflateEncoder, Compression};
+use petgraph::Graph;
+use sha2::{Digest, Sha256};
+
+use crate::dex::{
+    build_class_tables,
+    graph::{analysis::r#static::models::StaticRegister, build_graph, InfoNode, Supergraph},
+};
+
+const CACHE_MAGIC: &[u8; 8] = b"COEUSCAC";
+// `CACHED_MODELS_HASH`, which changes with every change to the cached models
+include!(concat!(env!("OUT_DIR"), "/cache_models.rs"));
+
+/// A directory holding cached analysis results
+#[derive(Debug, Clone)]
+pub struct AnalysisCache {
+    directory: PathBuf,
+}
+
+/// Header written in front of every cache entry
+#[derive(serde::Serialize, serde::Deserialize, PartialEq)]
+struct CacheHeader {
+    models_hash: String,
+    coeus_version: String,
+    key: String,
+}
+
+/// Class nodes are stored as a reference to the class in the cached `Files`
+#[derive(serde::Serialize, serde::Deserialize)]
+enum CachedNode {
+    Class(String, u32),
+    Node(Box<InfoNode>),
+}
+
+#[derive(serde::Serialize, serde::Deserialize)]
+struct CachedSupergraph {
+    class_node_mapping: HashMap<String, petgraph::graph::NodeIndex>,
+    super_graph: Graph<CachedNode, i32>,
+}
+
+impl AnalysisCache {
+    pub fn new<P: Into<PathBuf>>(directory: P) -> Self {
+        Self {
+            directory: directory.into(),
+        }
+    }
+
+    /// Compute the cache key (the hex encoded SHA-256) of the file at `path`
+    pub fn key_for_file<P: AsRef<Path>>(path: P) -> std::io::Result<String> {
+        let mut f = File::open(path)?;
+        let mut hasher = Sha256::new();
+        let mut buffer = vec![0; 1 << 16];
+        loop {
+            let read = f.read(&mut buffer)?;
+            if read == 0 {
+                break;
+            }
+            hasher.update(&buffer[..read]);
+        }
+        Ok(to_hex(&hasher.finalize()))
+    }
+
+    /// Load the files cached for `key`. Call graphs are rebuilt if `build_graph` is set.
+    pub fn load_files(&self, key: &str, build_graph: bool, max_depth: i64) -> Option<Files> {
+        let mut files: Files = self.read_entry(&self.files_path(key, max_depth), key)?;
+        restore_files(&mut files, build_graph);
+        Some(files)
+    }
+
+    pub fn store_files(&self, key: &str, max_depth: i64, files: &Files) -> std::io::Result<()> {
+        self.write_entry(&self.files_path(key, max_depth), key, files)
+    }
+
+    /// Load the supergraph cached for `key` and the given set of excluded classes. The class
+    /// nodes are linked to the classes in `files`.
+    pub fn load_supergraph(
+        &self,
+        key: &str,
+        multi_dex_index: usize,
+        exclude_classes: &[&str],
+        files: &Files,
+    ) -> Option<Supergraph> {
+        let path = self.supergraph_path(key, multi_dex_index, exclude_classes);
+        let cached: CachedSupergraph = self.read_entry(&path, key)?;
+        let classes: HashMap<(&str, u32), &Arc<Class>> = files
+            .multi_dex
+            .iter()
+            .flat_map(|md| std::iter::once(&md.primary).chain(md.secondary.iter()))
+            .flat_map(|dex| dex.classes.iter())
+            .map(|class| ((class.dex_identifier.as_str(), class.class_idx), class))
+            .collect();
+        let mut complete = true;
+        let super_graph = cached.super_graph.map(
+            |_, node| match node {
+                CachedNode::Class(dex_identifier, class_idx) => {
+                    match classes.get(&(dex_identifier.as_str(), *class_idx)) {
+                        Some(class) => InfoNode::ClassNode(Arc::clone(class)),
+                        None => {
+                            complete = false;
+                            InfoNode::TypeNode(String::new())
+                        }
+                    }
+                }
+                CachedNode::Node(node) => match node.as_ref() {
+                    InfoNode::StaticArgumentNode(register, idx) => {
+                        let mut register = register.clone();
+                        relink_dex_files(&mut register, &mut |dex| {
+                            files
+                                .dex_file_from_identifier(&dex.identifier)
+                                .unwrap_or_else(|| {
+                                    complete = false;
+                                    dex.clone()
+                                })
+                        });
+                        InfoNode::StaticArgumentNode(register, *idx)
+                    }
+                    node => node.clone(),
+                },
+            },
+            |_, edge| *edge,
+        );
+        if !complete {
+            log::warn!("Cached supergraph does not match the files, ignoring it");
+            return None;
+        }
+        Some(Supergraph {
+            class_node_mapping: cached.class_node_mapping,
+            super_graph,
+        })
+    }
+
+    pub fn store_supergraph(
+        &self,
+        key: &str,
+        multi_dex_index: usize,
+        exclude_classes: &[&str],
+        supergraph: &Supergraph,
+    ) -> std::io::Result<()> {
+        let super_graph = supergraph.super_graph.map(
+            |_, node| match node {
+                InfoNode::ClassNode(class) => {
+                    CachedNode::Class(class.dex_identifier.clone(), class.class_idx)
+                }
+                InfoNode::StaticArgumentNode(register, idx) => {
+                    let mut register = register.clone();
+                    relink_dex_files(&mut register, &mut |dex| Arc::new(dex_file_stub(dex)));
+                    CachedNode::Node(Box::new(InfoNode::StaticArgumentNode(register, *idx)))
+                }
+                node => CachedNode::Node(Box::new(node.clone())),
+            },
+            |_, edge| *edge,
+        );
+        let cached = CachedSupergraph {
+            class_node_mapping: supergraph.class_node_mapping.clone(),
+            super_graph,
+        };
+        let path = self.supergraph_path(key, multi_dex_index, exclude_classes);
+        self.write_entry(&path, key, &cached)
+    }
+
+    fn files_path(&self, key: &str, max_depth: i64) -> PathBuf {
+        self.directory.join(format!("{}-d{}.files", key, max_depth))
+    }
+
+    fn supergraph_path(
+        &self,
+        key: &str,
+        multi_dex_index: usize,
+        exclude_classes: &[&str],
+    ) -> PathBuf {
+        let mut exclude_classes = exclude_classes.to_vec();
+        exclude_classes.sort_unstable();
+        exclude_classes.dedup();
+        let mut hasher = Sha256::new();
+        for class in exclude_classes {
+            hasher.update(class.as_bytes());
+            hasher.update([0]);
+        }
+        let exclude_hash = to_hex(&hasher.finalize()[..8]);
+        self.directory.join(format!(
+            "{}-{}-{}.supergraph",
+            key, multi_dex_index, exclude_hash
+        ))
+    }
+
+    fn read_entry<T: serde::de::DeserializeOwned>(&self, path: &Path, key: &str) -> Option<T> {
+        let f = File::open(path).ok()?;
+        let mut reader = BufReader::new(f);
+        let mut magic = [0; 8];
+        reader.read_exact(&mut magic).ok()?;
+        if &magic != CACHE_MAGIC {
+            log::warn!("{} is not a cache file", path.display());
+            return None;
+        }
+        let header: CacheHeader = bincode::deserialize_from(&mut reader).ok()?;
+        if header != current_header(key) {
+            log::info!(
+                "Ignoring cache entry written by Coeus {} (models {})",
+                header.coeus_version,
+                header.models_hash
+            );
+            return None;
+        }
+        bincode::deserialize_from(DeflateDecoder::new(reader))
+            .map_err(|e| log::warn!("Could not read cache entry {}: {}", path.display(), e))
+            .ok()
+    }
+
+    fn write_entry<T: serde::Serialize>(
+        &self,
+        path: &Path,
+        key: &str,
+        value: &T,
+    ) -> std::io::Result<()> {
+        std::fs::create_dir_all(&self.directory)?;
+        // write to a temporary file first, so that concurrent readers never see partial entries
+        let tmp_path = path.with_extension("tmp");
+        let written = (|| {
+            let mut writer = BufWriter::new(File::create(&tmp_path)?);
+            writer.write_all(CACHE_MAGIC)?;
+            bincode::serialize_into(&mut writer, &current_header(key)).map_err(to_io_error)?;
+            let mut encoder = DeflateEncoder::new(writer, Compression::fast());
+            with_complete_serialization(|| bincode::serialize_into(&mut encoder, value))
+                .map_err(to_io_error)?;
+            encoder.finish()?.flush()
+        })();
+        match written {
+            Ok(()) => std::fs::rename(tmp_path, path),
+            Err(e) => {
+                let _ = std::fs::remove_file(tmp_path);
+                Err(e)
+            }
+        }
+    }
+}
+
+/// Parse the file at `path` or reuse the result from `cache`. Freshly parsed files are stored in
+/// the cache. Returns the files together with their cache key, which is needed to cache further
+/// results like supergraphs.
+pub fn load_file_cached(
+    path: &str,
+    build_graph: bool,
+    max_depth: i64,
+    cache: &AnalysisCache,
+) -> Result<(Files, String), std::io::Error> {
+    let key = AnalysisCache::key_for_file(path)?;
+    if let Some(files) = cache.load_files(&key, build_graph, max_depth) {
+        log::debug!("loaded {} from cache", path);
+        return Ok((files, key));
+    }
+    let files = crate::extraction::load_file(path, build_graph, max_depth)?;
+    if let Err(e) = cache.store_files(&key, max_depth, &files) {
+        log::warn!("Could not store {} in the cache: {}", path, e);
+    }
+    Ok((files, key))
+}
+
+/// Transformations refer to the dex file containing the called method. Only the identifier is
+/// needed to link it again after loading.
+fn dex_file_stub(dex: &DexFile) -> DexFile {
+    DexFile {
+        identifier: dex.identifier.clone(),
+        file_name: dex.file_name.clone(),
+        header: dex.header.clone(),
+        strings: Pool::default(),
+        types: Pool::default(),
+        methods: Pool::default(),
+        protos: Pool::default(),
+        fields: Pool::default(),
+        classes: Pool::default(),
+        call_sites: vec![],
+        interface_table: HashMap::new(),
+        superclass_table: HashMap::new(),
+        index: DexIndex::default(),
+    }
+}
+
+fn relink_dex_files(
+    register: &mut StaticRegister,
+    relink: &mut impl FnMut(&Arc<DexFile>) -> Arc<DexFile>,
+) {
+    let transformations = register.transformation.iter_mut().chain(
+        register
+            .last_branch
+            .iter_mut()
+            .flat_map(|(_, t)| t.iter_mut()),
+    );
+    for transformation in transformations {
+        transformation.dex_file = relink(&transformation.dex_file);
+        for input in &mut transformation.input_register {
+            relink_dex_files(input, relink);
+        }
+    }
+}
+
+fn current_header(key: &str) -> CacheHeader {
+    CacheHeader {
+        models_hash: CACHED_MODELS_HASH.to_string(),
+        coeus_version: env!("CARGO_PKG_VERSION").to_string(),
+        key: key.to_string(),
+    }
+}
+
+fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+fn to_io_error(e: bincode::Error) -> std::io::Error {
+    std::io::Error::other(e)
+}
+
+/// Rebuild the data not contained in the cache. The deserialized files are not shared yet, so
+/// everything can be modified in place.
+fn restore_files(files: &mut Files, build_graph: bool) {
+    for multi_dex in &mut files.multi_dex {
+        for dex in std::iter::once(&mut multi_dex.primary).chain(multi_dex.secondary.iter_mut()) {
+            if let Some(dex) = Arc::get_mut(dex) {
+                if build_graph {
+                    restore_call_graphs(dex);
+                }
+                build_class_tables(dex);
+            }
+        }
+    }
+}
+
+fn restore_call_graphs(dex: &mut DexFile) {
+    let DexFile {
+        header,
+        strings,
+        types,
+        methods,
+        classes,
+        ..
+    } = dex;
+    for class in classes.iter_mut() {
+        let Some(class) = Arc::get_mut(class) else {
+            continue;
+        };
+        let Some(class_data) = &class.class_data else {
+            continue;
+        };
+        let encoded_methods: HashMap<u32, _> = class_data
+            .direct_methods
+            .iter()
+            .chain(class_data.virtual_methods.iter())
+            .map(|m| (m.method_idx, m))
+            .collect();
+        for method_data in class.codes.iter_mut() {
+            let Some(method_data) = Arc::get_mut(method_data) else {
+                continue;
+            };
+            let (Some(code), Some(encoded_method)) = (
+                &method_data.code,
+                encoded_methods.get(&method_data.method_idx),
+            ) else {
+                continue;
+            };
+            method_data.call_graph =
+                build_graph(code, header, encoded_method, strings, types, methods);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use coeus_models::models::{AndroidManifest, MultiDexFile};
+
+    use super::*;
+
+    /// A public class file without members, extending `super_name` and implementing `interface`
+    fn class_file(name: &str, super_name: &str, interface: &str) -> Vec<u8> {
+        let mut data = vec![0xca, 0xfe, 0xba, 0xbe, 0, 0, 0, 52];
+        data.extend(7u16.to_be_bytes());
+        for (i, class_name) in [name, super_name, interface].iter().enumerate() {
+            // CONSTANT_Utf8 followed by the CONSTANT_Class referring to it
+            data.push(1);
+            data.extend((class_name.len() as u16).to_be_bytes());
+            data.extend(class_name.as_bytes());
+            data.push(7);
+            data.extend((2 * i as u16 + 1).to_be_bytes());
+        }
+        data.extend([0x00, 0x21, 0, 2, 0, 4, 0, 1, 0, 6, 0, 0, 0, 0, 0, 0]);
+        data
+    }
+
+    fn files() -> Files {
+        let a = class_file("a/A", "java/lang/Object", "java/lang/Runnable");
+        let b = class_file("a/B", "a/A", "java/lang/Cloneable");
+        let class_files = [
+            ("a/A.class".to_string(), &a[..]),
+            ("a/B.class".to_string(), &b[..]),
+        ];
+        let dex = crate::jvm::parse_class_files("test.jar", &class_files, false).remove(0);
+        let multi_dex = MultiDexFile::new(AndroidManifest::default(), String::new(), dex, vec![]);
+        Files::new(vec![multi_dex], HashMap::new())
+    }
+
+    fn cache(name: &str) -> AnalysisCache {
+        let directory =
+            std::env::temp_dir().join(format!("coeus-cache-test-{}-{}", name, std::process::id()));
+        let _ = std::fs::remove_dir_all(&directory);
+        AnalysisCache::new(directory)
+    }
+
+    /// The superclass and interface tables by name, in a stable order
+    fn class_tables(dex: &DexFile) -> Vec<(bool, String, Vec<String>)> {
+        let mut tables: Vec<_> = [(false, &dex.superclass_table), (true, &dex.interface_table)]
+            .iter()
+            .flat_map(|(is_interface, table)| {
+                table.iter().map(move |(name, classes)| {
+                    let mut classes: Vec<_> =
+                        classes.iter().map(|c| c.class_name.clone()).collect();
+                    classes.sort();
+                    (*is_interface, name.clone(), classes)
+                })
+            })
+            .collect();
+        tables.sort();
+        tables
+    }
+
+    #[test]
+    fn stored_files_are_loaded_with_their_class_tables() {
+        let cache = cache("round-trip");
+        let files = files();
+        assert!(cache.load_files("key", false, 1).is_none());
+        cache.store_files("key", 1, &files).unwrap();
+
+        let loaded = cache.load_files("key", false, 1).unwrap();
+        let (original, loaded_dex) = (&files.multi_dex[0].primary, &loaded.multi_dex[0].primary);
+        assert_eq!(loaded_dex.identifier, original.identifier);
+        let strings = |dex: &DexFile| -> Vec<String> {
+            dex.strings
+                .iter()
+                .map(|s| s.to_str_lossy().into_owned())
+                .collect()
+        };
+        assert_eq!(strings(loaded_dex), strings(original));
+        let class_names = |dex: &DexFile| -> Vec<String> {
+            dex.classes.iter().map(|c| c.class_name.clone()).collect()
+        };
+        assert_eq!(class_names(loaded_dex), ["La/A;", "La/B;"]);
+        assert_eq!(class_tables(loaded_dex), class_tables(original));
+        assert_eq!(
+            loaded_dex.superclass_table["La/A;"][0].class_name,
+            "La/B;".to_string()
+        );
+        assert_eq!(
+            loaded_dex.interface_table["Ljava/lang/Cloneable;"][0].class_name,
+            "La/B;".to_string()
+        );
+
+        // entries are specific to the depth and the key
+        assert!(cache.load_files("key", false, 2).is_none());
+        assert!(cache.load_files("other", false, 1).is_none());
+        std::fs::remove_dir_all(&cache.directory).unwrap();
+    }
+
+    #[test]
+    fn entries_of_other_builds_are_ignored() {
+        let cache = cache("other-build");
+        cache.store_files("key", 1, &files()).unwrap();
+        let path = cache.files_path("key", 1);
+        let entry = std::fs::read(&path).unwrap();
+        let header_size = bincode::serialized_size(&current_header("key")).unwrap() as usize;
+        let content = entry[CACHE_MAGIC.len() + header_size..].to_vec();
+
+        let headers = [
+            CacheHeader {
+                models_hash: "0000000000000000".to_string(),
+                ..current_header("key")
+            },
+            CacheHeader {
+                coeus_version: "0.0.0".to_string(),
+                ..current_header("key")
+            },
+            CacheHeader {
+                key: "other".to_string(),
+                ..current_header("key")
+            },
+        ];
+        for header in headers {
+            let mut entry = CACHE_MAGIC.to_vec();
+            bincode::serialize_into(&mut entry, &header).unwrap();
+            entry.extend(&content);
+            std::fs::write(&path, entry).unwrap();
+            assert!(cache.load_files("key", false, 1).is_none());
+        }
+
+        // the same content with the current header is read
+        let mut entry = CACHE_MAGIC.to_vec();
+        bincode::serialize_into(&mut entry, &current_header("key")).unwrap();
+        entry.extend(&content);
+        std::fs::write(&path, entry).unwrap();
+        assert!(cache.load_files("key", false, 1).is_some());
+        std::fs::remove_dir_all(&cache.directory).unwrap();
+    }
+}
@@ -106,8 +106,6 @@ pub fn parse_dex_buf(
 
     let mut ret_classes = Vec::with_capacity(classes.len());
     let vec_lock = Arc::new(Mutex::new(&mut ret_classes));
-    let v_table = Mutex::new(HashMap::new());
-    let s_table = Mutex::new(HashMap::new());
 
     iterator!(classes).for_each(|class| {
         //class is not here, but still link it (e.g. sdk stuff)
@@ -133,15 +131,8 @@ pub fn parse_dex_buf(
                 field_annotations: vec![],
             });
             if let Ok(mut ret_classes) = vec_lock.lock() {
-                ret_classes.push(the_class.clone());
+                ret_classes.push(the_class);
             };
-            if let Some(flags) = AccessFlags::from_bits(class.access_flags as u64) {
-                if flags.contains(AccessFlags::INTERFACE) {
-                    if let Ok(mut table_lock) = v_table.lock() {
-                        table_lock.insert(the_class.class_name.clone(), vec![]);
-                    }
-                }
-            }
             return;
         }
         let vec_lock = Arc::clone(&vec_lock);
@@ -425,7 +416,7 @@ pub fn parse_dex_buf(
             access_flags: AccessFlags::from_bits(class.access_flags as u64)
                 .expect("accessflags wrong"),
             super_class: class.superclass_idx,
-            class_name: class_name.clone(),
+            class_name,
             class_data,
             codes: vec![],
             static_fields,
@@ -448,6 +439,7 @@ pub fn parse_dex_buf(
                     access_flags: method.access_flags,
                     code: None,
                     call_graph: None,
+                    jvm_code: None,
                 }));
                 continue;
             }
@@ -468,6 +460,7 @@ pub fn parse_dex_buf(
                     None
                 },
                 code: Some(code),
+                jvm_code: None,
             }));
         }
         for method in &the_class.class_data.as_ref().unwrap().direct_methods {
@@ -481,6 +474,7 @@ pub fn parse_dex_buf(
                     method: new_m,
                     code: None,
                     call_graph: None,
+                    jvm_code: None,
                 }));
                 continue;
             }
@@ -501,56 +495,182 @@ pub fn parse_dex_buf(
                     None
                 },
                 code: Some(code),
+                jvm_code: None,
             }));
         }
-        let the_class = Arc::new(the_class);
+        if let Ok(mut ret_classes) = vec_lock.lock() {
+            ret_classes.push(Arc::new(the_class));
+        };
+    });
+    let call_sites = parse_call_sites(buffer.data, config.map_off, &strings);
+    let mut dex_file = DexFile {
+        identifier: format!("{:02x?}", config.signature),
+        file_name: file_name.to_string(),
+        header: config,
+        strings: strings.into(),
+        types: types.into(),
+        protos: protos.into(),
+        methods: methods.into(),
+        fields: fields.into(),
+        classes: ret_classes.into(),
+        call_sites,
+        interface_table: HashMap::new(),
+        superclass_table: HashMap::new(),
+        index: DexIndex::default(),
+    };
+    build_class_tables(&mut dex_file);
+    Some(dex_file)
+}
 
-        if let Ok(mut lock) = s_table.lock() {
-            if !lock.contains_key(&class_name) {
-                lock.insert(class_name, vec![]);
+/// Build the superclass and interface tables from the classes of `dex`. Every defined class gets
+/// an entry in the superclass table, classes without class data (e.g. linked sdk classes) only
+/// show up as interfaces.
+pub(crate) fn build_class_tables(dex: &mut DexFile) {
+    let mut superclass_table: HashMap<String, Vec<Arc<Class>>> = HashMap::new();
+    let mut interface_table: HashMap<String, Vec<Arc<Class>>> = HashMap::new();
+    for class in &dex.classes {
+        if class.class_data.is_none() {
+            if class.access_flags.contains(AccessFlags::INTERFACE) {
+                interface_table.entry(class.class_name.clone()).or_default();
             }
-            if class.superclass_idx < NO_INDEX {
-                let super_class =
-                    get_string_from_idx(types[class.superclass_idx as usize] as u16, &strings)
-                        .unwrap_or_else(|| {
-                            log::error!("Could not resolve class name");
-                            "-UNKONWN-".to_string()
-                        });
-                let entry = lock.entry(super_class).or_default();
-                entry.push(the_class.clone());
+            continue;
+        }
+        superclass_table
+            .entry(class.class_name.clone())
+            .or_default();
+        if class.super_class < NO_INDEX {
+            let super_class = dex
+                .get_type_name(class.super_class as usize)
+                .unwrap_or_else(|| {
+                    log::error!("Could not resolve class name");
+                    "-UNKNOWN-"
+                })
+                .to_string();
+            superclass_table
+                .entry(super_class)
+                .or_default()
+                .push(class.clone());
+        }
+        for &type_idx in &class.interfaces {
+            if let Some(interface) = dex.get_type_name(type_idx as usize) {
+                interface_table
+                    .entry(interface.to_string())
+                    .or_default()
+                    .push(class.clone());
             }
         }
+    }
+    dex.superclass_table = superclass_table;
+    dex.interface_table = interface_table;
+}
 
-        if let Ok(mut v_table) = v_table.lock() {
-            for &type_idx in &the_class.interfaces {
-                if let Some(iface_name) =
-                    get_string_from_idx(types[type_idx as usize] as u16, &strings)
-                {
-                    let entry = v_table.entry(iface_name).or_default();
-
-                    entry.push(the_class.clone());
-                }
-            }
+const TYPE_CALL_SITE_ID_ITEM: u16 = 0x0007;
+const TYPE_METHOD_HANDLE_ITEM: u16 = 0x0008;
+const VALUE_METHOD_TYPE: u8 = 0x15;
+const VALUE_METHOD_HANDLE: u8 = 0x16;
+const VALUE_STRING: u8 = 0x17;
+
+/// Resolve the call sites of `invoke-custom` to their bootstrap methods. Both sections are only
+/// reachable through the map list. Malformed call sites are kept without a bootstrap method, such
+/// that the indices of the remaining ones stay valid.
+fn parse_call_sites(data: &[u8], map_off: u32, strings: &[StringEntry]) -> Vec<CallSite> {
+    let read_u16 = |offset: usize| {
+        data.get(offset..offset.checked_add(2)?)
+            .map(|b| u16::from_le_bytes([b[0], b[1]]))
+    };
+    let read_u32 = |offset: usize| {
+        data.get(offset..offset.checked_add(4)?)
+            .map(|b| u32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+    };
+    let map_off = map_off as usize;
+    let Some(map_size) = read_u32(map_off) else {
+        return vec![];
+    };
+    let mut call_site_ids = None;
+    let mut method_handles = None;
+    for i in 0..map_size as usize {
+        let Some(item) = i.checked_mul(12).and_then(|o| o.checked_add(map_off + 4)) else {
+            break;
+        };
+        let (Some(item_type), Some(size), Some(offset)) =
+            (read_u16(item), read_u32(item + 4), read_u32(item + 8))
+        else {
+            break;
+        };
+        match item_type {
+            TYPE_CALL_SITE_ID_ITEM => call_site_ids = Some((offset as usize, size as usize)),
+            TYPE_METHOD_HANDLE_ITEM => method_handles = Some((offset as usize, size as usize)),
+            _ => {}
         }
-        if let Ok(mut ret_classes) = vec_lock.lock() {
-            ret_classes.push(the_class);
+    }
+    let Some((call_site_ids_off, call_site_ids_size)) = call_site_ids else {
+        return vec![];
+    };
+    let (method_handles_off, method_handles_size) = method_handles.unwrap_or_default();
+    let bootstrap_method = |handle_idx: usize| {
+        if handle_idx >= method_handles_size {
+            return None;
+        }
+        let handle = method_handles_off.checked_add(handle_idx * 8)?;
+        // handle types 0x00 to 0x03 are field accessors, 0x04 to 0x08 invoke methods
+        match read_u16(handle)? {
+            0x04..=0x08 => read_u16(handle + 4),
+            _ => None,
+        }
+    };
+    // the call site ids can not be larger than the file, the check stops huge sizes early
+    let call_site_ids_size = call_site_ids_size.min(data.len() / 4);
+    let mut call_sites = Vec::with_capacity(call_site_ids_size);
+    for i in 0..call_site_ids_size {
+        let Some(call_site_off) = call_site_ids_off.checked_add(i * 4).and_then(read_u32) else {
+            break;
         };
-    });
-    let v_table = v_table.into_inner().unwrap();
-    let s_table = s_table.into_inner().unwrap();
-    Some(DexFile {
-        identifier: format!("{:02x?}", config.signature),
-        file_name: file_name.to_string(),
-        header: config,
-        strings,
-        types,
-        protos,
-        methods,
-        fields,
-        classes: ret_classes,
-        interface_table: v_table,
-        superclass_table: s_table,
-    })
+        let Some(values) = read_call_site_values(data, call_site_off as usize) else {
+            log::warn!("Could not read call site {}", i);
+            call_sites.push(CallSite {
+                bootstrap_method_idx: None,
+                method_name: String::new(),
+                proto_idx: 0,
+            });
+            continue;
+        };
+        call_sites.push(CallSite {
+            bootstrap_method_idx: bootstrap_method(values[0] as usize),
+            method_name: get_string_from_idx(values[1] as usize, strings).unwrap_or_default(),
+            proto_idx: values[2] as u16,
+        });
+    }
+    call_sites
+}
+
+/// Read the bootstrap method handle, the method name and the method type of a call site. These
+/// are the first three values of the encoded array, further arguments are ignored.
+fn read_call_site_values(data: &[u8], offset: usize) -> Option<[u32; 3]> {
+    let mut position = offset;
+    // the uleb128 size of the array, it has at least three elements
+    loop {
+        let byte = *data.get(position)?;
+        position += 1;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+    let mut values = [0u32; 3];
+    let expected_types = [VALUE_METHOD_HANDLE, VALUE_STRING, VALUE_METHOD_TYPE];
+    for (value, expected_type) in values.iter_mut().zip(expected_types) {
+        let header = *data.get(position)?;
+        let length = (header >> 5) as usize + 1;
+        if header & 0x1f != expected_type || length > 4 {
+            return None;
+        }
+        let bytes = data.get(position + 1..position + 1 + length)?;
+        *value = bytes
+            .iter()
+            .rev()
+            .fold(0, |value, byte| (value << 8) | *byte as u32);
+        position += 1 + length;
+    }
+    Some(values)
 }
 
 fn get_string_from_idx<T>(idx: T, strings: &[StringEntry]) -> Option<String>
@@ -662,3 +782,72 @@ fn parse_string_table<T: Read + Seek>(
     }
     strings
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn put(data: &mut [u8], offset: usize, bytes: &[u8]) {
+        data[offset..offset + bytes.len()].copy_from_slice(bytes);
+    }
+
+    /// A map list with two call sites and one method handle, the second call site points outside
+    /// of the data
+    fn call_site_data() -> Vec<u8> {
+        let mut data = vec![0u8; 0x70];
+        put(&mut data, 0x10, &2u32.to_le_bytes());
+        put(&mut data, 0x14, &[0x07, 0, 0, 0, 2, 0, 0, 0, 0x40, 0, 0, 0]);
+        put(&mut data, 0x20, &[0x08, 0, 0, 0, 1, 0, 0, 0, 0x50, 0, 0, 0]);
+        put(&mut data, 0x40, &[0x60, 0, 0, 0, 0x00, 0x02, 0, 0]);
+        // invoke-static handle of method 7
+        put(&mut data, 0x50, &[0x06, 0, 0, 0, 0x07, 0, 0, 0]);
+        // handle 0, string 1, proto 0x102
+        put(
+            &mut data,
+            0x60,
+            &[0x03, 0x16, 0x00, 0x17, 0x01, 0x35, 0x02, 0x01],
+        );
+        data
+    }
+
+    #[test]
+    fn resolves_call_sites_to_bootstrap_methods() {
+        let strings = vec![
+            StringEntry::new(1, b"a".to_vec()),
+            StringEntry::new(5, b"apply".to_vec()),
+        ];
+        let call_sites = parse_call_sites(&call_site_data(), 0x10, &strings);
+        assert_eq!(
+            call_sites,
+            vec![
+                CallSite {
+                    bootstrap_method_idx: Some(7),
+                    method_name: "apply".to_string(),
+                    proto_idx: 0x102,
+                },
+                CallSite {
+                    bootstrap_method_idx: None,
+                    method_name: String::new(),
+                    proto_idx: 0,
+                },
+            ]
+        );
+    }
+
+    #[test]
+    fn malformed_map_lists_have_no_call_sites() {
+        let mut data = call_site_data();
+        assert!(parse_call_sites(&data, 0x1000, &[]).is_empty());
+        // a huge call site count is limited by the data
+        put(&mut data, 0x18, &u32::MAX.to_le_bytes());
+        assert!(parse_call_sites(&data, 0x10, &[]).len() <= data.len() / 4);
+        // a field handle has no bootstrap method
+        put(&mut data, 0x50, &[0x01]);
+        assert_eq!(
+            parse_call_sites(&data, 0x10, &[])[0].bootstrap_method_idx,
+            None
+        );
+        data.truncate(0x14);
+        assert!(parse_call_sites(&data, 0x10, &[]).is_empty());
+    }
+}
@@ -6,7 +6,7 @@
 
 //! This module handles zip extraction and gathers all files into a `Files` struct, separating dex files and binary files. The dex files are parsed and inserted into `MultiDexFile` corresponding to all dex files at the same level. For binary files, we use `goblin` to allow parsing of potentially binary files. The binary parsing is a lazy operation though.
 use abxml::{
-    visitor::{Executor, ModelVisitor, XmlVisitor},
+    visitor::{Executor, ModelVisitor, Resources, XmlVisitor},
     STR_ARSC,
 };
 
@@ -16,10 +16,15 @@ use std::{
     io::{Cursor, ErrorKind, Read, Seek},
     sync::Arc,
 };
-use zip::ZipArchive;
 
-use crate::dex::{parse_dex, parse_dex_buf, ArrayView};
-use coeus_models::models::{AndroidManifest, BinaryObject, DexFile, Files, MultiDexFile};
+use crate::{
+    archive::ZipReader,
+    dex::{parse_dex, parse_dex_buf, ArrayView},
+    jvm,
+};
+use coeus_models::models::{
+    AndroidManifest, BinaryObject, DexFile, Files, MultiDexFile, ResourceTable, CLASS_FILE_MAGIC,
+};
 
 pub fn extract_single_threaded(
     archive_name: &str,
@@ -29,28 +34,34 @@ pub fn extract_single_threaded(
     depth: u32,
     max_depth: u32,
 ) -> Files {
-    let mut archive = ZipArchive::new(f.get_cursor()).expect("Expected a zip file");
+    let mut archive = ZipReader::new(f.get_cursor().into_inner(), archive_name);
     let mut dex_files = vec![];
+    let mut class_files = vec![];
     let mut other_files = HashMap::new();
     let mut multi_dex = vec![];
     let mut bin_manifest = vec![];
     let mut bin_res_file = vec![];
+    let mut decoded_xml = HashMap::new();
+    let mut archive_anomalies = vec![];
 
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i).expect("Error Accessing file");
-        let mut zip_bytes: Vec<u8> = vec![];
-
-        std::io::copy(&mut file, &mut zip_bytes).expect("oops");
+        let entry = archive.entries()[i].clone();
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(zip_bytes) = archive.read(i) else {
+            continue;
+        };
         let ptr = zip_bytes.as_slice();
-        let file_name = format!("{}/{}", archive_name, file.name());
-        if file.name().contains("AndroidManifest.xml") {
+        let file_name = format!("{}/{}", archive_name, entry.name);
+        if entry.name.contains("AndroidManifest.xml") {
             bin_manifest = zip_bytes;
             other_files.insert(
-                file.name().to_string(),
+                entry.name.clone(),
                 Arc::new(BinaryObject::new(bin_manifest.to_vec())),
             );
             continue;
-        } else if file.name().contains("resources.arsc") {
+        } else if entry.name.contains("resources.arsc") {
             bin_res_file = zip_bytes;
             continue;
         }
@@ -59,11 +70,16 @@ pub fn extract_single_threaded(
             let array_view = ArrayView::new(zip_bytes.as_slice());
             dex_files.extend(found_dex(&file_name, &array_view, should_build_graph));
             other_files.insert(
-                file.name().to_string(),
+                entry.name.clone(),
+                Arc::new(BinaryObject::new(zip_bytes)),
+            );
+        } else if entry.name.ends_with(".class") && check_for_class_signature(ptr) {
+            class_files.push(entry.name.clone());
+            other_files.insert(
+                entry.name.clone(),
                 Arc::new(BinaryObject::new(zip_bytes)),
             );
         } else if (max_depth == 0 || depth <= max_depth) && check_for_zip_signature(ptr) {
-            let zip_bytes = zip_bytes;
             let array_view = ArrayView::new(zip_bytes.as_slice());
             let inner = extract_single_threaded(
                 &file_name,
@@ -75,43 +91,45 @@ pub fn extract_single_threaded(
             );
             multi_dex.extend(inner.multi_dex);
             other_files.extend(inner.binaries);
+            decoded_xml.extend(inner.decoded_xml);
+            archive_anomalies.extend(inner.archive_anomalies);
         } else {
             other_files.insert(
-                file.name().to_string(),
+                entry.name.clone(),
                 Arc::new(BinaryObject::new(zip_bytes)),
             );
         }
     }
-    if !dex_files.is_empty() {
-        let mut visitor = ModelVisitor::default();
-        Executor::arsc(STR_ARSC, &mut visitor).unwrap();
-        if !bin_res_file.is_empty() {
-            Executor::arsc(&bin_res_file, &mut visitor).unwrap();
+    dex_files.extend(convert_class_files(
+        archive_name,
+        class_files,
+        &other_files,
+        should_build_graph,
+    ));
+    archive_anomalies.splice(0..0, archive.into_anomalies());
+    let mut visitor = ModelVisitor::default();
+    Executor::arsc(STR_ARSC, &mut visitor).unwrap();
+    if !bin_res_file.is_empty() {
+        if let Err(e) = Executor::arsc(&bin_res_file, &mut visitor) {
+            log::warn!("Could not load resources of {}: {:?}", archive_name, e);
         }
-        let mut visitor = XmlVisitor::new(visitor.get_resources());
-        let _ = Executor::xml(Cursor::new(&bin_manifest), &mut visitor);
-        let (manifest_content, android_manifest) = {
-            let content = visitor.into_string().unwrap_or_else(|_| "".to_string());
-            (
-                content.clone(),
-                serde_xml_rs::from_str(&content).unwrap_or_default(),
-            )
-        };
-
-        let secondary = dex_files.split_off(1);
-        multi_dex.push(MultiDexFile::new(
-            android_manifest,
-            manifest_content,
-            dex_files.remove(0),
-            secondary,
-        ));
     }
+    decode_binary_xml_files(visitor.get_resources(), &other_files, &mut decoded_xml);
+    add_multi_dex(
+        visitor.get_resources(),
+        &bin_manifest,
+        dex_files,
+        &mut multi_dex,
+    );
 
+    let arsc = ResourceTable::parse(&bin_res_file).ok();
     Files {
         multi_dex,
         binaries: other_files,
         binary_resource_file: bin_res_file,
-        arsc: None
+        decoded_xml,
+        archive_anomalies,
+        arsc,
     }
 }
 
@@ -124,39 +142,36 @@ pub fn extract_zip(
     max_depth: u32,
 ) -> Files {
     let mut dex_files = vec![];
+    let mut class_files = vec![];
     let mut other_files = HashMap::new();
     let mut multi_dex = vec![];
     let mut dex_jobs = vec![];
     let mut bin_manifest = vec![];
     let mut bin_res_file = vec![];
+    let mut decoded_xml = HashMap::new();
+    let mut archive_anomalies = vec![];
 
-    let mut archive = if let Ok(archive) = ZipArchive::new(f.get_cursor()) {
-        archive
-    } else {
-        return Files {
-            multi_dex,
-            binaries: other_files,
-            binary_resource_file: vec![],
-            arsc: None
-        };
-    };
+    let mut archive = ZipReader::new(f.get_cursor().into_inner(), archive_name);
 
     for i in 0..archive.len() {
-        let mut file = archive.by_index(i).expect("Error Accessing file");
-        let mut zip_bytes: Vec<u8> = vec![];
-
-        std::io::copy(&mut file, &mut zip_bytes).expect("oops");
+        let entry = archive.entries()[i].clone();
+        if entry.is_dir() {
+            continue;
+        }
+        let Some(zip_bytes) = archive.read(i) else {
+            continue;
+        };
         let ptr = zip_bytes.as_slice();
-        let file_name = format!("{}/{}", archive_name, file.name());
-        if file.name().contains("AndroidManifest.xml") {
+        let file_name = format!("{}/{}", archive_name, entry.name);
+        if entry.name.contains("AndroidManifest.xml") {
             log::info!("Found AndroidManifest.xml in {}", archive_name);
             bin_manifest = zip_bytes;
             other_files.insert(
-                file.name().to_string(),
+                entry.name.clone(),
                 Arc::new(BinaryObject::new(bin_manifest.to_vec())),
             );
             continue;
-        } else if file.name().contains("resources.arsc") {
+        } else if entry.name.contains("resources.arsc") {
             log::info!("Found resources.arsc in {}", archive_name);
             bin_res_file = zip_bytes;
             continue;
@@ -169,11 +184,16 @@ pub fn extract_zip(
                 found_dex(&file_name, &array_view, should_build_graph)
             }));
             other_files.insert(
-                file.name().to_string(),
+                entry.name.clone(),
                 Arc::new(BinaryObject::new(dex_bytes)),
             );
+        } else if entry.name.ends_with(".class") && check_for_class_signature(ptr) {
+            class_files.push(entry.name.clone());
+            other_files.insert(
+                entry.name.clone(),
+                Arc::new(BinaryObject::new(zip_bytes)),
+            );
         } else if (max_depth == 0 || depth <= max_depth) && check_for_zip_signature(ptr) {
-            let zip_bytes = zip_bytes;
             let array_view = ArrayView::new(zip_bytes.as_slice());
             let inner = extract_zip(
                 &file_name,
@@ -185,9 +205,11 @@ pub fn extract_zip(
             );
             multi_dex.extend(inner.multi_dex);
             other_files.extend(inner.binaries);
+            decoded_xml.extend(inner.decoded_xml);
+            archive_anomalies.extend(inner.archive_anomalies);
         } else {
             other_files.insert(
-                file.name().to_string(),
+                entry.name.clone(),
                 Arc::new(BinaryObject::new(zip_bytes)),
             );
         }
@@ -197,41 +219,36 @@ pub fn extract_zip(
             dex_files.push(dex_file);
         }
     }
-    if !dex_files.is_empty() {
-        let mut visitor = ModelVisitor::default();
-        Executor::arsc(STR_ARSC, &mut visitor).unwrap();
-        if !bin_res_file.is_empty() {
-            Executor::arsc(&bin_res_file, &mut visitor).unwrap();
+    dex_files.extend(convert_class_files(
+        archive_name,
+        class_files,
+        &other_files,
+        should_build_graph,
+    ));
+    archive_anomalies.splice(0..0, archive.into_anomalies());
+    let mut visitor = ModelVisitor::default();
+    Executor::arsc(STR_ARSC, &mut visitor).unwrap();
+    if !bin_res_file.is_empty() {
+        if let Err(e) = Executor::arsc(&bin_res_file, &mut visitor) {
+            log::warn!("Could not load resources of {}: {:?}", archive_name, e);
         }
-        let mut visitor = XmlVisitor::new(visitor.get_resources());
-        let _ = Executor::xml(Cursor::new(&bin_manifest), &mut visitor);
-        let (manifest_content, android_manifest) = {
-            let content = visitor.into_string().unwrap_or_else(|_| "".to_string());
-            (
-                content.clone(),
-                serde_xml_rs::from_str(&content)
-                    .or_else::<AndroidManifest, _>(|err| {
-                        log::warn!("{:?}", err);
-                        Ok(AndroidManifest::default())
-                    })
-                    .unwrap(),
-            )
-        };
-
-        let secondary = dex_files.split_off(1);
-        multi_dex.push(MultiDexFile::new(
-            android_manifest,
-            manifest_content,
-            dex_files.remove(0),
-            secondary,
-        ));
     }
+    decode_binary_xml_files(visitor.get_resources(), &other_files, &mut decoded_xml);
+    add_multi_dex(
+        visitor.get_resources(),
+        &bin_manifest,
+        dex_files,
+        &mut multi_dex,
+    );
 
+    let arsc = ResourceTable::parse(&bin_res_file).ok();
     Files {
         multi_dex,
         binaries: other_files,
         binary_resource_file: bin_res_file,
-        arsc: None
+        decoded_xml,
+        archive_anomalies,
+        arsc,
     }
 }
 
@@ -261,6 +278,17 @@ pub fn load_file(path: &str, build_graph: bool, max_depth: i64) -> Result<Files,
             vec![],
         );
         Files::new(vec![multi_dex], HashMap::new())
+    } else if check_for_class_signature(ptr) {
+        log::debug!("found class file");
+        let coeus_file = jvm::parse_class_file_buf(path, &zip_bytes, build_graph)
+            .ok_or_else(|| std::io::Error::new(ErrorKind::Other, "Could not parse class file"))?;
+        let multi_dex = MultiDexFile::new(
+            AndroidManifest::default(),
+            String::new(),
+            coeus_file,
+            vec![],
+        );
+        Files::new(vec![multi_dex], HashMap::new())
     } else {
         log::debug!("nothing");
         Files::new(vec![], HashMap::new())
@@ -268,6 +296,121 @@ pub fn load_file(path: &str, build_graph: bool, max_depth: i64) -> Result<Files,
     Ok(found_files)
 }
 
+/// Converts the class files (stored in `other_files`) found on one level of the archive
+fn convert_class_files(
+    archive_name: &str,
+    class_files: Vec<String>,
+    other_files: &HashMap<String, Arc<BinaryObject>>,
+    should_build_graph: bool,
+) -> Vec<DexFile> {
+    if class_files.is_empty() {
+        return vec![];
+    }
+    let class_files: Vec<(String, &[u8])> = class_files
+        .into_iter()
+        .filter_map(|name| {
+            let data = other_files.get(&name)?.data();
+            Some((name, data))
+        })
+        .collect();
+    jvm::parse_class_files(archive_name, &class_files, should_build_graph)
+}
+
+/// Combines the dex files found on one level of the archive into a `MultiDexFile`. Libraries
+/// (`.aar`) carry their code in a nested `classes.jar`, which then gets the manifest of this level.
+fn add_multi_dex(
+    resources: &Resources,
+    manifest: &[u8],
+    mut dex_files: Vec<DexFile>,
+    multi_dex: &mut Vec<MultiDexFile>,
+) {
+    if dex_files.is_empty() {
+        if manifest.is_empty() {
+            return;
+        }
+        let (manifest_content, android_manifest) = decode_manifest(resources, manifest);
+        for nested in multi_dex
+            .iter_mut()
+            .filter(|nested| nested.manifest_content.is_empty())
+        {
+            nested.manifest_content = manifest_content.clone();
+            nested.android_manifest = android_manifest.clone();
+        }
+        return;
+    }
+    let (manifest_content, android_manifest) = decode_manifest(resources, manifest);
+    let secondary = dex_files.split_off(1);
+    multi_dex.push(MultiDexFile::new(
+        android_manifest,
+        manifest_content,
+        dex_files.remove(0),
+        secondary,
+    ));
+}
+
+/// Decodes the (usually binary) manifest. Libraries ship a plain text manifest.
+fn decode_manifest(resources: &Resources, manifest: &[u8]) -> (String, AndroidManifest) {
+    let content = if check_for_axml_signature(manifest) {
+        let mut visitor = XmlVisitor::new(resources);
+        let _ = Executor::xml(Cursor::new(manifest), &mut visitor);
+        visitor.into_string().unwrap_or_else(|_| "".to_string())
+    } else {
+        String::from_utf8_lossy(manifest).to_string()
+    };
+    if content.trim().is_empty() {
+        return (content, AndroidManifest::default());
+    }
+    let android_manifest = serde_xml_rs::from_str(&content)
+        .or_else::<AndroidManifest, _>(|err| {
+            log::warn!("{:?}", err);
+            Ok(AndroidManifest::default())
+        })
+        .unwrap();
+    (content, android_manifest)
+}
+
+/// Decode all binary xml files which were not decoded yet (e.g. as part of a nested archive)
+fn decode_binary_xml_files(
+    resources: &Resources,
+    binaries: &HashMap<String, Arc<BinaryObject>>,
+    decoded_xml: &mut HashMap<String, String>,
+) {
+    for (name, binary) in binaries {
+        if decoded_xml.contains_key(name) || !check_for_axml_signature(binary.data()) {
+            continue;
+        }
+        let mut visitor = XmlVisitor::new(resources);
+        if let Err(e) = Executor::xml(Cursor::new(binary.data()), &mut visitor) {
+            log::debug!("Could not decode {}: {:?}", name, e);
+            continue;
+        }
+        if let Ok(content) = visitor.into_string() {
+            decoded_xml.insert(name.clone(), content);
+        }
+    }
+}
+
+#[inline(always)]
+pub fn check_for_axml_signature<T: Read>(mut ptr: T) -> bool {
+    // RES_XML_TYPE chunk with a header size of 8 bytes
+    let mut buf: [u8; 4] = [0, 0, 0, 0];
+    match ptr.read_exact(&mut buf) {
+        Err(_) => false,
+        _ => buf == [0x03, 0x00, 0x08, 0x00],
+    }
+}
+
+#[inline(always)]
+pub fn check_for_class_signature<T: Read>(mut ptr: T) -> bool {
+    // fat Mach-O binaries share the magic, but have a small architecture count instead of the
+    // class file version
+    let mut buf: [u8; 8] = [0; 8];
+    match ptr.read_exact(&mut buf) {
+        Err(_) => false,
+        _ => buf[..4] == CLASS_FILE_MAGIC && u16::from_be_bytes([buf[6], buf[7]]) >= 45,
+    }
+}
+
 #[inline(always)]
 pub fn check_for_dex_signature<T: Read>(mut ptr: T) -> bool {
     let mut buf: [u8; 3] = [0, 0, 0];
@@ -290,3 +433,85 @@ pub fn check_for_zip_signature<T: Read>(mut ptr: T) -> bool {
         }
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A binary xml document consisting of a single empty element
+    fn binary_xml(element: &str) -> Vec<u8> {
+        let mut string_pool = vec![];
+        // one utf-16 string, no styles
+        string_pool.extend_from_slice(&1u16.to_le_bytes());
+        string_pool.extend_from_slice(&0x1cu16.to_le_bytes());
+        let string_data: Vec<u8> = std::iter::once(element.len() as u16)
+            .chain(element.encode_utf16())
+            .chain(std::iter::once(0))
+            .flat_map(u16::to_le_bytes)
+            .collect();
+        let padding = (4 - string_data.len() % 4) % 4;
+        let pool_size = 0x1c + 4 + string_data.len() + padding;
+        string_pool.extend_from_slice(&(pool_size as u32).to_le_bytes());
+        for value in [1u32, 0, 0, 0x1c + 4, 0, 0] {
+            string_pool.extend_from_slice(&value.to_le_bytes());
+        }
+        string_pool.extend_from_slice(&string_data);
+        string_pool.resize(pool_size, 0);
+
+        let mut tags = vec![];
+        // start tag without attributes
+        tags.extend_from_slice(&0x0102u16.to_le_bytes());
+        tags.extend_from_slice(&0x10u16.to_le_bytes());
+        tags.extend_from_slice(&0x24u32.to_le_bytes());
+        for value in [1u32, u32::MAX, u32::MAX, 0] {
+            tags.extend_from_slice(&value.to_le_bytes());
+        }
+        for value in [0x14u16, 0x14, 0, 0, 0, 0] {
+            tags.extend_from_slice(&value.to_le_bytes());
+        }
+        // end tag
+        tags.extend_from_slice(&0x0103u16.to_le_bytes());
+        tags.extend_from_slice(&0x10u16.to_le_bytes());
+        tags.extend_from_slice(&0x18u32.to_le_bytes());
+        for value in [1u32, u32::MAX, u32::MAX, 0] {
+            tags.extend_from_slice(&value.to_le_bytes());
+        }
+
+        let mut document = vec![];
+        document.extend_from_slice(&0x0003u16.to_le_bytes());
+        document.extend_from_slice(&0x8u16.to_le_bytes());
+        document.extend_from_slice(&((8 + string_pool.len() + tags.len()) as u32).to_le_bytes());
+        document.extend(string_pool);
+        document.extend(tags);
+        document
+    }
+
+    #[test]
+    fn binary_xml_files_are_decoded_eagerly() {
+        let mut visitor = ModelVisitor::default();
+        Executor::arsc(STR_ARSC, &mut visitor).unwrap();
+
+        let mut binaries = HashMap::new();
+        for (name, data) in [
+            ("res/a.xml", binary_xml("network-security-config")),
+            ("res/b.xml", binary_xml("paths")),
+            ("res/nested.xml", binary_xml("paths")),
+            ("res/raw.bin", b"\x03\x00\x08\x00garbage".to_vec()),
+            ("classes.txt", b"not xml".to_vec()),
+        ] {
+            binaries.insert(name.to_string(), Arc::new(BinaryObject::new(data)));
+        }
+        let mut decoded_xml = HashMap::new();
+        decoded_xml.insert("res/nested.xml".to_string(), "<kept/>".to_string());
+
+        decode_binary_xml_files(visitor.get_resources(), &binaries, &mut decoded_xml);
+
+        let mut names: Vec<_> = decoded_xml.keys().map(String::as_str).collect();
+        names.sort_unstable();
+        assert_eq!(names, ["res/a.xml", "res/b.xml", "res/nested.xml"]);
+        assert!(decoded_xml["res/a.xml"].contains("<network-security-config"));
+        assert!(decoded_xml["res/b.xml"].contains("<paths"));
+        // files decoded as part of a nested archive are not decoded again
+        assert_eq!(decoded_xml["res/nested.xml"], "<kept/>");
+    }
+}
@@ -0,0 +1,1505 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! Lowering of JVM bytecode to dalvik bytecode.
+//!
+//! Every operand stack slot gets its own register, such that the stack depth at each instruction
+//! (which the verifier guarantees to be static) determines the registers an instruction works on.
+//! The register layout is
+//!
+//! | registers                      | usage                                              |
+//! |--------------------------------|----------------------------------------------------|
+//! | `0..4`                         | scratch registers for operands which do not fit    |
+//! | `4..4 + max_stack`             | operand stack                                      |
+//! | `.. + max_locals`              | local variables                                    |
+//! | last `ins` registers           | parameters, copied to the locals in the prologue   |
+//!
+//! The result is not meant to be executed on a device, but it has the same data flow as the
+//! original method, so that the instruction based analyses (cross references, call graphs, the
+//! emulator) work on libraries as well. Exception handlers are lowered, but no try items are
+//! emitted, and `invokedynamic` is replaced by a placeholder constant.
+
+use std::{collections::HashMap, convert::TryFrom, io::Cursor};
+
+use coeus_models::models::*;
+
+use super::Pools;
+
+const SCRATCH_REGISTERS: u16 = 4;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Kind {
+    Single,
+    Reference,
+    Wide,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Slot {
+    Single,
+    Reference,
+    Wide,
+    /// The upper half of a wide value
+    WideHigh,
+}
+
+impl Kind {
+    fn of(ty: JvmType) -> Self {
+        match ty {
+            JvmType::Long | JvmType::Double => Kind::Wide,
+            JvmType::Reference => Kind::Reference,
+            _ => Kind::Single,
+        }
+    }
+    /// `None` for `V`
+    fn of_descriptor(descriptor: &str) -> Option<Self> {
+        match descriptor.as_bytes().first()? {
+            b'V' => None,
+            b'J' | b'D' => Some(Kind::Wide),
+            b'L' | b'[' => Some(Kind::Reference),
+            _ => Some(Kind::Single),
+        }
+    }
+    fn size(self) -> usize {
+        if self == Kind::Wide {
+            2
+        } else {
+            1
+        }
+    }
+}
+
+fn push(stack: &mut Vec<Slot>, kind: Kind) {
+    match kind {
+        Kind::Single => stack.push(Slot::Single),
+        Kind::Reference => stack.push(Slot::Reference),
+        Kind::Wide => stack.extend([Slot::Wide, Slot::WideHigh]),
+    }
+}
+
+fn pop(stack: &mut Vec<Slot>, slots: usize) -> Result<(), String> {
+    if stack.len() < slots {
+        return Err("Stack underflow".to_string());
+    }
+    stack.truncate(stack.len() - slots);
+    Ok(())
+}
+
+fn pop_kind(stack: &mut Vec<Slot>, kind: Kind) -> Result<(), String> {
+    pop(stack, kind.size())
+}
+
+/// Number of argument slots (including the receiver) and the return kind of an invocation
+fn invoke_shape(descriptor: &str, has_receiver: bool) -> Result<(usize, Option<Kind>), String> {
+    let (parameters, return_type) = split_method_descriptor(descriptor)
+        .ok_or_else(|| format!("Invalid method descriptor {}", descriptor))?;
+    let slots = parameters
+        .iter()
+        .filter_map(|p| Kind::of_descriptor(p))
+        .map(Kind::size)
+        .sum::<usize>()
+        + has_receiver as usize;
+    Ok((slots, Kind::of_descriptor(&return_type)))
+}
+
+/// The stack shuffle of `pop`, `dup` and `swap`: the number of slots consumed and the new slots
+/// as indices into the consumed ones (`0` is the deepest)
+fn shuffle_pattern(instruction: &JvmInstruction) -> Option<(usize, &'static [usize])> {
+    Some(match instruction {
+        JvmInstruction::Pop => (1, &[]),
+        JvmInstruction::Pop2 => (2, &[]),
+        JvmInstruction::Dup => (1, &[0, 0]),
+        JvmInstruction::DupX1 => (2, &[1, 0, 1]),
+        JvmInstruction::DupX2 => (3, &[2, 0, 1, 2]),
+        JvmInstruction::Dup2 => (2, &[0, 1, 0, 1]),
+        JvmInstruction::Dup2X1 => (3, &[1, 2, 0, 1, 2]),
+        JvmInstruction::Dup2X2 => (4, &[2, 3, 0, 1, 2, 3]),
+        JvmInstruction::Swap => (2, &[1, 0]),
+        _ => return None,
+    })
+}
+
+/// Applies a shuffle to the stack and returns the consumed slots
+fn apply_shuffle(
+    stack: &mut Vec<Slot>,
+    consumed: usize,
+    pattern: &[usize],
+) -> Result<Vec<Slot>, String> {
+    if stack.len() < consumed {
+        return Err("Stack underflow".to_string());
+    }
+    let old = stack.split_off(stack.len() - consumed);
+    if old.first() == Some(&Slot::WideHigh) {
+        return Err("Stack shuffle splits a wide value".to_string());
+    }
+    for (j, &src) in pattern.iter().enumerate() {
+        let is_valid = match old[src] {
+            Slot::Wide => pattern.get(j + 1) == Some(&(src + 1)),
+            Slot::WideHigh => j > 0 && pattern[j - 1] + 1 == src,
+            _ => true,
+        };
+        if !is_valid {
+            return Err("Stack shuffle splits a wide value".to_string());
+        }
+        stack.push(old[src]);
+    }
+    Ok(old)
+}
+
+/// Computes the stack after the instruction
+fn apply(instruction: &JvmInstruction, stack: &mut Vec<Slot>) -> Result<(), String> {
+    use JvmInstruction::*;
+    if let Some((consumed, pattern)) = shuffle_pattern(instruction) {
+        apply_shuffle(stack, consumed, pattern)?;
+        return Ok(());
+    }
+    match instruction {
+        Nop | Goto(_) | Ret(_) | Increment(..) | Return(None) | Unknown(_) => {}
+        Const(constant) => push(stack, constant_kind(constant)),
+        Load(ty, _) => push(stack, Kind::of(*ty)),
+        Store(ty, _) | Return(Some(ty)) => pop_kind(stack, Kind::of(*ty))?,
+        ArrayLoad(ty) => {
+            pop(stack, 2)?;
+            push(stack, Kind::of(*ty));
+        }
+        ArrayStore(ty) => pop(stack, 2 + Kind::of(*ty).size())?,
+        Arithmetic(JvmArithmetic::Neg, _) => {}
+        Arithmetic(JvmArithmetic::Shl | JvmArithmetic::Shr | JvmArithmetic::UShr, _) => {
+            pop(stack, 1)?
+        }
+        Arithmetic(_, ty) => pop_kind(stack, Kind::of(*ty))?,
+        Convert(from, to) => {
+            pop_kind(stack, Kind::of(*from))?;
+            push(stack, Kind::of(*to));
+        }
+        Compare(ty, _) => {
+            pop(stack, 2 * Kind::of(*ty).size())?;
+            push(stack, Kind::Single);
+        }
+        If(..) | IfNull(_) | IfNonNull(_) | TableSwitch { .. } | LookupSwitch { .. } => {
+            pop(stack, 1)?
+        }
+        IfCompare(..) | IfReferenceCompare(..) => pop(stack, 2)?,
+        Jsr(_) => push(stack, Kind::Reference),
+        GetStatic(member) => push(stack, field_kind(member)?),
+        PutStatic(member) => pop_kind(stack, field_kind(member)?)?,
+        GetField(member) => {
+            pop(stack, 1)?;
+            push(stack, field_kind(member)?);
+        }
+        PutField(member) => pop(stack, field_kind(member)?.size() + 1)?,
+        Invoke(kind, member) => {
+            let (slots, return_kind) =
+                invoke_shape(&member.descriptor, *kind != JvmInvokeKind::Static)?;
+            pop(stack, slots)?;
+            if let Some(return_kind) = return_kind {
+                push(stack, return_kind);
+            }
+        }
+        InvokeDynamic(dynamic) => {
+            let (slots, return_kind) = invoke_shape(&dynamic.descriptor, false)?;
+            pop(stack, slots)?;
+            if let Some(return_kind) = return_kind {
+                push(stack, return_kind);
+            }
+        }
+        New(_) => push(stack, Kind::Reference),
+        NewArray(_) | ANewArray(_) | CheckCast(_) => {
+            pop(stack, 1)?;
+            push(stack, Kind::Reference);
+        }
+        MultiANewArray(_, dimensions) => {
+            pop(stack, *dimensions as usize)?;
+            push(stack, Kind::Reference);
+        }
+        ArrayLength | InstanceOf(_) => {
+            pop(stack, 1)?;
+            push(stack, Kind::Single);
+        }
+        Throw | MonitorEnter | MonitorExit => pop(stack, 1)?,
+        Pop | Pop2 | Dup | DupX1 | DupX2 | Dup2 | Dup2X1 | Dup2X2 | Swap => unreachable!(),
+    }
+    Ok(())
+}
+
+fn constant_kind(constant: &JvmConstant) -> Kind {
+    match constant {
+        JvmConstant::Int(_) | JvmConstant::Float(_) => Kind::Single,
+        JvmConstant::Long(_) | JvmConstant::Double(_) => Kind::Wide,
+        JvmConstant::Dynamic(dynamic) => {
+            Kind::of_descriptor(&dynamic.descriptor).unwrap_or(Kind::Reference)
+        }
+        _ => Kind::Reference,
+    }
+}
+
+fn field_kind(member: &JvmMemberRef) -> Result<Kind, String> {
+    Kind::of_descriptor(&member.descriptor)
+        .ok_or_else(|| format!("Invalid field descriptor {}", member.descriptor))
+}
+
+/// Index of the typed variant of `iget`, `sget`, `aget` and their `put` counterparts
+fn variant_of_descriptor(descriptor: &str) -> u16 {
+    match descriptor.as_bytes().first() {
+        Some(b'J') | Some(b'D') => 1,
+        Some(b'L') | Some(b'[') => 2,
+        Some(b'Z') => 3,
+        Some(b'B') => 4,
+        Some(b'C') => 5,
+        Some(b'S') => 6,
+        _ => 0,
+    }
+}
+
+/// Computes the stack at every reachable instruction
+fn compute_stacks(
+    code: &JvmCode,
+    index: &HashMap<u32, usize>,
+) -> Result<Vec<Option<Vec<Slot>>>, String> {
+    let mut stacks: Vec<Option<Vec<Slot>>> = vec![None; code.insns.len()];
+    let mut worklist = vec![(0, vec![])];
+    for handler in &code.exception_table {
+        if let Some(&i) = index.get(&handler.handler_pc) {
+            worklist.push((i, vec![Slot::Reference]));
+        }
+    }
+    while let Some((i, stack)) = worklist.pop() {
+        if i >= code.insns.len() || stacks[i].is_some() {
+            continue;
+        }
+        if stack.len() > code.max_stack as usize {
+            return Err("Stack exceeds max_stack".to_string());
+        }
+        let instruction = &code.insns[i].1;
+        let mut next = stack.clone();
+        stacks[i] = Some(stack);
+        apply(instruction, &mut next)?;
+        for target in instruction.branch_targets() {
+            let &target = index
+                .get(&target)
+                .ok_or_else(|| format!("Invalid branch target {}", target))?;
+            worklist.push((target, next.clone()));
+        }
+        if instruction.falls_through() {
+            worklist.push((i + 1, next));
+        }
+    }
+    Ok(stacks)
+}
+
+pub(crate) fn lower_method(
+    class: &ClassFile,
+    method: &JvmMethod,
+    code: &JvmCode,
+    pools: &Pools,
+) -> Result<CodeItem, String> {
+    let index: HashMap<u32, usize> = code
+        .insns
+        .iter()
+        .enumerate()
+        .map(|(i, (pc, _))| (*pc, i))
+        .collect();
+    let stacks = compute_stacks(code, &index)?;
+    match Lowering::new(class, method, code, pools, false)?.lower(&stacks) {
+        Err(LoweringError::BranchOutOfRange) => Lowering::new(class, method, code, pools, true)?
+            .lower(&stacks)
+            .map_err(|e| e.to_string()),
+        result => result.map_err(|e| e.to_string()),
+    }
+}
+
+enum LoweringError {
+    /// A conditional branch does not fit 16 bits, we need to lower with long branches
+    BranchOutOfRange,
+    Other(String),
+}
+
+impl std::fmt::Display for LoweringError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            LoweringError::BranchOutOfRange => f.write_str("Branch out of range"),
+            LoweringError::Other(e) => f.write_str(e),
+        }
+    }
+}
+
+impl From<String> for LoweringError {
+    fn from(e: String) -> Self {
+        LoweringError::Other(e)
+    }
+}
+
+struct Fixup {
+    /// Start of the branch instruction, offsets are relative to it
+    instruction: usize,
+    /// Position of the offset in the code
+    position: usize,
+    target: u32,
+    is_wide: bool,
+}
+
+enum PayloadData {
+    Packed { first_key: i32, targets: Vec<u32> },
+    Sparse { pairs: Vec<(i32, u32)> },
+}
+
+struct Payload {
+    instruction: usize,
+    data: PayloadData,
+}
+
+struct Lowering<'a> {
+    class: &'a ClassFile,
+    code: &'a JvmCode,
+    pools: &'a Pools,
+    long_branches: bool,
+    /// (parameter kinds, including the receiver)
+    parameters: Vec<Kind>,
+    locals: u16,
+    insns: Vec<u16>,
+    fixups: Vec<Fixup>,
+    payloads: Vec<Payload>,
+    offsets: HashMap<u32, usize>,
+    outs: u16,
+}
+
+impl<'a> Lowering<'a> {
+    fn new(
+        class: &'a ClassFile,
+        method: &'a JvmMethod,
+        code: &'a JvmCode,
+        pools: &'a Pools,
+        long_branches: bool,
+    ) -> Result<Self, String> {
+        let (parameter_types, _) = split_method_descriptor(&method.descriptor)
+            .ok_or_else(|| format!("Invalid method descriptor {}", method.descriptor))?;
+        let mut parameters = vec![];
+        if method.access_flags & AccessFlags::STATIC.bits() as u16 == 0 {
+            parameters.push(Kind::Reference);
+        }
+        parameters.extend(
+            parameter_types
+                .iter()
+                .filter_map(|p| Kind::of_descriptor(p)),
+        );
+        let ins = parameters.iter().map(|p| p.size()).sum::<usize>();
+        let locals = (code.max_locals as usize).max(ins);
+        if SCRATCH_REGISTERS as usize + code.max_stack as usize + locals + ins > u16::MAX as usize {
+            return Err("Too many registers".to_string());
+        }
+        Ok(Lowering {
+            class,
+            code,
+            pools,
+            long_branches,
+            parameters,
+            locals: locals as u16,
+            insns: vec![],
+            fixups: vec![],
+            payloads: vec![],
+            offsets: HashMap::new(),
+            outs: 0,
+        })
+    }
+
+    fn ins(&self) -> u16 {
+        self.parameters.iter().map(|p| p.size() as u16).sum()
+    }
+
+    fn stack_register(&self, slot: usize) -> u16 {
+        SCRATCH_REGISTERS + slot as u16
+    }
+
+    fn local_register(&self, local: u16) -> u16 {
+        SCRATCH_REGISTERS + self.code.max_stack + local
+    }
+
+    fn lower(mut self, stacks: &[Option<Vec<Slot>>]) -> Result<CodeItem, LoweringError> {
+        // copy the parameters to their locals
+        let mut parameter_register = SCRATCH_REGISTERS + self.code.max_stack + self.locals;
+        let mut local = 0;
+        for kind in self.parameters.clone() {
+            let local_register = self.local_register(local);
+            self.emit_move(local_register, parameter_register, kind);
+            parameter_register += kind.size() as u16;
+            local += kind.size() as u16;
+        }
+
+        let handlers: Vec<u32> = self
+            .code
+            .exception_table
+            .iter()
+            .map(|handler| handler.handler_pc)
+            .collect();
+        for ((pc, instruction), stack) in self.code.insns.iter().zip(stacks) {
+            self.offsets.insert(*pc, self.insns.len());
+            let Some(stack) = stack else {
+                continue;
+            };
+            if handlers.contains(pc) && stack == &[Slot::Reference] {
+                let register = self.stack_register(0);
+                self.emit_result(register, Kind::Reference, |lowering, register| {
+                    lowering.insns.push(0x0d | register << 8)
+                });
+            }
+            self.lower_instruction(instruction, stack)?;
+        }
+        if self.insns.is_empty() {
+            self.insns.push(0x00);
+        }
+        self.write_payloads()?;
+        self.apply_fixups()?;
+
+        let mut bytes = vec![];
+        let register_size = SCRATCH_REGISTERS + self.code.max_stack + self.locals + self.ins();
+        for value in [register_size, self.ins(), self.outs, 0] {
+            bytes.extend(value.to_le_bytes());
+        }
+        bytes.extend(0u32.to_le_bytes());
+        bytes.extend((self.insns.len() as u32).to_le_bytes());
+        for unit in &self.insns {
+            bytes.extend(unit.to_le_bytes());
+        }
+        Ok(CodeItem::from_bytes(&mut Cursor::new(bytes)))
+    }
+
+    fn lower_instruction(
+        &mut self,
+        instruction: &JvmInstruction,
+        stack: &[Slot],
+    ) -> Result<(), LoweringError> {
+        use JvmInstruction::*;
+        let depth = stack.len();
+        let top = |slots: usize| SCRATCH_REGISTERS + (depth - slots) as u16;
+        if let Some((consumed, pattern)) = shuffle_pattern(instruction) {
+            self.lower_shuffle(stack, consumed, pattern)?;
+            return Ok(());
+        }
+        match instruction {
+            Nop | Pop | Pop2 | Dup | DupX1 | DupX2 | Dup2 | Dup2X1 | Dup2X2 | Swap => {}
+            Const(constant) => self.emit_const(top(0), constant)?,
+            Load(ty, local) => {
+                let local = self.local_register(*local);
+                self.emit_move(top(0), local, Kind::of(*ty));
+            }
+            Store(ty, local) => {
+                let kind = Kind::of(*ty);
+                let local = self.local_register(*local);
+                self.emit_move(local, top(kind.size()), kind);
+            }
+            ArrayLoad(ty) => {
+                let op = 0x44 + variant_of_descriptor(ty.descriptor());
+                self.emit_23x(op, (top(2), Kind::of(*ty)), top(2), top(1));
+            }
+            ArrayStore(ty) => {
+                let kind = Kind::of(*ty);
+                let value = top(kind.size());
+                let array = value - 2;
+                let op = 0x4b + variant_of_descriptor(ty.descriptor());
+                let value = self.operand(value, kind, 8, 2);
+                let index = self.operand(array + 1, Kind::Single, 8, 1);
+                let array = self.operand(array, Kind::Reference, 8, 0);
+                self.insns.extend([op | value << 8, array | index << 8]);
+            }
+            Arithmetic(op, ty) => self.lower_arithmetic(*op, *ty, depth),
+            Increment(local, value) => {
+                let local = self.local_register(*local);
+                if local <= 0xff && i8::try_from(*value).is_ok() {
+                    // add-int/lit8
+                    self.insns
+                        .extend([0xd8 | local << 8, local | (*value as u8 as u16) << 8]);
+                } else {
+                    // const/16 v2, value
+                    self.insns.extend([0x13 | 2 << 8, *value as u16]);
+                    self.emit_23x(0x90, (local, Kind::Single), local, 2);
+                }
+            }
+            Convert(from, to) => {
+                use JvmType::*;
+                let op = match (from, to) {
+                    (Int, Long) => 0x81,
+                    (Int, Float) => 0x82,
+                    (Int, Double) => 0x83,
+                    (Long, Int) => 0x84,
+                    (Long, Float) => 0x85,
+                    (Long, Double) => 0x86,
+                    (Float, Int) => 0x87,
+                    (Float, Long) => 0x88,
+                    (Float, Double) => 0x89,
+                    (Double, Int) => 0x8a,
+                    (Double, Long) => 0x8b,
+                    (Double, Float) => 0x8c,
+                    (Int, Byte) => 0x8d,
+                    (Int, Char) => 0x8e,
+                    (Int, Short) => 0x8f,
+                    _ => return Err(format!("Invalid conversion {}", instruction).into()),
+                };
+                let from = Kind::of(*from);
+                let register = top(from.size());
+                self.emit_12x(op, (register, Kind::of(*to)), (register, from));
+            }
+            Compare(ty, is_greater) => {
+                let op = match (ty, is_greater) {
+                    (JvmType::Long, _) => 0x31,
+                    (JvmType::Float, false) => 0x2d,
+                    (JvmType::Float, true) => 0x2e,
+                    (JvmType::Double, false) => 0x2f,
+                    _ => 0x30,
+                };
+                let size = Kind::of(*ty).size();
+                self.emit_23x(op, (top(2 * size), Kind::Single), top(2 * size), top(size));
+            }
+            If(condition, target) => self.emit_if_zero(*condition, top(1), Kind::Single, *target),
+            IfNull(target) => {
+                self.emit_if_zero(JvmCondition::Equal, top(1), Kind::Reference, *target)
+            }
+            IfNonNull(target) => {
+                self.emit_if_zero(JvmCondition::NotEqual, top(1), Kind::Reference, *target)
+            }
+            IfCompare(condition, target) => {
+                self.emit_if(*condition, top(2), top(1), Kind::Single, *target)
+            }
+            IfReferenceCompare(condition, target) => {
+                self.emit_if(*condition, top(2), top(1), Kind::Reference, *target)
+            }
+            Goto(target) => self.emit_goto(*target),
+            Jsr(target) => {
+                // there is no equivalent in dalvik, we only keep the control flow
+                self.emit_const(top(0), &JvmConstant::Null)?;
+                self.emit_goto(*target);
+            }
+            Ret(_) | Unknown(_) => self.insns.push(0x00),
+            TableSwitch {
+                default,
+                low,
+                targets,
+            } => {
+                let data = PayloadData::Packed {
+                    first_key: *low,
+                    targets: targets.clone(),
+                };
+                self.emit_switch(0x2b, top(1), data);
+                self.emit_goto(*default);
+            }
+            LookupSwitch { default, pairs } => {
+                if !pairs.is_empty() {
+                    let mut pairs = pairs.clone();
+                    pairs.sort_by_key(|(key, _)| *key);
+                    self.emit_switch(0x2c, top(1), PayloadData::Sparse { pairs });
+                }
+                self.emit_goto(*default);
+            }
+            Return(None) => self.insns.push(0x0e),
+            Return(Some(ty)) => {
+                let kind = Kind::of(*ty);
+                let op = match kind {
+                    Kind::Single => 0x0f,
+                    Kind::Wide => 0x10,
+                    Kind::Reference => 0x11,
+                };
+                let register = self.operand(top(kind.size()), kind, 8, 0);
+                self.insns.push(op | register << 8);
+            }
+            GetStatic(member) => {
+                let field = self.pools.field_idx(member)?;
+                let op = 0x60 + variant_of_descriptor(&member.descriptor);
+                self.emit_result(top(0), field_kind(member)?, |lowering, register| {
+                    lowering.insns.extend([op | register << 8, field])
+                });
+            }
+            PutStatic(member) => {
+                let field = self.pools.field_idx(member)?;
+                let kind = field_kind(member)?;
+                let op = 0x67 + variant_of_descriptor(&member.descriptor);
+                let register = self.operand(top(kind.size()), kind, 8, 0);
+                self.insns.extend([op | register << 8, field]);
+            }
+            GetField(member) => {
+                let field = self.pools.field_idx(member)?;
+                let op = 0x52 + variant_of_descriptor(&member.descriptor);
+                let object = top(1);
+                self.emit_22c(
+                    op,
+                    (object, field_kind(member)?),
+                    (object, Kind::Reference),
+                    field,
+                );
+            }
+            PutField(member) => {
+                let field = self.pools.field_idx(member)?;
+                let kind = field_kind(member)?;
+                let op = 0x59 + variant_of_descriptor(&member.descriptor);
+                let value = self.operand(top(kind.size()), kind, 4, 2);
+                let object = self.operand(top(kind.size() + 1), Kind::Reference, 4, 0);
+                self.insns.extend([op | value << 8 | object << 12, field]);
+            }
+            Invoke(kind, member) => self.lower_invoke(*kind, member, depth)?,
+            InvokeDynamic(dynamic) => {
+                // keep the string constants (e.g. the recipe of a string concatenation)
+                for argument in &dynamic.bootstrap_arguments {
+                    if let JvmConstant::String(_) = argument {
+                        self.emit_const(0, argument)?;
+                    }
+                }
+                let (slots, return_kind) = invoke_shape(&dynamic.descriptor, false)?;
+                if let Some(return_kind) = return_kind {
+                    let placeholder = match return_kind {
+                        Kind::Single => JvmConstant::Int(0),
+                        Kind::Wide => JvmConstant::Long(0),
+                        Kind::Reference => JvmConstant::Null,
+                    };
+                    self.emit_const(top(slots), &placeholder)?;
+                }
+            }
+            New(class_name) => {
+                let ty = self.pools.type_idx(&class_name_to_descriptor(class_name))?;
+                self.emit_result(top(0), Kind::Reference, |lowering, register| {
+                    lowering.insns.extend([0x22 | register << 8, ty])
+                });
+            }
+            NewArray(ty) => {
+                let ty = self.pools.type_idx(&format!("[{}", ty.descriptor()))?;
+                self.emit_22c(0x23, (top(1), Kind::Reference), (top(1), Kind::Single), ty);
+            }
+            ANewArray(class_name) => {
+                let descriptor = format!("[{}", class_name_to_descriptor(class_name));
+                let ty = self.pools.type_idx(&descriptor)?;
+                self.emit_22c(0x23, (top(1), Kind::Reference), (top(1), Kind::Single), ty);
+            }
+            MultiANewArray(class_name, dimensions) => {
+                // only the outermost dimension is allocated
+                let ty = self.pools.type_idx(&class_name_to_descriptor(class_name))?;
+                let register = top(*dimensions as usize);
+                self.emit_22c(
+                    0x23,
+                    (register, Kind::Reference),
+                    (register, Kind::Single),
+                    ty,
+                );
+            }
+            ArrayLength => self.emit_12x(0x21, (top(1), Kind::Single), (top(1), Kind::Reference)),
+            Throw | MonitorEnter | MonitorExit => {
+                let op = match instruction {
+                    Throw => 0x27,
+                    MonitorEnter => 0x1d,
+                    _ => 0x1e,
+                };
+                let register = self.operand(top(1), Kind::Reference, 8, 0);
+                self.insns.push(op | register << 8);
+            }
+            CheckCast(class_name) => {
+                let ty = self.pools.type_idx(&class_name_to_descriptor(class_name))?;
+                self.emit_result(top(1), Kind::Reference, |lowering, register| {
+                    lowering.insns.extend([0x1f | register << 8, ty])
+                });
+            }
+            InstanceOf(class_name) => {
+                let ty = self.pools.type_idx(&class_name_to_descriptor(class_name))?;
+                self.emit_22c(0x20, (top(1), Kind::Single), (top(1), Kind::Reference), ty);
+            }
+        }
+        Ok(())
+    }
+
+    fn lower_arithmetic(&mut self, op: JvmArithmetic, ty: JvmType, depth: usize) {
+        let kind = Kind::of(ty);
+        let top = |slots: usize| SCRATCH_REGISTERS + (depth - slots) as u16;
+        if op == JvmArithmetic::Neg {
+            let op = match ty {
+                JvmType::Long => 0x7d,
+                JvmType::Float => 0x7f,
+                JvmType::Double => 0x80,
+                _ => 0x7b,
+            };
+            let register = top(kind.size());
+            self.emit_12x(op, (register, kind), (register, kind));
+            return;
+        }
+        let index = match op {
+            JvmArithmetic::Add => 0,
+            JvmArithmetic::Sub => 1,
+            JvmArithmetic::Mul => 2,
+            JvmArithmetic::Div => 3,
+            JvmArithmetic::Rem => 4,
+            JvmArithmetic::And => 5,
+            JvmArithmetic::Or => 6,
+            JvmArithmetic::Xor => 7,
+            JvmArithmetic::Shl => 8,
+            JvmArithmetic::Shr => 9,
+            _ => 10,
+        };
+        let op = match ty {
+            JvmType::Long => 0x9b,
+            JvmType::Float => 0xa6,
+            JvmType::Double => 0xab,
+            _ => 0x90,
+        } + index;
+        let second = if matches!(
+            op,
+            0x98..=0x9a | 0xa3..=0xa5
+        ) {
+            // the shift distance is always an int
+            1
+        } else {
+            kind.size()
+        };
+        let first = top(second + kind.size());
+        self.emit_23x(op, (first, kind), first, top(second));
+    }
+
+    fn lower_invoke(
+        &mut self,
+        kind: JvmInvokeKind,
+        member: &JvmMemberRef,
+        depth: usize,
+    ) -> Result<(), LoweringError> {
+        let method = self.pools.method_idx(member)?;
+        let (slots, return_kind) = invoke_shape(&member.descriptor, kind != JvmInvokeKind::Static)?;
+        let op = match kind {
+            JvmInvokeKind::Virtual => 0x6e,
+            JvmInvokeKind::Special
+                if member.name == "<init>" || member.class_name == self.class.name =>
+            {
+                0x70
+            }
+            JvmInvokeKind::Special => 0x6f,
+            JvmInvokeKind::Static => 0x71,
+            JvmInvokeKind::Interface => 0x72,
+        };
+        let first = SCRATCH_REGISTERS + (depth - slots) as u16;
+        if slots <= 5 && first as usize + slots <= 0x10 {
+            let registers: Vec<u16> = (first..first + slots as u16).collect();
+            let fifth = registers.get(4).copied().unwrap_or(0);
+            let arguments = registers
+                .iter()
+                .take(4)
+                .enumerate()
+                .fold(0, |acc, (i, register)| acc | *register << (4 * i));
+            self.insns
+                .extend([op | (slots as u16) << 12 | fifth << 8, method, arguments]);
+        } else if slots <= 0xff {
+            self.insns
+                .extend([(op + 6) | (slots as u16) << 8, method, first]);
+        } else {
+            return Err(format!("Too many arguments for {}", member).into());
+        }
+        self.outs = self.outs.max(slots as u16);
+        if let Some(return_kind) = return_kind {
+            let op = match return_kind {
+                Kind::Single => 0x0a,
+                Kind::Wide => 0x0b,
+                Kind::Reference => 0x0c,
+            };
+            self.emit_result(first, return_kind, |lowering, register| {
+                lowering.insns.push(op | register << 8)
+            });
+        }
+        Ok(())
+    }
+
+    /// Lowers `pop`, `dup` and `swap`. Values copied above the old top of the stack are written
+    /// first, and then used as source for the values moving down.
+    fn lower_shuffle(
+        &mut self,
+        stack: &[Slot],
+        consumed: usize,
+        pattern: &[usize],
+    ) -> Result<(), String> {
+        let mut new_stack = stack.to_vec();
+        let old = apply_shuffle(&mut new_stack, consumed, pattern)?;
+        let base = stack.len() - consumed;
+        let register = |slot: usize| SCRATCH_REGISTERS + (base + slot) as u16;
+        let kind_of = |slot: Slot| match slot {
+            Slot::Single => Kind::Single,
+            Slot::Reference => Kind::Reference,
+            _ => Kind::Wide,
+        };
+        // where a copy of the consumed value starting at a slot can be found
+        let mut copies: HashMap<usize, u16> = HashMap::new();
+        for (j, &src) in pattern.iter().enumerate().skip(consumed) {
+            if old[src] != Slot::WideHigh {
+                self.emit_move(register(j), register(src), kind_of(old[src]));
+                copies.entry(src).or_insert_with(|| register(j));
+            }
+        }
+        if consumed == 2 && pattern == [1, 0] {
+            self.emit_move(0, register(1), kind_of(old[1]));
+            copies.insert(1, 0);
+        }
+        let mut moves: Vec<(usize, usize)> = pattern
+            .iter()
+            .enumerate()
+            .take(consumed)
+            .filter(|&(j, &src)| j != src && old[src] != Slot::WideHigh)
+            .map(|(j, &src)| (j, src))
+            .collect();
+        // values moving up are moved first, from the top down, such that no source is
+        // overwritten before it is read
+        moves.sort_by_key(|&(j, src)| (copies.contains_key(&src), std::cmp::Reverse(j)));
+        for (j, src) in moves {
+            let source = copies.get(&src).copied().unwrap_or_else(|| register(src));
+            self.emit_move(register(j), source, kind_of(old[src]));
+        }
+        Ok(())
+    }
+
+    fn emit_move(&mut self, dst: u16, src: u16, kind: Kind) {
+        if dst == src {
+            return;
+        }
+        let op = match kind {
+            Kind::Single => 0x01,
+            Kind::Wide => 0x04,
+            Kind::Reference => 0x07,
+        };
+        if dst <= 0xf && src <= 0xf {
+            self.insns.push(op | dst << 8 | src << 12);
+        } else if dst <= 0xff {
+            self.insns.extend([(op + 1) | dst << 8, src]);
+        } else {
+            self.insns.extend([op + 2, dst, src]);
+        }
+    }
+
+    /// Returns a register with at most `bits` bits holding the value, moving it to the scratch
+    /// register if needed
+    fn operand(&mut self, register: u16, kind: Kind, bits: u16, scratch: u16) -> u16 {
+        if register < 1 << bits {
+            register
+        } else {
+            self.emit_move(scratch, register, kind);
+            scratch
+        }
+    }
+
+    /// Emits an instruction with an 8 bit result register
+    fn emit_result<F: FnOnce(&mut Self, u16)>(&mut self, register: u16, kind: Kind, emit: F) {
+        if register <= 0xff {
+            emit(self, register);
+        } else {
+            emit(self, 0);
+            self.emit_move(register, 0, kind);
+        }
+    }
+
+    fn emit_12x(&mut self, op: u16, (dst, dst_kind): (u16, Kind), (src, src_kind): (u16, Kind)) {
+        let src = self.operand(src, src_kind, 4, 0);
+        let result = if dst <= 0xf { dst } else { 2 };
+        self.insns.push(op | result << 8 | src << 12);
+        if result != dst {
+            self.emit_move(dst, result, dst_kind);
+        }
+    }
+
+    fn emit_22c(
+        &mut self,
+        op: u16,
+        (dst, dst_kind): (u16, Kind),
+        (src, src_kind): (u16, Kind),
+        idx: u16,
+    ) {
+        let src = self.operand(src, src_kind, 4, 0);
+        let result = if dst <= 0xf { dst } else { 2 };
+        self.insns.extend([op | result << 8 | src << 12, idx]);
+        if result != dst {
+            self.emit_move(dst, result, dst_kind);
+        }
+    }
+
+    fn emit_23x(&mut self, op: u16, (dst, dst_kind): (u16, Kind), first: u16, second: u16) {
+        let (first_kind, second_kind) = match op {
+            // aget, the second operand is the index
+            0x44..=0x4a => (Kind::Reference, Kind::Single),
+            // shifts
+            0xa3..=0xa5 => (Kind::Wide, Kind::Single),
+            // the operand kind of the comparisons
+            0x2d | 0x2e => (Kind::Single, Kind::Single),
+            0x2f..=0x31 => (Kind::Wide, Kind::Wide),
+            _ => (dst_kind, dst_kind),
+        };
+        let first = self.operand(first, first_kind, 8, 0);
+        let second = self.operand(second, second_kind, 8, 2);
+        let result = if dst <= 0xff { dst } else { 0 };
+        self.insns.extend([op | result << 8, first | second << 8]);
+        if result != dst {
+            self.emit_move(dst, result, dst_kind);
+        }
+    }
+
+    fn emit_const(&mut self, register: u16, constant: &JvmConstant) -> Result<(), String> {
+        match constant {
+            JvmConstant::Int(_) | JvmConstant::Float(_) | JvmConstant::Null => {
+                let value = match constant {
+                    JvmConstant::Int(v) => *v,
+                    JvmConstant::Float(v) => v.to_bits() as i32,
+                    _ => 0,
+                };
+                let kind = constant_kind(constant);
+                if register <= 0xf && (-8..8).contains(&value) {
+                    // const/4
+                    self.insns
+                        .push(0x12 | register << 8 | (value as u16 & 0xf) << 12);
+                } else if let Ok(value) = i16::try_from(value) {
+                    self.emit_result(register, kind, |lowering, register| {
+                        lowering.insns.extend([0x13 | register << 8, value as u16])
+                    });
+                } else {
+                    self.emit_result(register, kind, |lowering, register| {
+                        lowering.insns.extend([
+                            0x14 | register << 8,
+                            value as u16,
+                            (value >> 16) as u16,
+                        ])
+                    });
+                }
+            }
+            JvmConstant::Long(_) | JvmConstant::Double(_) => {
+                let value = match constant {
+                    JvmConstant::Long(v) => *v,
+                    JvmConstant::Double(v) => v.to_bits() as i64,
+                    _ => 0,
+                };
+                self.emit_result(register, Kind::Wide, |lowering, register| {
+                    if let Ok(value) = i16::try_from(value) {
+                        lowering.insns.extend([0x16 | register << 8, value as u16]);
+                    } else if let Ok(value) = i32::try_from(value) {
+                        lowering.insns.extend([
+                            0x17 | register << 8,
+                            value as u16,
+                            (value >> 16) as u16,
+                        ]);
+                    } else {
+                        lowering.insns.push(0x18 | register << 8);
+                        lowering
+                            .insns
+                            .extend((0..4).map(|i| (value >> (16 * i)) as u16));
+                    }
+                });
+            }
+            JvmConstant::String(s) => {
+                let idx = self.pools.string_idx(s)?;
+                self.emit_result(register, Kind::Reference, |lowering, register| {
+                    if idx <= 0xffff {
+                        lowering.insns.extend([0x1a | register << 8, idx as u16]);
+                    } else {
+                        lowering.insns.extend([
+                            0x1b | register << 8,
+                            idx as u16,
+                            (idx >> 16) as u16,
+                        ]);
+                    }
+                });
+            }
+            JvmConstant::Class(name) => {
+                let ty = self.pools.type_idx(&class_name_to_descriptor(name))?;
+                self.emit_result(register, Kind::Reference, |lowering, register| {
+                    lowering.insns.extend([0x1c | register << 8, ty])
+                });
+            }
+            JvmConstant::MethodType(_) | JvmConstant::MethodHandle(..) => {
+                self.emit_const(register, &JvmConstant::Null)?
+            }
+            JvmConstant::Dynamic(dynamic) => {
+                let placeholder = match Kind::of_descriptor(&dynamic.descriptor) {
+                    Some(Kind::Wide) => JvmConstant::Long(0),
+                    Some(Kind::Single) => JvmConstant::Int(0),
+                    _ => JvmConstant::Null,
+                };
+                self.emit_const(register, &placeholder)?
+            }
+        }
+        Ok(())
+    }
+
+    fn emit_goto(&mut self, target: u32) {
+        let instruction = self.insns.len();
+        self.insns.extend([0x2a, 0, 0]);
+        self.fixups.push(Fixup {
+            instruction,
+            position: instruction + 1,
+            target,
+            is_wide: true,
+        });
+    }
+
+    /// Emits `if-*z`
+    fn emit_if_zero(&mut self, condition: JvmCondition, register: u16, kind: Kind, target: u32) {
+        let register = self.operand(register, kind, 8, 0);
+        let condition = condition as u16;
+        let instruction = self.insns.len();
+        if self.long_branches {
+            self.insns
+                .extend([(0x38 + (condition ^ 1)) | register << 8, 5]);
+            self.emit_goto(target);
+        } else {
+            self.insns.extend([(0x38 + condition) | register << 8, 0]);
+            self.fixups.push(Fixup {
+                instruction,
+                position: instruction + 1,
+                target,
+                is_wide: false,
+            });
+        }
+    }
+
+    /// Emits `if-*`
+    fn emit_if(
+        &mut self,
+        condition: JvmCondition,
+        first: u16,
+        second: u16,
+        kind: Kind,
+        target: u32,
+    ) {
+        let first = self.operand(first, kind, 4, 0);
+        let second = self.operand(second, kind, 4, 2);
+        let condition = condition as u16;
+        let instruction = self.insns.len();
+        if self.long_branches {
+            self.insns
+                .extend([(0x32 + (condition ^ 1)) | first << 8 | second << 12, 5]);
+            self.emit_goto(target);
+        } else {
+            self.insns
+                .extend([(0x32 + condition) | first << 8 | second << 12, 0]);
+            self.fixups.push(Fixup {
+                instruction,
+                position: instruction + 1,
+                target,
+                is_wide: false,
+            });
+        }
+    }
+
+    fn emit_switch(&mut self, op: u16, register: u16, data: PayloadData) {
+        let register = self.operand(register, Kind::Single, 8, 0);
+        let instruction = self.insns.len();
+        self.insns.extend([op | register << 8, 0, 0]);
+        self.payloads.push(Payload { instruction, data });
+    }
+
+    fn offset_of(&self, target: u32) -> Result<usize, LoweringError> {
+        self.offsets
+            .get(&target)
+            .copied()
+            .ok_or_else(|| format!("Invalid branch target {}", target).into())
+    }
+
+    fn write_payloads(&mut self) -> Result<(), LoweringError> {
+        for payload in std::mem::take(&mut self.payloads) {
+            if !self.insns.len().is_multiple_of(2) {
+                self.insns.push(0x00);
+            }
+            let position = self.insns.len();
+            let relative = (position - payload.instruction) as u32;
+            self.insns[payload.instruction + 1] = relative as u16;
+            self.insns[payload.instruction + 2] = (relative >> 16) as u16;
+            let mut words = vec![];
+            match &payload.data {
+                PayloadData::Packed { first_key, targets } => {
+                    self.insns.extend([0x0100, targets.len() as u16]);
+                    words.push(*first_key);
+                    for target in targets {
+                        words.push(self.offset_of(*target)? as i32 - payload.instruction as i32);
+                    }
+                }
+                PayloadData::Sparse { pairs } => {
+                    self.insns.extend([0x0200, pairs.len() as u16]);
+                    words.extend(pairs.iter().map(|(key, _)| *key));
+                    for (_, target) in pairs {
+                        words.push(self.offset_of(*target)? as i32 - payload.instruction as i32);
+                    }
+                }
+            }
+            for word in words {
+                self.insns.extend([word as u16, (word >> 16) as u16]);
+            }
+        }
+        Ok(())
+    }
+
+    fn apply_fixups(&mut self) -> Result<(), LoweringError> {
+        for fixup in std::mem::take(&mut self.fixups) {
+            let relative = self.offset_of(fixup.target)? as i64 - fixup.instruction as i64;
+            if fixup.is_wide {
+                self.insns[fixup.position] = relative as u16;
+                self.insns[fixup.position + 1] = (relative >> 16) as u16;
+            } else {
+                let relative =
+                    i16::try_from(relative).map_err(|_| LoweringError::BranchOutOfRange)?;
+                self.insns[fixup.position] = relative as u16;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::jvm::References;
+
+    const CLASS_NAME: &str = "a/B";
+
+    fn member(class_name: &str, name: &str, descriptor: &str) -> Arc<JvmMemberRef> {
+        Arc::new(JvmMemberRef {
+            class_name: class_name.to_string(),
+            name: name.to_string(),
+            descriptor: descriptor.to_string(),
+            is_interface: false,
+        })
+    }
+
+    /// Lowers a method of `a/B`, the byte offset of each instruction is its index. Returns the
+    /// code units together with the pools, such that the expected indices can be looked up.
+    fn lower(
+        is_static: bool,
+        descriptor: &str,
+        (max_stack, max_locals): (u16, u16),
+        insns: Vec<JvmInstruction>,
+    ) -> (CodeItem, Vec<u16>, Pools) {
+        let code = Arc::new(JvmCode {
+            max_stack,
+            max_locals,
+            insns: insns
+                .into_iter()
+                .enumerate()
+                .map(|(pc, instruction)| (pc as u32, instruction))
+                .collect(),
+            exception_table: vec![],
+        });
+        let method = JvmMethod {
+            access_flags: if is_static {
+                AccessFlags::STATIC.bits() as u16
+            } else {
+                0
+            },
+            name: "test".to_string(),
+            descriptor: descriptor.to_string(),
+            code: Some(code.clone()),
+        };
+        let class = ClassFile {
+            minor_version: 0,
+            major_version: 52,
+            access_flags: 0,
+            name: CLASS_NAME.to_string(),
+            super_name: Some("java/lang/Object".to_string()),
+            interfaces: vec![],
+            fields: vec![],
+            methods: vec![method.clone()],
+            source_file: None,
+        };
+        let pools = Pools::new(References::from_class(&class)).unwrap();
+        let code_item = lower_method(&class, &method, &code, &pools).unwrap();
+        let units = code_item
+            .raw_insns
+            .chunks(2)
+            .map(|unit| u16::from_le_bytes([unit[0], unit[1]]))
+            .collect();
+        (code_item, units, pools)
+    }
+
+    #[test]
+    fn constants_and_locals_are_lowered() {
+        use JvmInstruction::*;
+        // stack v4 and v5, locals v6 and v7
+        let (code, units, pools) = lower(
+            true,
+            "()V",
+            (2, 2),
+            vec![
+                Const(JvmConstant::Int(3)),
+                Store(JvmType::Int, 0),
+                Const(JvmConstant::Int(1000)),
+                Const(JvmConstant::Int(100000)),
+                Pop2,
+                Const(JvmConstant::Long(5)),
+                Store(JvmType::Long, 0),
+                Const(JvmConstant::String("hi".to_string())),
+                Const(JvmConstant::Class("java/lang/Object".to_string())),
+                Pop2,
+                Increment(0, 1),
+                Increment(0, 1000),
+                Return(None),
+            ],
+        );
+        let string = pools.string_idx("hi").unwrap() as u16;
+        let object = pools.type_idx("Ljava/lang/Object;").unwrap();
+        assert_eq!(
+            units,
+            [
+                // const/4 v4, #3 and move v6, v4
+                0x3412, 0x4601, //
+                // const/16 v4, #1000
+                0x0413, 1000, //
+                // const v5, #100000
+                0x0514, 0x86a0, 0x0001, //
+                // const-wide/16 v4, #5 and move-wide v6, v4
+                0x0416, 5, 0x4604, //
+                // const-string v4 and const-class v5
+                0x041a, string, 0x051c, object, //
+                // add-int/lit8 v6, v6, #1
+                0x06d8, 0x0106, //
+                // const/16 v2, #1000 and add-int v6, v6, v2
+                0x0213, 1000, 0x0690, 0x0206, //
+                0x000e,
+            ]
+        );
+        assert_eq!(code.register_size, 8);
+        assert_eq!(code.ins_size, 0);
+    }
+
+    #[test]
+    fn arithmetic_and_conversions_are_lowered() {
+        use JvmInstruction::*;
+        // stack v4 to v7, locals v8 to v10 and the parameters in v11 to v13
+        let (code, units, _) = lower(
+            true,
+            "(IJ)J",
+            (4, 3),
+            vec![
+                Load(JvmType::Int, 0),
+                Const(JvmConstant::Int(7)),
+                Arithmetic(JvmArithmetic::Add, JvmType::Int),
+                Convert(JvmType::Int, JvmType::Long),
+                Load(JvmType::Long, 1),
+                Arithmetic(JvmArithmetic::Mul, JvmType::Long),
+                Const(JvmConstant::Int(3)),
+                Arithmetic(JvmArithmetic::Shl, JvmType::Long),
+                Arithmetic(JvmArithmetic::Neg, JvmType::Long),
+                Return(Some(JvmType::Long)),
+            ],
+        );
+        assert_eq!(
+            units,
+            [
+                // the prologue copies the parameters to the locals
+                0xb801, 0xc904, //
+                // move v4, v8 and const/4 v5, #7
+                0x8401, 0x7512, //
+                // add-int v4, v4, v5
+                0x0490, 0x0504, //
+                // int-to-long v4, v4 and move-wide v6, v9
+                0x4481, 0x9604, //
+                // mul-long v4, v4, v6
+                0x049d, 0x0604, //
+                // const/4 v6, #3 and shl-long v4, v4, v6
+                0x3612, 0x04a3, 0x0604, //
+                // neg-long v4, v4 and return-wide v4
+                0x447d, 0x0410,
+            ]
+        );
+        assert_eq!(code.register_size, 14);
+        assert_eq!(code.ins_size, 3);
+    }
+
+    #[test]
+    fn branches_are_resolved() {
+        use JvmInstruction::*;
+        // stack v4 and v5, local v6 and the parameter in v7
+        let (_, units, _) = lower(
+            true,
+            "(I)I",
+            (2, 1),
+            vec![
+                Load(JvmType::Int, 0),
+                If(JvmCondition::Equal, 5),
+                Load(JvmType::Int, 0),
+                Const(JvmConstant::Int(2)),
+                IfCompare(JvmCondition::LessThan, 7),
+                Const(JvmConstant::Int(0)),
+                Return(Some(JvmType::Int)),
+                Goto(5),
+            ],
+        );
+        assert_eq!(
+            units,
+            [
+                0x7601, //
+                0x6401, //
+                // if-eqz v4, +6
+                0x0438, 6, //
+                0x6401, 0x2512, //
+                // if-lt v4, v5, +4
+                0x5434, 4, //
+                0x0412, 0x040f, //
+                // goto/32 -2
+                0x002a, 0xfffe, 0xffff,
+            ]
+        );
+    }
+
+    #[test]
+    fn switches_get_payloads() {
+        use JvmInstruction::*;
+        // stack v4, local v5 and the parameter in v6
+        let (_, units, _) = lower(
+            true,
+            "(I)V",
+            (1, 1),
+            vec![
+                Load(JvmType::Int, 0),
+                TableSwitch {
+                    default: 5,
+                    low: 1,
+                    targets: vec![2, 4],
+                },
+                Load(JvmType::Int, 0),
+                LookupSwitch {
+                    default: 5,
+                    pairs: vec![(10, 5), (-1, 4)],
+                },
+                Return(None),
+                Return(None),
+            ],
+        );
+        assert_eq!(
+            units,
+            [
+                0x6501, 0x5401, //
+                // packed-switch v4, +16
+                0x042b, 16, 0, //
+                // goto/32 +11 to the default
+                0x002a, 11, 0, //
+                // move v4, v5
+                0x5401, //
+                // sparse-switch v4, +17
+                0x042c, 17, 0, //
+                // goto/32 +4 to the default
+                0x002a, 4, 0, //
+                0x000e, 0x000e, //
+                // alignment of the payloads
+                0x0000, //
+                // packed payload, first key 1
+                0x0100, 2, 1, 0, 6, 0, 13, 0, //
+                // sparse payload, the keys are sorted
+                0x0200, 2, 0xffff, 0xffff, 10, 0, 6, 0, 7, 0,
+            ]
+        );
+    }
+
+    #[test]
+    fn members_and_objects_are_lowered() {
+        use JvmInstruction::*;
+        let init = member(CLASS_NAME, "<init>", "()V");
+        let size = member(CLASS_NAME, "size", "()I");
+        let count = member(CLASS_NAME, "count", "I");
+        let name = member(CLASS_NAME, "name", "Ljava/lang/String;");
+        let mut length = member("java/lang/CharSequence", "length", "()I");
+        Arc::make_mut(&mut length).is_interface = true;
+        let value_of = member("java/lang/Integer", "valueOf", "(I)Ljava/lang/Integer;");
+        // stack v4 to v6, local v7 and the receiver in v8
+        let (code, units, pools) = lower(
+            false,
+            "()V",
+            (3, 1),
+            vec![
+                New(CLASS_NAME.to_string()),
+                Dup,
+                Invoke(JvmInvokeKind::Special, init.clone()),
+                Invoke(JvmInvokeKind::Virtual, size.clone()),
+                PutStatic(count.clone()),
+                Load(JvmType::Reference, 0),
+                GetField(name.clone()),
+                CheckCast("java/lang/CharSequence".to_string()),
+                Invoke(JvmInvokeKind::Interface, length.clone()),
+                Invoke(JvmInvokeKind::Static, value_of.clone()),
+                InstanceOf("java/lang/Number".to_string()),
+                NewArray(JvmType::Int),
+                ArrayLength,
+                Pop,
+                Load(JvmType::Reference, 0),
+                Throw,
+            ],
+        );
+        let ty = |descriptor: &str| pools.type_idx(descriptor).unwrap();
+        let method = |member: &JvmMemberRef| pools.method_idx(member).unwrap();
+        let field = |member: &JvmMemberRef| pools.field_idx(member).unwrap();
+        assert_eq!(
+            units,
+            [
+                // move-object v7, v8
+                0x8707,
+                // new-instance v4 and the dup to v5
+                0x0422,
+                ty("La/B;"),
+                0x4507,
+                // invoke-direct {v5}
+                0x1070,
+                method(&init),
+                0x0005,
+                // invoke-virtual {v4} and move-result v4
+                0x106e,
+                method(&size),
+                0x0004,
+                0x040a,
+                // sput v4
+                0x0467,
+                field(&count),
+                // move-object v4, v7 and iget-object v4, v4
+                0x7407,
+                0x4454,
+                field(&name),
+                // check-cast v4
+                0x041f,
+                ty("Ljava/lang/CharSequence;"),
+                // invoke-interface {v4} and move-result v4
+                0x1072,
+                method(&length),
+                0x0004,
+                0x040a,
+                // invoke-static {v4} and move-result-object v4
+                0x1071,
+                method(&value_of),
+                0x0004,
+                0x040c,
+                // instance-of v4, v4, new-array v4, v4 and array-length v4, v4
+                0x4420,
+                ty("Ljava/lang/Number;"),
+                0x4423,
+                ty("[I"),
+                0x4421,
+                // move-object v4, v7 and throw v4
+                0x7407,
+                0x0427,
+            ]
+        );
+        assert_eq!(code.outs_size, 1);
+    }
+
+    #[test]
+    fn stack_shuffles_become_moves() {
+        use JvmInstruction::*;
+        // stack v4 to v6
+        let (_, units, _) = lower(
+            true,
+            "()V",
+            (3, 0),
+            vec![
+                Const(JvmConstant::Int(1)),
+                Const(JvmConstant::Int(2)),
+                Swap,
+                DupX1,
+                Pop2,
+                Pop,
+                Return(None),
+            ],
+        );
+        assert_eq!(
+            units,
+            [
+                0x1412, 0x2512, //
+                // swap through the scratch register v0
+                0x5001, 0x4501, 0x0401, //
+                // dup_x1: v6 = v5, v5 = v4, v4 = v6
+                0x5601, 0x4501, 0x6401, //
+                0x000e,
+            ]
+        );
+    }
+}
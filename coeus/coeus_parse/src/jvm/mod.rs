@@ -0,0 +1,742 @@
+// Copyright (c) 2022 Ubique Innovation AG <https://www.ubique.ch>
+//
+// This Source Code Form is subject to the terms of the Mozilla Public
+// License, v. 2.0. If a copy of the MPL was not distributed with this
+// file, You can obtain one at http://mozilla.org/MPL/2.0/.
+
+//! This module loads Java class files (e.g. from `.jar` or `.aar` libraries) into `DexFile`s, such
+//! that libraries can be analyzed with the same APIs as apps. Similar to `d8`, the classes are
+//! split into several dex files if the method, field, type or proto pool would exceed 65535 entries.
+//! The JVM bytecode is lowered to dalvik bytecode (see `lowering`), the original code is kept in
+//! `MethodData::jvm_code`.
+mod lowering;
+
+use std::{
+    collections::{hash_map::DefaultHasher, BTreeSet, HashMap, HashSet},
+    convert::TryFrom,
+    hash::{Hash, Hasher},
+    io::Cursor,
+    sync::{Arc, Mutex},
+};
+
+use coeus_macros::iterator;
+use coeus_models::models::*;
+
+use crate::dex::{build_class_tables, graph::build_graph};
+
+#[cfg(not(target_arch = "wasm32"))]
+use rayon::iter::ParallelIterator;
+
+const NO_INDEX: u32 = 0xffffffff;
+const MAX_POOL_SIZE: usize = 0xffff;
+/// Code offset of methods with code. The header's `data_off` is set accordingly, such that all
+/// consumers checking `code_off < data_off` treat the method as having code.
+const CODE_OFF: u64 = 1;
+
+/// (class descriptor, name, type descriptor)
+type FieldKey = (String, String, String);
+/// (class descriptor, name, method descriptor)
+type MethodKey = (String, String, String);
+
+/// Parses all class files and converts them into one or more `DexFile`s. Class files which cannot
+/// be parsed are skipped.
+pub fn parse_class_files(
+    file_name: &str,
+    class_files: &[(String, &[u8])],
+    should_build_graph: bool,
+) -> Vec<DexFile> {
+    let mut classes: Vec<(String, ClassFile)> = iterator!(class_files)
+        .filter(|(name, _)| !name.starts_with("META-INF/versions/"))
+        .filter_map(|(name, data)| match ClassFile::parse(data) {
+            Ok(class) if !class.is_module_info() => Some((name.clone(), class)),
+            Ok(_) => None,
+            Err(e) => {
+                log::warn!("Could not parse class file {}/{}: {}", file_name, name, e);
+                None
+            }
+        })
+        .collect();
+    classes.sort_by(|(a, _), (b, _)| a.cmp(b));
+    let mut seen = HashSet::new();
+    classes.retain(|(_, class)| seen.insert(class.name.clone()));
+    let file_size = class_files
+        .iter()
+        .map(|(_, data)| data.len())
+        .sum::<usize>();
+
+    let mut chunks: Vec<(References, Vec<&ClassFile>)> = vec![];
+    for (_, class) in &classes {
+        let class_references = References::from_class(class);
+        match chunks.last_mut() {
+            Some((chunk_references, chunk_classes)) if chunk_references.fits(&class_references) => {
+                chunk_references.extend(class_references);
+                chunk_classes.push(class);
+            }
+            _ => chunks.push((class_references, vec![class])),
+        }
+    }
+    let number_of_chunks = chunks.len();
+    chunks
+        .into_iter()
+        .enumerate()
+        .filter_map(|(i, (references, classes))| {
+            let chunk_name = if i == 0 {
+                file_name.to_string()
+            } else {
+                format!("{}#{}", file_name, i + 1)
+            };
+            log::debug!(
+                "Converting {} classes of {} ({}/{})",
+                classes.len(),
+                file_name,
+                i + 1,
+                number_of_chunks
+            );
+            build_dex_file(
+                &chunk_name,
+                i,
+                references,
+                &classes,
+                file_size as u32,
+                should_build_graph,
+            )
+            .map_err(|e| log::warn!("Could not convert {}: {}", chunk_name, e))
+            .ok()
+        })
+        .collect()
+}
+
+/// Parses a single class file
+pub fn parse_class_file_buf(
+    file_name: &str,
+    data: &[u8],
+    should_build_graph: bool,
+) -> Option<DexFile> {
+    let name = std::path::Path::new(file_name)
+        .file_name()
+        .and_then(|name| name.to_str())
+        .unwrap_or(file_name)
+        .to_string();
+    parse_class_files(file_name, &[(name, data)], should_build_graph)
+        .into_iter()
+        .next()
+}
+
+/// All pool entries referenced by a set of classes
+#[derive(Default)]
+struct References {
+    strings: BTreeSet<String>,
+    types: BTreeSet<String>,
+    protos: BTreeSet<String>,
+    fields: BTreeSet<FieldKey>,
+    methods: BTreeSet<MethodKey>,
+}
+
+impl References {
+    fn from_class(class: &ClassFile) -> Self {
+        let mut references = References::default();
+        let class_descriptor = class.descriptor();
+        references.add_type(&class_descriptor);
+        if let Some(super_name) = &class.super_name {
+            references.add_type(&class_name_to_descriptor(super_name));
+        }
+        for interface in &class.interfaces {
+            references.add_type(&class_name_to_descriptor(interface));
+        }
+        for field in &class.fields {
+            references.add_field(&class_descriptor, &field.name, &field.descriptor);
+            if let Some(JvmConstant::String(s)) = &field.constant_value {
+                references.strings.insert(s.clone());
+            }
+        }
+        for method in &class.methods {
+            references.add_method(&class_descriptor, &method.name, &method.descriptor);
+            let Some(code) = &method.code else {
+                continue;
+            };
+            for (_, instruction) in &code.insns {
+                references.add_instruction(instruction);
+            }
+        }
+        references
+    }
+
+    fn add_instruction(&mut self, instruction: &JvmInstruction) {
+        match instruction {
+            JvmInstruction::Const(JvmConstant::String(s)) => {
+                self.strings.insert(s.clone());
+            }
+            JvmInstruction::Const(JvmConstant::Class(name))
+            | JvmInstruction::New(name)
+            | JvmInstruction::CheckCast(name)
+            | JvmInstruction::InstanceOf(name)
+            | JvmInstruction::MultiANewArray(name, _) => {
+                self.add_type(&class_name_to_descriptor(name));
+            }
+            JvmInstruction::NewArray(ty) => self.add_type(&format!("[{}", ty.descriptor())),
+            JvmInstruction::ANewArray(name) => {
+                self.add_type(&format!("[{}", class_name_to_descriptor(name)))
+            }
+            JvmInstruction::GetStatic(member)
+            | JvmInstruction::PutStatic(member)
+            | JvmInstruction::GetField(member)
+            | JvmInstruction::PutField(member) => self.add_field(
+                &class_name_to_descriptor(&member.class_name),
+                &member.name,
+                &member.descriptor,
+            ),
+            JvmInstruction::Invoke(_, member) => self.add_method(
+                &class_name_to_descriptor(&member.class_name),
+                &member.name,
+                &member.descriptor,
+            ),
+            JvmInstruction::InvokeDynamic(dynamic) => {
+                for argument in &dynamic.bootstrap_arguments {
+                    if let JvmConstant::String(s) = argument {
+                        self.strings.insert(s.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn add_type(&mut self, descriptor: &str) {
+        if !self.types.contains(descriptor) {
+            self.types.insert(descriptor.to_string());
+            self.strings.insert(descriptor.to_string());
+        }
+    }
+
+    fn add_proto(&mut self, descriptor: &str) {
+        if self.protos.contains(descriptor) {
+            return;
+        }
+        let Some((parameters, return_type)) = split_method_descriptor(descriptor) else {
+            return;
+        };
+        self.strings.insert(shorty(&parameters, &return_type));
+        self.add_type(&return_type);
+        for parameter in &parameters {
+            self.add_type(parameter);
+        }
+        self.protos.insert(descriptor.to_string());
+    }
+
+    fn add_field(&mut self, class_descriptor: &str, name: &str, descriptor: &str) {
+        self.add_type(class_descriptor);
+        self.add_type(descriptor);
+        self.strings.insert(name.to_string());
+        self.fields.insert((
+            class_descriptor.to_string(),
+            name.to_string(),
+            descriptor.to_string(),
+        ));
+    }
+
+    fn add_method(&mut self, class_descriptor: &str, name: &str, descriptor: &str) {
+        if split_method_descriptor(descriptor).is_none() {
+            return;
+        }
+        self.add_type(class_descriptor);
+        self.add_proto(descriptor);
+        self.strings.insert(name.to_string());
+        self.methods.insert((
+            class_descriptor.to_string(),
+            name.to_string(),
+            descriptor.to_string(),
+        ));
+    }
+
+    /// Checks if the pools indexed by 16 bits still fit, if the other references are added
+    fn fits(&self, other: &References) -> bool {
+        fn union_len<T: Ord>(a: &BTreeSet<T>, b: &BTreeSet<T>) -> usize {
+            a.len() + b.iter().filter(|item| !a.contains(item)).count()
+        }
+        union_len(&self.types, &other.types) <= MAX_POOL_SIZE
+            && union_len(&self.protos, &other.protos) <= MAX_POOL_SIZE
+            && union_len(&self.fields, &other.fields) <= MAX_POOL_SIZE
+            && union_len(&self.methods, &other.methods) <= MAX_POOL_SIZE
+    }
+
+    fn extend(&mut self, other: References) {
+        self.strings.extend(other.strings);
+        self.types.extend(other.types);
+        self.protos.extend(other.protos);
+        self.fields.extend(other.fields);
+        self.methods.extend(other.methods);
+    }
+}
+
+fn shorty(parameters: &[String], return_type: &str) -> String {
+    std::iter::once(return_type)
+        .chain(parameters.iter().map(|p| p.as_str()))
+        .map(|descriptor| match descriptor.as_bytes()[0] {
+            b'[' => 'L',
+            c => c as char,
+        })
+        .collect()
+}
+
+/// The pools of a dex file, together with the lookup tables needed during lowering
+pub(crate) struct Pools {
+    pub strings: Vec<StringEntry>,
+    pub types: Vec<u32>,
+    pub protos: Vec<Arc<Proto>>,
+    pub fields: Vec<Arc<Field>>,
+    pub methods: Vec<Arc<Method>>,
+    string_lookup: HashMap<String, u32>,
+    type_lookup: HashMap<String, u16>,
+    field_lookup: HashMap<FieldKey, u16>,
+    method_lookup: HashMap<MethodKey, u16>,
+}
+
+/// The index of a pool entry, failing if a single class references more entries than a dex file
+/// can hold
+fn pool_idx(i: usize, pool: &str) -> Result<u16, String> {
+    u16::try_from(i).map_err(|_| format!("{} pool exceeds {} entries", pool, MAX_POOL_SIZE))
+}
+
+impl Pools {
+    fn new(references: References) -> Result<Self, String> {
+        let string_lookup: HashMap<String, u32> = references
+            .strings
+            .iter()
+            .enumerate()
+            .map(|(i, s)| (s.clone(), i as u32))
+            .collect();
+        let strings = references
+            .strings
+            .iter()
+            .map(|s| StringEntry::from(s.as_str()))
+            .collect();
+        let type_lookup: HashMap<String, u16> = references
+            .types
+            .iter()
+            .enumerate()
+            .map(|(i, t)| Ok((t.clone(), pool_idx(i, "Type")?)))
+            .collect::<Result<_, String>>()?;
+        let types = references.types.iter().map(|t| string_lookup[t]).collect();
+        let mut proto_lookup = HashMap::new();
+        let mut protos = vec![];
+        for (i, descriptor) in references.protos.iter().enumerate() {
+            let (parameters, return_type) =
+                split_method_descriptor(descriptor).expect("only valid descriptors are collected");
+            protos.push(Arc::new(Proto {
+                shorty_idx: string_lookup[&shorty(&parameters, &return_type)],
+                return_type_idx: type_lookup[&return_type] as u32,
+                parameters_off: 0,
+                arguments: parameters.iter().map(|p| type_lookup[p]).collect(),
+            }));
+            proto_lookup.insert(descriptor.clone(), pool_idx(i, "Proto")?);
+        }
+        let mut field_lookup = HashMap::new();
+        let mut fields = vec![];
+        for (i, key) in references.fields.into_iter().enumerate() {
+            let (class, name, descriptor) = &key;
+            fields.push(Arc::new(Field {
+                class_idx: type_lookup[class],
+                type_idx: type_lookup[descriptor],
+                name_idx: string_lookup[name],
+                name: name.clone(),
+            }));
+            field_lookup.insert(key, pool_idx(i, "Field")?);
+        }
+        let mut method_lookup = HashMap::new();
+        let mut methods = vec![];
+        for (i, key) in references.methods.into_iter().enumerate() {
+            let (class, name, descriptor) = &key;
+            let method_idx = pool_idx(i, "Method")?;
+            methods.push(Arc::new(Method {
+                class_idx: type_lookup[class],
+                method_idx,
+                proto_idx: proto_lookup[descriptor],
+                name_idx: string_lookup[name],
+                method_name: name.clone(),
+                proto_name: descriptor.clone(),
+            }));
+            method_lookup.insert(key, method_idx);
+        }
+        Ok(Pools {
+            strings,
+            types,
+            protos,
+            fields,
+            methods,
+            string_lookup,
+            type_lookup,
+            field_lookup,
+            method_lookup,
+        })
+    }
+
+    pub fn string_idx(&self, s: &str) -> Result<u32, String> {
+        self.string_lookup
+            .get(s)
+            .copied()
+            .ok_or_else(|| format!("String {:?} not in pool", s))
+    }
+    pub fn type_idx(&self, descriptor: &str) -> Result<u16, String> {
+        self.type_lookup
+            .get(descriptor)
+            .copied()
+            .ok_or_else(|| format!("Type {} not in pool", descriptor))
+    }
+    pub fn field_idx(&self, member: &JvmMemberRef) -> Result<u16, String> {
+        let key = (
+            class_name_to_descriptor(&member.class_name),
+            member.name.clone(),
+            member.descriptor.clone(),
+        );
+        self.field_lookup
+            .get(&key)
+            .copied()
+            .ok_or_else(|| format!("Field {} not in pool", member))
+    }
+    pub fn method_idx(&self, member: &JvmMemberRef) -> Result<u16, String> {
+        let key = (
+            class_name_to_descriptor(&member.class_name),
+            member.name.clone(),
+            member.descriptor.clone(),
+        );
+        self.method_lookup
+            .get(&key)
+            .copied()
+            .ok_or_else(|| format!("Method {} not in pool", member))
+    }
+}
+
+fn build_dex_file(
+    file_name: &str,
+    chunk: usize,
+    references: References,
+    classes: &[&ClassFile],
+    file_size: u32,
+    should_build_graph: bool,
+) -> Result<DexFile, String> {
+    let pools = Pools::new(references)?;
+    let header = build_header(file_name, chunk, classes, &pools, file_size);
+    let identifier = format!("{:02x?}", header.signature);
+
+    let mut ret_classes = Vec::with_capacity(classes.len());
+    let vec_lock = Arc::new(Mutex::new(&mut ret_classes));
+
+    iterator!(classes).for_each(|class| {
+        let the_class = Arc::new(build_class(
+            class,
+            &identifier,
+            &pools,
+            &header,
+            should_build_graph,
+        ));
+        if let Ok(mut ret_classes) = vec_lock.lock() {
+            ret_classes.push(the_class);
+        };
+    });
+    ret_classes.sort_by_key(|class| class.class_idx);
+    let mut dex_file = DexFile {
+        identifier,
+        file_name: file_name.to_string(),
+        header,
+        strings: pools.strings.into(),
+        types: pools.types.into(),
+        protos: pools.protos.into(),
+        methods: pools.methods.into(),
+        fields: pools.fields.into(),
+        classes: ret_classes.into(),
+        call_sites: vec![],
+        interface_table: HashMap::new(),
+        superclass_table: HashMap::new(),
+        index: DexIndex::default(),
+    };
+    build_class_tables(&mut dex_file);
+    Ok(dex_file)
+}
+
+fn build_header(
+    file_name: &str,
+    chunk: usize,
+    classes: &[&ClassFile],
+    pools: &Pools,
+    file_size: u32,
+) -> DexHeader {
+    // there is no checksum to derive the signature from, hence we hash the class names instead
+    let mut signature = [0u8; 20];
+    for (i, part) in signature.chunks_mut(8).enumerate() {
+        let mut hasher = DefaultHasher::new();
+        (i, file_name, chunk).hash(&mut hasher);
+        for class in classes {
+            class.name.hash(&mut hasher);
+        }
+        let hash = hasher.finish().to_be_bytes();
+        part.copy_from_slice(&hash[..part.len()]);
+    }
+    let (minor_version, major_version) = classes
+        .iter()
+        .map(|class| (class.minor_version, class.major_version))
+        .max_by_key(|&(minor, major)| (major, minor))
+        .unwrap_or_default();
+    let mut magic = [0u8; 8];
+    magic[..4].copy_from_slice(&CLASS_FILE_MAGIC);
+    magic[4..6].copy_from_slice(&minor_version.to_be_bytes());
+    magic[6..].copy_from_slice(&major_version.to_be_bytes());
+    DexHeader {
+        magic,
+        checksum: 0,
+        signature,
+        file_size,
+        header_size: 0x70,
+        endian_tag: 0x12345678,
+        link_size: 0,
+        link_off: 0,
+        map_off: 0,
+        string_ids_size: pools.strings.len() as u32,
+        string_ids_off: 0,
+        type_ids_size: pools.types.len() as u32,
+        type_ids_off: 0,
+        proto_ids_size: pools.protos.len() as u32,
+        proto_ids_off: 0,
+        fields_ids_size: pools.fields.len() as u32,
+        fields_ids_off: 0,
+        method_ids_size: pools.methods.len() as u32,
+        method_ids_off: 0,
+        class_defs_size: classes.len() as u32,
+        class_defs_off: 0,
+        data_size: 0,
+        data_off: CODE_OFF as u32,
+    }
+}
+
+fn build_class(
+    class: &ClassFile,
+    identifier: &str,
+    pools: &Pools,
+    header: &DexHeader,
+    should_build_graph: bool,
+) -> Class {
+    let class_descriptor = class.descriptor();
+    let class_idx = pools.type_idx(&class_descriptor).unwrap_or_default() as u32;
+    let super_class = class
+        .super_name
+        .as_ref()
+        .and_then(|name| pools.type_idx(&class_name_to_descriptor(name)).ok())
+        .map(|idx| idx as u32)
+        .unwrap_or(NO_INDEX);
+    let interfaces = class
+        .interfaces
+        .iter()
+        .filter_map(|name| pools.type_idx(&class_name_to_descriptor(name)).ok())
+        .collect();
+
+    let mut static_fields = vec![];
+    let mut instance_fields = vec![];
+    for field in &class.fields {
+        let member = JvmMemberRef {
+            class_name: class.name.clone(),
+            name: field.name.clone(),
+            descriptor: field.descriptor.clone(),
+            is_interface: false,
+        };
+        let Ok(field_idx) = pools.field_idx(&member) else {
+            continue;
+        };
+        let encoded_field = EncodedField {
+            field_idx: field_idx as u32,
+            access_flags: AccessFlags::from_bits_truncate(field.access_flags as u64),
+        };
+        if encoded_field.access_flags.contains(AccessFlags::STATIC) {
+            static_fields.push((encoded_field, field));
+        } else {
+            instance_fields.push(encoded_field);
+        }
+    }
+    static_fields.sort_by_key(|(field, _)| field.field_idx);
+    instance_fields.sort_by_key(|field| field.field_idx);
+    let static_values = encode_static_values(&static_fields, pools);
+
+    let mut direct_methods = vec![];
+    let mut virtual_methods = vec![];
+    let mut codes = vec![];
+    for method in &class.methods {
+        let member = JvmMemberRef {
+            class_name: class.name.clone(),
+            name: method.name.clone(),
+            descriptor: method.descriptor.clone(),
+            is_interface: false,
+        };
+        let Ok(method_idx) = pools.method_idx(&member) else {
+            log::debug!("Skipping method with invalid descriptor {}", member);
+            continue;
+        };
+        let is_constructor = method.name == "<init>" || method.name == "<clinit>";
+        let mut access_flags = AccessFlags::from_bits_truncate(method.access_flags as u64);
+        if is_constructor {
+            access_flags |= AccessFlags::CONSTRUCTOR;
+        }
+        let code = method.code.as_ref().and_then(|code| {
+            lowering::lower_method(class, method, code, pools)
+                .map_err(|e| {
+                    log::debug!("Could not lower {}: {}", member, e);
+                })
+                .ok()
+        });
+        let encoded_method = EncodedMethod {
+            method_idx: method_idx as u32,
+            access_flags,
+            code_off: if code.is_some() { CODE_OFF } else { 0 },
+        };
+        let new_m = pools.methods[method_idx as usize].clone();
+        let call_graph = match &code {
+            Some(code) if should_build_graph => build_graph(
+                code,
+                header,
+                &encoded_method,
+                &pools.strings,
+                &pools.types,
+                &pools.methods,
+            ),
+            _ => None,
+        };
+        codes.push(Arc::new(MethodData {
+            method_idx: method_idx as u32,
+            access_flags,
+            name: new_m.method_name.clone(),
+            method: new_m,
+            call_graph,
+            code,
+            jvm_code: method.code.clone(),
+        }));
+        if is_constructor || access_flags.intersects(AccessFlags::STATIC | AccessFlags::PRIVATE) {
+            direct_methods.push(encoded_method);
+        } else {
+            virtual_methods.push(encoded_method);
+        }
+    }
+    direct_methods.sort_by_key(|method| method.method_idx);
+    virtual_methods.sort_by_key(|method| method.method_idx);
+
+    let class_data = ClassData {
+        static_fields_size: static_fields.len() as u64,
+        instance_fields_size: instance_fields.len() as u64,
+        direct_methods_size: direct_methods.len() as u64,
+        virtual_methods_size: virtual_methods.len() as u64,
+        static_fields: static_fields.into_iter().map(|(field, _)| field).collect(),
+        instance_fields,
+        direct_methods,
+        virtual_methods,
+    };
+    let access_flags = AccessFlags::from_bits_truncate((class.access_flags & !ACC_SUPER) as u64);
+    Class {
+        dex_identifier: identifier.to_string(),
+        class_idx,
+        class_name: class_descriptor,
+        access_flags,
+        super_class,
+        interfaces,
+        annotations_off: 0,
+        annotations: vec![],
+        method_annotations: vec![],
+        field_annotations: vec![],
+        class_data: Some(class_data),
+        codes,
+        static_fields: static_values,
+    }
+}
+
+/// Encodes the `ConstantValue`s of the static fields as an `encoded_array`. Like in dex files,
+/// fields without a value get the default value, trailing fields are omitted.
+fn encode_static_values(
+    static_fields: &[(EncodedField, &JvmField)],
+    pools: &Pools,
+) -> Vec<EncodedItem> {
+    let Some(last) = static_fields
+        .iter()
+        .rposition(|(_, field)| field.constant_value.is_some())
+    else {
+        return vec![];
+    };
+    let mut bytes = vec![];
+    leb128::write::unsigned(&mut bytes, (last + 1) as u64).unwrap();
+    for (_, field) in &static_fields[..=last] {
+        let value = field.constant_value.as_ref();
+        let descriptor = field.descriptor.as_str();
+        match (descriptor, value) {
+            ("Z", Some(JvmConstant::Int(v))) => bytes.push(((*v != 0) as u8) << 5 | 0x1f),
+            ("Z", _) => bytes.push(0x1f),
+            ("B", v) => {
+                let v = if let Some(JvmConstant::Int(v)) = v {
+                    *v
+                } else {
+                    0
+                };
+                bytes.extend([0x00, v as u8]);
+            }
+            ("S", v) | ("C", v) => {
+                let v = if let Some(JvmConstant::Int(v)) = v {
+                    *v
+                } else {
+                    0
+                };
+                let value_type = if descriptor == "S" { 0x02 } else { 0x03 };
+                bytes.push(1 << 5 | value_type);
+                bytes.extend((v as u16).to_le_bytes());
+            }
+            ("I", v) => {
+                let v = if let Some(JvmConstant::Int(v)) = v {
+                    *v
+                } else {
+                    0
+                };
+                bytes.push(3 << 5 | 0x04);
+                bytes.extend(v.to_le_bytes());
+            }
+            ("J", v) => {
+                let v = if let Some(JvmConstant::Long(v)) = v {
+                    *v
+                } else {
+                    0
+                };
+                bytes.push(7 << 5 | 0x06);
+                bytes.extend(v.to_le_bytes());
+            }
+            ("F", v) => {
+                let v = if let Some(JvmConstant::Float(v)) = v {
+                    *v
+                } else {
+                    0.0
+                };
+                bytes.push(3 << 5 | 0x10);
+                bytes.extend(v.to_bits().to_le_bytes());
+            }
+            ("D", v) => {
+                let v = if let Some(JvmConstant::Double(v)) = v {
+                    *v
+                } else {
+                    0.0
+                };
+                bytes.push(7 << 5 | 0x11);
+                bytes.extend(v.to_bits().to_le_bytes());
+            }
+            (_, Some(JvmConstant::String(s))) => match pools.string_idx(s) {
+                Ok(idx) => {
+                    bytes.push(3 << 5 | 0x17);
+                    bytes.extend(idx.to_le_bytes());
+                }
+                Err(_) => bytes.push(0x1e),
+            },
+            _ => bytes.push(0x1e),
+        }
+    }
+    EncodedArray::from_bytes(&mut Cursor::new(bytes)).into_items()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pool_indices_do_not_wrap() {
+        assert_eq!(pool_idx(MAX_POOL_SIZE, "Method"), Ok(0xffff));
+        assert!(pool_idx(MAX_POOL_SIZE + 1, "Method").is_err());
+    }
+}
@@ -5,8 +5,12 @@
 // file, You can obtain one at http://mozilla.org/MPL/2.0/.
 
 //! This module provides functions to extract zips and parse dex files. Further it provides functions to obtain and work on graphs, especially the information-graph.
+pub mod api_database;
+pub mod archive;
+pub mod cache;
 pub mod dex;
 pub mod extraction;
+pub mod jvm;
 
 #[cfg(feature = "rhai-script")]
 pub mod scripting;